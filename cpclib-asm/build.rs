@@ -58,7 +58,9 @@ const STAND_ALONE_DIRECTIVE: &[&[u8]] = &[
     b"EQU",
     b"EXPORT",
     b"FAIL",
+    b"FLAVOR",
     b"INCBIN",
+    b"INCIMG",
     b"INCLUDE",
     b"INCLZ4",
     b"INCEXO",
@@ -163,6 +165,7 @@ const START_DIRECTIVE: &[&[u8]] = &[
     b"PHASE",
     b"REPEAT",
     b"REPT",
+    b"STABILIZE",
     b"STRUCT",
     b"SWITCH",
     b"WHILE"
@@ -201,6 +204,7 @@ const END_DIRECTIVE: &[&[u8]] = &[
     b"ENDREP", // repeat directive
     b"ENDREPEAT",
     b"ENDS",
+    b"ENDSTABILIZE",
     b"ENDSWITCH",
     b"ENDW",
     b"ENDWHILE",
@@ -0,0 +1,228 @@
+//! On-disk cache shared by successive assemblings.
+//! The crunched data are stored under the hash of their input and of the cruncher, so a crunched
+//! section, a crunched INCBIN or a `binary_transform` is crunched again only when its content changes.
+//!
+//! The parsed listings are stored under the hash of their source, of the parser options, of the
+//! symbols defined on the command line and of the build of basm. Their tokens are serialized with
+//! their spans as offsets within the source, so they are rebuilt on top of the source read again.
+//! The sources with a side effect at parse time (`PRINT_PARSE`) are never stored.
+use std::io::Write;
+use std::sync::LazyLock;
+use std::time::UNIX_EPOCH;
+
+use bincode::Options;
+use cpclib_common::camino::{Utf8Path, Utf8PathBuf};
+use cpclib_crunchers::CompressionResult;
+use cpclib_tokens::CrunchType;
+use sha2::{Digest, Sha256};
+
+use crate::error::AssemblerError;
+use crate::implementation::instructions::{AssemblerCompressionResult, Compressor};
+use crate::{LocatedListing, ParserContextBuilder, parse_z80_with_context_builder};
+
+/// Part of every key: a new version of basm (and of its crunchers) does not reuse the previous files
+const CACHE_VERSION: &str = concat!("basm-cache-1-", env!("CARGO_PKG_VERSION"));
+/// Stored in place of the delta of the crunchers that do not provide it
+const NO_DELTA: u64 = u64::MAX;
+
+/// Identifies the executable that serializes the listings. The serialized tokens have no schema,
+/// so their layout can change with any build, even without a new version number
+fn build_id() -> Option<&'static str> {
+    static BUILD_ID: LazyLock<Option<String>> = LazyLock::new(|| {
+        let exe = std::env::current_exe().ok()?;
+        let metadata = std::fs::metadata(&exe).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(format!(
+            "{}\n{}\n{}\n",
+            exe.display(),
+            metadata.len(),
+            modified.as_nanos()
+        ))
+    });
+    BUILD_ID.as_deref()
+}
+
+/// Encoding of the listing files. The trailing bytes are refused, so a truncated or damaged file
+/// fails to deserialize
+fn listing_encoding() -> impl bincode::Options {
+    bincode::DefaultOptions::new().with_fixint_encoding()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblingCache {
+    directory: Utf8PathBuf,
+    /// The files are read but never written
+    read_only: bool,
+    /// Symbols defined on the command line: the listings are parsed again when they change
+    defined_symbols: Vec<String>
+}
+
+#[allow(missing_docs)]
+impl AssemblingCache {
+    pub fn new<P: Into<Utf8PathBuf>>(directory: P) -> Self {
+        Self {
+            directory: directory.into(),
+            read_only: false,
+            defined_symbols: Vec::new()
+        }
+    }
+
+    pub fn directory(&self) -> &Utf8Path {
+        &self.directory
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) -> &mut Self {
+        self.read_only = read_only;
+        self
+    }
+
+    pub fn defined_symbols(&self) -> &[String] {
+        &self.defined_symbols
+    }
+
+    pub fn set_defined_symbols<S: Into<String>>(
+        &mut self,
+        symbols: impl IntoIterator<Item = S>
+    ) -> &mut Self {
+        self.defined_symbols = symbols.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Parse the source or rebuild the listing of a previous assembling.
+    /// A cache that cannot be read or written only makes the assembling slower
+    pub fn parse(
+        &self,
+        code: String,
+        builder: ParserContextBuilder
+    ) -> Result<LocatedListing, Box<AssemblerError>> {
+        let Some(path) = self.listing_path(&code, &builder)
+        else {
+            return parse_z80_with_context_builder(code, builder).map_err(Box::new);
+        };
+
+        if let Ok(content) = std::fs::read(&path) {
+            let cached =
+                LocatedListing::try_new_complete_source_from(code.clone(), builder.clone(), || {
+                    listing_encoding().deserialize(&content)
+                });
+            // a damaged file is a cache miss: the source is parsed and the file written again
+            if let Ok(listing) = cached {
+                return Ok(listing);
+            }
+        }
+
+        let listing = parse_z80_with_context_builder(code, builder)?;
+        if !self.read_only
+            && listing.ctx().is_cacheable()
+            && let Ok(content) =
+                listing.with_serializable_tokens(|tokens| listing_encoding().serialize(tokens))
+        {
+            let _ = Self::write_atomically(&path, &[&content]);
+        }
+        Ok(listing)
+    }
+
+    /// Crunch the data or reuse the result of a previous assembling.
+    /// A cache that cannot be read or written only makes the assembling slower
+    pub fn compress(
+        &self,
+        kind: &CrunchType,
+        raw: &[u8]
+    ) -> Result<AssemblerCompressionResult, Box<AssemblerError>> {
+        let path = self.crunch_path(kind, raw);
+        if let Some(result) = Self::read_compression(&path, raw) {
+            return Ok(result);
+        }
+
+        let result = kind.compress(raw)?;
+        if !self.read_only {
+            let _ = self.write_compression(&path, &result);
+        }
+        Ok(result)
+    }
+
+    /// Remove all the files of the cache
+    pub fn clear(&self) -> std::io::Result<()> {
+        if self.directory.exists() {
+            fs_err::remove_dir_all(&self.directory)
+        }
+        else {
+            Ok(())
+        }
+    }
+
+    fn crunch_path(&self, kind: &CrunchType, raw: &[u8]) -> Utf8PathBuf {
+        let mut hasher = Sha256::new();
+        hasher.update(CACHE_VERSION);
+        hasher.update(format!("{kind:?}"));
+        hasher.update(raw);
+
+        self.directory.join("crunch").join(Self::key(hasher))
+    }
+
+    /// No path when the build is unknown: the listings are always parsed
+    fn listing_path(&self, code: &str, builder: &ParserContextBuilder) -> Option<Utf8PathBuf> {
+        let mut hasher = Sha256::new();
+        hasher.update(CACHE_VERSION);
+        hasher.update(build_id()?);
+        hasher.update(format!("{builder:?}\n{:?}\n", self.defined_symbols));
+        hasher.update(code);
+
+        Some(self.directory.join("listing").join(Self::key(hasher)))
+    }
+
+    fn key(hasher: Sha256) -> String {
+        hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>()
+    }
+
+    /// The file contains the delta on 8 bytes followed by the crunched stream
+    fn read_compression(path: &Utf8Path, raw: &[u8]) -> Option<AssemblerCompressionResult> {
+        let content = std::fs::read(path).ok()?;
+        let (delta, stream) = content.split_first_chunk::<8>()?;
+        let delta = match u64::from_le_bytes(*delta) {
+            NO_DELTA => None,
+            delta => Some(delta as usize)
+        };
+
+        Some(AssemblerCompressionResult::new(
+            raw,
+            CompressionResult {
+                stream: stream.to_vec(),
+                delta
+            }
+        ))
+    }
+
+    fn write_compression(
+        &self,
+        path: &Utf8Path,
+        result: &AssemblerCompressionResult
+    ) -> std::io::Result<()> {
+        let delta = result
+            .compressed_delta()
+            .map(|d| d as u64)
+            .unwrap_or(NO_DELTA);
+        Self::write_atomically(path, &[&delta.to_le_bytes(), result.as_ref()])
+    }
+
+    fn write_atomically(path: &Utf8Path, parts: &[&[u8]]) -> std::io::Result<()> {
+        fs_err::create_dir_all(path.parent().unwrap())?;
+
+        // another assembling may read the file at the same time: it is renamed once complete
+        let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+        let mut file = fs_err::File::create(&tmp)?;
+        for part in parts {
+            file.write_all(part)?;
+        }
+        drop(file);
+        fs_err::rename(&tmp, path)
+    }
+}
@@ -0,0 +1,236 @@
+//! Files read and written while assembling.
+//! They are reported to the build tools as a make rule or as a json document,
+//! so a project is rebuilt as soon as one of its included files changes.
+use std::io::Write;
+
+use cpclib_common::camino::Utf8Path;
+use serde::{Deserialize, Serialize};
+
+/// Format of the dependencies file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DependenciesFormat {
+    /// `outputs: inputs` rule followed by an empty rule for each input (as `gcc -MP` does)
+    Make,
+    /// `{"inputs": [...], "outputs": [...]}`
+    Json
+}
+
+impl DependenciesFormat {
+    /// Json for the `.json` files, make rule for any other name
+    pub fn from_filename(fname: &str) -> Self {
+        if fname.to_ascii_lowercase().ends_with(".json") {
+            Self::Json
+        }
+        else {
+            Self::Make
+        }
+    }
+}
+
+/// Inputs and outputs of an assembling
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dependencies {
+    inputs: Vec<String>,
+    outputs: Vec<String>
+}
+
+impl Dependencies {
+    pub fn inputs(&self) -> impl Iterator<Item = &Utf8Path> {
+        self.inputs.iter().map(Utf8Path::new)
+    }
+
+    pub fn outputs(&self) -> impl Iterator<Item = &Utf8Path> {
+        self.outputs.iter().map(Utf8Path::new)
+    }
+
+    /// Add a file read while assembling. Nothing is done if it is already listed
+    pub fn add_input<P: AsRef<str>>(&mut self, fname: P) {
+        Self::add(&mut self.inputs, fname.as_ref());
+    }
+
+    /// Add a file written while assembling. Nothing is done if it is already listed
+    pub fn add_output<P: AsRef<str>>(&mut self, fname: P) {
+        Self::add(&mut self.outputs, fname.as_ref());
+    }
+
+    fn add(files: &mut Vec<String>, fname: &str) {
+        if !files.iter().any(|f| f == fname) {
+            files.push(fname.to_owned());
+        }
+    }
+
+    pub fn generate<W: Write>(&self, w: &mut W, format: DependenciesFormat) -> std::io::Result<()> {
+        match format {
+            DependenciesFormat::Make => self.generate_make(w),
+            DependenciesFormat::Json => {
+                serde_json::to_writer_pretty(&mut *w, self)?;
+                writeln!(w)
+            }
+        }
+    }
+
+    /// The embedded files (`inner://`) do not exist on disc and are not provided to make
+    fn generate_make<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        let inputs = self
+            .inputs
+            .iter()
+            .filter(|f| !f.starts_with(INNER_PREFIX))
+            .map(|f| escape_make(f))
+            .collect::<Vec<_>>();
+        let outputs = self
+            .outputs
+            .iter()
+            .map(|f| escape_make(f))
+            .collect::<Vec<_>>();
+
+        write!(w, "{}:", outputs.join(" "))?;
+        for input in &inputs {
+            write!(w, " \\\n  {input}")?;
+        }
+        writeln!(w)?;
+
+        for input in &inputs {
+            writeln!(w, "\n{input}:")?;
+        }
+
+        Ok(())
+    }
+
+    /// Read a dependencies file generated by basm. Its format is deduced from its name
+    pub fn load<P: AsRef<Utf8Path>>(fname: P) -> Result<Self, String> {
+        let fname = fname.as_ref();
+        let content = fs_err::read_to_string(fname).map_err(|e| e.to_string())?;
+        match DependenciesFormat::from_filename(fname.as_str()) {
+            DependenciesFormat::Json => {
+                serde_json::from_str(&content).map_err(|e| format!("{fname}: {e}"))
+            },
+            DependenciesFormat::Make => Ok(Self::from_make(&content))
+        }
+    }
+
+    /// Only the first rule is read: the following ones are the empty rules of the inputs
+    pub fn from_make(content: &str) -> Self {
+        let content = content.replace("\\\r\n", " ").replace("\\\n", " ");
+        let mut deps = Self::default();
+
+        let Some(rule) = content
+            .lines()
+            .find(|l| !l.trim().is_empty() && !l.trim_start().starts_with('#'))
+        else {
+            return deps;
+        };
+
+        let mut in_outputs = true;
+        for word in split_make(rule) {
+            if in_outputs {
+                match word.strip_suffix(':') {
+                    Some(word) => {
+                        in_outputs = false;
+                        if !word.is_empty() {
+                            deps.add_output(word);
+                        }
+                    },
+                    None if word == ":" => in_outputs = false,
+                    None => deps.add_output(word)
+                }
+            }
+            else {
+                deps.add_input(word);
+            }
+        }
+
+        deps
+    }
+}
+
+const INNER_PREFIX: &str = "inner://";
+
+fn escape_make(fname: &str) -> String {
+    fname
+        .replace('$', "$$")
+        .replace(' ', "\\ ")
+        .replace('#', "\\#")
+}
+
+/// Split a make rule on the unescaped spaces and remove the escapes
+fn split_make(rule: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut chars = rule.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if matches!(chars.peek(), Some(' ' | '#')) => word.push(chars.next().unwrap()),
+            '$' if chars.peek() == Some(&'$') => word.push(chars.next().unwrap()),
+            ' ' | '\t' => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            },
+            c => word.push(c)
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample() -> Dependencies {
+        let mut deps = Dependencies::default();
+        deps.add_input("main.asm");
+        deps.add_input("data/my sprite.png");
+        deps.add_input("inner://opcodes_first_byte.asm");
+        deps.add_input("main.asm");
+        deps.add_output("main.bin");
+        deps
+    }
+
+    #[test]
+    fn make_rule_round_trip() {
+        let deps = sample();
+        let mut content = Vec::new();
+        deps.generate(&mut content, DependenciesFormat::Make)
+            .unwrap();
+        let content = String::from_utf8(content).unwrap();
+
+        assert_eq!(
+            content,
+            "main.bin: \\\n  main.asm \\\n  data/my\\ sprite.png\n\nmain.asm:\n\ndata/my\\ sprite.png:\n"
+        );
+
+        let read = Dependencies::from_make(&content);
+        assert_eq!(
+            read.inputs().collect::<Vec<_>>(),
+            vec!["main.asm", "data/my sprite.png"]
+        );
+        assert_eq!(read.outputs().collect::<Vec<_>>(), vec!["main.bin"]);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let deps = sample();
+        let mut content = Vec::new();
+        deps.generate(&mut content, DependenciesFormat::Json)
+            .unwrap();
+
+        let read: Dependencies = serde_json::from_slice(&content).unwrap();
+        assert_eq!(read, deps);
+        assert_eq!(read.inputs().count(), 3);
+    }
+
+    #[test]
+    fn format_from_filename() {
+        assert_eq!(
+            DependenciesFormat::from_filename("deps.JSON"),
+            DependenciesFormat::Json
+        );
+        assert_eq!(
+            DependenciesFormat::from_filename("deps.d"),
+            DependenciesFormat::Make
+        );
+    }
+}
@@ -6,6 +6,7 @@ use std::ops::Deref;
 use cpclib_common::camino::{Utf8Path, Utf8PathBuf};
 use cpclib_common::itertools::Itertools;
 use cpclib_disc::amsdos::{AmsdosFileName, AmsdosHeader, AmsdosManagerNonMut};
+use cpclib_disc::cdt::{Cdt, is_tape_image};
 use cpclib_disc::disc::Disc;
 use cpclib_disc::edsk::{ExtendedDsk, Head};
 use either::Either;
@@ -205,7 +206,7 @@ impl<'fname> AnyFileName<'fname> {
 
 impl<'fname> From<&'fname str> for AnyFileName<'fname> {
     fn from(fname: &'fname str) -> Self {
-        const IMAGES_EXT: &[&str] = &[".dsk", ".edsk", ".hfe"];
+        const IMAGES_EXT: &[&str] = &[".dsk", ".edsk", ".hfe", ".cdt", ".tzx"];
 
         let components = fname.split(Self::DSK_SEPARATOR).collect_vec();
         match components[..] {
@@ -241,7 +242,11 @@ pub fn get_filename_to_read<S: AsRef<str>>(
 ) -> Result<Utf8PathBuf, Box<AssemblerError>> {
     let fname = fname.as_ref();
 
-    AnyFileName::from(fname).path_for_base_filename(options, env)
+    let path = AnyFileName::from(fname).path_for_base_filename(options, env)?;
+    if let Some(env) = env {
+        env.add_read_file(&path);
+    }
+    Ok(path)
 }
 
 /// TODO refactor and move that from asm stuff. Should be done only in the disc crate
@@ -288,6 +293,28 @@ pub fn load_file<'a, 'b, F: Into<Fname<'a, 'b>>>(
 
         (data, header)
     }
+    else if is_tape_image(any_filename.image_filename().unwrap()) {
+        // here we read from a tape
+        let image_fname = any_filename.image_filename().unwrap();
+        let tape_fname = any_filename.content_filename();
+
+        let tape =
+            Cdt::open(image_fname).map_err(|e| AssemblerError::AssemblingError { msg: e })?;
+        let file = tape
+            .get_file(tape_fname)
+            .map_err(|e| AssemblerError::AssemblingError { msg: e })?
+            .ok_or_else(|| {
+                AssemblerError::AssemblingError {
+                    msg: format!("Unable to get {tape_fname}")
+                }
+            })?;
+
+        let file = file.to_amsdos_file()?;
+        let header = file.header();
+        let data = VecDeque::from_iter(file.content().iter().cloned());
+
+        (data, header)
+    }
     else {
         // here we read from a dsk
         let image_fname = any_filename.image_filename().unwrap();
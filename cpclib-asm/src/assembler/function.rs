@@ -28,7 +28,7 @@ use crate::list::{list_extend, list_reverse};
 use crate::matrix::matrix_from_list;
 use crate::preamble::{LocatedExpr, LocatedToken, LocatedTokenInner, MayHaveSpan, ParsingState};
 use crate::section::*;
-use crate::{Compressor, Visited, maths};
+use crate::{Visited, maths};
 
 /// Returns the expression of the RETURN directive
 pub trait ReturnExpr {
@@ -823,7 +823,7 @@ impl HardCodedFunction {
                 }
                 let data = oks;
 
-                let data = crunch_type.compress(&data)?;
+                let data = env.compress(&crunch_type, &data)?;
                 let data = ExprResult::from(data.as_slice());
                 // TODO find a way to handle the side effects of the compression (extra variables to set up)
                 eprintln!(
@@ -7,3 +7,4 @@ pub use self::format::{
     DEFAULT_LISTING_LINE_TEMPLATE, ListingAddressRadix, ListingOutputFormat, ListingOutputKind,
     ListingSourceFileOutputMode, MAX_RENDERED_SOURCE_COLUMN_CHARS
 };
+pub(crate) use self::render::escape_html;
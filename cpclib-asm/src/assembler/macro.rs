@@ -6,6 +6,7 @@ use cpclib_common::winnow::Parser;
 use cpclib_tokens::symbols::{SourceLocation, Struct, ValueMacro};
 use cpclib_tokens::{AssemblerFlavor, MacroParamElement, Token};
 
+use super::AssemblerWarning;
 use crate::Env;
 use crate::error::AssemblerError;
 use crate::preamble::{Z80ParserError, Z80Span};
@@ -32,6 +33,27 @@ fn strip_raw_string_quotes<'a>(
     }
 }
 
+/// Compute the value of a `{eval}` argument
+fn evaluate_param(src: &str, env: &mut Env) -> Result<String, Box<AssemblerError>> {
+    let ctx_builder = env
+        .options()
+        .parse_options()
+        .clone()
+        .context_builder()
+        .remove_filename()
+        .set_context_name("MACRO parameter expansion");
+    let ctx = ctx_builder.build(src);
+    let src = Z80Span::new_extra(src, &ctx);
+    let expr_token = crate::parser::located_expr.parse(src.0).map_err(|e| {
+        let e: &Z80ParserError = e.inner();
+        AssemblerError::SyntaxError { error: e.clone() }
+    })?;
+    let value = env
+        .resolve_expr_must_never_fail(&expr_token)
+        .map_err(|e| AssemblerError::AssemblingError { msg: e.to_string() })?;
+    Ok(value.to_string())
+}
+
 #[inline]
 fn expand_param<'p, P: MacroParamElement>(
     m: &'p P,
@@ -42,24 +64,7 @@ fn expand_param<'p, P: MacroParamElement>(
         let s = m.single_argument();
         let _trimmed = s.trim();
         if m.must_be_evaluated() {
-            let src = &s[..];
-            let ctx_builder = env
-                .options()
-                .parse_options()
-                .clone()
-                .context_builder()
-                .remove_filename()
-                .set_context_name("MACRO parameter expansion");
-            let ctx = ctx_builder.build(src);
-            let src = Z80Span::new_extra(src, &ctx);
-            let expr_token = crate::parser::located_expr.parse(src.0).map_err(|e| {
-                let e: &Z80ParserError = e.inner();
-                AssemblerError::SyntaxError { error: e.clone() }
-            })?;
-            let value = env
-                .resolve_expr_must_never_fail(&expr_token)
-                .map_err(|e| AssemblerError::AssemblingError { msg: e.to_string() })?;
-            beef::lean::Cow::owned(value.to_string())
+            beef::lean::Cow::owned(evaluate_param(&s, env)?)
         }
         else {
             s
@@ -85,11 +90,86 @@ fn expand_param<'p, P: MacroParamElement>(
     Ok(extended)
 }
 
+/// Argument of a macro call once the named arguments and the default values are taken into account
+#[derive(Debug)]
+enum MacroArgument<'a, P: MacroParamElement> {
+    /// Positional argument of the call
+    Given(&'a P),
+    /// Value of a named argument (`name=value`) or default value of the parameter.
+    /// It is evaluated when prefixed by `{eval}`
+    Text(String)
+}
+
+impl<P: MacroParamElement> MacroArgument<'_, P> {
+    #[inline]
+    fn expand(&self, env: &mut Env) -> Result<beef::lean::Cow<'_, str>, Box<AssemblerError>> {
+        match self {
+            MacroArgument::Given(p) => expand_param(*p, env),
+            MacroArgument::Text(s) => {
+                match s
+                    .get(..EVAL_PREFIX.len())
+                    .filter(|prefix| prefix.eq_ignore_ascii_case(EVAL_PREFIX))
+                {
+                    Some(_) => {
+                        Ok(beef::lean::Cow::owned(evaluate_param(
+                            &s[EVAL_PREFIX.len()..],
+                            env
+                        )?))
+                    },
+                    None => Ok(beef::lean::Cow::borrowed(s.as_str()))
+                }
+            }
+        }
+    }
+}
+
+const EVAL_PREFIX: &str = "{eval}";
+
+/// Index of the parameter and value of a `name=value` argument.
+/// `name` must be a parameter of the macro, otherwise the argument is positional
+fn named_argument<P: MacroParamElement>(r#macro: &ValueMacro, arg: &P) -> Option<(usize, String)> {
+    if arg.is_list() || arg.must_be_evaluated() {
+        return None;
+    }
+
+    let arg = arg.single_argument();
+    let (name, value) = arg.split_once('=')?;
+    if value.starts_with('=') {
+        // `a == b` is a comparison
+        return None;
+    }
+
+    let name = name.trim();
+    r#macro
+        .params()
+        .iter()
+        .position(|param| param.strip_prefix("r#").unwrap_or(param) == name)
+        .map(|idx| (idx, value.trim().to_owned()))
+}
+
+/// Parameters of the macro with their default value
+fn signature(r#macro: &ValueMacro) -> String {
+    r#macro
+        .params()
+        .iter()
+        .enumerate()
+        .map(|(idx, param)| {
+            match r#macro.default_value(idx) {
+                Some(default) => format!("{param}={default}"),
+                None => param.to_string()
+            }
+        })
+        .join(",")
+}
+
 /// Encodes both the arguments and the macro
 #[derive(Debug)]
 pub struct MacroWithArgs<'a, P: MacroParamElement> {
     r#macro: ValueMacro, // TODO check if we can use a reference here
-    args: &'a [P]
+    /// The declared parameters followed by the extra arguments of a variadic macro
+    args: Vec<MacroArgument<'a, P>>,
+    /// `name=value` arguments read as named although the call is also valid with positional arguments only
+    ambiguous: Vec<String>
 }
 
 impl<'a, P: MacroParamElement> MacroWithArgs<'a, P> {
@@ -97,36 +177,129 @@ impl<'a, P: MacroParamElement> MacroWithArgs<'a, P> {
     /// variadic macro (`MACRO foo(a, b, ...)`) accepts `nb_args()` or more
     /// (the extras are indexed positionally in the body via `{2}`, `{3}`,
     /// ...); a non-variadic one still requires an exact match, unchanged.
+    /// The positional arguments come first and can be followed by named
+    /// arguments (`name=value`) in any order. The parameters not provided
+    /// by the call take their default value (`MACRO foo a, b=2`).
     #[inline]
     pub fn build(r#macro: &ValueMacro, args: &'a [P]) -> Result<Self, Box<AssemblerError>> {
-        let arity_ok = if r#macro.has_variadic() {
-            args.len() >= r#macro.nb_args()
-        }
-        else {
-            args.len() == r#macro.nb_args()
-        };
-
-        if !arity_ok {
-            Err(Box::new(AssemblerError::MacroError {
+        let error = |msg: String| {
+            Box::new(AssemblerError::MacroError {
                 name: r#macro.name().into(),
-                root: Box::new(AssemblerError::AssemblingError {
-                    msg: format!(
-                        "{} arguments provided, but {}{} expected. [{}]",
-                        args.len(),
-                        if r#macro.has_variadic() { "at least " } else { "" },
-                        r#macro.nb_args(),
-                        r#macro.params().join(",")
-                    )
-                }),
+                root: Box::new(AssemblerError::AssemblingError { msg }),
                 location: r#macro.source().cloned() // TODO set up the location
-            }))
+            })
+        };
+
+        let mut slots: Vec<Option<MacroArgument<'a, P>>> =
+            (0..r#macro.nb_args()).map(|_| None).collect();
+        let mut extra = Vec::new();
+        let mut nb_positional = 0;
+        let mut has_named = false;
+        let mut named = Vec::new();
+        for arg in args {
+            match named_argument(r#macro, arg) {
+                Some((idx, value)) => {
+                    named.push(arg.single_argument().into_owned());
+                    if slots[idx].is_some() {
+                        return Err(error(format!(
+                            "argument `{}` is provided several times",
+                            r#macro.params()[idx]
+                        )));
+                    }
+                    slots[idx] = Some(MacroArgument::Text(value));
+                    has_named = true;
+                },
+                None if has_named => {
+                    return Err(error(format!(
+                        "positional argument `{}` provided after named arguments",
+                        arg.single_argument()
+                    )));
+                },
+                None => {
+                    match slots.get_mut(nb_positional) {
+                        Some(slot) => *slot = Some(MacroArgument::Given(arg)),
+                        None => extra.push(MacroArgument::Given(arg))
+                    }
+                    nb_positional += 1;
+                }
+            }
+        }
+
+        let nb_required = (0..r#macro.nb_args())
+            .filter(|&idx| r#macro.default_value(idx).is_none())
+            .count();
+        let missing = slots
+            .iter()
+            .enumerate()
+            .filter(|(idx, slot)| slot.is_none() && r#macro.default_value(*idx).is_none())
+            .map(|(idx, _)| format!("`{}`", r#macro.params()[idx]))
+            .collect::<Vec<_>>();
+
+        if (!extra.is_empty() && !r#macro.has_variadic()) || (!missing.is_empty() && !has_named) {
+            let expected = if r#macro.has_variadic() {
+                format!("at least {nb_required}")
+            }
+            else if nb_required == r#macro.nb_args() {
+                nb_required.to_string()
+            }
+            else {
+                format!("from {nb_required} to {}", r#macro.nb_args())
+            };
+            return Err(error(format!(
+                "{} arguments provided, but {expected} expected. [{}]",
+                args.len(),
+                signature(r#macro)
+            )));
+        }
+
+        if !missing.is_empty() {
+            return Err(error(format!(
+                "argument{} {} missing. [{}]",
+                if missing.len() > 1 { "s" } else { "" },
+                missing.join(", "),
+                signature(r#macro)
+            )));
+        }
+
+        // before named arguments, such a call gave the `name=value` text to the parameter at its position
+        let ambiguous = if (r#macro.has_variadic() && args.len() >= r#macro.nb_args())
+            || args.len() == r#macro.nb_args()
+        {
+            named
         }
         else {
-            Ok(Self {
-                r#macro: r#macro.clone(), // TODO use reference?
-                args
+            Vec::new()
+        };
+
+        let args = slots
+            .into_iter()
+            .enumerate()
+            .map(|(idx, slot)| {
+                slot.unwrap_or_else(|| {
+                    MacroArgument::Text(r#macro.default_value(idx).unwrap().to_owned())
+                })
             })
-        }
+            .chain(extra)
+            .collect();
+
+        Ok(Self {
+            r#macro: r#macro.clone(), // TODO use reference?
+            args,
+            ambiguous
+        })
+    }
+
+    /// Warn about the `name=value` arguments that were positional ones before named arguments existed
+    #[inline]
+    pub fn ambiguity_warnings(&self) -> impl Iterator<Item = AssemblerWarning> + '_ {
+        self.ambiguous.iter().map(|arg| {
+            AssemblerWarning::AssemblingError {
+                msg: format!(
+                    "`{arg}` is a named argument of macro `{}`, not a positional one",
+                    self.r#macro.name()
+                )
+            }
+        })
     }
 
     #[inline]
@@ -167,8 +340,7 @@ impl<'a, P: MacroParamElement> MacroWithArgs<'a, P> {
                         };
 
                         if slot.is_none() {
-                            let argvalue = &self.args[index];
-                            let mut expanded = expand_param(argvalue, env)?;
+                            let mut expanded = self.args[index].expand(env)?;
                             // Extra (variadic) positional args have no
                             // declared name to check for the `r#`-raw-string
                             // convention - only named params are eligible.
@@ -232,9 +404,11 @@ impl<'a, P: MacroParamElement> MacroWithArgs<'a, P> {
 
     #[inline]
     fn expand_for_orgams(&self, env: &mut Env) -> Result<String, Box<AssemblerError>> {
-        // Orgams-flavor expansion substitutes named params only (a literal
-        // pattern->replacement pass over `params()`, no segment/index model
-        // at all - see this function's own body below) - it has no way to
+        // Orgams-flavor expansion (shared by the WinAPE and sjasmplus
+        // flavors, whose macro bodies also use bare parameter names)
+        // substitutes named params only (a literal pattern->replacement pass
+        // over `params()`, no segment/index model at all - see this
+        // function's own body below) - it has no way to
         // place a variadic macro's extra positional args anywhere, so
         // silently dropping them would be a real, confusing bug rather than
         // an unsupported-but-honest error.
@@ -242,9 +416,11 @@ impl<'a, P: MacroParamElement> MacroWithArgs<'a, P> {
             return Err(Box::new(AssemblerError::MacroError {
                 name: self.r#macro.name().into(),
                 root: Box::new(AssemblerError::AssemblingError {
-                    msg: "variadic macros (extra arguments beyond the named parameters) are not \
-                          yet supported for the orgams assembler flavor"
-                        .to_owned()
+                    msg: format!(
+                        "variadic macros (extra arguments beyond the named parameters) are not \
+                         yet supported for the {} assembler flavor",
+                        self.flavor().name().to_lowercase()
+                    )
                 }),
                 location: self.r#macro.source().cloned()
             }));
@@ -254,7 +430,7 @@ impl<'a, P: MacroParamElement> MacroWithArgs<'a, P> {
         let all_expanded = self
             .args
             .iter()
-            .map(|argvalue| expand_param(argvalue, env))
+            .map(|argvalue| argvalue.expand(env))
             .partition_map(|res| {
                 match res {
                     Ok(val) => either::Either::Left(val),
@@ -303,12 +479,11 @@ impl<'a, P: MacroParamElement> Expandable for MacroWithArgs<'a, P> {
     /// Develop the macro with the given arguments
     #[inline]
     fn expand(&self, env: &mut Env) -> Result<String, Box<AssemblerError>> {
-        if self.flavor() == AssemblerFlavor::Basm {
-            self.expand_for_basm(env)
-        }
-        else {
-            assert_eq!(self.flavor(), AssemblerFlavor::Orgams);
-            self.expand_for_orgams(env)
+        match self.flavor() {
+            AssemblerFlavor::Basm | AssemblerFlavor::Rasm => self.expand_for_basm(env),
+            AssemblerFlavor::Orgams | AssemblerFlavor::Winape | AssemblerFlavor::Sjasmplus => {
+                self.expand_for_orgams(env)
+            },
         }
 
         // make all replacements in one row :( sadly it is too slow :(
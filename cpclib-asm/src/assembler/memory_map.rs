@@ -0,0 +1,523 @@
+//! Memory map of the assembled program.
+//! Each 64kb page (snapshot page selected with BANKSET/MMR, free bank opened with BANK or cartridge bloc)
+//! is described by the regions of bytes written by each section and source file, the labels they contain,
+//! the free gaps, the protected areas and the overlaps.
+use std::fmt::Display;
+use std::io::Write;
+use std::str::FromStr;
+
+use cpclib_tokens::symbols::PhysicalAddress;
+use serde::Serialize;
+
+use super::listing_output::escape_html;
+use super::page_info::ProtectedArea;
+
+const PAGE_SIZE: usize = 0x1_0000;
+
+/// 64kb of memory that receives assembled bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(tag = "kind", content = "index", rename_all = "lowercase")]
+pub enum MemoryMapPage {
+    /// Page of the snapshot: 0 for the main memory, 1 and more for the extended memory
+    Page(u8),
+    /// Free bank opened with BANK
+    Bank(usize),
+    /// Cartridge bloc
+    Cpr(u8)
+}
+
+impl Display for MemoryMapPage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MemoryMapPage::Page(page) => write!(f, "Page {page}"),
+            MemoryMapPage::Bank(bank) => write!(f, "Bank {bank}"),
+            MemoryMapPage::Cpr(bloc) => write!(f, "Cartridge bloc {bloc}")
+        }
+    }
+}
+
+impl MemoryMapPage {
+    /// Page that contains the address and offset of the address in this page
+    pub fn locate(address: &PhysicalAddress) -> (Self, u16) {
+        match address {
+            PhysicalAddress::Memory(a) => (Self::Page(a.page()), a.offset_in_page()),
+            PhysicalAddress::Bank(a) => (Self::Bank(a.bank()), a.address()),
+            PhysicalAddress::Cpr(a) => (Self::Cpr(a.bloc()), a.address())
+        }
+    }
+}
+
+/// Bytes written consecutively by the same section and source file
+#[derive(Debug, Clone)]
+struct WrittenRun {
+    page: MemoryMapPage,
+    start: u16,
+    stop: u16,
+    section: Option<String>,
+    /// Index of the file, first and last lines
+    source: Option<(usize, usize, usize)>
+}
+
+/// Collect the written bytes during the pass (only when [crate::AssemblingOptionFlags::MemoryMap] is set)
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MemoryMapRecorder {
+    files: Vec<String>,
+    /// Index of the file and line of the token being assembled
+    source: Option<(usize, usize)>,
+    runs: Vec<WrittenRun>,
+    overlaps: Vec<(MemoryMapPage, u16, u16)>
+}
+
+impl MemoryMapRecorder {
+    pub(crate) fn clear(&mut self) {
+        self.source = None;
+        self.runs.clear();
+        self.overlaps.clear();
+    }
+
+    /// Remember the source of the bytes that follow
+    pub(crate) fn set_source(&mut self, source: Option<(&str, usize)>) {
+        self.source = source.map(|(file, line)| {
+            let idx = match self.files.iter().rposition(|f| f == file) {
+                Some(idx) => idx,
+                None => {
+                    self.files.push(file.to_owned());
+                    self.files.len() - 1
+                }
+            };
+            (idx, line)
+        });
+    }
+
+    pub(crate) fn record_byte(
+        &mut self,
+        address: &PhysicalAddress,
+        section: Option<&str>,
+        overlap: bool
+    ) {
+        let (page, offset) = MemoryMapPage::locate(address);
+
+        if overlap {
+            match self.overlaps.last_mut() {
+                Some((p, _, stop)) if *p == page && stop.checked_add(1) == Some(offset) => {
+                    *stop = offset
+                },
+                _ => self.overlaps.push((page, offset, offset))
+            }
+        }
+
+        if let Some(run) = self.runs.last_mut()
+            && run.page == page
+            && run.stop.checked_add(1) == Some(offset)
+            && run.section.as_deref() == section
+            && run.source.map(|s| s.0) == self.source.map(|s| s.0)
+        {
+            run.stop = offset;
+            if let (Some((_, first, last)), Some((_, line))) = (&mut run.source, self.source) {
+                *first = (*first).min(line);
+                *last = (*last).max(line);
+            }
+        }
+        else {
+            self.runs.push(WrittenRun {
+                page,
+                start: offset,
+                stop: offset,
+                section: section.map(ToOwned::to_owned),
+                source: self.source.map(|(file, line)| (file, line, line))
+            });
+        }
+    }
+
+    /// Build the map of the pages that have been written or protected
+    pub(crate) fn build(
+        &self,
+        protected_areas: &[(MemoryMapPage, ProtectedArea)],
+        labels: &[(MemoryMapPage, u16, String)]
+    ) -> MemoryMap {
+        let mut pages = self
+            .runs
+            .iter()
+            .map(|r| r.page)
+            .chain(protected_areas.iter().map(|p| p.0))
+            .collect::<Vec<_>>();
+        pages.sort();
+        pages.dedup();
+
+        let pages = pages
+            .into_iter()
+            .map(|page| self.build_page(page, protected_areas, labels))
+            .collect();
+        MemoryMap { pages }
+    }
+
+    fn build_page(
+        &self,
+        page: MemoryMapPage,
+        protected_areas: &[(MemoryMapPage, ProtectedArea)],
+        labels: &[(MemoryMapPage, u16, String)]
+    ) -> PageMap {
+        let mut regions = self
+            .runs
+            .iter()
+            .filter(|r| r.page == page)
+            .map(|r| {
+                let mut labels = labels
+                    .iter()
+                    .filter(|(p, address, _)| *p == page && (r.start..=r.stop).contains(address))
+                    .collect::<Vec<_>>();
+                labels.sort_by_key(|(_, address, name)| (*address, name.to_owned()));
+
+                MemoryRegion {
+                    start: r.start,
+                    stop: r.stop,
+                    section: r.section.clone(),
+                    file: r.source.map(|s| self.files[s.0].clone()),
+                    first_line: r.source.map(|s| s.1),
+                    last_line: r.source.map(|s| s.2),
+                    labels: labels.into_iter().map(|l| l.2.clone()).collect()
+                }
+            })
+            .collect::<Vec<_>>();
+        regions.sort_by_key(|r| (r.start, r.stop));
+
+        let mut protected = protected_areas
+            .iter()
+            .filter(|(p, _)| *p == page)
+            .map(|(_, area)| MemoryRange::new(*area.start(), *area.end()))
+            .collect::<Vec<_>>();
+        protected.sort_by_key(|r| (r.start, r.stop));
+        protected.dedup();
+
+        let overlaps = self
+            .overlaps
+            .iter()
+            .filter(|(p, ..)| *p == page)
+            .map(|(_, start, stop)| MemoryRange::new(*start, *stop))
+            .collect();
+
+        // the gaps are the bytes neither written nor protected
+        let mut written = vec![false; PAGE_SIZE];
+        for r in &regions {
+            written[r.start as usize..=r.stop as usize].fill(true);
+        }
+        let used = written.iter().filter(|w| **w).count();
+        for p in &protected {
+            written[p.start as usize..=p.stop as usize].fill(true);
+        }
+        let mut gaps = Vec::new();
+        let mut start = None;
+        for (address, busy) in written.iter().chain(std::iter::once(&true)).enumerate() {
+            match (start, busy) {
+                (None, false) => start = Some(address),
+                (Some(s), true) => {
+                    gaps.push(MemoryRange::new(s as u16, (address - 1) as u16));
+                    start = None;
+                },
+                _ => {}
+            }
+        }
+
+        PageMap {
+            page,
+            used,
+            free: gaps.iter().map(MemoryRange::len).sum(),
+            regions,
+            gaps,
+            protected,
+            overlaps
+        }
+    }
+}
+
+/// Inclusive range of addresses within a page
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct MemoryRange {
+    pub start: u16,
+    pub stop: u16
+}
+
+impl MemoryRange {
+    pub fn new(start: u16, stop: u16) -> Self {
+        Self { start, stop }
+    }
+
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.stop as usize - self.start as usize + 1
+    }
+}
+
+/// Bytes written consecutively by a section and a source file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MemoryRegion {
+    pub start: u16,
+    pub stop: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub section: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_line: Option<usize>,
+    /// Labels located in the region
+    pub labels: Vec<String>
+}
+
+impl MemoryRegion {
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.stop as usize - self.start as usize + 1
+    }
+
+    /// Human readable origin of the region
+    pub fn origin(&self) -> String {
+        let source = match (&self.file, self.first_line, self.last_line) {
+            (Some(file), Some(first), Some(last)) if first == last => format!("{file}:{first}"),
+            (Some(file), Some(first), Some(last)) => format!("{file}:{first}-{last}"),
+            _ => String::new()
+        };
+        match &self.section {
+            Some(section) if source.is_empty() => format!("SECTION {section}"),
+            Some(section) => format!("SECTION {section} ({source})"),
+            None => source
+        }
+    }
+}
+
+/// Occupancy of a 64kb page. The addresses are offsets in the page, except for the protected areas
+/// that keep the addresses given to PROTECT
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PageMap {
+    #[serde(flatten)]
+    pub page: MemoryMapPage,
+    /// Number of written bytes
+    pub used: usize,
+    /// Number of bytes neither written nor protected
+    pub free: usize,
+    pub regions: Vec<MemoryRegion>,
+    pub gaps: Vec<MemoryRange>,
+    pub protected: Vec<MemoryRange>,
+    /// Bytes written several times
+    pub overlaps: Vec<MemoryRange>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryMapFormat {
+    Json,
+    Html,
+    Svg
+}
+
+impl FromStr for MemoryMapFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "html" | "htm" => Ok(Self::Html),
+            "svg" => Ok(Self::Svg),
+            _ => Err(format!("Wrong memory map format {s}"))
+        }
+    }
+}
+
+impl MemoryMapFormat {
+    /// Format deduced from the extension of the file
+    pub fn from_filename(fname: &str) -> Result<Self, String> {
+        fname
+            .rsplit_once('.')
+            .map(|(_, extension)| extension)
+            .ok_or_else(|| format!("{fname} has no extension"))
+            .and_then(Self::from_str)
+    }
+}
+
+/// Memory map of all the pages written or protected during the last pass
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MemoryMap {
+    pages: Vec<PageMap>
+}
+
+/// Width in pixels of the bar of a page
+const SVG_WIDTH: usize = 1024;
+const SVG_BAR_HEIGHT: usize = 32;
+const SVG_ROW_HEIGHT: usize = 64;
+const SVG_MARGIN: usize = 16;
+
+const FREE_COLOR: &str = "#e8e8e8";
+const PROTECTED_COLOR: &str = "#707070";
+const OVERLAP_COLOR: &str = "#e00000";
+const REGION_COLORS: &[&str] = &[
+    "#4e79a7", "#f28e2b", "#59a14f", "#b07aa1", "#76b7b2", "#edc948", "#9c755f", "#ff9da7"
+];
+
+impl MemoryMap {
+    pub fn pages(&self) -> &[PageMap] {
+        &self.pages
+    }
+
+    pub fn page(&self, page: MemoryMapPage) -> Option<&PageMap> {
+        self.pages.iter().find(|p| p.page == page)
+    }
+
+    pub fn generate<W: Write>(&self, w: &mut W, format: MemoryMapFormat) -> std::io::Result<()> {
+        match format {
+            MemoryMapFormat::Json => self.generate_json(w),
+            MemoryMapFormat::Html => self.generate_html(w),
+            MemoryMapFormat::Svg => self.generate_svg(w)
+        }
+    }
+
+    pub fn generate_json<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        serde_json::to_writer_pretty(&mut *w, self)?;
+        writeln!(w)
+    }
+
+    /// One table per page, preceded by the same drawing as the SVG output
+    pub fn generate_html<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        writeln!(
+            w,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Memory map</title>"
+        )?;
+        writeln!(
+            w,
+            "<style>\nbody {{ font-family: sans-serif; }}\ntable {{ border-collapse: collapse; margin-bottom: 2em; }}\nth, td {{ border: 1px solid #ccc; padding: 2px 8px; text-align: left; font-family: monospace; }}\ntr.free {{ color: #888; }}\ntr.protected {{ background: #ddd; }}\ntr.overlap {{ background: #fcc; }}\n</style>\n</head>\n<body>\n<h1>Memory map</h1>"
+        )?;
+        self.generate_svg(w)?;
+
+        for page in &self.pages {
+            writeln!(
+                w,
+                "<h2>{}</h2>\n<p>{} bytes used, {} bytes free</p>",
+                page.page, page.used, page.free
+            )?;
+            writeln!(
+                w,
+                "<table>\n<tr><th>Start</th><th>Stop</th><th>Size</th><th>Kind</th><th>Origin</th><th>Labels</th></tr>"
+            )?;
+            for (kind, range, origin, labels) in Self::page_rows(page) {
+                writeln!(
+                    w,
+                    "<tr class=\"{kind}\"><td>0x{:04X}</td><td>0x{:04X}</td><td>{}</td><td>{kind}</td><td>{}</td><td>{}</td></tr>",
+                    range.start,
+                    range.stop,
+                    range.len(),
+                    escape_html(&origin),
+                    escape_html(&labels)
+                )?;
+            }
+            writeln!(w, "</table>")?;
+        }
+
+        writeln!(w, "</body>\n</html>")
+    }
+
+    /// One bar of 64kb per page
+    pub fn generate_svg<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        let width = SVG_WIDTH + 2 * SVG_MARGIN;
+        let height = self.pages.len() * SVG_ROW_HEIGHT + SVG_MARGIN;
+        writeln!(
+            w,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" viewBox=\"0 0 {width} {height}\" font-family=\"monospace\" font-size=\"12\">"
+        )?;
+
+        for (idx, page) in self.pages.iter().enumerate() {
+            let y = SVG_MARGIN + idx * SVG_ROW_HEIGHT;
+            let bar_y = y + 4;
+            writeln!(
+                w,
+                "<text x=\"{SVG_MARGIN}\" y=\"{y}\">{} - {} bytes used, {} bytes free</text>",
+                page.page, page.used, page.free
+            )?;
+            writeln!(
+                w,
+                "<rect x=\"{SVG_MARGIN}\" y=\"{bar_y}\" width=\"{SVG_WIDTH}\" height=\"{SVG_BAR_HEIGHT}\" fill=\"{FREE_COLOR}\" stroke=\"#000\" stroke-width=\"0.5\"/>"
+            )?;
+
+            for (kind, range, origin, labels) in Self::page_rows(page) {
+                let color = match kind {
+                    "free" => continue,
+                    "protected" => PROTECTED_COLOR,
+                    "overlap" => OVERLAP_COLOR,
+                    _ => {
+                        let key = origin.bytes().fold(0usize, |acc, b| {
+                            acc.wrapping_mul(31).wrapping_add(b as usize)
+                        });
+                        REGION_COLORS[key % REGION_COLORS.len()]
+                    }
+                };
+                let x =
+                    SVG_MARGIN as f64 + range.start as f64 * SVG_WIDTH as f64 / PAGE_SIZE as f64;
+                let rect_width = (range.len() as f64 * SVG_WIDTH as f64 / PAGE_SIZE as f64).max(1.);
+                let title = format!(
+                    "0x{:04X}-0x{:04X} ({} bytes) {kind} {origin} {labels}",
+                    range.start,
+                    range.stop,
+                    range.len()
+                );
+                writeln!(
+                    w,
+                    "<rect x=\"{x:.2}\" y=\"{bar_y}\" width=\"{rect_width:.2}\" height=\"{SVG_BAR_HEIGHT}\" fill=\"{color}\"><title>{}</title></rect>",
+                    escape_html(title.trim_end())
+                )?;
+            }
+
+            // one tick per 16kb
+            for tick in 0..=4 {
+                let x = SVG_MARGIN + tick * SVG_WIDTH / 4;
+                let tick_y = bar_y + SVG_BAR_HEIGHT;
+                writeln!(
+                    w,
+                    "<line x1=\"{x}\" y1=\"{bar_y}\" x2=\"{x}\" y2=\"{}\" stroke=\"#000\" stroke-width=\"0.5\"/>",
+                    tick_y + 4
+                )?;
+                if tick < 4 {
+                    writeln!(
+                        w,
+                        "<text x=\"{}\" y=\"{}\">0x{:04X}</text>",
+                        x + 2,
+                        tick_y + 14,
+                        tick * 0x4000
+                    )?;
+                }
+            }
+        }
+
+        writeln!(w, "</svg>")
+    }
+
+    /// Kind, range, origin and labels of all the areas of the page sorted by address
+    fn page_rows(page: &PageMap) -> Vec<(&'static str, MemoryRange, String, String)> {
+        let mut rows = page
+            .regions
+            .iter()
+            .map(|r| {
+                (
+                    "written",
+                    MemoryRange::new(r.start, r.stop),
+                    r.origin(),
+                    r.labels.join(" ")
+                )
+            })
+            .chain(
+                page.gaps
+                    .iter()
+                    .map(|r| ("free", *r, String::new(), String::new()))
+            )
+            .chain(
+                page.protected
+                    .iter()
+                    .map(|r| ("protected", *r, String::new(), String::new()))
+            )
+            .chain(
+                page.overlaps
+                    .iter()
+                    .map(|r| ("overlap", *r, String::new(), String::new()))
+            )
+            .collect::<Vec<_>>();
+        rows.sort_by_key(|(_, range, ..)| (range.start, range.stop));
+        rows
+    }
+}
@@ -1,7 +1,9 @@
 use crate::disass::disassemble;
 use crate::error::AssemblerError;
+pub mod cache;
 pub mod control;
 pub mod delayed_command;
+pub mod dependencies;
 pub mod embedded;
 pub mod file;
 pub mod function;
@@ -10,6 +12,7 @@ pub mod listing_output;
 pub mod r#macro;
 pub mod maths;
 pub mod matrix;
+pub mod memory_map;
 pub mod page_info;
 pub mod processed_token;
 pub mod report;
@@ -19,9 +22,10 @@ pub mod stable_ticker;
 pub mod string;
 pub mod support;
 pub mod symbols_output;
+pub mod unit_test;
 
 use std::borrow::BorrowMut;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt;
 use std::fmt::{Debug, Display};
 use std::io::Write;
@@ -39,11 +43,13 @@ use cpclib_common::smallvec::SmallVec;
 use cpclib_common::smol_str::SmolStr;
 use cpclib_common::winnow::stream::UpdateSlice;
 use cpclib_disc::built_info;
+use cpclib_disc::cdt::{CdtWriteOptions, is_tape_image};
 use cpclib_files::{FileType, StorageSupport};
 use cpclib_sna::*;
 use cpclib_tokens::ToSimpleToken;
+use dependencies::Dependencies;
 use either::Either;
-use file::AnyFileNameOwned;
+use file::{AnyFileName, AnyFileNameOwned, get_filename_to_read};
 use processed_token::build_processed_token;
 #[cfg(all(not(target_arch = "wasm32"), feature = "rayon"))]
 use rayon_cond::CondIterator;
@@ -54,11 +60,14 @@ use support::sna::SnaAssembler;
 use self::control::ControlOutputStore;
 use self::function::{Function, FunctionBuilder, HardCodedFunction};
 use self::listing_output::*;
+use self::memory_map::{MemoryMap, MemoryMapFormat, MemoryMapPage, MemoryMapRecorder};
 use self::processed_token::ProcessedToken;
 use self::report::SavedFile;
 use self::string::PreprocessedFormattedString;
-use self::symbols_output::{SymbolOutputFormat, SymbolOutputGenerator};
+use self::symbols_output::{SourceLine, SymbolOutputFormat, SymbolOutputGenerator};
+use self::unit_test::UnitTest;
 use crate::assembler::processed_token::visit_processed_tokens;
+use crate::branch_balance::StabilizeEdit;
 use crate::delayed_command::*;
 use crate::page_info::PageInformation;
 use crate::preamble::*;
@@ -167,6 +176,10 @@ impl EnvOptions {
         &self.assemble
     }
 
+    pub fn assemble_options_mut(&mut self) -> &mut AssemblingOptions {
+        &mut self.assemble
+    }
+
     #[cfg(not(target_arch = "wasm32"))]
     pub fn show_progress(&self) -> bool {
         self.parse.show_progress
@@ -411,6 +424,8 @@ pub struct Env {
     /// Current pass
     pass: AssemblingPass,
     options: EnvOptions,
+    /// Flavor requested by the options. Each pass starts with it
+    default_flavor: AssemblerFlavor,
     real_nb_passes: usize,
     /// If true at the end of the pass, can prematurely stop the assembling
     /// Hidden in a rwlock to allow a modification even in non mutable state
@@ -428,6 +443,9 @@ pub struct Env {
 
     /// gate array configuration
     ga_mmr: u8,
+    /// 16kb page mapped in 0xC000-0xFFFF by the sjasmplus PAGE directive.
+    /// The gate array cannot map any page there, so it overrides `ga_mmr` for this slot
+    slot3_page: Option<u8>,
     /// duplicate of the output address to be sure to select the appropriate page info
     output_address: u16,
 
@@ -467,6 +485,16 @@ pub struct Env {
     output_trigger: Option<ListingOutputTrigger>,
     /// Listing of symbols generator
     symbols_output: SymbolOutputGenerator,
+    /// Source of the token being assembled until it generates its first byte (only when the source map is requested)
+    pending_source_line: Option<Z80Span>,
+    /// Address of the first byte generated by each line of source
+    source_map: Vec<SourceLine>,
+    /// Origin of the written bytes (only when the memory map is requested)
+    memory_map: MemoryMapRecorder,
+    /// TEST blocks of the current pass
+    unit_tests: Vec<UnitTest>,
+    /// Number of RET cc rewritten by the STABILIZE blocks of the current pass (names their hidden labels)
+    stabilized_returns: usize,
 
     warnings: Vec<Box<AssemblerWarning>>,
 
@@ -489,6 +517,8 @@ pub struct Env {
     if_token_adr_to_unused_decision: HashMap<usize, bool>,
 
     included_paths: HashSet<Utf8PathBuf>,
+    /// Files read while assembling (shared with the environments of the crunched sections)
+    read_files: Arc<RwLock<BTreeSet<Utf8PathBuf>>>,
 
     map_counter: i32,
 
@@ -518,6 +548,7 @@ impl Clone for Env {
         Self {
             lookup_directory_stack: self.lookup_directory_stack.clone(),
             options: self.options.clone(),
+            default_flavor: self.default_flavor,
             can_skip_next_passes: (*self.can_skip_next_passes.read().unwrap().deref()).into(),
             request_additional_pass: (*self.request_additional_pass.read().unwrap().deref()).into(),
             pass: self.pass,
@@ -525,6 +556,7 @@ impl Clone for Env {
             crunched_section_state: self.crunched_section_state.clone(),
             stable_counters: self.stable_counters.clone(),
             ga_mmr: self.ga_mmr,
+            slot3_page: self.slot3_page,
             output_address: self.output_address,
             sna: self.sna.clone(),
             sna_version: self.sna_version,
@@ -536,6 +568,11 @@ impl Clone for Env {
             run_options: self.run_options,
             output_trigger: self.output_trigger.clone(),
             symbols_output: self.symbols_output.clone(),
+            pending_source_line: self.pending_source_line.clone(),
+            source_map: self.source_map.clone(),
+            memory_map: self.memory_map.clone(),
+            unit_tests: self.unit_tests.clone(),
+            stabilized_returns: self.stabilized_returns,
             warnings: self.warnings.clone(),
             nested_rorg: self.nested_rorg,
             sections: self.sections.clone(),
@@ -553,6 +590,7 @@ impl Clone for Env {
             previous_pass_discarded_errors: self.previous_pass_discarded_errors.clone(),
 
             included_paths: self.included_paths.clone(),
+            read_files: Arc::clone(&self.read_files),
             extra_print_from_function: self
                 .extra_print_from_function
                 .read()
@@ -969,6 +1007,34 @@ impl Env {
     }
 }
 
+/// Dependencies handling
+impl Env {
+    /// Remember a file read by INCLUDE, INCBIN, LOAD and co.
+    /// Only the image is kept for the files stored in a disc or tape image
+    pub(crate) fn add_read_file(&self, fname: &Utf8Path) {
+        let fname = AnyFileName::from(fname.as_str());
+        let fname = fname.image_filename().unwrap_or(fname.content_filename());
+        self.read_files.write().unwrap().insert(fname.into());
+    }
+
+    /// Files read and written by the assembling.
+    /// The main source and the output given to the assembler are not known here and must be added by the caller
+    pub fn dependencies(&self) -> Dependencies {
+        let mut deps = Dependencies::default();
+        for fname in self.read_files.read().unwrap().iter() {
+            deps.add_input(fname);
+        }
+        for saved in self.saved_files.iter().flatten() {
+            let fname = AnyFileName::from(saved.name.as_str());
+            deps.add_output(fname.image_filename().unwrap_or(fname.content_filename()));
+        }
+        if let Some(fname) = self.output_filename() {
+            deps.add_output(AnyFileName::from(fname).image_filename().unwrap_or(fname));
+        }
+        deps
+    }
+}
+
 /// Handle the file search relatively to the current file
 impl Env {
     fn set_current_working_directory<P: Into<Utf8PathBuf>>(&mut self, p: P) {
@@ -1061,6 +1127,88 @@ impl Env {
         &self.warnings
     }
 
+    /// Remember the source of the token about to be assembled for the source map
+    pub(crate) fn handle_source_line(&mut self, span: Option<&Z80Span>) {
+        if span.is_some()
+            && self
+                .options()
+                .assemble_options()
+                .get_flag(crate::AssemblingOptionFlags::SourceMap)
+        {
+            self.pending_source_line = span.cloned();
+        }
+
+        if self
+            .options()
+            .assemble_options()
+            .get_flag(crate::AssemblingOptionFlags::MemoryMap)
+        {
+            self.memory_map
+                .set_source(span.map(|s| (s.filename(), s.relative_line_and_column().0)));
+        }
+    }
+
+    /// Generator that selects the exported symbols (EXPORT/NOEXPORT directives)
+    pub fn symbols_output(&self) -> &SymbolOutputGenerator {
+        &self.symbols_output
+    }
+
+    /// Address of the first byte generated by each line of source (needs [crate::AssemblingOptionFlags::SourceMap])
+    pub fn source_map(&self) -> &[SourceLine] {
+        &self.source_map
+    }
+
+    /// Occupancy of the pages written or protected during the last pass (needs [crate::AssemblingOptionFlags::MemoryMap])
+    pub fn memory_map(&self) -> MemoryMap {
+        let protected_areas = self
+            .sna
+            .pages_info
+            .iter()
+            .enumerate()
+            .map(|(idx, info)| (MemoryMapPage::Page(idx as u8), info))
+            .chain(
+                self.free_banks
+                    .page_infos()
+                    .enumerate()
+                    .map(|(idx, info)| (MemoryMapPage::Bank(idx), info))
+            )
+            .chain(
+                self.cpr
+                    .iter()
+                    .flat_map(|cpr| cpr.blocs_page_infos())
+                    .map(|(bloc, info)| (MemoryMapPage::Cpr(bloc), info))
+            )
+            .flat_map(|(page, info)| {
+                info.protected_areas
+                    .iter()
+                    .map(move |area| (page, area.clone()))
+            })
+            .collect_vec();
+
+        let labels = self
+            .symbols()
+            .expression_symbol()
+            .into_iter()
+            .filter(|(s, _)| self.symbols_output.keep_symbol(s))
+            .filter_map(|(s, v)| {
+                match v.value() {
+                    Value::Address(a) => {
+                        let (page, offset) = MemoryMapPage::locate(a);
+                        Some((page, offset, s.value().to_owned()))
+                    },
+                    _ => None
+                }
+            })
+            .collect_vec();
+
+        self.memory_map.build(&protected_areas, &labels)
+    }
+
+    /// TEST blocks found in the source, to be executed by an emulator
+    pub fn unit_tests(&self) -> &[UnitTest] {
+        &self.unit_tests
+    }
+
     /// Manage the play with data for the output listing
     fn handle_output_trigger(&mut self, new: &LocatedToken) {
         if self.pass.is_listing_pass() && self.output_trigger.is_some() {
@@ -1158,6 +1306,11 @@ impl Env {
 
             self.stable_counters.new_pass();
             self.run_options = None;
+            self.pending_source_line = None;
+            self.source_map.clear();
+            self.memory_map.clear();
+            self.unit_tests.clear();
+            self.stabilized_returns = 0;
 
             self.sna.reset_written_bytes();
             if let Some(cpr) = self.cpr.as_mut() {
@@ -1180,15 +1333,18 @@ impl Env {
             self.free_banks.selected_index = None;
 
             // environnement is not reset when assembling is finished
-            self.output_address = 0;
+            let origin = self.options().assemble_options().default_origin();
+            self.output_address = origin;
             let page_info = self.active_page_info_mut();
-            page_info.logical_outputadr = 0;
-            page_info.logical_codeadr = 0;
+            page_info.logical_outputadr = origin;
+            page_info.logical_codeadr = origin;
             self.update_dollar();
 
             self.ga_mmr = 0xC0;
+            self.slot3_page = None;
             self.macro_seed = 0;
             self.charset_encoding.reset();
+            self.options.parse.set_flavor(self.default_flavor);
             // self.sna = Default::default(); // We finally keep the snapshot for the memory function
             // self.sna_version = cpclib_sna::SnapshotVersion::V3; // why changing it ?
 
@@ -1935,6 +2091,32 @@ impl Env {
             self.output_trigger.as_mut().unwrap().write_byte(v);
         }
 
+        if self.crunched_section_state.is_none()
+            && self
+                .options()
+                .assemble_options()
+                .get_flag(crate::AssemblingOptionFlags::MemoryMap)
+        {
+            let section = self.current_section.as_ref().map(|s| s.read().unwrap());
+            self.memory_map.record_byte(
+                &physical_output_address,
+                section.as_ref().map(|s| s.name()),
+                already_used
+            );
+        }
+
+        // Associate the first byte of the token to its source
+        if let Some(span) = self.pending_source_line.take()
+            && self.crunched_section_state.is_none()
+        {
+            self.source_map.push(SourceLine {
+                address: self.logical_code_address(),
+                physical_address: physical_code_address,
+                file: span.filename().to_owned(),
+                line: span.relative_line_and_column().0
+            });
+        }
+
         self.active_page_info_mut().logical_outputadr =
             self.logical_output_address().wrapping_add(1);
         self.output_address = self.logical_output_address();
@@ -2444,7 +2626,28 @@ impl Env {
         w: &mut W,
         fmt: SymbolOutputFormat
     ) -> std::io::Result<()> {
-        self.symbols_output.generate(w, self.symbols(), fmt)
+        match fmt {
+            SymbolOutputFormat::Json => {
+                let sections = self
+                    .sections
+                    .values()
+                    .map(|s| s.read().unwrap().clone())
+                    .sorted_by_key(|s| s.start)
+                    .collect_vec();
+                self.symbols_output
+                    .generate_json(w, self.symbols(), &sections, &self.source_map)
+            },
+            _ => self.symbols_output.generate(w, self.symbols(), fmt)
+        }
+    }
+
+    /// Write in w the memory map
+    pub fn generate_memory_map<W: Write>(
+        &self,
+        w: &mut W,
+        fmt: MemoryMapFormat
+    ) -> std::io::Result<()> {
+        self.memory_map().generate(w, fmt)
     }
 
     /// Visit all the tokens of the slice of tokens.
@@ -2597,7 +2800,10 @@ impl Env {
                         "Use (void) for macros or structs with no parameters to disambiguate them with labels"
                             .to_owned()
                 };
-            if self.options().assemble_options().force_void() {
+            // rasm never requires (void)
+            if self.options().assemble_options().force_void()
+                && !self.options().parse_options().is_rasm()
+            {
                 return Err(Box::new(message));
             }
             else {
@@ -2700,15 +2906,19 @@ impl Env {
     pub fn visit_macro_definition(
         &mut self,
         name: &str,
-        arguments: &[&str],
+        params: &[(&str, Option<&str>)],
         code: &str,
         source: Option<&Z80Span>,
         flavor: AssemblerFlavor,
         has_variadic: bool
     ) -> Result<(), Box<AssemblerError>> {
-        // ignore if it is the very same macro. That can happen with orgams
+        // ignore if it is the very same macro. That can happen with orgams.
+        // rasm refuses it when the macro has already been defined during this pass
         if let Some(r#macro) = self.symbols().macro_value(name)? {
-            if r#macro.code().trim() == code.trim() {
+            if r#macro.code().trim() == code.trim()
+                && !(self.options().parse_options().is_rasm()
+                    && self.symbols().symbol_exist_in_current_pass(name)?)
+            {
                 return Ok(());
             }
             else {
@@ -2750,10 +2960,11 @@ impl Env {
             ))));
         }
 
+        let arguments = params.iter().map(|(name, _)| *name).collect_vec();
         let tokenized_content =
-            cpclib_tokens::macro_segment::tokenize_macro_body(code, arguments, has_variadic);
+            cpclib_tokens::macro_segment::tokenize_macro_body(code, &arguments, has_variadic);
         for index in
-            crate::unused_bindings::unused_macro_parameter_indices(arguments, &tokenized_content)
+            crate::unused_bindings::unused_macro_parameter_indices(&arguments, &tokenized_content)
         {
             let msg = format!("'{}' is never used in this macro's body", arguments[index]);
             match source {
@@ -2783,7 +2994,7 @@ impl Env {
 
         let r#macro = ValueMacro::new(
             name.into(),
-            arguments,
+            params,
             code.to_owned(),
             tokenized_content,
             source,
@@ -2840,6 +3051,36 @@ impl Env {
         Ok(())
     }
 
+    /// rasm gives access to the fields of a structure instance through `<instance>.<field>`,
+    /// where the instance is the label that directly precedes the structure
+    pub fn visit_rasm_struct_instance(
+        &mut self,
+        r#struct: &Struct,
+        span: Option<&Z80Span>
+    ) -> Result<(), Box<AssemblerError>> {
+        let address = self.symbols().current_address()? as i32;
+        let instance = self.symbols().get_current_label().value().to_owned();
+        if self
+            .symbols()
+            .any_value(instance.as_str())?
+            .and_then(|v| v.integer())
+            != Some(address)
+        {
+            return Ok(());
+        }
+
+        let mut offset = 0;
+        for (field, size) in r#struct.fields_size(self.symbols()) {
+            self.symbols_mut().set_symbol_to_value(
+                format!("{instance}.{field}"),
+                ValueAndSource::new(address + offset, span)
+            )?;
+            offset += size;
+        }
+
+        Ok(())
+    }
+
     pub fn visit_buildcpr(&mut self) -> Result<(), Box<AssemblerError>> {
         if self.pass.is_first_pass() {
             self.cpr = Some(CprAssembler::default());
@@ -3073,7 +3314,14 @@ impl Env {
     /// https://grimware.org/doku.php/documentations/devices/gatearray#mmr
     pub fn logical_to_physical_address(&self, address: u16) -> PhysicalAddress {
         match self.output_kind() {
-            OutputKind::Snapshot => MemoryPhysicalAddress::new(address, self.ga_mmr).into(),
+            OutputKind::Snapshot => {
+                match self.slot3_page {
+                    Some(page) if address >= 0xC000 => {
+                        MemoryPhysicalAddress::new_in_bank(address, page / 4, page % 4).into()
+                    },
+                    _ => MemoryPhysicalAddress::new(address, self.ga_mmr).into()
+                }
+            },
             OutputKind::Cpr => {
                 CprPhysicalAddress::new(
                     address,
@@ -3129,6 +3377,7 @@ impl Env {
                 // prefix provided, we explicitely want one configuration
                 let exp = self.resolve_expr_must_never_fail(exp)?.int()?;
                 self.free_banks.selected_index = None;
+                self.slot3_page = None;
 
                 if output_kind == OutputKind::Cpr {
                     if !(0..=31).contains(&exp) {
@@ -3147,6 +3396,12 @@ impl Env {
                         self.output_address = 0
                     }
                 }
+                else if self.options().parse_options().is_rasm() {
+                    self.select_rasm_bank(exp)?;
+                }
+                else if self.options().parse_options().is_sjasmplus() {
+                    self.select_sjasmplus_page(exp)?;
+                }
                 else {
                     // Snapshot output
 
@@ -3158,8 +3413,8 @@ impl Env {
                     let mmr = mmr as u8;
                     self.ga_mmr = mmr;
 
-                    // ensure the page are present in the snapshot
-                    if mmr >= 0xC4 && self.sna.pages_info.len() < 2 {
+                    // ensure the page are present in the snapshot (every mmr but 0xC0 uses the page 1)
+                    if mmr != 0xC0 && self.sna.pages_info.len() < 2 {
                         self.sna.resize(2.max(self.sna.pages_info.len()));
                     }
 
@@ -3202,6 +3457,52 @@ impl Env {
         Ok(())
     }
 
+    /// rasm banks are 16kb blocks: 0 to 3 for the main memory, then the extended memory.
+    /// The bytes of an extended bank are assembled where the gate array maps it (0x4000-0x7FFF)
+    fn select_rasm_bank(&mut self, bank: i32) -> Result<(), Box<AssemblerError>> {
+        if !(0..32).contains(&bank) {
+            return Err(Box::new(AssemblerError::InvalidArgument {
+                msg: format!("{bank} is invalid. BANK only accept values from 0 to 31")
+            }));
+        }
+
+        let bank = bank as u8;
+        let (mmr, address) = rasm_bank_mapping(bank);
+
+        let expected_nb_pages = self.sna.pages_info.len().max(bank as usize / 4 + 1);
+        if expected_nb_pages > self.sna.pages_info.len() {
+            self.sna.resize(expected_nb_pages);
+        }
+
+        self.ga_mmr = mmr;
+        self.output_address = address;
+        let page_info = self.active_page_info_mut();
+        page_info.logical_outputadr = address;
+        page_info.logical_codeadr = address;
+        self.update_dollar();
+
+        Ok(())
+    }
+
+    /// sjasmplus PAGE maps the 16kb page in the slot 3 (0xC000-0xFFFF) and does not move $
+    fn select_sjasmplus_page(&mut self, page: i32) -> Result<(), Box<AssemblerError>> {
+        if !(0..32).contains(&page) {
+            return Err(Box::new(AssemblerError::InvalidArgument {
+                msg: format!("{page} is invalid. PAGE only accept values from 0 to 31")
+            }));
+        }
+
+        let page = page as u8;
+        let expected_nb_pages = self.sna.pages_info.len().max(page as usize / 4 + 1);
+        if expected_nb_pages > self.sna.pages_info.len() {
+            self.sna.resize(expected_nb_pages);
+        }
+
+        self.slot3_page = Some(page);
+
+        Ok(())
+    }
+
     // total switch of page
     fn visit_pageset<E: ExprEvaluationExt>(&mut self, exp: &E) -> Result<(), Box<AssemblerError>> {
         if self.nested_rorg > 0 {
@@ -3234,6 +3535,7 @@ impl Env {
         else {
             self.ga_mmr = 0b1100_0010 + ((page - 1) << 3);
         }
+        self.slot3_page = None;
 
         let page = page as usize;
         let expected_nb_pages = self.sna.pages_info.len().max(page + 1);
@@ -3342,6 +3644,12 @@ impl Env {
         Ok(())
     }
 
+    /// The parser already took the flavor into account; the assembler semantics follow it until the end of the file
+    pub fn visit_flavor(&mut self, flavor: AssemblerFlavor) -> Result<(), Box<AssemblerError>> {
+        self.options.parse.set_flavor(flavor);
+        Ok(())
+    }
+
     pub fn visit_output_file<E: ExprEvaluationExt>(
         &mut self,
         filename: &E
@@ -3372,7 +3680,7 @@ impl Env {
         size: Option<&E>,
         save_type: Option<&SaveType>,
         dsk_fname: Option<&E>,
-        _side: Option<&E>
+        side_or_speed: Option<&E>
     ) -> Result<(), Box<AssemblerError>> {
         if cfg!(target_arch = "wasm32") {
             return Err(Box::new(AssemblerError::AssemblingError {
@@ -3482,11 +3790,21 @@ impl Env {
             }
         }
 
+        if let Some(SaveType::Tape) = &save_type {
+            let tape_fname = dsk_fname.as_ref().unwrap();
+            if !is_tape_image(tape_fname) {
+                return Err(Box::new(AssemblerError::InvalidArgument {
+                    msg: format!("{tape_fname} has not a CDT compatible extension")
+                }));
+            }
+        }
+
         let file = match (save_type, dsk_fname, amsdos_fname) {
             (Some(save_type), Some(dsk_fname), amsdos_fname) => {
                 let support = match save_type {
                     SaveType::Disc(_) => StorageSupport::Disc(dsk_fname),
                     SaveType::Tape => StorageSupport::Tape(dsk_fname),
+                    _ if is_tape_image(&dsk_fname) => StorageSupport::Tape(dsk_fname),
                     _ => StorageSupport::Disc(dsk_fname)
                 };
                 let file_type = match save_type {
@@ -3498,10 +3816,13 @@ impl Env {
                 SaveFile::new(support, (file_type, amsdos_fname))
             },
             (None, Some(dsk_fname), amsdos_fname) => {
-                SaveFile::new(
-                    StorageSupport::Disc(dsk_fname),
-                    (FileType::Auto, amsdos_fname)
-                )
+                let support = if is_tape_image(&dsk_fname) {
+                    StorageSupport::Tape(dsk_fname)
+                }
+                else {
+                    StorageSupport::Disc(dsk_fname)
+                };
+                SaveFile::new(support, (FileType::Auto, amsdos_fname))
             },
             (Some(save_type), None, amsdos_fname) => {
                 let file_type = match save_type {
@@ -3519,6 +3840,23 @@ impl Env {
             },
         };
 
+        // the last argument of a tape save is its speed in bauds
+        let file = match side_or_speed {
+            Some(speed) if file.in_tape() => {
+                let baud = self.resolve_expr_must_never_fail(speed)?.int()?;
+                if !(1..=u16::MAX as i32).contains(&baud) {
+                    return Err(Box::new(AssemblerError::InvalidArgument {
+                        msg: format!("{baud} is not a valid tape speed")
+                    }));
+                }
+                file.with_tape_options(CdtWriteOptions {
+                    baud: baud as u16,
+                    ..Default::default()
+                })
+            },
+            _ => file
+        };
+
         //       eprintln!("MMR at save=0x{:x}", self.ga_mmr);
         let mmr = self.ga_mmr;
         let page_info = self.active_page_info_mut();
@@ -3577,6 +3915,23 @@ impl Env {
         self.output_bytes(data)
     }
 
+    /// Crunch the data through the on-disk cache when one is configured
+    pub fn compress(
+        &self,
+        kind: &CrunchType,
+        raw: &[u8]
+    ) -> Result<AssemblerCompressionResult, Box<AssemblerError>> {
+        let options = self.options().assemble_options();
+        match options.cache() {
+            // nothing is written on disk in a dry run
+            Some(cache) if options.dry_run() && !cache.read_only() => {
+                cache.clone().set_read_only(true).compress(kind, raw)
+            },
+            Some(cache) => cache.compress(kind, raw),
+            None => kind.compress(raw)
+        }
+    }
+
     fn build_crunched_section_env(&mut self, span: Option<&Z80Span>) -> Self {
         let mut crunched_env = self.clone();
         crunched_env.crunched_section_state = CrunchedSectionState::new(span.cloned()).into();
@@ -3686,7 +4041,7 @@ impl Env {
                 AssemblerCompressionResult::empty()
             }
             else {
-                kind.compress(&new_bytes_to_crunch).map_err(|e| {
+                self.compress(kind, &new_bytes_to_crunch).map_err(|e| {
                     match span {
                         Some(span) => {
                             AssemblerError::RelocatedError {
@@ -3806,8 +4161,10 @@ impl Env {
             lookup_directory_stack: Vec::with_capacity(3),
             pass: AssemblingPass::Uninitialized,
             options: EnvOptions::default(),
+            default_flavor: AssemblerFlavor::Basm,
             stable_counters: StableTickerCounters::default(),
             ga_mmr: 0xC0, // standard memory configuration
+            slot3_page: None,
 
             macro_seed: 0,
             charset_encoding: CharsetEncoding::new(),
@@ -3821,6 +4178,11 @@ impl Env {
             byte_written: false,
             output_trigger: None,
             symbols_output: Default::default(),
+            pending_source_line: None,
+            source_map: Vec::new(),
+            memory_map: MemoryMapRecorder::default(),
+            unit_tests: Vec::new(),
+            stabilized_returns: 0,
 
             crunched_section_state: None,
 
@@ -3848,6 +4210,7 @@ impl Env {
             previous_pass_discarded_errors: HashSet::default(),
 
             included_paths: HashSet::default(),
+            read_files: Default::default(),
 
             extra_print_from_function: Vec::new().into(),
             extra_failed_assert_from_function: Vec::new().into(),
@@ -3861,6 +4224,7 @@ impl Env {
             assembling_control_current_output_commands: Vec::new()
         };
 
+        env.default_flavor = options.parse_options().assembler_flavor;
         env.options = options;
 
         // prefill the snapshot representation with something else than the default
@@ -4095,9 +4459,15 @@ macro_rules! visit_token_impl {
             $cls::Even => $env.visit_even(),
 
             $cls::Fail(exp) => $env.visit_fail(exp.as_ref().map(|v| v.as_slice())),
+            $cls::Flavor(flavor) => $env.visit_flavor(*flavor),
             $cls::Warning(exp) => $env.visit_warning(exp.as_ref().map(|v| v.as_slice())),
             $cls::Field { label, expr, .. } => $env.visit_field(label, expr),
 
+            $cls::IncImage {
+                fname,
+                mode,
+                format
+            } => $env.visit_incimg(fname, mode, format, $span),
             $cls::Label(label) => $env.visit_label(label),
             $cls::Limit(exp) => $env.visit_limit(exp),
             $cls::List => {
@@ -4188,6 +4558,9 @@ macro_rules! visit_token_impl {
             },
 
             $cls::Undef(label) => $env.visit_undef(label),
+            $cls::UnitTest { name, steps } => {
+                $env.visit_unit_test(name.as_ref(), steps.as_slice(), $span)
+            },
             $cls::WaitNops(count) => $env.visit_waitnops(count),
 
             $cls::Include(..)
@@ -4422,7 +4795,7 @@ impl Env {
         self.warn_if_counter_unused(counter_name, span, "ITERATE loop");
 
         // TODO restore a previous value if any
-        self.symbols_mut().remove_symbol(counter_name)?;
+        self.remove_counter(counter_name)?;
 
         Ok(())
     }
@@ -4542,6 +4915,113 @@ impl Env {
         visit_processed_tokens(lst, self)
     }
 
+    /// Assemble a STABILIZE block: the cheaper arm of each conditional branch is padded with nops.
+    /// When the cheaper arm is an early `RET cc`, it becomes a `JR cc` to a padded `RET` assembled after the block
+    pub fn visit_stabilize<'token, E, T>(
+        &mut self,
+        tokens: &[&T],
+        lst: &mut [ProcessedToken<'token, T>],
+        span: Option<&Z80Span>
+    ) -> Result<(), Box<AssemblerError>>
+    where
+        E: ExprEvaluationExt + Sync,
+        T: ListingElement<Expr = E> + Visited + MayHaveSpan + Sync,
+        <T as cpclib_tokens::ListingElement>::Expr: ExprEvaluationExt + ExprElement,
+        <<T as cpclib_tokens::ListingElement>::TestKind as TestKindElement>::Expr:
+            ExprEvaluationExt,
+        ProcessedToken<'token, T>: FunctionBuilder
+    {
+        let locate = |msg: String| {
+            let e = AssemblerError::AssemblingError {
+                msg: format!("STABILIZE error: {msg}")
+            };
+            Box::new(match span {
+                Some(span) => e.locate(span.clone()),
+                None => e
+            })
+        };
+
+        let mut edits =
+            crate::branch_balance::balance_branches(tokens, crate::timing::instruction_cost)
+                .map_err(locate)?;
+        edits.reverse();
+
+        let mut tails = Vec::new();
+        let mut start = 0;
+        for edit in edits {
+            match edit {
+                StabilizeEdit::InsertPadding {
+                    insert_before_index,
+                    nop_count
+                } => {
+                    visit_processed_tokens(&mut lst[start..insert_before_index], self)?;
+                    self.visit_waitnops(&Expr::Value(nop_count as _))?;
+                    start = insert_before_index;
+                },
+                StabilizeEdit::RewriteConditionalRetAndPad {
+                    ret_token_index,
+                    nop_count
+                } => {
+                    // JR cc + RET last 2 nops more than RET cc
+                    let Some(nop_count) = nop_count.checked_sub(2)
+                    else {
+                        return Err(locate(format!(
+                            "the early return is {nop_count} nop(s) faster than the other path and needs at least 2"
+                        )));
+                    };
+                    let ends_with_jump = tokens
+                        .iter()
+                        .rev()
+                        .find_map(|t| t.mnemonic().map(|m| (m, t.mnemonic_arg1())))
+                        .is_some_and(|(m, arg)| {
+                            matches!(m, Mnemonic::Ret | Mnemonic::Jp | Mnemonic::Jr)
+                                && !arg.is_some_and(|arg| arg.is_flag_test())
+                        });
+                    if !ends_with_jump {
+                        return Err(locate(
+                            "the early return can only be padded when the block ends with RET, JP or JR"
+                                .to_owned()
+                        ));
+                    }
+
+                    visit_processed_tokens(&mut lst[start..ret_token_index], self)?;
+                    let flag = tokens[ret_token_index]
+                        .mnemonic_arg1()
+                        .and_then(|arg| arg.get_flag_test())
+                        .unwrap();
+                    let label = self
+                        .symbols()
+                        .extend_local_and_patterns_for_symbol(format!(
+                            ".__hidden__stabilize_{}",
+                            self.stabilized_returns
+                        ))?
+                        .value()
+                        .to_owned();
+                    self.stabilized_returns += 1;
+                    self.visit_token(&Token::OpCode(
+                        Mnemonic::Jr,
+                        Some(DataAccess::FlagTest(flag)),
+                        Some(DataAccess::Expression(Expr::Label(label.as_str().into()))),
+                        None
+                    ))?;
+                    tails.push((label, nop_count));
+                    start = ret_token_index + 1;
+                }
+            }
+        }
+        visit_processed_tokens(&mut lst[start..], self)?;
+
+        for (label, nop_count) in tails {
+            let value = self.symbols().current_address().unwrap_or_default();
+            let addr = self.logical_to_physical_address(value);
+            self.add_symbol_to_symbol_table(&label, addr, span.map(|s| s.into()))?;
+            self.visit_waitnops(&Expr::Value(nop_count as _))?;
+            self.visit_token(&Token::OpCode(Mnemonic::Ret, None, None, None))?;
+        }
+
+        Ok(())
+    }
+
     /// Handle the for directive
     pub fn visit_for<'token, E, T>(
         &mut self,
@@ -4703,13 +5183,34 @@ impl Env {
     /// kinds - see that module's doc comment for why MACRO parameters
     /// can't use this real-time approach at all (pure text substitution,
     /// never touches the symbol table).
+    /// rasm also gives access to the counter of a loop without the braces
+    fn rasm_counter_name<'n>(&self, bracketed_counter_name: &'n str) -> Option<&'n str> {
+        self.options().parse_options().is_rasm().then(|| {
+            bracketed_counter_name
+                .trim_start_matches('{')
+                .trim_end_matches('}')
+        })
+    }
+
+    fn remove_counter(&mut self, bracketed_counter_name: &str) -> Result<(), Box<AssemblerError>> {
+        self.symbols_mut().remove_symbol(bracketed_counter_name)?;
+        if let Some(plain_counter_name) = self.rasm_counter_name(bracketed_counter_name) {
+            self.symbols_mut().remove_symbol(plain_counter_name)?;
+        }
+        Ok(())
+    }
+
     fn warn_if_counter_unused(
         &mut self,
         bracketed_counter_name: &str,
         span: Option<&Z80Span>,
         construct: &str
     ) {
-        if self.symbols().is_used(bracketed_counter_name) {
+        if self.symbols().is_used(bracketed_counter_name)
+            || self
+                .rasm_counter_name(bracketed_counter_name)
+                .is_some_and(|name| self.symbols().is_used(name))
+        {
             return;
         }
         let name = bracketed_counter_name
@@ -4801,7 +5302,7 @@ impl Env {
 
         if let Some(counter_name) = counter_name {
             self.warn_if_counter_unused(counter_name, span, "REPEAT loop");
-            self.symbols_mut().remove_symbol(counter_name)?;
+            self.remove_counter(counter_name)?;
         }
         Ok(())
     }
@@ -4832,6 +5333,10 @@ impl Env {
         if let Some(counter_name) = counter_name {
             self.symbols_mut()
                 .set_symbol_to_value(counter_name, counter_value.clone().unwrap())?;
+            if let Some(plain_counter_name) = self.rasm_counter_name(counter_name) {
+                self.symbols_mut()
+                    .set_symbol_to_value(plain_counter_name, counter_value.clone().unwrap())?;
+            }
 
             let depth = self.symbols().counter_depth() + 1;
 
@@ -5941,6 +6446,17 @@ impl Env {
     }
 }
 
+/// Gate array configuration and address of a rasm (or sjasmplus) 16kb bank.
+/// The banks 0 to 3 are the main memory, the others are mapped in 0x4000-0x7FFF
+pub(crate) fn rasm_bank_mapping(bank: u8) -> (u8, u16) {
+    if bank < 4 {
+        (0xC0, bank as u16 * 0x4000)
+    }
+    else {
+        (0xC0 | ((bank / 4 - 1) << 3) | (4 + bank % 4), 0x4000)
+    }
+}
+
 /// Converts an absolute address to a relative one (relative to $)
 pub fn absolute_to_relative<T: AsRef<SymbolsTable>>(
     address: i32,
@@ -7492,6 +8008,246 @@ impl Env {
         }
     }
 
+    /// Collect a TEST block. Its expressions are evaluated now because the routines are called once assembling is over
+    pub fn visit_unit_test<S: UnitTestStepElement>(
+        &mut self,
+        name: &str,
+        steps: &[S],
+        span: Option<&Z80Span>
+    ) -> Result<(), Box<AssemblerError>> {
+        let mut resolved = Vec::with_capacity(steps.len());
+        for step in steps {
+            let value = step
+                .step()
+                .try_map(|exp| Ok(self.resolve_expr_may_fail_in_first_pass(exp)?.int()?))
+                .map_err(|e: Box<AssemblerError>| {
+                    match step.possible_span() {
+                        Some(span) => Box::new(e.locate(span.clone())),
+                        None => e
+                    }
+                })?;
+            resolved.push((value, step.possible_span().cloned()));
+        }
+
+        self.unit_tests
+            .push(UnitTest::new(name.to_owned(), span.cloned(), resolved));
+        Ok(())
+    }
+
+    /// Add a symbol relative to the current label
+    fn add_local_symbol<V: Into<Value>>(
+        &mut self,
+        name: &str,
+        value: V,
+        span: Option<&Z80Span>
+    ) -> Result<(), Box<AssemblerError>> {
+        let label = self
+            .symbols()
+            .extend_local_and_patterns_for_symbol(name)?
+            .value()
+            .to_owned();
+        self.add_symbol_to_symbol_table(&label, value, span.map(|s| s.into()))
+    }
+
+    /// Convert an image and emit its bytes. Its size and its inks (firmware numbers) are stored in
+    /// the local symbols `.width` (in bytes), `.height` (in lines) and `.ink0`, `.ink1`, ... of the
+    /// current label. Masked sprites also define `.mask` and tiles `.count`
+    pub fn visit_incimg<E: ExprEvaluationExt + Debug>(
+        &mut self,
+        fname: &E,
+        mode: &E,
+        format: &ImageFormat<E>,
+        span: Option<&Z80Span>
+    ) -> Result<(), Box<AssemblerError>> {
+        use cpclib_image::convert::{
+            CPCScreenDimension, DisplayCRTCAddress, GridHeightCapture, GridWidthCapture,
+            ImageConverter, Output, OutputFormat, SpriteEncoding, TileHeightCapture,
+            TileHorizontalCapture, TileVerticalCapture, TileWidthCapture, TransformationsList
+        };
+        use cpclib_image::ga::{Ink, LockablePalette, Pen};
+        use cpclib_image::image::Mode;
+
+        if cfg!(target_arch = "wasm32") {
+            return Err(Box::new(AssemblerError::AssemblingError {
+                msg: "INCIMG is not allowed in a web-based assembling.".into()
+            }));
+        }
+
+        let fname = self.build_fname(fname)?;
+        let fname = get_filename_to_read(&fname, self.options().parse_options(), Some(self))?;
+
+        let mode = match self.resolve_expr_must_never_fail(mode)?.int()? {
+            0 => Mode::Zero,
+            1 => Mode::One,
+            2 => Mode::Two,
+            mode => {
+                return Err(Box::new(AssemblerError::AssemblingError {
+                    msg: format!("INCIMG error: {mode} is not a valid mode")
+                }));
+            }
+        };
+
+        let format = format.try_map(|e| {
+            Ok::<_, Box<AssemblerError>>(self.resolve_expr_must_never_fail(e)?.int()?)
+        })?;
+        let ink = |firmware_number: i32| {
+            usize::try_from(firmware_number)
+                .ok()
+                .filter(|&number| number < 27)
+                .map(|number| Ink::INKS[number])
+                .ok_or_else(|| {
+                    Box::new(AssemblerError::AssemblingError {
+                        msg: format!("INCIMG error: {firmware_number} is not a valid ink")
+                    })
+                })
+        };
+        let output_format = match format {
+            ImageFormat::Sprite => OutputFormat::Sprite(SpriteEncoding::Linear),
+            ImageFormat::MaskedSprite {
+                mask_ink,
+                replacement_ink
+            } => {
+                OutputFormat::MaskedSprite {
+                    sprite_format: SpriteEncoding::Linear,
+                    mask_ink: ink(mask_ink)?,
+                    replacement_ink: ink(replacement_ink)?
+                }
+            },
+            ImageFormat::Tiles { width, height } => {
+                if width < 1 || height < 1 {
+                    return Err(Box::new(AssemblerError::AssemblingError {
+                        msg: format!("INCIMG error: {width}x{height} is not a valid tile size")
+                    }));
+                }
+                OutputFormat::TileEncoded {
+                    tile_width: TileWidthCapture::NbBytes(width as _),
+                    tile_height: TileHeightCapture::NbLines(height as _),
+                    horizontal_movement: TileHorizontalCapture::AlwaysFromLeftToRight,
+                    vertical_movement: TileVerticalCapture::AlwaysFromTopToBottom,
+                    grid_width: GridWidthCapture::FullWidth,
+                    grid_height: GridHeightCapture::FullHeight
+                }
+            },
+            ImageFormat::Screen => {
+                OutputFormat::CPCMemory {
+                    output_dimension: CPCScreenDimension::standard(),
+                    display_address: DisplayCRTCAddress::new_standard_from_page(3)
+                }
+            },
+            ImageFormat::Overscan => {
+                OutputFormat::CPCMemory {
+                    output_dimension: CPCScreenDimension::overscan(),
+                    display_address: DisplayCRTCAddress::new_overscan_from_page(2)
+                }
+            },
+        };
+
+        let output = ImageConverter::convert(
+            &fname,
+            LockablePalette::empty(),
+            mode,
+            TransformationsList::default(),
+            output_format,
+            false,
+            None,
+            &*self.observer()
+        )
+        .map_err(|e| {
+            Box::new(AssemblerError::AssemblingError {
+                msg: format!("INCIMG error: unable to convert {fname}. {e}")
+            })
+        })?;
+
+        let mut data = Vec::new();
+        let mut mask = None;
+        let mut count = None;
+        let (width, height, palette) = match output {
+            Output::Sprite(sprite) => {
+                data.extend_from_slice(sprite.data());
+                (
+                    sprite.bytes_width(),
+                    sprite.height(),
+                    sprite.palette().clone()
+                )
+            },
+            Output::SpriteAndMask {
+                sprite,
+                mask: sprite_mask
+            } => {
+                data.extend_from_slice(sprite.data());
+                mask = Some(sprite_mask.data().to_vec());
+                (
+                    sprite.bytes_width(),
+                    sprite.height(),
+                    sprite.palette().clone()
+                )
+            },
+            Output::TilesList {
+                tile_width,
+                tile_height,
+                palette,
+                list,
+                ..
+            } => {
+                count = Some(list.len());
+                data = list.concat();
+                (tile_width as usize, tile_height as usize, palette)
+            },
+            Output::CPCMemoryStandard(memory, palette) => {
+                let dimension = CPCScreenDimension::standard();
+                data.extend_from_slice(&memory);
+                (
+                    dimension.nb_byte_columns() as usize,
+                    dimension.height() as usize,
+                    palette
+                )
+            },
+            Output::CPCMemoryOverscan(first, second, palette) => {
+                let dimension = CPCScreenDimension::overscan();
+                data.extend_from_slice(&first);
+                if let Some(second) = second {
+                    data.extend_from_slice(&second);
+                }
+                (
+                    dimension.nb_byte_columns() as usize,
+                    dimension.height() as usize,
+                    palette
+                )
+            },
+            other => {
+                return Err(Box::new(AssemblerError::BugInAssembler {
+                    file: file!(),
+                    line: line!(),
+                    msg: format!("INCIMG: unexpected conversion output {other:?}")
+                }));
+            }
+        };
+
+        let mut values = vec![
+            (".width".to_owned(), width as i32),
+            (".height".to_owned(), height as i32),
+        ];
+        values.extend(count.map(|count| (".count".to_owned(), count as i32)));
+        for pen in 0..mode.max_colors() {
+            if let Some(ink) = palette.safe_get(&Pen::from(pen as u8)) {
+                values.push((format!(".ink{pen}"), i32::from(ink.firmware_number())));
+            }
+        }
+        for (name, value) in values {
+            self.add_local_symbol(&name, value, span)?;
+        }
+
+        self.output_bytes(&data)?;
+        if let Some(mask) = mask {
+            let value = self.symbols().current_address().unwrap_or_default();
+            let addr = self.logical_to_physical_address(value);
+            self.add_local_symbol(".mask", addr, span)?;
+            self.output_bytes(&mask)?;
+        }
+
+        Ok(())
+    }
+
     pub fn visit_stableticker<S: AsRef<str>>(
         &mut self,
         stable: &StableTickerAction<S>
@@ -25,12 +25,11 @@ use super::file::{get_filename_to_read, load_file, read_source};
 use super::function::{Function, FunctionBuilder};
 use super::r#macro::Expandable;
 use crate::implementation::expression::ExprEvaluationExt;
-use crate::implementation::instructions::Compressor;
 use crate::preamble::{LocatedListing, MayHaveSpan, SourceString, Z80Span};
 use crate::progress::{self, Progress};
 use crate::{
     AssemblerCompressionResult, AssemblerError, Env, LocatedToken, Visited, r#macro,
-    parse_z80_with_context_builder
+    parse_z80_with_cache, parse_z80_with_context_builder
 };
 
 /// Tokens are read only elements extracted from the parser
@@ -50,6 +49,8 @@ enum ProcessedTokenState<'token, T: Visited + ListingElement + Debug + Sync> {
         commands: Option<ControlOutputStore>
     },
     Confined(SimpleListingState<'token, T>),
+    /// The branches are balanced at each visit as the durations may depend on the pass
+    Stabilize(SimpleListingState<'token, T>),
     CrunchedSection {
         /// The token to assemble
         listing: SimpleListingState<'token, T>,
@@ -169,7 +170,11 @@ impl IncludeState {
                     .context_builder()
                     .set_current_filename(fname.clone());
 
-                let listing = parse_z80_with_context_builder(content, builder)?;
+                let listing = parse_z80_with_cache(
+                    content,
+                    builder,
+                    options.assemble_options().listing_cache().as_ref()
+                )?;
 
                 // Remove the progression
                 if options.show_progress() {
@@ -229,7 +234,13 @@ impl IncludeState {
                 //.map_err(|e| e.locate(span.clone()))?;
             }
 
-            // Visit the included listing
+            // Visit the included listing. A FLAVOR directive only applies to its own file
+            let flavor = env
+                .read()
+                .unwrap()
+                .options()
+                .parse_options()
+                .assembler_flavor;
             env.write()
                 .unwrap()
                 .enter_current_working_file(fname.clone());
@@ -242,6 +253,7 @@ impl IncludeState {
                 })
             };
             env.write().unwrap().leave_current_working_file();
+            env.write().unwrap().visit_flavor(flavor)?;
             res?;
 
             // Remove module if necessary
@@ -370,7 +382,10 @@ where <T as cpclib_tokens::ListingElement>::Expr: ExprEvaluationExt + Sync
         request_additional_pass: &mut bool
     ) -> Result<bool, Box<AssemblerError>> {
         let label = test.label_unchecked();
-        let is_used = env.symbols().is_used(label);
+        // rasm also considers the labels already defined as used
+        let is_used = env.symbols().is_used(label)
+            || (env.options().parse_options().is_rasm()
+                && env.symbols().symbol_exist_in_current_pass(label)?);
         let decision = if test.is_label_used_test() {
             is_used
         }
@@ -562,19 +577,31 @@ where
             env
         )?))
     }
+    else if token.is_stabilize() {
+        Some(ProcessedTokenState::Stabilize(build_simple_listing_state(
+            token.stabilize_listing(),
+            span.clone(),
+            env
+        )?))
+    }
     else if token.is_if() {
         let state = IfState::new(token);
         Some(ProcessedTokenState::If(state))
     }
     else if token.is_include() {
-        let (fname_result, options) = {
+        let (fname_result, options, cache) = {
             let mut env_guard = env.write().unwrap();
             let env: &mut Env = *env_guard;
 
             let options = env.options().parse_options().clone();
+            let cache = env.options().assemble_options().listing_cache();
             let fname = token.include_fname();
             let fname = env.build_fname(fname)?;
-            (get_filename_to_read(fname, &options, Some(env)), options)
+            (
+                get_filename_to_read(fname, &options, Some(env)),
+                options,
+                cache
+            )
         };
         let options = &options;
 
@@ -587,7 +614,7 @@ where
                             .context_builder()
                             .set_current_filename(fname.clone());
 
-                        match parse_z80_with_context_builder(content, ctx_builder) {
+                        match parse_z80_with_cache(content, ctx_builder, cache.as_ref()) {
                             Ok(listing) => {
                                 // Filename has already been added
                                 if token.include_is_standard_include()
@@ -938,6 +965,9 @@ where <T as ListingElement>::Expr: ExprEvaluationExt + Sync
             // get the generated code
             // TODO handle some errors there
             let (source, code, _flavor) = if let Some(r#macro) = &r#macro {
+                for warning in r#macro.ambiguity_warnings() {
+                    env.add_warning(Box::new(warning));
+                }
                 let source = r#macro.source();
                 let flavor = r#macro.flavor();
                 let code = r#macro.expand(env)?;
@@ -947,6 +977,12 @@ where <T as ListingElement>::Expr: ExprEvaluationExt + Sync
                 let r#struct = r#struct
                     .as_ref()
                     .expect("BUG: r#struct should be Some when r#macro is None");
+                if env.options().parse_options().is_rasm() {
+                    env.visit_rasm_struct_instance(
+                        r#struct.r#struct(),
+                        self.token.possible_span()
+                    )?;
+                }
                 (
                     r#struct.source(),
                     r#struct.expand(env)?,
@@ -1010,6 +1046,8 @@ where
 
         // Always work with Arc<RwLock<&mut Env>>
         let mut really_does_the_job = |possible_span: Option<&Z80Span>| {
+            env.handle_source_line(possible_span);
+
             let deferred = self.token.defer_listing_output();
             if !deferred {
                 // dbg!(&self.token, deferred);
@@ -1029,11 +1067,16 @@ where
             if self.token.is_macro_definition() {
                 // TODO really implement logic here
                 let name = self.token.macro_definition_name();
-                let arguments = self.token.macro_definition_arguments();
+                let params = self
+                    .token
+                    .macro_definition_arguments()
+                    .into_iter()
+                    .zip(self.token.macro_definition_defaults())
+                    .collect_vec();
                 let code = self.token.macro_definition_code();
                 env.visit_macro_definition(
                     name,
-                    &arguments,
+                    &params,
                     code,
                     self.possible_span(),
                     self.token.macro_flavor(),
@@ -1074,6 +1117,13 @@ where
                         processed_tokens,
                         span
                     })) => env.visit_confined(processed_tokens, span.as_ref()),
+                    Some(ProcessedTokenState::Stabilize(SimpleListingState {
+                        processed_tokens,
+                        span
+                    })) => {
+                        let tokens = processed_tokens.iter().map(|t| t.token).collect_vec();
+                        env.visit_stabilize(&tokens, processed_tokens, span.as_ref())
+                    },
                     Some(ProcessedTokenState::CrunchedSection {
                         listing:
                             SimpleListingState {
@@ -1261,7 +1311,7 @@ where
 
                                 let crunch_type = other.crunch_type()
                                     .expect("BUG: crunch_type should return Some for non-None transformation");
-                                let result = crunch_type.compress(data)?;
+                                let result = env.compress(&crunch_type, data)?;
                                 Cow::Owned(result.to_vec()) // TODO store the delta somewhere to allow a reuse
                             }
                         };
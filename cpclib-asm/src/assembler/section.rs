@@ -17,7 +17,7 @@ pub struct Section {
 }
 
 impl Section {
-    pub(crate) fn new(name: &str, start: u16, stop: u16, mmr: u8) -> Self {
+    pub fn new(name: &str, start: u16, stop: u16, mmr: u8) -> Self {
         Section {
             mmr,
             name: name.to_owned(),
@@ -31,6 +31,22 @@ impl Section {
         }
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn start(&self) -> u16 {
+        self.start
+    }
+
+    pub fn stop(&self) -> u16 {
+        self.stop
+    }
+
+    pub fn mmr(&self) -> u8 {
+        self.mmr
+    }
+
     pub fn contains(&self, addr: u16) -> bool {
         addr >= self.start && addr <= self.stop
     }
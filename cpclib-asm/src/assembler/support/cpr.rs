@@ -73,6 +73,10 @@ impl CprAssembler {
         self.pages.page_infos()
     }
 
+    pub fn blocs_page_infos(&self) -> impl Iterator<Item = (u8, &PageInformation)> {
+        self.codes.iter().map(|c| c.0).zip(self.pages.page_infos())
+    }
+
     // pub fn selected_bank_mut(&mut self) -> Option<&mut CartridgeBank> {
     // self.selected_index.as_ref()
     // .map(|&idx| self.cpr.bank_mut(idx).unwrap())
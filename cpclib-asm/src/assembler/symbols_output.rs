@@ -4,21 +4,80 @@ use std::str::FromStr;
 use cpclib_common::itertools::Itertools;
 use cpclib_sna::{AceSymbol, AceSymbolChunk, AceSymbolType, RemuChunk, RemuEntry};
 use cpclib_tokens::ExprResult;
-use cpclib_tokens::symbols::{Symbol, SymbolsTableTrait, Value};
+use cpclib_tokens::symbols::{
+    MemoryPhysicalAddress, PhysicalAddress, Symbol, SymbolsTableTrait, Value
+};
+use serde::Serialize;
+
+use super::section::Section;
 
 pub const NEVER_EXPORTED_SYMBOLS: &[&str] =
     &["$", "$$", "BASM_VERSION", "BASM", "BASM_FEATURE_HFE"];
 
 pub enum SymbolOutputFormat {
     Basm,
-    Winape
+    Winape,
+    /// `label #1234 B0` with the 16kb bank of the labels
+    Rasm,
+    /// `label: EQU 0x00001234`
+    Sjasmplus,
+    /// ACE reads the rasm convention
+    Ace,
+    /// `00:1234 label` with the 16kb bank of the labels
+    Nocash,
+    /// Symbols with their kind, section, memory configuration and source, plus the address of each line of source
+    Json
+}
+
+/// Address of the first byte generated by a line of source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    pub address: u16,
+    pub physical_address: PhysicalAddress,
+    pub file: String,
+    pub line: usize
+}
+
+/// Integer value of a symbol, if any
+fn numeric_value(v: &Value) -> Option<i32> {
+    match v {
+        Value::Address(a) => Some(a.address() as i32),
+        Value::Expr(ExprResult::Value(i)) => Some(*i),
+        Value::Expr(e @ ExprResult::Float(_)) => e.int().ok(),
+        Value::Expr(ExprResult::Char(c)) => Some(*c as i32),
+        _ => None
+    }
+}
+
+/// 16kb bank of the symbol, as numbered by rasm (0-3 for the main memory, 4 and more for the extended memory)
+fn rasm_bank(v: &Value) -> usize {
+    match v {
+        Value::Address(PhysicalAddress::Memory(a)) => (a.offset_in_cpc() / 0x4000) as usize,
+        Value::Address(PhysicalAddress::Bank(a)) => a.bank(),
+        Value::Address(PhysicalAddress::Cpr(a)) => a.bloc() as usize,
+        _ => 0
+    }
 }
 
 impl SymbolOutputFormat {
-    pub fn format(&self, k: &Symbol, v: &Value) -> String {
+    /// Line of the symbol in the format, or None when the format cannot represent it.
+    /// JSON has no line per symbol and is produced as a whole by [SymbolOutputGenerator::generate_json]
+    pub fn format(&self, k: &Symbol, v: &Value) -> Option<String> {
         match self {
+            SymbolOutputFormat::Rasm | SymbolOutputFormat::Ace => {
+                numeric_value(v)
+                    .map(|i| format!("{} #{:04X} B{}", k.value(), i as u16, rasm_bank(v)))
+            },
+            SymbolOutputFormat::Sjasmplus => {
+                numeric_value(v).map(|i| format!("{}: EQU 0x{:08X}", k.value(), i as u32))
+            },
+            SymbolOutputFormat::Nocash => {
+                numeric_value(v)
+                    .map(|i| format!("{:02x}:{:04x} {}", rasm_bank(v), i as u16, k.value()))
+            },
+            SymbolOutputFormat::Json => None,
             SymbolOutputFormat::Basm => {
-                match v {
+                let line = match v {
                     Value::Address(a) => {
                         format!("{} equ #{:04X}", k.value(), a.address())
                     },
@@ -52,30 +111,25 @@ impl SymbolOutputFormat {
                     },
 
                     _ => unimplemented!("{:?}", v)
-                }
+                };
+                Some(line)
             },
             SymbolOutputFormat::Winape => {
                 match v {
-                    Value::Address(a) => {
-                        format!("{} #{:X}", k.value(), a.address())
-                    },
-                    Value::Expr(ExprResult::Value(i)) => {
-                        format!("{} #{:X}", k.value(), i)
-                    },
-                    Value::Expr(ExprResult::Bool(b)) => {
-                        format!("{} {}", k.value(), *b)
-                    },
+                    Value::Address(a) => Some(format!("{} #{:X}", k.value(), a.address())),
+                    Value::Expr(ExprResult::Value(i)) => Some(format!("{} #{:X}", k.value(), i)),
+                    Value::Expr(ExprResult::Bool(b)) => Some(format!("{} {}", k.value(), *b)),
                     Value::Expr(e @ ExprResult::Float(_f)) => {
-                        format!("{} #{:X}", k.value(), e.int().unwrap())
+                        Some(format!("{} #{:X}", k.value(), e.int().unwrap()))
                     },
                     Value::Expr(ExprResult::String(_s)) => {
-                        "".to_owned() // ignored by winape
+                        None // ignored by winape
                     },
                     Value::Expr(_l @ ExprResult::List(_)) => {
-                        "".to_owned() // ignored by winape
+                        None // ignored by winape
                     },
                     Value::Expr(_m @ ExprResult::Matrix { .. }) => {
-                        "".to_owned() // ignored by winape
+                        None // ignored by winape
                     },
 
                     _ => unimplemented!("{:?}", v)
@@ -92,11 +146,92 @@ impl FromStr for SymbolOutputFormat {
         match s.to_ascii_lowercase().as_str() {
             "basm" => Ok(Self::Basm),
             "winape" => Ok(Self::Winape),
+            "rasm" => Ok(Self::Rasm),
+            "sjasmplus" => Ok(Self::Sjasmplus),
+            "ace" => Ok(Self::Ace),
+            "nocash" | "no$" => Ok(Self::Nocash),
+            "json" => Ok(Self::Json),
             _ => Err(format!("Wrong symbol format {s}"))
         }
     }
 }
 
+/// Memory configuration where a label or a line of source is assembled
+#[derive(Serialize)]
+struct JsonMemory {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bank: Option<usize>,
+    /// Gate array value that maps the bank in 0x4000-0x7fff
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mmr: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cpr_bloc: Option<u8>
+}
+
+impl From<&PhysicalAddress> for JsonMemory {
+    fn from(address: &PhysicalAddress) -> Self {
+        let mut memory = JsonMemory {
+            page: None,
+            bank: None,
+            mmr: None,
+            cpr_bloc: None
+        };
+        match address {
+            PhysicalAddress::Memory(a) => {
+                memory.page = Some(a.page());
+                memory.bank = Some(a.bank() as usize);
+                memory.mmr = Some((a.ga_bank() & 0xFF) as u8);
+            },
+            PhysicalAddress::Bank(a) => memory.bank = Some(a.bank()),
+            PhysicalAddress::Cpr(a) => memory.cpr_bloc = Some(a.bloc())
+        }
+        memory
+    }
+}
+
+#[derive(Serialize)]
+struct JsonSymbol<'a> {
+    name: &'a str,
+    kind: &'static str,
+    value: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    section: Option<&'a str>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    memory: Option<JsonMemory>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    column: Option<usize>
+}
+
+#[derive(Serialize)]
+struct JsonSection<'a> {
+    name: &'a str,
+    start: u16,
+    stop: u16,
+    mmr: u8
+}
+
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    address: u16,
+    #[serde(flatten)]
+    memory: JsonMemory,
+    file: &'a str,
+    line: usize
+}
+
+#[derive(Serialize)]
+struct JsonDebugMap<'a> {
+    symbols: Vec<JsonSymbol<'a>>,
+    sections: Vec<JsonSection<'a>>,
+    lines: Vec<JsonLine<'a>>
+}
+
 /// Manage the generation of the symbols output.
 /// Could be parametrize by some directives
 #[derive(Clone)]
@@ -204,18 +339,111 @@ impl SymbolOutputGenerator {
         symbs: &impl SymbolsTableTrait,
         format: SymbolOutputFormat
     ) -> std::io::Result<()> {
-        for (k, v) in symbs
+        if let SymbolOutputFormat::Json = format {
+            return self.generate_json(w, symbs, &[], &[]);
+        }
+
+        // symbols that cannot be represented in the format are skipped
+        for line in symbs
             .expression_symbol()
             .iter()
             .filter(|(s, _v)| self.keep_symbol(s))
             .sorted_by_key(|(s, _v)| s.to_string().to_ascii_lowercase())
+            .filter_map(|(k, v)| format.format(k, v))
         {
-            writeln!(w, "{}", format.format(k, v))?;
+            writeln!(w, "{line}")?;
         }
 
         Ok(())
     }
 
+    /// Generate the JSON debug map in w
+    pub fn generate_json<W: Write>(
+        &self,
+        w: &mut W,
+        symbs: &impl SymbolsTableTrait,
+        sections: &[Section],
+        source_map: &[SourceLine]
+    ) -> std::io::Result<()> {
+        let symbols = symbs
+            .expression_symbol()
+            .into_iter()
+            .filter(|(s, _v)| self.keep_symbol(s))
+            .sorted_by_key(|(s, _v)| s.to_string().to_ascii_lowercase())
+            .map(|(k, v)| {
+                let (kind, memory) = match v.value() {
+                    Value::Address(a) => ("label", Some(JsonMemory::from(a))),
+                    _ => ("constant", None)
+                };
+                let section = match v.value() {
+                    Value::Address(a) => {
+                        sections
+                            .iter()
+                            .find(|s| {
+                                s.contains(a.address())
+                                    && match a {
+                                        PhysicalAddress::Memory(m) => {
+                                            MemoryPhysicalAddress::new(a.address(), s.mmr) == *m
+                                        },
+                                        _ => false
+                                    }
+                            })
+                            .map(|s| s.name.as_str())
+                    },
+                    _ => None
+                };
+                let value = match v.value() {
+                    Value::Expr(ExprResult::Bool(b)) => serde_json::Value::from(*b),
+                    Value::Expr(ExprResult::String(s)) => serde_json::Value::from(s.to_string()),
+                    v => {
+                        numeric_value(v)
+                            .map(serde_json::Value::from)
+                            .unwrap_or(serde_json::Value::Null)
+                    },
+                };
+                JsonSymbol {
+                    name: k.value(),
+                    kind,
+                    value,
+                    section,
+                    memory,
+                    file: v.location().map(|l| l.fname()),
+                    line: v.location().map(|l| l.line()),
+                    column: v.location().map(|l| l.column())
+                }
+            })
+            .collect_vec();
+
+        let map = JsonDebugMap {
+            symbols,
+            sections: sections
+                .iter()
+                .map(|s| {
+                    JsonSection {
+                        name: &s.name,
+                        start: s.start,
+                        stop: s.stop,
+                        mmr: s.mmr
+                    }
+                })
+                .collect_vec(),
+            lines: source_map
+                .iter()
+                .map(|l| {
+                    JsonLine {
+                        address: l.address,
+                        memory: JsonMemory::from(&l.physical_address),
+                        file: &l.file,
+                        line: l.line
+                    }
+                })
+                .collect_vec()
+        };
+
+        serde_json::to_writer_pretty(&mut *w, &map).map_err(std::io::Error::other)?;
+        writeln!(w)
+    }
+
     /// Returns true if the symbol needs to be printed
     pub fn keep_symbol(&self, sym: &Symbol) -> bool {
         assert!(self.all_allowed ^ self.all_forbidden);
@@ -267,3 +495,85 @@ impl SymbolOutputGenerator {
         self.allowed.push(s.into());
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::{EnvOptions, visit_tokens_all_passes_with_options};
+    use crate::{AssemblingOptionFlags, AssemblingOptions, parse_z80_str};
+
+    fn assemble(code: &str, source_map: bool) -> crate::Env {
+        let mut options = AssemblingOptions::default();
+        options.set_flag(AssemblingOptionFlags::SourceMap, source_map);
+        let tokens = parse_z80_str(code).unwrap();
+        match visit_tokens_all_passes_with_options(&tokens, EnvOptions::from(options)) {
+            Ok((_tok, env)) => env,
+            Err((_tok, _env, e)) => panic!("assembling should not fail: {e}")
+        }
+    }
+
+    fn output(env: &crate::Env, format: &str) -> String {
+        let mut w = Vec::new();
+        env.generate_symbols_output(&mut w, SymbolOutputFormat::from_str(format).unwrap())
+            .unwrap();
+        String::from_utf8(w).unwrap()
+    }
+
+    const CODE: &str = " org 0x4000
+start
+ ld a, 5
+ ret
+value equ 0x12
+";
+
+    #[test]
+    fn test_text_formats() {
+        let env = assemble(CODE, false);
+
+        let rasm = output(&env, "rasm");
+        assert!(rasm.contains("start #4000 B1"), "{rasm}");
+        assert!(rasm.contains("value #0012 B0"), "{rasm}");
+        assert_eq!(output(&env, "ace"), rasm);
+
+        let sjasmplus = output(&env, "sjasmplus");
+        assert!(sjasmplus.contains("start: EQU 0x00004000"), "{sjasmplus}");
+
+        let nocash = output(&env, "no$");
+        assert!(nocash.contains("01:4000 start"), "{nocash}");
+        assert!(nocash.contains("00:0012 value"), "{nocash}");
+    }
+
+    #[test]
+    fn test_json_debug_map() {
+        let env = assemble(CODE, true);
+        assert_eq!(env.source_map().len(), 2);
+
+        let json: serde_json::Value = serde_json::from_str(&output(&env, "json")).unwrap();
+
+        let symbols = json["symbols"].as_array().unwrap();
+        let start = symbols.iter().find(|s| s["name"] == "start").unwrap();
+        assert_eq!(start["kind"], "label");
+        assert_eq!(start["value"], 0x4000);
+        assert_eq!(start["page"], 0);
+        assert_eq!(start["bank"], 1);
+        assert_eq!(start["line"], 2);
+        let value = symbols.iter().find(|s| s["name"] == "value").unwrap();
+        assert_eq!(value["kind"], "constant");
+        assert_eq!(value["value"], 0x12);
+
+        let lines = json["lines"].as_array().unwrap();
+        assert_eq!(lines[0]["address"], 0x4000);
+        assert_eq!(lines[0]["line"], 3);
+        assert_eq!(lines[1]["address"], 0x4002);
+        assert_eq!(lines[1]["line"], 4);
+
+        // without an assembling environment, only the symbols are known
+        let mut w = Vec::new();
+        SymbolOutputGenerator::default()
+            .generate(&mut w, env.symbols(), SymbolOutputFormat::Json)
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&w).unwrap();
+        assert_eq!(json["symbols"].as_array().unwrap(), symbols);
+        assert_eq!(json["lines"].as_array().unwrap().len(), 0);
+    }
+}
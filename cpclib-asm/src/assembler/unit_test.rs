@@ -0,0 +1,37 @@
+use cpclib_tokens::UnitTestStep;
+
+use crate::preamble::Z80Span;
+
+/// TEST block collected during the last pass.
+/// Its expressions are already evaluated, so it can be executed without the assembler
+#[derive(Debug, Clone)]
+pub struct UnitTest {
+    name: String,
+    span: Option<Z80Span>,
+    steps: Vec<(UnitTestStep<i32>, Option<Z80Span>)>
+}
+
+#[allow(missing_docs)]
+impl UnitTest {
+    pub fn new(
+        name: String,
+        span: Option<Z80Span>,
+        steps: Vec<(UnitTestStep<i32>, Option<Z80Span>)>
+    ) -> Self {
+        Self { name, span, steps }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Location of the whole TEST block
+    pub fn span(&self) -> Option<&Z80Span> {
+        self.span.as_ref()
+    }
+
+    /// Steps in their order of execution with their location
+    pub fn steps(&self) -> &[(UnitTestStep<i32>, Option<Z80Span>)] {
+        &self.steps
+    }
+}
@@ -10,6 +10,8 @@ pub mod branch_balance;
 
 pub mod cost_range;
 
+pub mod timing;
+
 /// All the stuff to parse z80 code.
 pub mod parser;
 
@@ -28,6 +30,9 @@ mod crunchers;
 pub mod lsp;
 pub mod orgams;
 pub mod progress;
+/// Peephole optimisation of the parsed listing
+pub mod rewrite;
+pub mod to_basm;
 pub mod unused_bindings;
 
 use std::fmt::Debug;
@@ -41,10 +46,11 @@ use preamble::function::FunctionBuilder;
 use preamble::processed_token::ProcessedToken;
 pub use preamble::*;
 
+use self::assembler::cache::AssemblingCache;
 use self::listing_output::{ListingOutput, ListingOutputFormat};
 
 #[bitflags]
-#[repr(u8)]
+#[repr(u16)]
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum AssemblingOptionFlags {
     /// Set to consider that the assembler pay attention to the case of the labels
@@ -62,7 +68,11 @@ pub enum AssemblingOptionFlags {
     // Save wabp chunck in a file
     WabpInFile,
     // generate breakpoint as code
-    BreakpointAsOpcode
+    BreakpointAsOpcode,
+    // Record the address of the first byte generated by each line of source
+    SourceMap,
+    // Record the origin of each written byte to build the memory map
+    MemoryMap
 }
 
 impl AssemblingOptionFlags {
@@ -96,6 +106,8 @@ pub struct AssemblingOptions {
     force_void: bool,
     debug: bool,
     forbid_memory_override: bool,
+    /// Address where the code is assembled until the first ORG (0 by default)
+    default_origin: u16,
     /// When set, guarantees no real-world side effect occurs during
     /// assembling: no file is written to disk (`SAVE`/`WRITE`, `BUILDSNA`,
     /// `BUILDCPR`, listing output), and no blocking read of the real
@@ -105,7 +117,9 @@ pub struct AssemblingOptions {
     /// effects" one. See each gated call site (`SaveCommand::execute_on`,
     /// `Env::save_sna`/`save_cpr`, `PauseCommand::execute`) for exactly
     /// what's suppressed.
-    dry_run: bool
+    dry_run: bool,
+    /// On-disk cache of the parsed listings and crunched data shared by successive assemblings
+    cache: Option<AssemblingCache>
 }
 
 impl Default for AssemblingOptions {
@@ -125,7 +139,9 @@ impl Default for AssemblingOptions {
             force_void: true,
             debug: false,
             forbid_memory_override: false,
-            dry_run: false
+            default_origin: 0,
+            dry_run: false,
+            cache: None
         }
     }
 }
@@ -233,6 +249,15 @@ impl AssemblingOptions {
         self
     }
 
+    pub fn default_origin(&self) -> u16 {
+        self.default_origin
+    }
+
+    pub fn set_default_origin(&mut self, origin: u16) -> &mut Self {
+        self.default_origin = origin;
+        self
+    }
+
     pub fn snapshot_model(&self) -> Option<&Snapshot> {
         self.snapshot_model.as_ref()
     }
@@ -254,6 +279,25 @@ impl AssemblingOptions {
         self.dry_run
     }
 
+    pub fn cache(&self) -> Option<&AssemblingCache> {
+        self.cache.as_ref()
+    }
+
+    pub fn set_cache(&mut self, cache: Option<AssemblingCache>) -> &mut Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Cache to parse the included sources: nothing is written on disk in a dry run
+    pub fn listing_cache(&self) -> Option<AssemblingCache> {
+        self.cache.clone().map(|mut cache| {
+            if self.dry_run {
+                cache.set_read_only(true);
+            }
+            cache
+        })
+    }
+
     /// Also clears any listing-output writer already configured via
     /// `write_listing_output[_with_format]` — defense in depth against a
     /// caller that configures output *before* enabling `dry_run`, which
@@ -320,6 +364,21 @@ pub fn assemble_with_options(
     assemble_tokens_with_options(&tokens, options)
 }
 
+/// Assemble a piece of code and give the environment of the last pass to `inspect`.
+/// The environment refers to the parsed source, so it cannot outlive this call and the errors are already rendered
+pub fn assemble_and_inspect<R>(
+    code: &str,
+    options: EnvOptions,
+    inspect: impl FnOnce(&Env) -> R
+) -> Result<R, Box<AssemblerError>> {
+    let builder = options.parse_options().clone().context_builder();
+    let tokens = parser::parse_z80_with_context_builder(code, builder)
+        .map_err(|e| AssemblerError::AlreadyRenderedError(e.to_string()))?;
+    let (_tok, env) = assembler::visit_tokens_all_passes_with_options(&tokens, options)
+        .map_err(|(_, _, e)| AssemblerError::AlreadyRenderedError(e.to_string()))?;
+    Ok(inspect(&env))
+}
+
 /// Assemble the predifined list of tokens
 pub fn assemble_tokens_with_options<
     'tokens,
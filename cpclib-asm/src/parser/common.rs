@@ -26,6 +26,7 @@ pub use super::registers::{
     parse_register_ix, parse_register_iy, parse_register_r, parse_register8, parse_register16
 };
 use super::*;
+use crate::assembler::cache::AssemblingCache;
 use crate::hashed_choice;
 use crate::parser::parser::{DOTTED_END_DIRECTIVE, END_DIRECTIVE};
 use crate::preamble::*;
@@ -284,6 +285,18 @@ pub fn parse_z80_with_context_builder<S: Into<String>>(
         .map_err(|l| AssemblerError::LocatedListingError(std::sync::Arc::new(l)))
 }
 
+/// Parse a complete source, or rebuild its listing from the on-disk cache when one is provided
+pub fn parse_z80_with_cache<S: Into<String>>(
+    str: S,
+    builder: ParserContextBuilder,
+    cache: Option<&AssemblingCache>
+) -> Result<LocatedListing, Box<AssemblerError>> {
+    match cache {
+        Some(cache) => cache.parse(str.into(), builder),
+        None => parse_z80_with_context_builder(str, builder).map_err(Box::new)
+    }
+}
+
 /// TODO better to build parse_z80_with_options from parse_z80_span than the opposite
 // pub fn parse_z80_span(span: InnerZ80Span) -> Result<LocatedListing, AssemblerError> {
 //    let ctx = span.extra.clone();
@@ -424,6 +437,20 @@ pub fn parse_line_component_standard(
             return Ok((None, repeat));
         }
     }
+    else if input.state.options().is_rasm()
+        && let Some((label, instance)) = opt(parse_rasm_struct_instance).parse_next(input)?
+    {
+        return Ok((Some(label), Some(instance)));
+    }
+    else if input.state.options().is_sjasmplus()
+        && let Some(directive) = opt(parse_sjasmplus_directive).parse_next(input)?
+    {
+        return Ok((None, Some(directive)));
+    }
+
+    if let Some(test) = opt(parse_unit_test).parse_next(input)? {
+        return Ok((None, Some(test)));
+    }
 
     let _before_let = input.checkpoint();
     let r#let = terminated(opt(parse_directive_word(b"LET")), my_space0).parse_next(input)?;
@@ -2,7 +2,9 @@ use std::borrow::{Borrow, Cow};
 use std::collections::HashSet;
 use std::ops::Deref;
 use std::path::PathBuf;
-use std::sync::{LazyLock, RwLock};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, RwLock};
 
 use cpclib_common::camino::{Utf8Path, Utf8PathBuf};
 use cpclib_common::winnow::BStr;
@@ -11,6 +13,7 @@ use cpclib_tokens::{AssemblerFlavor, ListingElement, Token};
 use either::Either;
 use enumflags2::BitFlags;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 
 use super::line_col::LineColLookup;
 use super::obtained::LocatedTokenInner;
@@ -20,7 +23,7 @@ use crate::assembler::Env;
 use crate::error::{AssemblerError, WarningCategory};
 
 /// State to limit the parsing abilities depending on the parsing context
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ParsingState {
     /// Parse of a standard Z80 code
     Standard,
@@ -152,6 +155,7 @@ impl ParserOptions {
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct ParserContextBuilder {
     options: ParserOptions,
     current_filename: Option<Utf8PathBuf>,
@@ -227,7 +231,8 @@ impl ParserContextBuilder {
 
     /// Build a ParserContext for the given source code
     #[inline]
-    pub fn build(self, code: &str) -> ParserContext {
+    pub fn build(mut self, code: &str) -> ParserContext {
+        self.options.set_flavor_from_directive(code);
         let code: &'static str = unsafe { std::mem::transmute(code) };
         let str: &'static BStr = unsafe { std::mem::transmute(BStr::new(code)) };
         ParserContext {
@@ -236,7 +241,8 @@ impl ParserContextBuilder {
             context_name: self.context_name,
             state: self.state,
             source: str,
-            line_col_lut: Default::default()
+            line_col_lut: Default::default(),
+            not_cacheable: Default::default()
         }
     }
 }
@@ -452,7 +458,118 @@ impl ParserOptions {
     pub fn is_orgams(&self) -> bool {
         self.assembler_flavor == AssemblerFlavor::Orgams
     }
+
+    #[inline(always)]
+    pub fn is_rasm(&self) -> bool {
+        self.assembler_flavor == AssemblerFlavor::Rasm
+    }
+
+    #[inline(always)]
+    pub fn is_winape(&self) -> bool {
+        self.assembler_flavor == AssemblerFlavor::Winape
+    }
+
+    #[inline(always)]
+    pub fn is_sjasmplus(&self) -> bool {
+        self.assembler_flavor == AssemblerFlavor::Sjasmplus
+    }
+
+    /// Select the flavor requested by a `FLAVOR` directive of `code`, if any.
+    /// The directive changes the way the whole file is parsed, so it is searched before parsing
+    pub fn set_flavor_from_directive(&mut self, code: &str) -> &mut Self {
+        if let Some(flavor) = flavor_directive(code) {
+            self.set_flavor(flavor);
+        }
+        self
+    }
+}
+
+/// Flavor of the first `FLAVOR <name>` statement that is not in an `IF 0` block
+fn flavor_directive(code: &str) -> Option<AssemblerFlavor> {
+    // depth of the conditional blocks in the current `IF 0` block
+    let mut inhibited_depth = 0usize;
+    raw_statements(code.as_bytes())
+        .into_iter()
+        .find_map(|(_, statement)| {
+            let statement = std::str::from_utf8(statement).ok()?;
+            let mut words = statement.split_whitespace();
+            let directive = words.next()?.trim_start_matches('.').to_ascii_uppercase();
+            let argument = match (words.next(), words.next()) {
+                (argument, None) => argument,
+                _ => return None
+            };
+
+            if inhibited_depth > 0 {
+                match directive.as_str() {
+                    "IF" | "IFNOT" | "IFDEF" | "IFNDEF" | "IFUSED" | "IFEXIST" | "IFNUSED" => {
+                        inhibited_depth += 1
+                    },
+                    "ENDIF" | "ENDI" => inhibited_depth -= 1,
+                    _ => {}
+                }
+                return None;
+            }
+
+            match (directive.as_str(), argument) {
+                ("IF", Some("0")) => {
+                    inhibited_depth = 1;
+                    None
+                },
+                ("FLAVOR", Some(name)) => AssemblerFlavor::from_str(name).ok(),
+                _ => None
+            }
+        })
 }
+
+/// Statements of the raw `code`, split on line endings and `:`, without their comments.
+/// Each one comes with the offset of the separator that precedes it (none for the first one).
+/// The separators in strings are ignored
+pub(crate) fn raw_statements(code: &[u8]) -> Vec<(Option<usize>, &[u8])> {
+    let mut statements = Vec::new();
+    let mut separator = None;
+    let mut statement_start = 0;
+    let mut quote = None;
+    let mut comment_start = None;
+    for (idx, &c) in code.iter().enumerate() {
+        match c {
+            b'\n' => quote = None,
+            _ if comment_start.is_some() => continue,
+            _ if quote.is_some() => {
+                if quote == Some(c) {
+                    quote = None;
+                }
+                continue;
+            },
+            b'"' | b'\'' => {
+                quote = Some(c);
+                continue;
+            },
+            b';' => {
+                comment_start = Some(idx);
+                continue;
+            },
+            b':' => {},
+            _ => continue
+        }
+
+        let statement_end = comment_start.take().unwrap_or(idx);
+        statements.push((separator, &code[statement_start..statement_end]));
+        separator = Some(if c == b'\n' && code[..idx].ends_with(b"\r") {
+            idx - 1
+        }
+        else {
+            idx
+        });
+        statement_start = idx + 1;
+    }
+    statements.push((
+        separator,
+        &code[statement_start..comment_start.unwrap_or(code.len())]
+    ));
+
+    statements
+}
+
 /// Context information that can guide the parser
 /// TODO add assembling flags
 #[derive(Debug)]
@@ -467,7 +584,10 @@ pub struct ParserContext {
     pub options: ParserOptions,
     /// Full source code of the parsing state
     pub source: &'static BStr,
-    pub line_col_lut: RwLock<Option<LineColLookup<'static>>>
+    pub line_col_lut: RwLock<Option<LineColLookup<'static>>>,
+    /// Set by the directives with a side effect at parse time: their listing must be parsed
+    /// again instead of being read from the cache. Shared by the contexts of the inner blocks
+    not_cacheable: Arc<AtomicBool>
 }
 
 impl Eq for ParserContext {}
@@ -513,6 +633,7 @@ impl ParserContext {
             source: self.source,
             options: self.options.clone(),
             line_col_lut: Default::default(), // no need to duplicate the structure
+            not_cacheable: Arc::clone(&self.not_cacheable),
             state
         }
     }
@@ -572,6 +693,17 @@ impl ParserContext {
         &self.state
     }
 
+    /// The listing cannot be reused without parsing its source again
+    #[inline]
+    pub fn set_not_cacheable(&self) {
+        self.not_cacheable.store(true, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn is_cacheable(&self) -> bool {
+        !self.not_cacheable.load(Ordering::Relaxed)
+    }
+
     #[inline]
     pub fn relative_line_and_column(&self, offset: usize) -> (usize, usize) {
         if self.line_col_lut.read().unwrap().is_none() {
@@ -8,7 +8,7 @@ use std::str::FromStr;
 
 use cpclib_common::itertools::Itertools;
 use cpclib_common::smol_str::SmolStr;
-use cpclib_common::winnow::ascii::{Caseless, alphanumeric1, line_ending};
+use cpclib_common::winnow::ascii::{Caseless, alphanumeric1, line_ending, space1};
 use cpclib_common::winnow::combinator::{
     alt, cut_err, delimited, eof, not, opt, peek, preceded, repeat, repeat_till, separated,
     terminated
@@ -24,7 +24,10 @@ use cpclib_sna::{
     RemuBreakPointAccessMode, RemuBreakPointRunMode, RemuBreakPointType, SnapshotVersion
 };
 use cpclib_tokens::macro_segment::tokenize_macro_body;
-use cpclib_tokens::{Expr, ExprFormat, FormattedExpr};
+use cpclib_tokens::{
+    AssemblerFlavor, BinaryOperation, Expr, ExprFormat, FormattedExpr, ImageFormat,
+    UnitTestExpectation, UnitTestRegister, UnitTestStep
+};
 
 use super::common::{
     inner_code, inner_code_with_state, my_line_ending, my_many0_nocollect, my_space0,
@@ -36,12 +39,17 @@ use super::context;
 use super::error::Z80ParserErrorKind;
 use super::expression::{
     expr, expr_list, ignore_ascii_case_allowed_label, located_expr, parse_any_function_call,
-    parse_assemble, parse_expr_bracketed_list, parse_flag_value_inner, parse_label, parse_string
+    parse_assemble, parse_expr_bracketed_list, parse_flag_test, parse_flag_value_inner,
+    parse_label, parse_string
 };
 use super::instructions::{parse_nop, parse_opcode_no_arg};
-use super::obtained::{LocatedToken, LocatedTokenInner};
+use super::obtained::{LocatedDataAccess, LocatedToken, LocatedTokenInner, LocatedUnitTestStep};
 use super::orgams::parse_orgams_fail;
 pub use super::parser::{END_DIRECTIVE, STAND_ALONE_DIRECTIVE, START_DIRECTIVE};
+use super::registers::{
+    parse_indexregister8, parse_indexregister16, parse_register_sp, parse_register8,
+    parse_register16
+};
 use super::source::Z80Span;
 use crate::hashed_choice;
 use crate::preamble::*;
@@ -64,6 +72,8 @@ const ERR_ITERATE_NOT_CLOSED: &str = "ITERATE: not closed";
 const ERR_ITERATE_ERROR_IN_BLOCK: &str = "ITERATE: error in block";
 const ERR_CONFINED_NOT_CLOSED: &str = "CONFINED: not closed";
 const ERR_CONFINED_ERROR_IN_BLOCK: &str = "CONFINED: error in block";
+const ERR_STABILIZE_NOT_CLOSED: &str = "STABILIZE: not closed";
+const ERR_STABILIZE_ERROR_IN_BLOCK: &str = "STABILIZE: error in block";
 const ERR_MODULE_NOT_CLOSED: &str = "MODULE: not closed";
 const ERR_MODULE_ERROR_IN_BLOCK: &str = "MODULE: error in block";
 const ERR_SWITCH_NOT_CLOSED: &str = "SWITCH: not closed";
@@ -421,6 +431,37 @@ pub fn parse_confined(input: &mut InnerZ80Span) -> ModalResult<LocatedToken, Z80
     Ok(token)
 }
 
+#[cfg_attr(not(target_arch = "wasm32"), inline)]
+#[cfg_attr(target_arch = "wasm32", inline(never))]
+pub fn parse_stabilize(input: &mut InnerZ80Span) -> ModalResult<LocatedToken, Z80ParserError> {
+    let _ = my_space0(input)?;
+    let stabilize_start = input.checkpoint();
+    let stabilize_start_span = *input;
+
+    let _ = parse_directive_word(b"STABILIZE").parse_next(input)?;
+
+    let inner = parse_block_error(
+        cut_err(inner_code.context(StrContext::Label("STABILIZE: issue in the content"))),
+        stabilize_start_span,
+        ERR_STABILIZE_ERROR_IN_BLOCK
+    )
+    .parse_next(input)?;
+
+    let _ = parse_block_error(
+        cut_err(
+            preceded(my_space0, parse_directive_word(b"ENDSTABILIZE"))
+                .context(StrContext::Label(ERR_STABILIZE_NOT_CLOSED))
+        ),
+        stabilize_start_span,
+        ERR_STABILIZE_NOT_CLOSED
+    )
+    .parse_next(input)?;
+
+    let token =
+        LocatedTokenInner::Stabilize(inner).into_located_token_between(&stabilize_start, *input);
+    Ok(token)
+}
+
 pub fn parse_repeat(input: &mut InnerZ80Span) -> ModalResult<LocatedToken, Z80ParserError> {
     let _ = my_space0(input)?;
     let repeat_start = input.checkpoint();
@@ -729,6 +770,62 @@ pub fn parse_incbin(
     }
 }
 
+/// Parse `INCIMG "file.png", mode, format`
+pub fn parse_incimg(input: &mut InnerZ80Span) -> ModalResult<LocatedTokenInner, Z80ParserError> {
+    let fname = cut_err(
+        preceded(my_space0, parse_fname_or_expression)
+            .context(StrContext::Label("INCIMG: error in fname"))
+    )
+    .parse_next(input)?;
+
+    let mode = cut_err(
+        preceded(parse_comma, located_expr).context(StrContext::Label("INCIMG: wrong mode"))
+    )
+    .parse_next(input)?;
+
+    let format = cut_err(
+        preceded(
+            parse_comma,
+            alt((
+                preceded(
+                    parse_word(b"MASKED_SPRITE"),
+                    (
+                        preceded(parse_comma, located_expr),
+                        preceded(parse_comma, located_expr)
+                    )
+                )
+                .map(|(mask_ink, replacement_ink)| {
+                    ImageFormat::MaskedSprite {
+                        mask_ink,
+                        replacement_ink
+                    }
+                }),
+                parse_word(b"SPRITE").value(ImageFormat::Sprite),
+                preceded(
+                    parse_word(b"TILES"),
+                    (
+                        preceded(parse_comma, located_expr),
+                        preceded(parse_comma, located_expr)
+                    )
+                )
+                .map(|(width, height)| ImageFormat::Tiles { width, height }),
+                parse_word(b"SCREEN").value(ImageFormat::Screen),
+                parse_word(b"OVERSCAN").value(ImageFormat::Overscan)
+            ))
+        )
+        .context(StrContext::Label(
+            "INCIMG: expected SPRITE, MASKED_SPRITE, TILES, SCREEN or OVERSCAN"
+        ))
+    )
+    .parse_next(input)?;
+
+    Ok(LocatedTokenInner::IncImage {
+        fname,
+        mode,
+        format
+    })
+}
+
 pub fn parse_save(
     save_kind: SaveKind
 ) -> impl Fn(&mut InnerZ80Span) -> ModalResult<LocatedTokenInner, Z80ParserError> {
@@ -858,26 +955,84 @@ pub fn parse_module(input: &mut InnerZ80Span) -> ModalResult<LocatedToken, Z80Pa
     )
     .parse_next(input)?;
 
+    let inner = if input.state.options().is_rasm() {
+        parse_rasm_module_content(input, module_start_span)?
+    }
+    else {
+        let inner = parse_block_error(
+            cut_err(inner_code.context(StrContext::Label("MODULE: issue in the content"))),
+            module_start_span,
+            ERR_MODULE_ERROR_IN_BLOCK
+        )
+        .parse_next(input)?;
+
+        let _ = parse_block_error(
+            cut_err(
+                preceded(my_space0, parse_directive_word(b"ENDMODULE"))
+                    .context(StrContext::Label(ERR_MODULE_NOT_CLOSED))
+            ),
+            module_start_span,
+            ERR_MODULE_NOT_CLOSED
+        )
+        .parse_next(input)?;
+
+        inner
+    };
+
+    let token = LocatedTokenInner::Module(name.into(), inner)
+        .into_located_token_between(&module_start, *input);
+    Ok(token)
+}
+
+/// A rasm module ends with ENDMODULE, MODULE OFF, the next MODULE or the end of the file
+fn parse_rasm_module_content(
+    input: &mut InnerZ80Span,
+    module_start_span: InnerZ80Span
+) -> ModalResult<LocatedListing, Z80ParserError> {
+    let code: &'static [u8] = input.peek_finish();
+    let length = rasm_module_length(code);
+    let mut content = (*input).update_slice(BStr::new(&code[..length]));
+    let content_start = content.checkpoint();
+
     let inner = parse_block_error(
         cut_err(inner_code.context(StrContext::Label("MODULE: issue in the content"))),
         module_start_span,
         ERR_MODULE_ERROR_IN_BLOCK
     )
-    .parse_next(input)?;
+    .parse_next(&mut content)?;
+    let _ = take::<_, _, ErrMode<Z80ParserError>>(content.offset_from(&content_start))
+        .parse_next(input)?;
 
-    let _ = parse_block_error(
-        cut_err(
-            preceded(my_space0, parse_directive_word(b"ENDMODULE"))
-                .context(StrContext::Label(ERR_MODULE_NOT_CLOSED))
-        ),
-        module_start_span,
-        ERR_MODULE_NOT_CLOSED
-    )
+    let _ = opt(preceded(
+        (my_line_ending, my_space0),
+        alt((
+            parse_directive_word(b"ENDMODULE").value(()),
+            (parse_directive_word(b"MODULE"), parse_word(b"OFF")).value(())
+        ))
+    ))
     .parse_next(input)?;
 
-    let token = LocatedTokenInner::Module(name.into(), inner)
-        .into_located_token_between(&module_start, *input);
-    Ok(token)
+    Ok(inner)
+}
+
+/// Number of bytes before the separator (line ending or `:`) that precedes the next MODULE or
+/// ENDMODULE statement
+fn rasm_module_length(code: &[u8]) -> usize {
+    let is_module_directive = |statement: &[u8]| {
+        let word = statement
+            .trim_ascii_start()
+            .split(|c| !(c.is_ascii_alphanumeric() || *c == b'_'))
+            .next()
+            .unwrap();
+        word.eq_ignore_ascii_case(b"MODULE") || word.eq_ignore_ascii_case(b"ENDMODULE")
+    };
+
+    // the first statement is the end of the MODULE line. The separator before the directive is
+    // left to the caller
+    context::raw_statements(code)
+        .into_iter()
+        .find_map(|(separator, statement)| separator.filter(|_| is_module_directive(statement)))
+        .unwrap_or(code.len())
 }
 
 /// Parse a sub-listing part that aims at being crunched after being assembled at first pass
@@ -1661,6 +1816,182 @@ pub fn parse_enum(input: &mut InnerZ80Span) -> ModalResult<LocatedTokenInner, Z8
     })
 }
 
+/// Parse the comparison of an EXPECT step of a TEST block
+fn parse_unit_test_comparison(
+    input: &mut InnerZ80Span
+) -> ModalResult<BinaryOperation, Z80ParserError> {
+    delimited(
+        my_space0,
+        alt((
+            b"==".value(BinaryOperation::Equal),
+            b"!=".value(BinaryOperation::Different),
+            b"<=".value(BinaryOperation::LowerOrEqual),
+            b">=".value(BinaryOperation::GreaterOrEqual),
+            b"<".value(BinaryOperation::StrictlyLower),
+            b">".value(BinaryOperation::StrictlyGreater)
+        )),
+        my_space0
+    )
+    .parse_next(input)
+}
+
+/// Parse a register that can be set or checked in a TEST block
+fn parse_unit_test_register(
+    input: &mut InnerZ80Span
+) -> ModalResult<UnitTestRegister, Z80ParserError> {
+    terminated(
+        alt((
+            parse_indexregister8,
+            parse_indexregister16,
+            parse_register_sp,
+            parse_register16,
+            parse_register8
+        )),
+        my_space0
+    )
+    .verify_map(|reg| {
+        match reg {
+            LocatedDataAccess::Register8(reg, _) => Some(UnitTestRegister::Register8(reg)),
+            LocatedDataAccess::Register16(reg, _) => Some(UnitTestRegister::Register16(reg)),
+            LocatedDataAccess::IndexRegister8(reg, _) => {
+                Some(UnitTestRegister::IndexRegister8(reg))
+            },
+            LocatedDataAccess::IndexRegister16(reg, _) => {
+                Some(UnitTestRegister::IndexRegister16(reg))
+            },
+            _ => None
+        }
+    })
+    .parse_next(input)
+}
+
+/// Parse the expectation of an EXPECT step of a TEST block
+fn parse_unit_test_expectation(
+    input: &mut InnerZ80Span
+) -> ModalResult<UnitTestExpectation<LocatedExpr>, Z80ParserError> {
+    alt((
+        preceded(
+            parse_word(b"NOPS"),
+            (parse_unit_test_comparison, located_expr)
+        )
+        .map(|(op, value)| UnitTestExpectation::Nops(op, value)),
+        (
+            delimited(('(', my_space0), located_expr, (my_space0, ')')),
+            parse_unit_test_comparison,
+            located_expr
+        )
+            .map(|(address, op, value)| UnitTestExpectation::Memory(address, op, value)),
+        // C is both a register and a flag
+        (
+            parse_unit_test_register,
+            parse_unit_test_comparison,
+            located_expr
+        )
+            .map(|(reg, op, value)| UnitTestExpectation::Register(reg, op, value)),
+        parse_flag_test.map(UnitTestExpectation::Flag)
+    ))
+    .parse_next(input)
+}
+
+/// Parse one step of a TEST block
+fn parse_unit_test_step(
+    input: &mut InnerZ80Span
+) -> ModalResult<LocatedUnitTestStep, Z80ParserError> {
+    let (step, taken) = alt((
+        preceded(
+            parse_word(b"LD"),
+            cut_err(
+                (parse_unit_test_register, parse_comma, located_expr)
+                    .context(StrContext::Label("TEST: LD register, value expected"))
+            )
+        )
+        .map(|(reg, _, value)| UnitTestStep::Load(reg, value)),
+        preceded(
+            parse_word(b"POKE"),
+            cut_err(
+                (
+                    located_expr,
+                    parse_comma,
+                    separated(1.., located_expr, parse_comma)
+                )
+                    .context(StrContext::Label("TEST: POKE address, bytes expected"))
+            )
+        )
+        .map(|(address, _, bytes)| UnitTestStep::Poke(address, bytes)),
+        preceded(
+            parse_word(b"CALL"),
+            cut_err(located_expr.context(StrContext::Label("TEST: CALL routine expected")))
+        )
+        .map(UnitTestStep::Call),
+        preceded(
+            parse_word(b"EXPECT"),
+            cut_err(
+                parse_unit_test_expectation
+                    .context(StrContext::Label("TEST: wrong EXPECT condition"))
+            )
+        )
+        .map(UnitTestStep::Expect)
+    ))
+    .with_taken()
+    .parse_next(input)?;
+
+    let span = (*input).update_slice(taken.trim_ascii_end());
+    Ok(LocatedUnitTestStep(step, span.into()))
+}
+
+/// Parse a TEST block.
+/// Syntax: TEST "name"
+///           LD register, value
+///           POKE address, byte [, byte ...]
+///           CALL routine
+///           EXPECT register|(address)|NOPS comparison value
+///           EXPECT flag
+///         ENDTEST
+/// TEST is not a reserved word to not forbid the labels named test: it must be followed by a string
+pub fn parse_unit_test(input: &mut InnerZ80Span) -> ModalResult<LocatedToken, Z80ParserError> {
+    let start = input.checkpoint();
+
+    let _ = (parse_directive_word(b"TEST"), peek(alt(('"', '\'')))).parse_next(input)?;
+    let name = cut_err(parse_string.context(StrContext::Label("TEST: name expected")))
+        .parse_next(input)?;
+
+    let skip = || {
+        repeat::<_, _, (), _, _>(
+            0..,
+            alt((
+                space1.value(()),
+                parse_comment.value(()),
+                line_ending.value(()),
+                ':'.value(())
+            ))
+        )
+    };
+
+    let mut steps = Vec::new();
+    loop {
+        skip().parse_next(input)?;
+        if parse_directive_word(b"ENDTEST").parse_next(input).is_ok() {
+            break;
+        }
+        if input.eof_offset() == 0 {
+            return Err(ErrMode::Cut(Z80ParserError::from_input(input).add_context(
+                input,
+                &start,
+                StrContext::Label("TEST: missing ENDTEST")
+            )));
+        }
+        steps.push(
+            cut_err(
+                parse_unit_test_step
+                    .context(StrContext::Label("TEST: LD, POKE, CALL or EXPECT expected"))
+            )
+            .parse_next(input)?
+        );
+    }
+
+    Ok(LocatedTokenInner::UnitTest { name, steps }.into_located_token_between(&start, *input))
+}
+
 pub fn parse_struct_directive(
     input: &mut InnerZ80Span
 ) -> ModalResult<LocatedToken, Z80ParserError> {
@@ -1696,6 +2027,96 @@ fn parse_struct_directive_inner(
     Ok(directive)
 }
 
+/// rasm instantiates a structure with `STRUCT <type> <label>`.
+/// The instance is the label followed by a call to the structure
+pub fn parse_rasm_struct_instance(
+    input: &mut InnerZ80Span
+) -> ModalResult<(LocatedToken, LocatedToken), Z80ParserError> {
+    let start = input.checkpoint();
+    let _ = parse_directive_word(b"STRUCT").parse_next(input)?;
+    let r#type = parse_label(false).parse_next(input)?;
+    let _ = my_space1.parse_next(input)?;
+    let label = parse_label(false).parse_next(input)?;
+    let _ = (
+        my_space0,
+        peek(alt((
+            eof.value(()),
+            line_ending.value(()),
+            ';'.value(()),
+            ':'.value(())
+        )))
+    )
+        .parse_next(input)?;
+
+    let label = LocatedTokenInner::Label(label.into()).into_located_token_direct();
+    let call = LocatedTokenInner::MacroCall(r#type.into(), Vec::new())
+        .into_located_token_between(&start, *input);
+    Ok((label, call))
+}
+
+/// sjasmplus directives that do not exist in basm are mapped on the basm tokens:
+/// DISP/ENT is a RORG block, PAGE selects a bank, DEFINE is an EQU (or an assignment for DEFINE+)
+/// and SAVEBIN is a SAVE. DEVICE has no meaning there and is kept as a comment
+pub fn parse_sjasmplus_directive(
+    input: &mut InnerZ80Span
+) -> ModalResult<LocatedToken, Z80ParserError> {
+    if peek(parse_directive_word(b"DISP"))
+        .parse_next(input)
+        .is_ok()
+    {
+        return parse_rorg(input);
+    }
+
+    let start = input.checkpoint();
+    let token = if let Some(device) = opt((
+        parse_directive_word(b"DEVICE"),
+        cut_err(parse_label(false).context(StrContext::Label("DEVICE: device name expected")))
+    )
+        .take())
+    .parse_next(input)?
+    {
+        LocatedTokenInner::Comment((*input).update_slice(device).into())
+    }
+    else if parse_directive_word(b"PAGE").parse_next(input).is_ok() {
+        let page = cut_err(located_expr.context(StrContext::Label("PAGE: page number expected")))
+            .parse_next(input)?;
+        LocatedTokenInner::Bank(Some(page))
+    }
+    else if parse_directive_word(b"DEFINE").parse_next(input).is_ok() {
+        let redefinable = opt(terminated('+', my_space0)).parse_next(input)?.is_some();
+        let label = cut_err(parse_label(false).context(StrContext::Label("DEFINE: name expected")))
+            .parse_next(input)?;
+        let _ = my_space0.parse_next(input)?;
+        // a define without value only serves to be tested by IFDEF
+        let expr = opt(located_expr)
+            .parse_next(input)?
+            .unwrap_or_else(|| LocatedExpr::Value(1, label.into()));
+
+        if redefinable {
+            LocatedTokenInner::Assign {
+                label: label.into(),
+                expr,
+                op: None
+            }
+        }
+        else {
+            LocatedTokenInner::Equ {
+                label: label.into(),
+                expr
+            }
+        }
+    }
+    else if parse_directive_word(b"SAVEBIN").parse_next(input).is_ok() {
+        parse_save(SaveKind::Save).parse_next(input)?
+    }
+    else {
+        input.reset(&start);
+        return Err(ErrMode::Backtrack(Z80ParserError::from_input(input)));
+    };
+
+    Ok(token.into_located_token_between(&start, *input))
+}
+
 pub fn parse_struct(input: &mut InnerZ80Span) -> ModalResult<LocatedTokenInner, Z80ParserError> {
     let name = cut_err(parse_label(false)).parse_next(input)?;
 
@@ -1794,14 +2215,26 @@ pub fn parse_snaset(
             parse_word(b"SNASET").parse_next(input)?;
         }
 
+        let to_flag = |bytes: &[u8]| {
+            let s = unsafe { std::str::from_utf8_unchecked(bytes) };
+            SnapshotFlag::from_str(s).ok()
+        };
+        // rasm separates the index of the flag with a comma instead of a colon
+        let to_rasm_flag = |bytes: &[u8]| {
+            let s = unsafe { std::str::from_utf8_unchecked(bytes) };
+            let s = s.replace(',', ":").split_whitespace().collect::<String>();
+            SnapshotFlag::from_str(&s).ok()
+        };
         let flag = cut_err(
-            (parse_label(false), opt((':', parse_value)))
-                .take()
-                .verify_map(|bytes: &[u8]| {
-                    let s = unsafe { std::str::from_utf8_unchecked(bytes) };
-                    SnapshotFlag::from_str(s).ok()
-                })
-                .context(StrContext::Label("SNASET: Invalid flag"))
+            alt((
+                (parse_label(false), opt((':', parse_value)))
+                    .take()
+                    .verify_map(to_flag),
+                (parse_label(false), parse_comma, parse_value)
+                    .take()
+                    .verify_map(to_rasm_flag)
+            ))
+            .context(StrContext::Label("SNASET: Invalid flag"))
         )
         .parse_next(input)?;
 
@@ -1987,12 +2420,14 @@ fn parse_directive_of_size_6(
         h if hashed_choice!(h, word, b"EXPORT") => {
             parse_export(ExportKind::Export).parse_next(input)
         },
+        h if hashed_choice!(h, word, b"FLAVOR") => parse_flavor.parse_next(input),
         h if hashed_choice!(h, word, b"INCBIN") => {
             parse_incbin(BinaryTransformation::None).parse_next(input)
         },
         h if hashed_choice!(h, word, b"INCEXO") => {
             parse_incbin(BinaryTransformation::Crunch(CrunchType::LZEXO)).parse_next(input)
         },
+        h if hashed_choice!(h, word, b"INCIMG") => parse_incimg.parse_next(input),
         h if hashed_choice!(h, word, b"INCLZ4") => {
             parse_incbin(BinaryTransformation::Crunch(CrunchType::LZ4)).parse_next(input)
         },
@@ -2096,7 +2531,14 @@ fn parse_directive_of_size_5(
         h if hashed_choice!(h, word, b"UNDEF") => parse_undef.parse_next(input),
 
         h if hashed_choice!(h, word, b"WRITE") => {
-            alt((parse_save(SaveKind::WriteDirect), parse_write_direct_memory)).parse_next(input)
+            if input.state.options().is_winape() {
+                alt((parse_write_direct_memory, parse_save(SaveKind::WriteDirect)))
+                    .parse_next(input)
+            }
+            else {
+                alt((parse_save(SaveKind::WriteDirect), parse_write_direct_memory))
+                    .parse_next(input)
+            }
         },
 
         _ => {
@@ -2155,7 +2597,10 @@ fn parse_directive_of_size_3(
             parse_db_or_dw_or_str(DbDwStr::Str, within_struct).parse_next(input)
         },
         h if hashed_choice!(h, word, b"END") && !is_orgams => Ok(LocatedTokenInner::End),
-        h if hashed_choice!(h, word, b"ENT") => parse_run(RunEnt::Ent).parse_next(input),
+        // sjasmplus uses ENT to close DISP
+        h if hashed_choice!(h, word, b"ENT") && !input.state.options().is_sjasmplus() => {
+            parse_run(RunEnt::Ent).parse_next(input)
+        },
         h if hashed_choice!(h, word, b"MAP") => parse_map.parse_next(input),
         h if hashed_choice!(h, word, b"NOP") => parse_nop.parse_next(input),
         h if hashed_choice!(h, word, b"ORG") => parse_org.parse_next(input),
@@ -2321,20 +2766,34 @@ pub fn parse_macro_inner(
         )))
         .parse_next(input)?;
 
-        let mut arguments = separated::<_, _, Vec<&[u8]>, _, _, _, _>(
+        // Each parameter can be followed by its default value (`MACRO foo a, b=2`)
+        let mut arguments = separated::<_, _, Vec<(&[u8], Option<&[u8]>)>, _, _, _, _>(
             0..,
-            delimited(
-                my_space0,
-                take_till(1.., |c| {
-                    c == b'\n'
-                        || c == b'\r'
-                        || c == b':'
-                        || c == b','
-                        || c == b' '
-                        || c == b')'
-                        || c == b';'
-                }),
-                my_space0
+            (
+                delimited(
+                    my_space0,
+                    take_till(1.., |c| {
+                        c == b'\n'
+                            || c == b'\r'
+                            || c == b':'
+                            || c == b','
+                            || c == b' '
+                            || c == b')'
+                            || c == b';'
+                            || c == b'='
+                    }),
+                    my_space0
+                ),
+                opt(preceded(
+                    ('=', my_space0),
+                    cut_err(
+                        parse_macro_arg
+                            .take()
+                            .verify(|default: &[u8]| !default.trim_ascii().is_empty())
+                            .map(|default: &[u8]| default.trim_ascii_end())
+                            .context(StrContext::Label("MACRO: wrong default value"))
+                    )
+                ))
             ),
             parse_comma
         )
@@ -2344,7 +2803,7 @@ pub fn parse_macro_inner(
         // arguments beyond `arguments` (`MACRO foo(a, b, ...)`) - it isn't
         // itself a parameter name, so strip it here before `arguments` is
         // turned into the declared param list.
-        let has_variadic = arguments.last() == Some(&b"...".as_slice());
+        let has_variadic = arguments.last() == Some(&(b"...".as_slice(), None));
         if has_variadic {
             arguments.pop();
         }
@@ -2362,11 +2821,15 @@ pub fn parse_macro_inner(
             .parse_next(input)?;
         }
 
-        let arguments = arguments
+        let (arguments, defaults): (Vec<Z80Span>, Vec<Option<Z80Span>>) = arguments
             .into_iter()
-            .map(|span| (*input).update_slice(span))
-            .map(|span| span.into())
-            .collect_vec();
+            .map(|(name, default)| {
+                (
+                    (*input).update_slice(name).into(),
+                    default.map(|default| (*input).update_slice(default).into())
+                )
+            })
+            .unzip();
 
         alt((my_space0.value(()), my_line_ending.value(()))).parse_next(input)?;
 
@@ -2403,6 +2866,7 @@ pub fn parse_macro_inner(
         Ok(LocatedTokenInner::Macro {
             name: name.into(),
             params: arguments,
+            defaults,
             content,
             flavor: input.state.options().assembler_flavor,
             tokenized_content,
@@ -2443,6 +2907,7 @@ pub fn parse_z80_directive_with_block(
                 parse_iterate.context(StrContext::Label("Error in iterate")),
                 parse_while.context(StrContext::Label("Error in while")),
                 parse_rorg.context(StrContext::Label("Error in rorg")),
+                parse_stabilize.context(StrContext::Label("Error in stabilize")),
                 parse_conditional.context(StrContext::Label("Error in condition")),
                 parse_assembler_control_max_passes_number
                     .context(StrContext::Label("Error in assembler control"))
@@ -2823,6 +3288,8 @@ pub fn parse_assembler_control_print_parse(
                         .map(|c| c.to_owned())
                         .unwrap_or_default()
                 });
+            // the message is printed at each parse, so the listing cannot come from the cache
+            input.state.set_not_cacheable();
             if !input.state.options.quiet {
                 let (line, column) = Z80Span::from(input2).relative_line_and_column();
                 println!("[PARSE] {ctx}:{line}:{column} {msg}");
@@ -3232,7 +3699,13 @@ pub fn parse_rorg(input: &mut InnerZ80Span) -> ModalResult<LocatedToken, Z80Pars
     let _ = my_space0.parse_next(input)?;
     let rorg_start = input.checkpoint();
     let rorg_start_span = *input;
-    let _ = alt((Caseless("PHASE"), Caseless("RORG"))).parse_next(input)?;
+    let is_sjasmplus = input.state.options().is_sjasmplus();
+    let _ = alt((
+        Caseless("PHASE"),
+        Caseless("RORG"),
+        Caseless("DISP").verify(move |_: &[u8]| is_sjasmplus)
+    ))
+    .parse_next(input)?;
 
     let exp = parse_block_error(
         cut_err(
@@ -3253,7 +3726,12 @@ pub fn parse_rorg(input: &mut InnerZ80Span) -> ModalResult<LocatedToken, Z80Pars
         cut_err(
             preceded(
                 my_space0,
-                alt((Caseless("DEPHASE"), Caseless("REND"), Caseless("ENDR")))
+                alt((
+                    Caseless("DEPHASE"),
+                    Caseless("REND"),
+                    Caseless("ENDR"),
+                    Caseless("ENT").verify(move |_: &[u8]| is_sjasmplus)
+                ))
             )
             .context(StrContext::Label(ERR_RORG_NOT_CLOSED))
         ),
@@ -3273,6 +3751,30 @@ pub fn parse_undef(input: &mut InnerZ80Span) -> ModalResult<LocatedTokenInner, Z
     Ok(LocatedTokenInner::Undef(label.into()))
 }
 
+pub fn parse_flavor(input: &mut InnerZ80Span) -> ModalResult<LocatedTokenInner, Z80ParserError> {
+    preceded(
+        my_space0,
+        cut_err(
+            terminated(
+                alt((
+                    Caseless("BASM").value(AssemblerFlavor::Basm),
+                    Caseless("ORGAMS").value(AssemblerFlavor::Orgams),
+                    Caseless("RASM").value(AssemblerFlavor::Rasm),
+                    Caseless("WINAPE").value(AssemblerFlavor::Winape),
+                    Caseless("MAXAM").value(AssemblerFlavor::Winape),
+                    Caseless("SJASMPLUS").value(AssemblerFlavor::Sjasmplus)
+                )),
+                not(alphanumeric1)
+            )
+            .context(StrContext::Label(
+                "FLAVOR: BASM, ORGAMS, RASM, WINAPE or SJASMPLUS expected"
+            ))
+        )
+    )
+    .map(LocatedTokenInner::Flavor)
+    .parse_next(input)
+}
+
 pub fn parse_section(input: &mut InnerZ80Span) -> ModalResult<LocatedTokenInner, Z80ParserError> {
     let name = preceded(my_space0, parse_label(false)).parse_next(input)?;
 
@@ -3281,25 +3783,30 @@ pub fn parse_section(input: &mut InnerZ80Span) -> ModalResult<LocatedTokenInner,
 
 /// parse write direct in memory / converted to a bank directive
 /// we do not care of the parameters for roms as we are not working in an emulator
+/// (WinAPE sources may select any rom there, they are ignored as well)
 pub fn parse_write_direct_memory(
     input: &mut InnerZ80Span
 ) -> ModalResult<LocatedTokenInner, Z80ParserError> {
+    let is_winape = input.state.options().is_winape();
+
     // filter all the stuff before
-    let _ = (
-        Caseless("DIRECT"),
-        my_space1,
-        Caseless("-1"),
-        parse_comma,
-        Caseless("-1"),
-        parse_comma
-    )
-        .parse_next(input)?;
+    let _ = (Caseless("DIRECT"), my_space1).parse_next(input)?;
+    if is_winape {
+        let _ = (located_expr, parse_comma, located_expr, parse_comma).parse_next(input)?;
+    }
+    else {
+        let _ = (Caseless("-1"), parse_comma, Caseless("-1"), parse_comma).parse_next(input)?;
+    }
 
     let bank =
         cut_err(located_expr.context(StrContext::Label("WRITE DIRECT -1, -1: BANK expected")))
             .parse_next(input)?;
 
     let token = LocatedTokenInner::Bank(Some(bank));
+    if is_winape {
+        // this is the native way to select the memory for WinAPE
+        return Ok(token);
+    }
 
     Ok(LocatedTokenInner::WarningWrapper(
         Box::new(token),
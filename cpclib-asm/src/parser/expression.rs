@@ -1133,11 +1133,11 @@ pub fn ignore_ascii_case_allowed_label(
     // Get min/max and bucket function based on flavor and dotted directive
     let (min_max, bucket_fn): ((usize, usize), fn(usize) -> &'static [&'static str]) =
         match (flavor, dotted_directive) {
-            (AssemblerFlavor::Basm, true) => {
-                (DOTTED_MIN_MAX_LABEL_SIZE, dotted_impossible_by_length)
+            (AssemblerFlavor::Orgams, _) => {
+                (ORGAMS_MIN_MAX_LABEL_SIZE, orgams_impossible_by_length)
             },
-            (AssemblerFlavor::Basm, false) => (MIN_MAX_LABEL_SIZE, impossible_by_length),
-            (AssemblerFlavor::Orgams, _) => (ORGAMS_MIN_MAX_LABEL_SIZE, orgams_impossible_by_length)
+            (_, true) => (DOTTED_MIN_MAX_LABEL_SIZE, dotted_impossible_by_length),
+            (_, false) => (MIN_MAX_LABEL_SIZE, impossible_by_length)
         };
 
     let (min, max) = min_max;
@@ -27,18 +27,22 @@ use cpclib_tokens::macro_segment::TokenizedMacroContent;
 use cpclib_tokens::ordered_float::OrderedFloat;
 use cpclib_tokens::{
     AssemblerControlCommand, AssemblerFlavor, BaseListing, BinaryOperation, CharsetFormat,
-    CrunchType, DataAccess, DataAccessElem, Expr, ExprResult, FlagTest, FormattedExpr,
+    CrunchType, DataAccess, DataAccessElem, Expr, ExprResult, FlagTest, FormattedExpr, ImageFormat,
     IndexRegister8, IndexRegister16, LabelPrefix, ListingElement, MacroParam, MacroParamElement,
     Mnemonic, Register8, Register16, SaveType, StableTickerAction, TestKind, TestKindElement,
-    ToSimpleToken, Token, UnaryOperation, UnaryTokenOperation, data_access_impl_most_methods,
-    data_access_is_any_indexregister8, data_access_is_any_indexregister16,
-    data_access_is_any_register8, data_access_is_any_register16, listing_element_impl_most_methods
+    ToSimpleToken, Token, UnaryOperation, UnaryTokenOperation, UnitTestStep,
+    data_access_impl_most_methods, data_access_is_any_indexregister8,
+    data_access_is_any_indexregister16, data_access_is_any_register8,
+    data_access_is_any_register16, listing_element_impl_most_methods
 };
 use ouroboros::self_referencing;
+use serde::de::{Error as _, SeqAccess, Visitor};
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use super::{
-    InnerZ80Span, ParserContext, SourceString, Z80ParserError, Z80Span, build_span,
-    my_many0_nocollect, parse_lines, parse_single_token, parse_z80_line_complete
+    InnerZ80Span, ParserContext, SourceString, SpanContextGuard, Z80ParserError, Z80Span,
+    build_span, my_many0_nocollect, parse_lines, parse_single_token, parse_z80_line_complete
 };
 use crate::assembler::Env;
 use crate::error::AssemblerError;
@@ -53,7 +57,7 @@ use crate::{
     ensure_orgams_type, resolve_impl
 };
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum LocatedExpr {
     RelativeDelta(i8, Z80Span),
     Value(i32, Z80Span),
@@ -95,7 +99,7 @@ impl std::fmt::Display for LocatedExpr {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct UnescapedString(pub(crate) String, pub(crate) Z80Span);
 
 impl AsRef<str> for UnescapedString {
@@ -569,7 +573,7 @@ impl MayHaveSpan for LocatedExpr {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum LocatedDataAccess {
     /// We are using an indexed register associated to its index
     IndexRegister16WithIndex(IndexRegister16, BinaryOperation, LocatedExpr, Z80Span),
@@ -740,7 +744,7 @@ impl DataAccessElem for LocatedDataAccess {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum LocatedMacroParam {
     Empty,
     /// Standard argument directly propagated
@@ -825,7 +829,7 @@ impl LocatedMacroParam {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum LocatedTestKind {
     // Test succeed if it is an expression that returns True
     True(LocatedExpr),
@@ -897,7 +901,7 @@ impl TestKindElement for LocatedTestKind {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum LocatedAssemblerControlCommand {
     RestrictedAssemblingEnvironment {
         passes: Option<LocatedExpr>,
@@ -955,7 +959,7 @@ impl AssemblerControlCommand for LocatedAssemblerControlCommand {
 }
 
 // Encode the LocatedToken BEFORE computing its span
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum LocatedTokenInner {
     Abyte(LocatedExpr, Vec<LocatedExpr>),
     Align(LocatedExpr, Option<LocatedExpr>),
@@ -1012,6 +1016,7 @@ pub enum LocatedTokenInner {
     Export(Vec<Z80Span>),
 
     Fail(Option<Vec<FormattedExpr>>),
+    Flavor(AssemblerFlavor),
     Field {
         label: Z80Span,
         expr: LocatedExpr
@@ -1036,6 +1041,11 @@ pub enum LocatedTokenInner {
         off: bool,
         transformation: BinaryTransformation
     },
+    IncImage {
+        fname: LocatedExpr,
+        mode: LocatedExpr,
+        format: ImageFormat<LocatedExpr>
+    },
     Include(LocatedExpr, Option<Z80Span>, bool),
     Iterate(
         Z80Span,
@@ -1051,6 +1061,8 @@ pub enum LocatedTokenInner {
     Macro {
         name: Z80Span,
         params: Vec<Z80Span>,
+        /// Default value of each parameter (`MACRO foo a, b=2`)
+        defaults: Vec<Option<Z80Span>>,
         content: Z80Span,
         flavor: AssemblerFlavor,
         tokenized_content: TokenizedMacroContent,
@@ -1111,6 +1123,7 @@ pub enum LocatedTokenInner {
         size: Option<LocatedExpr>,
         save_type: Option<SaveType>,
         dsk_filename: Option<LocatedExpr>,
+        /// Side of the disc, or speed in bauds of the tape
         side: Option<LocatedExpr>
     },
     Section(Z80Span),
@@ -1122,6 +1135,8 @@ pub enum LocatedTokenInner {
     Skip(LocatedExpr),
     SnaInit(LocatedExpr),
     SnaSet(SnapshotFlag, FlagValue),
+    /// STABILIZE block: its conditional branches are padded to last the same duration
+    Stabilize(LocatedListing),
     StableTicker(StableTickerAction<Z80Span>),
     StartingIndex {
         start: Option<LocatedExpr>,
@@ -1135,12 +1150,19 @@ pub enum LocatedTokenInner {
         Option<LocatedListing>
     ),
     Undef(Z80Span),
+    UnitTest {
+        name: UnescapedString,
+        steps: Vec<LocatedUnitTestStep>
+    },
 
     WaitNops(LocatedExpr),
     /// The directive warning
     Warning(Option<Vec<FormattedExpr>>),
     /// An instruction or directive that raises a warning
-    WarningWrapper(Box<Self>, beef::lean::Cow<'static, str>),
+    WarningWrapper(
+        Box<Self>,
+        #[serde(with = "warning_message")] beef::lean::Cow<'static, str>
+    ),
     While(LocatedExpr, LocatedListing)
 }
 
@@ -1228,14 +1250,74 @@ impl LocatedTokenInner {
 }
 
 /// Add span information for a Token.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct LocatedToken {
     // The token of interest or a warning with the token of interest
+    #[serde(with = "located_token_content")]
     pub(crate) inner:
         either::Either<LocatedTokenInner, (Box<LocatedToken>, beef::lean::Cow<'static, str>)>,
     pub(crate) span: Z80Span
 }
 
+/// The warning messages are (de)serialized as owned strings
+mod warning_message {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        msg: &beef::lean::Cow<'static, str>,
+        serializer: S
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(msg)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D
+    ) -> Result<beef::lean::Cow<'static, str>, D::Error> {
+        String::deserialize(deserializer).map(beef::lean::Cow::owned)
+    }
+}
+
+/// The content of a [`LocatedToken`]: the token itself or a warning about the token
+mod located_token_content {
+    use serde::ser::SerializeTupleVariant;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::{LocatedToken, LocatedTokenInner};
+
+    type Content =
+        either::Either<LocatedTokenInner, (Box<LocatedToken>, beef::lean::Cow<'static, str>)>;
+
+    #[derive(Deserialize)]
+    enum LocatedTokenContent {
+        Token(Box<LocatedTokenInner>),
+        Warning(Box<LocatedToken>, String)
+    }
+
+    pub fn serialize<S: Serializer>(inner: &Content, serializer: S) -> Result<S::Ok, S::Error> {
+        match inner {
+            either::Left(token) => {
+                serializer.serialize_newtype_variant("LocatedTokenContent", 0, "Token", token)
+            },
+            either::Right((token, msg)) => {
+                let mut variant =
+                    serializer.serialize_tuple_variant("LocatedTokenContent", 1, "Warning", 2)?;
+                variant.serialize_field(token)?;
+                variant.serialize_field(msg.as_ref())?;
+                variant.end()
+            }
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Content, D::Error> {
+        Ok(match LocatedTokenContent::deserialize(deserializer)? {
+            LocatedTokenContent::Token(token) => either::Left(*token),
+            LocatedTokenContent::Warning(token, msg) => {
+                either::Right((token, beef::lean::Cow::owned(msg)))
+            },
+        })
+    }
+}
+
 macro_rules! is_stuff_delegate {
     ($($name: ident)*) => {
         $(
@@ -1284,7 +1366,8 @@ impl ListingElement for LocatedToken {
         is_db is_dw is_str is_set is_comment is_org
         is_assembler_control is_while is_assert
         is_run is_breakpoint is_save
-        is_repeat_token is_return
+        is_repeat_token is_return is_stabilize
+        is_waitnops
     );
 
     any_delegate!(
@@ -1313,6 +1396,7 @@ impl ListingElement for LocatedToken {
         fn repeat_counter_step(&self) -> Option<&Self::Expr>;
         fn macro_definition_name(&self) -> &str;
         fn macro_definition_arguments(&self) -> SmallVec<[&str; 4]>;
+        fn macro_definition_defaults(&self) -> SmallVec<[Option<&str>; 4]>;
         fn macro_definition_code(&self) -> &str;
         fn macro_definition_is_variadic(&self) -> bool;
         fn macro_call_name(&self) -> &str;
@@ -1337,6 +1421,7 @@ impl ListingElement for LocatedToken {
         fn org_first(&self) -> &Self::Expr;
         fn org_second(&self) -> Option<&Self::Expr>;
         fn return_value(&self) -> &Self::Expr;
+        fn waitnops_count(&self) -> &Self::Expr;
     );
 
     fn to_token(&self) -> Cow<'_, cpclib_tokens::Token> {
@@ -1471,6 +1556,13 @@ impl ListingElement for LocatedToken {
         }
     }
 
+    fn stabilize_listing(&self) -> &[Self] {
+        match &self.inner {
+            either::Left(LocatedTokenInner::Stabilize(lst)) => lst,
+            _ => unreachable!()
+        }
+    }
+
     fn switch_cases(&self) -> Box<dyn Iterator<Item = (&Self::Expr, &[Self], bool)> + '_> {
         match &self.inner {
             either::Left(LocatedTokenInner::Switch(_, cases, ..)) => {
@@ -1633,6 +1725,26 @@ impl ListingElement for LocatedTokenInner {
                     symbols.extend(extended_offset.symbols());
                 }
             },
+            Self::IncImage {
+                fname,
+                mode,
+                format
+            } => {
+                symbols.extend(fname.symbols());
+                symbols.extend(mode.symbols());
+                if let ImageFormat::MaskedSprite {
+                    mask_ink: first,
+                    replacement_ink: second
+                }
+                | ImageFormat::Tiles {
+                    width: first,
+                    height: second
+                } = format
+                {
+                    symbols.extend(first.symbols());
+                    symbols.extend(second.symbols());
+                }
+            },
             Self::Include(fname, ..) => {
                 symbols.extend(fname.symbols());
             },
@@ -1662,7 +1774,7 @@ impl ListingElement for LocatedTokenInner {
             Self::Assert(expr, _) => {
                 symbols.extend(expr.symbols());
             },
-            Self::Fail(_) | Self::Warning(_) => {},
+            Self::Fail(_) | Self::Flavor(_) | Self::Warning(_) => {},
             Self::OutputFile(expr) => {
                 symbols.extend(expr.symbols());
             },
@@ -1799,6 +1911,26 @@ impl ListingElement for LocatedTokenInner {
                     params.iter().map(|p| p.to_macro_param()).collect_vec()
                 ))
             },
+            Self::IncImage {
+                fname,
+                mode,
+                format
+            } => {
+                Cow::Owned(Token::IncImage {
+                    fname: fname.to_expr().into_owned(),
+                    mode: mode.to_expr().into_owned(),
+                    format: format.map(|e| e.to_expr().into_owned())
+                })
+            },
+            Self::UnitTest { name, steps } => {
+                Cow::Owned(Token::UnitTest {
+                    name: name.as_ref().into(),
+                    steps: steps
+                        .iter()
+                        .map(|s| s.step().map(|e| e.to_expr().into_owned()))
+                        .collect_vec()
+                })
+            },
             Self::Struct(name, params) => {
                 Cow::Owned(Token::Struct(
                     name.into(),
@@ -1845,6 +1977,7 @@ impl ListingElement for LocatedTokenInner {
             Self::Macro {
                 name,
                 params,
+                defaults,
                 content,
                 flavor,
                 tokenized_content,
@@ -1853,13 +1986,18 @@ impl ListingElement for LocatedTokenInner {
                 Cow::Owned(Token::Macro {
                     name: name.into(),
                     params: params.iter().map(|p| p.into()).collect_vec(),
+                    defaults: defaults
+                        .iter()
+                        .map(|d| d.as_ref().map(|d| d.into()))
+                        .collect_vec(),
                     content: content.as_str().to_owned(),
                     flavor: *flavor,
                     tokenized_content: tokenized_content.clone(),
                     has_variadic: *has_variadic
                 })
             },
-            Self::Confined(..) => todo!(),
+            Self::Confined(l) => Cow::Owned(Token::Confined(l.as_listing())),
+            Self::Stabilize(l) => Cow::Owned(Token::Stabilize(l.as_listing())),
             Self::WarningWrapper(inner, msg) => {
                 Cow::Owned(Token::WarningWrapper(
                     Box::new(inner.to_token().into_owned()),
@@ -1914,6 +2052,7 @@ impl ListingElement for LocatedTokenInner {
             },
 
             Self::Fail(msg) => Cow::Owned(Token::Fail(msg.clone())),
+            Self::Flavor(flavor) => Cow::Owned(Token::Flavor(*flavor)),
             Self::Warning(msg) => Cow::Owned(Token::Warning(msg.clone())),
             Self::OutputFile(filename) => {
                 Cow::Owned(Token::OutputFile(filename.to_expr().into_owned()))
@@ -2047,6 +2186,15 @@ impl ListingElement for LocatedTokenInner {
         todo!()
     }
 
+    fn is_stabilize(&self) -> bool {
+        matches!(self, LocatedTokenInner::Stabilize(..))
+    }
+
+    // the block contains LocatedToken and is only visited through them
+    fn stabilize_listing(&self) -> &[Self] {
+        unreachable!()
+    }
+
     fn switch_cases(&self) -> Box<dyn Iterator<Item = (&Self::Expr, &[Self], bool)> + '_> {
         todo!()
     }
@@ -2109,6 +2257,61 @@ impl ToSimpleToken for LocatedToken {
     }
 }
 
+/// Step of a TEST block with its location in the source
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LocatedUnitTestStep(pub(crate) UnitTestStep<LocatedExpr>, pub(crate) Z80Span);
+
+/// Give access to the steps of a TEST block whatever is the kind of token
+pub trait UnitTestStepElement: MayHaveSpan {
+    type Expr: ExprEvaluationExt;
+
+    fn step(&self) -> &UnitTestStep<Self::Expr>;
+}
+
+impl UnitTestStepElement for LocatedUnitTestStep {
+    type Expr = LocatedExpr;
+
+    fn step(&self) -> &UnitTestStep<Self::Expr> {
+        &self.0
+    }
+}
+
+impl MayHaveSpan for LocatedUnitTestStep {
+    fn possible_span(&self) -> Option<&Z80Span> {
+        Some(&self.1)
+    }
+
+    fn span(&self) -> &Z80Span {
+        &self.1
+    }
+
+    fn has_span(&self) -> bool {
+        true
+    }
+}
+
+impl UnitTestStepElement for UnitTestStep<Expr> {
+    type Expr = Expr;
+
+    fn step(&self) -> &UnitTestStep<Self::Expr> {
+        self
+    }
+}
+
+impl MayHaveSpan for UnitTestStep<Expr> {
+    fn possible_span(&self) -> Option<&Z80Span> {
+        None
+    }
+
+    fn span(&self) -> &Z80Span {
+        panic!("A raw TEST step does not have a span")
+    }
+
+    fn has_span(&self) -> bool {
+        false
+    }
+}
+
 /// Trait to handle the span of listing elements
 pub trait MayHaveSpan {
     fn possible_span(&self) -> Option<&Z80Span>;
@@ -2527,6 +2730,60 @@ impl std::fmt::Debug for LocatedListing {
     }
 }
 
+/// Only the listings of inner blocks are (de)serialized this way: their context is rebuilt from
+/// the one of their span, which must already be registered by a [`SpanContextGuard`]
+impl Serialize for LocatedListing {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let _guard = SpanContextGuard::new(self.ctx());
+        self.with_parse_result(|parse_result| {
+            match parse_result {
+                ParseResult::SuccessInner {
+                    listing,
+                    inner_span
+                } => (self.ctx().state, inner_span, listing).serialize(serializer),
+                _ => Err(S::Error::custom("only the inner listings are serializable"))
+            }
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for LocatedListing {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct InnerListingVisitor;
+
+        impl<'de> Visitor<'de> for InnerListingVisitor {
+            type Value = LocatedListing;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("an inner listing")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<LocatedListing, A::Error> {
+                let missing = || A::Error::custom("incomplete inner listing");
+                let state: ParsingState = seq.next_element()?.ok_or_else(missing)?;
+                let inner_span: Z80Span = seq.next_element()?.ok_or_else(missing)?;
+                let ctx = inner_span.context().clone_with_state(state);
+
+                LocatedListingTryBuilder {
+                    src: None,
+                    ctx_builder: move |_src| Ok(ctx),
+                    parse_result_builder: |_src, ctx| {
+                        let _guard = SpanContextGuard::new(ctx);
+                        let listing = seq.next_element()?.ok_or_else(missing)?;
+                        Ok(ParseResult::SuccessInner {
+                            listing,
+                            inner_span
+                        })
+                    }
+                }
+                .try_build()
+            }
+        }
+
+        deserializer.deserialize_tuple(3, InnerListingVisitor)
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub(crate) enum ParseResult {
     /// Assembling is successful for a complete file
@@ -2624,6 +2881,33 @@ impl LocatedListing {
         }
     }
 
+    /// Build the listing of a complete source from the tokens provided by `tokens` instead of
+    /// parsing it. The spans deserialized by `tokens` refer to the new listing
+    pub(crate) fn try_new_complete_source_from<S: Into<String>, E>(
+        code: S,
+        builder: ParserContextBuilder,
+        tokens: impl FnOnce() -> Result<Vec<LocatedToken>, E>
+    ) -> Result<LocatedListing, E> {
+        LocatedListingTryBuilder {
+            src: Some(code.into().into()),
+            ctx_builder: move |src: &Option<Arc<String>>| {
+                let source = src.as_ref().map(|s| s.as_str()).unwrap();
+                Ok(builder.build(source))
+            },
+            parse_result_builder: |_, ctx| {
+                let _guard = SpanContextGuard::new(ctx);
+                tokens().map(|tokens| ParseResult::SuccessComplete(tokens.into()))
+            }
+        }
+        .try_build()
+    }
+
+    /// Give the tokens of a complete source to `f` while their spans are serializable
+    pub(crate) fn with_serializable_tokens<R>(&self, f: impl FnOnce(&[LocatedToken]) -> R) -> R {
+        let _guard = SpanContextGuard::new(self.ctx());
+        f(self.as_slice())
+    }
+
     /// By definition code is store in a Z80Span because the original string is Already contained in another Listing as a String
     /// As the code is already owned by another LocatedListing, we can return error messages that refer it
     #[inline]
@@ -2796,6 +3080,19 @@ impl LocatedListing {
         })
     }
 
+    /// Give a mutable access to the tokens of a properly parsed listing (e.g. to rewrite them before assembling).
+    /// Return None when the listing is a parse failure
+    pub fn with_tokens_mut<R>(&mut self, f: impl FnOnce(&mut Vec<LocatedToken>) -> R) -> Option<R> {
+        self.with_parse_result_mut(|parse_result| {
+            match parse_result {
+                ParseResult::SuccessComplete(listing) | ParseResult::SuccessInner { listing, .. } => {
+                    Some(f(listing.listing_mut()))
+                },
+                ParseResult::FailureInner(_) | ParseResult::FailureComplete(_) => None
+            }
+        })
+    }
+
     // pub fn fix_local_macro_labels_with_seed(&mut self, seed: usize) {
     // self.iter_mut()
     // .for_each(|e| e.fix_local_macro_labels_with_seed(seed));
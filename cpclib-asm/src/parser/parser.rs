@@ -1179,6 +1179,29 @@ add_to_a(3)\n\
         assert!(token.macro_definition_is_variadic());
     }
 
+    #[test]
+    fn test_parse_macro_default_values() {
+        let mut tokens = Vec::with_capacity(16);
+        let r#macro = "macro foo(a, b = 2, c=\"1, 2\", ...)
+                db {a}
+            endm;";
+        tokens.clear();
+        assert!(dbg!(parse_test(parse_line(&mut tokens), r#macro)).is_ok());
+        let token = tokens
+            .iter()
+            .find(|t| t.is_macro_definition())
+            .expect("a macro-definition token should have been parsed");
+        assert_eq!(
+            token.macro_definition_arguments().as_slice(),
+            ["a", "b", "c"]
+        );
+        assert_eq!(
+            token.macro_definition_defaults().as_slice(),
+            [None, Some("2"), Some("\"1, 2\"")]
+        );
+        assert!(token.macro_definition_is_variadic());
+    }
+
     #[test]
     fn test_parse_macro_not_variadic_without_trailing_ellipsis() {
         let mut tokens = Vec::with_capacity(16);
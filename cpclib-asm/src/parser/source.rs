@@ -1,13 +1,18 @@
 // SAFETY: All fields of Z80Span are Sync (InnerZ80Span, which is a wrapper around references to static data and ParserContext)
 unsafe impl Sync for Z80Span {}
+use std::cell::RefCell;
 use std::fmt::Display;
+use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
 
 use cpclib_common::smol_str::SmolStr;
-use cpclib_common::winnow::stream::{AsBStr, LocatingSlice, Offset};
+use cpclib_common::winnow::stream::{AsBStr, LocatingSlice, Offset, UpdateSlice};
 use cpclib_common::winnow::{BStr, Stateful};
 use cpclib_tokens::symbols::{SourceLocation, Symbol};
 use line_span::LineSpanExt;
+use serde::de::Error as _;
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use super::ParsingState;
 use super::context::ParserContext;
@@ -293,6 +298,108 @@ impl Z80Span {
     }
 }
 
+thread_local! {
+    /// Contexts of the listings whose spans are currently serialized or deserialized.
+    /// Only filled by [`SpanContextGuard`], which keeps each pointer valid while it is stored
+    static SPAN_CONTEXTS: RefCell<Vec<*const ParserContext>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Allows the spans of a context to be serialized and deserialized until it is dropped.
+/// A span is stored as the index of its context and its offsets within the source code, so
+/// the listing that owns the source must register its contexts in the same order for both.
+///
+/// The guard borrows the context, so the context outlives its registration, and the guards
+/// must be dropped in the reverse order of their creation (which is checked)
+pub(crate) struct SpanContextGuard<'ctx> {
+    ctx: *const ParserContext,
+    _ctx: PhantomData<&'ctx ParserContext>
+}
+
+impl<'ctx> SpanContextGuard<'ctx> {
+    pub(crate) fn new(ctx: &'ctx ParserContext) -> Self {
+        let ctx = ctx as *const ParserContext;
+        SPAN_CONTEXTS.with_borrow_mut(|contexts| contexts.push(ctx));
+        Self {
+            ctx,
+            _ctx: PhantomData
+        }
+    }
+}
+
+impl Drop for SpanContextGuard<'_> {
+    fn drop(&mut self) {
+        let last = SPAN_CONTEXTS.with_borrow_mut(|contexts| contexts.pop());
+        assert_eq!(
+            last,
+            Some(self.ctx),
+            "span contexts must be unregistered in the reverse order of their registration"
+        );
+    }
+}
+
+/// (context, start of the parsed input, its length, start of the span, its length)
+type SerializedSpan = (usize, usize, usize, usize, usize);
+
+impl Serialize for Z80Span {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let ctx = self.context();
+        let ctx_idx = SPAN_CONTEXTS
+            .with_borrow(|contexts| contexts.iter().position(|c| std::ptr::eq(*c, ctx)))
+            .ok_or_else(|| S::Error::custom("span of an unknown context"))?;
+
+        let source = ctx.source.as_bstr();
+        let offset = |bytes: &[u8]| {
+            let start = (bytes.as_ptr() as usize).wrapping_sub(source.as_ptr() as usize);
+            if start
+                .checked_add(bytes.len())
+                .is_some_and(|end| end <= source.len())
+            {
+                Ok((start, bytes.len()))
+            }
+            else {
+                Err(S::Error::custom("span out of its source code"))
+            }
+        };
+
+        let mut initial = self.0.input;
+        initial.reset_to_start();
+        let (initial_start, initial_len) = offset(initial.as_bstr())?;
+        let (start, len) = offset(self.as_bstr())?;
+
+        (ctx_idx, initial_start, initial_len, start, len).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Z80Span {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (ctx_idx, initial_start, initial_len, start, len) =
+            SerializedSpan::deserialize(deserializer)?;
+
+        let ctx = SPAN_CONTEXTS
+            .with_borrow(|contexts| contexts.get(ctx_idx).copied())
+            .ok_or_else(|| D::Error::custom("span of an unknown context"))?;
+        // SAFETY: the registered contexts are alive until their guard is dropped. The guards are
+        // only created by the listing that owns the context while it builds its tokens, so the
+        // spans end in that listing and do not outlive the context, as the spans of the parser
+        let ctx = unsafe { &*ctx as &'static ParserContext };
+
+        let source: &'static [u8] = ctx.source.as_bstr();
+        let slice = |start: usize, len: usize| {
+            start
+                .checked_add(len)
+                .and_then(|end| source.get(start..end))
+                .ok_or_else(|| D::Error::custom("span out of its source code"))
+        };
+        let initial = slice(initial_start, initial_len)?;
+        let input = slice(start, len)?;
+
+        Ok(Self(Stateful {
+            input: LocatingSlice::new(BStr::new(initial)).update_slice(BStr::new(input)),
+            state: ctx
+        }))
+    }
+}
+
 impl Z80Span {
     // Used when the state is changing (it controls the parsing)
     // pub fn clone_with_state(&self, state: ParsingState) -> Self {
@@ -1,159 +1,868 @@
-use crate::*;
-use crate::builder;
+//! Peephole optimisation of an already parsed listing.
+//!
+//! Based on some suggestions provided here: http://z80-heaven.wikidot.com/optimization
+//! The pass looks at each instruction (or at a short window starting at it)
+//! and replaces it by a cheaper equivalent. Every applied rewrite is
+//! reported with the bytes and NOPs it saves.
+//!
+//! Some rewrites are only valid when a register or a flag is not read
+//! anymore afterwards (`ld a,0` -> `xor a` destroys the flags, a dead `ld`
+//! is simply dropped). This module has no control-flow aware register model
+//! of its own: the caller provides the liveness of every token (basm relies
+//! on `cpclib_z80emu::track::liveness`), the same way `branch_balance` lets
+//! its caller provide the instruction costs.
+//!
+//! The pass is deliberately conservative:
+//! - only instructions are rewritten; macro calls, directives and included
+//!   files are assembled as written, and the code nested in blocks
+//!   (`REPEAT`, `IF`, `MODULE`, ...) is optimised block by block;
+//! - an instruction that directly follows a label, or that is directly
+//!   followed by an `EQU`/assignment, is left untouched as its bytes may be
+//!   patched at runtime (self-modifying code);
+//! - relative jumps are only introduced when every byte between the jump
+//!   and its target is known.
 
-/// Based on some suggestions provide here: http://z80-heaven.wikidot.com/optimization
-/// propose some classes to optimize code
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::ops::Range;
 
-/// This trait concerns simple optimisations that transform one Instruction in another one
-trait SingleInstructionModifier {
-    /// REturn True if the token can be replaces
-    fn match(&self, token: &Token) -> bool;
-    /// Generate the good value (never use if it does not match)
-    fn replace(&self, token: &Token) -> Token;
+use cpclib_tokens::{
+    DataAccessElem, ExprElement, FlagTest, IndexRegister8, IndexRegister16, ListingElement,
+    Mnemonic, Register8, Register16
+};
+use enumflags2::{BitFlags, bitflags};
+
+use crate::implementation::tokens::TokenExt;
+use crate::parser::obtained::{
+    LocatedDataAccess, LocatedExpr, LocatedListing, LocatedToken, LocatedTokenInner, MayHaveSpan
+};
+
+/// Registers and flags whose liveness drives the optimisations.
+/// Shadow and special registers are not tracked.
+#[bitflags]
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Resource {
+    A,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    Ixh,
+    Ixl,
+    Iyh,
+    Iyl,
+    Sp,
+    FlagS,
+    FlagZ,
+    FlagH,
+    FlagPV,
+    FlagN,
+    FlagC
+}
+
+/// Set of registers and flags whose value may still be read
+pub type LiveSet = BitFlags<Resource>;
+
+impl Resource {
+    /// Every flag of the F register
+    pub fn flags() -> LiveSet {
+        Self::FlagS | Self::FlagZ | Self::FlagH | Self::FlagPV | Self::FlagN | Self::FlagC
+    }
+
+    pub fn register8(r: Register8) -> Self {
+        match r {
+            Register8::A => Self::A,
+            Register8::B => Self::B,
+            Register8::C => Self::C,
+            Register8::D => Self::D,
+            Register8::E => Self::E,
+            Register8::H => Self::H,
+            Register8::L => Self::L
+        }
+    }
+
+    pub fn index8(r: IndexRegister8) -> Self {
+        match r {
+            IndexRegister8::Ixh => Self::Ixh,
+            IndexRegister8::Ixl => Self::Ixl,
+            IndexRegister8::Iyh => Self::Iyh,
+            IndexRegister8::Iyl => Self::Iyl
+        }
+    }
+
+    /// Both halves of a register pair. `AF` covers the accumulator and every flag
+    pub fn register16(r: Register16) -> LiveSet {
+        match r {
+            Register16::Af => Self::flags() | Self::A,
+            Register16::Bc => Self::B | Self::C,
+            Register16::De => Self::D | Self::E,
+            Register16::Hl => Self::H | Self::L,
+            Register16::Sp => Self::Sp.into()
+        }
+    }
+
+    pub fn index16(r: IndexRegister16) -> LiveSet {
+        Self::index8(r.high()) | Self::index8(r.low())
+    }
 
-    fn replace_if_match(&self, token: &Token) -> Token {
-        if self.match(token) {
-            self.replace(token)
+    /// Flag read by a condition
+    pub fn flag_test(test: FlagTest) -> Self {
+        match test {
+            FlagTest::NZ | FlagTest::Z => Self::FlagZ,
+            FlagTest::NC | FlagTest::C => Self::FlagC,
+            FlagTest::PO | FlagTest::PE => Self::FlagPV,
+            FlagTest::P | FlagTest::M => Self::FlagS
+        }
+    }
+}
+
+/// Position of the labels defined in a flat sequence of tokens.
+/// Local labels are qualified by their global label, as basm does.
+#[derive(Debug, Default)]
+pub struct LabelIndex {
+    /// Global label in effect at each token
+    globals: Vec<Option<String>>,
+    /// Every definition of each (qualified) label
+    definitions: HashMap<String, Vec<usize>>
+}
+
+impl LabelIndex {
+    pub fn new<T: ListingElement>(tokens: &[T]) -> Self {
+        let mut index = Self::default();
+        let mut current_global: Option<String> = None;
+
+        for (idx, token) in tokens.iter().enumerate() {
+            if token.is_label() {
+                let name = token.label_symbol();
+                let qualified = if name.starts_with('.') {
+                    format!("{}{name}", current_global.as_deref().unwrap_or_default())
+                }
+                else {
+                    current_global = Some(name.to_owned());
+                    name.to_owned()
+                };
+                index.definitions.entry(qualified).or_default().push(idx);
+            }
+            index.globals.push(current_global.clone());
+        }
+
+        index
+    }
+
+    /// Index of the label token `label` refers to when used by the token at `from`.
+    /// None when the label is not defined in the sequence or defined several times
+    pub fn resolve(&self, label: &str, from: usize) -> Option<usize> {
+        let qualified = if label.starts_with('.') {
+            let global = self
+                .globals
+                .get(from)
+                .and_then(|g| g.as_deref())
+                .unwrap_or_default();
+            format!("{global}{label}")
         }
         else {
-            *token
+            label.to_owned()
+        };
+
+        match self.definitions.get(&qualified).map(Vec::as_slice) {
+            Some(&[idx]) => Some(idx),
+            _ => None
         }
     }
 }
 
-/// Favor xor a to Ld A
+/// One rewrite applied by the optimiser
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rewrite {
+    /// Name of the rule that produced it
+    pub rule: &'static str,
+    pub filename: String,
+    /// Line of the first rewritten instruction
+    pub line: usize,
+    /// Instructions that have been replaced
+    pub before: Vec<String>,
+    /// Instructions that replace them (empty when they are removed)
+    pub after: Vec<String>,
+    pub saved_bytes: usize,
+    pub saved_nops: usize
+}
+
+impl Display for Rewrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let after = if self.after.is_empty() {
+            "(removed)".to_owned()
+        }
+        else {
+            self.after.join(" : ")
+        };
+        write!(
+            f,
+            "{}:{}: {} -> {} [{}] saves {} byte(s) and {} nop(s)",
+            self.filename,
+            self.line,
+            self.before.join(" : "),
+            after,
+            self.rule,
+            self.saved_bytes,
+            self.saved_nops
+        )
+    }
+}
+
+/// How an instruction is modified by a rule
+enum Replacement {
+    Remove,
+    By(Box<LocatedToken>)
+}
+
+/// Modifications proposed by a rule, indexed by token position
+type Edits = Vec<(usize, Replacement)>;
+
+/// This trait concerns simple optimisations that transform one Instruction in another one
+trait SingleInstructionModifier {
+    fn name(&self) -> &'static str;
+    /// Replacement of the instruction knowing the registers and flags read after it.
+    /// None when the rule does not apply
+    fn replace(&self, token: &LocatedToken, live_after: LiveSet) -> Option<Replacement>;
+}
+
+/// This trait concerns the modification of several instructions in a row
+trait MultipleInstructionsModifier {
+    fn name(&self) -> &'static str;
+    /// Modifications of the instructions starting at `idx`. None when the rule does not apply
+    fn rewrite(&self, window: &Window, idx: usize) -> Option<Edits>;
+}
+
+/// Favor xor a to Ld A,0
 struct ResetA;
 /// Favor or a over cp 0
 struct CompareA;
+/// Remove ld r,r
+struct UselessLoad;
+/// Remove a load whose destination is never read
+struct DeadLoad;
+/// Remove the second load of ld x,y : ld y,x
+struct RedundantLoad;
+/// Replace call x : ret by jp x
+struct TailCall;
+/// Favor jr over jp when the target is close enough
+struct ShortJump;
+
+const SINGLE_INSTRUCTION_RULES: &[&dyn SingleInstructionModifier] =
+    &[&UselessLoad, &DeadLoad, &ResetA, &CompareA];
+const MULTIPLE_INSTRUCTIONS_RULES: &[&dyn MultipleInstructionsModifier] =
+    &[&RedundantLoad, &TailCall, &ShortJump];
+
+fn opcode(
+    span: &crate::Z80Span,
+    mnemonic: Mnemonic,
+    arg1: Option<LocatedDataAccess>,
+    arg2: Option<LocatedDataAccess>
+) -> LocatedToken {
+    LocatedToken {
+        inner: either::Left(LocatedTokenInner::new_opcode(mnemonic, arg1, arg2)),
+        span: span.clone()
+    }
+}
+
+fn is_zero(access: Option<&LocatedDataAccess>) -> bool {
+    matches!(
+        access,
+        Some(LocatedDataAccess::Expression(LocatedExpr::Value(0, _)))
+    )
+}
+
+/// Registers written when the access is used as a destination
+fn written_registers(access: &LocatedDataAccess) -> Option<LiveSet> {
+    match access {
+        LocatedDataAccess::Register8(r, _) => Some(Resource::register8(*r).into()),
+        LocatedDataAccess::IndexRegister8(r, _) => Some(Resource::index8(*r).into()),
+        LocatedDataAccess::Register16(r, _) => Some(Resource::register16(*r)),
+        LocatedDataAccess::IndexRegister16(r, _) => Some(Resource::index16(*r)),
+        _ => None
+    }
+}
 
 impl SingleInstructionModifier for ResetA {
-    fn match(&self, token: &Token) -> bool {
-        match token {
-            Token::OpCode(
-                Mnemonic::Ld, 
-                Some(DataAccess::Register8(Register8::A)),
-                Some(DataAccess::Expression(Expression::Value(0))) // TODO allow to compute on the fly if it is 0
-            ) => true,
-            _ => false
+    fn name(&self) -> &'static str {
+        "ld a,0 -> xor a"
+    }
+
+    fn replace(&self, token: &LocatedToken, live_after: LiveSet) -> Option<Replacement> {
+        match (token.mnemonic(), token.mnemonic_arg1()) {
+            (Some(Mnemonic::Ld), Some(LocatedDataAccess::Register8(Register8::A, span)))
+                if is_zero(token.mnemonic_arg2()) && !live_after.intersects(Resource::flags()) =>
+            {
+                Some(Replacement::By(Box::new(opcode(
+                    token.span(),
+                    Mnemonic::Xor,
+                    None,
+                    Some(LocatedDataAccess::Register8(Register8::A, span.clone()))
+                ))))
+            },
+            _ => None
         }
     }
+}
 
-    fn replace(&self, token: &Token) -> Token {
-        xor_a()
+impl SingleInstructionModifier for CompareA {
+    fn name(&self) -> &'static str {
+        "cp 0 -> or a"
+    }
+
+    // `CP`'s first slot is the optional explicit `A,` prefix and doesn't
+    // affect whether this optimization applies; the compared value is
+    // always in the second slot. Both set S, Z, H and C the same way.
+    fn replace(&self, token: &LocatedToken, live_after: LiveSet) -> Option<Replacement> {
+        if token.mnemonic() == Some(&Mnemonic::Cp)
+            && is_zero(token.mnemonic_arg2())
+            && !live_after.intersects(Resource::FlagPV | Resource::FlagN)
+        {
+            let span = token.span();
+            Some(Replacement::By(Box::new(opcode(
+                span,
+                Mnemonic::Or,
+                None,
+                Some(LocatedDataAccess::Register8(Register8::A, span.clone()))
+            ))))
+        }
+        else {
+            None
+        }
     }
 }
 
+impl SingleInstructionModifier for UselessLoad {
+    fn name(&self) -> &'static str {
+        "ld r,r"
+    }
 
-impl SingleInstructionModifier for CompareA {
-    fn match(&self, token: &Token) -> bool {
-        match token {
-            // `CP`'s first slot is the optional explicit `A,` prefix (`CP
-            // A,0` vs bare `CP 0` - see `parse_cp` in cpclib-asm's parser)
-            // and doesn't affect whether this optimization applies; the
-            // compared value is always in the second slot.
-            Token::OpCode(
-                Mnemonic::Cp,
-                _,
-                Some(DataAccess::Expression(Expression::Value(0))) // TODO allow to compute on the fly if it is 0
-            ) => true,
-            _ => false
+    fn replace(&self, token: &LocatedToken, _live_after: LiveSet) -> Option<Replacement> {
+        match (
+            token.mnemonic(),
+            token.mnemonic_arg1(),
+            token.mnemonic_arg2()
+        ) {
+            (
+                Some(Mnemonic::Ld),
+                Some(LocatedDataAccess::Register8(dst, _)),
+                Some(LocatedDataAccess::Register8(src, _))
+            ) if dst == src => Some(Replacement::Remove),
+            (
+                Some(Mnemonic::Ld),
+                Some(LocatedDataAccess::IndexRegister8(dst, _)),
+                Some(LocatedDataAccess::IndexRegister8(src, _))
+            ) if dst == src => Some(Replacement::Remove),
+            _ => None
         }
     }
+}
+
+impl SingleInstructionModifier for DeadLoad {
+    fn name(&self) -> &'static str {
+        "dead ld"
+    }
 
-    fn replace(&self, token: &Token) -> Token {
-        or_a()
+    fn replace(&self, token: &LocatedToken, live_after: LiveSet) -> Option<Replacement> {
+        if token.mnemonic() != Some(&Mnemonic::Ld) {
+            return None;
+        }
+        // ld a,i and ld a,r also modify the flags
+        if matches!(
+            token.mnemonic_arg2(),
+            Some(LocatedDataAccess::SpecialRegisterI(_) | LocatedDataAccess::SpecialRegisterR(_))
+        ) {
+            return None;
+        }
+        let written = written_registers(token.mnemonic_arg1()?)?;
+        if live_after.intersects(written) {
+            None
+        }
+        else {
+            Some(Replacement::Remove)
+        }
     }
 }
 
+impl MultipleInstructionsModifier for RedundantLoad {
+    fn name(&self) -> &'static str {
+        "ld x,y : ld y,x"
+    }
 
-/// This trait concerns the modification of several instructions in a row
-trait MultipleInstructionsModifier {
-    /// If there is no rewrite, return None, Otherwise return the modified stream
-    fn rewrite_one(tokens: &[Token]) -> Option<Vec<Token>>;
-
-    /// Rewrite at maximum n times
-    pub fn rewrite_n(tokens: &[Token], n: usize) -> Option<Vec<Token>> {
-        let previous_run = None;
-        for i in 0..n {
-            let to_rewrite = match previous_run {
-                Some(ref tokens) => tokens,
-                None => tokens
-            };
-
-            let result = self.rewrite_one(to_rewrite);
-            if result.is_none() {
-                return previous_run;
+    /// Once `ld x,y` is executed, both hold the same value and `ld y,x` has no effect.
+    /// Memory operands are never considered: on the CPC a read may see the ROM while the
+    /// write goes to the RAM below, and `ld h,(hl)` changes the address of the second load.
+    fn rewrite(&self, window: &Window, idx: usize) -> Option<Edits> {
+        let first = &window.tokens[idx];
+        let next = window.next_instruction(idx)?;
+        let second = &window.tokens[next];
+        if first.mnemonic() != Some(&Mnemonic::Ld) || second.mnemonic() != Some(&Mnemonic::Ld) {
+            return None;
+        }
+
+        let (x, y) = (first.mnemonic_arg1()?, first.mnemonic_arg2()?);
+        let (y2, x2) = (second.mnemonic_arg1()?, second.mnemonic_arg2()?);
+        let register = |access: &LocatedDataAccess| {
+            matches!(
+                access,
+                LocatedDataAccess::Register8(..)
+                    | LocatedDataAccess::Register16(..)
+                    | LocatedDataAccess::IndexRegister8(..)
+                    | LocatedDataAccess::IndexRegister16(..)
+            )
+        };
+
+        if register(x)
+            && register(y)
+            && DataAccessElem::to_data_access(x) == DataAccessElem::to_data_access(x2)
+            && DataAccessElem::to_data_access(y) == DataAccessElem::to_data_access(y2)
+        {
+            Some(vec![(next, Replacement::Remove)])
+        }
+        else {
+            None
+        }
+    }
+}
+
+impl MultipleInstructionsModifier for TailCall {
+    fn name(&self) -> &'static str {
+        "call x : ret -> jp x"
+    }
+
+    fn rewrite(&self, window: &Window, idx: usize) -> Option<Edits> {
+        let call = &window.tokens[idx];
+        if call.mnemonic() != Some(&Mnemonic::Call) || call.mnemonic_arg1().is_some() {
+            return None;
+        }
+        let target = call.mnemonic_arg2()?;
+        if !matches!(target, LocatedDataAccess::Expression(_)) {
+            return None;
+        }
+
+        let next = window.next_instruction(idx)?;
+        let ret = &window.tokens[next];
+        if ret.mnemonic() != Some(&Mnemonic::Ret) || ret.mnemonic_arg1().is_some() {
+            return None;
+        }
+
+        Some(vec![
+            (
+                idx,
+                Replacement::By(Box::new(opcode(
+                    call.span(),
+                    Mnemonic::Jp,
+                    None,
+                    Some(target.clone())
+                )))
+            ),
+            (next, Replacement::Remove),
+        ])
+    }
+}
+
+impl MultipleInstructionsModifier for ShortJump {
+    fn name(&self) -> &'static str {
+        "jp -> jr"
+    }
+
+    fn rewrite(&self, window: &Window, idx: usize) -> Option<Edits> {
+        let jp = &window.tokens[idx];
+        if jp.mnemonic() != Some(&Mnemonic::Jp) {
+            return None;
+        }
+        // jr only handles a subset of the conditions
+        let condition = jp.mnemonic_arg1();
+        if let Some(condition) = condition
+            && !matches!(
+                condition,
+                LocatedDataAccess::FlagTest(
+                    FlagTest::NZ | FlagTest::Z | FlagTest::NC | FlagTest::C,
+                    _
+                )
+            )
+        {
+            return None;
+        }
+        let target = jp.mnemonic_arg2()?;
+        let LocatedDataAccess::Expression(expr) = target
+        else {
+            return None;
+        };
+        if !expr.is_label() {
+            return None;
+        }
+        let label = window.labels.resolve(expr.label(), idx)?;
+
+        // relative offset computed from the address following the 2 bytes of jr
+        let in_range = if label > idx {
+            window.bytes_in(idx + 1..label)? <= 127
+        }
+        else {
+            window.bytes_in(label..idx)? + 2 <= 128
+        };
+        if !in_range {
+            return None;
+        }
+
+        Some(vec![(
+            idx,
+            Replacement::By(Box::new(opcode(
+                jp.span(),
+                Mnemonic::Jr,
+                condition.cloned(),
+                Some(target.clone())
+            )))
+        )])
+    }
+}
+
+/// The tokens of a flat block under analysis
+struct Window<'t> {
+    tokens: &'t [LocatedToken],
+    live_after: &'t [LiveSet],
+    labels: LabelIndex,
+    /// Lazily computed size of each token (None when it cannot be known)
+    sizes: &'t [OnceCell<Option<usize>>]
+}
+
+impl Window<'_> {
+    fn is_instruction(&self, idx: usize) -> bool {
+        let token = &self.tokens[idx];
+        token.mnemonic().is_some() && !token.is_warning()
+    }
+
+    /// Index of the instruction that follows the one at `idx`, only separated by comments
+    fn next_instruction(&self, idx: usize) -> Option<usize> {
+        let next = (idx + 1..self.tokens.len()).find(|&i| !self.tokens[i].is_comment())?;
+        self.is_instruction(next).then_some(next)
+    }
+
+    /// The bytes of the instruction may be patched at runtime
+    fn may_be_patched(&self, idx: usize) -> bool {
+        let previous = (0..idx).rev().find(|&i| !self.tokens[i].is_comment());
+        let next = (idx + 1..self.tokens.len()).find(|&i| !self.tokens[i].is_comment());
+
+        previous.is_some_and(|i| self.tokens[i].is_label())
+            || next.is_some_and(|i| {
+                let token = &self.tokens[i];
+                token.is_equ() || token.is_assign() || token.is_set()
+            })
+    }
+
+    fn size(&self, idx: usize) -> Option<usize> {
+        *self.sizes[idx].get_or_init(|| {
+            let token = &self.tokens[idx];
+            if token.is_label() || token.is_comment() || token.is_equ() || token.is_assign() {
+                Some(0)
+            }
+            else if token.mnemonic().is_some() {
+                bytes(token)
             }
             else {
-                previous_run = result;
+                None
             }
-        }
-        return previous_run;
+        })
     }
 
-    /// Rewrite until it is not possible anymore
-    pub fn rewrite(tokens: &[Token]) -> Option<Vec<Token>> {
-        let previous_run = None;
-        loop {
-            let tokens = match previous_run {
-                Some(ref tokens) => tokens,
-                None => tokens
-            };
+    /// Number of bytes generated by the tokens of the range
+    fn bytes_in(&self, range: Range<usize>) -> Option<usize> {
+        range.map(|idx| self.size(idx)).sum()
+    }
+
+    /// First rewrite that can be applied to the block
+    fn find(&self) -> Option<(&'static str, Edits)> {
+        let allowed = |edits: &Edits| edits.iter().all(|(idx, _)| !self.may_be_patched(*idx));
 
-            let current_run = self.rewrite_one(tokens);
-            if current_run.is_none() {
-                return previous_run;
+        for idx in (0..self.tokens.len()).filter(|&idx| self.is_instruction(idx)) {
+            for rule in SINGLE_INSTRUCTION_RULES {
+                if let Some(replacement) = rule.replace(&self.tokens[idx], self.live_after[idx]) {
+                    let edits = vec![(idx, replacement)];
+                    if allowed(&edits) {
+                        return Some((rule.name(), edits));
+                    }
+                }
             }
-            else {
-                previous_run = current_run;
+            for rule in MULTIPLE_INSTRUCTIONS_RULES {
+                if let Some(edits) = rule.rewrite(self, idx)
+                    && allowed(&edits)
+                {
+                    return Some((rule.name(), edits));
+                }
             }
         }
+
+        None
     }
+}
 
+/// Size of an instruction, whatever the value of the labels it uses
+fn bytes(token: &LocatedToken) -> Option<usize> {
+    token.to_token().fallback_number_of_bytes().ok()
 }
 
-/// Fuse two successive DB in a single DB directive
-#[derive(Default, Debug)]
-struct FuseTwoSuccessiveDefbIntoDefb;
+fn duration(token: &LocatedToken) -> usize {
+    token.to_token().estimated_duration().unwrap_or(0)
+}
 
-impl MultipleInstructionsModifier for FuseTwoSuccessiveDefbIntoDefb {
-    /// If there is no rewrite, return None, Otherwise return the modified stream
-    fn rewrite_one(tokens: &[Token]) -> Option<Vec<Token>> {
-        match tokens {
-            beginning @.., Token::Defb(ref a), Token::Defb(ref b), end @.. 
-            => {
-                let mut bytes = Vec::new();
-                bytes.append(a);
-                bytes.append(b);
+/// Apply the edits of a rule and build its report
+fn apply(
+    tokens: &mut Vec<LocatedToken>,
+    sizes: &mut Vec<OnceCell<Option<usize>>>,
+    rule: &'static str,
+    mut edits: Edits
+) -> Rewrite {
+    edits.sort_by_key(|(idx, _)| *idx);
+    let first = &tokens[edits[0].0];
+    let filename = first.span().filename().to_owned();
+    let line = first.span().relative_line_and_column().0;
 
-                let mut tokens = Vec::new();
-                tokens.append_elements(beginning);
-                tokens.push(builder::defb_elements(bytes);
-                tokens.append_elements(end);
+    let mut before = Vec::new();
+    let mut after = Vec::new();
+    let (mut bytes_before, mut bytes_after) = (0, 0);
+    let (mut nops_before, mut nops_after) = (0, 0);
 
-                Some(tokens)
+    for (idx, replacement) in edits.into_iter().rev() {
+        let token = &tokens[idx];
+        before.insert(0, token.to_token().to_string());
+        bytes_before += bytes(token).unwrap_or(0);
+        nops_before += duration(token);
+
+        match replacement {
+            Replacement::Remove => {
+                tokens.remove(idx);
+                sizes.remove(idx);
             },
+            Replacement::By(token) => {
+                after.insert(0, token.to_token().to_string());
+                bytes_after += bytes(&token).unwrap_or(0);
+                nops_after += duration(&token);
+                tokens[idx] = *token;
+                sizes[idx] = OnceCell::new();
+            }
+        }
+    }
 
-            _ => None
+    Rewrite {
+        rule,
+        filename,
+        line,
+        before,
+        after,
+        saved_bytes: bytes_before.saturating_sub(bytes_after),
+        saved_nops: nops_before.saturating_sub(nops_after)
+    }
+}
+
+/// Listings nested in a token that contain code to optimise
+fn nested_listings(inner: &mut LocatedTokenInner) -> Vec<&mut LocatedListing> {
+    match inner {
+        LocatedTokenInner::Confined(listing)
+        | LocatedTokenInner::CrunchedSection(_, listing)
+        | LocatedTokenInner::Iterate(_, _, listing)
+        | LocatedTokenInner::Module(_, listing)
+        | LocatedTokenInner::Repeat(_, listing, ..)
+        | LocatedTokenInner::RepeatUntil(_, listing)
+        | LocatedTokenInner::Rorg(_, listing)
+        | LocatedTokenInner::Stabilize(listing)
+        | LocatedTokenInner::While(_, listing) => vec![listing],
+        LocatedTokenInner::For { listing, .. } => vec![listing.as_mut()],
+        LocatedTokenInner::If(branches, default) => {
+            branches
+                .iter_mut()
+                .map(|(_, listing)| listing)
+                .chain(default.iter_mut())
+                .collect()
+        },
+        LocatedTokenInner::Switch(_, cases, default) => {
+            cases
+                .iter_mut()
+                .map(|(_, listing, _)| listing)
+                .chain(default.iter_mut())
+                .collect()
+        },
+        _ => Vec::new()
+    }
+}
+
+fn optimize_tokens(
+    tokens: &mut Vec<LocatedToken>,
+    liveness: &dyn Fn(&[LocatedToken]) -> Vec<LiveSet>
+) -> Vec<Rewrite> {
+    let mut rewrites = Vec::new();
+
+    for token in tokens.iter_mut() {
+        for listing in nested_listings(token.inner_mut()) {
+            rewrites.extend(optimize(listing, liveness));
         }
     }
 
+    let mut sizes = (0..tokens.len())
+        .map(|_| OnceCell::new())
+        .collect::<Vec<_>>();
+    loop {
+        // liveness is recomputed after each rewrite as it may depend on it
+        let live_after = liveness(tokens);
+        let window = Window {
+            tokens,
+            live_after: &live_after,
+            labels: LabelIndex::new(tokens),
+            sizes: &sizes
+        };
+        let Some((rule, edits)) = window.find()
+        else {
+            break;
+        };
+        rewrites.push(apply(tokens, &mut sizes, rule, edits));
+    }
+
+    rewrites
+}
 
+/// Apply the peephole optimisations to the listing and return the list of applied rewrites.
+/// `liveness` must return, for each token of a flat sequence, the registers and flags that may be read after it.
+pub fn optimize(
+    listing: &mut LocatedListing,
+    liveness: &dyn Fn(&[LocatedToken]) -> Vec<LiveSet>
+) -> Vec<Rewrite> {
+    let mut rewrites = listing
+        .with_tokens_mut(|tokens| optimize_tokens(tokens, liveness))
+        .unwrap_or_default();
+    rewrites.sort_by_key(|r| r.line);
+    rewrites
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::{assemble, assemble_tokens_with_options, parse_z80_str};
+
+    /// Everything is read after each token
+    fn all_live(tokens: &[LocatedToken]) -> Vec<LiveSet> {
+        vec![LiveSet::all(); tokens.len()]
+    }
+
+    /// Nothing is read after each token
+    fn nothing_live(tokens: &[LocatedToken]) -> Vec<LiveSet> {
+        vec![LiveSet::empty(); tokens.len()]
+    }
+
+    fn optimized(
+        code: &str,
+        liveness: &dyn Fn(&[LocatedToken]) -> Vec<LiveSet>
+    ) -> (Vec<u8>, Vec<Rewrite>) {
+        let mut listing = parse_z80_str(code).unwrap();
+        let rewrites = optimize(&mut listing, liveness);
+        let (bytes, _) = assemble_tokens_with_options(&listing, Default::default()).unwrap();
+        (bytes, rewrites)
+    }
+
+    #[test]
+    fn reset_a_only_when_flags_are_dead() {
+        let (bytes, rewrites) = optimized(" nop\n ld a, 0\n", &|tokens| {
+            vec![LiveSet::from(Resource::A); tokens.len()]
+        });
+        assert_eq!(bytes, assemble(" nop\n xor a").unwrap());
+        assert_eq!(rewrites.len(), 1);
+        assert_eq!(rewrites[0].saved_bytes, 1);
+        assert_eq!(rewrites[0].saved_nops, 1);
+
+        let (bytes, rewrites) = optimized(" nop\n ld a, 0\n", &all_live);
+        assert_eq!(bytes, assemble(" nop\n ld a, 0").unwrap());
+        assert!(rewrites.is_empty());
+    }
 
     #[test]
-    fn optimize_consecutive_db_standard_case() {
-        let from = [builder::nop(), builder::defb(0xa), builder::defb(0xa), builder::nop()];
-        let to = [builder::nop(), builder::defb_elements([0xa, 0xb].into())];
-        let obtained = FuseTwoSuccessiveDefbIntoDefb::default().rewrite(&from);
+    fn compare_a_keeps_parity() {
+        let (bytes, _) = optimized(" nop\n cp 0\n", &|tokens| {
+            vec![Resource::FlagZ | Resource::FlagC; tokens.len()]
+        });
+        assert_eq!(bytes, assemble(" nop\n or a").unwrap());
 
+        let (bytes, _) = optimized(" nop\n cp 0\n", &|tokens| {
+            vec![Resource::FlagPV.into(); tokens.len()]
+        });
+        assert_eq!(bytes, assemble(" nop\n cp 0").unwrap());
+    }
+
+    #[test]
+    fn loads() {
+        let (bytes, _) = optimized(" nop\n ld b, b\n ld a, c\n ld c, a\n", &all_live);
+        assert_eq!(bytes, assemble(" nop\n ld a, c").unwrap());
+
+        for code in [
+            " nop\n ld (0x4000), hl\n ld hl, (0x4000)\n",
+            " nop\n ld a, (hl)\n ld (hl), a\n",
+            " nop\n ld h, (hl)\n ld (hl), h\n"
+        ] {
+            let (bytes, rewrites) = optimized(code, &all_live);
+            assert_eq!(bytes, assemble(code).unwrap());
+            assert!(rewrites.is_empty());
+        }
+
+        let (bytes, rewrites) = optimized(" nop\n ld b, 5\n ld c, (hl)\n", &nothing_live);
+        assert_eq!(bytes, assemble(" nop").unwrap());
+        assert_eq!(rewrites.len(), 2);
+        assert_eq!(rewrites.iter().map(|r| r.saved_bytes).sum::<usize>(), 3);
+    }
+
+    #[test]
+    fn tail_call() {
+        let (bytes, rewrites) = optimized(
+            " org 0x4000\n nop\n call 0x1234 ; comment\n ret\n",
+            &all_live
+        );
+        assert_eq!(bytes, assemble(" org 0x4000\n nop\n jp 0x1234").unwrap());
+        assert_eq!(rewrites[0].saved_bytes, 1);
+        assert_eq!(rewrites[0].saved_nops, 5);
+    }
+
+    #[test]
+    fn short_jumps() {
+        let code = " org 0x4000\nstart\n nop\n jp nz, start\n jp far\n defs 200\nfar\n nop\n jp po, start\n";
+        let (bytes, rewrites) = optimized(code, &all_live);
+        let expected = " org 0x4000\nstart\n nop\n jr nz, start\n jp far\n defs 200\nfar\n nop\n jp po, start\n";
+        assert_eq!(bytes, assemble(expected).unwrap());
+        assert_eq!(rewrites.len(), 1);
+        assert_eq!(rewrites[0].line, 4);
+    }
+
+    #[test]
+    fn jumps_to_labels() {
+        let code = " org 0x4000\nstart\n nop\n call routine\n ret\nroutine\n nop\n jp z, start\n";
+        let (bytes, rewrites) = optimized(code, &all_live);
+        let expected = " org 0x4000\nstart\n nop\n jr routine\nroutine\n nop\n jr z, start\n";
+        assert_eq!(bytes, assemble(expected).unwrap());
         assert_eq!(
-            to,
-            obtained.as_slice()
-        )
+            rewrites.iter().map(|r| r.saved_bytes).collect::<Vec<_>>(),
+            vec![1, 1, 1]
+        );
+    }
+
+    #[test]
+    fn patched_instructions_are_kept() {
+        let (bytes, rewrites) = optimized(" nop\nvalue\n ld a, 0\n", &nothing_live);
+        assert_eq!(bytes, assemble(" nop\nvalue\n ld a, 0").unwrap());
+        assert!(rewrites.is_empty());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn nested_blocks() {
+        let (bytes, _) = optimized(" repeat 2\n nop\n ld a, a\n endr\n", &all_live);
+        assert_eq!(bytes, assemble(" nop\n nop").unwrap());
+    }
+
+    #[test]
+    fn local_labels() {
+        let tokens = parse_z80_str("first\n.loop\nsecond\n.loop\n nop\n").unwrap();
+        let labels = LabelIndex::new(tokens.as_slice());
+        assert_eq!(labels.resolve(".loop", 4), Some(3));
+        assert_eq!(labels.resolve(".loop", 1), Some(1));
+        assert_eq!(labels.resolve("first.loop", 4), Some(1));
+        assert_eq!(labels.resolve("missing", 4), None);
+    }
+}
@@ -0,0 +1,224 @@
+//! Duration of the instructions as used by the assembler itself: the cost source given to
+//! `cost_range` for `basm --timing-report` and to `branch_balance` for the STABILIZE blocks.
+//!
+//! Durations are in nops. The conditional instructions use the durations of the CPC
+//! (taken/not taken): `JR cc` 3/2, `JP cc` 3/3, `RET cc` 4/2, `CALL cc` 5/3 and `DJNZ` 4/3.
+//! Only the tokens known to produce no code cost nothing; a macro call, a nested block or an
+//! instruction that repeats itself (`LDIR`, `HALT`, ...) has an unknown duration.
+
+use cpclib_common::itertools::Itertools;
+use cpclib_tokens::{DataAccessElem, ExprElement, ListingElement, Mnemonic};
+
+use crate::branch_balance::{InstructionCost, balance_branches};
+use crate::cost_range::{CostRange, cost_range};
+use crate::implementation::tokens::TokenExt;
+
+/// The duration of a token in nops
+pub fn instruction_cost<T: ListingElement>(token: &T) -> InstructionCost {
+    let Some(mnemonic) = token.mnemonic()
+    else {
+        return directive_cost(token);
+    };
+
+    let conditional = token.mnemonic_arg1().is_some_and(|arg| arg.is_flag_test());
+    let (taken, not_taken) = match mnemonic {
+        Mnemonic::Jr if conditional => (3, 2),
+        Mnemonic::Jp if conditional => (3, 3),
+        Mnemonic::Ret if conditional => (4, 2),
+        Mnemonic::Call if conditional => (5, 3),
+        Mnemonic::Djnz => (4, 3),
+        Mnemonic::Ldir
+        | Mnemonic::Lddr
+        | Mnemonic::Cpir
+        | Mnemonic::Cpdr
+        | Mnemonic::Inir
+        | Mnemonic::Indr
+        | Mnemonic::Otir
+        | Mnemonic::Otdr
+        | Mnemonic::Halt => return InstructionCost::Unknown,
+        _ => {
+            return match token.to_token().estimated_duration() {
+                Ok(duration) => InstructionCost::Fixed(duration as u32),
+                Err(_) => InstructionCost::Unknown
+            };
+        }
+    };
+
+    InstructionCost::Conditional { taken, not_taken }
+}
+
+fn directive_cost<T: ListingElement>(token: &T) -> InstructionCost {
+    if token.is_waitnops() {
+        let count = token.waitnops_count();
+        if count.is_value() {
+            InstructionCost::Fixed(count.value() as u32)
+        }
+        else {
+            InstructionCost::Unknown
+        }
+    }
+    else if token.is_stabilize() {
+        // once balanced, every path lasts as long as the longest one
+        let tokens = token.stabilize_listing().iter().collect_vec();
+        match (
+            balance_branches(&tokens, instruction_cost),
+            cost_range(&tokens, instruction_cost)
+        ) {
+            (Ok(_), Ok(range)) if !range.unbounded && range.unrecognized_count == 0 => {
+                InstructionCost::Fixed(range.max)
+            },
+            _ => InstructionCost::Unknown
+        }
+    }
+    else if token.is_call_macro_or_build_struct()
+        || token.is_if()
+        || token.is_switch()
+        || token.is_repeat()
+        || token.is_repeat_token()
+        || token.is_repeat_until()
+        || token.is_for()
+        || token.is_while()
+        || token.is_iterate()
+        || token.is_include()
+        || token.is_confined()
+        || token.is_crunched_section()
+        || token.is_rorg()
+        || token.is_module()
+    {
+        InstructionCost::Unknown
+    }
+    else if token.is_comment() || token.is_label() || token.is_directive() {
+        InstructionCost::Fixed(0)
+    }
+    else {
+        InstructionCost::Unknown
+    }
+}
+
+/// The duration of a routine, i.e. the code that follows a global label
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutineTiming {
+    pub name: String,
+    /// `Err` when the control flow of the routine cannot be analysed
+    pub cost: Result<CostRange, String>
+}
+
+impl std::fmt::Display for RoutineTiming {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let range = match &self.cost {
+            Ok(range) => range,
+            Err(msg) => return write!(f, "{}: {msg}", self.name)
+        };
+
+        if range.unbounded {
+            write!(f, "{}: {}..? nops (loop)", self.name, range.min)?;
+        }
+        else if range.min == range.max {
+            write!(f, "{}: {} nops", self.name, range.min)?;
+        }
+        else {
+            write!(f, "{}: {}..{} nops", self.name, range.min, range.max)?;
+        }
+
+        if range.unrecognized_count > 0 {
+            write!(
+                f,
+                " ({} instruction(s) of unknown duration)",
+                range.unrecognized_count
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Split the listing at each global label and compute the min/max duration of each routine.
+/// Labels followed by no instruction (data tables, constants) are not reported
+pub fn routines_timing<T: ListingElement>(listing: &[T]) -> Vec<RoutineTiming> {
+    let starts = listing
+        .iter()
+        .positions(|token| token.is_label() && !token.label_symbol().starts_with('.'))
+        .collect_vec();
+
+    starts
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, &start)| {
+            let end = starts.get(idx + 1).copied().unwrap_or(listing.len());
+            let tokens = listing[start..end].iter().collect_vec();
+            let cost = cost_range(&tokens, instruction_cost);
+            if let Ok(range) = &cost
+                && range.instruction_count == 0
+                && range.unrecognized_count == 0
+            {
+                return None;
+            }
+
+            Some(RoutineTiming {
+                name: listing[start].label_symbol().to_owned(),
+                cost
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse_z80_str;
+
+    fn timings(code: &str) -> Vec<String> {
+        let listing = parse_z80_str(code).unwrap();
+        routines_timing(&listing)
+            .iter()
+            .map(|r| r.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn routines_are_split_at_global_labels() {
+        let report = timings(
+            "
+first:
+    ld a, 1
+    or a
+    jr z, .skip
+    inc a
+    inc a
+.skip
+    ret
+table:
+    db 1, 2, 3
+second:
+    ld b, 10
+.loop
+    djnz .loop
+    ret
+"
+        );
+        assert_eq!(
+            report,
+            vec![
+                "first: 9..10 nops".to_owned(),
+                "second: 8..? nops (loop)".to_owned()
+            ]
+        );
+    }
+
+    #[test]
+    fn stabilized_block_lasts_its_longest_path() {
+        let report = timings(
+            "
+routine:
+    STABILIZE
+        or a
+        jr z, .skip
+        inc a
+        inc a
+.skip
+    ENDSTABILIZE
+    ret
+"
+        );
+        assert_eq!(report, vec!["routine: 8 nops".to_owned()]);
+    }
+}
@@ -0,0 +1,289 @@
+//! Conversion of WinAPE (Maxam) and sjasmplus sources into native basm sources.
+//!
+//! The source is parsed with the requested flavor, then only the constructs that
+//! do not exist in basm are rewritten; everything else (layout, comments, ...) is kept as is.
+
+use std::fmt::Display;
+use std::ops::Range;
+
+use cpclib_common::camino::Utf8Path;
+use cpclib_tokens::AssemblerFlavor;
+use regex::{Captures, Regex};
+
+use crate::assembler::rasm_bank_mapping;
+use crate::implementation::expression::ExprEvaluationExt;
+use crate::{
+    LocatedExpr, LocatedListing, LocatedTokenInner, MayHaveSpan, ParserContextBuilder,
+    ParserOptions, SourceString, Z80Span, parse_z80_with_context_builder
+};
+
+#[derive(Debug)]
+pub struct ToBasmError(String);
+
+impl Display for ToBasmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+impl From<String> for ToBasmError {
+    fn from(val: String) -> Self {
+        ToBasmError(val)
+    }
+}
+
+/// Replacement of a part of the source
+type Edit = (Range<usize>, String);
+
+fn span_range(span: &Z80Span) -> Range<usize> {
+    let start = span.offset_from_start();
+    start..(start + span.len())
+}
+
+fn starts_with_word(span: &Z80Span, word: &str) -> bool {
+    span.as_str()
+        .get(..word.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(word))
+}
+
+/// Listings nested in a token that may contain code to convert
+fn nested_listings(inner: &LocatedTokenInner) -> Vec<&LocatedListing> {
+    match inner {
+        LocatedTokenInner::Confined(listing)
+        | LocatedTokenInner::CrunchedSection(_, listing)
+        | LocatedTokenInner::Iterate(_, _, listing)
+        | LocatedTokenInner::Module(_, listing)
+        | LocatedTokenInner::Repeat(_, listing, ..)
+        | LocatedTokenInner::RepeatUntil(_, listing)
+        | LocatedTokenInner::Rorg(_, listing)
+        | LocatedTokenInner::Stabilize(listing)
+        | LocatedTokenInner::While(_, listing) => vec![listing],
+        LocatedTokenInner::For { listing, .. } => vec![listing.as_ref()],
+        LocatedTokenInner::If(branches, default) => {
+            branches
+                .iter()
+                .map(|(_, listing)| listing)
+                .chain(default.iter())
+                .collect()
+        },
+        LocatedTokenInner::Switch(_, cases, default) => {
+            cases
+                .iter()
+                .map(|(_, listing, _)| listing)
+                .chain(default.iter())
+                .collect()
+        },
+        _ => Vec::new()
+    }
+}
+
+/// Macro arguments are used without braces in WinAPE and sjasmplus
+fn brace_macro_arguments(content: &str, params: &[Z80Span]) -> String {
+    if params.is_empty() {
+        return content.to_owned();
+    }
+
+    let names = params
+        .iter()
+        .map(|p| regex::escape(p.as_str().trim_start_matches("r#")))
+        .collect::<Vec<_>>()
+        .join("|");
+    let re = Regex::new(&format!(r"\b({names})\b")).unwrap();
+    re.replace_all(content, |caps: &Captures| format!("{{{}}}", &caps[1]))
+        .into_owned()
+}
+
+fn page_to_basm(page: &LocatedExpr) -> Result<String, ToBasmError> {
+    let value = page
+        .eval()
+        .and_then(|v| Ok(v.int()?))
+        .map_err(|e| format!("PAGE {page}: a constant page number is expected. {e}"))?;
+    if !(0..8).contains(&value) {
+        return Err(format!("PAGE {value}: only the pages 0 to 7 can be converted").into());
+    }
+    let (mmr, address) = rasm_bank_mapping(value as u8);
+    Ok(format!("BANK 0x{mmr:02X} : ORG 0x{address:04X}"))
+}
+
+fn collect_edits(listing: &LocatedListing, edits: &mut Vec<Edit>) -> Result<(), ToBasmError> {
+    for token in listing.iter() {
+        let span = token.span();
+        let range = span_range(span);
+        let inner: &LocatedTokenInner = token;
+
+        match inner {
+            // the flavor is given to the conversion, there is no need to keep it
+            LocatedTokenInner::Flavor(_) => edits.push((range, String::new())),
+
+            // sjasmplus DEVICE
+            LocatedTokenInner::Comment(_) if starts_with_word(span, "DEVICE") => {
+                edits.push((range, format!("; {}", span.as_str())))
+            },
+
+            LocatedTokenInner::Bank(Some(page)) if starts_with_word(span, "PAGE") => {
+                edits.push((range, page_to_basm(page)?))
+            },
+            LocatedTokenInner::Bank(Some(bank)) if starts_with_word(span, "WRITE") => {
+                edits.push((range, format!("BANK {}", bank.span().as_str())))
+            },
+
+            LocatedTokenInner::Equ { label, expr }
+            | LocatedTokenInner::Assign { label, expr, .. }
+                if starts_with_word(span, "DEFINE") =>
+            {
+                // a define without value is only tested with IFDEF
+                let value = if expr.span().as_str() == label.as_str() {
+                    "1"
+                }
+                else {
+                    expr.span().as_str()
+                };
+                let operator = if matches!(inner, LocatedTokenInner::Equ { .. }) {
+                    "EQU"
+                }
+                else {
+                    "="
+                };
+                edits.push((range, format!("{} {operator} {value}", label.as_str())))
+            },
+
+            LocatedTokenInner::Save { .. } if starts_with_word(span, "SAVEBIN") => {
+                edits.push((
+                    range.start..(range.start + "SAVEBIN".len()),
+                    "SAVE".to_owned()
+                ))
+            },
+
+            LocatedTokenInner::Rorg(..) if starts_with_word(span, "DISP") => {
+                edits.push((range.start..(range.start + "DISP".len()), "RORG".to_owned()));
+                edits.push(((range.end - "ENT".len())..range.end, "REND".to_owned()));
+            },
+
+            LocatedTokenInner::Macro {
+                params,
+                content,
+                flavor: AssemblerFlavor::Winape | AssemblerFlavor::Sjasmplus,
+                ..
+            } => {
+                edits.push((
+                    span_range(content),
+                    brace_macro_arguments(content.as_str(), params)
+                ))
+            },
+
+            _ => {}
+        }
+
+        for listing in nested_listings(inner) {
+            collect_edits(listing, edits)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Convert a WinAPE or sjasmplus source into a basm source
+pub fn convert_source(code: &str, flavor: AssemblerFlavor) -> Result<String, ToBasmError> {
+    let mut options = ParserOptions::default();
+    options.set_flavor(flavor);
+    let builder = ParserContextBuilder::default()
+        .set_context_name("TO_BASM")
+        .set_options(options);
+    let lst = parse_z80_with_context_builder(code, builder)
+        .map_err(|e| ToBasmError(format!("Error while parsing. {e}")))?;
+
+    let mut edits = Vec::new();
+    collect_edits(&lst, &mut edits)?;
+    edits.sort_by_key(|(range, _)| range.start);
+
+    let mut converted = code.to_owned();
+    for (range, replacement) in edits.into_iter().rev() {
+        converted.replace_range(range, &replacement);
+    }
+
+    Ok(converted)
+}
+
+pub fn convert_from<P: AsRef<Utf8Path>>(
+    p: P,
+    flavor: AssemblerFlavor
+) -> Result<String, ToBasmError> {
+    let p = p.as_ref();
+    let code = fs_err::read_to_string(p)
+        .map_err(|e| ToBasmError(format!("Error while reading {p}. {e}")))?;
+    convert_source(&code, flavor).map_err(|e| format!("Error while handling {p}. {e}").into())
+}
+
+/// Convert a WinAPE or sjasmplus text source file as a basm text source file.
+///
+/// Included files are not converted; they have to be converted one by one.
+pub fn convert_from_to<P1: AsRef<Utf8Path>, P2: AsRef<Utf8Path>>(
+    src: P1,
+    tgt: P2,
+    flavor: AssemblerFlavor
+) -> Result<(), ToBasmError> {
+    let src = src.as_ref();
+    let tgt = tgt.as_ref();
+    let basm = convert_from(src, flavor)?;
+    fs_err::write(tgt, basm.as_bytes()).map_err(|e| format!("Error while saving {tgt}. {e}").into())
+}
+
+#[cfg(test)]
+mod test {
+    use cpclib_tokens::AssemblerFlavor;
+
+    use super::convert_source;
+
+    #[test]
+    fn convert_sjasmplus() {
+        let code = "\tDEVICE AMSTRADCPC6128
+\tDEFINE+ COUNT 3
+\tDEFINE DEBUG
+\tPAGE 5
+\tORG 0x4000
+\tMACRO fill value
+\tld a, value
+\tENDM
+\tDISP 0x8000
+start\tfill COUNT
+\tENT
+\tSAVEBIN \"code.bin\", 0x4000, 10
+";
+        let expected = "\t; DEVICE AMSTRADCPC6128
+\tCOUNT = 3
+\tDEBUG EQU 1
+\tBANK 0xC5 : ORG 0x4000
+\tORG 0x4000
+\tMACRO fill value
+\tld a, {value}
+\tENDM
+\tRORG 0x8000
+start\tfill COUNT
+\tREND
+\tSAVE \"code.bin\", 0x4000, 10
+";
+        assert_eq!(
+            convert_source(code, AssemblerFlavor::Sjasmplus).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn convert_winape() {
+        let code = "\twrite direct -1, -1, &c4
+\tmacro double reg
+\tadd reg, reg
+\tmend
+\tdouble hl
+";
+        let expected = "\tBANK &c4
+\tmacro double reg
+\tadd {reg}, {reg}
+\tmend
+\tdouble hl
+";
+        assert_eq!(
+            convert_source(code, AssemblerFlavor::Winape).unwrap(),
+            expected
+        );
+    }
+}
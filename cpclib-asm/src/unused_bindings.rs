@@ -22,11 +22,11 @@ pub enum UnusedBindingKind {
 
 /// Which MACRO/FUNCTION a `MacroParameter`/`FunctionParameter` binding
 /// belongs to, and its 0-based position in that definition's own declared
-/// parameter list. basm's call sites are strictly positional (no named
-/// arguments anywhere), so "definition param index N" maps exactly onto
-/// "call-site argument index N" - a consumer that wants to find/rewrite
-/// every call site needs both, and neither is derivable from
-/// `UnusedBinding`'s other fields.
+/// parameter list. A positional call-site argument N maps exactly onto
+/// "definition param index N"; a MACRO call can also pass it by name
+/// (`name=value`) or omit it when it has a default value - a consumer that
+/// wants to find/rewrite every call site needs both, and neither is
+/// derivable from `UnusedBinding`'s other fields.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct UnusedBindingOwner {
     pub name: String,
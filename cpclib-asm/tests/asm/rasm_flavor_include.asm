@@ -0,0 +1,5 @@
+    ; FLAVOR only applies to this file
+    FLAVOR RASM
+    repeat 2, cnt
+        db cnt
+    rend
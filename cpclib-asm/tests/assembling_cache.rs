@@ -0,0 +1,213 @@
+use cpclib_asm::assembler::cache::AssemblingCache;
+use cpclib_asm::preamble::*;
+
+fn assemble_with_cache(code: &str, cache: &AssemblingCache) -> Vec<u8> {
+    let mut options = EnvOptions::default();
+    options
+        .assemble_options_mut()
+        .set_cache(Some(cache.clone()));
+    cpclib_asm::assemble_with_options(code, options)
+        .expect("assemble failed")
+        .0
+}
+
+#[test]
+fn crunched_data_are_reused_from_the_cache() {
+    let directory = std::env::temp_dir().join(format!("basm_cache_test_{}", std::process::id()));
+    let cache = AssemblingCache::new(directory.to_str().unwrap());
+    cache.clear().unwrap();
+
+    let code = r#"
+    org 0x4000
+    LZ48
+        repeat 100, i
+            db {i}, {i}
+        rend
+    LZCLOSE
+    db BASM_LATEST_CRUNCH_INPUT_DATA_SIZE
+    "#;
+    let without_cache = cpclib_asm::assemble(code).unwrap();
+    let first = assemble_with_cache(code, &cache);
+    assert_eq!(first, without_cache);
+
+    let files = std::fs::read_dir(directory.join("crunch"))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect::<Vec<_>>();
+    assert_eq!(files.len(), 1);
+
+    // the cruncher is not called anymore: the content of the cache is used
+    let mut content = std::fs::read(&files[0]).unwrap();
+    *content.last_mut().unwrap() ^= 0xFF;
+    std::fs::write(&files[0], &content).unwrap();
+    let second = assemble_with_cache(code, &cache);
+    assert_ne!(second, first);
+    assert_eq!(second.len(), first.len());
+    assert_eq!(second.last(), Some(&200));
+
+    // another content is crunched again
+    let third = assemble_with_cache(&code.replace("{i}, {i}", "{i}, 0"), &cache);
+    assert_ne!(third.len(), 0);
+    assert_eq!(
+        std::fs::read_dir(directory.join("crunch")).unwrap().count(),
+        2
+    );
+
+    cache.clear().unwrap();
+}
+
+fn listing_files(directory: &std::path::Path) -> Vec<std::path::PathBuf> {
+    std::fs::read_dir(directory.join("listing"))
+        .map(|files| files.map(|e| e.unwrap().path()).collect())
+        .unwrap_or_default()
+}
+
+#[test]
+fn parsed_listings_are_reused_from_the_cache() {
+    let directory =
+        std::env::temp_dir().join(format!("basm_listing_cache_test_{}", std::process::id()));
+    let mut cache = AssemblingCache::new(directory.to_str().unwrap());
+    cache.clear().unwrap();
+
+    let code = r#"
+    org 0x4000
+label
+    ld hl, de ; fake instruction
+    if 1 == 1
+        repeat 3, i
+            db {i}, "a string"
+        rend
+    else
+        nop
+    endif
+    macro twice, x
+        db {x}, {x}
+    endm
+    twice 5
+    ld a, (ix+2)
+    jr label
+    "#;
+
+    let parsed = cpclib_asm::parse_z80_str(code).unwrap();
+    let first = cache
+        .parse(code.to_owned(), ParserContextBuilder::default())
+        .unwrap();
+    let files = listing_files(&directory);
+    assert_eq!(files.len(), 1);
+
+    // the tokens are rebuilt on top of the source: the file is not written again
+    let old = std::time::SystemTime::UNIX_EPOCH;
+    std::fs::File::options()
+        .write(true)
+        .open(&files[0])
+        .unwrap()
+        .set_modified(old)
+        .unwrap();
+    let second = cache
+        .parse(code.to_owned(), ParserContextBuilder::default())
+        .unwrap();
+    assert_eq!(
+        std::fs::metadata(&files[0]).unwrap().modified().unwrap(),
+        old
+    );
+    assert_eq!(format!("{second:?}"), format!("{parsed:?}"));
+    assert_eq!(format!("{first:?}"), format!("{parsed:?}"));
+    assert_eq!(
+        cpclib_asm::assemble_tokens_with_options(second.as_slice(), EnvOptions::default())
+            .unwrap()
+            .0,
+        cpclib_asm::assemble(code).unwrap()
+    );
+
+    // the options and the symbols are part of the key
+    let mut options = ParserOptions::default();
+    options.set_flavor(AssemblerFlavor::Rasm);
+    cache
+        .parse(code.to_owned(), options.context_builder())
+        .unwrap();
+    assert_eq!(listing_files(&directory).len(), 2);
+    cache.set_defined_symbols(["VALUE=1"]);
+    cache
+        .parse(code.to_owned(), ParserContextBuilder::default())
+        .unwrap();
+    assert_eq!(listing_files(&directory).len(), 3);
+
+    cache.clear().unwrap();
+}
+
+#[test]
+fn included_sources_are_parsed_through_the_cache() {
+    let directory =
+        std::env::temp_dir().join(format!("basm_include_cache_test_{}", std::process::id()));
+    let cache = AssemblingCache::new(directory.join("cache").to_str().unwrap());
+    cache.clear().unwrap();
+    std::fs::create_dir_all(&directory).unwrap();
+
+    let included = directory.join("included.asm");
+    std::fs::write(&included, "\tdb 1, 2, 3\n\trepeat 2\n\t\tnop\n\trend\n").unwrap();
+    let code = format!("\torg 0x4000\n\tinclude \"{}\"\n", included.display());
+
+    let first = assemble_with_cache(&code, &cache);
+    assert_eq!(first, [1, 2, 3, 0, 0]);
+    assert_eq!(listing_files(&directory.join("cache")).len(), 1);
+
+    let second = assemble_with_cache(&code, &cache);
+    assert_eq!(second, first);
+
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn damaged_listings_are_parsed_again() {
+    let directory =
+        std::env::temp_dir().join(format!("basm_damaged_cache_test_{}", std::process::id()));
+    let cache = AssemblingCache::new(directory.to_str().unwrap());
+    cache.clear().unwrap();
+
+    let code = "\torg 0x4000\n\tdb 1, 2, 3\n\tld a, (ix+2)\n";
+    let parsed = cpclib_asm::parse_z80_str(code).unwrap();
+    cache
+        .parse(code.to_owned(), ParserContextBuilder::default())
+        .unwrap();
+    let files = listing_files(&directory);
+    assert_eq!(files.len(), 1);
+    let content = std::fs::read(&files[0]).unwrap();
+
+    // a truncated file or a file with extra bytes is a cache miss and is written again
+    for damaged in [
+        &content[..content.len() / 2],
+        &[&content[..], &[0; 3]].concat()
+    ] {
+        std::fs::write(&files[0], damaged).unwrap();
+        let listing = cache
+            .parse(code.to_owned(), ParserContextBuilder::default())
+            .unwrap();
+        assert_eq!(format!("{listing:?}"), format!("{parsed:?}"));
+        assert_eq!(std::fs::read(&files[0]).unwrap(), content);
+    }
+
+    cache.clear().unwrap();
+}
+
+#[test]
+fn sources_printing_at_parse_time_are_not_cached() {
+    let directory = std::env::temp_dir().join(format!(
+        "basm_print_parse_cache_test_{}",
+        std::process::id()
+    ));
+    let cache = AssemblingCache::new(directory.to_str().unwrap());
+    cache.clear().unwrap();
+
+    // the directive is in an inner block: its context shares the flag of the source
+    let code = "\tif 1\n\t\tASMCONTROL PRINT_PARSE, \"hello\"\n\tendif\n\tnop\n";
+    let builder = ParserContextBuilder::default().set_quiet(true);
+    cache.parse(code.to_owned(), builder.clone()).unwrap();
+    assert!(listing_files(&directory).is_empty());
+
+    // the text of the directive alone does not prevent the caching
+    let code = "\tnop ; ASMCONTROL PRINT_PARSE, \"hello\"\n";
+    cache.parse(code.to_owned(), builder).unwrap();
+    assert_eq!(listing_files(&directory).len(), 1);
+
+    cache.clear().unwrap();
+}
@@ -0,0 +1,16 @@
+//! Helpers shared by the integration tests
+#![allow(dead_code)]
+
+use cpclib_asm::preamble::*;
+
+/// Assemble the code with the default options and return the produced bytes
+pub fn assemble(code: &str) -> Result<Vec<u8>, String> {
+    cpclib_asm::assemble_and_inspect(code, EnvOptions::default(), Env::produced_bytes)
+        .map_err(|e| e.to_string())
+}
+
+/// Assemble the code and give the environment of the last pass to `inspect`
+pub fn inspect<R>(code: &str, options: EnvOptions, inspect: impl FnOnce(&Env) -> R) -> R {
+    cpclib_asm::assemble_and_inspect(code, options, inspect)
+        .unwrap_or_else(|e| panic!("assemble failed: {e}"))
+}
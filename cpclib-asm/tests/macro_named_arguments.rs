@@ -0,0 +1,165 @@
+mod common;
+
+use common::{assemble, inspect};
+use cpclib_asm::preamble::*;
+
+const BLIT: &str = "
+    macro BLIT x, y, width = 2, height=8, r#label=\"none\"
+        db {x}, {y}, {width}, {height}
+    endm
+";
+
+#[test]
+fn defaults_complete_the_positional_arguments() {
+    let bytes = assemble(&format!(
+        "{BLIT}\n BLIT 1, 2\n BLIT 1, 2, 3\n BLIT 1, 2, 3, 4"
+    ))
+    .unwrap();
+    assert_eq!(bytes, vec![1, 2, 2, 8, 1, 2, 3, 8, 1, 2, 3, 4]);
+}
+
+#[test]
+fn named_arguments_are_given_in_any_order() {
+    let bytes = assemble(&format!(
+        "{BLIT}\n BLIT height=3, x=1, y = 2\n BLIT 5, height=(1+2)*2, y=6"
+    ))
+    .unwrap();
+    assert_eq!(bytes, vec![1, 2, 2, 3, 5, 6, 2, 6]);
+}
+
+#[test]
+fn named_arguments_in_parenthesis() {
+    let bytes = assemble(
+        "
+    macro point(x=1, y=(2+3))
+        db {x}, {y}
+    endm
+    point(y=7)
+    point()
+    point(x == 1)
+x equ 1
+"
+    )
+    .unwrap();
+    // `x == 1` is a comparison, not the named argument `x`
+    assert_eq!(bytes, vec![1, 7, 1, 5, 1, 5]);
+}
+
+#[test]
+fn unknown_names_stay_positional() {
+    // `counter` is not a parameter, so `counter = 5` is still given as is to `stmt`
+    let bytes = assemble(
+        "
+    macro ASSIGN stmt
+        {stmt}
+    endm
+counter = 1
+    ASSIGN counter = 5
+    db counter
+"
+    )
+    .unwrap();
+    assert_eq!(bytes, vec![5]);
+}
+
+#[test]
+fn ambiguous_named_arguments_are_reported() {
+    let code = "
+    macro PAIR a, b
+        db {a}, {b}
+    endm
+    PAIR 1, b=2
+";
+    let warnings = inspect(code, EnvOptions::default(), |env| {
+        env.warnings()
+            .iter()
+            .map(|w| w.to_string())
+            .collect::<Vec<_>>()
+    });
+    assert_eq!(assemble(code).unwrap(), vec![1, 2]);
+    assert!(
+        warnings
+            .iter()
+            .any(|w| w.contains("`b=2` is a named argument of macro `PAIR`")),
+        "{warnings:?}"
+    );
+
+    // parenthesis keep the argument positional
+    let bytes = assemble(
+        "
+    macro PAIR a, b
+        db {a}, {b}
+    endm
+b equ 2
+    PAIR 5, (b=2)
+"
+    )
+    .unwrap();
+    assert_eq!(bytes, vec![5, 1]);
+
+    // the call cannot be read with positional arguments only
+    let warnings = inspect(
+        "
+    macro PAIR a, b, c=3
+        db {a}, {b}, {c}
+    endm
+    PAIR 1, b=2
+",
+        EnvOptions::default(),
+        |env| {
+            env.warnings()
+                .iter()
+                .map(|w| w.to_string())
+                .collect::<Vec<_>>()
+        }
+    );
+    assert!(warnings.is_empty(), "{warnings:?}");
+}
+
+#[test]
+fn wrong_named_arguments_are_reported() {
+    let err = assemble(&format!("{BLIT}\n BLIT 1")).unwrap_err();
+    assert!(
+        err.contains("1 arguments provided, but from 2 to 5 expected"),
+        "{err}"
+    );
+
+    let err = assemble(&format!("{BLIT}\n BLIT height=1")).unwrap_err();
+    assert!(err.contains("arguments `x`, `y` missing"), "{err}");
+
+    let err = assemble(&format!("{BLIT}\n BLIT 1, 2, x=3")).unwrap_err();
+    assert!(
+        err.contains("argument `x` is provided several times"),
+        "{err}"
+    );
+
+    let err = assemble(&format!("{BLIT}\n BLIT 1, height=2, 3")).unwrap_err();
+    assert!(
+        err.contains("positional argument `3` provided after named arguments"),
+        "{err}"
+    );
+}
+
+#[test]
+fn defaults_are_displayed() {
+    let builder = EnvOptions::default()
+        .parse_options()
+        .clone()
+        .context_builder();
+    let tokens = parse_z80_with_context_builder(BLIT, builder).unwrap();
+    let token = tokens.iter().find(|t| t.is_macro_definition()).unwrap();
+    assert_eq!(
+        token.macro_definition_arguments().as_slice(),
+        ["x", "y", "width", "height", "r#label"]
+    );
+    assert_eq!(
+        token.macro_definition_defaults().as_slice(),
+        [None, None, Some("2"), Some("8"), Some("\"none\"")]
+    );
+    assert!(
+        token
+            .to_token()
+            .to_string()
+            .starts_with("MACRO BLIT x, y, width=2, height=8, r#label=\"none\"")
+    );
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use cpclib_asm::AssemblingOptionFlags;
+use cpclib_asm::assembler::memory_map::{MemoryMap, MemoryMapFormat, MemoryMapPage, MemoryRange};
+use cpclib_asm::preamble::*;
+
+fn memory_map(code: &str, allow_override: bool) -> MemoryMap {
+    let mut options = EnvOptions::default();
+    options
+        .assemble_options_mut()
+        .set_flag(AssemblingOptionFlags::MemoryMap, true)
+        .set_forbid_memory_override(!allow_override);
+    common::inspect(code, options, Env::memory_map)
+}
+
+#[test]
+fn regions_gaps_and_protected_areas() {
+    let map = memory_map(
+        r#"
+    range 0x8000, 0x8fff, data
+    protect 0x9000, 0x90ff
+
+    org 0x4000
+start:
+    ld a, 1
+    ret
+
+    section data
+table:
+    db 1, 2, 3
+    "#,
+        false
+    );
+
+    assert_eq!(map.pages().len(), 1);
+    let page = map.page(MemoryMapPage::Page(0)).unwrap();
+    assert_eq!(page.used, 6);
+
+    assert_eq!(page.regions.len(), 2);
+    let code = &page.regions[0];
+    assert_eq!((code.start, code.stop), (0x4000, 0x4002));
+    assert_eq!(code.section, None);
+    assert_eq!(code.labels, vec!["start".to_owned()]);
+    assert_eq!((code.first_line, code.last_line), (Some(7), Some(8)));
+
+    let data = &page.regions[1];
+    assert_eq!((data.start, data.stop), (0x8000, 0x8002));
+    assert_eq!(data.section.as_deref(), Some("data"));
+    assert_eq!(data.labels, vec!["table".to_owned()]);
+
+    assert_eq!(page.protected, vec![MemoryRange::new(0x9000, 0x90FF)]);
+    assert_eq!(
+        page.gaps,
+        vec![
+            MemoryRange::new(0x0000, 0x3FFF),
+            MemoryRange::new(0x4003, 0x7FFF),
+            MemoryRange::new(0x8003, 0x8FFF),
+            MemoryRange::new(0x9100, 0xFFFF)
+        ]
+    );
+    assert_eq!(page.free, 0x1_0000 - 6 - 0x100);
+    assert!(page.overlaps.is_empty());
+}
+
+#[test]
+fn banks_and_overlaps() {
+    let map = memory_map(
+        r#"
+    org 0x4000
+    db 1, 2, 3, 4
+    org 0x4002
+    db 5, 6
+
+    bankset 1
+    org 0x0000
+    db 7
+
+    bank
+    org 0x1000
+    db 8
+    "#,
+        true
+    );
+
+    let pages = map.pages().iter().map(|p| p.page).collect::<Vec<_>>();
+    assert_eq!(
+        pages,
+        vec![
+            MemoryMapPage::Page(0),
+            MemoryMapPage::Page(1),
+            MemoryMapPage::Bank(0)
+        ]
+    );
+
+    let main = map.page(MemoryMapPage::Page(0)).unwrap();
+    assert_eq!(main.regions.len(), 2);
+    assert_eq!(main.used, 4);
+    assert_eq!(main.overlaps, vec![MemoryRange::new(0x4002, 0x4003)]);
+
+    let extended = map.page(MemoryMapPage::Page(1)).unwrap();
+    assert_eq!(
+        (extended.regions[0].start, extended.regions[0].stop),
+        (0, 0)
+    );
+
+    let bank = map.page(MemoryMapPage::Bank(0)).unwrap();
+    assert_eq!(
+        (bank.regions[0].start, bank.regions[0].stop),
+        (0x1000, 0x1000)
+    );
+}
+
+#[test]
+fn memory_map_outputs() {
+    let map = memory_map(
+        r#"
+    org 0x4000
+label:
+    db 1, 2, 3
+    "#,
+        false
+    );
+
+    let mut json = Vec::new();
+    map.generate(&mut json, MemoryMapFormat::Json).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    let page = &json["pages"][0];
+    assert_eq!(page["kind"], "page");
+    assert_eq!(page["index"], 0);
+    assert_eq!(page["used"], 3);
+    assert_eq!(page["regions"][0]["start"], 0x4000);
+    assert_eq!(page["regions"][0]["labels"][0], "label");
+    assert_eq!(page["gaps"][1]["start"], 0x4003);
+
+    let mut svg = Vec::new();
+    map.generate(&mut svg, MemoryMapFormat::Svg).unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+    assert!(svg.starts_with("<svg"));
+    assert!(svg.contains("0x4000-0x4002 (3 bytes) written"));
+
+    let mut html = Vec::new();
+    map.generate(&mut html, MemoryMapFormat::Html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.contains("<h2>Page 0</h2>"));
+    assert!(html.contains("<td>0x4000</td><td>0x4002</td><td>3</td><td>written</td>"));
+
+    assert_eq!(
+        MemoryMapFormat::from_filename("out.SVG"),
+        Ok(MemoryMapFormat::Svg)
+    );
+    assert!(MemoryMapFormat::from_filename("out.txt").is_err());
+}
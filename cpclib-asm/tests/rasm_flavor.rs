@@ -0,0 +1,106 @@
+#[test]
+fn rasm_flavor_semantics() {
+    let code = r#"
+    flavor rasm
+    org 0x4000
+    macro nothing
+        nop
+    endm
+
+    struct point
+x       defb 1
+y       defb 2
+    endstruct
+
+    repeat 3, cnt
+        db cnt
+    rend
+
+    iterate value, 5, 6
+        db value
+    enditerate
+
+    nothing
+
+    struct point origin
+    ld a, (origin.y)
+"#;
+    let bytes = cpclib_asm::assemble(code).expect("assemble failed");
+    // origin is at 0x4006, so origin.y is at 0x4007
+    assert_eq!(bytes, vec![1, 2, 3, 5, 6, 0, 1, 2, 0x3A, 0x07, 0x40]);
+}
+
+#[test]
+fn rasm_flavor_modules() {
+    let code = r#"
+    flavor rasm
+    org 0x4000
+    module first
+start   nop
+    module second
+start   nop
+    module off
+    dw first.start, second.start
+"#;
+    let bytes = cpclib_asm::assemble(code).expect("assemble failed");
+    assert_eq!(bytes, vec![0, 0, 0x00, 0x40, 0x01, 0x40]);
+}
+
+#[test]
+fn basm_flavor_is_not_rasm() {
+    // Without the flavor, the repeat counter is only reachable with braces
+    let code = r#"
+    repeat 3, cnt
+        db cnt
+    rend
+"#;
+    assert!(cpclib_asm::assemble(code).is_err());
+}
+
+#[test]
+fn rasm_flavor_stays_in_included_file() {
+    // BANK 0xC4 is only valid with the basm flavor
+    let code = format!(
+        r#"
+    bank 0xC4
+    org 0x4000
+    db 0xAA
+    bank 0xC0
+    org 0x8000
+    include "{0}/tests/asm/rasm_flavor_include.asm"
+    include "{0}/tests/asm/rasm_flavor_include.asm"
+    bank 0xC4
+    db 0xBB
+"#,
+        env!("CARGO_MANIFEST_DIR")
+    );
+    let bytes = cpclib_asm::assemble(&code).expect("assemble failed");
+    // 0xAA is written in the extended memory
+    assert_eq!(bytes, vec![1, 2, 1, 2, 0xBB]);
+}
+
+#[test]
+fn rasm_flavor_directive_is_a_statement() {
+    // Neither a comment nor an inhibited block select the flavor
+    let code = r#"
+    ; flavor rasm
+    if 0
+        ifdef unknown
+        endif
+        flavor rasm
+    endif
+    repeat 3, cnt
+        db cnt
+    rend
+"#;
+    assert!(cpclib_asm::assemble(code).is_err());
+
+    let code = r#"
+    org 0x4000 : flavor rasm ; selected after a colon
+    repeat 3, cnt
+        db cnt
+    rend
+"#;
+    let bytes = cpclib_asm::assemble(code).expect("assemble failed");
+    assert_eq!(bytes, vec![1, 2, 3]);
+}
@@ -0,0 +1,146 @@
+mod common;
+
+use common::assemble;
+use cpclib_asm::preamble::*;
+
+#[test]
+fn cheaper_arm_is_padded() {
+    let bytes = assemble(
+        "
+    org 0x4000
+    STABILIZE
+        jr nz, .b
+        ld a, b
+        jr .over
+.b
+        ld a, b
+        ld c, d
+.over
+    ENDSTABILIZE
+    ret
+"
+    )
+    .unwrap();
+
+    // taken: 3 + 1 + 1 + 1 (padding); not taken: 2 + 1 + 3
+    assert_eq!(
+        bytes,
+        vec![0x20, 0x03, 0x78, 0x18, 0x03, 0x78, 0x4A, 0x00, 0xC9]
+    );
+}
+
+#[test]
+fn balanced_block_is_unchanged() {
+    let code = "
+    org 0x4000
+    STABILIZE
+        jr nz, .b
+        nop
+        nop
+        jr .over
+.b
+        nop
+        nop
+        nop
+        nop
+.over
+    ENDSTABILIZE
+";
+    let stabilized = assemble(code).unwrap();
+    let plain = assemble(&code.replace("ENDSTABILIZE", "").replace("STABILIZE", "")).unwrap();
+    assert_eq!(stabilized, plain);
+}
+
+#[test]
+fn early_return_is_rewritten() {
+    let bytes = assemble(
+        "
+    org 0x4000
+routine:
+    STABILIZE
+        ret nc
+        nop
+        nop
+        nop
+        ret
+    ENDSTABILIZE
+after:
+    db 0xFF
+"
+    )
+    .unwrap();
+
+    // ret nc becomes jr nc to a RET padded with 2 nops: 3 + 2 + 3 = 2 + 3 + 3
+    assert_eq!(
+        bytes,
+        vec![0x30, 0x04, 0x00, 0x00, 0x00, 0xC9, 0x00, 0x00, 0xC9, 0xFF]
+    );
+}
+
+#[test]
+fn unsupported_blocks_are_reported() {
+    let error = assemble(
+        "
+    org 0x4000
+    STABILIZE
+        call 0x1234
+    ENDSTABILIZE
+"
+    )
+    .unwrap_err();
+    assert!(error.contains("STABILIZE error"), "{error}");
+
+    let error = assemble(
+        "
+    org 0x4000
+    STABILIZE
+        ret nc
+        ret
+    ENDSTABILIZE
+"
+    )
+    .unwrap_err();
+    assert!(error.contains("needs at least 2"), "{error}");
+
+    let error = assemble(
+        "
+    org 0x4000
+    STABILIZE
+        ret nc
+        nop
+        nop
+        nop
+        nop
+        nop
+    ENDSTABILIZE
+"
+    )
+    .unwrap_err();
+    assert!(error.contains("ends with RET, JP or JR"), "{error}");
+}
+
+#[test]
+fn blocks_survive_the_conversion_to_tokens() {
+    let code = "
+    org 0x4000
+    CONFINED
+    STABILIZE
+        jr nz, .b
+        ld a, b
+        jr .over
+.b
+        ld a, b
+        ld c, d
+.over
+    ENDSTABILIZE
+    ENDCONFINED
+    ret
+";
+    let listing = parse_z80_str(code).unwrap().as_listing();
+    let Token::Confined(confined) = &listing[1]
+    else {
+        panic!("{listing:?}")
+    };
+    assert!(matches!(confined[0], Token::Stabilize(_)));
+    assert_eq!(listing.to_bytes().unwrap(), assemble(code).unwrap());
+}
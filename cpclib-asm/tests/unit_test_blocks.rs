@@ -0,0 +1,68 @@
+mod common;
+
+use cpclib_asm::preamble::*;
+use cpclib_tokens::{BinaryOperation, UnitTestExpectation, UnitTestStep};
+
+#[test]
+fn test_blocks_are_collected_with_their_values() {
+    let code = r#"
+    org 0x4000
+test  nop
+double
+    add a
+    ret
+    TEST "double"
+        ld a, 3 : ld hl, 0x1234
+        poke buffer, 1, 2 ; comment
+        call double
+        expect a == 6
+        expect (buffer) != 0
+        expect c == 0
+        expect nc
+        expect nops <= 5
+    ENDTEST
+    jp test
+buffer
+"#;
+    let bytes = cpclib_asm::assemble(code).expect("assemble failed");
+    // the TEST block generates nothing and test is still a valid label
+    assert_eq!(bytes, vec![0x00, 0x87, 0xC9, 0xC3, 0x00, 0x40]);
+
+    common::inspect(code, EnvOptions::default(), |env| {
+        let tests = env.unit_tests();
+        assert_eq!(tests.len(), 1);
+        assert_eq!(tests[0].name(), "double");
+
+        let steps = tests[0].steps();
+        assert_eq!(steps.len(), 9);
+        assert_eq!(steps[2].0, UnitTestStep::Poke(0x4006, vec![1, 2]));
+        assert_eq!(steps[3].0, UnitTestStep::Call(0x4001));
+        assert_eq!(
+            steps[5].0,
+            UnitTestStep::Expect(UnitTestExpectation::Memory(
+                0x4006,
+                BinaryOperation::Different,
+                0
+            ))
+        );
+        assert_eq!(steps[6].0.to_string(), "EXPECT C == 0");
+        assert_eq!(steps[7].0.to_string(), "EXPECT NC");
+        assert_eq!(steps[2].1.as_ref().unwrap().as_str(), "poke buffer, 1, 2");
+    });
+}
+
+#[test]
+fn test_block_needs_endtest() {
+    let code = r#"
+    TEST "unfinished"
+        call 0x4000
+"#;
+    assert!(cpclib_asm::assemble(code).is_err());
+
+    let code = r#"
+    TEST "wrong"
+        expect hl
+    ENDTEST
+"#;
+    assert!(cpclib_asm::assemble(code).is_err());
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use cpclib_asm::preamble::*;
+
+#[test]
+fn sjasmplus_flavor_semantics() {
+    let code = r#"
+    flavor sjasmplus
+    device amstradcpc6128
+    define+ VALUE 1
+    define+ VALUE 2
+    define DEBUG
+    org 0x4000
+
+    macro load reg, value
+        ld reg, value
+    endm
+
+    module first
+start   nop
+    endmodule
+
+    disp 0x8000
+here:   load a, VALUE
+    ent
+    dw first.start, here
+    ifdef DEBUG
+        db DEBUG
+    endif
+"#;
+    let bytes = cpclib_asm::assemble(code).expect("assemble failed");
+    assert_eq!(bytes, vec![0, 0x3E, 2, 0x00, 0x40, 0x00, 0x80, 1]);
+}
+
+#[test]
+fn sjasmplus_page_maps_the_slot_3() {
+    let code = r#"
+    flavor sjasmplus
+    org 0xC000
+    page 1
+    db 1
+    dw $
+"#;
+    let memory = common::inspect(code, EnvOptions::default(), |env| env.sna().memory_dump());
+    // the bytes land in the page 1 while $ stays in 0xC000-0xFFFF
+    assert_eq!(&memory[0x4000..0x4003], &[1, 0x01, 0xC0]);
+    assert_eq!(&memory[0xC000..0xC003], &[0, 0, 0]);
+}
+
+#[test]
+fn winape_flavor_semantics() {
+    let code = r#"
+    flavor winape
+    org 0x4000
+    macro double reg
+        add reg, reg
+    mend
+    double hl
+    write direct 0, 7, &c0
+"#;
+    let bytes = cpclib_asm::assemble(code).expect("assemble failed");
+    assert_eq!(bytes, vec![0x29]);
+}
+
+#[test]
+fn sjasmplus_to_basm_assembles_the_same() {
+    let code = r#"
+    define+ VALUE 5
+    org 0x4000
+    macro load reg, value
+        ld reg, value
+    endm
+    disp 0x8000
+here:   load b, VALUE
+    ent
+    dw here
+"#;
+    let converted =
+        cpclib_asm::to_basm::convert_source(code, cpclib_asm::AssemblerFlavor::Sjasmplus)
+            .expect("conversion failed");
+    let bytes = cpclib_asm::assemble(&converted).expect("assemble failed");
+    assert_eq!(bytes, vec![0x06, 5, 0x00, 0x80]);
+}
@@ -79,6 +79,9 @@ impl<'src> Formatter<'src> {
                 &["ENDCONFINED", "CEND", "ENDC"]
             );
         }
+        else if token.is_stabilize() {
+            self.format_block(token.stabilize_listing(), depth, line_0, &["ENDSTABILIZE"]);
+        }
         else if token.is_repeat_until() {
             self.format_repeat_until(token, depth, line_0);
         }
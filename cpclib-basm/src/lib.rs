@@ -6,15 +6,19 @@ use std::str::FromStr;
 use std::sync::{Arc, LazyLock};
 
 use cpclib_asm::AssemblingOptionFlags;
+use cpclib_asm::assembler::cache::AssemblingCache;
+use cpclib_asm::assembler::dependencies::{Dependencies, DependenciesFormat};
 use cpclib_asm::assembler::file::get_filename_to_read;
 use cpclib_asm::assembler::listing_output::{
     DEFAULT_LISTING_LINE_TEMPLATE, ListingAddressRadix, ListingOutputFormat, ListingOutputKind,
     ListingSourceFileOutputMode
 };
+use cpclib_asm::assembler::memory_map::MemoryMapFormat;
 use cpclib_asm::preamble::file::read_source;
 use cpclib_asm::preamble::symbols_output::SymbolOutputFormat;
 use cpclib_asm::preamble::*;
 use cpclib_asm::progress::{Progress, normalize};
+use cpclib_asm::timing::routines_timing;
 use cpclib_common::camino::Utf8Path;
 use cpclib_common::clap;
 use cpclib_common::clap::builder::{PossibleValue, PossibleValuesParser};
@@ -26,6 +30,7 @@ use cpclib_disc::amsdos::{AmsdosError, AmsdosFile, AmsdosFileName, AmsdosHeader}
 use cpclib_disc::disc::Disc;
 use cpclib_disc::edsk::Head;
 use cpclib_disc::open_disc;
+use cpclib_link::Object;
 #[cfg(feature = "xferlib")]
 use cpclib_xfer::CpcXfer;
 use enumflags2::BitFlags;
@@ -58,7 +63,7 @@ pub enum BasmError {
     },
     ErrorWithListing {
         error: Box<BasmError>,
-        listing: LocatedListing
+        listing: Box<LocatedListing>
     },
 
     // #[fail(display = "Invalid Amsdos filename: {}", filename)]
@@ -160,6 +165,17 @@ fn disabled_warning_categories_from_matches(matches: &ArgMatches) -> BitFlags<Wa
     disabled
 }
 
+/// The listings of the cache depend on the symbols defined on the command line
+fn cache_from_matches(matches: &ArgMatches) -> Option<AssemblingCache> {
+    matches.get_one::<String>("CACHE").map(|directory| {
+        let mut cache = AssemblingCache::new(directory.as_str());
+        if let Some(definitions) = matches.get_many::<String>("DEFINE_SYMBOL") {
+            cache.set_defined_symbols(definitions);
+        }
+        cache
+    })
+}
+
 /// Parse the given code.
 /// TODO read options to configure the search path
 pub fn parse(matches: &ArgMatches) -> Result<(LocatedListing, ParserOptions), BasmError> {
@@ -180,6 +196,15 @@ pub fn parse(matches: &ArgMatches) -> Result<(LocatedListing, ParserOptions), Ba
     if matches.get_flag("ORGAMS") {
         options.set_flavor(AssemblerFlavor::Orgams);
     }
+    else if matches.get_flag("RASM") {
+        options.set_flavor(AssemblerFlavor::Rasm);
+    }
+    else if matches.get_flag("WINAPE") {
+        options.set_flavor(AssemblerFlavor::Winape);
+    }
+    else if matches.get_flag("SJASMPLUS") {
+        options.set_flavor(AssemblerFlavor::Sjasmplus);
+    }
 
     match std::env::current_dir() {
         Ok(cwd) => {
@@ -218,6 +243,9 @@ pub fn parse(matches: &ArgMatches) -> Result<(LocatedListing, ParserOptions), Ba
         ));
     };
 
+    // the assembler also needs the flavor selected by the source
+    options.set_flavor_from_directive(&code);
+
     let fname = builder
         .current_filename()
         .map(normalize)
@@ -228,7 +256,12 @@ pub fn parse(matches: &ArgMatches) -> Result<(LocatedListing, ParserOptions), Ba
         Progress::progress().add_parse(&fname);
     };
 
-    let res = crate::parse_z80_with_context_builder(code, builder)
+    // nothing is written on disk in a dry run
+    let cache = cache_from_matches(matches).map(|mut cache| {
+        cache.set_read_only(matches.get_flag("DRY_RUN"));
+        cache
+    });
+    let res = crate::parse_z80_with_cache(code, builder, cache.as_ref())
         .map_err(|e| BasmError::from(e.render()));
 
     if options.show_progress {
@@ -264,6 +297,8 @@ pub fn assemble(
     let dry_run = matches.get_flag("DRY_RUN");
     assemble_options.set_dry_run(dry_run);
 
+    assemble_options.set_cache(cache_from_matches(matches));
+
     if matches.get_flag("OVERRIDE") {
         assemble_options
             .set_save_behavior(cpclib_disc::amsdos::AmsdosAddBehavior::ReplaceAndEraseIfPresent);
@@ -294,6 +329,17 @@ pub fn assemble(
         AssemblingOptionFlags::BreakpointAsOpcode,
         matches.get_flag("BREAKPOINT_AS_OPCODES")
     );
+    assemble_options.set_flag(
+        AssemblingOptionFlags::SourceMap,
+        matches.get_one::<String>("SYMBOLS_OUTPUT").is_some()
+            && matches
+                .get_one::<String>("SYMBOLS_KIND")
+                .is_some_and(|kind| kind == "json")
+    );
+    assemble_options.set_flag(
+        AssemblingOptionFlags::MemoryMap,
+        matches.get_one::<String>("MEMORY_MAP").is_some()
+    );
 
     // TODO add symbols if any
     if let Some(files) = matches.get_many::<String>("LOAD_SYMBOLS") {
@@ -394,13 +440,19 @@ pub fn assemble(
 
     let options = EnvOptions::new(parse_options, assemble_options, o);
 
-    let (_tokens, mut env) =
-        visit_tokens_all_passes_with_options(listing, options).map_err(|(_t_, mut env, e)| {
-            let _ = env.handle_print(); // do the prints even if there is an assembling issue
-            BasmError::AssemblerError {
-                error: Box::new(AssemblerError::AlreadyRenderedError(e.to_string()))
-            }
-        })?;
+    let mut env = if matches.get_flag("OBJECT") {
+        assemble_object(matches, listing, &options)?
+    }
+    else {
+        visit_tokens_all_passes_with_options(listing, options)
+            .map_err(|(_t_, mut env, e)| {
+                let _ = env.handle_print(); // do the prints even if there is an assembling issue
+                BasmError::AssemblerError {
+                    error: Box::new(AssemblerError::AlreadyRenderedError(e.to_string()))
+                }
+            })?
+            .1
+    };
 
     let _ = env
         .handle_post_actions(listing)
@@ -465,6 +517,76 @@ pub fn assemble(
         })?;
     }
 
+    if let Some(dest) = matches.get_one::<String>("MEMORY_MAP")
+        && !dry_run
+    {
+        let format = MemoryMapFormat::from_filename(dest).map_err(BasmError::InvalidArgument)?;
+        let mut f = File::create(dest).map_err(|e| {
+            BasmError::Io {
+                io: e,
+                ctx: format!("creating {dest}")
+            }
+        })?;
+        env.generate_memory_map(&mut f, format).map_err(|e| {
+            BasmError::Io {
+                io: e,
+                ctx: format!("writing {dest}")
+            }
+        })?;
+    }
+
+    if let Some(dest) = matches.get_one::<String>("DEPENDENCIES")
+        && !dry_run
+    {
+        let mut deps = Dependencies::default();
+        if let Some(input) = matches.get_one::<String>("INPUT") {
+            let input = get_filename_to_read(input, env.options().parse_options(), None)?;
+            deps.add_input(input);
+        }
+
+        let from_env = env.dependencies();
+        for input in from_env.inputs() {
+            deps.add_input(input);
+        }
+        for output in from_env.outputs() {
+            deps.add_output(output);
+        }
+        for id in [
+            "OUTPUT",
+            "LISTING_OUTPUT",
+            "SYMBOLS_OUTPUT",
+            "MEMORY_MAP",
+            "REMU_OUTPUT",
+            "WABP_OUTPUT"
+        ] {
+            if let Some(fname) = matches.get_one::<String>(id)
+                && fname != "-"
+            {
+                let fname = AnyFileNameOwned::from(fname.as_str());
+                let fname = fname.as_any_filename();
+                deps.add_output(fname.image_filename().unwrap_or(fname.content_filename()));
+            }
+        }
+        // make needs a target
+        if deps.outputs().next().is_none() {
+            deps.add_output(dest);
+        }
+
+        let mut f = File::create(dest).map_err(|e| {
+            BasmError::Io {
+                io: e,
+                ctx: format!("creating {dest}")
+            }
+        })?;
+        deps.generate(&mut f, DependenciesFormat::from_filename(dest))
+            .map_err(|e| {
+                BasmError::Io {
+                    io: e,
+                    ctx: format!("writing {dest}")
+                }
+            })?;
+    }
+
     Ok(env)
 }
 
@@ -567,6 +689,36 @@ fn save_binary_to_file(
     Ok(())
 }
 
+/// Assemble the listing as a relocatable object and write it to the output file
+fn assemble_object(
+    matches: &ArgMatches,
+    listing: &LocatedListing,
+    options: &EnvOptions
+) -> Result<Env, BasmError> {
+    let section = matches.get_one::<String>("OBJECT_SECTION").unwrap();
+    let name = matches
+        .get_one::<String>("INPUT")
+        .and_then(|fname| Utf8Path::new(fname).file_stem())
+        .unwrap_or("module");
+
+    let (object, env) = Object::assemble(name, listing, options, section).map_err(|e| {
+        BasmError::AssemblerError {
+            error: Box::new(AssemblerError::AlreadyRenderedError(e.to_string()))
+        }
+    })?;
+
+    if !matches.get_flag("DRY_RUN") {
+        let fname = matches.get_one::<String>("OUTPUT").unwrap();
+        object.save(fname).map_err(|e| {
+            BasmError::AssemblerError {
+                error: Box::new(AssemblerError::AlreadyRenderedError(e.to_string()))
+            }
+        })?;
+    }
+
+    Ok(env)
+}
+
 /// Save the provided result
 /// TODO manage the various save options and delegate them with save commands
 pub fn save(matches: &ArgMatches, env: &Env) -> Result<(), BasmError> {
@@ -578,6 +730,11 @@ pub fn save(matches: &ArgMatches, env: &Env) -> Result<(), BasmError> {
     // uploading to a physical M4 over `xferlib`.
     let dry_run = matches.get_flag("DRY_RUN");
 
+    // the object has already been written when assembling
+    if matches.get_flag("OBJECT") {
+        return Ok(());
+    }
+
     if matches.get_flag("SNAPSHOT")
         && !matches.contains_id("TO_M4")
         && !matches.contains_id("OUTPUT")
@@ -695,6 +852,21 @@ pub fn save(matches: &ArgMatches, env: &Env) -> Result<(), BasmError> {
     Ok(())
 }
 
+/// Apply the peephole optimiser on the parsed listing and report each rewrite
+fn peephole(listing: &mut LocatedListing, o: &dyn EnvEventObserver) {
+    let rewrites = cpclib_asm::rewrite::optimize(listing, &cpclib_z80emu::track::liveness);
+    for rewrite in &rewrites {
+        o.emit_stdout(&format!("{rewrite}"));
+    }
+
+    let bytes = rewrites.iter().map(|r| r.saved_bytes).sum::<usize>();
+    let nops = rewrites.iter().map(|r| r.saved_nops).sum::<usize>();
+    o.emit_stdout(&format!(
+        "Peephole optimiser: {} rewrite(s), {bytes} byte(s) and {nops} nop(s) saved",
+        rewrites.len()
+    ));
+}
+
 /// Launch the assembling of everythin
 pub fn process(
     matches: &ArgMatches,
@@ -726,13 +898,32 @@ pub fn process(
     }
 
     // standard assembling
-    let (listing, options) = parse(matches)?;
-    let env = assemble(matches, &listing, options, o.clone()).map_err(move |error| {
-        BasmError::ErrorWithListing {
-            error: Box::new(error),
-            listing
+    let (mut listing, options) = parse(matches)?;
+    if matches.get_flag("PEEPHOLE") {
+        peephole(&mut listing, o.deref());
+    }
+    let env = match assemble(matches, &listing, options, o.clone()) {
+        Ok(env) => env,
+        Err(error) => {
+            return Err(BasmError::ErrorWithListing {
+                error: Box::new(error),
+                listing: Box::new(listing)
+            });
         }
-    })?;
+    };
+
+    if matches.get_flag("TEST")
+        && let Err(error) = run_unit_tests(&env, o.deref())
+    {
+        return Err(BasmError::ErrorWithListing {
+            error: Box::new(error.into()),
+            listing: Box::new(listing)
+        });
+    }
+
+    if matches.get_flag("TIMING_REPORT") {
+        timing_report(&listing, o.deref());
+    }
 
     //  o.emit_stderr(format!("TODO: include parse warnings");
     // warnings.extend_from_slice(env.warnings());
@@ -761,6 +952,43 @@ pub fn process(
     }
 }
 
+/// Execute the TEST blocks of the assembled code and report each of them.
+/// The failures are gathered in a single error
+pub fn run_unit_tests(env: &Env, o: &dyn EnvEventObserver) -> Result<(), Box<AssemblerError>> {
+    let tests = env.unit_tests();
+    let mut errors = Vec::new();
+    for test in tests {
+        match cpclib_z80emu::run_unit_test(env.sna(), test) {
+            Ok(()) => o.emit_stdout(&format!("test {} ... ok", test.name())),
+            Err(e) => {
+                o.emit_stdout(&format!("test {} ... FAILED", test.name()));
+                errors.push(e);
+            }
+        }
+    }
+
+    o.emit_stdout(&format!(
+        "test result: {}. {} passed; {} failed",
+        if errors.is_empty() { "ok" } else { "FAILED" },
+        tests.len() - errors.len(),
+        errors.len()
+    ));
+
+    if errors.is_empty() {
+        Ok(())
+    }
+    else {
+        Err(Box::new(AssemblerError::MultipleErrors { errors }))
+    }
+}
+
+/// Print the duration of the routines of the listing
+pub fn timing_report(listing: &LocatedListing, o: &dyn EnvEventObserver) {
+    for routine in routines_timing(listing) {
+        o.emit_stdout(&routine.to_string());
+    }
+}
+
 static EMBEDDED_FILES_NAME: LazyLock<Vec<String>> =
     LazyLock::new(|| EmbeddedFiles::iter().map(|s| s.into_owned()).collect_vec());
 static EMBEDDED_FILES: LazyLock<Vec<PossibleValue>> = LazyLock::new(|| {
@@ -932,8 +1160,20 @@ If a placeholder appears multiple times in the template, only the first occurren
                     .arg(Arg::new("SYMBOLS_KIND")                        
                         .help("Format of the output symbols file")
                         .long("sym_kind")
-                        .value_parser(["winape", "basm"])
+                        .value_parser(["winape", "basm", "rasm", "sjasmplus", "ace", "nocash", "json"])
                         .default_value("basm")
+                    )
+                    .arg(Arg::new("MEMORY_MAP")
+                        .help("Filename of the memory map of the written pages. Its extension selects the format: json, html or svg")
+                        .long("memory-map")
+                        .value_hint(ValueHint::FilePath)
+                        .value_parser(|fname: &str| MemoryMapFormat::from_filename(fname).map(|_| fname.to_owned()))
+                    )
+                    .arg(Arg::new("DEPENDENCIES")
+                        .help("Filename of the list of the files read and written, for the build tools. It is a json document for a .json file and a make rule otherwise")
+                        .short('M')
+                        .long("dependencies")
+                        .value_hint(ValueHint::FilePath)
                     )
 					.arg(
 						Arg::new("OUTPUT")
@@ -980,6 +1220,21 @@ If a placeholder appears multiple times in the template, only the first occurren
                             .action(ArgAction::SetTrue)
                             .conflicts_with("CPR")
                     )
+                    .arg(
+                        Arg::new("OBJECT")
+                            .help("Generate a relocatable object for the bdlink linker instead of a binary. The code must not use ORG")
+                            .long("object")
+                            .action(ArgAction::SetTrue)
+                            .requires("OUTPUT")
+                            .conflicts_with_all(["CPR", "SNAPSHOT", "LISTING_OUTPUT"])
+                    )
+                    .arg(
+                        Arg::new("OBJECT_SECTION")
+                            .help("Name of the section that receives the bytes of the object")
+                            .long("object-section")
+                            .default_value("code")
+                            .requires("OBJECT")
+                    )
                     .arg(
                         Arg::new("NO_SNA_CHUNK")
                             .help("Deactivate some snapshot chunks (comma separated)")
@@ -1057,6 +1312,31 @@ If a placeholder appears multiple times in the template, only the first occurren
                         .long("dry-run")
                         .action(ArgAction::SetTrue)
                     )
+                    .arg(
+                        Arg::new("CACHE")
+                        .help("Directory of the on-disk cache shared by successive assemblings. The source files are only parsed again, and the crunched sections and crunched INCBIN only crunched again, when their content changes.")
+                        .long("cache")
+                        .value_hint(ValueHint::DirPath)
+                        .num_args(1)
+                    )
+                    .arg(
+                        Arg::new("PEEPHOLE")
+                        .help("Optimise the main source file before assembling it (ld a,0 -> xor a, jp -> jr, call x : ret -> jp x, redundant ld removal) and report each rewrite with its gain. Macros and included files are left untouched.")
+                        .long("peephole")
+                        .action(ArgAction::SetTrue)
+                    )
+                    .arg(
+                        Arg::new("TEST")
+                        .help("Run the TEST blocks on an emulated Z80 once the code is assembled and report their result. Nothing is saved when a test fails.")
+                        .long("test")
+                        .action(ArgAction::SetTrue)
+                    )
+                    .arg(
+                        Arg::new("TIMING_REPORT")
+                        .help("Print the minimum and maximum duration in nops of each routine (the code that follows a global label) of the main source file.")
+                        .long("timing-report")
+                        .action(ArgAction::SetTrue)
+                    )
                     .arg(
                         Arg::new("FORBID_MEMORY_OVERRIDE")
                         .help("Forbid memory override (convert warnings to errors)")
@@ -1083,6 +1363,27 @@ If a placeholder appears multiple times in the template, only the first occurren
                         .long("orgams")
                         .action(ArgAction::SetTrue)
                     )
+                    .arg(
+                        Arg::new("RASM")
+                        .help("Main source is at RASM format (rasm syntax and semantics)")
+                        .long("rasm")
+                        .conflicts_with("ORGAMS")
+                        .action(ArgAction::SetTrue)
+                    )
+                    .arg(
+                        Arg::new("WINAPE")
+                        .help("Main source is at WINAPE format (Maxam syntax, macro arguments without braces)")
+                        .long("winape")
+                        .conflicts_with_all(["ORGAMS", "RASM"])
+                        .action(ArgAction::SetTrue)
+                    )
+                    .arg(
+                        Arg::new("SJASMPLUS")
+                        .help("Main source is at SJASMPLUS format (DEVICE, PAGE, DISP/ENT, DEFINE)")
+                        .long("sjasmplus")
+                        .conflicts_with_all(["ORGAMS", "RASM", "WINAPE"])
+                        .action(ArgAction::SetTrue)
+                    )
                     ;
 
     let cmd = if cfg!(feature = "xferlib") {
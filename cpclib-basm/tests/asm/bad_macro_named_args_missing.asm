@@ -0,0 +1,9 @@
+
+	org 0x4000
+
+	macro sprite_blit(x, y, width=2)
+		db {x}, {y}, {width}
+	endm
+
+; y has no default value, so it must be provided
+	sprite_blit x=1, width=3
@@ -0,0 +1,9 @@
+
+	org 0x4000
+
+	macro sprite_blit(x, y, width=2)
+		db {x}, {y}, {width}
+	endm
+
+; the positional arguments must come before the named ones
+	sprite_blit x=1, 2
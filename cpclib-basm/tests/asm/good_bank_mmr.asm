@@ -0,0 +1,57 @@
+    ; Each MMR value only maps the extended memory where the gate array does
+
+    ; 0xC1 maps the page 1 in 0xC000-0xFFFF only
+    BANK 0xc1
+    org 0x0000 + 0
+    db 0x10
+    org 0x4000 + 0
+    db 0x11
+    org 0x8000 + 0
+    db 0x12
+    org 0xC000 + 0
+    db 0x13
+
+    ; 0xC4-0xC7 map one bank of the page 1 in 0x4000-0x7FFF only
+    BANK 0xc4
+    org 0x0000 + 1
+    db 0x40
+    org 0x4000 + 1
+    db 0x41
+    org 0x8000 + 1
+    db 0x42
+    org 0xC000 + 1
+    db 0x43
+
+    BANK 0xc5
+    org 0x4000 + 2
+    db 0x51
+    org 0xC000 + 2
+    db 0x53
+
+    BANK 0xc6
+    org 0x4000 + 3
+    db 0x61
+    org 0xC000 + 3
+    db 0x63
+
+    BANK 0xc7
+    org 0x4000 + 4
+    db 0x71
+    org 0xC000 + 4
+    db 0x73
+
+    BANKSET 0
+    assert memory(0x0000 + 0) == 0x10
+    assert memory(0x4000 + 0) == 0x11
+    assert memory(0x8000 + 0) == 0x12
+    assert memory(0x0000 + 1) == 0x40
+    assert memory(0x8000 + 1) == 0x42
+    assert memory(0xC000 + 1) == 0x43
+    assert memory(0xC000 + 2) == 0x53
+
+    BANKSET 1
+    assert memory(0xC000 + 0) == 0x13
+    assert memory(0x0000 + 1) == 0x41
+    assert memory(0x4000 + 2) == 0x51
+    assert memory(0x8000 + 3) == 0x61
+    assert memory(0xC000 + 4) == 0x71
@@ -0,0 +1,33 @@
+    ; FLAVOR directive
+    ; The whole file is read with rasm syntax and semantics
+    FLAVOR RASM
+
+    org 0x4000
+
+    macro wait_vbl
+        ld b, 0xf5
+.loop   in a, (c)
+        rra
+        jr nc, .loop
+    mend
+
+    struct point
+x       defb 0
+y       defb 0
+    endstruct
+
+    module screen
+init
+        wait_vbl                ; no (void) needed
+        ret
+
+    module data
+    repeat 4, idx
+        db idx                  ; counter used without braces
+    rend
+
+    struct point origin         ; fields are origin.x and origin.y
+
+    module off
+    ld a, (data.origin.y)
+    call screen.init
@@ -0,0 +1,21 @@
+	org 0x4000
+
+; 8x4 pixels in mode 1: 2 bytes per line
+hero
+	INCIMG "incimg_sprite.png", 1, SPRITE
+	assert hero.width == 2 && hero.height == 4
+	assert $ == hero + hero.width * hero.height
+
+; the inks (firmware numbers) of the pens used by the image
+palette
+	db hero.ink0, hero.ink1, hero.ink2, hero.ink3
+
+; black is transparent and drawn in white
+masked_hero
+	INCIMG "incimg_sprite.png", 1, MASKED_SPRITE, 0, 26
+	assert masked_hero.mask == masked_hero + masked_hero.width * masked_hero.height
+
+; tiles of 1 byte and 2 lines
+tiles
+	INCIMG "incimg_sprite.png", 1, TILES, 1, 2
+	assert tiles.count == 4 && tiles.width == 1 && tiles.height == 2
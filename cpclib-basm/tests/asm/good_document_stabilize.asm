@@ -0,0 +1,47 @@
+	org 0x4000
+
+; Set A to 0 when it is negative. Both cases last the same duration
+clamp
+	STABILIZE
+		or a
+		jp m, .negative
+		ld b, a
+		jr .done
+.negative
+		xor a
+.done
+	ENDSTABILIZE
+	ret
+
+; Leave early when A is 0. Both cases last the same duration
+add_three
+	STABILIZE
+		or a
+		ret z
+		inc a
+		inc a
+		inc a
+		ret
+	ENDSTABILIZE
+
+	TEST "clamp"
+		ld a, 5
+		call clamp
+		expect a == 5
+		expect nops == 11
+		ld a, -5
+		call clamp
+		expect a == 0
+		expect nops == 11
+	ENDTEST
+
+	TEST "add_three"
+		ld a, 1
+		call add_three
+		expect a == 4
+		expect nops == 9
+		ld a, 0
+		call add_three
+		expect a == 0
+		expect nops == 9
+	ENDTEST
@@ -0,0 +1,38 @@
+	org 0x4000
+
+; Multiply A by 10 without modifying the other registers
+times_ten
+	push bc
+	add a		; x2
+	ld b, a
+	add a		; x4
+	add a		; x8
+	add b		; x10
+	pop bc
+	ret
+
+; Fill B bytes from HL with A
+fill
+	ld (hl), a
+	inc hl
+	djnz fill
+	ret
+
+	TEST "times_ten"
+		ld a, 12 : ld bc, 0x1234
+		call times_ten
+		expect a == 120
+		expect bc == 0x1234
+		expect nops <= 20
+	ENDTEST
+
+	TEST "fill"
+		poke 0xC000, 0, 0, 0, 0
+		ld hl, 0xC000 : ld b, 3 : ld a, 0xAA
+		call fill
+		expect (0xC000) == 0xAA
+		expect (0xC002) == 0xAA
+		expect (0xC003) == 0
+		expect hl == 0xC003
+		expect b == 0
+	ENDTEST
@@ -0,0 +1,31 @@
+	org 0x4000
+
+	macro sprite_blit(x, y, width=2, height=8, mask=0)
+		db {x}, {y}, {width}, {height}, {mask}
+	endm
+
+call1:
+	sprite_blit 1, 2
+call2:
+	sprite_blit 1, 2, height=16
+call3:
+	sprite_blit mask=1, y=4, x=3
+call4:
+	sprite_blit 5, 6, 7, mask = {eval}10*2
+call4_end:
+
+	assert peek(call1) == 1
+	assert peek(call1+2) == 2
+	assert peek(call1+3) == 8
+
+	assert peek(call2+2) == 2
+	assert peek(call2+3) == 16
+
+	assert peek(call3) == 3
+	assert peek(call3+1) == 4
+	assert peek(call3+4) == 1
+
+	assert peek(call4+2) == 7
+	assert peek(call4+3) == 8
+	assert peek(call4+4) == 20
+	assert call4_end - call4 == 5
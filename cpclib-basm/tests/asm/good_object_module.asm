@@ -0,0 +1,4 @@
+; module assembled with --object: no ORG, external is provided by another module
+entry
+	call external
+	jp entry
@@ -0,0 +1,9 @@
+	org 0x4000
+start
+	defs 3000, 0xAA
+	db "END"
+stop
+
+	run start
+	save "CODE.BIN", start, stop-start, TAPE, "TESTTAPE.CDT"
+	save "TESTTAPE.CDT#HELLO.TXT", start+3000, 3, ASCII
@@ -0,0 +1,6 @@
+	org 0x4000
+start
+	db "TURBO"
+stop
+
+	save "FAST.BIN", start, stop-start, TAPE, "TESTTURBO.CDT", 3000
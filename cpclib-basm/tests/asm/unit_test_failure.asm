@@ -0,0 +1,12 @@
+	org 0x4000
+
+; Wrong on purpose: A should be doubled
+double
+	add 1
+	ret
+
+	TEST "double"
+		ld a, 5
+		call double
+		expect a == 10
+	ENDTEST
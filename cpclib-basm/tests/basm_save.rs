@@ -1,8 +1,11 @@
 use std::sync::Arc;
 
 use cpclib_asm::ParserOptions;
+use cpclib_asm::assembler::dependencies::Dependencies;
+use cpclib_asm::assembler::memory_map::{MemoryMapFormat, MemoryMapPage};
 use cpclib_asm::file::load_file;
 use cpclib_basm::*;
+use cpclib_disc::cdt::{Cdt, CdtWriteOptions, TzxBlock};
 
 /// ! This test file has been created to track some wrong memory handling when saving data with banksets
 
@@ -91,3 +94,321 @@ fn bankset_check_save() {
         fs_err::remove_file(fname.unwrap()).unwrap();
     }
 }
+
+#[test]
+fn bank_mmr_check_memory() {
+    let args_parser = build_args_parser();
+    let args = args_parser.get_matches_from(["basm", "-I", "tests/asm/", "good_bank_mmr.asm"]);
+    let (env, _) = process(&args, Arc::new(())).expect("Unable to assemble the file");
+
+    let mem = env.sna().memory_dump();
+
+    // 0xC1 only moves 0xC000-0xFFFF to the last bank of the page 1
+    assert_eq!(mem[0x0000], 0x10);
+    assert_eq!(mem[0x4000], 0x11);
+    assert_eq!(mem[0x8000], 0x12);
+    assert_eq!(mem[0xC000], 0x00);
+    assert_eq!(mem[0x1C000], 0x13);
+    assert_eq!(mem[0x10000], 0x00);
+
+    // 0xC4-0xC7 only move 0x4000-0x7FFF to a bank of the page 1
+    assert_eq!(mem[0x0001], 0x40);
+    assert_eq!(mem[0x10001], 0x41);
+    assert_eq!(mem[0x8001], 0x42);
+    assert_eq!(mem[0xC001], 0x43);
+    assert_eq!(mem[0x14002], 0x51);
+    assert_eq!(mem[0xC002], 0x53);
+    assert_eq!(mem[0x18003], 0x61);
+    assert_eq!(mem[0xC003], 0x63);
+    assert_eq!(mem[0x1C004], 0x71);
+    assert_eq!(mem[0xC004], 0x73);
+}
+
+#[test]
+fn test_save_tape() {
+    let _ = fs_err::remove_file("TESTTAPE.CDT");
+
+    let args_parser = build_args_parser();
+    let args = args_parser.get_matches_from(["basm", "-I", "tests/asm/", "good_save_tape.asm"]);
+    let (_env, _) = process(&args, Arc::new(())).expect("Unable to assemble the file");
+
+    let (content, header) = load_file("TESTTAPE.CDT#CODE.BIN", &ParserOptions::default()).unwrap();
+    let header = header.expect("Binary files have a header");
+    assert_eq!(header.loading_address(), 0x4000);
+    assert_eq!(header.execution_address(), 0x4000);
+    let content: Vec<u8> = content.into();
+    assert_eq!(content.len(), 3003);
+    assert_eq!(&content[3000..], b"END");
+
+    let (content, header) = load_file("TESTTAPE.CDT#HELLO.TXT", &ParserOptions::default()).unwrap();
+    assert!(header.is_none(), "ASCII files have no header");
+    let content: Vec<u8> = content.into();
+    assert_eq!(content, b"END");
+
+    fs_err::remove_file("TESTTAPE.CDT").unwrap();
+}
+
+#[test]
+fn test_save_tape_at_another_speed() {
+    let _ = fs_err::remove_file("TESTTURBO.CDT");
+
+    let args_parser = build_args_parser();
+    let args =
+        args_parser.get_matches_from(["basm", "-I", "tests/asm/", "good_save_tape_turbo.asm"]);
+    let (_env, _) = process(&args, Arc::new(())).expect("Unable to assemble the file");
+
+    let (content, _) = load_file("TESTTURBO.CDT#FAST.BIN", &ParserOptions::default()).unwrap();
+    let content: Vec<u8> = content.into();
+    assert_eq!(content, b"TURBO");
+
+    let expected = CdtWriteOptions {
+        baud: 3000,
+        ..Default::default()
+    }
+    .zero_pulse();
+    assert_ne!(expected, CdtWriteOptions::default().zero_pulse());
+    let tape = Cdt::open("TESTTURBO.CDT").unwrap();
+    let pulses = tape
+        .blocks()
+        .iter()
+        .filter_map(TzxBlock::zero_pulse)
+        .collect::<Vec<_>>();
+    assert_eq!(pulses, vec![expected; 2]);
+
+    fs_err::remove_file("TESTTURBO.CDT").unwrap();
+}
+
+#[test]
+fn test_save_object() {
+    let output = std::env::temp_dir().join("basm_good_object_module.o");
+    let output = output.to_str().unwrap();
+
+    let args_parser = build_args_parser();
+    let args = args_parser.get_matches_from([
+        "basm",
+        "tests/asm/good_object_module.asm",
+        "--object",
+        "-o",
+        output
+    ]);
+    process(&args, Arc::new(())).expect("Unable to assemble the file");
+
+    let object = cpclib_link::Object::load(output).unwrap();
+    assert_eq!(object.name, "good_object_module");
+    assert_eq!(object.imports, vec!["external".to_owned()]);
+    assert_eq!(object.sections[0].name, "code");
+    assert_eq!(object.sections[0].relocations.len(), 2);
+    assert!(object.exports.iter().any(|s| s.name == "entry"));
+}
+
+#[test]
+fn unit_tests_are_run() {
+    let args = build_args_parser().get_matches_from([
+        "basm",
+        "-I",
+        "tests/asm/",
+        "good_document_test.asm",
+        "--test",
+        "--dry-run"
+    ]);
+    let (env, _) = process(&args, Arc::new(())).expect("The tests must pass");
+    assert_eq!(env.unit_tests().len(), 2);
+
+    let args = build_args_parser().get_matches_from([
+        "basm",
+        "-I",
+        "tests/asm/",
+        "unit_test_failure.asm",
+        "--test",
+        "--dry-run"
+    ]);
+    let error = process(&args, Arc::new(())).unwrap_err().to_string();
+    assert!(error.contains("A = 6"), "{error}");
+
+    // TEST blocks are ignored without --test
+    let args = build_args_parser().get_matches_from([
+        "basm",
+        "-I",
+        "tests/asm/",
+        "unit_test_failure.asm",
+        "--dry-run"
+    ]);
+    assert!(process(&args, Arc::new(())).is_ok());
+}
+
+#[test]
+fn memory_map_is_saved() {
+    let dest = std::env::temp_dir().join(format!("basm_memory_map_{}.json", std::process::id()));
+    let args = build_args_parser().get_matches_from([
+        "basm",
+        "-I",
+        "tests/asm/",
+        "good_bankset.asm",
+        "--memory-map",
+        dest.to_str().unwrap()
+    ]);
+    let (env, _) = process(&args, Arc::new(())).expect("Unable to assemble the file");
+
+    let saved = std::fs::read_to_string(&dest).unwrap();
+    std::fs::remove_file(&dest).unwrap();
+    let mut expected = Vec::new();
+    env.generate_memory_map(&mut expected, MemoryMapFormat::Json)
+        .unwrap();
+    assert_eq!(saved.as_bytes(), expected);
+    let pages = env.memory_map();
+    assert_eq!(pages.pages().len(), 2);
+    assert_eq!(pages.pages()[1].page, MemoryMapPage::Page(1));
+
+    // the format comes from the extension
+    assert!(
+        build_args_parser()
+            .try_get_matches_from(["basm", "good_bankset.asm", "--memory-map", "map.txt"])
+            .is_err()
+    );
+}
+
+#[test]
+fn dependencies_are_saved() {
+    let dir = std::env::temp_dir();
+    let output = dir.join(format!("basm_dependencies_{}.bin", std::process::id()));
+    let output = output.to_str().unwrap();
+
+    for ext in ["d", "json"] {
+        let dest = dir.join(format!("basm_dependencies_{}.{ext}", std::process::id()));
+        let args = build_args_parser().get_matches_from([
+            "basm",
+            "-I",
+            "tests/asm/",
+            "good_include.asm",
+            "-o",
+            output,
+            "-M",
+            dest.to_str().unwrap()
+        ]);
+        process(&args, Arc::new(())).expect("Unable to assemble the file");
+
+        let saved = std::fs::read_to_string(&dest).unwrap();
+        let deps = Dependencies::load(dest.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&dest).unwrap();
+        if ext == "json" {
+            assert!(saved.contains("\"inputs\""), "{saved}");
+        }
+        else {
+            assert!(saved.starts_with(&format!("{output}:")), "{saved}");
+        }
+
+        let inputs = deps.inputs().map(|p| p.as_str()).collect::<Vec<_>>();
+        assert!(
+            inputs[0].ends_with("tests/asm/good_include.asm"),
+            "{inputs:?}"
+        );
+        assert!(
+            inputs.iter().any(|p| p.ends_with("good_db.asm")),
+            "{inputs:?}"
+        );
+        assert_eq!(deps.outputs().collect::<Vec<_>>(), [output]);
+    }
+    std::fs::remove_file(output).unwrap();
+}
+
+#[derive(Debug, Default)]
+struct CapturedOutput(std::sync::Mutex<Vec<String>>);
+
+impl cpclib_common::event::EventObserver for CapturedOutput {
+    fn emit_stdout(&self, s: &str) {
+        self.0.lock().unwrap().push(s.to_owned());
+    }
+
+    fn emit_stderr(&self, s: &str) {
+        self.0.lock().unwrap().push(s.to_owned());
+    }
+}
+
+#[test]
+fn stabilized_routines_are_reported() {
+    let args = build_args_parser().get_matches_from([
+        "basm",
+        "-I",
+        "tests/asm/",
+        "good_document_stabilize.asm",
+        "--test",
+        "--timing-report",
+        "--dry-run"
+    ]);
+    let output = Arc::new(CapturedOutput::default());
+    // the TEST blocks check the duration of both paths on the emulator
+    process(&args, output.clone()).expect("The tests must pass");
+
+    let output = output.0.lock().unwrap();
+    assert!(output.contains(&"clamp: 11 nops".to_owned()), "{output:?}");
+    assert!(
+        output.contains(&"add_three: 9 nops".to_owned()),
+        "{output:?}"
+    );
+}
+
+#[test]
+fn images_are_converted_in_place() {
+    let args = build_args_parser().get_matches_from([
+        "basm",
+        "-I",
+        "tests/asm/",
+        "good_document_incimg.asm"
+    ]);
+    let (env, _) = process(&args, Arc::new(())).expect("Unable to assemble the file");
+    let mem = env.sna().memory_dump();
+
+    // black, red, yellow and white get the pens in their order of appearance
+    assert_eq!(
+        &mem[0x4000..0x4008],
+        &[0x00, 0xFF, 0xF0, 0x0F, 0x65, 0x65, 0xFF, 0xFF]
+    );
+    assert_eq!(&mem[0x4008..0x400C], &[0, 6, 24, 26]);
+
+    // the masked sprite has no black anymore and its mask sets the black pixels
+    assert_eq!(
+        &mem[0x400C..0x401C],
+        &[
+            0x0F, 0x0F, 0x00, 0xF0, 0x1C, 0x1C, 0x0F, 0x0F, 0xFF, 0x00, 0x00, 0x00, 0x88, 0x88,
+            0x00, 0x00
+        ]
+    );
+
+    // the tiles are read column after column in each row of tiles
+    assert_eq!(
+        &mem[0x401C..0x4024],
+        &[0x00, 0xF0, 0xFF, 0x0F, 0x65, 0xFF, 0x65, 0xFF]
+    );
+}
+
+#[test]
+fn images_errors_are_reported() {
+    for (code, expected) in [
+        (
+            "INCIMG \"incimg_sprite.png\", 4, SPRITE",
+            "4 is not a valid mode"
+        ),
+        (
+            "INCIMG \"incimg_sprite.png\", 1, MASKED_SPRITE, 30, 0",
+            "30 is not a valid ink"
+        ),
+        (
+            "INCIMG \"incimg_sprite.png\", 1, TILES, 0, 2",
+            "0x2 is not a valid tile size"
+        ),
+        ("INCIMG \"good_all.bin\", 1, SPRITE", "unable to convert")
+    ] {
+        let input = camino_tempfile::NamedUtf8TempFile::new().unwrap();
+        fs_err::write(input.path(), format!(" org 0x4000\n {code}\n")).unwrap();
+        let args = build_args_parser().get_matches_from([
+            "basm",
+            "-I",
+            "tests/asm/",
+            input.path().as_str()
+        ]);
+        let error = process(&args, Arc::new(()))
+            .err()
+            .expect("The image must be refused");
+        assert!(error.to_string().contains(expected), "{error}");
+    }
+}
@@ -51,14 +51,28 @@ macro_rules! import_rasm_success {
 		;
 	)  => {$(
                 #[cfg(feature = "rasm-autotests")]
-                #[test]
-				fn $name() {
-                    let mut verif = VerifyOutput::default();
-					$(
-						//verif.chk = Some($chk);
-						import_rasm_check!(verif, $test($value));
-					)?
-					assemble_success(concat!($($code),+), verif)
+				#[allow(non_snake_case)]
+				mod $name {
+					use super::*;
+
+					fn verif() -> VerifyOutput {
+						let mut verif = VerifyOutput::default();
+						$(
+							//verif.chk = Some($chk);
+							import_rasm_check!(verif, $test($value));
+						)?
+						verif
+					}
+
+					#[test]
+					fn basm() {
+						assemble_success(concat!($($code),+), verif(), &[])
+					}
+
+					#[test]
+					fn rasm() {
+						assemble_success(concat!($($code),+), verif(), &["--rasm"])
+					}
 				}
 		)+
 	};
@@ -71,9 +85,55 @@ macro_rules! import_rasm_success {
 macro_rules! import_rasm_failure {
     ($ (#define $name:ident $($code:expr)+);+ ;)  => {$(
                 #[cfg(feature = "rasm-autotests")]
-                #[test]
-				fn $name() {
-					assemble_failure(concat!($($code),+), VerifyOutput::default())
+				#[allow(non_snake_case)]
+				mod $name {
+					use super::*;
+
+					#[test]
+					fn basm() {
+						assemble_failure(concat!($($code),+), VerifyOutput::default(), &[])
+					}
+
+					#[test]
+					fn rasm() {
+						assemble_failure(concat!($($code),+), VerifyOutput::default(), &["--rasm"])
+					}
+				}
+		)+
+	}
+
+	}
+
+/// Same as `import_rasm_success` for the tests that rely on the rasm semantics (open MODULE, BANK numbers)
+macro_rules! import_rasm_flavor_success {
+    ($ (#define $name:ident $($code:expr)+);+ ;)  => {$(
+                #[cfg(feature = "rasm-autotests")]
+				#[allow(non_snake_case)]
+				mod $name {
+					use super::*;
+
+					#[test]
+					fn rasm() {
+						assemble_success(concat!($($code),+), VerifyOutput::default(), &["--rasm"])
+					}
+				}
+		)+
+	}
+
+	}
+
+/// Same as `import_rasm_failure` for the code that basm accepts but not rasm
+macro_rules! import_rasm_flavor_failure {
+    ($ (#define $name:ident $($code:expr)+);+ ;)  => {$(
+                #[cfg(feature = "rasm-autotests")]
+				#[allow(non_snake_case)]
+				mod $name {
+					use super::*;
+
+					#[test]
+					fn rasm() {
+						assemble_failure(concat!($($code),+), VerifyOutput::default(), &["--rasm"])
+					}
 				}
 		)+
 	}
@@ -278,17 +338,6 @@ import_rasm_success! {
     "y+=5: rend: startingindex: y=1: repeat 2,x: assert {x}==y: y+=1: rend: nop ";
 
 
-    // XXX a decision has to be taken. BANK semantic seems to be different in both assemblers
-    #define AUTOTEST_SNASET "buildsna:bank 0:nop:"
-    ":snaset Z80_AF,0x1234 :snaset Z80_A,0x11 :snaset Z80_F,0x11 :snaset Z80_BC,0x11 :snaset Z80_B,0x11 :snaset Z80_C,0x11 :snaset Z80_DE,0x11 :snaset Z80_D,0x11"
-    ":snaset Z80_E,0x11 :snaset Z80_HL,0x11 :snaset Z80_H,0x11 :snaset Z80_L,0x11 :snaset Z80_R,0x11 :snaset Z80_I,0x11 :snaset Z80_IFF0,0x11 :snaset Z80_IFF1,0x11"
-    ":snaset Z80_IX,0x11 :snaset Z80_IY,0x11 :snaset Z80_IXL,0x11 :snaset Z80_IXH,0x11 :snaset Z80_IYL,0x11 :snaset Z80_IYH,0x11 :snaset Z80_SP,0x11 :snaset Z80_PC,0x11"
-    ":snaset Z80_IM,0x11 :snaset Z80_AFX,0x11 :snaset Z80_AX,0x11 :snaset Z80_FX,0x11 :snaset Z80_BCX,0x11 :snaset Z80_BX,0x11 :snaset Z80_CX,0x11 :snaset Z80_DEX,0x11"
-    ":snaset Z80_DX,0x11 :snaset Z80_EX,0x11 :snaset Z80_HLX,0x11 :snaset Z80_HX,0x11 :snaset Z80_LX,0x11 :snaset FDD_MOTOR,0x11 :snaset FDD_TRACK,0x11 :snaset PRNT_DATA,0x11"
-    ":snaset PPI_A,0x1 :snaset PPI_B,0x1 :snaset PPI_C,0x1 :snaset PPI_CTL,0x1 :snaset INT_NUM,0x1 :snaset INT_REQ,0x11 :snaset PSG_SEL,0x1 :snaset CRTC_SEL,0x1"
-    ":snaset CRTC_TYPE,0x1 :snaset CRTC_HCC,0x11 :snaset CRTC_CLC,0x11 :snaset CRTC_RLC,0x11 :snaset CRTC_VAC,0x11 :snaset CRTC_VSWC,0x11 :snaset CRTC_HSWC,0x11"
-    ":snaset CRTC_STATE,0x11 :snaset GA_VSC,0x11 :snaset GA_ISC,0x11 :snaset GA_PEN,0x11 :snaset GA_ROMCFG,0x11 :snaset GA_RAMCFG,0x11 :snaset ROM_UP,0x11"
-    ":snaset CRTC_REG,0,0x11 :snaset CRTC_REG,1,0x11 :snaset CRTC_REG,16,0x11 :snaset PSG_REG,0,0x11 :snaset PSG_REG,5,0x11 :snaset PSG_REG,15,0x11: bank : nop";
     #define AUTOTEST_VIRGULE2 "print '5,,5':nop";
     // (void) added
     #define AUTOTEST_IFDEFMACRO	"macro test:nop:endm:ifndef test:error:else:test (void):endif:ifdef test:test (void):else:error:endif:nop";
@@ -307,6 +356,10 @@ import_rasm_success! {
     :chk(0x312):
     :len(16):
     "charset 97,97+26,0:defb 'roua':charset:charset 97,10:defb 'roua':charset 'o',5:defb 'roua':charset 'ou',6:defb 'roua'";
+    // nothing is assembled in the inhibited blocks
+    #define AUTOTEST_INHIBITION
+    :len(0):
+    "if 0:ifused truc:ifnused glop:ifdef bidule:ifndef machin:ifnot 1:nop:endif:nop:else:nop:endif:endif:endif:endif:endif";
 
     #define AUTOTEST_LZSEGMENT	"org #100:debut:jr nz,zend:lz48:repeat 128:nop:rend:lzclose:jp zend:lz48:repeat 2:dec a:jr nz,@next:ld a,5:@next:jp debut:rend:"
                             "lzclose:zend";
@@ -331,10 +384,6 @@ import_rasm_success! {
 
 #define AUTOTEST_INTORAM1 " buildsna : bankset 0 :  org #3FF8 : defb 'roudoudou in da house' ";
 
-#define AUTOTEST_DEFMOD "nbt=0: module preums: label1 nop: label3: label4: ifdef label1:nbt+=1:endif: ifdef label3:nbt+=1:endif:"
-"ifdef label4:nbt+=1:endif: ifndef label5:nbt+=1:endif: module deuze: label1 nop: label3: label5: ifdef label1:nbt+=1:endif:"
-"ifdef label3:nbt+=1:endif: ifndef label4:nbt+=1:endif: ifdef label5:nbt+=1:endif: assert nbt==8:"
-"module grouik: plop: ifused plop : glop=1 : endif:assert glop==1";
 
 #define AUTOTEST_LZ4	"lz4:repeat 10:nop:rend:defb 'roudoudoudouoneatxkjhgfdskljhsdfglkhnopnopnopnop':lzclose";
 
@@ -346,6 +395,25 @@ import_rasm_success! {
 
 }
 
+import_rasm_flavor_success! {
+    // MODULE is closed by the next one
+    #define AUTOTEST_DEFMOD "nbt=0: module preums: label1 nop: label3: label4: ifdef label1:nbt+=1:endif: ifdef label3:nbt+=1:endif:"
+    "ifdef label4:nbt+=1:endif: ifndef label5:nbt+=1:endif: module deuze: label1 nop: label3: label5: ifdef label1:nbt+=1:endif:"
+    "ifdef label3:nbt+=1:endif: ifndef label4:nbt+=1:endif: ifdef label5:nbt+=1:endif: assert nbt==8:"
+    "module grouik: plop: ifused plop : glop=1 : endif:assert glop==1";
+    // BANK selects a 16kb bank instead of a gate array configuration
+    #define AUTOTEST_SNASET "buildsna:bank 0:nop:"
+    ":snaset Z80_AF,0x1234 :snaset Z80_A,0x11 :snaset Z80_F,0x11 :snaset Z80_BC,0x11 :snaset Z80_B,0x11 :snaset Z80_C,0x11 :snaset Z80_DE,0x11 :snaset Z80_D,0x11"
+    ":snaset Z80_E,0x11 :snaset Z80_HL,0x11 :snaset Z80_H,0x11 :snaset Z80_L,0x11 :snaset Z80_R,0x11 :snaset Z80_I,0x11 :snaset Z80_IFF0,0x11 :snaset Z80_IFF1,0x11"
+    ":snaset Z80_IX,0x11 :snaset Z80_IY,0x11 :snaset Z80_IXL,0x11 :snaset Z80_IXH,0x11 :snaset Z80_IYL,0x11 :snaset Z80_IYH,0x11 :snaset Z80_SP,0x11 :snaset Z80_PC,0x11"
+    ":snaset Z80_IM,0x11 :snaset Z80_AFX,0x11 :snaset Z80_AX,0x11 :snaset Z80_FX,0x11 :snaset Z80_BCX,0x11 :snaset Z80_BX,0x11 :snaset Z80_CX,0x11 :snaset Z80_DEX,0x11"
+    ":snaset Z80_DX,0x11 :snaset Z80_EX,0x11 :snaset Z80_HLX,0x11 :snaset Z80_HX,0x11 :snaset Z80_LX,0x11 :snaset FDD_MOTOR,0x11 :snaset FDD_TRACK,0x11 :snaset PRNT_DATA,0x11"
+    ":snaset PPI_A,0x1 :snaset PPI_B,0x1 :snaset PPI_C,0x1 :snaset PPI_CTL,0x1 :snaset INT_NUM,0x1 :snaset INT_REQ,0x11 :snaset PSG_SEL,0x1 :snaset CRTC_SEL,0x1"
+    ":snaset CRTC_TYPE,0x1 :snaset CRTC_HCC,0x11 :snaset CRTC_CLC,0x11 :snaset CRTC_RLC,0x11 :snaset CRTC_VAC,0x11 :snaset CRTC_VSWC,0x11 :snaset CRTC_HSWC,0x11"
+    ":snaset CRTC_STATE,0x11 :snaset GA_VSC,0x11 :snaset GA_ISC,0x11 :snaset GA_PEN,0x11 :snaset GA_ROMCFG,0x11 :snaset GA_RAMCFG,0x11 :snaset ROM_UP,0x11"
+    ":snaset CRTC_REG,0,0x11 :snaset CRTC_REG,1,0x11 :snaset CRTC_REG,16,0x11 :snaset PSG_REG,0,0x11 :snaset PSG_REG,5,0x11 :snaset PSG_REG,15,0x11: bank : nop";
+}
+
 // AUTOTEST_INSTRMUSTFAILED is not tested as expected by asm as we stop right after the first failure
 import_rasm_failure! {
     #define AUTOTEST_BADINCLUDE "include 'truc\n .bin' \n nop nop";
@@ -363,7 +431,6 @@ import_rasm_failure! {
 
 #define AUTOTEST_SAVEINVALID3	"nop : save'gruik',40000,30000";
 
-#define AUTOTEST_MACRO_CONF01   " nop: macro label1: nop: mend: macro label1: nop: mend:";
 #define AUTOTEST_MACRO_CONF02   " label1=0: macro label1: nop: mend: nop:";
 #define AUTOTEST_MACRO_CONF03   " label1 equ #100: macro label1: nop: mend: nop:";
 #define AUTOTEST_MACRO_CONF04   " label1 nop: macro label1: nop: mend: nop";
@@ -372,20 +439,25 @@ import_rasm_failure! {
 #define AUTOTEST_LIMIT04	"limit #10001 : nop";
 #define AUTOTEST_LIMIT05	"org #FFFF : ldir";
 #define AUTOTEST_LIMIT07	"org #ffff : Start:  equ $ :    di :     ld hl,#c9fb :  ld (#38),hl";
-#define AUTOTEST_INHIBITION	"if 0:ifused truc:ifnused glop:ifdef bidule:ifndef machin:ifnot 1:nop:endif:nop:else:nop:endif:endif:endif:endif:endif";
 #define AUTOTEST_LIMITOK "org #100:limit #102:nop:limit #103:ld a,0:protect #105,#107:limit #108:xor a:org $+3:inc a" ;
 
 }
 
-fn assemble_success(code: &str, verify: VerifyOutput) {
-    test_assemble(code, true, verify)
+// basm silently ignores the redefinition of the very same macro
+import_rasm_flavor_failure! {
+    #define AUTOTEST_MACRO_CONF01   " nop: macro label1: nop: mend: macro label1: nop: mend:";
+}
+
+fn assemble_success(code: &str, verify: VerifyOutput, flavor: &[&str]) {
+    test_assemble(code, true, verify, flavor)
 }
 
-fn assemble_failure(code: &str, verify: VerifyOutput) {
-    test_assemble(code, false, verify)
+fn assemble_failure(code: &str, verify: VerifyOutput, flavor: &[&str]) {
+    test_assemble(code, false, verify, flavor)
 }
 
-fn test_assemble(code: &str, success: bool, verify: VerifyOutput) {
+/// Assemble the code with basm. `flavor` contains the arguments that select the flavor (none for the default one)
+fn test_assemble(code: &str, success: bool, verify: VerifyOutput, flavor: &[&str]) {
     let input_file =
         camino_tempfile::NamedUtf8TempFile::new().expect("Unable to build temporary file");
     let input_fname = input_file.path().as_os_str().to_str().unwrap();
@@ -396,6 +468,7 @@ fn test_assemble(code: &str, success: bool, verify: VerifyOutput) {
     let output_fname = output_file.path().as_os_str().to_str().unwrap();
 
     let res = Command::new("../target/debug/basm")
+        .args(flavor)
         .args(["-I", "tests/asm/", "-i", input_fname, "-o", output_fname])
         .output()
         .expect("Unable to launch basm");
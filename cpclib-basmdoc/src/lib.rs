@@ -1713,6 +1713,7 @@ mod test {
             Token::Macro {
                 name: "macro_name".into(),
                 params: Vec::new(),
+                defaults: Vec::new(),
                 content: "".into(),
                 tokenized_content: Default::default(),
                 flavor: cpclib_asm::AssemblerFlavor::Basm,
@@ -22,7 +22,7 @@ pub struct DisassemblyStats {
 /// Resolves the absolute target address of a JR or DJNZ instruction.
 ///
 /// The disassembler writes the offset already adjusted (+2 bias removed), so target = PC + offset.
-fn resolve_jr_djnz_target(
+pub(crate) fn resolve_jr_djnz_target(
     offset_expr: &Expr,
     current_address: Option<u16>,
     instruction: &Token
@@ -32,7 +32,7 @@ fn resolve_jr_djnz_target(
     {
         current_address.ok_or_else(|| {
             BdAsmError::UnknownAssemblerAddress {
-                instruction: instruction.clone(),
+                instruction: Box::new(instruction.clone()),
                 bytes: 0
             }
         })
@@ -45,7 +45,7 @@ fn resolve_jr_djnz_target(
             .map_err(|e| BdAsmError::ExprEvaluation(e.to_string()))?;
         let base_addr = current_address.ok_or_else(|| {
             BdAsmError::UnknownAssemblerAddress {
-                instruction: instruction.clone(),
+                instruction: Box::new(instruction.clone()),
                 bytes: 0
             }
         })? as i32;
@@ -138,7 +138,7 @@ pub fn collect_addresses_from_expressions(
                 None => {
                     if nb_bytes != 0 {
                         return Err(BdAsmError::UnknownAssemblerAddress {
-                            instruction: current_instruction.clone(),
+                            instruction: Box::new(current_instruction.clone()),
                             bytes: nb_bytes
                         });
                     }
@@ -224,7 +224,7 @@ pub fn inject_labels_into_expressions<O: EventObserver>(
                 None => {
                     if nb_bytes != 0 {
                         return Err(BdAsmError::UnknownAssemblerAddress {
-                            instruction: current_instruction.clone(),
+                            instruction: Box::new(current_instruction.clone()),
                             bytes: nb_bytes
                         });
                     }
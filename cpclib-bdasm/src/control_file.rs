@@ -13,8 +13,13 @@ pub enum ControlDirective {
     Skip(usize),
     Length(u16),
     DataBloc(DataBlocString),
-    Label { name: String, address: u16 },
-    CpcString(DataBlocString)
+    Label {
+        name: String,
+        address: u16
+    },
+    CpcString(DataBlocString),
+    /// Entry point used to trace the code (address or label)
+    Entry(String)
 }
 
 impl fmt::Display for ControlDirective {
@@ -37,6 +42,9 @@ impl fmt::Display for ControlDirective {
             },
             ControlDirective::CpcString(spec) => {
                 write!(f, "cpcstring {}", spec)
+            },
+            ControlDirective::Entry(entry) => {
+                write!(f, "entry {}", entry)
             }
         }
     }
@@ -54,7 +62,7 @@ impl fmt::Display for ControlFile {
         writeln!(f, "; Format: <directive> <parameters>")?;
         writeln!(
             f,
-            "; Directives: origin, skip, length, data, label, cpcstring"
+            "; Directives: origin, skip, length, data, label, cpcstring, entry"
         )?;
         writeln!(f)?;
 
@@ -114,6 +122,11 @@ impl ControlFile {
             self.directives
                 .push(ControlDirective::DataBloc(spec.clone()));
         }
+
+        // Add entry points from CLI
+        for entry in &cli.entry {
+            self.directives.push(ControlDirective::Entry(entry.clone()));
+        }
     }
 
     /// Get the skip value from the control file
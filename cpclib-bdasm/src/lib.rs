@@ -15,11 +15,13 @@ mod cpc_strings;
 mod error;
 mod formatting;
 mod parser;
+mod trace;
 
 use analysis::{collect_addresses_from_expressions, inject_labels_into_expressions};
 use control_file::{ControlFile, save_control_file};
 use error::{BdAsmError, Result};
 use parser::{load_control_file, parse_data_bloc_string, parse_u16_value, parse_value_or_label};
+use trace::{CodeMap, VECTORS};
 
 pub mod built_info {
     include!(concat!(env!("OUT_DIR"), "/built.rs"));
@@ -156,7 +158,8 @@ struct BdAsmEnv {
     origin: Option<u16>,
     length: Option<u16>,
     address2label: HashMap<u16, String>,
-    blocs: Vec<DataBloc>
+    blocs: Vec<DataBloc>,
+    entries: Vec<u16>
 }
 
 impl BdAsmEnv {
@@ -166,6 +169,7 @@ impl BdAsmEnv {
         let mut origin = None;
         let mut address2label = HashMap::new();
         let mut data_bloc_specs = Vec::new();
+        let mut entry_specs = Vec::new();
 
         // First pass: collect origin and all labels
         for directive in &control.directives {
@@ -180,6 +184,10 @@ impl BdAsmEnv {
                     // Store for later resolution
                     data_bloc_specs.push(spec);
                 },
+                control_file::ControlDirective::Entry(spec) => {
+                    // Store for later resolution
+                    entry_specs.push(spec);
+                },
                 _ => {} // Skip other directives (e.g., Skip is handled separately)
             }
         }
@@ -196,11 +204,19 @@ impl BdAsmEnv {
             blocs.push(bloc);
         }
 
+        let mut entries = Vec::new();
+        for spec in entry_specs {
+            let entry = parse_value_or_label(spec, &label_map_cow)
+                .map_err(|e| BdAsmError::ControlFile(format!("Invalid entry: {}", e)))?;
+            entries.push(entry);
+        }
+
         Ok(BdAsmEnv {
             origin,
             length: None,
             address2label,
-            blocs
+            blocs,
+            entries
         })
     }
 
@@ -300,6 +316,42 @@ impl BdAsmEnv {
         Ok(listing)
     }
 
+    /// Separate code and data by following the execution flow from the origin, the entry points
+    /// and the restart vectors. The data blocs are replaced by all the bytes that are never executed
+    /// and a label is set on each branch target
+    fn trace<O: EventObserver>(&mut self, input_bytes: &[u8], o: &O) -> Result<()> {
+        let origin = self.origin.unwrap_or(0);
+        let data = self
+            .blocs
+            .iter()
+            .map(DataBloc::to_range_inclusive)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let entries = std::iter::once(origin)
+            .chain(self.entries.iter().copied())
+            .chain(VECTORS)
+            .collect::<Vec<_>>();
+
+        let map = CodeMap::trace(input_bytes, origin, &entries, &data);
+        for target in map.targets() {
+            self.address2label
+                .entry(*target)
+                .or_insert_with(|| format!("label_{target:04x}"));
+        }
+        self.blocs = map
+            .data_blocs()
+            .into_iter()
+            .map(|r| DataBloc::InclusiveRange(*r.start(), *r.end()))
+            .collect();
+
+        o.emit_stderr(&format!(
+            " Traced 0x{:x} bytes of code and 0x{:x} bytes of data",
+            map.code_bytes(),
+            input_bytes.len() - map.code_bytes()
+        ));
+
+        Ok(())
+    }
+
     /// Inject labels into the listing
     /// Collects addresses from expressions and injects all labels
     fn inject_labels<O: EventObserver>(
@@ -374,6 +426,15 @@ pub struct BdAsmCli {
     #[arg(short = 'l', long = "label")]
     pub label: Vec<String>,
 
+    /// Follow jumps and calls from the entry points to separate code from data automatically.
+    /// Entry points are the origin, the --entry addresses, the restart vectors and the AMSDOS execution address
+    #[arg(short = 't', long = "trace")]
+    pub trace: bool,
+
+    /// Additional entry point for --trace (address or label)
+    #[arg(short = 'e', long = "entry")]
+    pub entry: Vec<String>,
+
     /// Skip the first <SKIP> bytes (supports hex, decimal, binary, octal)
     #[arg(short = 's', long = "skip", value_parser = parse_u16_value)]
     pub skip: Option<u16>,
@@ -412,12 +473,12 @@ pub struct BdAsmCli {
 /// For SNA files: extracts memory starting at the specified origin address
 /// For raw binary: reads entire file and optionally strips AMSDOS header
 ///
-/// Returns: (bytes to disassemble, optional AMSDOS loading address)
+/// Returns: (bytes to disassemble, optional AMSDOS loading address, optional AMSDOS execution address)
 fn load_input_bytes<O: EventObserver>(
     filename: &Utf8PathBuf,
     origin: Option<u16>,
     o: &O
-) -> Result<(Vec<u8>, Option<u16>)> {
+) -> Result<(Vec<u8>, Option<u16>, Option<u16>)> {
     // Check if this is a SNA file by extension
     let is_sna = filename
         .extension()
@@ -456,28 +517,32 @@ fn load_input_bytes<O: EventObserver>(
         }
 
         // For SNA files, return the origin as the load address (no separate AMSDOS header)
-        Ok((bytes, origin))
+        Ok((bytes, origin, None))
     }
     else {
         // Load raw binary file
         let input_bytes = fs_err::read(filename)?;
 
         // Check if there is an amsdos header and remove it if any
-        let (bytes, amsdos_load) = if input_bytes.len() > 128 {
+        let (bytes, amsdos_load, amsdos_exec) = if input_bytes.len() > 128 {
             let header = AmsdosHeader::from_buffer(&input_bytes);
             if header.is_checksum_valid() {
                 o.emit_stdout("Amsdos header detected and removed");
-                (input_bytes[128..].to_vec(), Some(header.loading_address()))
+                (
+                    input_bytes[128..].to_vec(),
+                    Some(header.loading_address()),
+                    Some(header.execution_address())
+                )
             }
             else {
-                (input_bytes, None)
+                (input_bytes, None, None)
             }
         }
         else {
-            (input_bytes, None)
+            (input_bytes, None, None)
         };
 
-        Ok((bytes, amsdos_load))
+        Ok((bytes, amsdos_load, amsdos_exec))
     }
 }
 
@@ -510,7 +575,7 @@ pub fn process<O: EventObserver>(cli: &BdAsmCli, o: &O) -> Result<()> {
 
     // Load the input bytes from either SNA or raw binary file
     // Pass the origin from control_file to help with SNA extraction
-    let (input_bytes, amsdos_load) =
+    let (input_bytes, amsdos_load, amsdos_exec) =
         load_input_bytes(&input_filename, control_file.get_origin(), o)?;
 
     // Check if first bytes need to be removed (skip directive)
@@ -553,6 +618,12 @@ pub fn process<O: EventObserver>(cli: &BdAsmCli, o: &O) -> Result<()> {
         env.origin = Some(0);
     }
 
+    // Separate code and data if requested
+    if cli.trace {
+        env.entries.extend(amsdos_exec);
+        env.trace(input_bytes, o)?;
+    }
+
     // Create the listing and inject labels
     let mut listing = env.create_listing(input_bytes)?;
     env.inject_labels(&mut listing, input_bytes.len(), o)?;
@@ -588,6 +659,28 @@ pub fn process<O: EventObserver>(cli: &BdAsmCli, o: &O) -> Result<()> {
                 .push(control_file::ControlDirective::Skip(skip_bytes));
         }
 
+        // Add entry points
+        for entry in env.entries.iter() {
+            control
+                .directives
+                .push(control_file::ControlDirective::Entry(format!(
+                    "0x{entry:04x}"
+                )));
+        }
+
+        // Add data blocs
+        for bloc in env.blocs.iter() {
+            let range = bloc.to_range_inclusive()?;
+            control
+                .directives
+                .push(control_file::ControlDirective::DataBloc(
+                    DataBlocString::InclusiveRange(
+                        format!("0x{:04x}", range.start()),
+                        format!("0x{:04x}", range.end())
+                    )
+                ));
+        }
+
         // Add labels
         for (address, label) in env.address2label.iter() {
             control
@@ -178,6 +178,18 @@ fn cpcstring_directive(
     .parse_next(input)
 }
 
+/// Parse entry directive: entry <address or label> | e <address or label>
+fn entry_directive(
+    input: &mut &[u8]
+) -> std::result::Result<ControlDirective, ErrMode<ContextError>> {
+    preceded(
+        (alt((Caseless("entry"), Caseless("e"))), space1),
+        parse_value_or_label_string
+    )
+    .map(|e| ControlDirective::Entry(bytes_to_string(e)))
+    .parse_next(input)
+}
+
 /// Parse any directive
 fn directive(input: &mut &[u8]) -> std::result::Result<ControlDirective, ErrMode<ContextError>> {
     preceded(
@@ -188,7 +200,8 @@ fn directive(input: &mut &[u8]) -> std::result::Result<ControlDirective, ErrMode
             length_directive,
             data_directive,
             label_directive,
-            cpcstring_directive
+            cpcstring_directive,
+            entry_directive
         ))
     )
     .parse_next(input)
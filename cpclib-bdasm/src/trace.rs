@@ -0,0 +1,212 @@
+use std::collections::BTreeSet;
+use std::ops::RangeInclusive;
+
+use cpclib_asm::disass::disassemble;
+use cpclib_asm::{DataAccess, ExprEvaluationExt, Mnemonic, Token, TokenExt};
+
+use crate::analysis::resolve_jr_djnz_target;
+
+/// Restart and interrupt routines entered by the Z80 without any visible jump
+pub const VECTORS: [u16; 9] = [0x00, 0x08, 0x10, 0x18, 0x20, 0x28, 0x30, 0x38, 0x66];
+
+/// Longest Z80 instruction
+const MAX_INSTRUCTION_SIZE: usize = 4;
+
+/// Classification of a byte of the binary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteKind {
+    /// Never reached by the execution flow
+    Unknown,
+    /// Part of an instruction reached from an entry point
+    Code,
+    /// Explicitly declared as data
+    Data
+}
+
+/// Where the execution continues after an instruction
+struct Flow {
+    falls_through: bool,
+    target: Option<u16>,
+    /// Number of data bytes that follow the instruction and are skipped by the execution
+    inline: usize
+}
+
+impl Flow {
+    fn new(falls_through: bool, target: Option<u16>) -> Self {
+        Self {
+            falls_through,
+            target,
+            inline: 0
+        }
+    }
+}
+
+/// Code and data of a binary obtained by following jumps and calls from its entry points
+#[derive(Debug)]
+pub struct CodeMap {
+    origin: u16,
+    kinds: Vec<ByteKind>,
+    targets: BTreeSet<u16>
+}
+
+impl CodeMap {
+    /// Follow the execution flow of `bytes` (loaded at `origin`) from each entry point.
+    /// Addresses outside of the binary are ignored and `data` ranges are never executed.
+    pub fn trace(bytes: &[u8], origin: u16, entries: &[u16], data: &[RangeInclusive<u16>]) -> Self {
+        let mut map = CodeMap {
+            origin,
+            kinds: vec![ByteKind::Unknown; bytes.len()],
+            targets: BTreeSet::new()
+        };
+
+        for address in data.iter().flat_map(|r| r.clone()) {
+            if let Some(offset) = map.offset(address) {
+                map.kinds[offset] = ByteKind::Data;
+            }
+        }
+
+        let mut pending = entries
+            .iter()
+            .copied()
+            .filter(|a| map.offset(*a).is_some())
+            .collect::<Vec<_>>();
+        map.targets.extend(pending.iter().copied());
+
+        while let Some(mut address) = pending.pop() {
+            while let Some(offset) = map.offset(address)
+                && map.kinds[offset] == ByteKind::Unknown
+                && let Some((token, size)) = decode(&bytes[offset..])
+                && map.kinds[offset..offset + size]
+                    .iter()
+                    .all(|k| *k == ByteKind::Unknown)
+            {
+                map.kinds[offset..offset + size].fill(ByteKind::Code);
+
+                let flow = flow(&token, &bytes[offset..], size, address);
+                for inline in offset + size..(offset + size + flow.inline).min(bytes.len()) {
+                    if map.kinds[inline] == ByteKind::Unknown {
+                        map.kinds[inline] = ByteKind::Data;
+                    }
+                }
+                if let Some(target) = flow.target
+                    && map.offset(target).is_some()
+                    && map.targets.insert(target)
+                {
+                    pending.push(target);
+                }
+                if !flow.falls_through {
+                    break;
+                }
+                address = address.wrapping_add((size + flow.inline) as u16);
+            }
+        }
+
+        map
+    }
+
+    /// Offset in the binary of an address
+    fn offset(&self, address: u16) -> Option<usize> {
+        let offset = address.wrapping_sub(self.origin) as usize;
+        (offset < self.kinds.len()).then_some(offset)
+    }
+
+    /// Entry points and destinations of the jumps and calls inside the binary
+    pub fn targets(&self) -> &BTreeSet<u16> {
+        &self.targets
+    }
+
+    /// Number of bytes of code
+    pub fn code_bytes(&self) -> usize {
+        self.kinds.iter().filter(|k| **k == ByteKind::Code).count()
+    }
+
+    /// Address ranges that are not code
+    pub fn data_blocs(&self) -> Vec<RangeInclusive<u16>> {
+        let mut blocs = Vec::new();
+        let mut start = None;
+        for (offset, kind) in self.kinds.iter().enumerate() {
+            match (kind, start) {
+                (ByteKind::Code, Some(s)) => {
+                    blocs.push(self.range(s, offset - 1));
+                    start = None;
+                },
+                (ByteKind::Unknown | ByteKind::Data, None) => start = Some(offset),
+                _ => {}
+            }
+        }
+        if let Some(s) = start {
+            blocs.push(self.range(s, self.kinds.len() - 1));
+        }
+        blocs
+    }
+
+    fn range(&self, start: usize, end: usize) -> RangeInclusive<u16> {
+        self.origin.wrapping_add(start as u16)..=self.origin.wrapping_add(end as u16)
+    }
+}
+
+/// Disassemble the instruction at the start of `bytes`.
+/// Returns None when the bytes do not encode a complete instruction
+fn decode(bytes: &[u8]) -> Option<(Token, usize)> {
+    let listing = disassemble(&bytes[..bytes.len().min(MAX_INSTRUCTION_SIZE)]);
+    let token = listing.iter().next()?;
+    if let Token::Defb(_) = token {
+        return None;
+    }
+    let size = token.number_of_bytes().ok()?;
+    (size > 0 && size <= bytes.len()).then(|| (token.clone(), size))
+}
+
+/// Absolute address of a jump or call
+fn absolute_target(arg: &Option<DataAccess>) -> Option<u16> {
+    match arg {
+        Some(DataAccess::Expression(e)) => e.eval().ok()?.int().ok().map(|a| a as u16),
+        _ => None
+    }
+}
+
+/// Flow of the firmware restarts that read the address following them:
+/// LOW JUMP (`rst 1`) and FIRM JUMP (`rst 5`) jump to it,
+/// SIDE CALL (`rst 2`) and FAR CALL (`rst 3`, whose address points to a far address) return after it
+fn firmware_restart(vector: u8, inline: Option<u16>) -> Option<Flow> {
+    let (falls_through, target) = match vector {
+        0x08 => (false, inline.map(|a| a & 0x3FFF)),
+        0x10 | 0x18 => (true, None),
+        0x28 => (false, inline),
+        _ => return None
+    };
+    Some(Flow {
+        falls_through,
+        target,
+        inline: 2
+    })
+}
+
+fn flow(token: &Token, bytes: &[u8], size: usize, address: u16) -> Flow {
+    match token {
+        Token::OpCode(Mnemonic::Jp, cond, target, _) => {
+            Flow::new(cond.is_some(), absolute_target(target))
+        },
+        Token::OpCode(Mnemonic::Jr, cond, Some(DataAccess::Expression(e)), _) => {
+            Flow::new(
+                cond.is_some(),
+                resolve_jr_djnz_target(e, Some(address), token).ok()
+            )
+        },
+        Token::OpCode(Mnemonic::Djnz, Some(DataAccess::Expression(e)), ..) => {
+            Flow::new(true, resolve_jr_djnz_target(e, Some(address), token).ok())
+        },
+        Token::OpCode(Mnemonic::Call, _, target, _) => Flow::new(true, absolute_target(target)),
+        // the argument of the disassembled RST is ambiguous, the opcode is not
+        Token::OpCode(Mnemonic::Rst, ..) => {
+            let vector = bytes[0] & 0x38;
+            let inline = bytes
+                .get(size..size + 2)
+                .map(|w| u16::from_le_bytes([w[0], w[1]]));
+            firmware_restart(vector, inline).unwrap_or_else(|| Flow::new(true, Some(vector as u16)))
+        },
+        Token::OpCode(Mnemonic::Ret, cond, ..) => Flow::new(cond.is_some(), None),
+        Token::OpCode(Mnemonic::Reti | Mnemonic::Retn, ..) => Flow::new(false, None),
+        _ => Flow::new(true, None)
+    }
+}
@@ -1,6 +1,8 @@
 use std::path::PathBuf;
 use std::process::Command;
 
+use clap::Parser;
+use cpclib_bdasm::{BdAsmCli, process};
 use escargot::CargoBuild;
 use fs_err as fs;
 #[cfg(unix)]
@@ -18,6 +20,13 @@ fn get_bdasm_bin() -> PathBuf {
         .to_path_buf()
 }
 
+/// Run bdasm inside the test process: tracing tests do not need the release binary
+fn run_bdasm_in_process(args: &[&str]) {
+    let cli =
+        BdAsmCli::try_parse_from(std::iter::once("bdasm").chain(args.iter().copied())).unwrap();
+    process(&cli, &()).expect("bdasm should succeed");
+}
+
 #[test]
 fn test_hello_world_roundtrip() {
     let temp_dir = TempDir::new().unwrap();
@@ -375,3 +384,137 @@ fn test_save_and_load_control_workflow() {
         output2_content
     );
 }
+
+#[test]
+fn test_trace_separates_code_and_data() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let test_binary = temp_dir.path().join("traced.bin");
+    let test_data = vec![
+        0x18, 0x03, // JR 0x4005
+        0x01, 0x02, 0x03, // data skipped by the jump
+        0xCD, 0x0A, 0x40, // CALL 0x400A
+        0xC9, // RET
+        0xAA, // data after the return
+        0x3E, 0x01, // LD A, 1
+        0xC9, // RET
+    ];
+    fs::write(&test_binary, &test_data).unwrap();
+
+    let control_file = temp_dir.path().join("traced.ctl");
+    let output = temp_dir.path().join("traced.asm");
+
+    run_bdasm_in_process(&[
+        test_binary.to_str().unwrap(),
+        "--origin",
+        "0x4000",
+        "--trace",
+        "-O",
+        output.to_str().unwrap(),
+        "--save-control",
+        control_file.to_str().unwrap()
+    ]);
+
+    let control_content = fs::read_to_string(&control_file).unwrap();
+    assert!(
+        control_content.contains("data 0x4002..=0x4004"),
+        "{control_content}"
+    );
+    assert!(
+        control_content.contains("data 0x4009..=0x4009"),
+        "{control_content}"
+    );
+    assert!(
+        control_content.contains("label label_400a=0x400a"),
+        "{control_content}"
+    );
+
+    let output_content = fs::read_to_string(&output).unwrap();
+    assert!(
+        output_content.contains("CALL label_400a"),
+        "{output_content}"
+    );
+    assert!(
+        output_content.contains("DB 0x1,0x2,0x3"),
+        "{output_content}"
+    );
+}
+
+#[test]
+fn test_trace_skips_firmware_restart_addresses() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let test_binary = temp_dir.path().join("restarts.bin");
+    let test_data = vec![
+        0xCF, // RST 1 (LOW JUMP)
+        0x05, 0x10, // DW 0x1005
+        0x01, 0x02, // data never reached
+        0xD7, // RST 2 (SIDE CALL)
+        0x00, 0xC0, // DW 0xC000
+        0xC9, // RET
+    ];
+    fs::write(&test_binary, &test_data).unwrap();
+
+    let control_file = temp_dir.path().join("restarts.ctl");
+
+    run_bdasm_in_process(&[
+        test_binary.to_str().unwrap(),
+        "--origin",
+        "0x1000",
+        "--trace",
+        "-O",
+        temp_dir.path().join("restarts.asm").to_str().unwrap(),
+        "--save-control",
+        control_file.to_str().unwrap()
+    ]);
+
+    let control_content = fs::read_to_string(&control_file).unwrap();
+    assert!(
+        control_content.contains("data 0x1001..=0x1004"),
+        "{control_content}"
+    );
+    assert!(
+        control_content.contains("data 0x1006..=0x1007"),
+        "{control_content}"
+    );
+    assert!(
+        control_content.contains("label label_1005=0x1005"),
+        "{control_content}"
+    );
+}
+
+#[test]
+fn test_trace_from_control_file_entry() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let test_binary = temp_dir.path().join("entry.bin");
+    let test_data = vec![
+        0xC9, // RET
+        0xAA, // data
+        0x3E, 0x01, // LD A, 1 only reachable from the entry
+        0xC9, // RET
+    ];
+    fs::write(&test_binary, &test_data).unwrap();
+
+    let control_file = temp_dir.path().join("entry.ctl");
+    fs::write(
+        &control_file,
+        "origin 0x8000\nlabel routine=0x8002\nentry routine\n"
+    )
+    .unwrap();
+    let output = temp_dir.path().join("entry.asm");
+
+    run_bdasm_in_process(&[
+        test_binary.to_str().unwrap(),
+        "--control",
+        control_file.to_str().unwrap(),
+        "--trace",
+        "-O",
+        output.to_str().unwrap()
+    ]);
+
+    let output_content = fs::read_to_string(&output).unwrap();
+    assert!(output_content.contains("DB 0xaa"), "{output_content}");
+    assert!(output_content.contains("routine"), "{output_content}");
+    assert!(output_content.contains("LD A, 0x1"), "{output_content}");
+}
@@ -376,8 +376,8 @@ pub const TASK_TYPES: &[TaskType] = &[
     },
     TaskType {
         names: CSL_CMDS,
-        description: "Process CSL files",
-        synopsis: "csl [OPTIONS] <FILE>",
+        description: "Parse or execute CSL files",
+        synopsis: "csl [OPTIONS] <FILE> | csl run [OPTIONS] <FILE>",
         example: ""
     },
     TaskType {
@@ -1,6 +1,7 @@
 use std::fmt::Display;
 use std::time::SystemTime;
 
+use cpclib_asm::assembler::dependencies::Dependencies;
 use cpclib_common::camino::{Utf8Path, Utf8PathBuf};
 use cpclib_common::itertools::Itertools;
 #[cfg(feature = "rayon")]
@@ -361,13 +362,28 @@ impl Rule {
             .map(|p| p.metadata().unwrap().modified().unwrap())
             .min();
 
-        let newest_dependencies = self
+        let mut newest_dependencies = self
             .dependencies
             .iter()
             .filter(|p| p.exists())
             .map(|p| p.metadata().unwrap().modified().unwrap())
             .max();
 
+        // the dependencies files generated by basm also list the included files
+        for fname in self.commands.iter().filter_map(|c| c.dependencies_file()) {
+            let Ok(dependencies) = Dependencies::load(&fname)
+            else {
+                // the command has never generated it
+                return false;
+            };
+            let newest_input = dependencies
+                .inputs()
+                .filter(|p| p.exists())
+                .map(|p| p.metadata().unwrap().modified().unwrap())
+                .max();
+            newest_dependencies = newest_dependencies.max(newest_input);
+        }
+
         oldest_target > newest_dependencies
     }
 
@@ -462,4 +478,52 @@ mod tests {
             vec![Utf8PathBuf::from("foo.txt"), Utf8PathBuf::from("bar.txt")]
         );
     }
+
+    #[test]
+    fn test_up_to_date_with_dependencies_file() {
+        use std::time::Duration;
+
+        let dir = camino_tempfile::tempdir().unwrap();
+        let path = |fname: &str| dir.path().join(fname);
+        let touch = |fname: &str, age: u64| {
+            let f = fs_err::File::create(path(fname)).unwrap();
+            f.file()
+                .set_modified(SystemTime::now() - Duration::from_secs(age))
+                .unwrap();
+        };
+
+        let rule = Rule::new(
+            &[path("main.bin")],
+            &[path("main.asm")],
+            &[Task::new_basm(&format!(
+                "{} -o {} -M {}",
+                path("main.asm"),
+                path("main.bin"),
+                path("main.d")
+            ))]
+        );
+
+        touch("main.asm", 30);
+        touch("included.asm", 30);
+        touch("main.bin", 20);
+        assert!(
+            !rule.is_up_to_date(None, None::<&Utf8Path>),
+            "the dependencies file has never been generated"
+        );
+
+        fs_err::write(
+            path("main.d"),
+            format!(
+                "{}: {} {}\n",
+                path("main.bin"),
+                path("main.asm"),
+                path("included.asm")
+            )
+        )
+        .unwrap();
+        assert!(rule.is_up_to_date(None, None::<&Utf8Path>));
+
+        touch("included.asm", 10);
+        assert!(!rule.is_up_to_date(None, None::<&Utf8Path>));
+    }
 }
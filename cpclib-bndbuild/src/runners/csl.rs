@@ -65,4 +65,21 @@ mod test {
         );
         assert!(obs.get_stdout().is_empty(), "no stdout on arg error");
     }
+
+    #[test]
+    fn test_csl_run_executes_the_script() {
+        let dir = camino_tempfile::tempdir().unwrap();
+        let script = dir.path().join("script.csl");
+        fs_err::write(
+            &script,
+            "csl_version 1.1\nwait 20000\nscreenshot_name 'shot'\nscreenshot\n"
+        )
+        .unwrap();
+
+        let runner = super::CslRunner::default();
+        let obs = CapturingObserver::new();
+        let result = runner.inner_run(&["run", script.as_str()], &obs);
+        assert!(result.is_ok(), "{result:?}");
+        assert!(dir.path().join("shot.png").exists());
+    }
 }
@@ -4,7 +4,7 @@ use std::str::FromStr;
 use std::sync::atomic::AtomicUsize;
 use std::sync::{Arc, LazyLock};
 
-use camino::Utf8Path;
+use camino::{Utf8Path, Utf8PathBuf};
 use cpclib_common::clap::ArgMatches;
 use cpclib_common::itertools::Itertools;
 use cpclib_runner::emucontrol::EMUCTRL_CMD;
@@ -899,6 +899,30 @@ impl InnerTask {
         }
     }
 
+    /// Dependencies file (`-M`) generated by basm.
+    /// It lists all the files read when assembling
+    pub fn dependencies_file(&self) -> Option<Utf8PathBuf> {
+        match self {
+            InnerTask::Assembler(Assembler::Basm, t) => {
+                let args = shlex::split(&t.args).unwrap_or_default();
+                let mut args = args.iter();
+                while let Some(arg) = args.next() {
+                    if arg == "-M" || arg == "--dependencies" {
+                        return args.next().map(Utf8PathBuf::from);
+                    }
+                    else if let Some(fname) = arg
+                        .strip_prefix("--dependencies=")
+                        .or_else(|| arg.strip_prefix("-M"))
+                    {
+                        return Some(fname.into());
+                    }
+                }
+                None
+            },
+            _ => None
+        }
+    }
+
     fn standard_task_arguments(&self) -> &StandardTaskArguments {
         match self {
             InnerTask::Assembler(_, t)
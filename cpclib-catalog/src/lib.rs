@@ -638,6 +638,7 @@ fn load_catalog_bytes(catalog_fname: &str) -> Result<Vec<u8>, String> {
         let disc = open_disc(catalog_fname, true)
             .map_err(|e| format!("Unable to read the disc file: {:?}", e))?;
         let manager = AmsdosManagerNonMut::new_from_disc(&disc, Head::A);
+        manager.dpb().try_nb_blocs().map_err(|e| e.to_string())?;
         Ok(manager.catalog_slice())
     }
     else {
@@ -657,6 +658,7 @@ fn load_catalog_entries(catalog_fname: &str) -> Result<AmsdosEntries, String> {
         let disc = open_disc(catalog_fname, true)
             .map_err(|e| format!("Unable to read the disc file: {:?}", e))?;
         let manager = AmsdosManagerNonMut::new_from_disc(&disc, Head::A);
+        manager.dpb().try_nb_blocs().map_err(|e| e.to_string())?;
         Ok(manager.catalog())
     }
     else {
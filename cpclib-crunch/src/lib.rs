@@ -37,7 +37,16 @@ pub struct CrunchArgs {
     #[arg(
         short,
         long,
-        help = "Compressed output file. Can be a binary, an Amsdos file, a file in a disc",
+        help = "Decompress the input file (crunched with the same cruncher) instead of compressing it",
+        default_value_t = false,
+        conflicts_with = "keep_header"
+    )]
+    decompress: bool,
+
+    #[arg(
+        short,
+        long,
+        help = "Compressed (or decompressed) output file. Can be a binary, an Amsdos file, a file in a disc",
         requires = "input"
     )]
     output: Option<Utf8PathBuf>,
@@ -89,6 +98,9 @@ pub enum Cruncher {
     #[cfg(feature = "pucrunch")]
     #[value(help = "PuCrunch (LGPL, Pasi Ojala 1997-2008)")]
     Pucrunch,
+    #[cfg(feature = "pucrunch")]
+    #[value(help = "PuCrunch preceded by the header of the z80 decompressor")]
+    PucrunchWithHeader,
     #[cfg(feature = "shrinkler")]
     #[value(help = "Shrinkler (Custom/Proprietary, Aske Simon Christensen 1999-2020)")]
     Shrinkler,
@@ -158,7 +170,9 @@ impl Cruncher {
             #[cfg(feature = "lzsa")]
             Cruncher::Lzsa2 => "inner://unlzsa1_fast.asm",
             #[cfg(feature = "pucrunch")]
-            Cruncher::Pucrunch => "inner://uncrunch/pucrunch_z80.asm",
+            Cruncher::Pucrunch | Cruncher::PucrunchWithHeader => {
+                "inner://uncrunch/pucrunch_z80.asm"
+            },
             #[cfg(feature = "shrinkler")]
             Cruncher::Shrinkler => "inner://deshrink.asm",
             #[cfg(feature = "zx0")]
@@ -280,9 +294,11 @@ pub fn process(args: CrunchArgs, o: &dyn EventObserver) -> Result<(), String> {
         #[cfg(feature = "lzsa")]
         Cruncher::Lzsa1 => CompressMethod::Lzsa(LzsaVersion::V1, None),
         #[cfg(feature = "lzsa")]
-        Cruncher::Lzsa2 => CompressMethod::Lzsa(LzsaVersion::V1, None),
+        Cruncher::Lzsa2 => CompressMethod::Lzsa(LzsaVersion::V2, None),
         #[cfg(feature = "pucrunch")]
         Cruncher::Pucrunch => CompressMethod::Pucrunch,
+        #[cfg(feature = "pucrunch")]
+        Cruncher::PucrunchWithHeader => CompressMethod::PucrunchWithHeader,
         #[cfg(feature = "shrinkler")]
         Cruncher::Shrinkler => CompressMethod::Shrinkler(Default::default()),
         #[cfg(feature = "zx0")]
@@ -309,15 +325,23 @@ pub fn process(args: CrunchArgs, o: &dyn EventObserver) -> Result<(), String> {
         Cruncher::BackwardBx2 => CompressMethod::BackwardBx2
     };
 
-    let crunched = cruncher
-        .compress(&data)
-        .map_err(|_e| "Error when crunching file.".to_string())?;
+    let processed: Vec<u8> = if args.decompress {
+        cruncher
+            .decompress(&data)
+            .map_err(|e| format!("Error when decrunching file. {e}"))?
+    }
+    else {
+        cruncher
+            .compress(&data)
+            .map_err(|_e| "Error when crunching file.".to_string())?
+            .into()
+    };
 
     let file_and_support = FileAndSupport::new_auto(args.output.unwrap(), args.header);
 
     file_and_support
         .save(
-            &crunched,
+            &processed,
             Some(0xC000),
             None,
             Some(AmsdosAddBehavior::ReplaceAndEraseIfPresent)
@@ -41,6 +41,11 @@ fn build_others() {
         .opt_level(3)
         .cargo_metadata(true)
         .compile("pucrunch");
+    #[cfg(feature = "pucrunch")]
+    {
+        println!("cargo:rerun-if-changed=extra/pucrunch.c");
+        println!("cargo:rerun-if-changed=extra/pucrunch_ffi.c");
+    }
 
     // zx7 crunch
     #[cfg(feature = "zx7")]
@@ -1396,6 +1396,54 @@ void OptimizeRle(int flags) {
     InitRleLen();
 }
 
+/* Set the LZ77 and RLE limits for the current maxGamma, as main() does
+   once the options are parsed, and clear the output of a previous pack. */
+void InitPacker(void) {
+    lrange = LRANGE;
+    maxlzlen = MAXLZLEN;
+    maxrlelen = MAXRLELEN;
+
+    memset(outBuffer, 0, sizeof(outBuffer));
+    outPointer = 0;
+    bitMask = 0x80;
+}
+
+/* Write in head the header SavePack outputs when no decompressor is
+   requested (the one expected by the z80 decompressor).
+   Returns the number of bytes written (at most 16 + 31). */
+int SaveHeader(unsigned char *head, int size, int start, int exec,
+	       int escape, int endAddr) {
+    int i, cnt = 0;
+
+    head[cnt++] = (endAddr - size) & 0xff;	/* INPOS */
+    head[cnt++] = ((endAddr - size) >> 8);
+
+    head[cnt++] = 'p';
+    head[cnt++] = 'u';
+
+    head[cnt++] = (endAddr - 0x100) & 0xff;
+    head[cnt++] = ((endAddr - 0x100) >> 8);
+
+    head[cnt++] = (escape>>(8-escBits));
+    head[cnt++] = (start & 0xff);	/* OUTPOS */
+    head[cnt++] = (start >> 8);
+    head[cnt++] = escBits;
+
+    head[cnt++] = maxGamma + 1;
+    head[cnt++] = (1<<maxGamma); /* Short/Long RLE */
+
+    head[cnt++] = extraLZPosBits;
+
+    head[cnt++] = (exec & 0xff);
+    head[cnt++] = (exec >> 8);
+
+    head[cnt++] = rleUsed;
+    for(i = 1; i <= rleUsed; i++) {
+	head[cnt++] = rleValues[i];
+    }
+    return cnt;
+}
+
 
 static const unsigned char *up_Data;
 static int up_Mask, up_Byte;
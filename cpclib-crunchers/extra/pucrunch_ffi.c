@@ -4,19 +4,21 @@
 
 // Minimal declarations needed by the FFI wrapper.
 int PackLz77(int lzlen, int flags, int *startEscape, int endAddr, int memEnd, int type);
+void InitPacker(void);
+int SaveHeader(unsigned char *head, int size, int start, int exec, int escape, int endAddr);
 extern unsigned char *pucrunch_indata;
 extern int pucrunch_inlen;
 extern unsigned char outBuffer[65536];
 extern int outPointer;
 
 // Minimal FFI wrapper: compresses input to output buffer, returns output size or -1 on error
-int pucrunch_compress(const uint8_t* input, size_t input_len, uint8_t* output, size_t* output_len) {
+// The header expected by the z80 decompressor is only written when with_header is not 0
+int pucrunch_compress(const uint8_t* input, size_t input_len, uint8_t* output, size_t* output_len, int with_header) {
     // Setup global input
     pucrunch_indata = (unsigned char*)malloc(input_len);
     if (!pucrunch_indata) return -1;
     memcpy(pucrunch_indata, input, input_len);
     pucrunch_inlen = (int)input_len;
-    outPointer = 0;
 
     // Use default pucrunch settings (raw, no load address, C64 mode)
     int lzlen = -1;
@@ -26,14 +28,16 @@ int pucrunch_compress(const uint8_t* input, size_t input_len, uint8_t* output, s
     int memEnd = 0x10000;
     int type = 64; // C64
 
+    InitPacker();
     int res = PackLz77(lzlen, flags, &startEscape, endAddr, memEnd, type);
     if (res != 0) {
         free(pucrunch_indata);
         return -1;
     }
-    // Copy output from static outBuffer
-    memcpy(output, outBuffer, outPointer);
-    *output_len = outPointer;
+    // Prepend the header needed to decompress the stream, then copy output from static outBuffer
+    int headerLen = with_header ? SaveHeader(output, outPointer, 0, 0, startEscape, endAddr) : 0;
+    memcpy(output + headerLen, outBuffer, outPointer);
+    *output_len = headerLen + outPointer;
     free(pucrunch_indata);
     return 0;
 }
@@ -1,3 +1,6 @@
+use crate::CrunchersError;
+use crate::decrunch::InputStream;
+
 unsafe extern "C" {
     fn APULTRA_crunch(
         data: *const libc::c_uchar,
@@ -41,3 +44,113 @@ pub fn compress(data: &[u8]) -> Vec<u8> {
 
     crunched
 }
+
+const MINMATCH3_OFFSET: usize = 1280;
+const MINMATCH4_OFFSET: usize = 32000;
+
+/// aPLib bit reader: bits are read msb first from bytes interleaved with the data bytes
+struct BitReader<'a> {
+    input: InputStream<'a>,
+    bits: u8,
+    mask: u8
+}
+
+impl BitReader<'_> {
+    fn read_bit(&mut self) -> Result<usize, CrunchersError> {
+        if self.mask == 0 {
+            self.bits = self.input.read_byte()?;
+            self.mask = 0x80;
+        }
+        let bit = (self.bits & self.mask != 0) as usize;
+        self.mask >>= 1;
+        Ok(bit)
+    }
+
+    fn read_gamma2(&mut self) -> Result<usize, CrunchersError> {
+        let mut value = 1;
+        loop {
+            value = (value << 1) + self.read_bit()?;
+            if self.read_bit()? == 0 {
+                return Ok(value);
+            }
+        }
+    }
+}
+
+/// Decompress a raw aPLib stream produced by [compress]
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, CrunchersError> {
+    let mut reader = BitReader {
+        input: InputStream::new(data, "aPLib"),
+        bits: 0,
+        mask: 0
+    };
+    let mut out = Vec::with_capacity(data.len() * 2);
+
+    let mut offset = 1;
+    let mut follows_literal = 2;
+
+    // first byte always literal
+    out.push(reader.input.read_byte()?);
+
+    loop {
+        if reader.read_bit()? == 0 {
+            // '0': literal
+            out.push(reader.input.read_byte()?);
+            follows_literal = 2;
+        }
+        else if reader.read_bit()? == 0 {
+            // '10': 8+n bits offset or rep-match
+            let high = reader.read_gamma2()?;
+            let length = if high > follows_literal {
+                offset = ((high - follows_literal - 1) << 8) | reader.input.read_byte()? as usize;
+                let length = reader.read_gamma2()?;
+                if !(128..MINMATCH4_OFFSET).contains(&offset) {
+                    length + 2
+                }
+                else if offset >= MINMATCH3_OFFSET {
+                    length + 1
+                }
+                else {
+                    length
+                }
+            }
+            else if high == follows_literal {
+                reader.read_gamma2()?
+            }
+            else {
+                return Err(reader.input.error("invalid match offset"));
+            };
+
+            reader.input.copy_match(&mut out, offset, length)?;
+            follows_literal = 1;
+        }
+        else if reader.read_bit()? == 0 {
+            // '110': 7 bits offset + 1 bit length
+            let command = reader.input.read_byte()? as usize;
+            if command == 0 {
+                break;
+            }
+            offset = command >> 1;
+            reader
+                .input
+                .copy_match(&mut out, offset, (command & 1) + 2)?;
+            follows_literal = 1;
+        }
+        else {
+            // '111': 4 bits offset, 0 stands for a zero byte
+            let mut short_offset = 0;
+            for _ in 0..4 {
+                short_offset = (short_offset << 1) | reader.read_bit()?;
+            }
+            if short_offset == 0 {
+                out.push(0);
+            }
+            else {
+                reader.input.copy_match(&mut out, short_offset, 1)?;
+            }
+            follows_literal = 2;
+        }
+    }
+
+    Ok(out)
+}
@@ -1,3 +1,6 @@
+use crate::CrunchersError;
+use crate::decrunch::InputStream;
+
 /// Bzpack compression formats: LZM, EF8, BX0, BX2
 /// These are LZSS-based formats designed for minimal Z80 decoders.
 ///
@@ -45,3 +48,200 @@ pub fn compress(data: &[u8], format: BzpackFormat) -> Vec<u8> {
 pub fn compress_backward(data: &[u8], format: BzpackFormat) -> Vec<u8> {
     ffi::bzpack_compress(data, format as u8, true, true, false, false, false)
 }
+
+/// Port of the bzpack BitStream reader.
+/// Bytes and bit groups share the same cursor. Bit groups of EF8, BX0 and BX2 are complemented
+/// and the first one has been incremented to help the z80 decoders.
+struct BitReader<'a> {
+    input: InputStream<'a>,
+    bits: u8,
+    mask: u8,
+    complement: u8,
+    first_bits_read: bool
+}
+
+impl BitReader<'_> {
+    fn read_bit(&mut self) -> Result<usize, CrunchersError> {
+        self.mask >>= 1;
+        if self.mask == 0 {
+            self.mask = 0x80;
+            self.bits = self.input.read_byte()?;
+            if !self.first_bits_read {
+                self.first_bits_read = true;
+                if self.complement != 0 {
+                    self.bits = self.bits.wrapping_sub(1);
+                }
+            }
+        }
+        Ok(((self.bits ^ self.complement) & self.mask != 0) as usize)
+    }
+
+    fn read_byte(&mut self) -> Result<u8, CrunchersError> {
+        self.input.read_byte()
+    }
+
+    fn read_elias(&mut self) -> Result<usize, CrunchersError> {
+        let mut value = 1;
+        while self.read_bit()? == 1 {
+            value = (value << 1) | self.read_bit()?;
+            if value > 0x1_0000 {
+                return Err(self.input.error("invalid elias gamma value"));
+            }
+        }
+        Ok(value)
+    }
+
+    fn read_elias_with_flag(&mut self, mut flag: bool) -> Result<usize, CrunchersError> {
+        let mut value = 1;
+        while flag {
+            value = (value << 1) | self.read_bit()?;
+            flag = self.read_bit()? == 1;
+            if value > 0x1_0000 {
+                return Err(self.input.error("invalid elias gamma value"));
+            }
+        }
+        Ok(value)
+    }
+
+    fn copy_match(
+        &self,
+        out: &mut Vec<u8>,
+        offset: usize,
+        length: usize
+    ) -> Result<(), CrunchersError> {
+        self.input.copy_match(out, offset, length)
+    }
+}
+
+fn decode_lzm(reader: &mut BitReader, out: &mut Vec<u8>) -> Result<(), CrunchersError> {
+    loop {
+        let length = reader.read_byte()?;
+        if length == 0 {
+            return Ok(());
+        }
+
+        let is_literal = length & 1 != 0;
+        let length = (length >> 1) as usize;
+        if is_literal {
+            for _ in 0..length {
+                out.push(reader.read_byte()?);
+            }
+        }
+        else {
+            let offset = reader.read_byte()? as usize;
+            reader.copy_match(out, offset, length)?;
+        }
+    }
+}
+
+fn decode_ef8(reader: &mut BitReader, out: &mut Vec<u8>) -> Result<(), CrunchersError> {
+    loop {
+        let length = reader.read_elias()?;
+        if length >= 0x100 {
+            return Ok(());
+        }
+
+        if reader.read_bit()? == 1 {
+            for _ in 0..length {
+                out.push(reader.read_byte()?);
+            }
+        }
+        else {
+            let offset = reader.read_byte()? as usize;
+            reader.copy_match(out, offset, length + 1)?;
+        }
+    }
+}
+
+fn decode_bx0(reader: &mut BitReader, out: &mut Vec<u8>) -> Result<(), CrunchersError> {
+    let mut rep_offset = 0;
+    let mut was_literal = false;
+
+    loop {
+        if !out.is_empty() && reader.read_bit()? == 1 {
+            let offset = reader.read_elias()? - 1;
+            if offset & 0x80 != 0 {
+                return Ok(());
+            }
+
+            let offset = (offset << 8) | reader.read_byte()? as usize;
+            let length = reader.read_elias_with_flag(offset & 1 != 0)? + 1;
+            let offset = offset >> 1;
+            reader.copy_match(out, offset, length)?;
+
+            rep_offset = offset;
+            was_literal = false;
+        }
+        else if was_literal {
+            let length = reader.read_elias()?;
+            reader.copy_match(out, rep_offset, length)?;
+            was_literal = false;
+        }
+        else {
+            let length = reader.read_elias()?;
+            for _ in 0..length {
+                out.push(reader.read_byte()?);
+            }
+            was_literal = true;
+        }
+    }
+}
+
+fn decode_bx2(reader: &mut BitReader, out: &mut Vec<u8>) -> Result<(), CrunchersError> {
+    let mut rep_offset = 0;
+    let mut was_literal = false;
+
+    loop {
+        let length = reader.read_elias()?;
+
+        if reader.read_bit()? == 1 {
+            if was_literal {
+                reader.copy_match(out, rep_offset, length)?;
+            }
+            else {
+                for _ in 0..length {
+                    out.push(reader.read_byte()?);
+                }
+            }
+            was_literal = !was_literal;
+        }
+        else {
+            let offset = reader.read_byte()? as usize;
+            if offset == 0 {
+                return Ok(());
+            }
+            reader.copy_match(out, offset, length + 1)?;
+            rep_offset = offset;
+            was_literal = false;
+        }
+    }
+}
+
+/// Decompress a stream produced by [compress] with the same format.
+pub fn decompress(data: &[u8], format: BzpackFormat) -> Result<Vec<u8>, CrunchersError> {
+    let mut reader = BitReader {
+        input: InputStream::new(data, "bzpack"),
+        bits: 0,
+        mask: 0,
+        complement: if format == BzpackFormat::Lzm { 0 } else { 0xFF },
+        first_bits_read: false
+    };
+    let mut out = Vec::with_capacity(data.len() * 2);
+
+    match format {
+        BzpackFormat::Lzm => decode_lzm(&mut reader, &mut out),
+        BzpackFormat::Ef8 => decode_ef8(&mut reader, &mut out),
+        BzpackFormat::Bx0 => decode_bx0(&mut reader, &mut out),
+        BzpackFormat::Bx2 => decode_bx2(&mut reader, &mut out)
+    }?;
+
+    Ok(out)
+}
+
+/// Decompress a stream produced by [compress_backward] with the same format.
+pub fn decompress_backward(data: &[u8], format: BzpackFormat) -> Result<Vec<u8>, CrunchersError> {
+    let data = data.iter().rev().cloned().collect::<Vec<u8>>();
+    let mut result = decompress(&data, format)?;
+    result.reverse();
+    Ok(result)
+}
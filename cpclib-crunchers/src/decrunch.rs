@@ -0,0 +1,77 @@
+//! Helpers shared by the host decompressors
+
+use crate::CrunchersError;
+
+/// Byte oriented reader over a compressed stream.
+/// Each method builds its bit reader on top of it.
+pub(crate) struct InputStream<'a> {
+    data: &'a [u8],
+    pos: usize,
+    method: &'static str
+}
+
+impl<'a> InputStream<'a> {
+    pub(crate) fn new(data: &'a [u8], method: &'static str) -> Self {
+        Self {
+            data,
+            pos: 0,
+            method
+        }
+    }
+
+    /// Read the next byte and fail when the stream is exhausted
+    pub(crate) fn read_byte(&mut self) -> Result<u8, CrunchersError> {
+        let byte = self
+            .data
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| self.error("unexpected end of the compressed stream"))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    /// Read a little endian word
+    pub(crate) fn read_word(&mut self) -> Result<u16, CrunchersError> {
+        let low = self.read_byte()? as u16;
+        let high = self.read_byte()? as u16;
+        Ok(low + (high << 8))
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.pos)
+    }
+
+    pub(crate) fn error(&self, msg: &str) -> CrunchersError {
+        CrunchersError::DecompressionFailed(format!(
+            "{} stream, byte {}: {msg}",
+            self.method, self.pos
+        ))
+    }
+
+    /// Append `length` bytes located `offset` bytes before the end of `out`.
+    /// The copy is done byte per byte as source and destination may overlap.
+    pub(crate) fn copy_match(
+        &self,
+        out: &mut Vec<u8>,
+        offset: usize,
+        length: usize
+    ) -> Result<(), CrunchersError> {
+        if offset == 0 || offset > out.len() {
+            return Err(self.error(&format!(
+                "offset {offset} is out of the {} already decompressed bytes",
+                out.len()
+            )));
+        }
+
+        let start = out.len() - offset;
+        out.reserve(length);
+        for idx in 0..length {
+            out.push(out[start + idx]);
+        }
+        Ok(())
+    }
+}
@@ -1,3 +1,6 @@
+use crate::CrunchersError;
+use crate::decrunch::InputStream;
+
 unsafe extern "C" {
     fn Exomizer_crunch(
         input_data: *const libc::c_uchar,
@@ -31,3 +34,110 @@ pub fn compress(data: &[u8]) -> Vec<u8> {
 
     crunched
 }
+
+/// Exomizer bit reader.
+/// The bit buffer contains a sentinel bit that tells when a new byte must be fetched
+struct BitReader<'a> {
+    input: InputStream<'a>,
+    bitbuf: u16
+}
+
+impl BitReader<'_> {
+    fn read_bits(&mut self, count: u8) -> Result<usize, CrunchersError> {
+        let mut value = 0;
+        for _ in 0..count {
+            if self.bitbuf & 0x1FF == 1 {
+                self.bitbuf = self.input.read_byte()? as u16 | 0x100;
+            }
+            value = (value << 1) | (self.bitbuf & 1) as usize;
+            self.bitbuf >>= 1;
+        }
+        Ok(value)
+    }
+
+    fn read_gamma(&mut self) -> Result<usize, CrunchersError> {
+        let mut gamma = 0;
+        while self.read_bits(1)? == 0 {
+            gamma += 1;
+            if gamma > 17 {
+                return Err(self.input.error("invalid gamma code"));
+            }
+        }
+        Ok(gamma)
+    }
+}
+
+/// Decoding tables of an exomizer stream
+struct Tables {
+    base: [usize; 52],
+    bits: [u8; 52]
+}
+
+impl Tables {
+    const TABLE_BIT: [u8; 3] = [2, 4, 4];
+    const TABLE_OFF: [usize; 3] = [48, 32, 16];
+
+    fn read(reader: &mut BitReader) -> Result<Self, CrunchersError> {
+        let mut base = [0; 52];
+        let mut bits = [0; 52];
+
+        let mut a = 0;
+        let mut b = 0;
+        for i in 0..52 {
+            if i & 0xF != 0 {
+                a += 1 << b;
+            }
+            else {
+                a = 1;
+            }
+            base[i] = a;
+            b = reader.read_bits(4)?;
+            bits[i] = b as u8;
+        }
+
+        Ok(Self { base, bits })
+    }
+
+    fn cooked_code(&self, reader: &mut BitReader, index: usize) -> Result<usize, CrunchersError> {
+        Ok(self.base[index] + reader.read_bits(self.bits[index])?)
+    }
+}
+
+/// Decompress a stream produced by [compress].
+/// The tables come first and the data is decoded from start to end.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, CrunchersError> {
+    let mut reader = BitReader {
+        input: InputStream::new(data, "Exomizer"),
+        bitbuf: 0
+    };
+    reader.bitbuf = reader.input.read_byte()? as u16;
+    let tables = Tables::read(&mut reader)?;
+
+    let mut out = Vec::with_capacity(data.len() * 2);
+    loop {
+        if reader.read_bits(1)? == 1 {
+            out.push(reader.input.read_byte()?);
+            continue;
+        }
+
+        let gamma = reader.read_gamma()?;
+        match gamma {
+            16 => break,
+            17 => {
+                let length = reader.read_bits(16)?;
+                for _ in 0..length {
+                    out.push(reader.input.read_byte()?);
+                }
+            },
+            _ => {
+                let length = tables.cooked_code(&mut reader, gamma)?;
+                let i = length.clamp(1, 3) - 1;
+                let index = Tables::TABLE_OFF[i] + reader.read_bits(Tables::TABLE_BIT[i])?;
+                let offset = tables.cooked_code(&mut reader, index)?;
+                reader.input.copy_match(&mut out, offset, length)?;
+            }
+        }
+    }
+
+    Ok(out)
+}
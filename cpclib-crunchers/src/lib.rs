@@ -38,6 +38,21 @@ pub mod lzsa;
 #[cfg(all(feature = "bzpack", not(target_arch = "wasm32")))]
 pub mod bzpack;
 
+#[cfg(any(
+    feature = "apultra",
+    feature = "bzpack",
+    feature = "exomizer",
+    feature = "lz4",
+    feature = "lz48",
+    feature = "lz49",
+    feature = "lzsa",
+    feature = "pucrunch",
+    feature = "shrinkler",
+    feature = "zx0",
+    feature = "zx7"
+))]
+mod decrunch;
+
 pub enum CompressMethod {
     // No compression at all
     None,
@@ -55,8 +70,12 @@ pub enum CompressMethod {
     Lzsa(LzsaVersion, Option<LzsaMinMatch>),
     #[cfg(all(feature = "shrinkler", not(target_arch = "wasm32")))]
     Shrinkler(ShrinklerConfiguration),
+    /// Pucrunch stream without header
     #[cfg(all(feature = "pucrunch", not(target_arch = "wasm32")))]
     Pucrunch,
+    /// Pucrunch stream preceded by the header expected by the z80 decompressor
+    #[cfg(all(feature = "pucrunch", not(target_arch = "wasm32")))]
+    PucrunchWithHeader,
     #[cfg(all(feature = "upkr", not(target_arch = "wasm32")))]
     Upkr,
     #[cfg(all(feature = "zx0", not(target_arch = "wasm32")))]
@@ -120,7 +139,30 @@ impl From<CompressionResult> for Vec<u8> {
 
 #[derive(Debug)]
 pub enum CrunchersError {
-    CompressionFailed
+    CompressionFailed,
+    DecompressionFailed(String)
+}
+
+impl std::fmt::Display for CrunchersError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CrunchersError::CompressionFailed => write!(f, "Compression failed"),
+            CrunchersError::DecompressionFailed(msg) => write!(f, "Decompression failed. {msg}")
+        }
+    }
+}
+
+/// Configuration shared by the upkr packer and unpacker.
+/// It must stay in sync with the z80 decompression routine
+#[cfg(all(feature = "upkr", not(target_arch = "wasm32")))]
+fn upkr_config() -> upkr::Config {
+    upkr::Config {
+        use_bitstream: true,
+        bitstream_is_big_endian: true,
+        invert_bit_encoding: true,
+        simplified_prob_update: true,
+        ..Default::default()
+    }
 }
 
 impl CompressMethod {
@@ -147,17 +189,12 @@ impl CompressMethod {
             CompressMethod::Shrinkler(conf) => Ok(conf.compress(data).into()),
             #[cfg(all(feature = "pucrunch", not(target_arch = "wasm32")))]
             CompressMethod::Pucrunch => pucrunch::compress(data),
+            #[cfg(all(feature = "pucrunch", not(target_arch = "wasm32")))]
+            CompressMethod::PucrunchWithHeader => pucrunch::compress_with_header(data),
             #[cfg(all(feature = "upkr", not(target_arch = "wasm32")))]
             CompressMethod::Upkr => {
-                let config = upkr::Config {
-                    use_bitstream: true,
-                    bitstream_is_big_endian: true,
-                    invert_bit_encoding: true,
-                    simplified_prob_update: true,
-                    ..Default::default()
-                };
                 let level = 9;
-                Ok(upkr::pack(data, level, &config, None).into())
+                Ok(upkr::pack(data, level, &upkr_config(), None).into())
             },
             #[cfg(all(feature = "zx0", not(target_arch = "wasm32")))]
             CompressMethod::Zx0 => Ok(zx0::compress(data)),
@@ -192,4 +229,74 @@ impl CompressMethod {
             },
         }
     }
+
+    /// Decompress a stream produced by [CompressMethod::compress] with the very same method.
+    /// [CompressMethod::Pucrunch] streams lack the parameters needed and are refused.
+    /// All the decompressors are written in Rust and do not rely on the C/C++ crunchers.
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, CrunchersError> {
+        match self {
+            CompressMethod::None => Ok(data.to_vec()),
+            #[cfg(all(feature = "apultra", not(target_arch = "wasm32")))]
+            CompressMethod::Apultra => apultra::decompress(data),
+            #[cfg(all(feature = "exomizer", not(target_arch = "wasm32")))]
+            CompressMethod::Exomizer => exomizer::decompress(data),
+            #[cfg(all(feature = "lz4", not(target_arch = "wasm32")))]
+            CompressMethod::Lz4 => lz4::decompress(data),
+            #[cfg(feature = "lz48")]
+            CompressMethod::Lz48 => lz48::lz48_decode(data),
+            #[cfg(feature = "lz49")]
+            CompressMethod::Lz49 => lz49::lz49_decode(data),
+            #[cfg(feature = "lzsa")]
+            CompressMethod::Lzsa(version, _minmatch) => {
+                lzsa::decompress(data, *version)
+                    .map_err(|e| CrunchersError::DecompressionFailed(format!("{e:?}")))
+            },
+            #[cfg(all(feature = "shrinkler", not(target_arch = "wasm32")))]
+            CompressMethod::Shrinkler(_conf) => shrinkler::decompress(data),
+            #[cfg(all(feature = "pucrunch", not(target_arch = "wasm32")))]
+            CompressMethod::Pucrunch => {
+                Err(CrunchersError::DecompressionFailed(
+                    "a pucrunch stream without header cannot be decompressed".to_owned()
+                ))
+            },
+            #[cfg(all(feature = "pucrunch", not(target_arch = "wasm32")))]
+            CompressMethod::PucrunchWithHeader => pucrunch::decompress(data),
+            #[cfg(all(feature = "upkr", not(target_arch = "wasm32")))]
+            CompressMethod::Upkr => {
+                upkr::unpack(data, &upkr_config(), usize::MAX)
+                    .map_err(|e| CrunchersError::DecompressionFailed(e.to_string()))
+            },
+            #[cfg(all(feature = "zx0", not(target_arch = "wasm32")))]
+            CompressMethod::Zx0 => zx0::decompress(data),
+            #[cfg(all(feature = "zx0", not(target_arch = "wasm32")))]
+            CompressMethod::BackwardZx0 => zx0::decompress_backward(data),
+
+            #[cfg(all(feature = "zx7", not(target_arch = "wasm32")))]
+            CompressMethod::Zx7 => zx7::decompress(data),
+            #[cfg(all(feature = "bzpack", not(target_arch = "wasm32")))]
+            CompressMethod::Lzm => bzpack::decompress(data, bzpack::BzpackFormat::Lzm),
+            #[cfg(all(feature = "bzpack", not(target_arch = "wasm32")))]
+            CompressMethod::BackwardLzm => {
+                bzpack::decompress_backward(data, bzpack::BzpackFormat::Lzm)
+            },
+            #[cfg(all(feature = "bzpack", not(target_arch = "wasm32")))]
+            CompressMethod::Ef8 => bzpack::decompress(data, bzpack::BzpackFormat::Ef8),
+            #[cfg(all(feature = "bzpack", not(target_arch = "wasm32")))]
+            CompressMethod::BackwardEf8 => {
+                bzpack::decompress_backward(data, bzpack::BzpackFormat::Ef8)
+            },
+            #[cfg(all(feature = "bzpack", not(target_arch = "wasm32")))]
+            CompressMethod::Bx0 => bzpack::decompress(data, bzpack::BzpackFormat::Bx0),
+            #[cfg(all(feature = "bzpack", not(target_arch = "wasm32")))]
+            CompressMethod::BackwardBx0 => {
+                bzpack::decompress_backward(data, bzpack::BzpackFormat::Bx0)
+            },
+            #[cfg(all(feature = "bzpack", not(target_arch = "wasm32")))]
+            CompressMethod::Bx2 => bzpack::decompress(data, bzpack::BzpackFormat::Bx2),
+            #[cfg(all(feature = "bzpack", not(target_arch = "wasm32")))]
+            CompressMethod::BackwardBx2 => {
+                bzpack::decompress_backward(data, bzpack::BzpackFormat::Bx2)
+            },
+        }
+    }
 }
@@ -1,3 +1,6 @@
+use crate::CrunchersError;
+use crate::decrunch::InputStream;
+
 unsafe extern "C" {
     fn LZ4_embedded_crunch(
         input_data: *const libc::c_uchar,
@@ -29,3 +32,46 @@ pub fn compress(data: &[u8]) -> Vec<u8> {
 
     crunched
 }
+
+fn decode_extended_length(input: &mut InputStream) -> Result<usize, CrunchersError> {
+    let mut length = 0;
+    loop {
+        let byte = input.read_byte()?;
+        length += byte as usize;
+        if byte != 0xFF {
+            return Ok(length);
+        }
+    }
+}
+
+/// Decompress a raw LZ4 block produced by [compress]
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, CrunchersError> {
+    let mut input = InputStream::new(data, "LZ4");
+    let mut out = Vec::with_capacity(data.len() * 2);
+
+    loop {
+        let token = input.read_byte()?;
+
+        let mut literals = (token >> 4) as usize;
+        if literals == 15 {
+            literals += decode_extended_length(&mut input)?;
+        }
+        for _ in 0..literals {
+            out.push(input.read_byte()?);
+        }
+
+        // the last sequence only contains literals
+        if input.is_empty() {
+            break;
+        }
+
+        let offset = input.read_word()? as usize;
+        let mut length = (token & 0xF) as usize;
+        if length == 15 {
+            length += decode_extended_length(&mut input)?;
+        }
+        input.copy_match(&mut out, offset, length + 4)?;
+    }
+
+    Ok(out)
+}
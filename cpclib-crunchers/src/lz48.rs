@@ -1,3 +1,6 @@
+use crate::CrunchersError;
+use crate::decrunch::InputStream;
+
 /// ! Dummy manual c to rust adaptation of lz48 cruncher of Roudoudou
 fn lz48_encode_extended_length(odata: &mut Vec<u8>, mut length: usize) {
     while length >= 255 {
@@ -134,3 +137,52 @@ pub fn lz48_encode_legacy(data: &[u8]) -> Vec<u8> {
 
     odata
 }
+
+fn lz48_decode_extended_length(input: &mut InputStream) -> Result<usize, CrunchersError> {
+    let mut length = 0;
+    loop {
+        let byte = input.read_byte()?;
+        length += byte as usize;
+        if byte != 0xFF {
+            return Ok(length);
+        }
+    }
+}
+
+/// Decompress a stream produced by [lz48_encode_legacy]
+pub fn lz48_decode(data: &[u8]) -> Result<Vec<u8>, CrunchersError> {
+    let mut input = InputStream::new(data, "LZ48");
+    let mut odata = Vec::with_capacity(data.len() * 2);
+
+    // first byte always literal
+    odata.push(input.read_byte()?);
+
+    loop {
+        let token = input.read_byte()?;
+
+        let mut literalcpt = (token >> 4) as usize;
+        if literalcpt == 15 {
+            literalcpt += lz48_decode_extended_length(&mut input)?;
+        }
+        for _i in 0..literalcpt {
+            odata.push(input.read_byte()?);
+        }
+
+        let mut length = (token & 0xF) as usize;
+        if length == 15 {
+            length = 18 + lz48_decode_extended_length(&mut input)?;
+        }
+        else {
+            length += 3;
+        }
+
+        // an offset of 0 marks the end of the stream
+        let offset = input.read_byte()?;
+        if offset == 0xFF {
+            break;
+        }
+        input.copy_match(&mut odata, offset as usize + 1, length)?;
+    }
+
+    Ok(odata)
+}
@@ -1,3 +1,6 @@
+use crate::CrunchersError;
+use crate::decrunch::InputStream;
+
 /// ! Dummy manual c to rust adaptation of lz49 cruncher of Roudoudou
 fn lz49_encode_extended_length(odata: &mut Vec<u8>, mut length: usize) {
     while length >= 255 {
@@ -153,3 +156,55 @@ pub fn lz49_encode_legacy(data: &[u8]) -> Vec<u8> {
     lz49_encode_block(&mut odata, data, literaloffset, literal, 0, 0);
     odata
 }
+
+fn lz49_decode_extended_length(input: &mut InputStream) -> Result<usize, CrunchersError> {
+    let mut length = 0;
+    loop {
+        let byte = input.read_byte()?;
+        length += byte as usize;
+        if byte != 0xFF {
+            return Ok(length);
+        }
+    }
+}
+
+/// Decompress a stream produced by [lz49_encode_legacy]
+pub fn lz49_decode(data: &[u8]) -> Result<Vec<u8>, CrunchersError> {
+    let mut input = InputStream::new(data, "LZ49");
+    let mut odata = Vec::with_capacity(data.len() * 2);
+
+    // first byte always literal
+    odata.push(input.read_byte()?);
+
+    loop {
+        let token = input.read_byte()?;
+
+        let mut literalcpt = ((token >> 4) & 7) as usize;
+        if literalcpt == 7 {
+            literalcpt += lz49_decode_extended_length(&mut input)?;
+        }
+        for _i in 0..literalcpt {
+            odata.push(input.read_byte()?);
+        }
+
+        let mut length = (token & 0xF) as usize;
+        if length == 15 {
+            length = 18 + lz49_decode_extended_length(&mut input)?;
+        }
+        else {
+            length += 3;
+        }
+
+        // bit 7 of the token selects the second half of the 512 bytes window
+        let offset = input.read_byte()?;
+        let offset = match (token & 0x80 != 0, offset) {
+            (false, 0xFF) => break,
+            (true, 0xFF) => 256,
+            (false, offset) => offset as usize + 1,
+            (true, offset) => offset as usize + 1 + 256
+        };
+        input.copy_match(&mut odata, offset, length)?;
+    }
+
+    Ok(odata)
+}
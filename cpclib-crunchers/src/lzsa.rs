@@ -1,3 +1,5 @@
+use crate::decrunch::InputStream;
+
 unsafe extern "C" {
     unsafe fn lzsa_compress_inmem(
         pInputData: *const libc::c_uchar,
@@ -37,7 +39,8 @@ pub enum LzsaMinMatch {
 
 #[derive(Debug)]
 pub enum LzsaError {
-    CompressionFailed
+    CompressionFailed,
+    DecompressionFailed(String)
 }
 
 pub fn compress(
@@ -80,3 +83,169 @@ pub fn compress(
         }
     }
 }
+
+/// Nibble reader shared by the LZSA2 length and offset codes
+struct Nibbles {
+    pending: Option<u8>
+}
+
+impl Nibbles {
+    fn read(&mut self, input: &mut InputStream) -> Result<usize, LzsaError> {
+        match self.pending.take() {
+            Some(byte) => Ok((byte & 0x0F) as usize),
+            None => {
+                let byte = input.read_byte().map_err(to_lzsa_error)?;
+                self.pending = Some(byte);
+                Ok((byte >> 4) as usize)
+            }
+        }
+    }
+}
+
+fn to_lzsa_error(e: crate::CrunchersError) -> LzsaError {
+    LzsaError::DecompressionFailed(e.to_string())
+}
+
+fn read_byte(input: &mut InputStream) -> Result<usize, LzsaError> {
+    input.read_byte().map(|b| b as usize).map_err(to_lzsa_error)
+}
+
+fn read_word(input: &mut InputStream) -> Result<usize, LzsaError> {
+    input.read_word().map(|w| w as usize).map_err(to_lzsa_error)
+}
+
+fn decompress_v1(data: &[u8]) -> Result<Vec<u8>, LzsaError> {
+    let mut input = InputStream::new(data, "LZSA1");
+    let mut out = Vec::with_capacity(data.len() * 2);
+
+    while !input.is_empty() {
+        let token = read_byte(&mut input)?;
+
+        let mut literals = (token & 0x70) >> 4;
+        if literals == 7 {
+            literals = match read_byte(&mut input)? {
+                250 => 256 + read_byte(&mut input)?,
+                249 => read_word(&mut input)?,
+                extra => 7 + extra
+            };
+        }
+        for _ in 0..literals {
+            out.push(read_byte(&mut input)? as u8);
+        }
+
+        // the last token in the block does not include match information
+        if input.remaining() < 2 {
+            break;
+        }
+
+        let mut offset = read_byte(&mut input)? ^ 0xFF;
+        if token & 0x80 != 0 {
+            offset |= (read_byte(&mut input)? << 8) ^ 0xFF00;
+        }
+        let offset = offset + 1;
+
+        let mut length = (token & 0x0F) + 3;
+        if length == 15 + 3 {
+            length = match read_byte(&mut input)? {
+                239 => 256 + read_byte(&mut input)?,
+                238 => read_word(&mut input)?,
+                extra => length + extra
+            };
+            if length == 0 {
+                break;
+            }
+        }
+
+        input
+            .copy_match(&mut out, offset, length)
+            .map_err(to_lzsa_error)?;
+    }
+
+    Ok(out)
+}
+
+fn decompress_v2(data: &[u8]) -> Result<Vec<u8>, LzsaError> {
+    fn read_length(
+        input: &mut InputStream,
+        nibbles: &mut Nibbles,
+        base: usize
+    ) -> Result<usize, LzsaError> {
+        let nibble = nibbles.read(input)?;
+        let mut length = base + nibble;
+        if nibble == 15 {
+            length += read_byte(input)?;
+            if length == 257 {
+                length = read_word(input)?;
+            }
+            else if length == 256 {
+                length = 0;
+            }
+        }
+        Ok(length)
+    }
+
+    let mut input = InputStream::new(data, "LZSA2");
+    let mut out = Vec::with_capacity(data.len() * 2);
+    let mut nibbles = Nibbles { pending: None };
+    let mut offset = 0;
+
+    while !input.is_empty() {
+        let token = read_byte(&mut input)?;
+
+        let mut literals = (token & 0x18) >> 3;
+        if literals == 3 {
+            literals = read_length(&mut input, &mut nibbles, 3)?;
+        }
+        for _ in 0..literals {
+            out.push(read_byte(&mut input)? as u8);
+        }
+
+        // the last token in the block does not include match information
+        if input.is_empty() {
+            break;
+        }
+
+        let z = (token & 0x20) >> 5;
+        match token & 0xC0 {
+            0x00 => {
+                offset = (((nibbles.read(&mut input)? << 1) | z) ^ 0x1E) + 1;
+            },
+            0x40 => {
+                offset = ((read_byte(&mut input)? | (z << 8)) ^ 0x0FF) + 1;
+            },
+            0x80 => {
+                let high = nibbles.read(&mut input)?;
+                offset = ((read_byte(&mut input)? | (high << 9) | (z << 8)) ^ 0x1EFF) + 512 + 1;
+            },
+            _ => {
+                // 111 keeps the previous offset
+                if z == 0 {
+                    let high = read_byte(&mut input)?;
+                    offset = (((high << 8) | read_byte(&mut input)?) ^ 0xFFFF) + 1;
+                }
+            }
+        }
+
+        let mut length = (token & 0x07) + 2;
+        if length == 7 + 2 {
+            length = read_length(&mut input, &mut nibbles, length)?;
+            if length == 0 {
+                break;
+            }
+        }
+
+        input
+            .copy_match(&mut out, offset, length)
+            .map_err(to_lzsa_error)?;
+    }
+
+    Ok(out)
+}
+
+/// Decompress a raw block produced by [compress] with the same version
+pub fn decompress(data: &[u8], version: LzsaVersion) -> Result<Vec<u8>, LzsaError> {
+    match version {
+        LzsaVersion::V1 => decompress_v1(data),
+        LzsaVersion::V2 => decompress_v2(data)
+    }
+}
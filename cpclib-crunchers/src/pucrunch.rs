@@ -1,3 +1,4 @@
+use crate::decrunch::InputStream;
 use crate::{CompressionResult, CrunchersError};
 
 unsafe extern "C" {
@@ -5,11 +6,22 @@ unsafe extern "C" {
         input: *const u8,
         input_len: usize,
         output: *mut u8,
-        output_len: *mut usize
+        output_len: *mut usize,
+        with_header: i32
     ) -> i32;
 }
 
+/// Compress without header: the decompression parameters are not stored in the stream
 pub fn compress(data: &[u8]) -> Result<CompressionResult, CrunchersError> {
+    compress_impl(data, false)
+}
+
+/// Compress with the header expected by the z80 decompressor and by [decompress]
+pub fn compress_with_header(data: &[u8]) -> Result<CompressionResult, CrunchersError> {
+    compress_impl(data, true)
+}
+
+fn compress_impl(data: &[u8], with_header: bool) -> Result<CompressionResult, CrunchersError> {
     // Output buffer: input size + 256 (header worst case)
     let mut out = vec![0u8; data.len() + 256];
     let mut out_len: usize = 0;
@@ -18,7 +30,8 @@ pub fn compress(data: &[u8]) -> Result<CompressionResult, CrunchersError> {
             data.as_ptr(),
             data.len(),
             out.as_mut_ptr(),
-            &mut out_len as *mut usize
+            &mut out_len as *mut usize,
+            with_header as i32
         )
     };
     if res == 0 {
@@ -32,3 +45,140 @@ pub fn compress(data: &[u8]) -> Result<CompressionResult, CrunchersError> {
         Err(CrunchersError::CompressionFailed)
     }
 }
+
+/// Pucrunch bit reader: bits are read msb first from a pure bit stream
+struct BitReader<'a> {
+    input: InputStream<'a>,
+    bits: u8,
+    mask: u8,
+    max_gamma: u32
+}
+
+impl BitReader<'_> {
+    fn read_bits(&mut self, count: u32) -> Result<usize, CrunchersError> {
+        let mut value = 0;
+        for _ in 0..count {
+            if self.mask == 0 {
+                self.bits = self.input.read_byte()?;
+                self.mask = 0x80;
+            }
+            value = (value << 1) | (self.bits & self.mask != 0) as usize;
+            self.mask >>= 1;
+        }
+        Ok(value)
+    }
+
+    fn read_value(&mut self) -> Result<usize, CrunchersError> {
+        let mut i = 0;
+        while i < self.max_gamma && self.read_bits(1)? == 1 {
+            i += 1;
+        }
+        Ok((1 << i) | self.read_bits(i)?)
+    }
+}
+
+/// Decompress a stream produced by [compress_with_header]
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, CrunchersError> {
+    let mut input = InputStream::new(data, "Pucrunch");
+
+    // header
+    let _inpos = input.read_word()?;
+    if input.read_byte()? != b'p' || input.read_byte()? != b'u' {
+        return Err(input.error("missing pucrunch header"));
+    }
+    let _end_addr = input.read_word()?;
+    let mut start_esc = input.read_byte()? as usize;
+    let _start = input.read_word()?;
+    let esc_bits = input.read_byte()? as u32;
+    if esc_bits > 8 {
+        return Err(input.error("invalid escape bits"));
+    }
+    let max_gamma = (input.read_byte()? as u32).wrapping_sub(1);
+    if !(5..=7).contains(&max_gamma) || input.read_byte()? as usize != 1 << max_gamma {
+        return Err(input.error("invalid max gamma"));
+    }
+    let extra_lz_pos_bits = input.read_byte()? as u32;
+    if extra_lz_pos_bits > 4 {
+        return Err(input.error("invalid LZPOS bits"));
+    }
+    let _exec = input.read_word()?;
+    let rle_used = input.read_byte()? as usize;
+    if rle_used > 15 {
+        return Err(input.error("invalid RLE table"));
+    }
+    let mut rle_values = vec![0; rle_used + 1];
+    for value in rle_values.iter_mut().skip(1) {
+        *value = input.read_byte()?;
+    }
+
+    let mut reader = BitReader {
+        input,
+        bits: 0,
+        mask: 0,
+        max_gamma
+    };
+    let mut out = Vec::with_capacity(data.len() * 2);
+
+    loop {
+        let sel = if esc_bits > 0 {
+            reader.read_bits(esc_bits)?
+        }
+        else {
+            start_esc
+        };
+
+        if sel != start_esc {
+            let byte = (sel << (8 - esc_bits)) | reader.read_bits(8 - esc_bits)?;
+            out.push(byte as u8);
+            continue;
+        }
+
+        let lz_len = reader.read_value()?;
+        let lz_pos = if lz_len != 1 {
+            let lz_pos_hi = reader.read_value()? - 1;
+            if lz_pos_hi == (2 << max_gamma) - 2 {
+                break;
+            }
+            let lz_pos_hi =
+                (lz_pos_hi << extra_lz_pos_bits) | reader.read_bits(extra_lz_pos_bits)?;
+            let lz_pos_lo = reader.read_bits(8)? ^ 0xFF;
+            (lz_pos_hi << 8) | lz_pos_lo
+        }
+        else if reader.read_bits(1)? == 0 {
+            // 2 bytes long match
+            reader.read_bits(8)? ^ 0xFF
+        }
+        else if reader.read_bits(1)? == 0 {
+            // escaped byte: the escape code changes
+            let new_esc = reader.read_bits(esc_bits)?;
+            let byte = (start_esc << (8 - esc_bits)) | reader.read_bits(8 - esc_bits)?;
+            out.push(byte as u8);
+            start_esc = new_esc;
+            continue;
+        }
+        else {
+            // run length encoding
+            let mut rle_len = reader.read_value()?;
+            if rle_len >= (1 << max_gamma) {
+                rle_len = ((rle_len - (1 << max_gamma)) << (8 - max_gamma))
+                    | reader.read_bits(8 - max_gamma)?;
+                rle_len |= (reader.read_value()? - 1) << 8;
+            }
+            let byte_code = reader.read_value()?;
+            let byte = if byte_code < 16 {
+                *rle_values
+                    .get(byte_code)
+                    .ok_or_else(|| reader.input.error("invalid RLE byte code"))?
+            }
+            else {
+                (((byte_code - 16) << 4) | reader.read_bits(4)?) as u8
+            };
+            out.extend(std::iter::repeat_n(byte, rle_len + 1));
+            continue;
+        };
+
+        reader.input.copy_match(&mut out, lz_pos + 1, lz_len + 1)?;
+    }
+
+    Ok(out)
+}
@@ -1,3 +1,5 @@
+use crate::CrunchersError;
+
 #[cxx::bridge]
 mod ffi {
     unsafe extern "C++" {
@@ -26,3 +28,134 @@ impl ShrinklerConfiguration {
         ffi::compress_for_basm(data, self.iterations as i32, self.log)
     }
 }
+
+const ADJUST_SHIFT: u32 = 4;
+const NUM_SINGLE_CONTEXTS: usize = 1;
+const NUM_CONTEXT_GROUPS: usize = 4;
+const CONTEXT_GROUP_SIZE: usize = 256;
+const CONTEXT_KIND: usize = 0;
+const CONTEXT_REPEATED: usize = 0;
+const CONTEXT_GROUP_OFFSET: usize = 2;
+const CONTEXT_GROUP_LENGTH: usize = 3;
+
+/// Port of the range decoder of Shrinkler (without parity context).
+/// The compressed stream is made of big endian longwords, so bits are simply read msb first.
+struct RangeDecoder<'a> {
+    data: &'a [u8],
+    contexts: Vec<u16>,
+    bit_index: usize,
+    interval_size: u32,
+    interval_value: u32
+}
+
+impl<'a> RangeDecoder<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            contexts: vec![0x8000; NUM_SINGLE_CONTEXTS + NUM_CONTEXT_GROUPS * CONTEXT_GROUP_SIZE],
+            bit_index: 0,
+            interval_size: 1,
+            interval_value: 0
+        }
+    }
+
+    fn get_bit(&mut self) -> u32 {
+        // bits after the end of the stream are 0
+        let bit = self
+            .data
+            .get(self.bit_index >> 3)
+            .map(|byte| ((byte >> (7 - (self.bit_index & 7))) & 1) as u32)
+            .unwrap_or(0);
+        self.bit_index += 1;
+        bit
+    }
+
+    fn decode(&mut self, context_index: usize) -> Result<usize, CrunchersError> {
+        let prob = *self.contexts.get(context_index).ok_or_else(|| {
+            CrunchersError::DecompressionFailed("Shrinkler stream: invalid context".to_owned())
+        })? as u32;
+
+        while self.interval_size < 0x8000 {
+            self.interval_size <<= 1;
+            self.interval_value = (self.interval_value << 1) | self.get_bit();
+        }
+
+        let threshold = (self.interval_size * prob) >> 16;
+        let (bit, new_prob) = if self.interval_value >= threshold {
+            self.interval_value -= threshold;
+            self.interval_size -= threshold;
+            (0, prob - (prob >> ADJUST_SHIFT))
+        }
+        else {
+            self.interval_size = threshold;
+            (1, prob + (0xFFFF >> ADJUST_SHIFT) - (prob >> ADJUST_SHIFT))
+        };
+        self.contexts[context_index] = new_prob as u16;
+
+        Ok(bit)
+    }
+
+    fn decode_number(&mut self, context_group: usize) -> Result<usize, CrunchersError> {
+        let base_context = NUM_SINGLE_CONTEXTS + (context_group << 8);
+
+        let mut i = 0;
+        while self.decode(base_context + i * 2 + 2)? != 0 {
+            i += 1;
+            if i > 30 {
+                return Err(CrunchersError::DecompressionFailed(
+                    "Shrinkler stream: invalid number".to_owned()
+                ));
+            }
+        }
+
+        let mut number = 1;
+        for i in (0..=i).rev() {
+            number = (number << 1) | self.decode(base_context + i * 2 + 1)?;
+        }
+        Ok(number)
+    }
+}
+
+/// Decompress a stream produced by [ShrinklerConfiguration::compress]
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, CrunchersError> {
+    let mut decoder = RangeDecoder::new(data);
+    let mut out = Vec::with_capacity(data.len() * 3);
+
+    let mut is_ref = false;
+    let mut prev_was_ref = false;
+    let mut offset = 0;
+    loop {
+        if is_ref {
+            let repeated = !prev_was_ref && decoder.decode(CONTEXT_REPEATED)? == 1;
+            if !repeated {
+                offset = decoder.decode_number(CONTEXT_GROUP_OFFSET)? - 2;
+                if offset == 0 {
+                    break;
+                }
+            }
+            let length = decoder.decode_number(CONTEXT_GROUP_LENGTH)?;
+            if offset > out.len() {
+                return Err(CrunchersError::DecompressionFailed(format!(
+                    "Shrinkler stream: offset {offset} is out of the {} already decompressed bytes",
+                    out.len()
+                )));
+            }
+            let start = out.len() - offset;
+            for idx in 0..length {
+                out.push(out[start + idx]);
+            }
+            prev_was_ref = true;
+        }
+        else {
+            let mut context = 1;
+            for _ in 0..8 {
+                context = (context << 1) | decoder.decode(NUM_SINGLE_CONTEXTS + context)?;
+            }
+            out.push(context as u8);
+            prev_was_ref = false;
+        }
+        is_ref = decoder.decode(NUM_SINGLE_CONTEXTS + CONTEXT_KIND)? == 1;
+    }
+
+    Ok(out)
+}
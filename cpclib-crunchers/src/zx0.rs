@@ -1,6 +1,7 @@
 use ::zx0;
 
-use crate::CompressionResult;
+use crate::decrunch::InputStream;
+use crate::{CompressionResult, CrunchersError};
 
 impl From<zx0::CompressionResult> for CompressionResult {
     fn from(value: zx0::CompressionResult) -> Self {
@@ -36,3 +37,128 @@ pub fn compress_backward(data: &[u8]) -> CompressionResult {
     result.output.reverse();
     result.into()
 }
+
+/// ZX0 bit reader.
+/// The first bit of the length following a new offset is stored in the offset byte
+struct BitReader<'a> {
+    input: InputStream<'a>,
+    bits: u8,
+    mask: u8,
+    backtrack: Option<usize>,
+    backwards: bool
+}
+
+impl BitReader<'_> {
+    fn read_bit(&mut self) -> Result<usize, CrunchersError> {
+        if let Some(bit) = self.backtrack.take() {
+            return Ok(bit);
+        }
+        if self.mask == 0 {
+            self.bits = self.input.read_byte()?;
+            self.mask = 0x80;
+        }
+        let bit = (self.bits & self.mask != 0) as usize;
+        self.mask >>= 1;
+        Ok(bit)
+    }
+
+    /// Read an interlaced elias gamma value
+    fn read_gamma(&mut self, inverted: bool) -> Result<usize, CrunchersError> {
+        let stop = if self.backwards { 0 } else { 1 };
+        let mut value = 1usize;
+        while self.read_bit()? != stop {
+            value = (value << 1) | (self.read_bit()? ^ inverted as usize);
+            if value > 0x1_0000 {
+                return Err(self.input.error("invalid elias gamma value"));
+            }
+        }
+        Ok(value)
+    }
+}
+
+fn decompress_inner(data: &[u8], backwards: bool) -> Result<Vec<u8>, CrunchersError> {
+    enum State {
+        Literals,
+        RepMatch,
+        NewOffset
+    }
+
+    let mut reader = BitReader {
+        input: InputStream::new(data, "ZX0"),
+        bits: 0,
+        mask: 0,
+        backtrack: None,
+        backwards
+    };
+    let invert_mode = !backwards;
+    let mut out = Vec::with_capacity(data.len() * 2);
+    let mut last_offset = 1;
+
+    // the indicator of the very first literals is not stored
+    let mut state = State::Literals;
+    loop {
+        state = match state {
+            State::Literals => {
+                let length = reader.read_gamma(false)?;
+                for _ in 0..length {
+                    out.push(reader.input.read_byte()?);
+                }
+                if reader.read_bit()? == 0 {
+                    State::RepMatch
+                }
+                else {
+                    State::NewOffset
+                }
+            },
+            State::RepMatch => {
+                let length = reader.read_gamma(false)?;
+                reader.input.copy_match(&mut out, last_offset, length)?;
+                if reader.read_bit()? == 0 {
+                    State::Literals
+                }
+                else {
+                    State::NewOffset
+                }
+            },
+            State::NewOffset => {
+                let msb = reader.read_gamma(invert_mode)?;
+                if msb == 256 {
+                    break;
+                }
+                let byte = reader.input.read_byte()? as usize;
+                last_offset = if backwards {
+                    (msb - 1) * 128 + (byte >> 1) + 1
+                }
+                else {
+                    (msb * 128)
+                        .checked_sub(byte >> 1)
+                        .ok_or_else(|| reader.input.error("invalid offset"))?
+                };
+                reader.backtrack = Some(byte & 1);
+                let length = reader.read_gamma(false)? + 1;
+                reader.input.copy_match(&mut out, last_offset, length)?;
+                if reader.read_bit()? == 0 {
+                    State::Literals
+                }
+                else {
+                    State::NewOffset
+                }
+            }
+        };
+    }
+
+    Ok(out)
+}
+
+/// Decompress a stream produced by [compress]
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, CrunchersError> {
+    decompress_inner(data, false)
+}
+
+/// Decompress a stream produced by [compress_backward]
+pub fn decompress_backward(data: &[u8]) -> Result<Vec<u8>, CrunchersError> {
+    let data = data.iter().rev().cloned().collect::<Vec<u8>>();
+    let mut result = decompress_inner(&data, true)?;
+    result.reverse();
+    Ok(result)
+}
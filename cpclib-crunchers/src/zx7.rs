@@ -1,3 +1,6 @@
+use crate::CrunchersError;
+use crate::decrunch::InputStream;
+
 unsafe extern "C" {
 
     unsafe fn zx7_optimize(
@@ -40,3 +43,72 @@ pub fn compress(data: &[u8]) -> Vec<u8> {
         crunched
     }
 }
+
+struct BitReader<'a> {
+    input: InputStream<'a>,
+    bits: u8,
+    mask: u8
+}
+
+impl BitReader<'_> {
+    fn read_bit(&mut self) -> Result<usize, CrunchersError> {
+        if self.mask == 0 {
+            self.bits = self.input.read_byte()?;
+            self.mask = 0x80;
+        }
+        let bit = (self.bits & self.mask != 0) as usize;
+        self.mask >>= 1;
+        Ok(bit)
+    }
+}
+
+/// Decompress a stream produced by [compress]
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, CrunchersError> {
+    let mut reader = BitReader {
+        input: InputStream::new(data, "ZX7"),
+        bits: 0,
+        mask: 0
+    };
+    let mut out = Vec::with_capacity(data.len() * 2);
+
+    // first byte is always literal
+    out.push(reader.input.read_byte()?);
+
+    loop {
+        if reader.read_bit()? == 0 {
+            out.push(reader.input.read_byte()?);
+            continue;
+        }
+
+        // sequence length coded with elias gamma; 16 leading zeros mark the end
+        let mut zeros = 0;
+        while reader.read_bit()? == 0 {
+            zeros += 1;
+        }
+        if zeros >= 16 {
+            break;
+        }
+        let mut length = 1;
+        for _ in 0..zeros {
+            length = (length << 1) | reader.read_bit()?;
+        }
+        let length = length + 1;
+
+        // sequence offset on 7 or 11 bits
+        let byte = reader.input.read_byte()? as usize;
+        let offset = if byte & 0x80 == 0 {
+            byte + 1
+        }
+        else {
+            let mut high = 0;
+            for _ in 0..4 {
+                high = (high << 1) | reader.read_bit()?;
+            }
+            ((byte & 0x7F) | (high << 7)) + 128 + 1
+        };
+
+        reader.input.copy_match(&mut out, offset, length)?;
+    }
+
+    Ok(out)
+}
@@ -6,10 +6,44 @@ use cpclib_crunchers::shrinkler::ShrinklerConfiguration;
 
 static DATA_TO_CRUNCH: &[u8] = "AAAAAAAAAAAAAAAAABNBNBNBNBAAAAAAAAACVCBCBCA".as_bytes();
 
+/// Build a buffer mixing runs, repeated sequences at various distances and noise
+fn data_to_roundtrip() -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut seed: u32 = 0x1234_5678;
+    let mut random = move || {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (seed >> 16) as u8
+    };
+
+    for round in 0..4 {
+        data.extend(std::iter::repeat_n(round as u8, 300 + round * 7));
+        for _ in 0..200 {
+            data.push(random());
+        }
+        data.extend_from_slice(DATA_TO_CRUNCH);
+        let far = data.len() / 3;
+        let repeated = data[far..far + 280].to_vec();
+        data.extend(repeated);
+        for i in 0..100 {
+            data.push((i % 5) as u8 * 3);
+        }
+    }
+    data
+}
+
 fn crunch_any(method: CompressMethod) {
     let res = method.compress(DATA_TO_CRUNCH).unwrap();
     dbg!(res.len(), DATA_TO_CRUNCH.len());
     assert!(res.len() < DATA_TO_CRUNCH.len());
+
+    let decompressed = method.decompress(&res).unwrap();
+    assert_eq!(DATA_TO_CRUNCH, decompressed.as_slice());
+
+    let data = data_to_roundtrip();
+    let res = method.compress(&data).unwrap();
+    let decompressed = method.decompress(&res).unwrap();
+    assert_eq!(data.len(), decompressed.len());
+    assert!(data == decompressed);
 }
 
 #[test]
@@ -40,7 +74,18 @@ fn crunch_zx7() {
 #[test]
 #[cfg(feature = "pucrunch")]
 fn crunch_pucrunch() {
-    crunch_any(CompressMethod::Pucrunch);
+    // the default stream has no header: it is the one of the header variant without it
+    let res = CompressMethod::Pucrunch.compress(DATA_TO_CRUNCH).unwrap();
+    assert!(res.len() < DATA_TO_CRUNCH.len());
+    let with_header = CompressMethod::PucrunchWithHeader
+        .compress(DATA_TO_CRUNCH)
+        .unwrap();
+    assert_eq!(&with_header[2..4], b"pu");
+    assert_eq!(with_header.len(), 16 + with_header[15] as usize + res.len());
+    assert!(with_header.ends_with(&res));
+    assert!(CompressMethod::Pucrunch.decompress(&res).is_err());
+
+    crunch_any(CompressMethod::PucrunchWithHeader);
 }
 
 #[test]
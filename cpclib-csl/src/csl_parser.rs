@@ -591,17 +591,22 @@ fn parse_screenshot_dir<'a>(input: &mut LocatingSlice<&'a str>) -> ParseResult<'
 
 /// Parse screenshot instruction
 fn parse_screenshot<'a>(input: &mut LocatingSlice<&'a str>) -> ParseResult<'a, CslInstruction> {
-    ("screenshot", ws0)
+    "screenshot"
         .context(StrContext::Label("screenshot"))
         .parse_next(input)?;
 
-    cut_err(opt(preceded(ws1, "vsync")).context(StrContext::Label("optional 'vsync' flag")))
-        .map(|vsync| {
-            CslInstruction::Screenshot {
-                wait_vsync: vsync.is_some()
-            }
-        })
-        .parse_next(input)
+    // the flag (written `V` by the generator) needs the separating space,
+    // so trailing spaces are only consumed afterwards
+    cut_err(
+        terminated(opt(preceded(ws1, alt(("vsync", "V")))), ws0)
+            .context(StrContext::Label("optional 'vsync' flag"))
+    )
+    .map(|vsync| {
+        CslInstruction::Screenshot {
+            wait_vsync: vsync.is_some()
+        }
+    })
+    .parse_next(input)
 }
 
 /// Parse snapshot_name instruction
@@ -617,17 +622,22 @@ fn parse_snapshot_name<'a>(input: &mut LocatingSlice<&'a str>) -> ParseResult<'a
 
 /// Parse snapshot instruction
 fn parse_snapshot<'a>(input: &mut LocatingSlice<&'a str>) -> ParseResult<'a, CslInstruction> {
-    ("snapshot", ws0)
+    "snapshot"
         .context(StrContext::Label("snapshot"))
         .parse_next(input)?;
 
-    cut_err(opt(preceded(ws1, "vsync")).context(StrContext::Label("optional 'vsync' flag")))
-        .map(|vsync| {
-            CslInstruction::Snapshot {
-                wait_vsync: vsync.is_some()
-            }
-        })
-        .parse_next(input)
+    // the flag (written `V` by the generator) needs the separating space,
+    // so trailing spaces are only consumed afterwards
+    cut_err(
+        terminated(opt(preceded(ws1, alt(("vsync", "V")))), ws0)
+            .context(StrContext::Label("optional 'vsync' flag"))
+    )
+    .map(|vsync| {
+        CslInstruction::Snapshot {
+            wait_vsync: vsync.is_some()
+        }
+    })
+    .parse_next(input)
 }
 
 /// Parse snapshot version
@@ -946,6 +956,25 @@ mod tests {
         assert_eq!(result.unwrap(), CslInstruction::Reset(ResetType::Hard));
     }
 
+    #[test]
+    fn test_parse_screenshot_and_snapshot_vsync() {
+        let result = parse_test(parse_line, "screenshot vsync\n");
+        assert_eq!(
+            result.unwrap(),
+            CslInstruction::Screenshot { wait_vsync: true }
+        );
+
+        let result = parse_test(parse_line, "snapshot \n");
+        assert_eq!(
+            result.unwrap(),
+            CslInstruction::Snapshot { wait_vsync: false }
+        );
+
+        let instruction = CslInstruction::Snapshot { wait_vsync: true };
+        let result = parse_test(parse_line, &format!("{instruction}\n"));
+        assert_eq!(result.unwrap(), instruction);
+    }
+
     #[test]
     fn test_parse_disk_insert() {
         let result = parse_test(parse_line, "disk_insert 'SHAKER25.DSK'\n");
@@ -0,0 +1,570 @@
+//! Execution of CSL scripts
+//!
+//! The interpreter handles everything that only concerns the script itself
+//! (directories, names of the exported files, key delays, included scripts)
+//! and delegates the actions on the machine to a [`CslBackend`].
+//! A backend only has to implement the instructions it is able to honor:
+//! the others fail with an explicit error instead of being silently ignored.
+
+use cpclib_common::camino::{Utf8Path, Utf8PathBuf};
+
+use crate::csl::{
+    CpcModel, CrtcModel, CslInstruction, CslScript, Drive, GateArrayModel, KeyElement, KeyOutput,
+    MemoryExpansion, ResetType, RomType, SnapshotVersion, SpecialKey
+};
+use crate::parse_csl_with_rich_errors;
+
+/// Default time (in microseconds) a key is held down
+pub const DEFAULT_PRESS_DELAY: u64 = 40_000;
+/// Default time (in microseconds) waited after the release of a key
+pub const DEFAULT_DELAY_AFTER_KEY: u64 = 40_000;
+/// Default time (in microseconds) waited after the release of RETURN or ENTER
+pub const DEFAULT_DELAY_AFTER_CR: u64 = 200_000;
+
+/// Maximum depth of `csl_load` to protect against scripts that load themselves
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+fn unsupported(instruction: &str) -> String {
+    format!("{instruction} is not supported by this emulator")
+}
+
+/// Actions a CSL script can request from an emulator.
+///
+/// Durations are expressed in microseconds of emulated time.
+/// Paths are already resolved against the directories selected by the script.
+#[allow(unused_variables)]
+pub trait CslBackend {
+    fn reset(&mut self, kind: ResetType) -> Result<(), String>;
+
+    fn load_snapshot(&mut self, fname: &Utf8Path) -> Result<(), String>;
+
+    fn save_snapshot(&mut self, fname: &Utf8Path, version: SnapshotVersion) -> Result<(), String>;
+
+    /// Save a PNG picture of the screen
+    fn save_screenshot(&mut self, fname: &Utf8Path) -> Result<(), String>;
+
+    /// Press the keys. They are either [`KeyElement::Character`] or [`KeyElement::Special`]
+    fn press_keys(&mut self, keys: &[KeyElement]) -> Result<(), String>;
+
+    /// Release the keys previously pressed with [`CslBackend::press_keys`]
+    fn release_keys(&mut self, keys: &[KeyElement]) -> Result<(), String>;
+
+    /// Replace the whole keyboard matrix. A bit set to 0 is a pressed key
+    fn write_keyboard(&mut self, lines: &[u8; 10]) -> Result<(), String>;
+
+    fn wait(&mut self, microseconds: u64) -> Result<(), String>;
+
+    fn wait_vsync_off_on(&mut self) -> Result<(), String>;
+
+    fn wait_drive_on_off(&mut self, count: u32) -> Result<(), String> {
+        Err(unsupported("wait_driveonoff"))
+    }
+
+    fn wait_ssm0000(&mut self) -> Result<(), String> {
+        Err(unsupported("wait_ssm0000"))
+    }
+
+    fn select_crtc(&mut self, model: CrtcModel) -> Result<(), String> {
+        Err(unsupported("crtc_select"))
+    }
+
+    fn select_gate_array(&mut self, model: GateArrayModel) -> Result<(), String> {
+        Err(unsupported("gate_array"))
+    }
+
+    fn select_cpc_model(&mut self, model: CpcModel) -> Result<(), String> {
+        Err(unsupported("cpc_model"))
+    }
+
+    fn select_memory_expansion(&mut self, expansion: MemoryExpansion) -> Result<(), String> {
+        Err(unsupported("memory_exp"))
+    }
+
+    fn configure_rom(&mut self, kind: &RomType, num: u8, fname: &Utf8Path) -> Result<(), String> {
+        Err(unsupported("rom_config"))
+    }
+
+    fn insert_disc(&mut self, drive: Drive, fname: &Utf8Path) -> Result<(), String> {
+        Err(unsupported("disk_insert"))
+    }
+
+    fn insert_tape(&mut self, fname: &Utf8Path) -> Result<(), String> {
+        Err(unsupported("tape_insert"))
+    }
+
+    fn play_tape(&mut self) -> Result<(), String> {
+        Err(unsupported("tape_play"))
+    }
+
+    fn stop_tape(&mut self) -> Result<(), String> {
+        Err(unsupported("tape_stop"))
+    }
+
+    fn rewind_tape(&mut self) -> Result<(), String> {
+        Err(unsupported("tape_rewind"))
+    }
+}
+
+/// Run CSL scripts on a backend
+#[derive(Debug)]
+pub struct CslInterpreter<B: CslBackend> {
+    backend: B,
+    /// Directory of the script in execution. Relative paths are resolved from it
+    script_dir: Utf8PathBuf,
+    /// Name of the script in execution, used to name the exported files
+    script_stem: String,
+    disk_dir: Option<Utf8PathBuf>,
+    tape_dir: Option<Utf8PathBuf>,
+    snapshot_dir: Option<Utf8PathBuf>,
+    screenshot_dir: Option<Utf8PathBuf>,
+    rom_dir: Option<Utf8PathBuf>,
+    snapshot_name: Option<Utf8PathBuf>,
+    screenshot_name: Option<Utf8PathBuf>,
+    snapshot_version: SnapshotVersion,
+    press_delay: u64,
+    delay_after_key: u64,
+    delay_after_cr: u64,
+    /// Number of snapshots and screenshots automatically named
+    exports: usize,
+    depth: usize
+}
+
+#[allow(missing_docs)]
+impl<B: CslBackend> CslInterpreter<B> {
+    pub fn new(backend: B) -> Self {
+        Self {
+            backend,
+            script_dir: Utf8PathBuf::from("."),
+            script_stem: "csl".to_owned(),
+            disk_dir: None,
+            tape_dir: None,
+            snapshot_dir: None,
+            screenshot_dir: None,
+            rom_dir: None,
+            snapshot_name: None,
+            screenshot_name: None,
+            snapshot_version: SnapshotVersion::V3,
+            press_delay: DEFAULT_PRESS_DELAY,
+            delay_after_key: DEFAULT_DELAY_AFTER_KEY,
+            delay_after_cr: DEFAULT_DELAY_AFTER_CR,
+            exports: 0,
+            depth: 0
+        }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    pub fn into_backend(self) -> B {
+        self.backend
+    }
+
+    /// Parse and execute the script stored in the file.
+    /// Its directory becomes the reference for the relative paths
+    pub fn run_file<P: AsRef<Utf8Path>>(&mut self, fname: P) -> Result<(), String> {
+        let fname = fname.as_ref();
+        let content = fs_err::read_to_string(fname)
+            .map_err(|e| format!("Unable to read the CSL script {fname}. {e}"))?;
+        let script = parse_csl_with_rich_errors(&content, Some(fname.to_string()))
+            .map_err(|e| e.to_string())?;
+
+        let dir = fname
+            .parent()
+            .filter(|p| !p.as_str().is_empty())
+            .unwrap_or(Utf8Path::new("."))
+            .to_owned();
+        let stem = fname.file_stem().unwrap_or("csl").to_owned();
+        let previous_dir = std::mem::replace(&mut self.script_dir, dir);
+        let previous_stem = std::mem::replace(&mut self.script_stem, stem);
+
+        let res = self.run_script(&script);
+
+        self.script_dir = previous_dir;
+        self.script_stem = previous_stem;
+        res
+    }
+
+    /// Execute all the instructions of the script and stop at the first failure
+    pub fn run_script(&mut self, script: &CslScript) -> Result<(), String> {
+        script.iter().try_for_each(|i| self.execute(i))
+    }
+
+    /// Execute a single instruction
+    pub fn execute(&mut self, instruction: &CslInstruction) -> Result<(), String> {
+        self.execute_inner(instruction)
+            .map_err(|e| format!("Error with `{instruction}`. {e}"))
+    }
+
+    fn execute_inner(&mut self, instruction: &CslInstruction) -> Result<(), String> {
+        match instruction {
+            CslInstruction::CslVersion(_) | CslInstruction::Comment(_) | CslInstruction::Empty => {
+                Ok(())
+            },
+            CslInstruction::InstructionWithComment(instruction, _) => {
+                self.execute_inner(instruction)
+            },
+
+            CslInstruction::Reset(kind) => self.backend.reset(*kind),
+            CslInstruction::CrtcSelect(model) => self.backend.select_crtc(*model),
+            CslInstruction::GateArray(model) => self.backend.select_gate_array(*model),
+            CslInstruction::CpcModel(model) => self.backend.select_cpc_model(*model),
+            CslInstruction::MemoryExp(expansion) => {
+                self.backend.select_memory_expansion(*expansion)
+            },
+            CslInstruction::RomDir(dir) => {
+                self.rom_dir = Some(self.resolve(None, dir));
+                Ok(())
+            },
+            CslInstruction::RomConfig(config) => {
+                let fname = self.resolve(self.rom_dir.as_deref(), &config.filename);
+                self.backend
+                    .configure_rom(&config.rom_type, config.num, &fname)
+            },
+
+            CslInstruction::DiskDir(dir) => {
+                self.disk_dir = Some(self.resolve(None, dir));
+                Ok(())
+            },
+            CslInstruction::DiskInsert { drive, filename } => {
+                let fname = self.resolve(self.disk_dir.as_deref(), filename);
+                self.backend.insert_disc(*drive, &fname)
+            },
+            CslInstruction::TapeDir(dir) => {
+                self.tape_dir = Some(self.resolve(None, dir));
+                Ok(())
+            },
+            CslInstruction::TapeInsert(filename) => {
+                let fname = self.resolve(self.tape_dir.as_deref(), filename);
+                self.backend.insert_tape(&fname)
+            },
+            CslInstruction::TapePlay => self.backend.play_tape(),
+            CslInstruction::TapeStop => self.backend.stop_tape(),
+            CslInstruction::TapeRewind => self.backend.rewind_tape(),
+
+            CslInstruction::SnapshotDir(dir) => {
+                self.snapshot_dir = Some(self.resolve(None, dir));
+                Ok(())
+            },
+            CslInstruction::SnapshotLoad(filename) => {
+                let fname = self.resolve(self.snapshot_dir.as_deref(), filename);
+                self.backend.load_snapshot(&fname)
+            },
+
+            CslInstruction::KeyDelay {
+                press_delay,
+                delay_after_key,
+                delay_after_cr
+            } => {
+                self.press_delay = *press_delay;
+                self.delay_after_key = delay_after_key.unwrap_or(*press_delay);
+                self.delay_after_cr = delay_after_cr.unwrap_or(self.delay_after_key);
+                Ok(())
+            },
+            CslInstruction::KeyOutput(output) => self.type_keys(output),
+            CslInstruction::KeyFromFile(filename) => {
+                let fname = self.resolve(None, filename);
+                let content = fs_err::read_to_string(&fname)
+                    .map_err(|e| format!("Unable to read {fname}. {e}"))?;
+                let output = KeyOutput::try_from(content.as_str())?;
+                self.type_keys(&output)
+            },
+            CslInstruction::KeyboardWrite(lines) => self.backend.write_keyboard(lines),
+
+            CslInstruction::Wait(microseconds) => self.backend.wait(*microseconds),
+            CslInstruction::WaitDriveOnOff(count) => self.backend.wait_drive_on_off(*count),
+            CslInstruction::WaitVsyncOffOn => self.backend.wait_vsync_off_on(),
+            CslInstruction::WaitSsm0000 => self.backend.wait_ssm0000(),
+
+            CslInstruction::ScreenshotDir(dir) => {
+                self.screenshot_dir = Some(self.resolve(None, dir));
+                Ok(())
+            },
+            CslInstruction::ScreenshotName(name) => {
+                self.screenshot_name = Some(name.clone());
+                Ok(())
+            },
+            CslInstruction::Screenshot { wait_vsync } => {
+                if *wait_vsync {
+                    self.backend.wait_vsync_off_on()?;
+                }
+                let name = self.screenshot_name.take();
+                let fname = self.export_fname(name, self.screenshot_dir.clone(), "png");
+                self.backend.save_screenshot(&fname)
+            },
+            CslInstruction::SnapshotName(name) => {
+                self.snapshot_name = Some(name.clone());
+                Ok(())
+            },
+            CslInstruction::SnapshotVersion(version) => {
+                self.snapshot_version = *version;
+                Ok(())
+            },
+            CslInstruction::Snapshot { wait_vsync } => {
+                if *wait_vsync {
+                    self.backend.wait_vsync_off_on()?;
+                }
+                let name = self.snapshot_name.take();
+                let fname = self.export_fname(name, self.snapshot_dir.clone(), "sna");
+                self.backend.save_snapshot(&fname, self.snapshot_version)
+            },
+
+            CslInstruction::CslLoad(filename) => {
+                if self.depth >= MAX_INCLUDE_DEPTH {
+                    return Err(format!(
+                        "Too many nested csl_load (more than {MAX_INCLUDE_DEPTH})"
+                    ));
+                }
+                let fname = self.resolve(None, filename);
+                self.depth += 1;
+                let res = self.run_file(fname);
+                self.depth -= 1;
+                res
+            }
+        }
+    }
+
+    /// Type each key of the output. The keys of a simultaneous group are pressed together
+    fn type_keys(&mut self, output: &KeyOutput) -> Result<(), String> {
+        let mut skip_delay = false;
+        for element in output.elements() {
+            let keys = match element {
+                KeyElement::Special(SpecialKey::NoDelayNextKey) => {
+                    skip_delay = true;
+                    continue;
+                },
+                KeyElement::Simultaneous(group) => flatten_keys(group),
+                key => vec![key.clone()]
+            };
+            if keys.is_empty() {
+                continue;
+            }
+
+            self.backend.press_keys(&keys)?;
+            self.backend.wait(self.press_delay)?;
+            self.backend.release_keys(&keys)?;
+
+            let is_cr = keys.iter().any(|k| {
+                matches!(
+                    k,
+                    KeyElement::Special(SpecialKey::Return | SpecialKey::Enter)
+                        | KeyElement::Character('\n' | '\r')
+                )
+            });
+            if !std::mem::take(&mut skip_delay) {
+                let delay = if is_cr {
+                    self.delay_after_cr
+                }
+                else {
+                    self.delay_after_key
+                };
+                self.backend.wait(delay)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Path of a file manipulated by the script.
+    /// Relative paths are searched in the directory selected by the script, or in the directory of the script
+    fn resolve(&self, dir: Option<&Utf8Path>, fname: &Utf8Path) -> Utf8PathBuf {
+        let fname = host_path(fname);
+        if fname.is_absolute() {
+            return fname;
+        }
+        match dir {
+            Some(dir) => dir.join(fname),
+            None => self.script_dir.join(fname)
+        }
+    }
+
+    /// Path of an exported file. Without name provided by the script, files are numbered after the script
+    fn export_fname(
+        &mut self,
+        name: Option<Utf8PathBuf>,
+        dir: Option<Utf8PathBuf>,
+        extension: &str
+    ) -> Utf8PathBuf {
+        let name = name.unwrap_or_else(|| {
+            self.exports += 1;
+            Utf8PathBuf::from(format!("{}-{:04}", self.script_stem, self.exports))
+        });
+        let mut fname = self.resolve(dir.as_deref(), &name);
+        fname.set_extension(extension);
+        fname
+    }
+}
+
+/// Keys of a simultaneous group, without the nested groups
+fn flatten_keys(group: &[KeyElement]) -> Vec<KeyElement> {
+    group
+        .iter()
+        .flat_map(|k| {
+            match k {
+                KeyElement::Simultaneous(inner) => flatten_keys(inner),
+                KeyElement::Special(SpecialKey::NoDelayNextKey) => Vec::new(),
+                k => vec![k.clone()]
+            }
+        })
+        .collect()
+}
+
+/// Scripts generated under Linux for Windows emulators reference the host filesystem through the `Z:` drive
+fn host_path(fname: &Utf8Path) -> Utf8PathBuf {
+    let fname = fname.as_str();
+    if cfg!(windows) {
+        return fname.into();
+    }
+    let fname = fname
+        .strip_prefix("Z:")
+        .or_else(|| fname.strip_prefix("z:"))
+        .unwrap_or(fname);
+    fname.replace('\\', "/").into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder {
+        actions: Vec<String>
+    }
+
+    impl CslBackend for Recorder {
+        fn reset(&mut self, kind: ResetType) -> Result<(), String> {
+            self.actions.push(format!("reset {kind}"));
+            Ok(())
+        }
+
+        fn load_snapshot(&mut self, fname: &Utf8Path) -> Result<(), String> {
+            self.actions.push(format!("load {fname}"));
+            Ok(())
+        }
+
+        fn save_snapshot(
+            &mut self,
+            fname: &Utf8Path,
+            version: SnapshotVersion
+        ) -> Result<(), String> {
+            self.actions.push(format!("snapshot {fname} v{version}"));
+            Ok(())
+        }
+
+        fn save_screenshot(&mut self, fname: &Utf8Path) -> Result<(), String> {
+            self.actions.push(format!("screenshot {fname}"));
+            Ok(())
+        }
+
+        fn press_keys(&mut self, keys: &[KeyElement]) -> Result<(), String> {
+            self.actions.push(format!(
+                "press {}",
+                keys.iter().map(|k| k.to_string()).collect::<String>()
+            ));
+            Ok(())
+        }
+
+        fn release_keys(&mut self, _keys: &[KeyElement]) -> Result<(), String> {
+            self.actions.push("release".to_owned());
+            Ok(())
+        }
+
+        fn write_keyboard(&mut self, _lines: &[u8; 10]) -> Result<(), String> {
+            self.actions.push("keyboard".to_owned());
+            Ok(())
+        }
+
+        fn wait(&mut self, microseconds: u64) -> Result<(), String> {
+            self.actions.push(format!("wait {microseconds}"));
+            Ok(())
+        }
+
+        fn wait_vsync_off_on(&mut self) -> Result<(), String> {
+            self.actions.push("vsync".to_owned());
+            Ok(())
+        }
+    }
+
+    fn run(source: &str) -> Result<Vec<String>, String> {
+        let script = parse_csl_with_rich_errors(source, None).map_err(|e| e.to_string())?;
+        let mut interpreter = CslInterpreter::new(Recorder::default());
+        interpreter.run_script(&script)?;
+        Ok(interpreter.into_backend().actions)
+    }
+
+    #[test]
+    fn directories_and_names() {
+        let actions = run("csl_version 1.1\n\
+            snapshot_dir 'snas'\n\
+            snapshot_load 'start.sna'\n\
+            screenshot_dir '/tmp/shots'\n\
+            screenshot_name 'title'\n\
+            screenshot\n\
+            screenshot vsync\n\
+            snapshot_version 2\n\
+            snapshot\n")
+        .unwrap();
+        assert_eq!(
+            actions,
+            vec![
+                "load ./snas/start.sna",
+                "screenshot /tmp/shots/title.png",
+                "vsync",
+                "screenshot /tmp/shots/csl-0001.png",
+                "snapshot ./snas/csl-0002.sna v2"
+            ]
+        );
+    }
+
+    #[test]
+    fn key_delays() {
+        let actions = run("key_delay 10 20 30\n\
+            key_output 'a\\(RET)'\n\
+            key_output '\\(KOF)b{\\(SHI)c}'\n")
+        .unwrap();
+        assert_eq!(
+            actions,
+            vec![
+                "press a",
+                "wait 10",
+                "release",
+                "wait 20",
+                "press \\(RET)",
+                "wait 10",
+                "release",
+                "wait 30",
+                "press b",
+                "wait 10",
+                "release",
+                "press \\(SHI)c",
+                "wait 10",
+                "release",
+                "wait 20"
+            ]
+        );
+    }
+
+    #[test]
+    fn unsupported_instructions_fail() {
+        let err = run("reset\ndisk_insert 'test.dsk'\n").unwrap_err();
+        assert!(err.contains("disk_insert"), "{err}");
+    }
+
+    #[test]
+    fn windows_paths() {
+        if cfg!(windows) {
+            return;
+        }
+        assert_eq!(
+            host_path(Utf8Path::new(r"Z:\home\user\test.sna")),
+            Utf8PathBuf::from("/home/user/test.sna")
+        );
+        assert_eq!(
+            host_path(Utf8Path::new(r"dir\test.sna")),
+            Utf8PathBuf::from("dir/test.sna")
+        );
+    }
+}
@@ -1,13 +1,15 @@
 //! CSL (CPC Script Language) parser and types
 //!
-//! This crate provides parsing and manipulation of CSL scripts for controlling
+//! This crate provides parsing, manipulation and execution of CSL scripts for controlling
 //! Amstrad CPC emulators.
 
 pub mod csl;
 pub mod csl_parser;
 pub mod error;
+pub mod interpreter;
 
 // Re-export commonly used types
 pub use csl::*;
 pub use csl_parser::{parse_csl_with_rich_errors, parse_instruction};
 pub use error::CslError;
+pub use interpreter::{CslBackend, CslInterpreter};
@@ -1,32 +1,79 @@
 use anyhow::{Context, Result};
-use clap::{CommandFactory, Parser};
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+use clap::{CommandFactory, Parser, Subcommand};
+use cpclib_common::camino::Utf8PathBuf;
 use cpclib_common::event::EventObserver;
-use cpclib_csl::parse_csl_with_rich_errors;
+use cpclib_csl::{CslInterpreter, parse_csl_with_rich_errors};
+use cpclib_runner::emucontrol::{Crtc, parse_upper_rom};
+use cpclib_runner::headless::HeadlessEmulator;
 
-/// CSL (CPC Script Language) parser and validator
+/// CSL (CPC Script Language) parser, validator and interpreter
 #[derive(Parser, Debug)]
 #[command(name = "csl")]
 #[command(version = env!("CARGO_PKG_VERSION"))]
 #[command(author = "Krusty/Benediction")]
-#[command(about = "CSL (CPC Script Language) parser and validator")]
+#[command(about = "CSL (CPC Script Language) parser, validator and interpreter")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct CslCliArgs {
+    #[command(subcommand)]
+    pub command: Option<CslCommand>,
+
     /// Path to the CSL file to parse
     #[arg(required = true, index = 1)]
-    pub file: String,
+    pub file: Option<String>,
 
     /// Enable verbose output
     #[arg(short, long)]
     pub verbose: bool
 }
 
+#[derive(Subcommand, Debug)]
+pub enum CslCommand {
+    /// Execute the script with the headless emulator
+    Run(CslRunArgs)
+}
+
+#[derive(Parser, Debug)]
+pub struct CslRunArgs {
+    /// Path to the CSL file to execute
+    #[arg(required = true)]
+    pub file: Utf8PathBuf,
+
+    /// CRTC used until the script selects one
+    #[arg(
+        long,
+        default_value = "0",
+        value_parser = PossibleValuesParser::new(["0", "1"]).map(|crtc| crtc.parse::<Crtc>().unwrap())
+    )]
+    pub crtc: Crtc,
+
+    /// Firmware ROM file
+    #[arg(long, value_name = "ROM")]
+    pub lower_rom: Option<Utf8PathBuf>,
+
+    /// Upper ROM file to plug in the given slot
+    #[arg(long, value_name = "SLOT=ROM", value_parser = parse_upper_rom)]
+    pub upper_rom: Vec<(u8, Utf8PathBuf)>,
+
+    /// Display the emulated time once the script is executed
+    #[arg(short, long)]
+    pub verbose: bool
+}
+
 /// Run the CSL CLI with the provided arguments
 pub fn run<O: EventObserver>(args: &CslCliArgs, o: &O) -> Result<()> {
+    if let Some(CslCommand::Run(args)) = &args.command {
+        return execute(args, o);
+    }
+
+    let file = args.file.as_ref().context("A CSL file is required")?;
+
     // Read the file
-    let content = fs_err::read_to_string(&args.file)
-        .context(format!("Failed to read file '{}'", args.file))?;
+    let content =
+        fs_err::read_to_string(file).context(format!("Failed to read file '{}'", file))?;
 
     // Parse the CSL file
-    let script = parse_csl_with_rich_errors(&content, Some(args.file.clone()))
+    let script = parse_csl_with_rich_errors(&content, Some(file.clone()))
         .map_err(|e| anyhow::anyhow!("{}", e))?;
 
     if args.verbose {
@@ -42,6 +89,31 @@ pub fn run<O: EventObserver>(args: &CslCliArgs, o: &O) -> Result<()> {
     Ok(())
 }
 
+/// Execute the script on the headless emulator
+fn execute<O: EventObserver>(args: &CslRunArgs, o: &O) -> Result<()> {
+    let mut emu = HeadlessEmulator::new(args.crtc);
+    if let Some(rom) = &args.lower_rom {
+        emu.load_rom(None, rom).map_err(anyhow::Error::msg)?;
+    }
+    for (slot, rom) in &args.upper_rom {
+        emu.load_rom(Some(*slot), rom).map_err(anyhow::Error::msg)?;
+    }
+
+    let mut interpreter = CslInterpreter::new(emu);
+    interpreter
+        .run_file(&args.file)
+        .map_err(anyhow::Error::msg)?;
+
+    if args.verbose {
+        o.emit_stderr(&format!(
+            "Script executed in {} nops of emulated time",
+            interpreter.backend().cpc().nops()
+        ));
+    }
+
+    Ok(())
+}
+
 pub fn build_command() -> clap::Command {
     <CslCliArgs as clap::CommandFactory>::command()
 }
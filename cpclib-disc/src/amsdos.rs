@@ -40,7 +40,15 @@ pub enum AmsdosError {
     FileAlreadyExists(String),
 
     #[error("File `{0}` not present in disc")]
-    FileDoesNotExist(String)
+    FileDoesNotExist(String),
+
+    #[error(
+        "The disc has {nb_tracks} tracks, not enough for its {reserved_tracks} reserved tracks"
+    )]
+    NotEnoughTracks {
+        nb_tracks: usize,
+        reserved_tracks: u8
+    }
 }
 
 impl From<std::io::Error> for AmsdosError {
@@ -412,24 +420,47 @@ pub enum BlocIdx {
     /// The block is deleted
     Deleted, // TODO find a real name
     /// Index of a real bloc
-    Index(std::num::NonZeroU8)
+    Index(std::num::NonZeroU16)
 }
 
+/// Bloc index stored on 8 bits
 impl From<u8> for BlocIdx {
     fn from(val: u8) -> Self {
         match val {
             0 => BlocIdx::Empty,
             0xE5 => BlocIdx::Deleted,
-            val => BlocIdx::Index(unsafe { std::num::NonZeroU8::new_unchecked(val) })
+            val => BlocIdx::Index(unsafe { std::num::NonZeroU16::new_unchecked(val.into()) })
+        }
+    }
+}
+
+/// Bloc index stored on 16 bits for discs of more than 256 blocs
+impl From<u16> for BlocIdx {
+    fn from(val: u16) -> Self {
+        match val {
+            0 => BlocIdx::Empty,
+            0xE5E5 => BlocIdx::Deleted,
+            val => BlocIdx::Index(unsafe { std::num::NonZeroU16::new_unchecked(val) })
         }
     }
 }
 
 impl From<&BlocIdx> for u8 {
+    #[allow(clippy::cast_possible_truncation)]
     fn from(b: &BlocIdx) -> u8 {
         match b {
             BlocIdx::Empty => 0,
             BlocIdx::Deleted => 0xE5,
+            BlocIdx::Index(val) => val.get() as u8
+        }
+    }
+}
+
+impl From<&BlocIdx> for u16 {
+    fn from(b: &BlocIdx) -> u16 {
+        match b {
+            BlocIdx::Empty => 0,
+            BlocIdx::Deleted => 0xE5E5,
             BlocIdx::Index(val) => val.get()
         }
     }
@@ -441,20 +472,20 @@ impl BlocIdx {
         matches!(self, BlocIdx::Index(_))
     }
 
-    pub fn value(self) -> u8 {
+    pub fn value(self) -> u16 {
         (&self).into()
     }
 
-    /// only valid for a valid block
+    /// only valid for a valid block of a DATA disc
     #[allow(clippy::cast_possible_truncation)]
     pub fn track(self) -> u8 {
-        ((u16::from(self.value()) << 1) / 9) as u8
+        ((self.value() << 1) / 9) as u8
     }
 
-    /// only valid for a valid block
+    /// only valid for a valid block of a DATA disc
     #[allow(clippy::cast_possible_truncation)]
     pub fn sector(self) -> u8 {
-        ((u16::from(self.value()) << 1) % 9) as u8
+        ((self.value() << 1) % 9) as u8
     }
 }
 
@@ -472,7 +503,9 @@ pub struct AmsdosEntry {
     /// Encoded page size
     pub(crate) page_size: u8,
     /// list of block indexes
-    pub(crate) blocs: [BlocIdx; 16]
+    pub(crate) blocs: [BlocIdx; 16],
+    /// Layout of the disc the entry comes from
+    pub(crate) dpb: DiscParameterBlock
 }
 
 impl std::fmt::Display for AmsdosEntry {
@@ -504,7 +537,7 @@ impl AmsdosEntry {
 
     /// Provide the size, in Kb, eaten by the file on disc
     pub fn used_space(&self) -> usize {
-        (self.nb_blocs() * DATA_SECTOR_SIZE * 2) / 1024
+        (self.nb_blocs() * self.dpb.bloc_size as usize) / 1024
     }
 
     /// Check if the given filename corresponds to the entry
@@ -520,8 +553,25 @@ impl AmsdosEntry {
         Self::from_buffer(idx, array_ref!(slice, 0, 32))
     }
 
-    /// Create the entry from its 32 bytes
+    /// Create the entry from its 32 bytes, for a DATA disc
     pub fn from_buffer(idx: u8, buffer: &[u8; 32]) -> Self {
+        Self::from_buffer_with_dpb(idx, buffer, DiscParameterBlock::DATA)
+    }
+
+    /// Create the entry from its 32 bytes for a disc of the given layout
+    pub fn from_buffer_with_dpb(idx: u8, buffer: &[u8; 32], dpb: DiscParameterBlock) -> Self {
+        let mut blocs = [BlocIdx::default(); 16];
+        if dpb.has_wide_bloc_pointers() {
+            for (bloc, bytes) in blocs.iter_mut().zip(buffer[16..].chunks_exact(2)) {
+                *bloc = BlocIdx::from(u16::from_le_bytes([bytes[0], bytes[1]]));
+            }
+        }
+        else {
+            for (bloc, &byte) in blocs.iter_mut().zip(&buffer[16..]) {
+                *bloc = BlocIdx::from(byte);
+            }
+        }
+
         Self {
             idx,
             file_name: AmsdosFileName::from_entry_format(array_ref!(buffer, 0, 12)),
@@ -529,33 +579,50 @@ impl AmsdosEntry {
             system: buffer[1 + 8 + 1].bit(7),
             num_page: buffer[12],
             page_size: buffer[15],
-            blocs: {
-                let blocs = buffer[16..]
-                    .iter()
-                    .map(|&b| BlocIdx::from(b))
-                    .collect::<Vec<BlocIdx>>();
-                let mut array_blocs = [BlocIdx::default(); 16];
-                array_blocs[..16].clone_from_slice(&blocs[..16]);
-                array_blocs
-            }
+            blocs,
+            dpb
         }
     }
 
+    /// Location of the entry in the catalog
+    pub fn idx(&self) -> u8 {
+        self.idx
+    }
+
     /// Returns the list of used blocs by tis entry
     pub fn used_blocs(&self) -> &[BlocIdx] {
-        &self.blocs[..(self.nb_blocs())]
+        &self.blocs[..self.nb_blocs().min(self.dpb.blocs_per_entry())]
+    }
+
+    /// Returns all the bloc slots of the entry, even the ones after its last record
+    pub fn all_blocs(&self) -> &[BlocIdx] {
+        &self.blocs[..self.dpb.blocs_per_entry()]
     }
 
     /// Compute the real number of blocs to read
     pub fn nb_blocs(&self) -> usize {
-        (self.page_size as usize + 7) >> 3
+        self.nb_records().div_ceil(self.dpb.records_per_bloc())
+    }
+
+    /// Number of 128 bytes records stored in the entry.
+    /// The low bits of the extent number count the 16K extents already filled in the entry
+    pub fn nb_records(&self) -> usize {
+        (self.num_page & self.dpb.extent_mask()) as usize * 128 + self.page_size as usize
+    }
+
+    /// Set the number of records while keeping the position of the entry in the file.
+    /// The extent number is the one of the last 16K extent and the page size counts its records
+    pub fn set_nb_records(&mut self, nb_records: usize) {
+        let last_extent = nb_records.saturating_sub(1) / 128;
+        self.num_page = (self.num_page & !self.dpb.extent_mask()) | last_extent as u8;
+        self.page_size = (nb_records - last_extent * 128) as u8;
     }
 
     /// Set the number of blocs
     pub fn set_blocs(&mut self, blocs: &[BlocIdx]) {
         let mut nb_blocs = 0;
         for idx in 0..16 {
-            self.blocs[idx] = if blocs.len() > idx {
+            self.blocs[idx] = if blocs.len() > idx && idx < self.dpb.blocs_per_entry() {
                 nb_blocs += 1;
                 blocs[idx]
             }
@@ -564,7 +631,10 @@ impl AmsdosEntry {
             }
         }
 
-        self.page_size = (nb_blocs << 3) - 7;
+        let records_per_bloc = self.dpb.records_per_bloc();
+        self.page_size = (nb_blocs * records_per_bloc)
+            .saturating_sub(records_per_bloc - 1)
+            .min(128) as u8;
     }
 
     pub fn as_bytes(&self) -> [u8; 32] {
@@ -576,13 +646,16 @@ impl AmsdosEntry {
         );
         bytes[12] = self.num_page;
         bytes[15] = self.page_size;
-        bytes[16..].copy_from_slice(
-            &self
-                .blocs
-                .iter()
-                .map(|b| -> u8 { b.into() })
-                .collect::<Vec<u8>>()
-        );
+        if self.dpb.has_wide_bloc_pointers() {
+            for (bytes, bloc) in bytes[16..].chunks_exact_mut(2).zip(&self.blocs) {
+                bytes.copy_from_slice(&u16::from(bloc).to_le_bytes());
+            }
+        }
+        else {
+            for (byte, bloc) in bytes[16..].iter_mut().zip(&self.blocs) {
+                *byte = bloc.into();
+            }
+        }
 
         bytes
     }
@@ -595,6 +668,11 @@ impl AmsdosEntry {
         self.file_name.user() == 0xE5
     }
 
+    /// A never used entry is entirely filled with 0xE5, unlike an erased one that keeps its name and blocs
+    pub fn is_never_used(&self) -> bool {
+        *self == Self::from_buffer_with_dpb(self.idx, &[0xE5; 32], self.dpb)
+    }
+
     pub fn is_read_only(&self) -> bool {
         self.read_only
     }
@@ -639,29 +717,15 @@ impl AmsdosEntry {
             self.file_name.extension()
         )
     }
-
-    fn track_and_sector<D: Disc>(&self, disc: &D, head: Head) -> (u8, u8) {
-        let min_sect = disc.global_min_sector(head);
-        let sector_id = (self.idx >> 4) + min_sect;
-        let track = if min_sect == 0x41 {
-            2
-        }
-        else if min_sect == 1 {
-            1
-        }
-        else {
-            0
-        };
-
-        (track, sector_id)
-    }
 }
 
 /// Encode the catalog of an existing disc
 #[derive(PartialEq)]
 pub struct AmsdosEntries {
     /// List of entried in the catalog
-    entries: Vec<AmsdosEntry>
+    entries: Vec<AmsdosEntry>,
+    /// Layout of the disc the catalog comes from
+    dpb: DiscParameterBlock
 }
 
 impl std::fmt::Debug for AmsdosEntries {
@@ -684,7 +748,8 @@ pub struct AmsdosCatalogEntry {
     system: bool,
     /// The indices, in the real catalog, of the entries that represent this one
     entries_idx: Vec<u8>,
-    blocs: Vec<BlocIdx>
+    blocs: Vec<BlocIdx>,
+    bloc_size: usize
 }
 
 impl AmsdosCatalogEntry {
@@ -708,7 +773,8 @@ impl From<(u8, u8, AmsdosEntry)> for AmsdosCatalogEntry {
                 .blocs
                 .iter()
                 .filter_map(|b| if b.is_valid() { Some(*b) } else { None })
-                .collect()
+                .collect(),
+            bloc_size: e.dpb.bloc_size as usize
         }
     }
 }
@@ -733,7 +799,8 @@ impl AmsdosCatalogEntry {
                 let mut blocs = e1.blocs.clone();
                 blocs.extend_from_slice(&e2.blocs);
                 blocs
-            }
+            },
+            bloc_size: e1.bloc_size
         }
     }
 
@@ -751,7 +818,7 @@ impl AmsdosCatalogEntry {
 
     /// Size in kilobytes
     pub fn size(&self) -> usize {
-        self.blocs.len() * 512 * 2 / 1024
+        self.blocs.len() * self.bloc_size / 1024
     }
 }
 
@@ -849,19 +916,21 @@ impl AmsdosEntries {
         (0..self.entries.len()).find(|&idx| &self.entries[idx] == entry)
     }
 
+    /// Return the layout of the disc the catalog comes from
+    pub fn dpb(&self) -> &DiscParameterBlock {
+        &self.dpb
+    }
+
     /// Return the track that contains the entry
     pub fn track(&self, entry: &AmsdosEntry) -> Option<u8> {
-        self.entry_index(entry).map(|idx| (4 * idx / 64) as u8)
+        self.entry_index(entry)
+            .map(|idx| self.dpb.entry_position(Head::A, idx as u8).0.1)
     }
 
+    /// Return the id of the sector that contains the entry
     pub fn sector(&self, entry: &AmsdosEntry) -> Option<u8> {
-        match self.entry_index(entry) {
-            Some(idx) => {
-                let idx = idx % 16;
-                Some([0xC1, 0xC2, 0xC3, 0xC4, 0xC5, 0xC6, 0xC7, 0xC8, 0xC9][idx / 16])
-            },
-            _ => None
-        }
+        self.entry_index(entry)
+            .map(|idx| self.dpb.entry_position(Head::A, idx as u8).0.2)
     }
 
     pub fn get_entry_mut(&mut self, idx: usize) -> &mut AmsdosEntry {
@@ -869,24 +938,26 @@ impl AmsdosEntries {
     }
 
     /// Generate a binary version that can be used to export the catalog
-    pub fn as_bytes(&self) -> [u8; 64 * 32] {
-        let mut content = [0; 64 * 32];
-        for i in 0..64 {
-            content[i * 32..(i + 1) * 32].copy_from_slice(&self.entries[i].as_bytes());
-        }
-        content
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.entries.iter().flat_map(|e| e.as_bytes()).collect()
     }
 
-    /// Manually create the catalog from a byte slice. Usefull to manipulate catarts
+    /// Manually create the catalog of a DATA disc from a byte slice. Usefull to manipulate catarts
     pub fn from_slice(slice: &[u8]) -> Self {
+        Self::from_slice_with_dpb(slice, DiscParameterBlock::DATA)
+    }
+
+    /// Manually create the catalog of a disc of the given layout from a byte slice
+    pub fn from_slice_with_dpb(slice: &[u8], dpb: DiscParameterBlock) -> Self {
         let mut entries = Vec::new();
-        for i in 0..64 {
-            entries.push(AmsdosEntry::from_slice(
+        for i in 0..dpb.directory_entries as usize {
+            entries.push(AmsdosEntry::from_buffer_with_dpb(
                 i as _,
-                &slice[i * 32..(i + 1) * 32]
+                array_ref!(slice, i * 32, 32),
+                dpb
             ))
         }
-        Self { entries }
+        Self { entries, dpb }
     }
 
     /// Returns all the entries of the catalog
@@ -938,7 +1009,7 @@ impl AmsdosEntries {
     /// Returns the blocs that are not referenced in the catalog.
     /// Bloc used in erased files are returned (so they may be broken)
     pub fn available_blocs(&self) -> impl Iterator<Item = BlocIdx> + use<> {
-        // first 2 blocs are not available if we trust idsk. So  I do the same
+        // the first blocs are used by the directory
 
         let used_blocs = self
             .used_entries()
@@ -946,8 +1017,9 @@ impl AmsdosEntries {
             .copied()
             .collect::<std::collections::BTreeSet<BlocIdx>>();
 
-        (2..=255)
-            .map(BlocIdx::from)
+        let dpb = self.dpb;
+        (dpb.directory_blocs()..dpb.nb_blocs())
+            .map(move |bloc| dpb.bloc_idx(bloc as u16))
             .filter(|&v| v.is_valid()) // TODO I'm pretty sure there is womething wrong there with the erased value
             .filter(move |b| !used_blocs.contains(b))
     }
@@ -999,23 +1071,210 @@ const DATA_RECORDS_PER_TRACK: u8 = 4;
 #[allow(unused)]
 const DATA_SECTOR_SIZE: usize = 512;
 
-/// Minimal information needed to access to twe two sectors of a given bloc
+/// Disc parameter block: layout of the Amsdos-like file system of a disc.
+/// It tells where are the directory and the blocs of the files
+/// http://www.cpcwiki.eu/index.php/Disk_structure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiscParameterBlock {
+    /// Id of the first sector of each track
+    pub first_sector_id: u8,
+    /// Number of sectors per track
+    pub sectors_per_track: u8,
+    /// Size of a sector in bytes
+    pub sector_size: u16,
+    /// Number of tracks per head
+    pub nb_tracks: u8,
+    /// Number of heads. Logical tracks alternate between the heads of a double headed disc
+    pub nb_heads: u8,
+    /// Number of logical tracks before the directory
+    pub reserved_tracks: u8,
+    /// Size of a bloc in bytes
+    pub bloc_size: u16,
+    /// Number of entries in the directory
+    pub directory_entries: u16
+}
+
+impl Default for DiscParameterBlock {
+    fn default() -> Self {
+        Self::DATA
+    }
+}
+
 #[allow(missing_docs)]
-#[derive(Debug)]
-struct BlocAccessInformation {
-    track1: u8,
-    sector1_id: u8,
-    track2: u8,
-    sector2_id: u8
+impl DiscParameterBlock {
+    pub const DATA: Self = Self {
+        first_sector_id: DATA_FIRST_SECTOR_NUMBER,
+        sectors_per_track: DATA_SECTORS_PER_TRACK,
+        sector_size: DATA_SECTOR_SIZE as u16,
+        nb_tracks: 40,
+        nb_heads: 1,
+        reserved_tracks: DATA_RESERVED_TRACK,
+        bloc_size: 1024,
+        directory_entries: DIRECTORY_SIZE as u16
+    };
+    pub const IBM: Self = Self {
+        first_sector_id: 0x01,
+        sectors_per_track: 8,
+        reserved_tracks: 1,
+        ..Self::DATA
+    };
+    pub const PARADOS80: Self = Self {
+        first_sector_id: 0x91,
+        sectors_per_track: 10,
+        nb_tracks: 80,
+        bloc_size: 2048,
+        directory_entries: 128,
+        ..Self::DATA
+    };
+    pub const ROMDOS_D1: Self = Self {
+        first_sector_id: 0x01,
+        nb_tracks: 80,
+        nb_heads: 2,
+        bloc_size: 2048,
+        directory_entries: 128,
+        ..Self::DATA
+    };
+    pub const SYSTEM: Self = Self {
+        first_sector_id: SYSTEM_FIRST_SECTOR_NUMBER,
+        reserved_tracks: 2,
+        ..Self::DATA
+    };
+
+    /// Guess the layout of the disc from the id of the first sector of its track 0.
+    /// Unknown ids are handled as a DATA disc starting at this id
+    pub fn from_disc<D: Disc, S: Into<Head>>(disc: &D, head: S) -> Self {
+        let head = head.into();
+        let nb_tracks = disc.nb_tracks_per_head();
+        let dpb = match disc.track_min_sector(head, 0) {
+            DATA_FIRST_SECTOR_NUMBER => Self::DATA,
+            SYSTEM_FIRST_SECTOR_NUMBER => Self::SYSTEM,
+            0x91 => Self::PARADOS80,
+            // ROMDOS D1 uses a 9th sector where IBM stops at 8
+            0x01 if nb_tracks >= 80 && disc.sector_read_bytes(head, 0, 0x09).is_some() => {
+                Self::ROMDOS_D1
+            },
+            0x01 => Self::IBM,
+            first_sector_id => {
+                Self {
+                    first_sector_id,
+                    ..Self::DATA
+                }
+            },
+        };
+
+        Self { nb_tracks, ..dpb }
+    }
+
+    /// Number of blocs available for the directory and the files.
+    /// A truncated disc without room after its reserved tracks has none
+    pub fn nb_blocs(&self) -> usize {
+        self.try_nb_blocs().unwrap_or(0)
+    }
+
+    /// Number of blocs available for the directory and the files,
+    /// or an error when the disc has no track after the reserved ones
+    pub fn try_nb_blocs(&self) -> Result<usize, AmsdosError> {
+        let nb_tracks = self.nb_tracks as usize * self.nb_heads as usize;
+        match nb_tracks.checked_sub(self.reserved_tracks as usize) {
+            Some(nb_data_tracks) if nb_data_tracks > 0 => {
+                Ok(
+                    nb_data_tracks * self.sectors_per_track as usize * self.sector_size as usize
+                        / self.bloc_size as usize
+                )
+            },
+            _ => {
+                Err(AmsdosError::NotEnoughTracks {
+                    nb_tracks,
+                    reserved_tracks: self.reserved_tracks
+                })
+            },
+        }
+    }
+
+    /// Number of blocs used by the directory. They are the first ones
+    pub fn directory_blocs(&self) -> usize {
+        (self.directory_entries as usize * AmsdosEntry::len()).div_ceil(self.bloc_size as usize)
+    }
+
+    /// Bloc indexes are stored on 16 bits when there are more than 256 blocs
+    pub fn has_wide_bloc_pointers(&self) -> bool {
+        self.nb_blocs() > 256
+    }
+
+    pub fn blocs_per_entry(&self) -> usize {
+        if self.has_wide_bloc_pointers() { 8 } else { 16 }
+    }
+
+    /// Number of 128 bytes records in a bloc
+    pub fn records_per_bloc(&self) -> usize {
+        self.bloc_size as usize / 128
+    }
+
+    pub fn records_per_entry(&self) -> usize {
+        self.blocs_per_entry() * self.records_per_bloc()
+    }
+
+    /// Mask of the extent number for the 16K extents stored in a single entry
+    pub fn extent_mask(&self) -> u8 {
+        (self.records_per_entry() / 128 - 1) as u8
+    }
+
+    /// Build the bloc index with the width used by the disc
+    pub fn bloc_idx(&self, value: u16) -> BlocIdx {
+        if self.has_wide_bloc_pointers() {
+            BlocIdx::from(value)
+        }
+        else {
+            BlocIdx::from(value as u8)
+        }
+    }
+
+    /// Return the head, track and sector id of a sector of the file system.
+    /// Sectors are counted from the first one after the reserved tracks.
+    /// The head is only used by single headed layouts
+    pub fn sector_position<S: Into<Head>>(&self, head: S, sector_idx: usize) -> (Head, u8, u8) {
+        let track = self.reserved_tracks as usize + sector_idx / self.sectors_per_track as usize;
+        let sector_id = self.first_sector_id + (sector_idx % self.sectors_per_track as usize) as u8;
+
+        if self.nb_heads == 2 {
+            (Head::from((track % 2) as u8), (track / 2) as u8, sector_id)
+        }
+        else {
+            (head.into(), track as u8, sector_id)
+        }
+    }
+
+    /// Return the position of the sectors of the bloc
+    pub fn bloc_sectors<S: Into<Head>>(&self, head: S, bloc_idx: BlocIdx) -> Vec<(Head, u8, u8)> {
+        assert!(bloc_idx.is_valid());
+
+        let head = head.into();
+        let sectors_per_bloc = (self.bloc_size / self.sector_size) as usize;
+        let first = bloc_idx.value() as usize * sectors_per_bloc;
+        (first..first + sectors_per_bloc)
+            .map(|sector_idx| self.sector_position(head, sector_idx))
+            .collect()
+    }
+
+    /// Return the position of the sector that contains the entry and the offset of the entry in it
+    pub fn entry_position<S: Into<Head>>(&self, head: S, idx: u8) -> ((Head, u8, u8), usize) {
+        let offset = idx as usize * AmsdosEntry::len();
+        let sector_size = self.sector_size as usize;
+        (
+            self.sector_position(head, offset / sector_size),
+            offset % sector_size
+        )
+    }
 }
 
 /// http://cpctech.cpc-live.com/docs/manual/s968se09.pdf
-/// Current implementatin only focus on DATA format
+/// The layout of the disc is described by its [DiscParameterBlock]
 #[allow(missing_docs)]
 #[derive(Debug)]
 pub struct AmsdosManagerMut<'dsk, D: Disc> {
     disc: &'dsk mut D,
-    head: Head
+    head: Head,
+    dpb: DiscParameterBlock
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -1031,10 +1290,22 @@ pub enum AmsdosAddBehavior {
 }
 
 impl<'dsk, D: Disc> AmsdosManagerMut<'dsk, D> {
+    /// The layout of the disc is guessed from its first sector
     pub fn new_from_disc<S: Into<Head>>(disc: &'dsk mut D, head: S) -> Self {
+        let head = head.into();
+        let dpb = DiscParameterBlock::from_disc(disc, head);
+        Self::new_from_disc_with_dpb(disc, head, dpb)
+    }
+
+    pub fn new_from_disc_with_dpb<S: Into<Head>>(
+        disc: &'dsk mut D,
+        head: S,
+        dpb: DiscParameterBlock
+    ) -> Self {
         Self {
             disc,
-            head: head.into()
+            head: head.into(),
+            dpb
         }
     }
 
@@ -1042,18 +1313,19 @@ impl<'dsk, D: Disc> AmsdosManagerMut<'dsk, D> {
         self.disc
     }
 
+    pub fn dpb(&self) -> &DiscParameterBlock {
+        &self.dpb
+    }
+
+    /// Erase the entry the Amsdos way: only the user is modified, so the blocs of the file are still known
     fn erase_entry(&mut self, entry: &AmsdosEntry) {
-        let (track, sector_id) = entry.track_and_sector(self.disc, self.head);
+        let ((head, track, sector_id), idx_in_sector) =
+            self.dpb.entry_position(self.head, entry.idx);
 
-        let mut sector = self
-            .disc
-            .sector_read_bytes(self.head, track, sector_id)
-            .unwrap();
-        let idx_in_sector: usize = ((entry.idx & 15) << 5) as usize;
-        let bytes = &mut (sector[idx_in_sector..(idx_in_sector + AmsdosEntry::len())]);
-        bytes.fill(0xE5);
+        let mut sector = self.disc.sector_read_bytes(head, track, sector_id).unwrap();
+        sector[idx_in_sector] = 0xE5;
         self.disc
-            .sector_write_bytes(self.head, track, sector_id, &sector)
+            .sector_write_bytes(head, track, sector_id, &sector)
             .unwrap();
     }
 
@@ -1061,31 +1333,42 @@ impl<'dsk, D: Disc> AmsdosManagerMut<'dsk, D> {
     /// Panic if dsk is invalid
     /// Still stolen to iDSK
     pub fn update_entry(&mut self, entry: &AmsdosEntry) {
-        // TODO refactor
         // compute the track/sector
-        let (track, sector_id) = entry.track_and_sector(self.disc, self.head);
+        let ((head, track, sector_id), idx_in_sector) =
+            self.dpb.entry_position(self.head, entry.idx);
 
         // eprintln!("head:{:?} track: {} sector: {}", &self.head, track, sector_id);
-        let mut sector = self
-            .disc
-            .sector_read_bytes(self.head, track, sector_id)
-            .unwrap();
-        let idx_in_sector: usize = ((entry.idx & 15) << 5) as usize;
+        let mut sector = self.disc.sector_read_bytes(head, track, sector_id).unwrap();
         let bytes = &mut (sector[idx_in_sector..(idx_in_sector + AmsdosEntry::len())]);
         bytes.copy_from_slice(entry.as_bytes().as_ref());
         self.disc
-            .sector_write_bytes(self.head, track, sector_id, &sector)
+            .sector_write_bytes(head, track, sector_id, &sector)
             .unwrap();
     }
 
     /// Rewrite the whole catalog
     pub fn set_catalog(&mut self, entries: &AmsdosEntries) {
-        assert_eq!(64, entries.entries.len());
+        assert_eq!(self.dpb.directory_entries as usize, entries.entries.len());
         for entry in &entries.entries {
             self.update_entry(entry);
         }
     }
 
+    /// Rewrite the raw bytes of the catalog. Missing bytes are filled with 0xE5
+    pub fn set_catalog_slice(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let sector_size = self.dpb.sector_size as usize;
+        let catalog_size = self.dpb.directory_entries as usize * AmsdosEntry::len();
+        let mut bytes = bytes.to_vec();
+        bytes.resize(catalog_size, 0xE5);
+
+        for (sector_idx, content) in bytes.chunks(sector_size).enumerate() {
+            let (head, track, sector_id) = self.dpb.sector_position(self.head, sector_idx);
+            self.disc
+                .sector_write_bytes(head, track, sector_id, content)?;
+        }
+        Ok(())
+    }
+
     /// Add the given amsdos file to the disc
     /// Code is greatly inspired by idsk with no special verifications.
     /// In case of error, the disk is in a broken state => part of the file may be stored...
@@ -1140,6 +1423,8 @@ impl<'dsk, D: Disc> AmsdosManagerMut<'dsk, D> {
         let mut nb_entries = 0;
 
         let mut available_blocs = self.catalog().available_blocs();
+        let bloc_size = self.dpb.bloc_size as usize;
+        let extent_mask = self.dpb.extent_mask();
 
         // println!("File size {} bytes", file_size);
         while file_pos < file_size {
@@ -1154,17 +1439,11 @@ impl<'dsk, D: Disc> AmsdosManagerMut<'dsk, D> {
             let entry_num_page = nb_entries;
             nb_entries += 1;
 
-            // TODO need to find and comment why there is such strange maths
-            let page_size = {
-                let mut size = (file_size - file_pos + 127) >> 7;
-                if size > 128 {
-                    size = 128;
-                }
-                size
-            };
+            // number of 128 bytes records stored in this entry
+            let nb_records = ((file_size - file_pos + 127) >> 7).min(self.dpb.records_per_entry());
 
             // //Nombre de blocs=TaillePage/8 arrondi par le haut
-            let nb_blocs = (page_size + 7) >> 3;
+            let nb_blocs = nb_records.div_ceil(self.dpb.records_per_bloc());
             let chosen_blocs = {
                 let mut chosen_blocs = [BlocIdx::default(); 16];
                 for current_chosen_bloc in chosen_blocs.iter_mut().take(nb_blocs) {
@@ -1179,64 +1458,57 @@ impl<'dsk, D: Disc> AmsdosManagerMut<'dsk, D> {
                     self.update_bloc(
                         bloc_idx,
                         &AmsdosManagerNonMut::<'dsk, D>::padding(
-                            &content[file_pos..(file_pos + 2 * DATA_SECTOR_SIZE).min(file_size)],
-                            2 * DATA_SECTOR_SIZE
+                            &content[file_pos..(file_pos + bloc_size).min(file_size)],
+                            bloc_size
                         )
                     )?;
                     // eprintln!("Has updated  bloc {:?}", bloc_idx);
 
-                    file_pos += 2 * DATA_SECTOR_SIZE;
+                    file_pos += bloc_size;
                 }
                 chosen_blocs
             };
 
             // Update the entry on disc
-            let new_entry = AmsdosEntry {
+            let mut new_entry = AmsdosEntry {
                 idx: entry_idx,
                 file_name: filename,
                 read_only: is_read_only,
                 system: is_system,
-                num_page: entry_num_page,
-                page_size: page_size as u8,
-                blocs: chosen_blocs
+                // an entry covers several 16K extents when the mask is not null
+                num_page: entry_num_page * (extent_mask + 1),
+                page_size: 0,
+                blocs: chosen_blocs,
+                dpb: self.dpb
             };
+            new_entry.set_nb_records(nb_records);
             self.update_entry(&new_entry)
         }
         Ok(())
     }
 
-    /// Write bloc content on disc. One bloc use 2 sectors
-    /// Implementation is stolen to iDSK
+    /// Write bloc content on disc. One bloc spans several consecutive sectors
     pub fn update_bloc(&mut self, bloc_idx: BlocIdx, content: &[u8]) -> Result<(), String> {
         assert!(bloc_idx.is_valid());
 
         // More tests are needed to check if it can work without that
-        assert_eq!(content.len(), DATA_SECTOR_SIZE * 2);
-
-        let access_info = self.bloc_access_information(bloc_idx);
-
-        // Copy in first sector
-        self.disc.sector_write_bytes(
-            self.head,
-            access_info.track1,
-            access_info.sector1_id,
-            &content[0..DATA_SECTOR_SIZE]
-        )?;
-
-        // Copy in second sector
-        self.disc.sector_write_bytes(
-            self.head,
-            access_info.track2,
-            access_info.sector2_id,
-            &content[DATA_SECTOR_SIZE..2 * DATA_SECTOR_SIZE]
-        )?;
+        assert_eq!(content.len(), self.dpb.bloc_size as usize);
+
+        let sectors = self.bloc_access_information(bloc_idx);
+        for ((head, track, sector_id), content) in sectors
+            .into_iter()
+            .zip(content.chunks(self.dpb.sector_size as usize))
+        {
+            self.disc
+                .sector_write_bytes(head, track, sector_id, content)?;
+        }
         Ok(())
     }
 
     fn erase_bloc(&mut self, bloc_idx: BlocIdx) -> Result<(), String> {
         assert!(bloc_idx.is_valid());
 
-        let content = vec![0xE5; DATA_SECTOR_SIZE * 2];
+        let content = vec![0xE5; self.dpb.bloc_size as usize];
         self.update_bloc(bloc_idx, &content)
     }
 
@@ -1284,10 +1556,7 @@ impl<'dsk, D: Disc> AmsdosManagerMut<'dsk, D> {
         nonmut.catalog()
     }
 
-    fn bloc_access_information<'mngr: 'dsk>(
-        &'mngr self,
-        bloc_idx: BlocIdx
-    ) -> BlocAccessInformation {
+    fn bloc_access_information<'mngr: 'dsk>(&'mngr self, bloc_idx: BlocIdx) -> Vec<(Head, u8, u8)> {
         let nonmut: AmsdosManagerNonMut<'dsk, D> = self.into();
         nonmut.bloc_access_information(bloc_idx)
     }
@@ -1315,7 +1584,8 @@ impl<'dsk, 'mngr: 'dsk, D: Disc> From<&'mngr AmsdosManagerMut<'dsk, D>>
     fn from(val: &'mngr AmsdosManagerMut<'dsk, D>) -> Self {
         AmsdosManagerNonMut {
             disc: val.disc,
-            head: val.head
+            head: val.head,
+            dpb: val.dpb
         }
     }
 }
@@ -1323,7 +1593,8 @@ impl<'dsk, 'mngr: 'dsk, D: Disc> From<&'mngr AmsdosManagerMut<'dsk, D>>
 #[derive(Debug)]
 pub struct AmsdosManagerNonMut<'dsk, D: Disc> {
     disc: &'dsk D,
-    head: Head
+    head: Head,
+    dpb: DiscParameterBlock
 }
 
 #[allow(missing_docs)]
@@ -1332,35 +1603,50 @@ impl<'dsk, 'mng: 'dsk, D: Disc> AmsdosManagerNonMut<'dsk, D> {
         self.disc
     }
 
+    /// The layout of the disc is guessed from its first sector
     pub fn new_from_disc<S: Into<Head>>(disc: &'dsk D, head: S) -> Self {
+        let head = head.into();
+        let dpb = DiscParameterBlock::from_disc(disc, head);
+        Self::new_from_disc_with_dpb(disc, head, dpb)
+    }
+
+    pub fn new_from_disc_with_dpb<S: Into<Head>>(
+        disc: &'dsk D,
+        head: S,
+        dpb: DiscParameterBlock
+    ) -> Self {
         Self {
             disc,
-            head: head.into()
+            head: head.into(),
+            dpb
         }
     }
 
+    pub fn dpb(&self) -> &DiscParameterBlock {
+        &self.dpb
+    }
+
     /// Format the disc. Currently it only modifies the catalog
     pub fn format(&mut self) {
         let _catalog = self.catalog();
         unimplemented!();
     }
 
-    /// Return the raw bytes of the Amsdos catalog (2048 bytes = 64 entries * 32 bytes/entry)
+    /// Return the raw bytes of the Amsdos catalog (2048 bytes = 64 entries * 32 bytes/entry for a DATA disc)
     /// This is useful for tools that need to work with the raw catalog data without parsing
     pub fn catalog_slice(&self) -> Vec<u8> {
-        let (sector_id, track) = if self.is_data() {
-            (DATA_FIRST_SECTOR_NUMBER, 0)
-        }
-        else if self.is_system() {
-            (SYSTEM_FIRST_SECTOR_NUMBER, 2)
-        }
-        else {
-            // thisi s probably a two sided dic ?
-            (DATA_FIRST_SECTOR_NUMBER, 0)
-        };
-        self.disc
-            .consecutive_sectors_read_bytes(self.head, track, sector_id, 4)
-            .expect("Unable to read catalog sectors")
+        let catalog_size = self.dpb.directory_entries as usize * AmsdosEntry::len();
+        let nb_sectors = catalog_size.div_ceil(self.dpb.sector_size as usize);
+        let mut bytes = (0..nb_sectors)
+            .flat_map(|sector_idx| {
+                let (head, track, sector_id) = self.dpb.sector_position(self.head, sector_idx);
+                self.disc
+                    .sector_read_bytes(head, track, sector_id)
+                    .expect("Unable to read catalog sectors")
+            })
+            .collect::<Vec<u8>>();
+        bytes.truncate(catalog_size);
+        bytes
     }
 
     /// Check if the disc is in DATA format by looking at the first sector of track 0
@@ -1375,18 +1661,7 @@ impl<'dsk, 'mng: 'dsk, D: Disc> AmsdosManagerNonMut<'dsk, D> {
     /// Return the entries of the Amsdos catalog
     /// Panic if dsk is not compatible
     pub fn catalog(&self) -> AmsdosEntries {
-        let mut entries = Vec::new();
-        let bytes = self.catalog_slice();
-
-        for idx in 0..DIRECTORY_SIZE
-        // (bytes.len() / 32)
-        {
-            let entry_buffer = &bytes[(idx * 32)..(idx + 1) * 32];
-            let entry = AmsdosEntry::from_buffer(idx as u8, array_ref!(entry_buffer, 0, 32));
-            entries.push(entry);
-        }
-
-        AmsdosEntries { entries }
+        AmsdosEntries::from_slice_with_dpb(&self.catalog_slice(), self.dpb)
     }
 
     /// Print the catalog on screen
@@ -1453,75 +1728,52 @@ impl<'dsk, 'mng: 'dsk, D: Disc> AmsdosManagerNonMut<'dsk, D> {
         }
     }
 
-    /// Returns the appropriate information to access the bloc
-    /// Blindly stolen to iDSK
-    fn bloc_access_information(&self, bloc_idx: BlocIdx) -> BlocAccessInformation {
-        assert!(bloc_idx.is_valid());
-
-        // Compute the information to access the first sector
-        let sector_pos = bloc_idx.sector();
-        let min_sector = self.disc.track_min_sector(self.head, 0);
-        let track = {
-            let mut track = bloc_idx.track();
-            if min_sector == 0x41 {
-                track += 2; // we skip file table ?
-            }
-            else if min_sector == 0x01 {
-                track += 1;
-            }
-            track
-        };
+    /// Returns the position of the sectors of the bloc
+    fn bloc_access_information(&self, bloc_idx: BlocIdx) -> Vec<(Head, u8, u8)> {
+        let sectors = self.dpb.bloc_sectors(self.head, bloc_idx);
 
-        if track > self.disc.nb_tracks_per_head() - 1 {
+        let last_track = sectors.last().unwrap().1;
+        if last_track > self.disc.nb_tracks_per_head() - 1 {
             unimplemented!(
                 "Need to format track. [{:?}] => {} > {}",
                 bloc_idx,
-                track,
+                last_track,
                 self.disc.nb_tracks_per_head() - 1
             );
         }
 
-        let track1 = track;
-        let sector1_id = sector_pos + min_sector;
+        sectors
+    }
 
-        // Compute the information to access the second sector
-        // TODO set this knowledge in edsk
-        let (sector2_id, track2) = {
-            if (sector_pos + 1) > 8 {
-                (min_sector, track + 1)
-            }
-            else {
-                (sector_pos + 1 + min_sector, track)
-            }
-        };
+    /// Read the content of the given bloc, or None when one of its sectors is not on the disc
+    pub fn try_read_bloc(&self, bloc_idx: BlocIdx) -> Option<Vec<u8>> {
+        if !bloc_idx.is_valid() || bloc_idx.value() as usize >= self.dpb.nb_blocs() {
+            return None;
+        }
 
-        BlocAccessInformation {
-            track1,
-            sector1_id,
-            track2,
-            sector2_id
+        let mut content = Vec::with_capacity(self.dpb.bloc_size as usize);
+        for (head, track, sector_id) in self.dpb.bloc_sectors(self.head, bloc_idx) {
+            if track >= self.disc.nb_tracks_per_head() {
+                return None;
+            }
+            content.extend(self.disc.sector_read_bytes(head, track, sector_id)?);
         }
+        Some(content)
     }
 
     /// Read the content of the given bloc
     pub fn read_bloc(&self, bloc_idx: BlocIdx) -> Vec<u8> {
         assert!(bloc_idx.is_valid());
-        let access_info = self.bloc_access_information(bloc_idx);
-
-        let sector1_data = self
-            .disc
-            .sector_read_bytes(self.head, access_info.track1, access_info.sector1_id)
-            .unwrap();
 
-        let sector2_data = self
-            .disc
-            .sector_read_bytes(self.head, access_info.track2, access_info.sector2_id)
-            .unwrap();
-
-        let mut content = sector1_data;
-        content.extend(sector2_data);
+        let content = self
+            .bloc_access_information(bloc_idx)
+            .into_iter()
+            .flat_map(|(head, track, sector_id)| {
+                self.disc.sector_read_bytes(head, track, sector_id).unwrap()
+            })
+            .collect::<Vec<u8>>();
 
-        assert_eq!(content.len(), DATA_SECTOR_SIZE * 2);
+        assert_eq!(content.len(), self.dpb.bloc_size as usize);
 
         content
     }
@@ -1901,7 +2153,7 @@ impl AmsdosFile {
 
     /// Return an header if the checksum is valid
     pub fn header(&self) -> Option<AmsdosHeader> {
-        if self.all_data.len() > 128 {
+        if self.all_data.len() >= 128 {
             let header = AmsdosHeader::from_buffer(&self.all_data[..128]);
             if header.is_checksum_valid() {
                 Some(header)
@@ -1956,3 +2208,172 @@ impl AmsdosFile {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::{DISC_FORMAT_NAMES, DiscConfig};
+    use crate::edsk::ExtendedDsk;
+
+    fn binary_file(name: &str, size: usize) -> AmsdosFile {
+        let content = (0..size)
+            .map(|i| (i * 13 + i / 256) as u8)
+            .collect::<Vec<_>>();
+        AmsdosFile::binary_file_from_buffer(
+            &AmsdosFileName::try_from(name).unwrap(),
+            0x4000,
+            0x4000,
+            &content
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn named_formats_are_recognized() {
+        let expected = [
+            ("data", DiscParameterBlock::DATA, 180),
+            ("system", DiscParameterBlock::SYSTEM, 171),
+            ("vendor", DiscParameterBlock::SYSTEM, 171),
+            ("ibm", DiscParameterBlock::IBM, 156),
+            ("parados80", DiscParameterBlock::PARADOS80, 200),
+            ("romdos-d1", DiscParameterBlock::ROMDOS_D1, 360)
+        ];
+
+        for (name, dpb, nb_blocs) in expected {
+            let dsk: ExtendedDsk = DiscConfig::from_format_name(name).unwrap().into();
+            let found = DiscParameterBlock::from_disc(&dsk, Head::A);
+            assert_eq!(found, dpb, "{name}");
+            assert_eq!(found.nb_blocs(), nb_blocs, "{name}");
+
+            let manager = AmsdosManagerNonMut::new_from_disc(&dsk, Head::A);
+            assert_eq!(
+                manager.catalog().free_entries().count(),
+                dpb.directory_entries as usize
+            );
+        }
+
+        assert!(
+            DISC_FORMAT_NAMES
+                .iter()
+                .all(|name| DiscConfig::from_format_name(name).is_some())
+        );
+        assert!(!DiscParameterBlock::PARADOS80.has_wide_bloc_pointers());
+        assert_eq!(DiscParameterBlock::PARADOS80.extent_mask(), 1);
+        assert!(DiscParameterBlock::ROMDOS_D1.has_wide_bloc_pointers());
+        assert_eq!(DiscParameterBlock::ROMDOS_D1.extent_mask(), 0);
+    }
+
+    #[test]
+    fn truncated_system_disc_has_no_data_area() {
+        let cfg: DiscConfig = "
+NbTrack = 1
+NbHead = 1
+
+[Track:0]
+SectorSize = 512
+Gap3 = 82
+SectorID = 0x41,0x46,0x42,0x47,0x43,0x48,0x44,0x49,0x45
+sectorIDHead = 0,0,0,0,0,0,0,0,0
+"
+        .parse()
+        .unwrap();
+        let mut dsk: ExtendedDsk = cfg.into();
+
+        let dpb = DiscParameterBlock::from_disc(&dsk, Head::A);
+        assert_eq!(
+            dpb.reserved_tracks,
+            DiscParameterBlock::SYSTEM.reserved_tracks
+        );
+        assert_eq!(dpb.nb_blocs(), 0);
+        assert!(matches!(
+            dpb.try_nb_blocs(),
+            Err(AmsdosError::NotEnoughTracks {
+                nb_tracks: 1,
+                reserved_tracks: 2
+            })
+        ));
+
+        let mut manager = AmsdosManagerMut::new_from_disc(&mut dsk, Head::A);
+        assert!(matches!(
+            manager.repair(),
+            Err(AmsdosError::NotEnoughTracks { .. })
+        ));
+    }
+
+    #[test]
+    fn add_and_get_on_all_formats() {
+        for name in DISC_FORMAT_NAMES {
+            let mut dsk: ExtendedDsk = DiscConfig::from_format_name(name).unwrap().into();
+            let files = [
+                binary_file("small.bin", 100),
+                binary_file("big.bin", 40_000),
+                binary_file("other.bin", 17_000)
+            ];
+
+            let mut manager = AmsdosManagerMut::new_from_disc(&mut dsk, Head::A);
+            for file in &files {
+                manager
+                    .add_file(file, None, false, false, AmsdosAddBehavior::FailIfPresent)
+                    .unwrap();
+            }
+
+            let catalog = manager.catalog().to_amsdos_catalog();
+            assert_eq!(catalog.len(), 3, "{name}");
+
+            for file in &files {
+                let fname = file.amsdos_filename().unwrap().unwrap();
+                let read = manager.get_file(fname).unwrap();
+                assert_eq!(read.content(), file.content(), "{name} {fname:?}");
+            }
+
+            // shared blocs would have corrupted the files
+            let blocs = manager
+                .catalog()
+                .used_entries()
+                .flat_map(|e| e.used_blocs().to_vec())
+                .collect::<Vec<_>>();
+            let unique = blocs.iter().collect::<std::collections::BTreeSet<_>>();
+            assert_eq!(blocs.len(), unique.len(), "{name}");
+        }
+    }
+
+    #[test]
+    fn big_disc_uses_16_bits_bloc_indexes() {
+        let mut dsk: ExtendedDsk = DiscConfig::double_head_romdos_d1_format().into();
+        let mut manager = AmsdosManagerMut::new_from_disc(&mut dsk, Head::A);
+
+        let files = (0..11)
+            .map(|i| binary_file(&format!("file{i}.bin"), 60_000))
+            .collect::<Vec<_>>();
+        for file in &files {
+            manager
+                .add_file(file, None, false, false, AmsdosAddBehavior::FailIfPresent)
+                .unwrap();
+        }
+
+        let highest = manager
+            .catalog()
+            .used_entries()
+            .flat_map(|e| e.used_blocs().to_vec())
+            .max()
+            .unwrap();
+        assert!(highest.value() > 255);
+
+        for file in &files {
+            let fname = file.amsdos_filename().unwrap().unwrap();
+            assert_eq!(manager.get_file(fname).unwrap().content(), file.content());
+        }
+
+        // the disc is full
+        assert!(matches!(
+            manager.add_file(
+                &binary_file("full.bin", 60_000),
+                None,
+                false,
+                false,
+                AmsdosAddBehavior::FailIfPresent
+            ),
+            Err(AmsdosError::NoBlocAvailable)
+        ));
+    }
+}
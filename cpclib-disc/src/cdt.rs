@@ -0,0 +1,795 @@
+//! CDT tape images.
+//!
+//! A CDT file is a TZX file whose data blocks contain the records written by the CPC firmware.
+//! Each file is cut in blocks of 2K; each block is stored as a header record (sync byte `0x2C`)
+//! followed by a data record (sync byte `0x16`). Records are split in segments of 256 bytes,
+//! each one followed by its CRC, and end with a trailer.
+
+use cpclib_common::camino::Utf8Path;
+
+use crate::amsdos::{
+    AmsdosAddBehavior, AmsdosError, AmsdosFile, AmsdosFileName, AmsdosFileType, AmsdosHeader
+};
+
+/// Signature of any TZX file
+const TZX_SIGNATURE: &[u8; 8] = b"ZXTape!\x1a";
+const TZX_MAJOR: u8 = 1;
+const TZX_MINOR: u8 = 20;
+
+/// Turbo speed data block, used to store the firmware records
+const TZX_TURBO_SPEED_DATA: u8 = 0x11;
+const TZX_STANDARD_SPEED_DATA: u8 = 0x10;
+const TZX_PURE_DATA: u8 = 0x14;
+
+/// Frequency used by the TZX format to express pulse lengths
+const TZX_CLOCK: u32 = 3_500_000;
+
+/// Size of the blocks the firmware cuts the files in
+pub const TAPE_BLOCK_SIZE: usize = 2048;
+/// Size of a segment protected by a CRC
+const SEGMENT_SIZE: usize = 256;
+/// Meaningful part of a header record
+const HEADER_SIZE: usize = 64;
+/// Maximum number of characters of a tape filename
+pub const TAPE_FILENAME_SIZE: usize = 16;
+
+const HEADER_SYNC: u8 = 0x2C;
+const DATA_SYNC: u8 = 0x16;
+/// The firmware ends each record by 32 bits set to 1
+const TRAILER: [u8; 4] = [0xFF; 4];
+/// The leader is made of 2048 bits set to 1, each one being 2 pulses
+const PILOT_PULSES: u16 = 4096;
+
+/// File type byte of an ASCII file (version 1, unprotected)
+const TAPE_ASCII_TYPE: u8 = 0x16;
+
+/// Parameters used when writing files on a tape
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CdtWriteOptions {
+    /// Speed in bauds. The firmware uses 1000 (`SPEED WRITE 0`) or 2000 (`SPEED WRITE 1`)
+    pub baud: u16,
+    /// Pause (in ms) between the header record and the data record of a block
+    pub pause_after_header: u16,
+    /// Pause (in ms) after the data record of a block
+    pub pause_after_data: u16
+}
+
+impl Default for CdtWriteOptions {
+    fn default() -> Self {
+        Self {
+            baud: 2000,
+            pause_after_header: 16,
+            pause_after_data: 2000
+        }
+    }
+}
+
+impl CdtWriteOptions {
+    /// Length in T-states of the pulses of a 0 bit.
+    /// A 1 bit lasts twice a 0 bit and the baud rate is the average for both.
+    pub fn zero_pulse(&self) -> u16 {
+        (TZX_CLOCK / (3 * self.baud.max(1) as u32)) as u16
+    }
+
+    pub fn one_pulse(&self) -> u16 {
+        2 * self.zero_pulse()
+    }
+}
+
+/// Compute the CRC of a segment the way the firmware does (CCITT, inverted)
+pub fn crc(data: &[u8]) -> u16 {
+    let crc = data.iter().fold(0xFFFF_u16, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            }
+            else {
+                crc << 1
+            }
+        })
+    });
+    !crc
+}
+
+/// Build the content of a record: sync byte, segments with their CRC and trailer
+fn encode_record(sync: u8, data: &[u8]) -> Vec<u8> {
+    let mut record = vec![sync];
+    let nb_segments = data.len().div_ceil(SEGMENT_SIZE).max(1);
+    for idx in 0..nb_segments {
+        let mut segment = [0; SEGMENT_SIZE];
+        let chunk = &data[(idx * SEGMENT_SIZE).min(data.len())..];
+        let chunk = &chunk[..chunk.len().min(SEGMENT_SIZE)];
+        segment[..chunk.len()].copy_from_slice(chunk);
+
+        record.extend_from_slice(&segment);
+        record.extend_from_slice(&crc(&segment).to_be_bytes());
+    }
+    record.extend_from_slice(&TRAILER);
+    record
+}
+
+/// Extract `len` bytes of a record after checking its sync byte and the CRC of each segment
+fn decode_record(sync: u8, record: &[u8], len: usize) -> Result<Vec<u8>, String> {
+    match record.first() {
+        Some(&b) if b == sync => {},
+        Some(b) => return Err(format!("Wrong sync byte 0x{b:02X} instead of 0x{sync:02X}")),
+        None => return Err("Empty record".to_owned())
+    }
+
+    let mut data = Vec::with_capacity(len);
+    let nb_segments = len.div_ceil(SEGMENT_SIZE).max(1);
+    for idx in 0..nb_segments {
+        let start = 1 + idx * (SEGMENT_SIZE + 2);
+        let segment = record
+            .get(start..start + SEGMENT_SIZE + 2)
+            .ok_or_else(|| format!("Record truncated in segment {idx}"))?;
+        let (segment, expected) = segment.split_at(SEGMENT_SIZE);
+        if crc(segment).to_be_bytes() != expected {
+            return Err(format!("Wrong CRC in segment {idx}"));
+        }
+        data.extend_from_slice(segment);
+    }
+    data.truncate(len);
+    Ok(data)
+}
+
+/// The 64 bytes header the firmware writes before each block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TapeBlockHeader {
+    pub filename: String,
+    /// Number of the block, starting at 1
+    pub block_number: u8,
+    pub last_block: bool,
+    pub file_type: u8,
+    /// Number of bytes in this block
+    pub data_length: u16,
+    /// Address where this block is loaded
+    pub data_location: u16,
+    pub first_block: bool,
+    /// Length of the whole file
+    pub logical_length: u16,
+    pub entry_address: u16
+}
+
+impl TapeBlockHeader {
+    pub fn as_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0; HEADER_SIZE];
+        let name = self.filename.as_bytes();
+        let name = &name[..name.len().min(TAPE_FILENAME_SIZE)];
+        bytes[..name.len()].copy_from_slice(name);
+        bytes[16] = self.block_number;
+        bytes[17] = if self.last_block { 0xFF } else { 0x00 };
+        bytes[18] = self.file_type;
+        bytes[19..21].copy_from_slice(&self.data_length.to_le_bytes());
+        bytes[21..23].copy_from_slice(&self.data_location.to_le_bytes());
+        bytes[23] = if self.first_block { 0xFF } else { 0x00 };
+        bytes[24..26].copy_from_slice(&self.logical_length.to_le_bytes());
+        bytes[26..28].copy_from_slice(&self.entry_address.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let word = |idx: usize| u16::from_le_bytes([bytes[idx], bytes[idx + 1]]);
+        let name = &bytes[..TAPE_FILENAME_SIZE];
+        let name = &name[..name
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(TAPE_FILENAME_SIZE)];
+
+        Self {
+            filename: String::from_utf8_lossy(name).trim_end().to_owned(),
+            block_number: bytes[16],
+            last_block: bytes[17] != 0,
+            file_type: bytes[18],
+            data_length: word(19),
+            data_location: word(21),
+            first_block: bytes[23] != 0,
+            logical_length: word(24),
+            entry_address: word(26)
+        }
+    }
+}
+
+/// A file stored on a tape
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TapeFile {
+    filename: String,
+    file_type: u8,
+    loading_address: u16,
+    execution_address: u16,
+    content: Vec<u8>
+}
+
+impl TapeFile {
+    pub fn new<S: Into<String>>(
+        filename: S,
+        file_type: u8,
+        loading_address: u16,
+        execution_address: u16,
+        content: Vec<u8>
+    ) -> Self {
+        Self {
+            filename: filename.into(),
+            file_type,
+            loading_address,
+            execution_address,
+            content
+        }
+    }
+
+    /// Build the tape version of an amsdos file. ASCII files use the ASCII file type
+    pub fn from_amsdos_file(file: &AmsdosFile) -> Result<Self, AmsdosError> {
+        let filename = file
+            .amsdos_filename()
+            .ok_or_else(|| {
+                AmsdosError::WrongFileName {
+                    msg: "A tape file needs a name".to_owned()
+                }
+            })??
+            .ibm_filename();
+
+        let (file_type, loading_address, execution_address) = match file.header() {
+            Some(header) => {
+                (
+                    header.file_type()? as u8,
+                    header.loading_address(),
+                    header.execution_address()
+                )
+            },
+            None => (TAPE_ASCII_TYPE, 0, 0)
+        };
+
+        Ok(Self::new(
+            filename,
+            file_type,
+            loading_address,
+            execution_address,
+            file.content().to_vec()
+        ))
+    }
+
+    /// Build the amsdos version of the file (with a header unless it is an ASCII file)
+    pub fn to_amsdos_file(&self) -> Result<AmsdosFile, AmsdosError> {
+        let filename = AmsdosFileName::try_from(self.filename.as_str())?;
+        if self.is_ascii() {
+            return Ok(AmsdosFile::ascii_file_from_buffer_with_name(
+                &filename,
+                &self.content
+            ));
+        }
+
+        if self.content.len() > 0xFFFF {
+            return Err(AmsdosError::FileLargerThan64Kb);
+        }
+        let file_type =
+            AmsdosFileType::try_from(self.file_type & 0b111).unwrap_or(AmsdosFileType::Binary);
+        let header = AmsdosHeader::build_header(
+            &filename,
+            file_type,
+            self.loading_address,
+            self.execution_address,
+            self.content.len() as u16
+        );
+        AmsdosFile::from_header_and_buffer(header, &self.content)
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    pub fn file_type(&self) -> u8 {
+        self.file_type
+    }
+
+    pub fn is_ascii(&self) -> bool {
+        self.file_type == TAPE_ASCII_TYPE
+    }
+
+    pub fn loading_address(&self) -> u16 {
+        self.loading_address
+    }
+
+    pub fn execution_address(&self) -> u16 {
+        self.execution_address
+    }
+
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+
+    /// Headers of the 2K blocks of the file
+    pub fn block_headers(&self) -> Vec<TapeBlockHeader> {
+        let nb_blocks = self.content.len().div_ceil(TAPE_BLOCK_SIZE).max(1);
+        (0..nb_blocks)
+            .map(|idx| {
+                let start = idx * TAPE_BLOCK_SIZE;
+                let length = (self.content.len() - start).min(TAPE_BLOCK_SIZE);
+                TapeBlockHeader {
+                    filename: self.filename.clone(),
+                    block_number: (idx + 1) as u8,
+                    last_block: idx + 1 == nb_blocks,
+                    file_type: self.file_type,
+                    data_length: length as u16,
+                    data_location: self.loading_address.wrapping_add(start as u16),
+                    first_block: idx == 0,
+                    logical_length: self.content.len() as u16,
+                    entry_address: self.execution_address
+                }
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Display for TapeFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.file_type {
+            TAPE_ASCII_TYPE => "ASCII",
+            t if t & 0b1110 == 0 => "BASIC",
+            _ => "BINARY"
+        };
+        write!(
+            f,
+            "{:16} {kind:6} {:5} bytes load=0x{:04X} exec=0x{:04X}",
+            self.filename,
+            self.content.len(),
+            self.loading_address,
+            self.execution_address
+        )
+    }
+}
+
+/// A raw TZX block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TzxBlock {
+    id: u8,
+    body: Vec<u8>
+}
+
+impl TzxBlock {
+    /// Build a turbo speed data block containing a firmware record
+    pub fn record(data: &[u8], options: &CdtWriteOptions, pause: u16) -> Self {
+        let one = options.one_pulse();
+        let zero = options.zero_pulse();
+        let len = data.len() as u32;
+
+        let mut body = Vec::with_capacity(18 + data.len());
+        body.extend_from_slice(&one.to_le_bytes()); // pilot
+        body.extend_from_slice(&zero.to_le_bytes()); // sync 1
+        body.extend_from_slice(&zero.to_le_bytes()); // sync 2
+        body.extend_from_slice(&zero.to_le_bytes());
+        body.extend_from_slice(&one.to_le_bytes());
+        body.extend_from_slice(&PILOT_PULSES.to_le_bytes());
+        body.push(8); // used bits of the last byte
+        body.extend_from_slice(&pause.to_le_bytes());
+        body.extend_from_slice(&len.to_le_bytes()[..3]);
+        body.extend_from_slice(data);
+
+        Self {
+            id: TZX_TURBO_SPEED_DATA,
+            body
+        }
+    }
+
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    /// Length in T-states of the pulses of a 0 bit of a turbo speed data block
+    pub fn zero_pulse(&self) -> Option<u16> {
+        if self.id == TZX_TURBO_SPEED_DATA {
+            Some(u16::from_le_bytes([self.body[6], self.body[7]]))
+        }
+        else {
+            None
+        }
+    }
+
+    /// Position of the data in the body, and of its length field (with its size)
+    fn data_layout(&self) -> Option<(usize, usize, usize)> {
+        match self.id {
+            TZX_STANDARD_SPEED_DATA => Some((4, 2, 2)),
+            TZX_TURBO_SPEED_DATA => Some((18, 15, 3)),
+            TZX_PURE_DATA => Some((10, 7, 3)),
+            _ => None
+        }
+    }
+
+    /// The bytes stored by the block when it contains data
+    pub fn data(&self) -> Option<&[u8]> {
+        let (start, ..) = self.data_layout()?;
+        self.body.get(start..)
+    }
+
+    /// Replace the bytes stored by a data block while keeping its timings
+    fn set_data(&mut self, data: &[u8]) {
+        let (start, len_pos, len_size) = self
+            .data_layout()
+            .expect("Only data blocks can be modified");
+        self.body.truncate(start);
+        self.body.extend_from_slice(data);
+        self.body[len_pos..len_pos + len_size]
+            .copy_from_slice(&(data.len() as u32).to_le_bytes()[..len_size]);
+    }
+
+    /// The header of the firmware record stored in the block, if any
+    fn record_header(&self) -> Option<TapeBlockHeader> {
+        self.data()
+            .filter(|record| record.first() == Some(&HEADER_SYNC))
+            .and_then(|record| decode_record(HEADER_SYNC, record, HEADER_SIZE).ok())
+            .map(|bytes| TapeBlockHeader::from_bytes(&bytes))
+    }
+
+    /// Read the block starting at `bytes[0]` and return it with its full size
+    fn parse(bytes: &[u8]) -> Result<(Self, usize), String> {
+        let id = bytes[0];
+        let body = &bytes[1..];
+        let at = |idx: usize| -> Result<usize, String> {
+            body.get(idx)
+                .map(|&b| b as usize)
+                .ok_or_else(|| format!("Truncated TZX block 0x{id:02X}"))
+        };
+        let word = |idx: usize| -> Result<usize, String> { Ok(at(idx)? + (at(idx + 1)? << 8)) };
+        let triple =
+            |idx: usize| -> Result<usize, String> { Ok(word(idx)? + (at(idx + 2)? << 16)) };
+        let dword =
+            |idx: usize| -> Result<usize, String> { Ok(word(idx)? + (word(idx + 2)? << 16)) };
+
+        let len = match id {
+            0x10 => 4 + word(2)?,
+            0x11 => 18 + triple(15)?,
+            0x12 => 4,
+            0x13 => 1 + 2 * at(0)?,
+            0x14 => 10 + triple(7)?,
+            0x15 => 8 + triple(5)?,
+            0x18 | 0x19 => 4 + dword(0)?,
+            0x20 | 0x23 | 0x24 => 2,
+            0x21 | 0x30 => 1 + at(0)?,
+            0x22 | 0x25 | 0x27 => 0,
+            0x26 => 2 + 2 * word(0)?,
+            0x28 | 0x32 => 2 + word(0)?,
+            0x2A => 4,
+            0x2B => 5,
+            0x31 => 2 + at(1)?,
+            0x33 => 1 + 3 * at(0)?,
+            0x35 => 20 + dword(16)?,
+            0x5A => 9,
+            _ => return Err(format!("Unknown TZX block 0x{id:02X}"))
+        };
+
+        let body = body
+            .get(..len)
+            .ok_or_else(|| format!("Truncated TZX block 0x{id:02X}"))?;
+        Ok((
+            Self {
+                id,
+                body: body.to_vec()
+            },
+            1 + len
+        ))
+    }
+}
+
+/// A CDT image: a sequence of TZX blocks
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Cdt {
+    blocks: Vec<TzxBlock>
+}
+
+impl Cdt {
+    pub fn open<P: AsRef<Utf8Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let bytes = fs_err::read(path).map_err(|e| format!("Unable to read {path}. {e}"))?;
+        Self::from_bytes(&bytes).map_err(|e| format!("Unable to read {path}. {e}"))
+    }
+
+    /// Open the tape or provide an empty one when it does not exist
+    pub fn open_or_new<P: AsRef<Utf8Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        if path.exists() {
+            Self::open(path)
+        }
+        else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < 10 || &bytes[..8] != TZX_SIGNATURE {
+            return Err("This is not a CDT/TZX file".to_owned());
+        }
+
+        let mut blocks = Vec::new();
+        let mut idx = 10;
+        while idx < bytes.len() {
+            let (block, len) = TzxBlock::parse(&bytes[idx..])?;
+            blocks.push(block);
+            idx += len;
+        }
+        Ok(Self { blocks })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = TZX_SIGNATURE.to_vec();
+        bytes.push(TZX_MAJOR);
+        bytes.push(TZX_MINOR);
+        for block in &self.blocks {
+            bytes.push(block.id);
+            bytes.extend_from_slice(&block.body);
+        }
+        bytes
+    }
+
+    pub fn save<P: AsRef<Utf8Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        fs_err::write(path, self.to_bytes()).map_err(|e| format!("Unable to save {path}. {e}"))
+    }
+
+    pub fn blocks(&self) -> &[TzxBlock] {
+        &self.blocks
+    }
+
+    /// Append a file at the end of the tape
+    pub fn add_file(&mut self, file: &TapeFile, options: &CdtWriteOptions) {
+        for header in file.block_headers() {
+            let start = (header.block_number as usize - 1) * TAPE_BLOCK_SIZE;
+            let data = &file.content[start..start + header.data_length as usize];
+
+            self.blocks.push(TzxBlock::record(
+                &encode_record(HEADER_SYNC, &header.as_bytes()),
+                options,
+                options.pause_after_header
+            ));
+            self.blocks.push(TzxBlock::record(
+                &encode_record(DATA_SYNC, data),
+                options,
+                options.pause_after_data
+            ));
+        }
+    }
+
+    /// Append an amsdos file at the end of the tape.
+    /// `behavior` tells what to do when a file of the same name is already stored
+    pub fn add_amsdos_file(
+        &mut self,
+        file: &AmsdosFile,
+        options: &CdtWriteOptions,
+        behavior: AmsdosAddBehavior
+    ) -> Result<(), AmsdosError> {
+        let file = TapeFile::from_amsdos_file(file)?;
+
+        if self.contains_file(&file.filename) {
+            match behavior {
+                AmsdosAddBehavior::FailIfPresent => {
+                    return Err(AmsdosError::FileAlreadyExists(file.filename));
+                },
+                AmsdosAddBehavior::ReplaceIfPresent
+                | AmsdosAddBehavior::ReplaceAndEraseIfPresent => {
+                    self.remove_file(&file.filename);
+                },
+                AmsdosAddBehavior::BackupIfPresent => {
+                    let mut backup_fname = AmsdosFileName::try_from(file.filename.as_str())?;
+                    backup_fname.set_extension("BAK");
+                    let backup_fname = backup_fname.ibm_filename();
+
+                    self.remove_file(&backup_fname);
+                    self.rename_file(&file.filename, &backup_fname);
+                }
+            }
+        }
+
+        self.add_file(&file, options);
+        Ok(())
+    }
+
+    fn contains_file(&self, filename: &str) -> bool {
+        self.blocks
+            .iter()
+            .filter_map(TzxBlock::record_header)
+            .any(|header| header.filename.eq_ignore_ascii_case(filename))
+    }
+
+    /// Remove all the blocks of a file. Return false if the file is absent
+    pub fn remove_file(&mut self, filename: &str) -> bool {
+        let mut removed = false;
+        let mut remove_data = false;
+        self.blocks.retain(|block| {
+            if block.data().is_none() {
+                return true;
+            }
+            if std::mem::take(&mut remove_data) {
+                return false;
+            }
+
+            remove_data = block
+                .record_header()
+                .is_some_and(|header| header.filename.eq_ignore_ascii_case(filename));
+            removed |= remove_data;
+            !remove_data
+        });
+        removed
+    }
+
+    /// Rename a file by rewriting all its header records. Return false if the file is absent
+    pub fn rename_file(&mut self, filename: &str, new_filename: &str) -> bool {
+        let mut renamed = false;
+        for block in &mut self.blocks {
+            if let Some(mut header) = block.record_header()
+                && header.filename.eq_ignore_ascii_case(filename)
+            {
+                header.filename = new_filename.to_owned();
+                block.set_data(&encode_record(HEADER_SYNC, &header.as_bytes()));
+                renamed = true;
+            }
+        }
+        renamed
+    }
+
+    /// Decode all the files stored with the firmware format.
+    /// Blocks that do not contain firmware records are ignored.
+    pub fn files(&self) -> Result<Vec<TapeFile>, String> {
+        let mut files = Vec::new();
+        let mut current: Option<TapeFile> = None;
+        let mut records = self.blocks.iter().filter_map(TzxBlock::data);
+
+        while let Some(record) = records.next() {
+            if record.first() != Some(&HEADER_SYNC) {
+                continue;
+            }
+            let header =
+                TapeBlockHeader::from_bytes(&decode_record(HEADER_SYNC, record, HEADER_SIZE)?);
+            let data = records.next().ok_or_else(|| {
+                format!(
+                    "Missing data of block {} of {}",
+                    header.block_number, header.filename
+                )
+            })?;
+            let data =
+                decode_record(DATA_SYNC, data, header.data_length as usize).map_err(|e| {
+                    format!("Block {} of {}: {e}", header.block_number, header.filename)
+                })?;
+
+            let file = match current.as_mut() {
+                Some(file) if !header.first_block && file.filename == header.filename => file,
+                _ => {
+                    current.insert(TapeFile::new(
+                        header.filename.clone(),
+                        header.file_type,
+                        header.data_location,
+                        header.entry_address,
+                        Vec::with_capacity(header.logical_length as usize)
+                    ))
+                },
+            };
+            file.content.extend_from_slice(&data);
+
+            if header.last_block {
+                files.extend(current.take());
+            }
+        }
+
+        if let Some(file) = current {
+            return Err(format!("{} has no last block", file.filename));
+        }
+        Ok(files)
+    }
+
+    /// Retrieve a file by its name (case insensitive)
+    pub fn get_file(&self, filename: &str) -> Result<Option<TapeFile>, String> {
+        Ok(self
+            .files()?
+            .into_iter()
+            .find(|f| f.filename.eq_ignore_ascii_case(filename)))
+    }
+}
+
+/// Check if the file name is the one of a tape image
+pub fn is_tape_image<P: AsRef<Utf8Path>>(path: P) -> bool {
+    let lower = path.as_ref().as_str().to_ascii_lowercase();
+    lower.ends_with(".cdt") || lower.ends_with(".tzx")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc_check_value() {
+        // CRC-16/CCITT-FALSE check value, inverted by the firmware
+        assert_eq!(crc(b"123456789"), !0x29B1);
+    }
+
+    #[test]
+    fn write_then_read() {
+        let content = (0..5000).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let file = TapeFile::new("TEST.BIN", 2, 0x4000, 0x4010, content.clone());
+        let text = TapeFile::new("README.TXT", TAPE_ASCII_TYPE, 0, 0, b"hello".to_vec());
+
+        let mut cdt = Cdt::default();
+        cdt.add_file(&file, &CdtWriteOptions::default());
+        cdt.add_file(
+            &text,
+            &CdtWriteOptions {
+                baud: 1000,
+                ..Default::default()
+            }
+        );
+        // 3 blocks of 2 records, then 1 block
+        assert_eq!(cdt.blocks().len(), 8);
+
+        let cdt = Cdt::from_bytes(&cdt.to_bytes()).unwrap();
+        assert_eq!(cdt.files().unwrap(), vec![file.clone(), text]);
+
+        let retrieved = cdt.get_file("test.bin").unwrap().unwrap();
+        let amsdos = retrieved.to_amsdos_file().unwrap();
+        assert!(amsdos.is_binary());
+        assert_eq!(amsdos.content(), &content[..]);
+        assert_eq!(amsdos.header().unwrap().loading_address(), 0x4000);
+        assert_eq!(TapeFile::from_amsdos_file(&amsdos).unwrap(), file);
+    }
+
+    #[test]
+    fn block_layout() {
+        let file = TapeFile::new("A", 2, 0x1000, 0x1000, vec![0xAA; 2100]);
+        let headers = file.block_headers();
+        assert_eq!(headers.len(), 2);
+        assert!(headers[0].first_block && !headers[0].last_block);
+        assert_eq!(headers[1].data_location, 0x1800);
+        assert_eq!(headers[1].data_length, 52);
+        assert_eq!(
+            TapeBlockHeader::from_bytes(&headers[1].as_bytes()),
+            headers[1]
+        );
+
+        // sync + 8 segments with their CRC + trailer
+        let mut cdt = Cdt::default();
+        cdt.add_file(&file, &CdtWriteOptions::default());
+        assert_eq!(cdt.blocks()[1].data().unwrap().len(), 1 + 8 * 258 + 4);
+        assert_eq!(cdt.blocks()[2].data().unwrap()[0], HEADER_SYNC);
+    }
+
+    #[test]
+    fn add_behaviors() {
+        let options = CdtWriteOptions::default();
+        let fname = AmsdosFileName::try_from("code.bin").unwrap();
+        let v1 = AmsdosFile::binary_file_from_buffer(&fname, 0x8000, 0x8000, &[1; 3000]).unwrap();
+        let v2 = AmsdosFile::binary_file_from_buffer(&fname, 0x8000, 0x8000, &[2; 10]).unwrap();
+
+        let mut cdt = Cdt::default();
+        cdt.add_amsdos_file(&v1, &options, AmsdosAddBehavior::FailIfPresent)
+            .unwrap();
+        assert_eq!(
+            cdt.add_amsdos_file(&v2, &options, AmsdosAddBehavior::FailIfPresent),
+            Err(AmsdosError::FileAlreadyExists("CODE.BIN".to_owned()))
+        );
+
+        cdt.add_amsdos_file(&v2, &options, AmsdosAddBehavior::BackupIfPresent)
+            .unwrap();
+        let files = cdt.files().unwrap();
+        assert_eq!(
+            files.iter().map(TapeFile::filename).collect::<Vec<_>>(),
+            ["CODE.BAK", "CODE.BIN"]
+        );
+        assert_eq!(files[0].content(), &[1; 3000]);
+
+        cdt.add_amsdos_file(&v1, &options, AmsdosAddBehavior::ReplaceIfPresent)
+            .unwrap();
+        let files = cdt.files().unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[1].content(), &[1; 3000]);
+        assert_eq!(cdt.blocks().len(), 8);
+    }
+
+    #[test]
+    fn corrupted_data_is_detected() {
+        let mut cdt = Cdt::default();
+        cdt.add_file(
+            &TapeFile::new("A", 2, 0, 0, vec![1, 2, 3]),
+            &CdtWriteOptions::default()
+        );
+        let mut bytes = cdt.to_bytes();
+        let last = bytes.len() - 10;
+        bytes[last] ^= 0xFF;
+        let err = Cdt::from_bytes(&bytes).unwrap().files().unwrap_err();
+        assert!(err.contains("CRC"), "{err}");
+    }
+}
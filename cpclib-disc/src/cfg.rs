@@ -40,6 +40,67 @@ SectorID = 0xc1,0xc6,0xc2,0xc7,0xc3,0xc8,0xc4,0xc9,0xc5
 sectorIDHead = 0,0,0,0,0,0,0,0,0
 ";
 
+const SYSTEM_FORMAT_CFG: &str = "
+NbTrack = 40
+NbHead = 1
+
+[Track:0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31,32,33,34,35,36,37,38,39]
+SectorSize = 512
+Gap3 = 82
+SectorID = 0x41,0x46,0x42,0x47,0x43,0x48,0x44,0x49,0x45
+sectorIDHead = 0,0,0,0,0,0,0,0,0
+";
+
+const IBM_FORMAT_CFG: &str = "
+NbTrack = 40
+NbHead = 1
+
+[Track:0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31,32,33,34,35,36,37,38,39]
+SectorSize = 512
+Gap3 = 0x50
+SectorID = 0x01,0x05,0x02,0x06,0x03,0x07,0x04,0x08
+sectorIDHead = 0,0,0,0,0,0,0,0
+";
+
+const PARADOS80_FORMAT_CFG: &str = "
+NbTrack = 80
+NbHead = 1
+
+[Track:0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31,32,33,34,35,36,37,38,39,40,41,42,43,44,45,46,47,48,49,50,51,52,53,54,55,56,57,58,59,60,61,62,63,64,65,66,67,68,69,70,71,72,73,74,75,76,77,78,79]
+SectorSize = 512
+Gap3 = 0x22
+SectorID = 0x91,0x96,0x92,0x97,0x93,0x98,0x94,0x99,0x95,0x9a
+sectorIDHead = 0,0,0,0,0,0,0,0,0,0
+";
+
+const ROMDOS_D1_FORMAT_CFG: &str = "
+NbTrack = 80
+NbHead = 2
+
+[Track-A:0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31,32,33,34,35,36,37,38,39,40,41,42,43,44,45,46,47,48,49,50,51,52,53,54,55,56,57,58,59,60,61,62,63,64,65,66,67,68,69,70,71,72,73,74,75,76,77,78,79]
+SectorSize = 512
+Gap3 = 82
+SectorID = 0x01,0x06,0x02,0x07,0x03,0x08,0x04,0x09,0x05
+sectorIDHead = 0,0,0,0,0,0,0,0,0
+
+[Track-B:0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31,32,33,34,35,36,37,38,39,40,41,42,43,44,45,46,47,48,49,50,51,52,53,54,55,56,57,58,59,60,61,62,63,64,65,66,67,68,69,70,71,72,73,74,75,76,77,78,79]
+SectorSize = 512
+Gap3 = 82
+SectorID = 0x01,0x06,0x02,0x07,0x03,0x08,0x04,0x09,0x05
+sectorIDHead = 1,1,1,1,1,1,1,1,1
+";
+
+/// Names of the formats known by [DiscConfig::from_format_name]
+pub const DISC_FORMAT_NAMES: [&str; 7] = [
+    "data",
+    "data42",
+    "system",
+    "vendor",
+    "ibm",
+    "parados80",
+    "romdos-d1"
+];
+
 custom_error! {
 #[allow(missing_docs)]
 /// Errors specifics to the configuration manipulation
@@ -86,6 +147,46 @@ impl DiscConfig {
         Self::from_str(DATA_FORMAT42_CFG).unwrap()
     }
 
+    /// SYSTEM format: DATA layout with sectors 0x41-0x49 and two reserved tracks
+    pub fn single_head_system_format() -> Self {
+        Self::from_str(SYSTEM_FORMAT_CFG).unwrap()
+    }
+
+    /// VENDOR format: same layout as SYSTEM, without the CP/M boot tracks
+    pub fn single_head_vendor_format() -> Self {
+        Self::single_head_system_format()
+    }
+
+    /// IBM format: 8 sectors 0x01-0x08 per track and one reserved track
+    pub fn single_head_ibm_format() -> Self {
+        Self::from_str(IBM_FORMAT_CFG).unwrap()
+    }
+
+    /// PARADOS 80 format: 80 tracks of 10 sectors 0x91-0x9A for 3.5" drives
+    pub fn single_head_parados80_format() -> Self {
+        Self::from_str(PARADOS80_FORMAT_CFG).unwrap()
+    }
+
+    /// ROMDOS D1 format: 80 tracks of 9 sectors 0x01-0x09 on both heads
+    pub fn double_head_romdos_d1_format() -> Self {
+        Self::from_str(ROMDOS_D1_FORMAT_CFG).unwrap()
+    }
+
+    /// Return the configuration of one of the [DISC_FORMAT_NAMES]
+    pub fn from_format_name(name: &str) -> Option<Self> {
+        let cfg = match name.to_ascii_lowercase().as_str() {
+            "data" => Self::single_head_data_format(),
+            "data42" => Self::single_head_data42_format(),
+            "system" => Self::single_head_system_format(),
+            "vendor" => Self::single_head_vendor_format(),
+            "ibm" => Self::single_head_ibm_format(),
+            "parados80" => Self::single_head_parados80_format(),
+            "romdos-d1" => Self::double_head_romdos_d1_format(),
+            _ => return None
+        };
+        Some(cfg)
+    }
+
     /// Create a configuration from the provided file
     pub fn new<P: AsRef<Utf8Path>>(p: P) -> Result<Self, DiscConfigError> {
         let mut content = String::new();
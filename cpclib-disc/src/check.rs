@@ -0,0 +1,676 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use cpclib_common::itertools::Itertools;
+
+use crate::amsdos::{
+    AmsdosEntries, AmsdosEntry, AmsdosError, AmsdosFileName, AmsdosHeader, AmsdosManagerMut,
+    AmsdosManagerNonMut, BlocIdx
+};
+use crate::disc::Disc;
+
+/// A consistency problem of the Amsdos catalog of a disc
+#[derive(Debug, Clone, PartialEq)]
+pub enum CatalogProblem {
+    /// The bloc is used by several entries
+    SharedBloc {
+        bloc: BlocIdx,
+        entries: Vec<AmsdosEntry>
+    },
+    /// The entry uses a bloc that is not in the data area of the disc
+    BlocOutOfDisc { bloc: BlocIdx, entry: AmsdosEntry },
+    /// The number of records of the entry does not correspond to its number of blocs
+    WrongRecordCount { entry: AmsdosEntry, nb_blocs: usize },
+    /// The Amsdos header of the file has a wrong checksum
+    BadHeaderChecksum {
+        entries: Vec<AmsdosEntry>,
+        checksum: u16,
+        expected: u16
+    },
+    /// These blocs contain data but belong to no file
+    OrphanBlocs { blocs: Vec<BlocIdx> }
+}
+
+fn format_entry(entry: &AmsdosEntry) -> String {
+    format!(
+        "entry {} ({})",
+        entry.idx(),
+        entry.amsdos_filename().filename_with_user()
+    )
+}
+
+fn format_blocs(blocs: &[BlocIdx]) -> String {
+    blocs.iter().map(|b| b.value()).join(", ")
+}
+
+impl fmt::Display for CatalogProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CatalogProblem::SharedBloc { bloc, entries } => {
+                write!(
+                    f,
+                    "Bloc {} is shared by {}",
+                    bloc.value(),
+                    entries.iter().map(format_entry).join(", ")
+                )
+            },
+            CatalogProblem::BlocOutOfDisc { bloc, entry } => {
+                write!(
+                    f,
+                    "Bloc {} of {} is outside of the disc",
+                    bloc.value(),
+                    format_entry(entry)
+                )
+            },
+            CatalogProblem::WrongRecordCount { entry, nb_blocs } => {
+                write!(
+                    f,
+                    "{} counts {} records for {} blocs",
+                    format_entry(entry),
+                    entry.nb_records(),
+                    nb_blocs
+                )
+            },
+            CatalogProblem::BadHeaderChecksum {
+                entries,
+                checksum,
+                expected
+            } => {
+                write!(
+                    f,
+                    "Header of {} has the checksum 0x{:04X} instead of 0x{:04X} ({})",
+                    entries[0].amsdos_filename().filename_with_user(),
+                    checksum,
+                    expected,
+                    entries.iter().map(format_entry).join(", ")
+                )
+            },
+            CatalogProblem::OrphanBlocs { blocs } => {
+                write!(
+                    f,
+                    "Blocs {} contain data but belong to no file",
+                    format_blocs(blocs)
+                )
+            }
+        }
+    }
+}
+
+impl AmsdosEntries {
+    /// Check the blocs and record counts of the entries that are not erased
+    pub fn check(&self) -> Vec<CatalogProblem> {
+        let dpb = *self.dpb();
+        let data_area = dpb.directory_blocs()..dpb.nb_blocs();
+
+        let mut problems = Vec::new();
+        let mut owners: BTreeMap<BlocIdx, Vec<AmsdosEntry>> = BTreeMap::new();
+        for entry in self.without_erased_entries() {
+            let blocs = entry
+                .all_blocs()
+                .iter()
+                .filter(|b| b.is_valid())
+                .copied()
+                .collect_vec();
+
+            for &bloc in &blocs {
+                if data_area.contains(&(bloc.value() as usize)) {
+                    owners.entry(bloc).or_default().push(*entry);
+                }
+                else {
+                    problems.push(CatalogProblem::BlocOutOfDisc {
+                        bloc,
+                        entry: *entry
+                    });
+                }
+            }
+
+            if entry.page_size() > 128 || entry.nb_blocs() != blocs.len() {
+                problems.push(CatalogProblem::WrongRecordCount {
+                    entry: *entry,
+                    nb_blocs: blocs.len()
+                });
+            }
+        }
+
+        problems.extend(
+            owners
+                .into_iter()
+                .filter(|(_, entries)| entries.len() > 1)
+                .map(|(bloc, entries)| CatalogProblem::SharedBloc { bloc, entries })
+        );
+
+        problems
+    }
+
+    /// Blocs referenced by any entry, even after its last record.
+    /// Blocs of erased files are kept out of the orphans as they can still be undeleted.
+    fn referenced_blocs(&self) -> BTreeSet<BlocIdx> {
+        self.all_entries()
+            .flat_map(|e| e.all_blocs().iter())
+            .filter(|b| b.is_valid())
+            .copied()
+            .collect()
+    }
+
+    /// Entries of each file, sorted by extent
+    fn files(&self) -> Vec<Vec<AmsdosEntry>> {
+        let mut files: Vec<Vec<AmsdosEntry>> = Vec::new();
+        for entry in self.used_entries() {
+            match files
+                .iter_mut()
+                .find(|f| f[0].amsdos_filename() == entry.amsdos_filename())
+            {
+                Some(file) => file.push(*entry),
+                None => files.push(vec![*entry])
+            }
+        }
+
+        for file in &mut files {
+            file.sort_by_key(|e| e.num_page);
+        }
+        files
+    }
+}
+
+/// A bloc filled with a single value has been formatted but never written
+fn contains_data(bloc: &[u8]) -> bool {
+    bloc.iter().any(|&b| b != bloc[0])
+}
+
+#[allow(missing_docs)]
+impl<'dsk, D: Disc> AmsdosManagerNonMut<'dsk, D> {
+    /// Check the catalog, the headers of the files and look for blocs of data that belong to no file
+    pub fn check(&self) -> Vec<CatalogProblem> {
+        let catalog = self.catalog();
+        let mut problems = catalog.check();
+        problems.extend(self.check_headers(&catalog));
+
+        let orphans = self.orphan_blocs(&catalog);
+        if !orphans.is_empty() {
+            problems.push(CatalogProblem::OrphanBlocs { blocs: orphans });
+        }
+
+        problems
+    }
+
+    /// Files whose first bloc starts with their own header are expected to have a valid checksum
+    fn check_headers(&self, catalog: &AmsdosEntries) -> Vec<CatalogProblem> {
+        let mut problems = Vec::new();
+        for entries in catalog.files() {
+            let Some(bloc) = self.first_bloc(&entries[0])
+            else {
+                continue;
+            };
+
+            let header = AmsdosHeader::from_buffer(&bloc);
+            if AmsdosFileName::from_slice(&bloc) == *entries[0].amsdos_filename()
+                && !header.is_checksum_valid()
+            {
+                problems.push(CatalogProblem::BadHeaderChecksum {
+                    checksum: header.checksum(),
+                    expected: header.compute_checksum(),
+                    entries
+                });
+            }
+        }
+        problems
+    }
+
+    fn first_bloc(&self, entry: &AmsdosEntry) -> Option<Vec<u8>> {
+        self.try_read_bloc(*entry.all_blocs().first()?)
+    }
+
+    /// Blocs of the data area that contain data but that no entry references
+    pub fn orphan_blocs(&self, catalog: &AmsdosEntries) -> Vec<BlocIdx> {
+        let dpb = self.dpb();
+        let referenced = catalog.referenced_blocs();
+
+        (dpb.directory_blocs()..dpb.nb_blocs())
+            .map(|bloc| dpb.bloc_idx(bloc as u16))
+            .filter(|bloc| bloc.is_valid() && !referenced.contains(bloc))
+            .filter(|&bloc| {
+                self.try_read_bloc(bloc)
+                    .map(|content| contains_data(&content))
+                    .unwrap_or(false)
+            })
+            .collect()
+    }
+}
+
+#[allow(missing_docs)]
+impl<'dsk, D: Disc> AmsdosManagerMut<'dsk, D> {
+    /// Fix the problems that can be fixed without losing data and describe what has been done.
+    /// Record counts and header checksums are recomputed, orphaned blocs are recovered as
+    /// `ORPHANxx.BIN` files and shared blocs are copied so each entry owns its blocs.
+    /// Blocs outside of the disc are left untouched.
+    pub fn repair(&mut self) -> Result<Vec<String>, AmsdosError> {
+        self.dpb().try_nb_blocs()?;
+        let (problems, orphans) = {
+            let nonmut: AmsdosManagerNonMut<'_, D> = (&*self).into();
+            let catalog = nonmut.catalog();
+            (catalog.check(), nonmut.orphan_blocs(&catalog))
+        };
+        let mut repairs = Vec::new();
+
+        for problem in &problems {
+            if let CatalogProblem::WrongRecordCount { entry, nb_blocs } = problem
+                && *nb_blocs > 0
+            {
+                let mut entry = *entry;
+                entry.set_nb_records(nb_blocs * self.dpb().records_per_bloc());
+                self.update_entry(&entry);
+                repairs.push(format!(
+                    "{} now counts {} records",
+                    format_entry(&entry),
+                    entry.nb_records()
+                ));
+            }
+        }
+
+        repairs.extend(self.repair_headers()?);
+        repairs.extend(self.recover_orphans(&orphans));
+
+        for problem in problems {
+            if let CatalogProblem::SharedBloc { bloc, entries } = problem {
+                repairs.extend(self.unshare_bloc(bloc, &entries[1..], &orphans)?);
+            }
+        }
+
+        Ok(repairs)
+    }
+
+    fn repair_headers(&mut self) -> Result<Vec<String>, AmsdosError> {
+        let bad_headers = {
+            let nonmut: AmsdosManagerNonMut<'_, D> = (&*self).into();
+            let catalog = nonmut.catalog();
+            nonmut
+                .check_headers(&catalog)
+                .into_iter()
+                .filter_map(|problem| {
+                    match problem {
+                        CatalogProblem::BadHeaderChecksum { entries, .. } => {
+                            let bloc = entries[0].all_blocs()[0];
+                            nonmut
+                                .try_read_bloc(bloc)
+                                .map(|content| (bloc, content, entries))
+                        },
+                        _ => None
+                    }
+                })
+                .collect_vec()
+        };
+
+        let mut repairs = Vec::new();
+        for (bloc, mut content, entries) in bad_headers {
+            let mut header = AmsdosHeader::from_buffer(&content);
+            header.update_checksum();
+            content[..AmsdosHeader::HEADER_SIZE].copy_from_slice(header.as_bytes());
+            self.update_bloc(bloc, &content)?;
+            repairs.push(format!(
+                "Header checksum of {} set to 0x{:04X}",
+                entries[0].amsdos_filename().filename_with_user(),
+                header.checksum()
+            ));
+        }
+        Ok(repairs)
+    }
+
+    /// Each run of consecutive orphaned blocs becomes a file that directly uses them
+    fn recover_orphans(&mut self, orphans: &[BlocIdx]) -> Vec<String> {
+        let dpb = *self.dpb();
+        let mut repairs = Vec::new();
+        let mut file_number = 0;
+
+        let runs = orphans
+            .iter()
+            .copied()
+            .enumerate()
+            .chunk_by(|(idx, bloc)| bloc.value() as usize - idx)
+            .into_iter()
+            .map(|(_, run)| run.map(|(_, bloc)| bloc).collect_vec())
+            .collect_vec();
+
+        for run in runs {
+            let catalog = self.catalog();
+            // erased entries still reference the blocs of their file, so they are kept
+            let free_entries = catalog
+                .all_entries()
+                .filter(|e| e.is_never_used())
+                .map(|e| e.idx())
+                .collect_vec();
+            let nb_entries = run.len().div_ceil(dpb.blocs_per_entry());
+            if free_entries.len() < nb_entries {
+                repairs.push(format!(
+                    "No entry left to recover blocs {}",
+                    format_blocs(&run)
+                ));
+                continue;
+            }
+
+            let file_name = loop {
+                let file_name =
+                    AmsdosFileName::new_correct_case(0, format!("ORPHAN{file_number:02}"), "BIN")
+                        .unwrap();
+                file_number += 1;
+                if catalog.for_file(&file_name).next().is_none() {
+                    break file_name;
+                }
+            };
+
+            for (extent, (blocs, idx)) in run
+                .chunks(dpb.blocs_per_entry())
+                .zip(free_entries)
+                .enumerate()
+            {
+                let mut entry = AmsdosEntry::from_buffer_with_dpb(idx, &[0; 32], dpb);
+                entry.file_name = file_name;
+                entry.set_num_page(extent as u8 * (dpb.extent_mask() + 1));
+                entry.set_blocs(blocs);
+                entry.set_nb_records(blocs.len() * dpb.records_per_bloc());
+                self.update_entry(&entry);
+            }
+
+            repairs.push(format!(
+                "Blocs {} recovered as {}",
+                format_blocs(&run),
+                file_name.filename_with_user()
+            ));
+        }
+
+        repairs
+    }
+
+    /// Give a copy of the bloc to each of the entries
+    fn unshare_bloc(
+        &mut self,
+        bloc: BlocIdx,
+        entries: &[AmsdosEntry],
+        orphans: &[BlocIdx]
+    ) -> Result<Vec<String>, AmsdosError> {
+        let content = {
+            let nonmut: AmsdosManagerNonMut<'_, D> = (&*self).into();
+            nonmut.try_read_bloc(bloc)
+        };
+        let Some(content) = content
+        else {
+            return Ok(Vec::new());
+        };
+
+        let mut repairs = Vec::new();
+        for entry in entries {
+            let catalog = self.catalog();
+            let Some(copy) = catalog.available_blocs().find(|b| !orphans.contains(b))
+            else {
+                repairs.push(format!(
+                    "No free bloc left to copy bloc {} for {}",
+                    bloc.value(),
+                    format_entry(entry)
+                ));
+                continue;
+            };
+
+            self.update_bloc(copy, &content)?;
+
+            let mut entry = *catalog.all_entries().nth(entry.idx() as usize).unwrap();
+            for b in entry.blocs.iter_mut().filter(|b| **b == bloc) {
+                *b = copy;
+            }
+            self.update_entry(&entry);
+
+            repairs.push(format!(
+                "Bloc {} copied in bloc {} for {}",
+                bloc.value(),
+                copy.value(),
+                format_entry(&entry)
+            ));
+        }
+        Ok(repairs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amsdos::{AmsdosAddBehavior, AmsdosFile};
+    use crate::cfg::DiscConfig;
+    use crate::edsk::{ExtendedDsk, Head};
+
+    fn binary_file(name: &str, size: usize) -> AmsdosFile {
+        let content = (0..size)
+            .map(|i| (i * 7 + name.len()) as u8)
+            .collect::<Vec<_>>();
+        AmsdosFile::binary_file_from_buffer(
+            &AmsdosFileName::try_from(name).unwrap(),
+            0x4000,
+            0x4000,
+            &content
+        )
+        .unwrap()
+    }
+
+    fn entry(manager: &AmsdosManagerMut<'_, ExtendedDsk>, name: &str) -> AmsdosEntry {
+        let fname = AmsdosFileName::try_from(name).unwrap();
+        *manager.catalog().for_file(&fname).next().unwrap()
+    }
+
+    /// Build a disc with one problem of each kind
+    fn corrupted_disc() -> (ExtendedDsk, Vec<AmsdosFile>, Vec<u8>) {
+        let mut dsk: ExtendedDsk = DiscConfig::single_head_data_format().into();
+        let files = ["a.bin", "b.bin", "c.bin", "d.bin"]
+            .iter()
+            .zip([3000, 2000, 1000, 500])
+            .map(|(name, size)| binary_file(name, size))
+            .collect::<Vec<_>>();
+        let lost = (0..1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+        let mut manager = AmsdosManagerMut::new_from_disc(&mut dsk, Head::A);
+        for file in &files {
+            manager
+                .add_file(file, None, false, false, AmsdosAddBehavior::FailIfPresent)
+                .unwrap();
+        }
+
+        // the last bloc of B.BIN is lost and replaced by the last one of A.BIN
+        let a = entry(&manager, "a.bin");
+        let mut b = entry(&manager, "b.bin");
+        let mut blocs = b.used_blocs().to_vec();
+        *blocs.last_mut().unwrap() = *a.used_blocs().last().unwrap();
+        b.set_blocs(&blocs);
+        b.set_nb_records(blocs.len() * 8);
+        manager.update_entry(&b);
+
+        // C.BIN references a bloc after the end of the disc
+        let mut c = entry(&manager, "c.bin");
+        let mut blocs = c.used_blocs().to_vec();
+        blocs.push(BlocIdx::from(200u8));
+        c.set_blocs(&blocs);
+        c.set_nb_records(blocs.len() * 8);
+        manager.update_entry(&c);
+
+        // D.BIN counts too many records
+        let mut d = entry(&manager, "d.bin");
+        d.set_page_size(0x20);
+        manager.update_entry(&d);
+
+        // A.BIN has a broken header
+        let first = a.used_blocs()[0];
+        let mut content = AmsdosManagerNonMut::from(&manager).read_bloc(first);
+        content[67] ^= 0xFF;
+        manager.update_bloc(first, &content).unwrap();
+
+        // the last bloc of the disc contains data of an unknown file
+        manager.update_bloc(BlocIdx::from(179u8), &lost).unwrap();
+
+        (dsk, files, lost)
+    }
+
+    #[test]
+    fn clean_disc_has_no_problem() {
+        let mut dsk: ExtendedDsk = DiscConfig::single_head_data_format().into();
+        let mut manager = AmsdosManagerMut::new_from_disc(&mut dsk, Head::A);
+        manager
+            .add_file(
+                &binary_file("a.bin", 40_000),
+                None,
+                false,
+                false,
+                AmsdosAddBehavior::FailIfPresent
+            )
+            .unwrap();
+        manager
+            .add_file(
+                &binary_file("b.bin", 100),
+                None,
+                false,
+                false,
+                AmsdosAddBehavior::FailIfPresent
+            )
+            .unwrap();
+        // the blocs of an erased file are not orphaned while its entries are not reused
+        manager
+            .erase_file(AmsdosFileName::try_from("a.bin").unwrap(), false)
+            .unwrap();
+
+        let manager = AmsdosManagerNonMut::new_from_disc(&dsk, Head::A);
+        assert_eq!(manager.check(), Vec::new());
+    }
+
+    #[test]
+    fn problems_are_detected() {
+        let (dsk, ..) = corrupted_disc();
+        let manager = AmsdosManagerNonMut::new_from_disc(&dsk, Head::A);
+        let problems = manager.check();
+        let names = |entries: &[AmsdosEntry]| {
+            entries
+                .iter()
+                .map(|e| e.amsdos_filename().filename())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(problems.len(), 5, "{problems:#?}");
+        assert!(problems.iter().any(|p| {
+            matches!(p, CatalogProblem::SharedBloc { entries, .. }
+                if names(entries) == ["A.BIN", "B.BIN"])
+        }));
+        assert!(problems.iter().any(|p| {
+            matches!(p, CatalogProblem::BlocOutOfDisc { bloc, entry }
+                if bloc.value() == 200 && entry.amsdos_filename().filename() == "C.BIN")
+        }));
+        assert!(problems.iter().any(|p| {
+            matches!(p, CatalogProblem::WrongRecordCount { entry, nb_blocs: 1 }
+                if entry.amsdos_filename().filename() == "D.BIN")
+        }));
+        assert!(problems.iter().any(|p| {
+            matches!(p, CatalogProblem::BadHeaderChecksum { entries, .. }
+                if names(entries) == ["A.BIN"])
+        }));
+        assert!(problems.iter().any(|p| {
+            matches!(p, CatalogProblem::OrphanBlocs { blocs }
+                if blocs.len() == 2 && blocs[1].value() == 179)
+        }));
+    }
+
+    #[test]
+    fn repair_keeps_the_data() {
+        let (mut dsk, files, lost) = corrupted_disc();
+
+        let mut manager = AmsdosManagerMut::new_from_disc(&mut dsk, Head::A);
+        let repairs = manager.repair().unwrap();
+        assert_eq!(repairs.len(), 5, "{repairs:#?}");
+
+        let a = &files[0];
+        let read = manager
+            .get_file(a.amsdos_filename().unwrap().unwrap())
+            .unwrap();
+        assert_eq!(read.content(), a.content());
+        assert!(read.header().unwrap().is_checksum_valid());
+
+        let orphan = manager
+            .get_file(AmsdosFileName::try_from("orphan01.bin").unwrap())
+            .unwrap();
+        assert_eq!(orphan.header_and_content(), lost);
+
+        // only the bloc outside of the disc cannot be fixed
+        let manager = AmsdosManagerNonMut::new_from_disc(&dsk, Head::A);
+        let problems = manager.check();
+        assert_eq!(problems.len(), 1, "{problems:#?}");
+        assert!(matches!(problems[0], CatalogProblem::BlocOutOfDisc { .. }));
+    }
+
+    #[test]
+    fn repair_keeps_erased_entries() {
+        let mut dsk: ExtendedDsk = DiscConfig::single_head_data_format().into();
+        let lost = (0..1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut manager = AmsdosManagerMut::new_from_disc(&mut dsk, Head::A);
+        manager
+            .add_file(
+                &binary_file("a.bin", 100),
+                None,
+                false,
+                false,
+                AmsdosAddBehavior::FailIfPresent
+            )
+            .unwrap();
+        manager
+            .erase_file(AmsdosFileName::try_from("a.bin").unwrap(), false)
+            .unwrap();
+        manager.update_bloc(BlocIdx::from(179u8), &lost).unwrap();
+
+        let erased = *manager
+            .catalog()
+            .all_entries()
+            .find(|e| e.is_erased() && !e.is_never_used())
+            .unwrap();
+        let repairs = manager.repair().unwrap();
+        assert_eq!(repairs, ["Blocs 179 recovered as 0:ORPHAN00.BIN"]);
+        let catalog = manager.catalog();
+        let kept = catalog.all_entries().nth(erased.idx().into()).unwrap();
+        assert_eq!(kept.as_bytes(), erased.as_bytes());
+
+        // once only erased entries are left, the orphans are reported but not recovered
+        let mut dsk: ExtendedDsk = DiscConfig::single_head_data_format().into();
+        let mut manager = AmsdosManagerMut::new_from_disc(&mut dsk, Head::A);
+        manager
+            .set_catalog_slice(&erased.as_bytes().repeat(64))
+            .unwrap();
+        manager.update_bloc(BlocIdx::from(179u8), &lost).unwrap();
+
+        let catalog = manager.catalog().as_bytes();
+        let repairs = manager.repair().unwrap();
+        assert_eq!(repairs, ["No entry left to recover blocs 179"]);
+        assert_eq!(manager.catalog().as_bytes(), catalog);
+    }
+
+    #[test]
+    fn repair_keeps_empty_entries() {
+        let mut dsk: ExtendedDsk = DiscConfig::single_head_data_format().into();
+        let mut manager = AmsdosManagerMut::new_from_disc(&mut dsk, Head::A);
+        manager
+            .add_file(
+                &binary_file("a.bin", 100),
+                None,
+                false,
+                false,
+                AmsdosAddBehavior::FailIfPresent
+            )
+            .unwrap();
+
+        // A.BIN is still in the catalog but has lost its bloc and records
+        let mut a = entry(&manager, "a.bin");
+        a.set_blocs(&[]);
+        manager.update_entry(&a);
+
+        let repairs = manager.repair().unwrap();
+        assert_eq!(repairs.len(), 1, "{repairs:#?}");
+
+        let catalog = manager.catalog();
+        let kept = catalog.all_entries().nth(a.idx().into()).unwrap();
+        assert!(!kept.is_erased());
+        assert_eq!(kept.amsdos_filename().filename(), "A.BIN");
+        assert!(
+            manager
+                .get_file(AmsdosFileName::try_from("orphan00.bin").unwrap())
+                .is_some()
+        );
+    }
+}
@@ -1,5 +1,6 @@
 // http://www.cpcwiki.eu/index.php/Format:DSK_disk_image_file_format
 
+use std::fmt;
 use std::io::prelude::*;
 use std::iter::zip;
 use std::string::ToString;
@@ -8,7 +9,7 @@ use cpclib_common::bitflags::bitflags;
 use cpclib_common::camino::Utf8Path;
 use delegate::delegate;
 use fs_err::File;
-use getset::Getters;
+use getset::{Getters, Setters};
 
 use crate::disc::Disc;
 
@@ -138,8 +139,8 @@ impl DiscInformation {
         data
     }
 
-    /// Build an eDSK from a buffer of bytes
-    ///  TODO manage the case of standard dsk
+    /// Build an eDSK from a buffer of bytes.
+    /// Standard DSK are converted beforehand by [`ExtendedDsk::from_buffer`]
     pub fn from_buffer(buffer: &[u8]) -> Self {
         assert_eq!(buffer.len(), 256);
         assert_eq!(
@@ -318,6 +319,11 @@ impl TrackInformation {
     pub fn real_track_size(&self) -> u16 {
         self.track_size() - 256
     }
+
+    /// Check if the track is present in the image
+    pub fn is_formatted(&self) -> bool {
+        self.track_size != 0
+    }
 }
 
 #[allow(missing_docs)]
@@ -497,6 +503,9 @@ impl TrackInformation {
             buffer.extend_from_slice(&s.values);
         });
 
+        // Sectors with odd sizes or several copies do not end on a 256 bytes boundary
+        buffer.resize(buffer.len().next_multiple_of(256), 0);
+
         // TODO find why this coded was previously present as it raise issues
         // Ensure the size is correct
         // let added_bytes = (buffer.len() - start_size) as u16;
@@ -538,6 +547,28 @@ impl TrackInformation {
     }
 }
 
+/// Layout of the track as shown by the inspection: format parameters then ID field and status of each sector in the physical order
+impl fmt::Display for TrackInformation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Track {} head {}: {} sectors, N={}, GAP#3 0x{:02X}, filler 0x{:02X}, {:?}, {:?}",
+            self.track_number,
+            self.head_number,
+            self.number_of_sectors,
+            self.sector_size,
+            self.gap3_length,
+            self.filler_byte,
+            self.data_rate,
+            self.recording_mode
+        )?;
+        for sector in self.sector_information_list.sectors() {
+            writeln!(f, "  {sector}")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 #[allow(missing_docs)]
 #[derive(Default)]
@@ -606,26 +637,26 @@ impl From<RecordingMode> for u8 {
     }
 }
 
-#[derive(Getters, Debug, Default, PartialEq, Clone, Copy)]
+#[derive(Getters, Setters, Debug, Default, PartialEq, Clone, Copy)]
 #[allow(missing_docs)]
 pub struct SectorInformation {
     /// track (equivalent to C parameter in NEC765 commands)
-    #[get = "pub"]
+    #[getset(get = "pub", set = "pub")]
     pub(crate) track: u8,
     /// Head (equivalent to H parameter in NEC765 commands)
-    #[get = "pub"]
+    #[getset(get = "pub", set = "pub")]
     pub(crate) head: u8,
     /// sector ID (equivalent to R parameter in NEC765 commands)
-    #[get = "pub"]
+    #[getset(get = "pub", set = "pub")]
     pub(crate) sector_id: u8,
     /// sector size (equivalent to N parameter in NEC765 commands)
-    #[get = "pub"]
+    #[getset(get = "pub", set = "pub")]
     pub(crate) sector_size: u8,
     /// FDC status register 1 (equivalent to NEC765 ST1 status register)
-    #[get = "pub"]
+    #[getset(get = "pub", set = "pub")]
     pub(crate) fdc_status_register_1: u8,
     /// FDC status register 2 (equivalent to NEC765 ST2 status register)
-    #[get = "pub"]
+    #[getset(get = "pub", set = "pub")]
     pub(crate) fdc_status_register_2: u8,
     /// actual data length in bytes
     #[get = "pub"]
@@ -635,9 +666,79 @@ pub struct SectorInformation {
 #[allow(missing_docs)]
 #[allow(clippy::trivially_copy_pass_by_ref)]
 impl SectorInformation {
-    /// Return the real size of the sector
+    /// Return the size of the sector declared by N.
+    /// The FDC does not transfer more than 0x8000 bytes whatever N is
     pub fn len(&self) -> usize {
-        self.sector_size as usize * 256
+        convert_fdc_sector_size_to_real_sector_size(self.sector_size.min(8)) as usize
+    }
+
+    /// Number of copies stored for the sector.
+    /// Weak sectors are stored several times, one after the other, as they read differently at each try
+    pub fn nb_copies(&self) -> usize {
+        let len = self.len();
+        let data_length = self.data_length as usize;
+        if data_length > len && data_length.is_multiple_of(len) {
+            data_length / len
+        }
+        else {
+            1
+        }
+    }
+
+    /// Check if the sector is stored several times
+    pub fn is_weak(&self) -> bool {
+        self.nb_copies() > 1
+    }
+
+    /// Check if the data are preceded by a deleted data address mark
+    pub fn has_deleted_data_mark(&self) -> bool {
+        self.st2().contains(FdcStatusRegister2::CONTROL_MARK)
+    }
+
+    /// Check if the FDC reports a CRC error when reading the sector
+    pub fn has_data_error(&self) -> bool {
+        self.st1().contains(FdcStatusRegister1::DATA_ERROR)
+            || self
+                .st2()
+                .contains(FdcStatusRegister2::DATA_ERROR_IN_DATA_FIELD)
+    }
+
+    fn st1(&self) -> FdcStatusRegister1 {
+        FdcStatusRegister1::from_bits_retain(self.fdc_status_register_1)
+    }
+
+    fn st2(&self) -> FdcStatusRegister2 {
+        FdcStatusRegister2::from_bits_retain(self.fdc_status_register_2)
+    }
+
+    /// Describe the particularities of the sector
+    pub fn flags(&self) -> Vec<&'static str> {
+        let mut flags = Vec::new();
+        if self.has_deleted_data_mark() {
+            flags.push("deleted data");
+        }
+        if self.has_data_error() {
+            flags.push("data error");
+        }
+        if self.st1().contains(FdcStatusRegister1::NO_DATA) {
+            flags.push("no data");
+        }
+        if self
+            .st1()
+            .contains(FdcStatusRegister1::MISSING_ADDRESS_MARK)
+        {
+            flags.push("missing address mark");
+        }
+        if self
+            .st2()
+            .contains(FdcStatusRegister2::MISSING_ADDRESS_MARK_IN_DATA_FIELD)
+        {
+            flags.push("missing data address mark");
+        }
+        if self.st1().contains(FdcStatusRegister1::END_OF_CYLINDER) {
+            flags.push("end of cylinder");
+        }
+        flags
     }
 
     /// Check if the sector is empty
@@ -803,7 +904,7 @@ bitflags! {
 
 bitflags! {
     struct FdcStatusRegister2: u8 {
-        const CONTROL_MARK = 1<<5;
+        const CONTROL_MARK = 1<<6;
         const DATA_ERROR_IN_DATA_FIELD = 1<<5;
         const MISSING_ADDRESS_MARK_IN_DATA_FIELD = 1<<0;
     }
@@ -813,7 +914,7 @@ bitflags! {
 #[allow(missing_docs)]
 #[allow(unused)]
 pub struct Sector {
-    #[getset(get)]
+    #[getset(get = "pub")]
     pub(crate) sector_information_bloc: SectorInformation,
     /// Some DSK seem to have a vector with not the right size. In tFor this reason, it is better to not give access to it directly
     pub(crate) values: Vec<u8>
@@ -833,8 +934,12 @@ impl Sector {
         self.values.len() as u16
     }
 
+    /// Number of bytes read by the FDC.
+    /// Bytes stored after the declared size are gap bytes of the track or other copies of a weak sector.
+    /// Less bytes than the declared size can be stored (for example with N=6 on a full track)
+    #[allow(clippy::cast_possible_truncation)]
     pub fn len(&self) -> u16 {
-        self.sector_information_bloc.len() as u16
+        self.sector_information_bloc.len().min(self.values.len()) as u16
     }
 
     pub fn is_empty(&self) -> bool {
@@ -854,7 +959,61 @@ impl Sector {
         &mut self.values[..idx]
     }
 
-    /// Set all the values stored in the sector
+    /// Returns all the bytes stored for the sector, including the gap bytes or the other copies
+    pub fn stored_values(&self) -> &[u8] {
+        &self.values
+    }
+
+    /// Returns the copies of a weak sector, or the only version of a normal one
+    pub fn copies(&self) -> impl Iterator<Item = &[u8]> {
+        let nb_copies = self.sector_information_bloc.nb_copies();
+        // sectors without data have no copy
+        self.values
+            .chunks(self.len().max(1) as usize)
+            .take(nb_copies)
+    }
+
+    /// Replace the stored data by the given copies.
+    /// Several copies make a weak sector and must all have the size declared by N.
+    /// A single copy may have any size: bigger than N to store gap bytes, smaller to truncate the sector
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn set_copies(&mut self, copies: &[&[u8]]) -> Result<(), String> {
+        let declared = self.sector_information_bloc.len();
+        match copies {
+            [] => return Err("A sector needs at least one copy of its data".to_owned()),
+            [_] => {},
+            _ => {
+                if let Some(copy) = copies.iter().find(|c| c.len() != declared) {
+                    return Err(format!(
+                        "Copies of a weak sector must have {} bytes, not {}.",
+                        declared,
+                        copy.len()
+                    ));
+                }
+            },
+        }
+
+        let values = copies.concat();
+        if values.len() > u16::MAX as usize {
+            return Err(format!(
+                "{} bytes cannot be stored in a sector.",
+                values.len()
+            ));
+        }
+
+        self.sector_information_bloc.data_length = values.len() as u16;
+        self.values = values;
+        Ok(())
+    }
+
+    /// Set or remove the deleted data address mark
+    pub fn set_deleted_data_mark(&mut self, deleted: bool) {
+        let mut st2 = self.sector_information_bloc.st2();
+        st2.set(FdcStatusRegister2::CONTROL_MARK, deleted);
+        self.sector_information_bloc.fdc_status_register_2 = st2.bits();
+    }
+
+    /// Set the values read by the FDC. They are written in each copy of a weak sector
     pub fn set_values(&mut self, data: &[u8]) -> Result<(), String> {
         if data.len() < self.len() as usize {
             return Err(format!(
@@ -872,7 +1031,48 @@ impl Sector {
             ));
         }
 
-        self.values[..].clone_from_slice(data);
+        let len = self.len().max(1) as usize;
+        let nb_copies = self.sector_information_bloc.nb_copies();
+        self.values
+            .chunks_mut(len)
+            .take(nb_copies)
+            .for_each(|copy| copy.clone_from_slice(data));
+        Ok(())
+    }
+}
+
+impl fmt::Display for Sector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let info = &self.sector_information_bloc;
+        write!(
+            f,
+            "C=0x{:02X} H=0x{:02X} R=0x{:02X} N=0x{:02X} ST1=0x{:02X} ST2=0x{:02X} ",
+            info.track,
+            info.head,
+            info.sector_id,
+            info.sector_size,
+            info.fdc_status_register_1,
+            info.fdc_status_register_2
+        )?;
+
+        if (self.len() as usize) < info.len() {
+            write!(f, "{} of {} bytes", self.len(), info.len())?;
+        }
+        else {
+            write!(f, "{} bytes", self.len())?;
+        }
+
+        if info.is_weak() {
+            write!(f, ", {} copies", info.nb_copies())?;
+        }
+        else if self.values.len() > info.len() {
+            write!(f, ", {} stored", self.values.len())?;
+        }
+
+        let flags = info.flags();
+        if !flags.is_empty() {
+            write!(f, " [{}]", flags.join(", "))?;
+        }
         Ok(())
     }
 }
@@ -910,11 +1110,27 @@ impl TrackInformationList {
 
     /// Write the track list in the given buffer
     fn to_buffer(&self, buffer: &mut Vec<u8>) {
-        for track in &self.list {
+        for track in self.list.iter().filter(|t| t.is_formatted()) {
             track.to_buffer(buffer);
         }
     }
 
+    /// High bytes of the size of each track, including its header, as expected in the disc information
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn track_size_table(&self) -> Vec<u8> {
+        self.list
+            .iter()
+            .map(|t| {
+                if t.is_formatted() {
+                    ((256 + t.compute_track_size()) / 256) as u8
+                }
+                else {
+                    0
+                }
+            })
+            .collect()
+    }
+
     /// Add an empty track and return it. It is up to the caller to properly feed it
     pub fn add_empty_track(&mut self) -> &mut TrackInformation {
         let track = TrackInformation::default();
@@ -960,10 +1176,59 @@ impl Default for ExtendedDsk {
     }
 }
 
+/// Standard DSK images use the same size for all the tracks and do not store the length of the sectors.
+/// They are rewritten with the layout of an extended DSK
+#[allow(clippy::cast_possible_truncation)]
+fn standard_dsk_as_extended(buffer: &[u8]) -> Vec<u8> {
+    let number_of_tracks = buffer[0x30] as usize;
+    let number_of_heads = buffer[0x31] as usize;
+    let track_size = u16::from_le_bytes([buffer[0x32], buffer[0x33]]) as usize;
+
+    let mut extended = Vec::with_capacity(buffer.len());
+    extended.extend_from_slice(b"EXTENDED CPC DSK File\r\nDisk-Info\r\n");
+    extended.extend_from_slice(&buffer[0x22..0x32]);
+    extended.resize(256, 0);
+
+    // the track size table of the disc information stops at the end of its bloc
+    let tracks = buffer[256..]
+        .chunks(track_size.max(256))
+        .take((number_of_tracks * number_of_heads).min(256 - 0x34));
+    for (idx, track) in tracks.enumerate() {
+        // a truncated track without its whole header is kept unformatted
+        if track.len() < 256 {
+            break;
+        }
+
+        let mut track = track.to_vec();
+        let mut available = track.len() - 256;
+        // the sector information list cannot go past the header
+        track[0x15] = track[0x15].min(((256 - 0x18) / 8) as u8);
+        for sector in 0..track[0x15] as usize {
+            let info = 0x18 + sector * 8;
+            let length = convert_fdc_sector_size_to_real_sector_size(track[info + 3].min(8))
+                .min(available as u16);
+            available -= length as usize;
+            track[info + 6..info + 8].copy_from_slice(&length.to_le_bytes());
+        }
+        // the padding of the track is not part of an extended dsk,
+        // but a truncated track is completed up to its size in the table
+        track.resize((track.len() - available).next_multiple_of(256), 0);
+
+        extended[0x34 + idx] = (track.len() / 256) as u8;
+        extended.extend_from_slice(&track);
+    }
+
+    extended
+}
+
 #[allow(missing_docs)]
 impl ExtendedDsk {
+    /// Build the dsk from the content of an extended or a standard DSK file
     pub fn from_buffer(buffer: &[u8]) -> Self {
         assert!(buffer.len() >= 256);
+        if buffer.starts_with(b"MV - CPC") {
+            return Self::from_buffer(&standard_dsk_as_extended(buffer));
+        }
         let disc_info = DiscInformation::from_buffer(&buffer[..256]);
 
         let track_list =
@@ -1004,9 +1269,12 @@ impl ExtendedDsk {
         Ok(pos)
     }
 
-    /// Write the dsk in the provided buffer
+    /// Write the dsk in the provided buffer.
+    /// The size of the tracks is computed again as the sectors may have been modified
     pub fn to_buffer(&self, buffer: &mut Vec<u8>) {
-        self.disc_information_bloc.to_buffer(buffer);
+        let mut disc_information_bloc = self.disc_information_bloc.clone();
+        disc_information_bloc.track_size_table = self.track_list.track_size_table();
+        disc_information_bloc.to_buffer(buffer);
         self.track_list.to_buffer(buffer);
     }
 
@@ -1160,7 +1428,7 @@ impl Disc for ExtendedDsk {
         sector_id: u8
     ) -> Option<Vec<u8>> {
         self.sector(head, track, sector_id)
-            .map(|s| s.values.clone())
+            .map(|s| s.values().to_vec())
     }
 
     fn sector_write_bytes<S: Into<Head>>(
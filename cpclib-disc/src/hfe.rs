@@ -1,6 +1,6 @@
 // typedef struct picfileformatheader_
 // {
-// unsigned char HEADERSIGNATURE[8]; // “HXCPICFE”
+// unsigned char HEADERSIGNATURE[8]; // “HXCPICFE” or "HXCHFEV3"
 // unsigned char formatrevision; // Revision 0
 // unsigned char number_of_track; // Number of track in the file
 // unsigned char number_of_side; // Number of valid side (Not used by the emulator)
@@ -20,22 +20,69 @@
 // unsigned char track0s1_altencoding; // 0x00 : Use an alternate track_encoding for track 0 Side 1
 // unsigned char track0s1_encoding; // alternate track_encoding for track 0 Side 1
 // }picfileformatheader;
+//
+// The track list contains for each track its offset in blocks of 512 bytes and its length in bytes.
+// The data of the two sides are interleaved by chunks of 256 bytes and the cells of each byte are sent from bit 0 to bit 7.
+// HFE v3 adds opcodes in the stream: bytes with their 4 low bits set (0xF0 to 0xF4 once the bits are reversed).
+
+use std::fs::File;
+use std::io::{Read, Write};
 
-use camino_tempfile::Builder;
 use cpclib_common::camino::Utf8Path;
-use hxcfe::{Hxcfe, Img, TrackEncoding};
+use delegate::delegate;
+use getset::{Getters, MutGetters, Setters};
 
 use crate::builder::build_edsk_from_cfg;
 use crate::cfg::DiscConfig;
 use crate::disc::Disc;
-use crate::edsk::{ExtendedDsk, Head};
+use crate::edsk::{DiscInformation, ExtendedDsk, Head, TrackInformationList};
+use crate::mfm::{self, WEAK_CELL};
 
-#[derive(Debug)]
-pub struct Hfe {
-    img: Img
+const HFE_V1_SIGNATURE: &[u8; 8] = b"HXCPICFE";
+const HFE_V3_SIGNATURE: &[u8; 8] = b"HXCHFEV3";
+
+const ISOIBM_MFM_ENCODING: u8 = 0x00;
+const CPC_DD_FLOPPYMODE: u8 = 0x06;
+
+const OPCODE_NOP: u8 = 0x0F;
+const OPCODE_SET_INDEX: u8 = 0x8F;
+const OPCODE_SET_BITRATE: u8 = 0x4F;
+const OPCODE_SKIP_BITS: u8 = 0xCF;
+const OPCODE_RAND: u8 = 0x2F;
+
+/// Revision of the HFE format
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HfeVersion {
+    /// Raw MFM stream understood by all the tools
+    #[default]
+    V1,
+    /// Stream with opcodes. Weak sectors are kept
+    V3
 }
 
-impl Hfe {}
+/// HFE image of a disc.
+/// The tracks are decoded when opening the image and encoded again when saving it
+#[derive(Debug, Clone, PartialEq, Getters, MutGetters, Setters)]
+pub struct Hfe {
+    /// Revision used to save the image
+    #[getset(get = "pub", set = "pub")]
+    version: HfeVersion,
+    /// Bitrate in kbit/s
+    #[getset(get = "pub", set = "pub")]
+    bitrate: u16,
+    /// Rotation speed in rotations per minute
+    #[getset(get = "pub", set = "pub")]
+    rpm: u16,
+    /// Interface mode expected by the HxC floppy emulator
+    #[getset(get = "pub", set = "pub")]
+    interface_mode: u8,
+    /// The emulator can write on the disc
+    #[getset(get = "pub", set = "pub")]
+    write_allowed: bool,
+    /// Content of the disc
+    #[getset(get = "pub", get_mut = "pub")]
+    dsk: ExtendedDsk
+}
 
 impl Default for Hfe {
     fn default() -> Self {
@@ -44,154 +91,319 @@ impl Default for Hfe {
     }
 }
 
-impl Disc for Hfe {
-    fn open<P: AsRef<Utf8Path>>(fname: P) -> Result<Self, String> {
-        let hxcfe = Hxcfe::get();
-        hxcfe.load(fname.as_ref()).map(|img| Hfe { img })
-    }
-
-    fn save<P>(&self, path: P) -> Result<(), String>
-    where P: AsRef<Utf8Path> {
-        let path = path.as_ref();
-        let format = match path.extension().unwrap().to_lowercase().as_str() {
-            "dsk" | "edsk" => hxcfe::ImageFormat::AmstradcpcDsk,
-            "hfe" => hxcfe::ImageFormat::HxcHfe,
-            _ => return Err(format!("i do not know how to save {}", path))
+#[allow(missing_docs)]
+impl Hfe {
+    /// Decode the tracks of an HFE v1 or v3 image
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn from_buffer(buffer: &[u8]) -> Result<Self, String> {
+        if buffer.len() < 512 {
+            return Err("HFE file is too small".to_owned());
+        }
+        let signature = &buffer[..8];
+        let version = if signature == HFE_V1_SIGNATURE {
+            HfeVersion::V1
+        }
+        else if signature == HFE_V3_SIGNATURE {
+            HfeVersion::V3
+        }
+        else {
+            return Err(format!(
+                "Wrong HFE signature {:?}",
+                String::from_utf8_lossy(signature)
+            ));
         };
-        self.img.save(path, format)
-    }
-
-    fn sector_read_bytes<S: Into<Head>>(
-        &self,
-        head: S,
-        track: u8,
-        sector_id: u8
-    ) -> Option<Vec<u8>> {
-        let _hxcfe = Hxcfe::get();
-
-        let head: i32 = head.into().into();
-        assert!(head == 0 || head == 1);
-
-        let sector_access = self.img.sector_access().unwrap();
-        let cfg = sector_access.search_sector(
-            head.into(),
-            (track as i32).into(),
-            (sector_id as i32).into(),
-            TrackEncoding::IsoibmMfm
-        )?;
-        let data = cfg.read().to_vec();
-
-        Some(data)
-    }
-
-    fn sector_write_bytes<S: Into<Head>>(
-        &mut self,
-        head: S,
-        track: u8,
-        sector_id: u8,
-        bytes: &[u8]
-    ) -> Result<(), String> {
-        let head: i32 = head.into().into();
-        let encoding = TrackEncoding::IsoibmMfm;
-        let sector_access = self.img.sector_access().unwrap();
-        let mut cfg = sector_access
-            .search_sector(
-                head.into(),
-                (track as i32).into(),
-                (sector_id as i32).into(),
-                encoding
-            )
-            .ok_or_else(|| "sector not found".to_owned())?;
-
-        cfg.write(encoding, bytes); // TODO handle error
-        Ok(())
-    }
-
-    fn global_min_sector<S: Into<Head>>(&self, side: S) -> u8 {
-        let _side = side.into();
-        let _access = self.img.sector_access().unwrap();
-        let mut min_sector = u8::MAX;
-        for t in 0..(self.img.nb_tracks()) {
-            for s in 0..self.img.nb_sides() {
-                min_sector = min_sector.min(self.track_min_sector(s as u8, t as _));
-            }
+
+        let number_of_tracks = buffer[9];
+        let number_of_sides = buffer[10];
+        let track_encoding = buffer[11];
+        let bitrate = u16::from_le_bytes([buffer[12], buffer[13]]);
+        let rpm = u16::from_le_bytes([buffer[14], buffer[15]]);
+        let interface_mode = buffer[16];
+        let track_list_offset = u16::from_le_bytes([buffer[18], buffer[19]]) as usize * 512;
+        let write_allowed = buffer[20] != 0x00;
+
+        if track_encoding != ISOIBM_MFM_ENCODING {
+            return Err(format!(
+                "Track encoding 0x{track_encoding:02X} is not handled. Only ISO MFM is."
+            ));
+        }
+        if !(1..=2).contains(&number_of_sides) {
+            return Err(format!("Wrong number of sides {number_of_sides}"));
         }
 
-        if min_sector == u8::MAX {
-            panic!(
-                "HFE image has no formatted tracks. \
-                 Unable to determine minimum sector ID."
-            );
+        let mut track_list = TrackInformationList::default();
+        for track_number in 0..number_of_tracks {
+            let lut = track_list_offset + track_number as usize * 4;
+            let entry = buffer
+                .get(lut..lut + 4)
+                .ok_or_else(|| format!("Track {track_number} is missing in the track list"))?;
+            let offset = u16::from_le_bytes([entry[0], entry[1]]) as usize * 512;
+            let length = u16::from_le_bytes([entry[2], entry[3]]) as usize;
+            let data = buffer
+                .get(offset..offset + length.next_multiple_of(512))
+                .or_else(|| buffer.get(offset..offset + length))
+                .ok_or_else(|| format!("Track {track_number} is outside of the file"))?;
+
+            for head_number in 0..number_of_sides {
+                let side = side_of_track(data, length, head_number);
+                let cells = match version {
+                    HfeVersion::V1 => raw_cells(&side),
+                    HfeVersion::V3 => v3_cells(&side)
+                };
+                let track = mfm::decode_track(&cells, track_number, head_number);
+                track_list.list.push(track);
+            }
         }
 
-        min_sector as _
-    }
-
-    fn track_min_sector<S: Into<Head>>(&self, side: S, track: u8) -> u8 {
-        let s: i32 = side.into().into();
-        let access = self.img.sector_access().unwrap();
-        let sca =
-            access.all_track_sectors(s.into(), (track as i32).into(), TrackEncoding::IsoibmMfm);
-        let sca = match sca {
-            Some(sca) => sca,
-            None => {
-                // Try FM encoding if MFM fails
-                access
-                    .all_track_sectors(s.into(), (track as i32).into(), TrackEncoding::IsoibmFm)
-                    .unwrap_or_else(|| {
-                        panic!(
-                            "HFE image has unformatted track: side={}, track={}. \
-                         The track contains no sectors in either MFM or FM encoding.",
-                            s, track
-                        )
-                    })
+        let disc_information_bloc = DiscInformation {
+            creator_name: "RUST CPC - BND".to_owned(),
+            number_of_tracks,
+            number_of_heads: number_of_sides,
+            track_size_table: track_list.track_size_table()
+        };
+
+        Ok(Self {
+            version,
+            bitrate,
+            rpm,
+            interface_mode,
+            write_allowed,
+            dsk: ExtendedDsk {
+                disc_information_bloc,
+                track_list
+            }
+        })
+    }
+
+    /// Encode the tracks in the HFE format of the selected version.
+    /// Tracks too long for one rotation are kept longer
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn to_buffer(&self, buffer: &mut Vec<u8>) {
+        let start = buffer.len();
+        let nb_tracks = self.dsk.nb_tracks_per_head();
+        let nb_heads = self.dsk.nb_heads();
+
+        // Header
+        let mut header = Vec::with_capacity(512);
+        header.extend_from_slice(match self.version {
+            HfeVersion::V1 => HFE_V1_SIGNATURE,
+            HfeVersion::V3 => HFE_V3_SIGNATURE
+        });
+        header.push(0); // revision
+        header.push(nb_tracks);
+        header.push(nb_heads);
+        header.push(ISOIBM_MFM_ENCODING);
+        header.extend_from_slice(&self.bitrate.to_le_bytes());
+        header.extend_from_slice(&self.rpm.to_le_bytes());
+        header.push(self.interface_mode);
+        header.push(0x01); // dnu
+        header.extend_from_slice(&1_u16.to_le_bytes()); // track list offset
+        header.push(if self.write_allowed { 0xFF } else { 0x00 });
+        header.push(0xFF); // single step
+        header.extend_from_slice(&[0xFF; 4]); // no alternate encoding for track 0
+        header.resize(512, 0xFF);
+        buffer.extend_from_slice(&header);
+
+        // Tracks
+        let sides = (0..nb_tracks)
+            .map(|track| {
+                let mut sides = (0..nb_heads)
+                    .map(|head| self.side_bytes(head, track))
+                    .collect::<Vec<_>>();
+                let length = sides.iter().map(Vec::len).max().unwrap_or(0);
+                // Single sided discs still have room for the second side
+                sides.resize(2, Vec::new());
+                sides.iter_mut().for_each(|side| pad_side(side, length));
+                sides
+            })
+            .collect::<Vec<_>>();
+
+        let lut_blocks = (nb_tracks as usize * 4).div_ceil(512);
+        let mut lut = Vec::new();
+        let mut data = Vec::new();
+        for sides in &sides {
+            let offset = 1 + lut_blocks + data.len() / 512;
+            let side_length = sides[0].len();
+            lut.extend_from_slice(&(offset as u16).to_le_bytes());
+            lut.extend_from_slice(&((side_length * 2) as u16).to_le_bytes());
+
+            for chunk in 0..side_length.div_ceil(256) {
+                for side in sides {
+                    let mut bytes = side[chunk * 256..side_length.min((chunk + 1) * 256)].to_vec();
+                    bytes.resize(256, 0x00);
+                    data.extend_from_slice(&bytes);
+                }
             }
+        }
+        lut.resize(lut_blocks * 512, 0xFF);
+        buffer.extend_from_slice(&lut);
+        buffer.extend_from_slice(&data);
+
+        debug_assert_eq!((buffer.len() - start) % 512, 0);
+    }
+
+    /// Number of bytes of the stream of a side for one rotation
+    fn rotation_length(&self) -> usize {
+        let rpm = if self.rpm == 0 {
+            300
+        }
+        else {
+            self.rpm as usize
         };
+        // 2 cells per bit, 8 cells per byte
+        self.bitrate as usize * 1000 * 2 * 60 / rpm / 8
+    }
 
-        (0..sca.nb_sectors())
-            .map(|k| sca.sector_config(k).sector_id().get() as u8)
-            .min()
-            .unwrap_or_else(|| {
-                panic!(
-                    "HFE track has no sector IDs: side={}, track={}. \
-                 Track exists but contains no valid sectors.",
-                    s, track
-                )
+    /// Bytes of the stream of a side, as stored in the file
+    fn side_bytes(&self, head: u8, track: u8) -> Vec<u8> {
+        let track = self
+            .dsk
+            .get_track_information(head, track)
+            .expect("Track is present");
+        let cells = mfm::encode_track(track, self.rotation_length() / 2);
+        self.side_bytes_of_cells(&cells)
+    }
+
+    fn side_bytes_of_cells(&self, cells: &[u8]) -> Vec<u8> {
+        cells
+            .chunks(8)
+            .map(|cells| {
+                if self.version == HfeVersion::V3 && cells.iter().any(|c| c & WEAK_CELL != 0) {
+                    OPCODE_RAND
+                }
+                else {
+                    cells
+                        .iter()
+                        .enumerate()
+                        .fold(0, |byte, (idx, cell)| byte | ((cell & 1) << idx))
+                }
             })
+            .collect()
     }
+}
 
-    fn nb_tracks_per_head(&self) -> u8 {
-        self.img.nb_tracks_per_head() as _
+/// Extract the stream of a side from the interleaved data of the track
+fn side_of_track(data: &[u8], length: usize, head: u8) -> Vec<u8> {
+    let side_length = length / 2;
+    data.chunks(512)
+        .flat_map(|block| {
+            let start = (head as usize * 256).min(block.len());
+            let end = (start + 256).min(block.len());
+            &block[start..end]
+        })
+        .take(side_length)
+        .copied()
+        .collect()
+}
+
+/// Cells of an HFE v1 stream
+fn raw_cells(bytes: &[u8]) -> Vec<u8> {
+    bytes
+        .iter()
+        .flat_map(|byte| (0..8).map(move |idx| (byte >> idx) & 1))
+        .collect()
+}
+
+/// Cells of an HFE v3 stream. Random bytes are returned as weak cells
+fn v3_cells(bytes: &[u8]) -> Vec<u8> {
+    let mut cells = Vec::with_capacity(bytes.len() * 8);
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let byte = bytes[idx];
+        idx += 1;
+        match byte {
+            OPCODE_NOP | OPCODE_SET_INDEX => {},
+            OPCODE_SET_BITRATE => idx += 1,
+            OPCODE_SKIP_BITS => {
+                let skipped = bytes.get(idx).copied().unwrap_or(0).min(8);
+                let data = bytes.get(idx + 1).copied().unwrap_or(0);
+                cells.extend((skipped..8).map(|bit| (data >> bit) & 1));
+                idx += 2;
+            },
+            OPCODE_RAND => cells.extend([WEAK_CELL; 8]),
+            _ => cells.extend((0..8).map(|bit| (byte >> bit) & 1))
+        }
     }
+    cells
+}
 
-    fn next_position(&self, head: u8, track: u8, sector: u8) -> Option<(u8, u8, u8)> {
-        let _head = head;
-        let _track = track;
-        let _sector = sector;
-        unimplemented!()
+/// Fill the stream of a side with gap bytes up to the expected length
+fn pad_side(side: &mut Vec<u8>, length: usize) {
+    // 0x4E with its clocks once the bits are reversed
+    let gap = [0x49, 0x2A];
+    while side.len() < length {
+        side.push(gap[side.len() % 2]);
+    }
+}
+
+impl Disc for Hfe {
+    delegate! {
+        to self.dsk {
+            fn next_position(&self, head: u8, track: u8, sector: u8) -> Option<(u8, u8, u8)>;
+            fn global_min_sector<S: Into<Head>>(&self, side: S) -> u8;
+            fn track_min_sector<S: Into<Head>>(&self, side: S, track: u8) -> u8;
+            fn nb_tracks_per_head(&self) -> u8;
+            fn sector_read_bytes<S: Into<Head>>(
+                &self,
+                head: S,
+                track: u8,
+                sector_id: u8
+            ) -> Option<Vec<u8>>;
+            fn sector_write_bytes<S: Into<Head>>(
+                &mut self,
+                head: S,
+                track: u8,
+                sector_id: u8,
+                bytes: &[u8]
+            ) -> Result<(), String>;
+        }
+    }
+
+    fn open<P: AsRef<Utf8Path>>(fname: P) -> Result<Self, String> {
+        let mut buffer = Vec::new();
+        File::open(fname.as_ref())
+            .and_then(|mut f| f.read_to_end(&mut buffer))
+            .map_err(|e| e.to_string())?;
+        Self::from_buffer(&buffer)
+    }
+
+    fn save<P>(&self, path: P) -> Result<(), String>
+    where P: AsRef<Utf8Path> {
+        let path = path.as_ref();
+        match path.extension().map(|ext| ext.to_lowercase()).as_deref() {
+            Some("dsk" | "edsk") => self.dsk.save(path),
+            Some("hfe") => {
+                let mut buffer = Vec::new();
+                self.to_buffer(&mut buffer);
+                File::create(path)
+                    .and_then(|mut f| f.write_all(&buffer))
+                    .map_err(|e| e.to_string())
+            },
+            _ => Err(format!("i do not know how to save {}", path))
+        }
     }
 }
 
 impl From<ExtendedDsk> for Hfe {
-    // TODO do it WITHOUT saving a disc
     fn from(dsk: ExtendedDsk) -> Self {
-        // Save the DSK on disc
-        let tmp = Builder::new()
-            .suffix(".dsk")
-            .rand_bytes(6)
-            .tempfile()
-            .unwrap();
-        let fname = tmp.into_temp_path();
-        let fname = fname.to_path_buf();
-        dsk.save(&fname).unwrap();
-
-        // Reload it as an hfe
-        Hfe::open(fname).unwrap()
+        Self {
+            version: HfeVersion::default(),
+            bitrate: 250,
+            rpm: 300,
+            interface_mode: CPC_DD_FLOPPYMODE,
+            write_allowed: true,
+            dsk
+        }
+    }
+}
+
+impl From<Hfe> for ExtendedDsk {
+    fn from(hfe: Hfe) -> Self {
+        hfe.dsk
     }
 }
 
 #[allow(missing_docs)]
-// TODO implement directly without conversion from dsk
 impl From<DiscConfig> for Hfe {
     fn from(config: DiscConfig) -> Self {
         Hfe::from(build_edsk_from_cfg(&config))
@@ -199,7 +411,6 @@ impl From<DiscConfig> for Hfe {
 }
 
 #[allow(missing_docs)]
-// TODO implement directly without conversion from dsk
 impl From<&DiscConfig> for Hfe {
     fn from(config: &DiscConfig) -> Self {
         build_edsk_from_cfg(config).into()
@@ -208,11 +419,139 @@ impl From<&DiscConfig> for Hfe {
 
 #[cfg(test)]
 mod test {
-    use super::Hfe;
+    use super::{Hfe, HfeVersion};
     use crate::disc::Disc;
+    use crate::edsk::{ExtendedDsk, Head};
 
     #[test]
     fn load_hfe() {
-        let _hfe = Hfe::open("tests/MOODY.HFE");
+        let hfe = Hfe::open("tests/MOODY.HFE").unwrap();
+        let dsk = ExtendedDsk::open("tests/Moody.dsk").unwrap();
+
+        assert_eq!(hfe.nb_tracks_per_head(), dsk.nb_tracks_per_head());
+        for (decoded, expected) in hfe.dsk().tracks().iter().zip(dsk.tracks()) {
+            let sectors = decoded.sector_information_list().sectors();
+            let expected_sectors = expected.sector_information_list().sectors();
+            assert_eq!(sectors.len(), expected_sectors.len(), "{expected}");
+            for (sector, expected_sector) in sectors.iter().zip(expected_sectors) {
+                assert_eq!(sector.sector_id(), expected_sector.sector_id());
+                assert_eq!(
+                    sector.values(),
+                    expected_sector.values(),
+                    "{expected_sector}"
+                );
+            }
+        }
+
+        assert_eq!(
+            hfe.sector_read_bytes(Head::A, 0, 0xC1),
+            dsk.sector_read_bytes(Head::A, 0, 0xC1)
+        );
+    }
+
+    fn reload(hfe: &Hfe) -> Hfe {
+        let mut buffer = Vec::new();
+        hfe.to_buffer(&mut buffer);
+        Hfe::from_buffer(&buffer).unwrap()
+    }
+
+    #[test]
+    fn dsk_round_trip() {
+        let dsk = ExtendedDsk::open("tests/Moody.dsk").unwrap();
+        let hfe = reload(&Hfe::from(dsk.clone()));
+
+        for (decoded, expected) in hfe.dsk().tracks().iter().zip(dsk.tracks()) {
+            assert_eq!(decoded.gap3_length(), expected.gap3_length());
+            assert_eq!(
+                decoded.sector_information_list(),
+                expected.sector_information_list()
+            );
+        }
+    }
+
+    #[test]
+    fn non_standard_sectors_round_trip() {
+        let mut dsk = ExtendedDsk::default();
+        let track = dsk.get_track_information_mut(Head::A, 1).unwrap();
+        track.gap3_length = 0x30;
+        let sectors = &mut track.sector_information_list.sectors;
+        sectors[0].set_deleted_data_mark(true);
+        sectors[1].sector_information_bloc.set_track(0x28);
+        sectors[2]
+            .sector_information_bloc
+            .set_fdc_status_register_1(0x20)
+            .set_fdc_status_register_2(0x20);
+        sectors[3]
+            .sector_information_bloc
+            .set_fdc_status_register_1(0x20);
+        let mut copy = vec![0xE5; 512];
+        copy[100..110].fill(0x55);
+        sectors[4]
+            .sector_information_bloc
+            .set_fdc_status_register_1(0x20)
+            .set_fdc_status_register_2(0x20);
+        sectors[4].set_copies(&[&[0xE5; 512], &copy]).unwrap();
+        sectors[5]
+            .sector_information_bloc
+            .set_fdc_status_register_1(0x01)
+            .set_fdc_status_register_2(0x01);
+        sectors[5].set_copies(&[&[]]).unwrap();
+
+        let mut hfe = Hfe::from(dsk.clone());
+        for version in [HfeVersion::V1, HfeVersion::V3] {
+            hfe.set_version(version);
+            let reloaded = reload(&hfe);
+            let track = reloaded.dsk().get_track_information(Head::A, 1).unwrap();
+            assert_eq!(*track.gap3_length(), 0x30);
+
+            let sectors = track.sector_information_list().sectors();
+            let expected = dsk
+                .get_track_information(Head::A, 1)
+                .unwrap()
+                .sector_information_list()
+                .sectors();
+            assert_eq!(sectors.len(), expected.len());
+            for (idx, (sector, expected)) in sectors.iter().zip(expected).enumerate() {
+                if idx != 4 {
+                    assert_eq!(
+                        sector.sector_information_bloc(),
+                        expected.sector_information_bloc(),
+                        "sector {idx}"
+                    );
+                    assert_eq!(sector.stored_values(), expected.stored_values());
+                }
+            }
+
+            // Only HFE v3 keeps the weak bits
+            let weak = &sectors[4];
+            assert!(weak.sector_information_bloc().has_data_error());
+            match version {
+                HfeVersion::V1 => assert_eq!(weak.values(), &[0xE5; 512]),
+                HfeVersion::V3 => {
+                    let copies = weak.copies().collect::<Vec<_>>();
+                    assert_eq!(copies.len(), 2);
+                    assert_eq!(copies[0][..100], copies[1][..100]);
+                    assert_ne!(copies[0][100..110], copies[1][100..110]);
+                    assert_eq!(copies[0][110..], copies[1][110..]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_sector() {
+        let mut dsk = ExtendedDsk::default();
+        let track = dsk.get_track_information_mut(Head::A, 2).unwrap();
+        let sectors = &mut track.sector_information_list.sectors;
+        sectors.truncate(1);
+        sectors[0].sector_information_bloc.set_sector_size(6);
+        sectors[0].set_copies(&[&[0x11; 6000]]).unwrap();
+        track.number_of_sectors = 1;
+
+        let hfe = reload(&Hfe::from(dsk));
+        let sector = hfe.dsk().sector(Head::A, 2, 0xC1).unwrap();
+        assert!(sector.len() < 8192);
+        assert_eq!(sector.values()[..6000], [0x11; 6000]);
+        assert!(sector.sector_information_bloc().has_data_error());
     }
 }
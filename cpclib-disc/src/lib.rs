@@ -26,8 +26,12 @@ use crate::edsk::Head;
 pub mod amsdos;
 /// Utility function to build a DSK thanks to a format description
 pub mod builder;
+/// CDT (TZX) tape images
+pub mod cdt;
 /// Parser of the format description
 pub mod cfg;
+/// Consistency checks and repair of the Amsdos catalog
+pub mod check;
 pub mod disc;
 /// EDSK File format
 pub mod edsk;
@@ -36,10 +40,11 @@ pub mod hideur;
 /// HFE File format
 #[cfg(feature = "hfe")]
 pub mod hfe;
+/// MFM encoding of the tracks
+#[cfg(feature = "hfe")]
+pub mod mfm;
 
 use custom_error::custom_error;
-#[cfg(feature = "hfe")]
-use hxcfe::{Hxcfe, ImageFormat};
 
 use crate::amsdos::AmsdosHeader;
 use crate::edsk::ExtendedDsk;
@@ -111,17 +116,17 @@ impl Disc for AnyDisc {
         Self: Sized,
         P: AsRef<Utf8Path>
     {
-        // let path = path.as_ref();
-        // if path
-        //    .extension()
-        //    .map(|ext| ext.eq_ignore_ascii_case("hfe"))
-        //    .unwrap_or(false)
-        //{
-        Hfe::open(path).map(|d| d.into())
-        //}
-        // else {
-        //    ExtendedDsk::open(path).map(|d| d.into())
-        // }
+        let path = path.as_ref();
+        if path
+            .extension()
+            .map(|ext| ext.eq_ignore_ascii_case("hfe"))
+            .unwrap_or(false)
+        {
+            Hfe::open(path).map(|d| d.into())
+        }
+        else {
+            ExtendedDsk::open(path).map(|d| d.into())
+        }
     }
 
     #[cfg(not(feature = "hfe"))]
@@ -203,6 +208,10 @@ pub fn dsk_manager_handle(
     let dsk_fname = matches.get_one::<String>("DSK_FILE").unwrap();
     let behavior = amsdos::AmsdosAddBehavior::ReplaceIfPresent;
 
+    if cdt::is_tape_image(dsk_fname) {
+        return tape_manager_handle(dsk_fname, matches, o);
+    }
+
     // Manipulate the catalog of a disc
     if let Some(sub) = matches.subcommand_matches("catalog") {
         let mut dsk = open_disc(dsk_fname, true)
@@ -215,21 +224,18 @@ pub fn dsk_manager_handle(
             let mut bytes = Vec::new();
             let size = f.read_to_end(&mut bytes)?;
 
-            if size != 64 * 32 {
+            let mut manager = AmsdosManagerMut::new_from_disc(&mut dsk, 0);
+            let expected_size = manager.dpb().directory_entries as usize * 32;
+            if size != expected_size {
                 o.emit_stderr(&format!(
                     "Catalog size uses {} bytes whereas it should be {}",
-                    size,
-                    64 * 32
+                    size, expected_size
                 ));
             }
 
-            for idx in 0..4 {
-                let mut sector = dsk.sector_mut(0, 0, idx + 0xc1).expect("Wrong format");
-                let idx = idx as usize;
-                sector
-                    .set_values(&bytes[idx * 512..(idx + 1) * 512])
-                    .unwrap();
-            }
+            manager
+                .set_catalog_slice(&bytes[..size.min(expected_size)])
+                .map_err(|e| DskManagerError::AnyError { msg: e })?;
 
             dsk.save(dsk_fname)
             .map_err(|e| DskManagerError::AnyError { msg: e })?;
@@ -257,11 +263,13 @@ pub fn dsk_manager_handle(
             o.emit_stderr("WIP - We assume the format of the Track 0 is similar to Amsdos one");
 
             let manager = AmsdosManagerNonMut::new_from_disc(&dsk, 0);
+            manager.dpb().try_nb_blocs()?;
             let bytes = manager.catalog().as_bytes();
             let mut f = File::create(fname)?;
             f.write_all(&bytes)?;
         } else if sub.contains_id("LIST") {
             let manager = AmsdosManagerNonMut::new_from_disc(&dsk, 0);
+            manager.dpb().try_nb_blocs()?;
             let catalog = manager.catalog();
             let entries = catalog.visible_entries().collect::<Vec<_>>();
             // TODO manage files instead of entries
@@ -423,11 +431,7 @@ pub fn dsk_manager_handle(
             crate::cfg::DiscConfig::new(desc_fname)?
         }
         else if let Some(desc) = sub.get_one::<String>("FORMAT_NAME") {
-            match desc.as_str() {
-                "data42" => DiscConfig::single_head_data42_format(),
-                "data" => DiscConfig::single_head_data_format(),
-                _ => unreachable!()
-            }
+            DiscConfig::from_format_name(desc).unwrap_or_else(|| unreachable!())
         }
         else {
             DiscConfig::single_head_data_format()
@@ -438,6 +442,64 @@ pub fn dsk_manager_handle(
         dsk.save(dsk_fname)
             .map_err(|e| DskManagerError::AnyError { msg: e })?;
     }
+    else if let Some(sub) = matches.subcommand_matches("check") {
+        // Report (and possibly repair) the inconsistencies of the catalog
+        let mut disc = open_disc(dsk_fname, true)
+            .unwrap_or_else(|_| panic!("Unable to open the file {dsk_fname}"));
+
+        if sub.get_flag("REPAIR") {
+            let repairs = AmsdosManagerMut::new_from_disc(&mut disc, 0).repair()?;
+            for repair in &repairs {
+                o.emit_stdout(&format!("Repaired: {repair}"));
+            }
+            if !repairs.is_empty() {
+                disc.save(dsk_fname)
+                    .map_err(|e| DskManagerError::AnyError { msg: e })?;
+            }
+        }
+
+        let manager = AmsdosManagerNonMut::new_from_disc(&disc, 0);
+        manager.dpb().try_nb_blocs()?;
+        let problems = manager.check();
+        if problems.is_empty() {
+            o.emit_stdout("No problem found");
+        }
+        else {
+            for problem in &problems {
+                o.emit_stdout(&format!("{problem}"));
+            }
+            return Err(DskManagerError::AnyError {
+                msg: format!("{} problems found", problems.len())
+            });
+        }
+    }
+    else if let Some(sub) = matches.subcommand_matches("inspect") {
+        // Show the physical layout of the tracks, as stored in the EDSK
+        let dsk = ExtendedDsk::open(dsk_fname).map_err(|msg| DskManagerError::AnyError { msg })?;
+        let selected_track = sub.get_one::<u8>("TRACK");
+        let selected_head = sub.get_one::<u8>("HEAD");
+
+        o.emit_stdout(&format!(
+            "Dsk {} -- {} tracks, {} heads",
+            dsk_fname,
+            dsk.nb_tracks_per_head(),
+            dsk.nb_heads()
+        ));
+        for track in 0..dsk.nb_tracks_per_head() {
+            for head in 0..dsk.nb_heads() {
+                if selected_track.is_some_and(|&t| t != track)
+                    || selected_head.is_some_and(|&h| h != head)
+                {
+                    continue;
+                }
+
+                match dsk.get_track_information(head, track) {
+                    Some(info) if info.is_formatted() => o.emit_stdout(&format!("{info}")),
+                    _ => o.emit_stdout(&format!("Track {track} head {head}: unformatted"))
+                }
+            }
+        }
+    }
     else {
         o.emit_stderr("Missing command\n");
     }
@@ -445,17 +507,95 @@ pub fn dsk_manager_handle(
     Ok(())
 }
 
+/// Handle the commands that make sense for a CDT file
+#[cfg(feature = "cmdline")]
+fn tape_manager_handle(
+    cdt_fname: &str,
+    matches: &ArgMatches,
+    o: &dyn EventObserver
+) -> Result<(), DskManagerError> {
+    use crate::cdt::{Cdt, CdtWriteOptions};
+
+    if let Some(sub) = matches.subcommand_matches("catalog") {
+        if !sub.get_flag("LIST") {
+            return Err(DskManagerError::AnyError {
+                msg: "Only --list is available for tapes".to_owned()
+            });
+        }
+
+        let cdt = Cdt::open(cdt_fname).map_err(|msg| DskManagerError::AnyError { msg })?;
+        let files = cdt
+            .files()
+            .map_err(|msg| DskManagerError::AnyError { msg })?;
+        o.emit_stdout(&format!("Tape {} -- {} files", cdt_fname, files.len()));
+        for file in &files {
+            o.emit_stdout(&format!("{file}"));
+        }
+    }
+    else if let Some(sub) = matches.subcommand_matches("get") {
+        let cdt = Cdt::open(cdt_fname).map_err(|msg| DskManagerError::AnyError { msg })?;
+
+        for filename in sub.get_many::<String>("OUTPUT_FILES").unwrap() {
+            let file = cdt
+                .get_file(filename)
+                .map_err(|msg| DskManagerError::AnyError { msg })?
+                .ok_or_else(|| {
+                    DskManagerError::AnyError {
+                        msg: format!("missing {filename}")
+                    }
+                })?;
+
+            if sub.get_flag("noheader") {
+                fs_err::write(file.filename(), file.content())?;
+            }
+            else {
+                let ams_file = file.to_amsdos_file()?;
+                fs_err::write(file.filename(), ams_file.header_and_content())?;
+            }
+        }
+    }
+    else if let Some(sub) = matches.subcommand_matches("add") {
+        let mut cdt =
+            Cdt::open_or_new(cdt_fname).map_err(|msg| DskManagerError::AnyError { msg })?;
+        let options = CdtWriteOptions {
+            baud: *sub.get_one::<u16>("BAUD").unwrap(),
+            ..Default::default()
+        };
+
+        for fname in sub.get_many::<Utf8PathBuf>("INPUT_FILES").unwrap() {
+            let ams_file = if sub.get_flag("ASCII") {
+                AmsdosFile::open_valid_ascii(fname)?.0
+            }
+            else {
+                AmsdosFile::open_valid(fname)?
+            };
+            cdt.add_amsdos_file(&ams_file, &options, AmsdosAddBehavior::ReplaceIfPresent)?;
+            o.emit_stdout(&format!("{fname} added"));
+        }
+
+        cdt.save(cdt_fname)
+            .map_err(|msg| DskManagerError::AnyError { msg })?;
+    }
+    else {
+        return Err(DskManagerError::AnyError {
+            msg: "Only catalog, get and add are available for tapes".to_owned()
+        });
+    }
+
+    Ok(())
+}
+
 #[cfg(feature = "cmdline")]
 pub fn dsk_manager_build_arg_parser() -> Command {
     #[cfg(feature = "hfe")]
-    let about = "Manipulate DSK or HFE files";
+    let about = "Manipulate DSK, HFE or CDT files";
     #[cfg(not(feature = "hfe"))]
-    let about = "Manipulate DSK files";
+    let about = "Manipulate DSK or CDT files";
 
     #[cfg(feature = "hfe")]
-    let f_help = "DSK or HFE file to manipulate";
+    let f_help = "DSK, HFE or CDT file to manipulate";
     #[cfg(not(feature = "hfe"))]
-    let f_help = "DSK file to manipulate";
+    let f_help = "DSK or CDT file to manipulate";
 
     Command::new("disc_manager")
                        .about(about)
@@ -481,7 +621,7 @@ pub fn dsk_manager_build_arg_parser() -> Command {
                                     .help("Provide the name of a format that can be used")
                                     .short('f')
                                     .long("format")
-                                    .value_parser(["data", "data42"])
+                                    .value_parser(crate::cfg::DISC_FORMAT_NAMES)
                             )
                             .group(
                                 ArgGroup::new("command")
@@ -571,6 +711,13 @@ pub fn dsk_manager_build_arg_parser() -> Command {
                         .action(ArgAction::SetTrue)
 
                             )
+                            .arg(
+                                Arg::new("BAUD")
+                                .help("Writing speed in bauds, only used for CDT files (1000 and 2000 are the firmware speeds)")
+                                .long("baud")
+                                .default_value("2000")
+                                .value_parser(clap::value_parser!(u16).range(500..=4000))
+                            )
                             .arg(
                                 Arg::new("AS_AMSDOS")
                                 .help("[unimplemented] Uses the same strategy as amsdos when adding a file: add .???, delete .BAK, rename other as .BAK, rename .??? with real extension")
@@ -623,6 +770,33 @@ pub fn dsk_manager_build_arg_parser() -> Command {
                                 .last(true)
                            )
                        )
+                       .subcommand(
+                           Command::new("inspect")
+                           .about("Display the layout of the tracks of an EDSK: sector IDs, sizes, gaps, FDC status bytes, weak sectors and deleted data marks")
+                           .arg(
+                               Arg::new("TRACK")
+                               .help("Only display this track")
+                               .long("track")
+                               .short('t')
+                               .value_parser(clap::value_parser!(u8))
+                           )
+                           .arg(
+                               Arg::new("HEAD")
+                               .help("Only display this head")
+                               .long("head")
+                               .value_parser(clap::value_parser!(u8).range(0..=1))
+                           )
+                       )
+                       .subcommand(
+                           Command::new("check")
+                           .about("Check the consistency of the Amsdos catalog: shared blocs, blocs outside of the disc, wrong record counts, bad header checksums and orphaned blocs")
+                           .arg(
+                               Arg::new("REPAIR")
+                               .help("Fix what can be fixed without losing data and recover orphaned blocs as ORPHANxx.BIN files")
+                               .long("repair")
+                               .action(ArgAction::SetTrue)
+                           )
+                       )
 }
 
 /// Open the file and remove the header if any
@@ -658,25 +832,17 @@ pub fn split_header<D: Into<VecDeque<u8>>>(data: D) -> (VecDeque<u8>, Option<Ams
 
 #[cfg(feature = "hfe")]
 pub fn convert_dsk_to_hfe<P: AsRef<Utf8Path>>(dsk_path: P, hfe_path: P) -> Result<(), String> {
-    let hxc = Hxcfe::get();
-    let format = ImageFormat::from_str("HFE").expect("HFE format should be supported");
-    let img = hxc
-        .load(dsk_path.as_ref())
-        .map_err(|e| format!("Error loading DSK: {e}"))?;
-    img.save(hfe_path.as_ref(), format)
-        .map_err(|e| format!("Error saving HFE: {e}"))?;
-    Ok(())
+    let dsk =
+        ExtendedDsk::open(dsk_path.as_ref()).map_err(|e| format!("Error loading DSK: {e}"))?;
+    Hfe::from(dsk)
+        .save(hfe_path.as_ref())
+        .map_err(|e| format!("Error saving HFE: {e}"))
 }
 
 #[cfg(feature = "hfe")]
 pub fn convert_hfe_to_dsk<P: AsRef<Utf8Path>>(hfe_path: P, dsk_path: P) -> Result<(), String> {
-    let hxc = Hxcfe::get();
-    let format =
-        ImageFormat::from_str("AMSTRADCPC_DSK").expect("AMSTRADCPC_DSK format should be supported");
-    let img = hxc
-        .load(hfe_path.as_ref())
-        .map_err(|e| format!("Error loading HFE: {e}"))?;
-    img.save(dsk_path.as_ref(), format)
-        .map_err(|e| format!("Error saving DSK: {e}"))?;
-    Ok(())
+    let hfe = Hfe::open(hfe_path.as_ref()).map_err(|e| format!("Error loading HFE: {e}"))?;
+    hfe.dsk()
+        .save(dsk_path.as_ref())
+        .map_err(|e| format!("Error saving DSK: {e}"))
 }
@@ -0,0 +1,435 @@
+//! Double density (MFM) encoding of the tracks as written by the uPD765.
+//!
+//! A track is a stream of cells: each data bit is preceded by a clock bit set only
+//! between two 0 data bits. Address marks are written with a missing clock so they
+//! cannot be confused with data.
+//! Cells are stored one per byte: bit 0 is the value, bit 1 flags a weak cell that
+//! does not read the same way at each revolution.
+
+use crate::edsk::{DataRate, RecordingMode, Sector, SectorInformation, TrackInformation};
+
+/// Value stored for a weak cell
+pub const WEAK_CELL: u8 = 0b10;
+
+/// A1 with a missing clock between bits 4 and 5
+const SYNC_A1: u16 = 0x4489;
+/// C2 with a missing clock between bits 3 and 4
+const SYNC_C2: u16 = 0x5224;
+/// Three consecutive A1 syncs
+const SYNC_A1_X3: u64 = 0x4489_4489_4489;
+
+const GAP_BYTE: u8 = 0x4E;
+const INDEX_ADDRESS_MARK: u8 = 0xFC;
+const ID_ADDRESS_MARK: u8 = 0xFE;
+const DATA_ADDRESS_MARK: u8 = 0xFB;
+const DELETED_DATA_ADDRESS_MARK: u8 = 0xF8;
+
+const GAP4A_LENGTH: usize = 80;
+const GAP1_LENGTH: usize = 50;
+const GAP2_LENGTH: usize = 22;
+const SYNC_LENGTH: usize = 12;
+
+/// Maximum number of bytes between the end of an ID field and its data address mark
+const MAX_ID_TO_DATA_DISTANCE: usize = 60;
+
+const ST1_DATA_ERROR: u8 = 1 << 5;
+const ST1_MISSING_ADDRESS_MARK: u8 = 1 << 0;
+const ST2_CONTROL_MARK: u8 = 1 << 6;
+const ST2_DATA_ERROR_IN_DATA_FIELD: u8 = 1 << 5;
+const ST2_MISSING_ADDRESS_MARK_IN_DATA_FIELD: u8 = 1 << 0;
+
+/// CRC-CCITT used by the FDC for the ID and data fields
+pub fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0xFFFF, |crc, &byte| {
+        (0..8).fold(crc ^ (u16::from(byte) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            }
+            else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Produce the cells of a track
+#[derive(Default)]
+struct MfmEncoder {
+    cells: Vec<u8>,
+    previous_bit: u8
+}
+
+impl MfmEncoder {
+    /// Encode a byte; the bits set in `weak` are flagged as weak
+    fn weak_byte(&mut self, byte: u8, weak: u8) {
+        for shift in (0..8).rev() {
+            let bit = (byte >> shift) & 1;
+            let flag = if (weak >> shift) & 1 == 1 {
+                WEAK_CELL
+            }
+            else {
+                0
+            };
+            let clock = u8::from(self.previous_bit == 0 && bit == 0);
+            self.cells.push(clock | flag);
+            self.cells.push(bit | flag);
+            self.previous_bit = bit;
+        }
+    }
+
+    fn byte(&mut self, byte: u8) {
+        self.weak_byte(byte, 0);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        bytes.iter().for_each(|&b| self.byte(b));
+    }
+
+    fn repeat(&mut self, byte: u8, count: usize) {
+        (0..count).for_each(|_| self.byte(byte));
+    }
+
+    fn sync(&mut self, pattern: u16) {
+        for shift in (0..16).rev() {
+            self.cells.push(((pattern >> shift) & 1) as u8);
+        }
+        self.previous_bit = (pattern & 1) as u8;
+    }
+
+    /// Number of bytes encoded so far
+    fn len(&self) -> usize {
+        self.cells.len() / 16
+    }
+}
+
+/// Encode the track with the layout of the uPD765 format command.
+/// The gap after each sector is the GAP#3 of the track and the end of the track is filled with gap bytes up to `track_length` bytes.
+///
+/// The sectors keep their particularities:
+/// - bytes stored after the declared size replace the CRC and the gap
+/// - a truncated sector has no CRC
+/// - the copies of a weak sector differ on weak cells
+/// - a data error in the ID or in the data field is written as a wrong CRC
+/// - a deleted data address mark is kept and a missing one is not written
+pub fn encode_track(track: &TrackInformation, track_length: usize) -> Vec<u8> {
+    let mut encoder = MfmEncoder::default();
+    if !track.is_formatted() {
+        encoder.repeat(GAP_BYTE, track_length);
+        return encoder.cells;
+    }
+
+    encoder.repeat(GAP_BYTE, GAP4A_LENGTH);
+    encoder.repeat(0x00, SYNC_LENGTH);
+    (0..3).for_each(|_| encoder.sync(SYNC_C2));
+    encoder.byte(INDEX_ADDRESS_MARK);
+    encoder.repeat(GAP_BYTE, GAP1_LENGTH);
+
+    for sector in track.sector_information_list().sectors() {
+        encode_sector(&mut encoder, sector, *track.gap3_length());
+    }
+
+    let missing = track_length.saturating_sub(encoder.len());
+    encoder.repeat(GAP_BYTE, missing);
+    encoder.cells
+}
+
+fn encode_sector(encoder: &mut MfmEncoder, sector: &Sector, gap3_length: u8) {
+    let info = sector.sector_information_bloc();
+    let st1 = *info.fdc_status_register_1();
+    let st2 = *info.fdc_status_register_2();
+
+    // ID field
+    let id = [
+        0xA1,
+        0xA1,
+        0xA1,
+        ID_ADDRESS_MARK,
+        *info.track(),
+        *info.head(),
+        *info.sector_id(),
+        *info.sector_size()
+    ];
+    let mut crc = crc16(&id);
+    if st1 & ST1_DATA_ERROR != 0 && st2 & ST2_DATA_ERROR_IN_DATA_FIELD == 0 {
+        crc = !crc;
+    }
+    encoder.repeat(0x00, SYNC_LENGTH);
+    (0..3).for_each(|_| encoder.sync(SYNC_A1));
+    encoder.bytes(&id[3..]);
+    encoder.bytes(&crc.to_be_bytes());
+    encoder.repeat(GAP_BYTE, GAP2_LENGTH);
+
+    if st2 & ST2_MISSING_ADDRESS_MARK_IN_DATA_FIELD != 0 {
+        encoder.repeat(
+            GAP_BYTE,
+            SYNC_LENGTH + 4 + info.len() + 2 + gap3_length as usize
+        );
+        return;
+    }
+
+    // Data field
+    let mark = if info.has_deleted_data_mark() {
+        DELETED_DATA_ADDRESS_MARK
+    }
+    else {
+        DATA_ADDRESS_MARK
+    };
+    encoder.repeat(0x00, SYNC_LENGTH);
+    (0..3).for_each(|_| encoder.sync(SYNC_A1));
+    encoder.byte(mark);
+
+    let stored = sector.stored_values();
+    let declared = info.len();
+    if info.is_weak() {
+        let copies = sector.copies().collect::<Vec<_>>();
+        for (idx, &byte) in copies[0].iter().enumerate() {
+            let weak = copies[1..]
+                .iter()
+                .fold(0, |weak, copy| weak | (copy[idx] ^ byte));
+            encoder.weak_byte(byte, weak);
+        }
+    }
+    else {
+        encoder.bytes(&stored[..stored.len().min(declared)]);
+    }
+
+    if stored.len() > declared && !info.is_weak() {
+        // The stored bytes already contain the CRC and the gap
+        encoder.bytes(&stored[declared..]);
+    }
+    else if stored.len() >= declared {
+        let mut crc = crc16(&[&[0xA1, 0xA1, 0xA1, mark], &stored[..declared]].concat());
+        if st2 & ST2_DATA_ERROR_IN_DATA_FIELD != 0 {
+            crc = !crc;
+        }
+        encoder.bytes(&crc.to_be_bytes());
+        encoder.repeat(GAP_BYTE, gap3_length as usize);
+    }
+}
+
+/// Read the cells of a track
+struct MfmDecoder<'c> {
+    cells: &'c [u8]
+}
+
+impl MfmDecoder<'_> {
+    /// Read the byte starting at the given cell.
+    /// The weak bits are returned with their value on the cell and set to 1
+    fn byte_at(&self, pos: usize) -> Option<(u8, u8)> {
+        let cells = self.cells.get(pos..pos + 16)?;
+        let (low, high) = cells.chunks(2).fold((0, 0), |(low, high), pair| {
+            let data = pair[1];
+            (
+                (low << 1) | (data & 1),
+                (high << 1) | (data & 1) | (data >> 1)
+            )
+        });
+        Some((low, high))
+    }
+
+    /// Read `count` bytes; returns the two possible readings
+    fn bytes_at(&self, pos: usize, count: usize) -> (Vec<u8>, Vec<u8>) {
+        (0..count)
+            .map_while(|idx| self.byte_at(pos + idx * 16))
+            .unzip()
+    }
+
+    /// Returns the position of the cell following each group of three A1 syncs
+    fn syncs(&self) -> Vec<usize> {
+        let mut register = 0_u64;
+        let mut positions = Vec::new();
+        for (idx, cell) in self.cells.iter().enumerate() {
+            register = (register << 1) | u64::from(cell & 1);
+            if register & 0xFFFF_FFFF_FFFF == SYNC_A1_X3 {
+                positions.push(idx + 1);
+            }
+        }
+        positions
+    }
+
+    /// Count the gap bytes starting at the given cell
+    fn gap_length_at(&self, pos: usize) -> usize {
+        (0..)
+            .map_while(|idx| self.byte_at(pos + idx * 16))
+            .take_while(|(low, high)| *low == GAP_BYTE && *high == GAP_BYTE)
+            .count()
+    }
+}
+
+/// ID field waiting for its data field
+struct PendingId {
+    info: SectorInformation,
+    end: usize
+}
+
+/// Decode the sectors of the track.
+/// The CRC errors, the deleted data address marks, the missing data address marks, the truncated and the weak sectors
+/// are stored in the way of the EDSK format.
+/// Returns an unformatted track when no sector is found
+#[allow(clippy::cast_possible_truncation)]
+pub fn decode_track(cells: &[u8], track_number: u8, head_number: u8) -> TrackInformation {
+    let decoder = MfmDecoder { cells };
+    let mut sectors: Vec<Sector> = Vec::new();
+    let mut pending: Option<PendingId> = None;
+    let mut gap3_length = None;
+    let mut last_data_end = None;
+
+    let mut next_free_cell = 0;
+    for pos in decoder.syncs() {
+        // syncs inside the data of a sector are ignored
+        if pos < next_free_cell {
+            continue;
+        }
+        let Some((mark, _)) = decoder.byte_at(pos)
+        else {
+            break;
+        };
+
+        match mark {
+            ID_ADDRESS_MARK => {
+                let (id, _) = decoder.bytes_at(pos + 16, 6);
+                if id.len() < 6 {
+                    break;
+                }
+
+                if let Some(previous) = pending.take() {
+                    sectors.push(sector_without_data(previous.info));
+                }
+                if let Some(end) = last_data_end.take() {
+                    gap3_length.get_or_insert(decoder.gap_length_at(end));
+                }
+
+                let crc = crc16(&[&[0xA1, 0xA1, 0xA1, ID_ADDRESS_MARK], &id[..4]].concat());
+                let mut info = SectorInformation::default();
+                info.set_track(id[0])
+                    .set_head(id[1])
+                    .set_sector_id(id[2])
+                    .set_sector_size(id[3]);
+                if crc != u16::from_be_bytes([id[4], id[5]]) {
+                    info.set_fdc_status_register_1(ST1_DATA_ERROR);
+                }
+                let end = pos + 16 * 7;
+                pending = Some(PendingId { info, end });
+                next_free_cell = end;
+            },
+            DATA_ADDRESS_MARK | DELETED_DATA_ADDRESS_MARK => {
+                let Some(PendingId { mut info, end }) = pending.take()
+                else {
+                    continue;
+                };
+                if pos - end > MAX_ID_TO_DATA_DISTANCE * 16 {
+                    sectors.push(sector_without_data(info));
+                    continue;
+                }
+
+                let start = pos + 16;
+                let declared = info.len();
+                let (low, high) = decoder.bytes_at(start, declared);
+                let (crc_bytes, _) = decoder.bytes_at(start + declared * 16, 2);
+
+                let mut st1 = *info.fdc_status_register_1();
+                let mut st2 = 0;
+                if mark == DELETED_DATA_ADDRESS_MARK {
+                    st2 |= ST2_CONTROL_MARK;
+                }
+                let crc = crc16(&[&[0xA1, 0xA1, 0xA1, mark], low.as_slice()].concat());
+                if low.len() < declared
+                    || crc_bytes.len() < 2
+                    || crc != u16::from_be_bytes([crc_bytes[0], crc_bytes[1]])
+                    || low != high
+                {
+                    st1 |= ST1_DATA_ERROR;
+                    st2 |= ST2_DATA_ERROR_IN_DATA_FIELD;
+                }
+                info.set_fdc_status_register_1(st1)
+                    .set_fdc_status_register_2(st2);
+
+                let copies = if low == high {
+                    vec![low.as_slice()]
+                }
+                else {
+                    vec![low.as_slice(), high.as_slice()]
+                };
+                let mut sector = Sector {
+                    sector_information_bloc: info,
+                    ..Default::default()
+                };
+                sector
+                    .set_copies(&copies)
+                    .expect("Copies read on the track have the declared size");
+                sectors.push(sector);
+
+                next_free_cell = start + (low.len() + 2) * 16;
+                if low.len() == declared {
+                    last_data_end = Some(next_free_cell);
+                }
+            },
+            _ => {}
+        }
+    }
+
+    if let Some(previous) = pending.take() {
+        sectors.push(sector_without_data(previous.info));
+    }
+
+    if sectors.is_empty() {
+        return TrackInformation::unformatted();
+    }
+
+    let mut track = TrackInformation {
+        track_number,
+        head_number,
+        sector_size: *sectors[0].sector_information_bloc().sector_size(),
+        number_of_sectors: sectors.len() as u8,
+        gap3_length: gap3_length.unwrap_or(GAP_BYTE as usize).min(0xFF) as u8,
+        filler_byte: 0xE5,
+        data_rate: DataRate::SingleOrDoubleDensity,
+        recording_mode: RecordingMode::MFM,
+        ..Default::default()
+    };
+    sectors
+        .into_iter()
+        .for_each(|s| track.sector_information_list.add_sector(s));
+    track.track_size = 256 + track.compute_track_size() as u16;
+    track
+}
+
+fn sector_without_data(mut info: SectorInformation) -> Sector {
+    info.set_fdc_status_register_1(info.fdc_status_register_1() | ST1_MISSING_ADDRESS_MARK)
+        .set_fdc_status_register_2(ST2_MISSING_ADDRESS_MARK_IN_DATA_FIELD);
+    Sector {
+        sector_information_bloc: info,
+        values: Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn id_field_crc() {
+        assert_eq!(
+            crc16(&[0xA1, 0xA1, 0xA1, 0xFE, 0x00, 0x00, 0x01, 0x02]),
+            0xCA6F
+        );
+    }
+
+    #[test]
+    fn encoding_follows_mfm_rules() {
+        let mut encoder = MfmEncoder::default();
+        encoder.repeat(0x00, 1);
+        encoder.sync(SYNC_A1);
+        encoder.byte(0x4E);
+
+        let as_u16 = |cells: &[u8]| cells.iter().fold(0_u16, |v, c| (v << 1) | u16::from(*c));
+        assert_eq!(as_u16(&encoder.cells[0..16]), 0xAAAA);
+        assert_eq!(as_u16(&encoder.cells[16..32]), SYNC_A1);
+        assert_eq!(as_u16(&encoder.cells[32..48]), 0x1254);
+
+        let decoder = MfmDecoder {
+            cells: &encoder.cells
+        };
+        assert_eq!(decoder.byte_at(32), Some((0x4E, 0x4E)));
+    }
+}
@@ -3,6 +3,7 @@ use cpclib_common::itertools::Itertools;
 use cpclib_disc::amsdos::{
     AmsdosAddBehavior, AmsdosError, AmsdosFile, AmsdosFileName, AmsdosHeader
 };
+use cpclib_disc::cdt::{Cdt, CdtWriteOptions, is_tape_image};
 use cpclib_disc::disc::Disc;
 use cpclib_disc::edsk::Head;
 use cpclib_disc::open_disc;
@@ -52,7 +53,9 @@ impl StorageSupport {
 pub struct FileAndSupport {
     support: StorageSupport,
     file: (FileType, Utf8PathBuf),
-    content: Option<Vec<u8>>
+    content: Option<Vec<u8>>,
+    /// Used only when the file is saved on a tape
+    tape_options: CdtWriteOptions
 }
 
 impl FileAndSupport {
@@ -69,15 +72,23 @@ impl FileAndSupport {
         Self {
             support,
             file,
-            content: None
+            content: None,
+            tape_options: CdtWriteOptions::default()
         }
     }
 
+    /// Set the speed and pauses used when the file is saved on a tape
+    pub fn with_tape_options(mut self, options: CdtWriteOptions) -> Self {
+        self.tape_options = options;
+        self
+    }
+
     pub fn new_amsdos<P: Into<Utf8PathBuf>>(p: P) -> Self {
         Self {
             support: StorageSupport::Host,
             file: (FileType::AmsdosBin, p.into()),
-            content: None
+            content: None,
+            tape_options: CdtWriteOptions::default()
         }
     }
 
@@ -85,7 +96,8 @@ impl FileAndSupport {
         Self {
             support: StorageSupport::Disc(p.into()),
             file: (FileType::AmsdosBin, f.into()),
-            content: None
+            content: None,
+            tape_options: CdtWriteOptions::default()
         }
     }
 
@@ -93,7 +105,8 @@ impl FileAndSupport {
         Self {
             support: StorageSupport::Host,
             file: (FileType::AmsdosBas, p.into()),
-            content: None
+            content: None,
+            tape_options: CdtWriteOptions::default()
         }
     }
 
@@ -101,7 +114,8 @@ impl FileAndSupport {
         Self {
             support: StorageSupport::Disc(p.into()),
             file: (FileType::AmsdosBas, f.into()),
-            content: None
+            content: None,
+            tape_options: CdtWriteOptions::default()
         }
     }
 
@@ -109,7 +123,8 @@ impl FileAndSupport {
         Self {
             support: StorageSupport::Host,
             file: (FileType::Ascii, p.into()),
-            content: None
+            content: None,
+            tape_options: CdtWriteOptions::default()
         }
     }
 
@@ -117,7 +132,8 @@ impl FileAndSupport {
         Self {
             support: StorageSupport::Disc(p.into()),
             file: (FileType::Ascii, f.into()),
-            content: None
+            content: None,
+            tape_options: CdtWriteOptions::default()
         }
     }
 
@@ -125,7 +141,8 @@ impl FileAndSupport {
         Self {
             support: StorageSupport::Host,
             file: (FileType::NoHeader, p.into()),
-            content: None
+            content: None,
+            tape_options: CdtWriteOptions::default()
         }
     }
 
@@ -195,7 +212,16 @@ impl FileAndSupport {
                     Self {
                         support: StorageSupport::Disc(first.into()),
                         file: (FileType::Auto, second.into()),
-                        content: None
+                        content: None,
+                        tape_options: CdtWriteOptions::default()
+                    }
+                }
+                else if is_tape_image(first) {
+                    Self {
+                        support: StorageSupport::Tape(first.into()),
+                        file: (FileType::Auto, second.into()),
+                        content: None,
+                        tape_options: CdtWriteOptions::default()
                     }
                 }
                 else if header {
@@ -213,8 +239,9 @@ impl FileAndSupport {
 
     pub fn filename(&self) -> Utf8PathBuf {
         match &self.support {
-            StorageSupport::Disc(p) => Utf8PathBuf::from(format!("{}#{}", p, self.file.1)),
-            StorageSupport::Tape(_utf8_path_buf) => todo!(),
+            StorageSupport::Disc(p) | StorageSupport::Tape(p) => {
+                Utf8PathBuf::from(format!("{}#{}", p, self.file.1))
+            },
             StorageSupport::Host => Utf8PathBuf::from(format!("{}", self.file.1))
         }
     }
@@ -270,7 +297,7 @@ impl FileAndSupport {
                 ))
             },
             Err(e) => {
-                if self.in_disc() {
+                if self.in_disc() || self.in_tape() {
                     Err(e)?;
                 }
                 Ok(AmsdosFile::from_buffer(data))
@@ -331,7 +358,21 @@ impl FileAndSupport {
                 disc.save(disc_filename)
                     .map_err(|e| format!("Error while saving {e}"))?;
             },
-            StorageSupport::Tape(_utf8_path_buf) => unimplemented!(),
+            StorageSupport::Tape(tape_filename) => {
+                // files are appended at the end of the tape
+                let mut tape =
+                    Cdt::open_or_new(tape_filename).map_err(|msg| format!("Tape error: {msg}"))?;
+
+                let amsdos_file = built_file.unwrap_left();
+                tape.add_amsdos_file(
+                    &amsdos_file,
+                    &self.tape_options,
+                    add_behavior.unwrap_or(AmsdosAddBehavior::FailIfPresent)
+                )
+                .map_err(|e| e.to_string())?;
+
+                tape.save(tape_filename)?;
+            },
             StorageSupport::Host => {
                 // handle case with and without header
                 let (fname, content) = match &built_file {
@@ -13,6 +13,7 @@ use image as im;
 
 use crate::ga::*;
 use crate::image::*;
+use crate::quantize::{self, Dithering};
 
 /// Encode the position of a line or column to transform in the source image
 #[derive(Copy, Clone, Debug)]
@@ -221,7 +222,9 @@ impl Transformation {
 #[derive(Clone, Debug, Default)]
 pub struct TransformationsList {
     /// list of transformations
-    transformations: Vec<Transformation>
+    transformations: Vec<Transformation>,
+    /// Colour reduction done on the true colour image, before the transformations
+    color_reduction: Option<Dithering>
 }
 
 #[allow(missing_docs)]
@@ -229,10 +232,22 @@ impl TransformationsList {
     /// Create an empty list of transformations
     pub fn new(transformations: &[Transformation]) -> Self {
         TransformationsList {
-            transformations: transformations.to_vec()
+            transformations: transformations.to_vec(),
+            color_reduction: None
         }
     }
 
+    /// Reduce the colours of the image to the inks allowed by the mode and the palette.
+    /// The best inks are selected for the pens that are not set in the palette.
+    pub fn reduce_colors(mut self, dithering: Dithering) -> Self {
+        self.color_reduction = Some(dithering);
+        self
+    }
+
+    pub fn color_reduction(&self) -> Option<Dithering> {
+        self.color_reduction
+    }
+
     /// Add a transformation that remove one pixel column out of two
     pub fn skip_odd_pixels(mut self) -> Self {
         self.transformations.push(Transformation::SkipOddPixels);
@@ -272,6 +287,8 @@ impl TransformationsList {
     pub fn build_mask_from_background_ink(mut self, background: Ink) -> Self {
         self.transformations
             .push(Transformation::MaskFromBackgroundInk(background));
+        // the mask palette has nothing to do with the colours of the image
+        self.color_reduction = None;
         self
     }
 
@@ -1267,7 +1284,7 @@ impl ImageConverter {
                     crop_if_too_large
                 };
 
-                let sprite = converter.load_sprite(input_file, missing_pen);
+                let sprite = converter.load_sprite(input_file, missing_pen)?;
                 converter
                     .apply_sprite_conversion(&sprite, o)
                     .map(|output| output.sprite().unwrap())
@@ -1383,7 +1400,7 @@ impl ImageConverter {
         };
 
         if let OutputFormat::LinearEncodedChuncky = &output {
-            let mut matrix = converter.load_color_matrix(input_file);
+            let mut matrix = converter.load_color_matrix(input_file)?;
             matrix.double_horizontally();
             let sprite = matrix.as_sprite(mode, LockablePalette::empty(), None);
             Ok(Output::LinearEncodedChuncky {
@@ -1453,7 +1470,7 @@ impl ImageConverter {
             Ok(Output::SpriteAndMask { sprite, mask })
         }
         else {
-            let sprite = converter.load_sprite(input_file, missing_pen);
+            let sprite = converter.load_sprite(input_file, missing_pen)?;
             converter.apply_sprite_conversion(&sprite, o)
         }
     }
@@ -1477,20 +1494,60 @@ impl ImageConverter {
 
     /// Load the initial image
     /// TODO make compatibility tests are alike
-    /// TODO propagate errors when needed
-    fn load_sprite(&mut self, input_file: &Utf8Path, missing_pen: Option<Pen>) -> Sprite {
-        let matrix = self.load_color_matrix(input_file);
+    fn load_sprite(
+        &mut self,
+        input_file: &Utf8Path,
+        missing_pen: Option<Pen>
+    ) -> anyhow::Result<Sprite> {
+        let matrix = self.load_color_matrix(input_file)?;
         let sprite = matrix.as_sprite(self.mode, self.palette.clone(), missing_pen);
         self.palette = LockablePalette::locked(sprite.palette().unwrap());
 
-        sprite
+        Ok(sprite)
     }
 
-    fn load_color_matrix(&self, input_file: &Utf8Path) -> ColorMatrix {
+    fn load_color_matrix(&self, input_file: &Utf8Path) -> anyhow::Result<ColorMatrix> {
         let img = im::open(input_file)
-            .unwrap_or_else(|e| panic!("Unable to convert {input_file:?} properly. {e}"));
-        let mat = ColorMatrix::convert(&img.to_rgb8(), ConversionRule::AnyModeUseAllPixels);
-        self.transformations.apply(&mat)
+            .map_err(|e| anyhow::anyhow!("Unable to convert {input_file} properly. {e}"))?
+            .to_rgb8();
+        let mat = match self.transformations.color_reduction() {
+            Some(dithering) => {
+                let inks = self.reduction_inks(&img)?;
+                ColorMatrix::convert_with_inks(
+                    &img,
+                    ConversionRule::AnyModeUseAllPixels,
+                    &inks,
+                    dithering
+                )
+            },
+            None => ColorMatrix::convert(&img, ConversionRule::AnyModeUseAllPixels)
+        };
+        Ok(self.transformations.apply(&mat))
+    }
+
+    /// Inks to use when reducing the colours of the image: the inks of the palette
+    /// completed by the best ones for the image when the palette is not locked
+    fn reduction_inks(&self, img: &im::RgbImage) -> anyhow::Result<Vec<Ink>> {
+        let count = self.mode.max_colors();
+        let fixed = self
+            .palette
+            .pens()
+            .into_iter()
+            .filter(|pen| (pen.number() as usize) < count)
+            .map(|pen| *self.palette.get(&pen))
+            .collect_vec();
+
+        if !self.palette.is_locked() {
+            Ok(quantize::select_inks(img, count, &fixed))
+        }
+        else if fixed.is_empty() {
+            Err(anyhow::anyhow!(
+                "The locked palette has no pen below {count} to reduce the colors"
+            ))
+        }
+        else {
+            Ok(fixed)
+        }
     }
 
     /// Manage the conversion on the given sprite
@@ -1831,4 +1888,27 @@ mod tests {
         assert_eq!(mask, mask2);
         assert_eq!(sprite, sprite2);
     }
+
+    #[test]
+    fn reduction_needs_inks() {
+        let img = im::RgbImage::from_pixel(4, 4, im::Rgb([100, 100, 100]));
+        let mut palette = Palette::empty();
+        palette.set(Pen::from(4), Ink::BLACK);
+        let mut converter = ImageConverter {
+            palette: LockablePalette::locked(palette),
+            mode: Mode::One,
+            output: OutputFormat::Sprite(SpriteEncoding::Linear),
+            transformations: TransformationsList::default(),
+            crop_if_too_large: false
+        };
+
+        // pen 4 is not available in mode 1
+        assert!(converter.reduction_inks(&img).is_err());
+
+        converter.mode = Mode::Zero;
+        assert_eq!(converter.reduction_inks(&img).unwrap(), [Ink::BLACK]);
+
+        converter.palette = LockablePalette::empty();
+        assert!(!converter.reduction_inks(&img).unwrap().is_empty());
+    }
 }
@@ -12,6 +12,8 @@ use image as im;
 use crate::ga::*;
 use crate::pixels;
 use crate::pixels::bytes_to_pens;
+use crate::quantize::{self, Dithering};
+use crate::screen::CrtcScreen;
 
 /// Screen mode
 #[derive(Clone, Copy, PartialEq, Debug)]
@@ -275,6 +277,25 @@ impl ColorMatrix {
             .into()
     }
 
+    /// Build the picture displayed by the CRTC from the 64kb of the video memory
+    pub fn from_crtc_memory(
+        memory: &[u8],
+        crtc: &CrtcScreen,
+        mode: Mode,
+        palette: &Palette
+    ) -> Self {
+        (0..crtc.height())
+            .map(|line| {
+                (0..crtc.bytes_width())
+                    .map(|column| memory[crtc.address(line, column) as usize])
+                    .flat_map(|b| pixels::byte_to_pens(b, mode))
+                    .map(|pen| *palette.get(&pen))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+            .into()
+    }
+
     pub fn from_sprite(data: &[u8], pixels_width: u16, mode: Mode, palette: &Palette) -> Self {
         let width = mode.nb_bytes_for_pixels_width(pixels_width as _);
 
@@ -531,6 +552,10 @@ impl ColorMatrix {
         inks: &[Ink],
         strategy: ColorConversionStrategy
     ) -> Result<(), anyhow::Error> {
+        if inks.is_empty() {
+            return Err(anyhow::anyhow!("No ink available to reduce the colors"));
+        }
+
         for y in 0..(self.height() as usize) {
             for x in 0..(self.width() as usize) {
                 let ink = &mut self.data[y][x];
@@ -539,7 +564,9 @@ impl ColorMatrix {
                         ColorConversionStrategy::ReplaceWrongColorByFirstColor => {
                             *ink = inks[0];
                         },
-                        ColorConversionStrategy::ReplaceWrongColorByClosestInk => unimplemented!(),
+                        ColorConversionStrategy::ReplaceWrongColorByClosestInk => {
+                            *ink = quantize::closest_ink(ink.color(), inks);
+                        },
                         ColorConversionStrategy::Fail => {
                             return Err(anyhow::anyhow!(
                                 "{:?} not available in {:?} at [{}, {}]",
@@ -611,6 +638,28 @@ impl ColorMatrix {
         Self { data: lines }
     }
 
+    /// Convert the image by only using the provided inks.
+    /// Each pixel is replaced by its closest ink with the requested dithering.
+    pub fn convert_with_inks(
+        img: &im::ImageBuffer<im::Rgb<u8>, Vec<u8>>,
+        conversion: ConversionRule,
+        inks: &[Ink],
+        dithering: Dithering
+    ) -> Self {
+        let img = match conversion {
+            ConversionRule::AnyModeUseAllPixels => img.clone(),
+            ConversionRule::ZeroSkipOddPixels => {
+                im::ImageBuffer::from_fn(img.width() / 2, img.height(), |x, y| {
+                    *img.get_pixel(x * 2, y)
+                })
+            },
+        };
+
+        Self {
+            data: quantize::dither(&img, inks, dithering)
+        }
+    }
+
     /// Compute a difference map to see the problematic positions
     pub fn diff(&self, other: &Self) -> Self {
         // Create a map encoding a complete success
@@ -1421,8 +1470,47 @@ impl MultiModeSprite {
 
 #[cfg(test)]
 mod tests {
-    use super::ColorMatrix;
-    use crate::ga::Ink;
+    use super::{ColorMatrix, Mode};
+    use crate::ga::{Ink, Palette};
+    use crate::screen::CrtcScreen;
+
+    #[test]
+    fn test_crtc_addresses() {
+        let standard = CrtcScreen::default();
+        assert_eq!(standard.address(0, 0), 0xC000);
+        assert_eq!(standard.address(1, 0), 0xC800);
+        assert_eq!(standard.address(8, 1), 0xC051);
+        assert_eq!(standard.height(), 200);
+
+        // 32kb overscan: the character counter overflows from 0x8000-0xbfff to 0xc000-0xffff
+        let overscan = CrtcScreen::from_registers(&[
+            63, 48, 50, 0x8E, 38, 0, 34, 35, 0, 7, 0, 0, 0x2C, 0x00, 0, 0, 0, 0
+        ]);
+        assert_eq!(overscan.address(0, 0), 0x8000);
+        assert_eq!(overscan.address(21 * 8 + 7, 30), 0xBFFE);
+        assert_eq!(overscan.address(21 * 8, 32), 0xC000);
+        assert_eq!(overscan.address(22 * 8, 0), 0xC040);
+        assert_eq!(overscan.bytes_width(), 96);
+    }
+
+    #[test]
+    fn test_from_crtc_memory() {
+        let mut memory = vec![0; 0x10000];
+        memory[0xC000] = 0b1000_1000; // pen 3 on the first pixel in mode 1
+        memory[0xC800] = 0b0000_0001; // pen 2 on the last pixel of the byte in mode 1
+
+        let mut palette = Palette::new();
+        palette.set(2, Ink::BRIGHT_RED);
+        palette.set(3, Ink::BRIGHT_WHITE);
+
+        let matrix =
+            ColorMatrix::from_crtc_memory(&memory, &CrtcScreen::default(), Mode::One, &palette);
+        assert_eq!(matrix.width(), 320);
+        assert_eq!(matrix.height(), 200);
+        assert_eq!(*matrix.get_ink(0, 0), Ink::BRIGHT_WHITE);
+        assert_eq!(*matrix.get_ink(1, 0), Ink::BLACK);
+        assert_eq!(*matrix.get_ink(3, 1), Ink::BRIGHT_RED);
+    }
 
     #[test]
     fn test_masking() {
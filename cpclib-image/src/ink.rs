@@ -494,6 +494,7 @@ impl Ink {
         Ink::INKS[idx]
     }
 
+    /// Hardware colours 1, 8, 9, 16 and 17 are duplicates of other colours
     pub fn from_hardware_color_number(col: u8) -> Ink {
         match col {
             20 => 0,
@@ -523,6 +524,11 @@ impl Ink {
             10 => 24,
             3 => 25,
             11 => 26,
+            1 => 13,
+            8 => 7,
+            9 => 25,
+            16 => 1,
+            17 => 19,
             _ => panic!("{col} bad value")
         }
         .into()
@@ -786,6 +792,17 @@ mod tests {
         assert_eq!(Ink::from(5i64), Ink::INKS[5]);
     }
 
+    #[test]
+    fn test_every_hardware_color_is_an_ink() {
+        for col in 0..32 {
+            Ink::from_hardware_color_number(col);
+        }
+        assert_eq!(
+            Ink::from_hardware_color_number(1),
+            Ink::from_hardware_color_number(0)
+        );
+    }
+
     #[test]
     fn test_rgb() {
         const RGB_RATIOS: &[InkComponentQuantity] =
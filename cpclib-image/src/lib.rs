@@ -5,6 +5,7 @@ pub mod ocp;
 pub mod palette;
 pub mod pen;
 pub mod pixels;
+pub mod quantize;
 pub mod screen;
 
 /// PC to CPC image conversions. WIP
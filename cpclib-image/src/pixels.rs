@@ -6,7 +6,14 @@ pub fn byte_to_pens(byte: u8, mode: Mode) -> Box<dyn Iterator<Item = Pen>> {
         Mode::Zero => Box::new(mode0::byte_to_pens(byte).into_iter()),
         Mode::One => Box::new(mode1::byte_to_pens(byte).into_iter()),
         Mode::Two => Box::new(mode2::byte_to_pens(byte).into_iter()),
-        _ => unimplemented!()
+        // mode 3 uses the mode 0 encoding but only the 2 lowest bits of the pens
+        Mode::Three => {
+            Box::new(
+                mode0::byte_to_pens(byte)
+                    .into_iter()
+                    .map(|pen| Pen::from(pen.number() & 3))
+            )
+        },
     }
 }
 
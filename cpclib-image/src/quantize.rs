@@ -0,0 +1,400 @@
+//! Colour reduction of true colour images.
+//! The best inks of an image are selected among the 27 inks of the firmware,
+//! then each pixel is mapped on one of them, optionally with a dithering.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use image as im;
+
+use crate::ga::Ink;
+
+/// Number of different inks (the duplicated hardware colors are not counted)
+const NB_DIFFERENT_INKS: usize = 27;
+
+/// Error diffusion kernel: list of (dx, dy, weight) and divisor of the weights
+type DiffusionKernel = (&'static [(isize, usize, f32)], f32);
+
+/// Dithering applied when mapping the pixels of an image on a restricted set of inks
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Dithering {
+    /// Each pixel is replaced by its closest ink
+    #[default]
+    None,
+    /// Floyd-Steinberg error diffusion
+    FloydSteinberg,
+    /// Atkinson error diffusion (only 3/4 of the error is propagated)
+    Atkinson,
+    /// Ordered dithering with a 4x4 Bayer matrix
+    Bayer
+}
+
+impl Dithering {
+    /// Names accepted by [Dithering::from_str]
+    pub const NAMES: [&'static str; 4] = ["none", "floyd-steinberg", "atkinson", "bayer"];
+
+    /// Error diffusion kernel of the dithering, if any
+    fn kernel(&self) -> Option<DiffusionKernel> {
+        match self {
+            Dithering::FloydSteinberg => {
+                Some((&[(1, 0, 7.), (-1, 1, 3.), (0, 1, 5.), (1, 1, 1.)], 16.))
+            },
+            Dithering::Atkinson => {
+                Some((
+                    &[
+                        (1, 0, 1.),
+                        (2, 0, 1.),
+                        (-1, 1, 1.),
+                        (0, 1, 1.),
+                        (1, 1, 1.),
+                        (0, 2, 1.)
+                    ],
+                    8.
+                ))
+            },
+            Dithering::None | Dithering::Bayer => None
+        }
+    }
+}
+
+impl FromStr for Dithering {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Dithering::None),
+            "floyd-steinberg" | "floydsteinberg" | "fs" => Ok(Dithering::FloydSteinberg),
+            "atkinson" => Ok(Dithering::Atkinson),
+            "bayer" => Ok(Dithering::Bayer),
+            _ => {
+                Err(format!(
+                    "{s} is not a valid dithering. Expected one of {}",
+                    Dithering::NAMES.join(", ")
+                ))
+            },
+        }
+    }
+}
+
+impl Display for Dithering {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Dithering::None => "none",
+            Dithering::FloydSteinberg => "floyd-steinberg",
+            Dithering::Atkinson => "atkinson",
+            Dithering::Bayer => "bayer"
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// 4x4 Bayer threshold matrix
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Amplitude of the Bayer noise: the distance between two levels of a CPC colour component
+const BAYER_SPREAD: f32 = 128.;
+
+fn distance(a: [f32; 3], b: im::Rgb<u8>) -> f32 {
+    (0..3).map(|c| (a[c] - f32::from(b[c])).powi(2)).sum()
+}
+
+fn distance_u8(a: im::Rgb<u8>, b: im::Rgb<u8>) -> u32 {
+    (0..3)
+        .map(|c| (i32::from(a[c]) - i32::from(b[c])).pow(2) as u32)
+        .sum()
+}
+
+fn closest_index(color: [f32; 3], colors: &[im::Rgb<u8>]) -> usize {
+    colors
+        .iter()
+        .enumerate()
+        .map(|(idx, candidate)| (idx, distance(color, *candidate)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(idx, _)| idx)
+        .unwrap()
+}
+
+/// Return the ink of `inks` that is the closest to `color`.
+/// `inks` must not be empty.
+pub fn closest_ink(color: im::Rgb<u8>, inks: &[Ink]) -> Ink {
+    let colors = inks.iter().map(Ink::color).collect::<Vec<_>>();
+    let color = [0, 1, 2].map(|c| f32::from(color[c]));
+    inks[closest_index(color, &colors)]
+}
+
+/// Select at most `count` inks that minimize the error when the image is drawn with them.
+/// The `fixed` inks are always part of the selection.
+/// Less than `count` inks are returned when extra inks would not improve the image.
+pub fn select_inks(img: &im::RgbImage, count: usize, fixed: &[Ink]) -> Vec<Ink> {
+    // Weight of each colour of the image
+    let mut histogram: HashMap<im::Rgb<u8>, u64> = HashMap::new();
+    for pixel in img.pixels() {
+        *histogram.entry(*pixel).or_default() += 1;
+    }
+    let (colors, weights): (Vec<_>, Vec<_>) = histogram.into_iter().unzip();
+
+    // Cost of representing each colour by each ink
+    let costs = colors
+        .iter()
+        .map(|color| {
+            Ink::INKS[..NB_DIFFERENT_INKS]
+                .iter()
+                .map(|ink| distance_u8(*color, ink.color()))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    // Cost of each colour with the selected inks, except the one at position `skip`
+    let cost_with = |selection: &[usize], skip: Option<usize>| -> Vec<u32> {
+        costs
+            .iter()
+            .map(|cost| {
+                selection
+                    .iter()
+                    .enumerate()
+                    .filter(|(position, _)| Some(*position) != skip)
+                    .map(|(_, &ink)| cost[ink])
+                    .min()
+                    .unwrap_or(u32::MAX)
+            })
+            .collect()
+    };
+    // Error of the image for the given costs
+    let error_of = |current: &[u32]| -> u64 {
+        current
+            .iter()
+            .zip(&weights)
+            .map(|(&current, &weight)| {
+                if current == u32::MAX {
+                    u64::MAX
+                }
+                else {
+                    u64::from(current) * weight
+                }
+            })
+            .fold(0, u64::saturating_add)
+    };
+    // Error of the image when `ink` is added to the inks that produced `current`
+    let error_with = |current: &[u32], ink: usize| -> u64 {
+        current
+            .iter()
+            .zip(&costs)
+            .zip(&weights)
+            .map(|((&current, cost), &weight)| u64::from(current.min(cost[ink])) * weight)
+            .sum()
+    };
+
+    // The fixed inks are kept as provided, even if they are duplicates of other ones
+    let mut selected_fixed = Vec::new();
+    let mut selection: Vec<usize> = Vec::with_capacity(count);
+    for ink in fixed {
+        let idx = ink
+            .duplicate()
+            .map_or(ink.number(), |dup| dup.number().min(ink.number())) as usize;
+        if selection.len() < count && !selection.contains(&idx) {
+            selection.push(idx);
+            selected_fixed.push(*ink);
+        }
+    }
+    let nb_fixed = selection.len();
+
+    // Greedily add the ink that reduces the most the error
+    let mut error = error_of(&cost_with(&selection, None));
+    while selection.len() < count && error > 0 {
+        let current = cost_with(&selection, None);
+        let best = (0..NB_DIFFERENT_INKS)
+            .filter(|ink| !selection.contains(ink))
+            .map(|ink| (ink, error_with(&current, ink)))
+            .min_by_key(|(_, error)| *error);
+        match best {
+            Some((ink, new_error)) if new_error < error => {
+                selection.push(ink);
+                error = new_error;
+            },
+            _ => break
+        }
+    }
+
+    // Then replace the inks that have been chosen while it improves the result
+    let mut improved = true;
+    while improved && error > 0 {
+        improved = false;
+        for position in nb_fixed..selection.len() {
+            let others = cost_with(&selection, Some(position));
+            let best = (0..NB_DIFFERENT_INKS)
+                .filter(|ink| !selection.contains(ink))
+                .map(|ink| (ink, error_with(&others, ink)))
+                .min_by_key(|(_, error)| *error);
+            if let Some((ink, new_error)) = best
+                && new_error < error
+            {
+                selection[position] = ink;
+                error = new_error;
+                improved = true;
+            }
+        }
+    }
+
+    // Finally remove the inks that have become useless
+    let mut position = nb_fixed;
+    while position < selection.len() {
+        let others = cost_with(&selection, Some(position));
+        if error_of(&others) == error {
+            selection.remove(position);
+        }
+        else {
+            position += 1;
+        }
+    }
+
+    selected_fixed
+        .into_iter()
+        .chain(selection[nb_fixed..].iter().map(|&idx| Ink::INKS[idx]))
+        .collect()
+}
+
+/// Map each pixel of the image on one of the `inks` with the requested dithering.
+/// `inks` must not be empty.
+pub fn dither(img: &im::RgbImage, inks: &[Ink], dithering: Dithering) -> Vec<Vec<Ink>> {
+    let width = img.width() as usize;
+    let height = img.height() as usize;
+    let colors = inks.iter().map(Ink::color).collect::<Vec<_>>();
+
+    // Working copy that accumulates the diffused errors
+    let mut values = img
+        .pixels()
+        .map(|p| [0, 1, 2].map(|c| f32::from(p[c])))
+        .collect::<Vec<_>>();
+
+    let mut lines = Vec::with_capacity(height);
+    for y in 0..height {
+        let mut line = Vec::with_capacity(width);
+        for x in 0..width {
+            let mut value = values[y * width + x].map(|c| c.clamp(0., 255.));
+            if dithering == Dithering::Bayer {
+                let threshold = f32::from(BAYER_4X4[y % 4][x % 4]);
+                let offset = ((threshold + 0.5) / 16. - 0.5) * BAYER_SPREAD;
+                value = value.map(|c| c + offset);
+            }
+
+            let idx = closest_index(value, &colors);
+            line.push(inks[idx]);
+
+            if let Some((kernel, divisor)) = dithering.kernel() {
+                let error = [0, 1, 2].map(|c| value[c] - f32::from(colors[idx][c]));
+                for &(dx, dy, weight) in kernel {
+                    let (Some(nx), ny) = (x.checked_add_signed(dx), y + dy)
+                    else {
+                        continue;
+                    };
+                    if nx >= width || ny >= height {
+                        continue;
+                    }
+                    let target = &mut values[ny * width + nx];
+                    for c in 0..3 {
+                        target[c] += error[c] * weight / divisor;
+                    }
+                }
+            }
+        }
+        lines.push(line);
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use image as im;
+
+    use super::*;
+
+    fn gradient() -> im::RgbImage {
+        im::RgbImage::from_fn(64, 16, |x, y| {
+            im::Rgb([(x * 4) as u8, (y * 16) as u8, (255 - x * 4) as u8])
+        })
+    }
+
+    #[test]
+    fn select_inks_of_cpc_image() {
+        let img = im::RgbImage::from_fn(8, 8, |x, _y| {
+            if x < 4 {
+                Ink::BRIGHTRED.color()
+            }
+            else {
+                Ink::PASTELBLUE.color()
+            }
+        });
+
+        let inks = select_inks(&img, 16, &[]);
+        assert_eq!(inks.len(), 2);
+        assert!(inks.contains(&Ink::BRIGHTRED));
+        assert!(inks.contains(&Ink::PASTELBLUE));
+
+        let inks = select_inks(&img, 4, &[Ink::BLACK]);
+        assert_eq!(inks[0], Ink::BLACK);
+        assert_eq!(inks.len(), 3);
+    }
+
+    #[test]
+    fn select_inks_respects_count() {
+        let img = gradient();
+        for count in [2, 4] {
+            assert_eq!(select_inks(&img, count, &[]).len(), count);
+        }
+        // some inks are never useful for this gradient
+        let inks = select_inks(&img, 16, &[]);
+        assert!(inks.len() > 4 && inks.len() <= 16);
+    }
+
+    #[test]
+    fn dithering_only_uses_provided_inks() {
+        let img = gradient();
+        let inks = select_inks(&img, 4, &[]);
+        for dithering in [
+            Dithering::None,
+            Dithering::FloydSteinberg,
+            Dithering::Atkinson,
+            Dithering::Bayer
+        ] {
+            let res = dither(&img, &inks, dithering);
+            assert_eq!(res.len(), 16);
+            assert!(res.iter().all(|line| line.len() == 64));
+            assert!(res.iter().flatten().all(|ink| inks.contains(ink)));
+        }
+    }
+
+    #[test]
+    fn dithering_mixes_inks_of_flat_color() {
+        // A dark grey must be rendered by mixing black and white
+        let img = im::RgbImage::from_pixel(16, 16, im::Rgb([100, 100, 100]));
+        let inks = [Ink::BLACK, Ink::BRIGHTWHITE];
+
+        let flat = dither(&img, &inks, Dithering::None);
+        assert!(flat.iter().flatten().all(|ink| *ink == Ink::BLACK));
+
+        for dithering in [
+            Dithering::FloydSteinberg,
+            Dithering::Atkinson,
+            Dithering::Bayer
+        ] {
+            let res = dither(&img, &inks, dithering);
+            let nb_white = res
+                .iter()
+                .flatten()
+                .filter(|ink| **ink == Ink::BRIGHTWHITE)
+                .count();
+            assert!(nb_white > 0, "{dithering}");
+            assert!(nb_white < 16 * 16 / 2, "{dithering}");
+        }
+    }
+
+    #[test]
+    fn parse_dithering() {
+        for name in Dithering::NAMES {
+            assert_eq!(name.parse::<Dithering>().unwrap().to_string(), name);
+        }
+        assert!("foo".parse::<Dithering>().is_err());
+    }
+}
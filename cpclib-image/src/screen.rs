@@ -11,6 +11,69 @@ pub const MAX_WIDTH_MODE3: u32 = 96 * 2;
 #[allow(missing_docs)]
 pub const MAX_HEIGHT: u32 = 39 * 8;
 
+/// Part of the CRTC configuration that defines which bytes of the memory are displayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrtcScreen {
+    /// R1: number of characters (i.e. 2 bytes) displayed per line
+    pub horizontal_displayed: u8,
+    /// R6: number of character lines displayed
+    pub vertical_displayed: u8,
+    /// R9: number of the last raster line of a character line
+    pub maximum_raster_address: u8,
+    /// R12/R13: address of the first character
+    pub start_address: u16
+}
+
+impl Default for CrtcScreen {
+    /// The screen set up by the firmware at 0xc000
+    fn default() -> Self {
+        Self {
+            horizontal_displayed: 40,
+            vertical_displayed: 25,
+            maximum_raster_address: 7,
+            start_address: 0x3000
+        }
+    }
+}
+
+impl CrtcScreen {
+    /// Build the configuration from the 18 CRTC registers
+    pub fn from_registers(registers: &[u8]) -> Self {
+        Self {
+            horizontal_displayed: registers[1],
+            vertical_displayed: registers[6],
+            maximum_raster_address: registers[9] & 0x1F,
+            start_address: (u16::from(registers[12] & 0x3F) << 8) + u16::from(registers[13])
+        }
+    }
+
+    /// Number of bytes displayed per line
+    pub fn bytes_width(&self) -> usize {
+        self.horizontal_displayed as usize * 2
+    }
+
+    /// Number of lines displayed
+    pub fn height(&self) -> usize {
+        self.vertical_displayed as usize * (self.maximum_raster_address as usize + 1)
+    }
+
+    /// Memory address of the byte displayed at the given line and byte column.
+    /// The 14 bits character counter of the CRTC overflows in the next bank, which allows 32kb screens
+    pub fn address(&self, line: usize, column: usize) -> u16 {
+        let rasters = self.maximum_raster_address as usize + 1;
+        let character = (self.start_address as usize
+            + (line / rasters) * self.horizontal_displayed as usize
+            + column / 2)
+            & 0x3FFF;
+        let raster = line % rasters;
+
+        (((character & 0x3000) << 2)
+            | ((raster & 7) << 11)
+            | ((character & 0x3FF) << 1)
+            | (column & 1)) as u16
+    }
+}
+
 #[allow(missing_docs)]
 #[derive(Debug)]
 pub struct SimpleMonitor {
@@ -17,6 +17,7 @@ use cpclib::image::convert::*;
 use cpclib::image::ga::{LockablePalette, Palette};
 use cpclib::image::image::{ColorMatrix, Mode};
 use cpclib::image::ocp::{self, OcpPalette};
+use cpclib::image::quantize::Dithering;
 use cpclib::sna::*;
 #[cfg(feature = "xferlib")]
 use cpclib::xfer::CpcXfer;
@@ -678,6 +679,12 @@ fn convert(matches: &ArgMatches, o: &dyn EventObserver) -> anyhow::Result<()> {
     if matches.get_flag("SKIP_ODD_PIXELS") {
         transformations = transformations.skip_odd_pixels();
     }
+    if let Some(dithering) = matches.get_one::<String>("DITHERING") {
+        transformations = transformations.reduce_colors(dithering.parse().unwrap());
+    }
+    else if matches.get_flag("REDUCE_COLORS") {
+        transformations = transformations.reduce_colors(Dithering::None);
+    }
     if matches.contains_id("PIXEL_COLUMN_START") {
         transformations = transformations.column_start(
             matches
@@ -1151,6 +1158,18 @@ pub fn build_img2cpc_args_parser() -> clap::Command {
                         .help("Skip odd pixels when reading the image (usefull when the picture is mode 0 with duplicated pixels")
                         .action(ArgAction::SetTrue)
                 )
+                .arg(
+                    Arg::new("REDUCE_COLORS")
+                        .long("reduce-colors")
+                        .help("Select the best inks for the pens not provided by the palette and replace each pixel by its closest ink. Without it, the image must not use more colors than the mode allows")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("DITHERING")
+                        .long("dithering")
+                        .help("Dithering used when reducing the colors (implies --reduce-colors)")
+                        .value_parser(Dithering::NAMES)
+                )
                 .arg(
                     Arg::new("PIXEL_COLUMN_START")
                     .long("columnstart")
@@ -0,0 +1,51 @@
+use cpclib_asm::AssemblerError;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum LinkError {
+    #[error("{0}")]
+    Assembler(Box<AssemblerError>),
+
+    #[error("Module {module} is not relocatable: {reason}")]
+    NotRelocatable { module: String, reason: String },
+
+    #[error("Invalid object file {file}: {msg}")]
+    InvalidObject { file: String, msg: String },
+
+    #[error("Placement script line {line}: {msg}")]
+    Script { line: usize, msg: String },
+
+    #[error("Section {section} of module {module} is not placed by the script")]
+    UnplacedSection { module: String, section: String },
+
+    #[error("Section {section} ends at 0x{end:04X} after its limit 0x{limit:04X}")]
+    SectionOverflow {
+        section: String,
+        end: u32,
+        limit: u16
+    },
+
+    #[error("Symbol {symbol} is exported by {first} and {second}")]
+    DuplicateSymbol {
+        symbol: String,
+        first: String,
+        second: String
+    },
+
+    #[error("Symbol {symbol} imported by {module} is not exported by any module")]
+    UndefinedSymbol { symbol: String, module: String },
+
+    #[error("Unable to produce {output}: {msg}")]
+    Output { output: String, msg: String },
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error)
+}
+
+impl From<Box<AssemblerError>> for LinkError {
+    fn from(error: Box<AssemblerError>) -> Self {
+        LinkError::Assembler(error)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, LinkError>;
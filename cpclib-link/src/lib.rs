@@ -0,0 +1,96 @@
+pub mod error;
+pub mod linker;
+pub mod object;
+pub mod script;
+
+use std::str::FromStr;
+
+use cpclib_asm::preamble::symbols_output::{SymbolOutputFormat, SymbolOutputGenerator};
+use cpclib_common::camino::Utf8PathBuf;
+use cpclib_common::clap;
+use cpclib_sna::SnapshotVersion;
+pub use error::{LinkError, Result};
+pub use linker::{Program, link};
+pub use object::Object;
+pub use script::Script;
+
+pub mod built_info {
+    include!(concat!(env!("OUT_DIR"), "/built.rs"));
+}
+
+#[derive(clap::Parser, Debug)]
+#[command(
+    version,
+    about = "Link the relocatable objects produced by `basm --object`",
+    long_about = None
+)]
+pub struct LinkArgs {
+    #[arg(required = true, help = "Objects to link, in placement order")]
+    objects: Vec<Utf8PathBuf>,
+
+    #[arg(
+        short,
+        long,
+        help = "Placement script giving the address of each section and the entry point"
+    )]
+    script: Utf8PathBuf,
+
+    #[arg(
+        short,
+        long,
+        help = "Linked file. A .sna extension produces a snapshot, a .cpr extension a cartridge, anything else a binary"
+    )]
+    output: Utf8PathBuf,
+
+    #[arg(long, help = "Filename of the symbols of the linked program")]
+    sym: Option<Utf8PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "basm",
+        value_parser = ["winape", "basm", "rasm", "sjasmplus", "ace", "nocash"],
+        help = "Format of the symbols file"
+    )]
+    sym_kind: String
+}
+
+/// Link the objects and save the result
+pub fn process(args: LinkArgs) -> Result<()> {
+    let script = Script::from_str(&fs_err::read_to_string(&args.script)?)?;
+    let objects = args
+        .objects
+        .iter()
+        .map(Object::load)
+        .collect::<Result<Vec<_>>>()?;
+
+    let program = link(&script, &objects)?;
+
+    match args
+        .output
+        .extension()
+        .map(|e| e.to_ascii_lowercase())
+        .as_deref()
+    {
+        Some("sna") => {
+            program
+                .snapshot()?
+                .save(&args.output, SnapshotVersion::V3)
+                .map_err(|e| {
+                    LinkError::Output {
+                        output: args.output.to_string(),
+                        msg: format!("{e:?}")
+                    }
+                })?;
+        },
+        Some("cpr") => program.cartridge()?.save(&args.output)?,
+        _ => fs_err::write(&args.output, program.binary()?)?
+    }
+
+    if let Some(sym) = &args.sym {
+        let format = SymbolOutputFormat::from_str(&args.sym_kind).unwrap();
+        let mut file = fs_err::File::create(sym)?;
+        SymbolOutputGenerator::default().generate(&mut file, &program.symbols_table(), format)?;
+    }
+
+    Ok(())
+}
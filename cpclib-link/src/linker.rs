@@ -0,0 +1,435 @@
+use std::collections::{BTreeMap, HashMap};
+
+use cpclib_asm::preamble::*;
+use cpclib_cpr::{CartridgeBank, Cpr};
+use cpclib_sna::{Snapshot, SnapshotFlag};
+
+use crate::error::{LinkError, Result};
+use crate::object::{Object, RelocationTarget};
+use crate::script::{Entry, Placement, Script};
+
+/// Bytes of all the modules placed in a section of the script
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub placement: Placement,
+    pub bytes: Vec<u8>
+}
+
+impl Chunk {
+    fn start(&self) -> u16 {
+        self.placement.area.start()
+    }
+
+    fn mmr(&self) -> u8 {
+        self.placement.area.mmr()
+    }
+
+    /// Address following the last byte
+    fn end(&self) -> u32 {
+        self.start() as u32 + self.bytes.len() as u32
+    }
+
+    fn overlaps(&self, other: &Chunk) -> bool {
+        !self.bytes.is_empty()
+            && !other.bytes.is_empty()
+            && self.placement.bloc == other.placement.bloc
+            && self.mmr() == other.mmr()
+            && (self.start() as u32) < other.end()
+            && (other.start() as u32) < self.end()
+    }
+}
+
+/// Result of the link of several objects
+#[derive(Debug, Clone)]
+pub struct Program {
+    chunks: Vec<Chunk>,
+    symbols: BTreeMap<String, Value>,
+    entry: Option<u16>
+}
+
+/// Place the sections of the objects according to the script and resolve their relocations.
+/// Sections with the same name are concatenated in the order of the objects
+pub fn link(script: &Script, objects: &[Object]) -> Result<Program> {
+    let mut chunks = script
+        .placements()
+        .iter()
+        .map(|placement| {
+            Chunk {
+                placement: placement.clone(),
+                bytes: Vec::new()
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // address of each section of each object
+    let mut bases = HashMap::new();
+    for (idx, object) in objects.iter().enumerate() {
+        for section in &object.sections {
+            let chunk = chunks
+                .iter_mut()
+                .find(|c| c.placement.area.name() == section.name)
+                .ok_or_else(|| {
+                    LinkError::UnplacedSection {
+                        module: object.name.clone(),
+                        section: section.name.clone()
+                    }
+                })?;
+            bases.insert((idx, section.name.as_str()), chunk.end() as u16);
+            chunk.bytes.extend_from_slice(&section.bytes);
+            if chunk.end() - 1 > chunk.placement.area.stop() as u32 {
+                return Err(LinkError::SectionOverflow {
+                    section: section.name.clone(),
+                    end: chunk.end() - 1,
+                    limit: chunk.placement.area.stop()
+                });
+            }
+        }
+    }
+
+    for (idx, chunk) in chunks.iter().enumerate() {
+        if let Some(other) = chunks[idx + 1..].iter().find(|c| c.overlaps(chunk)) {
+            return Err(LinkError::Output {
+                output: "the memory".to_owned(),
+                msg: format!(
+                    "sections {} and {} overlap",
+                    chunk.placement.area.name(),
+                    other.placement.area.name()
+                )
+            });
+        }
+    }
+
+    // exported symbols with their final value
+    let mut symbols: BTreeMap<String, (Value, &str)> = BTreeMap::new();
+    for (idx, object) in objects.iter().enumerate() {
+        for export in &object.exports {
+            let value = match &export.section {
+                Some(section) => {
+                    let base = bases.get(&(idx, section.as_str())).ok_or_else(|| {
+                        LinkError::InvalidObject {
+                            file: object.name.clone(),
+                            msg: format!("{} refers to the unknown section {section}", export.name)
+                        }
+                    })?;
+                    let chunk = chunks
+                        .iter()
+                        .find(|c| c.placement.area.name() == section)
+                        .unwrap();
+                    address_value(base.wrapping_add(export.value as u16), &chunk.placement)
+                },
+                None => Value::from(export.value)
+            };
+            if let Some((_, first)) = symbols.get(&export.name) {
+                return Err(LinkError::DuplicateSymbol {
+                    symbol: export.name.clone(),
+                    first: first.to_string(),
+                    second: object.name.clone()
+                });
+            }
+            symbols.insert(export.name.clone(), (value, &object.name));
+        }
+    }
+    let symbols = symbols
+        .into_iter()
+        .map(|(name, (value, _))| (name, value))
+        .collect::<BTreeMap<_, _>>();
+
+    // patch the bytes now that everything is known
+    for (idx, object) in objects.iter().enumerate() {
+        for section in &object.sections {
+            let base = bases[&(idx, section.name.as_str())];
+            let chunk = chunks
+                .iter_mut()
+                .find(|c| c.placement.area.name() == section.name)
+                .unwrap();
+            let chunk_start = chunk.start();
+            for relocation in &section.relocations {
+                let value = match &relocation.target {
+                    RelocationTarget::Section { name } => {
+                        *bases.get(&(idx, name.as_str())).ok_or_else(|| {
+                            LinkError::InvalidObject {
+                                file: object.name.clone(),
+                                msg: format!("relocation to the unknown section {name}")
+                            }
+                        })?
+                    },
+                    RelocationTarget::Import { name } => {
+                        symbols.get(name).and_then(|v| v.integer()).ok_or_else(|| {
+                            LinkError::UndefinedSymbol {
+                                symbol: name.clone(),
+                                module: object.name.clone()
+                            }
+                        })? as u16
+                    },
+                };
+
+                let offset = (base - chunk_start) as usize + relocation.offset as usize;
+                if relocation.offset as usize + 1 >= section.bytes.len() {
+                    return Err(LinkError::InvalidObject {
+                        file: object.name.clone(),
+                        msg: format!("relocation outside of section {}", section.name)
+                    });
+                }
+                let word = u16::from_le_bytes([chunk.bytes[offset], chunk.bytes[offset + 1]])
+                    .wrapping_add(value);
+                chunk.bytes[offset..offset + 2].copy_from_slice(&word.to_le_bytes());
+            }
+        }
+    }
+
+    let entry = match script.entry() {
+        None => None,
+        Some(Entry::Address(address)) => Some(*address),
+        Some(Entry::Symbol(name)) => {
+            Some(symbols.get(name).and_then(|v| v.integer()).ok_or_else(|| {
+                LinkError::UndefinedSymbol {
+                    symbol: name.clone(),
+                    module: "the placement script".to_owned()
+                }
+            })? as u16)
+        },
+    };
+
+    Ok(Program {
+        chunks,
+        symbols,
+        entry
+    })
+}
+
+/// Value of a label placed in a section
+fn address_value(address: u16, placement: &Placement) -> Value {
+    let address: PhysicalAddress = match placement.bloc {
+        Some(bloc) => CprPhysicalAddress::new(address, bloc).into(),
+        None => MemoryPhysicalAddress::new(address, placement.area.mmr()).into()
+    };
+    Value::Address(address)
+}
+
+impl Program {
+    pub fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+
+    pub fn entry(&self) -> Option<u16> {
+        self.entry
+    }
+
+    /// Value of an exported symbol
+    pub fn symbol(&self, name: &str) -> Option<&Value> {
+        self.symbols.get(name)
+    }
+
+    /// Exported symbols of all the modules
+    pub fn symbols_table(&self) -> SymbolsTable {
+        let mut table = SymbolsTable::default();
+        for (name, value) in &self.symbols {
+            table
+                .assign_symbol_to_value(name.as_str(), value.clone())
+                .expect("Symbols are unique");
+        }
+        table
+    }
+
+    fn filled_chunks(&self) -> impl Iterator<Item = &Chunk> {
+        self.chunks.iter().filter(|c| !c.bytes.is_empty())
+    }
+
+    /// Memory from the lowest to the highest generated byte, gaps are filled with 0
+    pub fn binary(&self) -> Result<Vec<u8>> {
+        let error = |msg: String| {
+            LinkError::Output {
+                output: "a binary".to_owned(),
+                msg
+            }
+        };
+        if let Some(chunk) = self
+            .filled_chunks()
+            .find(|c| c.placement.bloc.is_some() || c.mmr() != 0xC0)
+        {
+            return Err(error(format!(
+                "section {} is not in the main memory",
+                chunk.placement.area.name()
+            )));
+        }
+
+        let Some(start) = self.filled_chunks().map(|c| c.start()).min()
+        else {
+            return Ok(Vec::new());
+        };
+        let end = self.filled_chunks().map(|c| c.end()).max().unwrap();
+
+        let mut bytes = vec![0; (end - start as u32) as usize];
+        for chunk in self.filled_chunks() {
+            let offset = (chunk.start() - start) as usize;
+            bytes[offset..offset + chunk.bytes.len()].copy_from_slice(&chunk.bytes);
+        }
+        Ok(bytes)
+    }
+
+    /// 6128 snapshot with the sections in memory and PC set to the entry point
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        let error = |msg: String| {
+            LinkError::Output {
+                output: "a snapshot".to_owned(),
+                msg
+            }
+        };
+        let mut sna = Snapshot::new_6128().map_err(error)?;
+
+        for chunk in self.filled_chunks() {
+            if chunk.placement.bloc.is_some() {
+                return Err(error(format!(
+                    "section {} is in a cartridge bloc",
+                    chunk.placement.area.name()
+                )));
+            }
+            for (idx, byte) in chunk.bytes.iter().enumerate() {
+                let address = MemoryPhysicalAddress::new(chunk.start() + idx as u16, chunk.mmr());
+                sna.set_byte(address.offset_in_cpc(), *byte);
+            }
+        }
+
+        if let Some(entry) = self.entry {
+            sna.set_value(SnapshotFlag::Z80_PC, entry)
+                .map_err(|e| error(format!("{e:?}")))?;
+        }
+        Ok(sna)
+    }
+
+    /// Cartridge where each section is stored in its bloc
+    pub fn cartridge(&self) -> Result<Cpr> {
+        let error = |msg: String| {
+            LinkError::Output {
+                output: "a cartridge".to_owned(),
+                msg
+            }
+        };
+        let mut banks: BTreeMap<u8, CartridgeBank> = BTreeMap::new();
+
+        for chunk in self.filled_chunks() {
+            let name = chunk.placement.area.name();
+            let bloc = chunk
+                .placement
+                .bloc
+                .ok_or_else(|| error(format!("section {name} has no bloc")))?;
+            if bloc >= 32 {
+                return Err(error(format!(
+                    "bloc {bloc} of section {name} does not exist"
+                )));
+            }
+            let offset = chunk.start() & 0x3FFF;
+            if offset as usize + chunk.bytes.len() > 0x4000 {
+                return Err(error(format!("section {name} does not fit in its bloc")));
+            }
+            let bank = banks
+                .entry(bloc)
+                .or_insert_with(|| CartridgeBank::new(bloc));
+            for (idx, byte) in chunk.bytes.iter().enumerate() {
+                bank.set_byte(offset + idx as u16, *byte);
+            }
+        }
+
+        let mut cpr = Cpr::empty();
+        for bank in banks.into_values() {
+            cpr.add_bank(bank);
+        }
+        Ok(cpr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn object(name: &str, code: &str) -> Object {
+        let listing = parse_z80_str(code).unwrap();
+        Object::assemble(name, &listing, &EnvOptions::default(), "code")
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn test_relocatable_object() {
+        let main = object(
+            "main",
+            " call draw\n jp loop\nloop jr loop\nvalue equ 5\n dw loop, value\n"
+        );
+
+        assert_eq!(main.imports, vec!["draw".to_owned()]);
+        let relocations = &main.sections[0].relocations;
+        assert_eq!(relocations.len(), 3);
+        assert_eq!(relocations[0].offset, 1);
+        assert_eq!(
+            relocations[0].target,
+            RelocationTarget::Import {
+                name: "draw".to_owned()
+            }
+        );
+        assert_eq!(relocations[1].offset, 4);
+        assert_eq!(relocations[2].offset, 8);
+
+        let loop_ = main.exports.iter().find(|s| s.name == "loop").unwrap();
+        assert_eq!(loop_.value, 6);
+        assert_eq!(loop_.section.as_deref(), Some("code"));
+        let value = main.exports.iter().find(|s| s.name == "value").unwrap();
+        assert_eq!(value.value, 5);
+        assert_eq!(value.section, None);
+
+        let saved = serde_json::to_string(&main).unwrap();
+        assert_eq!(serde_json::from_str::<Object>(&saved).unwrap(), main);
+    }
+
+    #[test]
+    fn test_not_relocatable() {
+        let listing = parse_z80_str(" ld a, label & 255\nlabel nop\n").unwrap();
+        assert!(matches!(
+            Object::assemble("bad", &listing, &EnvOptions::default(), "code"),
+            Err(LinkError::NotRelocatable { .. })
+        ));
+    }
+
+    #[test]
+    fn test_link_two_modules() {
+        let main = object("main", "start\n call draw\n jr start\n");
+        let draw = object("draw", "draw\n ld a, (message)\n ret\nmessage db 65\n");
+
+        let script = Script::from_str("section code at 0x4000\nentry start").unwrap();
+        let program = link(&script, &[main.clone(), draw.clone()]).unwrap();
+
+        assert_eq!(program.entry(), Some(0x4000));
+        assert_eq!(program.symbol("draw").unwrap().integer(), Some(0x4005));
+        assert_eq!(
+            program.binary().unwrap(),
+            vec![0xCD, 0x05, 0x40, 0x18, 0xFB, 0x3A, 0x09, 0x40, 0xC9, 65]
+        );
+
+        let snapshot = program.snapshot().unwrap();
+        assert_eq!(snapshot.get_byte(0x4006), 0x09);
+
+        assert!(matches!(
+            link(
+                &Script::from_str("section code at 0x4000 limit 0x4004").unwrap(),
+                &[main.clone(), draw.clone()]
+            ),
+            Err(LinkError::SectionOverflow { .. })
+        ));
+        assert!(matches!(
+            link(&script, std::slice::from_ref(&main)),
+            Err(LinkError::UndefinedSymbol { .. })
+        ));
+        assert!(matches!(
+            link(&script, &[main.clone(), draw.clone(), draw]),
+            Err(LinkError::DuplicateSymbol { .. })
+        ));
+        assert!(matches!(
+            link(
+                &Script::from_str("section data at 0x4000").unwrap(),
+                &[main]
+            ),
+            Err(LinkError::UnplacedSection { .. })
+        ));
+    }
+}
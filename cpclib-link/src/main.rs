@@ -0,0 +1,7 @@
+use cpclib_common::clap::Parser;
+use cpclib_link::LinkArgs;
+
+fn main() -> Result<(), String> {
+    let args = LinkArgs::parse();
+    cpclib_link::process(args).map_err(|e| e.to_string())
+}
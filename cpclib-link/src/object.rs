@@ -0,0 +1,326 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+use cpclib_asm::preamble::*;
+use cpclib_common::camino::Utf8Path;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{LinkError, Result};
+
+/// Version of the object format, increased at each incompatible change
+pub const OBJECT_VERSION: u32 = 1;
+
+/// Address where the module is assembled to obtain its reference bytes
+const ORIGIN: u16 = 0x0000;
+/// Displacement applied to the origin or to an import to spot the words that depend on it.
+/// Its low byte is not null, so the low byte of a relocated word always changes
+const DELTA: u16 = 0x1111;
+
+/// Module assembled once and placed later by the linker
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Object {
+    pub version: u32,
+    /// Name of the module (the source file stem for basm)
+    pub name: String,
+    pub sections: Vec<ObjectSection>,
+    /// Symbols provided to the other modules
+    pub exports: Vec<ObjectSymbol>,
+    /// Symbols expected from the other modules
+    pub imports: Vec<String>
+}
+
+/// Bytes of a module that are placed together
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObjectSection {
+    pub name: String,
+    /// Bytes assembled at address 0 with all the imports set to 0
+    pub bytes: Vec<u8>,
+    pub relocations: Vec<Relocation>
+}
+
+/// 16-bit little-endian word to fix once the addresses are known
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Relocation {
+    /// Position of the word in the bytes of the section
+    pub offset: u16,
+    pub target: RelocationTarget
+}
+
+/// Value added to a relocated word
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum RelocationTarget {
+    /// Address where the section is placed
+    Section { name: String },
+    /// Value of a symbol exported by another module
+    Import { name: String }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObjectSymbol {
+    pub name: String,
+    /// Offset in the section, or value when there is no section
+    pub value: i32,
+    pub section: Option<String>
+}
+
+impl Object {
+    /// Assemble `listing` as a relocatable module whose bytes belong to `section`.
+    ///
+    /// The listing is assembled several times: once at the reference address, once at a
+    /// displaced address and once per import with a displaced value. The words that follow
+    /// the displacement are relocations; any other difference means the code cannot be
+    /// relocated (ORG, address used in an 8-bit expression, ...).
+    /// The environment of the reference assembling is returned along with the object.
+    pub fn assemble(
+        name: &str,
+        listing: &LocatedListing,
+        options: &EnvOptions,
+        section: &str
+    ) -> Result<(Self, Env)> {
+        let imports = discover_imports(listing, options)?;
+        let not_relocatable = |reason: String| {
+            LinkError::NotRelocatable {
+                module: name.to_owned(),
+                reason
+            }
+        };
+
+        let env = assemble_at(listing, options.clone(), ORIGIN, &imports, None)?;
+        let bytes = module_bytes(&env, ORIGIN).map_err(not_relocatable)?;
+
+        let moved = assemble_at(listing, silent(options), ORIGIN + DELTA, &imports, None)?;
+        let moved_bytes = module_bytes(&moved, ORIGIN + DELTA).map_err(not_relocatable)?;
+        let mut relocations = relocated_words(&bytes, &moved_bytes)
+            .map_err(not_relocatable)?
+            .into_iter()
+            .map(|offset| {
+                Relocation {
+                    offset,
+                    target: RelocationTarget::Section {
+                        name: section.to_owned()
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        for import in &imports {
+            let shifted = assemble_at(listing, silent(options), ORIGIN, &imports, Some(import))?;
+            let shifted_bytes = module_bytes(&shifted, ORIGIN).map_err(not_relocatable)?;
+            relocations.extend(
+                relocated_words(&bytes, &shifted_bytes)
+                    .map_err(not_relocatable)?
+                    .into_iter()
+                    .map(|offset| {
+                        Relocation {
+                            offset,
+                            target: RelocationTarget::Import {
+                                name: import.clone()
+                            }
+                        }
+                    })
+            );
+        }
+        relocations.sort_by_key(|r| r.offset);
+
+        let exports = exported_symbols(&env, &moved, &imports, section);
+
+        let object = Object {
+            version: OBJECT_VERSION,
+            name: name.to_owned(),
+            sections: vec![ObjectSection {
+                name: section.to_owned(),
+                bytes,
+                relocations
+            }],
+            exports,
+            imports: imports.into_iter().collect()
+        };
+        Ok((object, env))
+    }
+
+    pub fn load<P: AsRef<Utf8Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs_err::read_to_string(path)?;
+        let object: Object = serde_json::from_str(&content).map_err(|e| {
+            LinkError::InvalidObject {
+                file: path.to_string(),
+                msg: e.to_string()
+            }
+        })?;
+        if object.version != OBJECT_VERSION {
+            return Err(LinkError::InvalidObject {
+                file: path.to_string(),
+                msg: format!("version {} instead of {}", object.version, OBJECT_VERSION)
+            });
+        }
+        Ok(object)
+    }
+
+    pub fn save<P: AsRef<Utf8Path>>(&self, path: P) -> Result<()> {
+        let content = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        fs_err::write(path.as_ref(), content)?;
+        Ok(())
+    }
+}
+
+/// Options of the extra assemblings: no message and no side effect
+fn silent(options: &EnvOptions) -> EnvOptions {
+    let mut assemble = options.assemble_options().clone();
+    assemble.set_dry_run(true);
+    EnvOptions::new(options.parse_options().clone(), assemble, Arc::new(()))
+}
+
+/// Symbols used but never defined by the module
+fn discover_imports(listing: &LocatedListing, options: &EnvOptions) -> Result<BTreeSet<String>> {
+    let mut imports = BTreeSet::new();
+    loop {
+        let options = import_options(silent(options), ORIGIN, &imports, None)?;
+        let (env, error) = match visit_tokens_all_passes_with_options(listing, options) {
+            Ok(_) => return Ok(imports),
+            Err((_, env, error)) => (env, error)
+        };
+
+        let symbols = env.symbols();
+        let unknown = symbols
+            .used_symbols()
+            .filter(|s| !symbols.contains_symbol(s.value()).unwrap_or(true))
+            .map(|s| s.value().to_owned())
+            .filter(|s| !imports.contains(s))
+            .collect::<Vec<_>>();
+        if unknown.is_empty() {
+            return Err(LinkError::Assembler(error));
+        }
+        imports.extend(unknown);
+    }
+}
+
+/// Options to assemble at `origin` with all the imports set to 0, except `shifted` set to DELTA
+fn import_options(
+    mut options: EnvOptions,
+    origin: u16,
+    imports: &BTreeSet<String>,
+    shifted: Option<&String>
+) -> Result<EnvOptions> {
+    options.assemble_options_mut().set_default_origin(origin);
+    for import in imports {
+        let value = if Some(import) == shifted { DELTA } else { 0 };
+        options
+            .symbols_mut()
+            .assign_symbol_to_value(import.as_str(), Value::from(value))
+            .map_err(|e| Box::new(AssemblerError::from(e)))?;
+    }
+    Ok(options)
+}
+
+fn assemble_at(
+    listing: &LocatedListing,
+    options: EnvOptions,
+    origin: u16,
+    imports: &BTreeSet<String>,
+    shifted: Option<&String>
+) -> Result<Env> {
+    let options = import_options(options, origin, imports, shifted)?;
+    visit_tokens_all_passes_with_options(listing, options)
+        .map(|(_, env)| env)
+        .map_err(|(_, _, e)| LinkError::Assembler(e))
+}
+
+/// Bytes produced from `origin`
+fn module_bytes(env: &Env, origin: u16) -> std::result::Result<Vec<u8>, String> {
+    match env.start_address() {
+        None => Ok(Vec::new()),
+        Some(start) if start != origin => {
+            Err(format!(
+                "its first byte is assembled at 0x{start:04X} (is there an ORG?)"
+            ))
+        },
+        Some(_) => Ok(env.produced_bytes())
+    }
+}
+
+/// Offsets of the words that differ by DELTA between both assemblings
+fn relocated_words(reference: &[u8], moved: &[u8]) -> std::result::Result<Vec<u16>, String> {
+    if reference.len() != moved.len() {
+        return Err(format!(
+            "its size changes with its address ({} and {} bytes)",
+            reference.len(),
+            moved.len()
+        ));
+    }
+
+    let word = |bytes: &[u8], offset: usize| {
+        bytes
+            .get(offset..offset + 2)
+            .map(|w| u16::from_le_bytes([w[0], w[1]]))
+    };
+
+    let mut offsets = Vec::new();
+    let mut offset = 0;
+    while offset < reference.len() {
+        if reference[offset] == moved[offset] {
+            offset += 1;
+            continue;
+        }
+        match (word(reference, offset), word(moved, offset)) {
+            (Some(a), Some(b)) if b.wrapping_sub(a) == DELTA => {
+                offsets.push(offset as u16);
+                offset += 2;
+            },
+            _ => {
+                return Err(format!(
+                    "byte at offset 0x{offset:04X} depends on an address but is not a 16-bit word"
+                ));
+            }
+        }
+    }
+    Ok(offsets)
+}
+
+/// Symbols defined by the module. Labels are relative to the section, other values are constants.
+/// Values that neither follow nor ignore the displacement cannot be expressed and are dropped
+fn exported_symbols(
+    env: &Env,
+    moved: &Env,
+    imports: &BTreeSet<String>,
+    section: &str
+) -> Vec<ObjectSymbol> {
+    let moved_values = moved
+        .symbols()
+        .expression_symbol()
+        .into_iter()
+        .filter_map(|(symbol, value)| value.integer().map(|v| (symbol.value().to_owned(), v)))
+        .collect::<HashMap<_, _>>();
+
+    let mut exports = env
+        .symbols()
+        .expression_symbol()
+        .into_iter()
+        .filter(|(symbol, value)| {
+            value.is_located()
+                && !imports.contains(symbol.value())
+                && env.symbols_output().keep_symbol(symbol)
+        })
+        .filter_map(|(symbol, value)| {
+            let name = symbol.value();
+            let value = value.integer()?;
+            let moved = *moved_values.get(name)?;
+            let section = if moved == value {
+                None
+            }
+            else if moved - value == DELTA as i32 {
+                Some(section.to_owned())
+            }
+            else {
+                return None;
+            };
+            Some(ObjectSymbol {
+                name: name.to_owned(),
+                value: value - ORIGIN as i32,
+                section
+            })
+        })
+        .collect::<Vec<_>>();
+    exports.sort_by(|a, b| a.name.cmp(&b.name));
+    exports
+}
@@ -0,0 +1,153 @@
+use std::str::FromStr;
+
+use cpclib_asm::preamble::section::Section;
+use cpclib_common::parse_value;
+use cpclib_common::winnow::Parser;
+use cpclib_common::winnow::error::ParseError;
+
+use crate::error::{LinkError, Result};
+
+/// Where the linked program starts
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+    Address(u16),
+    Symbol(String)
+}
+
+/// Address of a section in the final memory
+#[derive(Debug, Clone)]
+pub struct Placement {
+    /// Memory area (start, limit and memory configuration) of the section
+    pub area: Section,
+    /// Cartridge bloc that receives the section
+    pub bloc: Option<u8>
+}
+
+/// Placement script of the linker.
+///
+/// ```text
+/// ; comments start with a semicolon
+/// section code at 0x4000 limit 0x7FFF
+/// section data at 0xC000 mmr 0xC4
+/// section music at 0x0000 bloc 2
+/// entry start
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    placements: Vec<Placement>,
+    entry: Option<Entry>
+}
+
+impl Script {
+    pub fn placements(&self) -> &[Placement] {
+        &self.placements
+    }
+
+    pub fn placement(&self, section: &str) -> Option<&Placement> {
+        self.placements.iter().find(|p| p.area.name() == section)
+    }
+
+    pub fn entry(&self) -> Option<&Entry> {
+        self.entry.as_ref()
+    }
+}
+
+impl FromStr for Script {
+    type Err = LinkError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut script = Script::default();
+
+        for (idx, line) in s.lines().enumerate() {
+            let error = |msg: String| LinkError::Script { line: idx + 1, msg };
+            let line = line.split(';').next().unwrap();
+            let words = line.split_whitespace().collect::<Vec<_>>();
+
+            match words.as_slice() {
+                [] => {},
+                ["entry", value] => {
+                    let entry = match parse_u16(value) {
+                        Ok(address) => Entry::Address(address),
+                        Err(_) => Entry::Symbol(value.to_string())
+                    };
+                    if script.entry.replace(entry).is_some() {
+                        return Err(error("entry already set".to_owned()));
+                    }
+                },
+                ["section", name, "at", start, options @ ..] => {
+                    if script.placement(name).is_some() {
+                        return Err(error(format!("section {name} already placed")));
+                    }
+                    let start = parse_u16(start).map_err(error)?;
+                    let mut limit = 0xFFFF;
+                    let mut mmr = 0xC0;
+                    let mut bloc = None;
+                    for option in options.chunks(2) {
+                        match option {
+                            ["limit", value] => limit = parse_u16(value).map_err(error)?,
+                            ["mmr", value] => mmr = parse_u8(value).map_err(error)?,
+                            ["bloc", value] => bloc = Some(parse_u8(value).map_err(error)?),
+                            _ => {
+                                return Err(error(format!("unexpected \"{}\"", option.join(" "))));
+                            }
+                        }
+                    }
+                    if limit < start {
+                        return Err(error(format!("limit of section {name} before its start")));
+                    }
+                    script.placements.push(Placement {
+                        area: Section::new(name, start, limit, mmr),
+                        bloc
+                    });
+                },
+                _ => return Err(error(format!("unable to parse \"{}\"", line.trim())))
+            }
+        }
+
+        Ok(script)
+    }
+}
+
+fn parse_u32(s: &str) -> std::result::Result<u32, String> {
+    let result: std::result::Result<u32, ParseError<_, ()>> = parse_value.parse(s.as_bytes());
+    result.map_err(|_| format!("invalid numeric value {s}"))
+}
+
+fn parse_u16(s: &str) -> std::result::Result<u16, String> {
+    parse_u32(s)?
+        .try_into()
+        .map_err(|_| format!("{s} does not fit in 16 bits"))
+}
+
+fn parse_u8(s: &str) -> std::result::Result<u8, String> {
+    parse_u32(s)?
+        .try_into()
+        .map_err(|_| format!("{s} does not fit in 8 bits"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_script() {
+        let script = Script::from_str(
+            "; memory layout\nsection code at 0x4000 limit 0x7FFF\n\nsection data at #C000 mmr 0xC4 ; banked\nsection music at 0 bloc 2\nentry start\n"
+        )
+        .unwrap();
+
+        assert_eq!(script.placements().len(), 3);
+        let code = script.placement("code").unwrap();
+        assert_eq!(code.area.start(), 0x4000);
+        assert_eq!(code.area.stop(), 0x7FFF);
+        assert_eq!(code.area.mmr(), 0xC0);
+        assert_eq!(script.placement("data").unwrap().area.mmr(), 0xC4);
+        assert_eq!(script.placement("music").unwrap().bloc, Some(2));
+        assert_eq!(script.entry(), Some(&Entry::Symbol("start".to_owned())));
+
+        assert!(Script::from_str("section code 0x4000").is_err());
+        assert!(Script::from_str("section code at 0x4000 limit").is_err());
+        assert!(Script::from_str("section code at 0x4000 limit 0x3000").is_err());
+        assert!(Script::from_str("section code at 0x4000\nsection code at 0x8000").is_err());
+    }
+}
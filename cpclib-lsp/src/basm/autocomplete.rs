@@ -11,7 +11,7 @@ use cpclib_tokens::{ListingElement, Token};
 use tower_lsp::lsp_types::*;
 
 use super::AssemblyAnalyzer;
-use super::token::{DIRECTIVE_FILE_ARGS, SNASET_FLAGS, is_ident_byte};
+use super::token::{DIRECTIVE_FILE_ARGS, DIRECTIVE_SET, SNASET_FLAGS, is_ident_byte};
 use crate::common::document::Document;
 
 /// Semantic completion context for Z80 assembly.
@@ -1075,6 +1075,17 @@ impl AssemblyAnalyzer {
                     completions.extend(snaset_flag_completions());
                 }
                 else {
+                    // A macro call also accepts its parameters as `name=value`.
+                    let before = &line[..byte_offset_for_col(&line, col)];
+                    let (name, args) = split_first_word(skip_label_definition(before.trim_start()));
+                    if !DIRECTIVE_SET.contains(directive.as_str()) {
+                        completions.extend(self.macro_named_argument_completions(
+                            document,
+                            name,
+                            args,
+                            symbol_range
+                        ));
+                    }
                     // Directives accept any expression — offer symbols.
                     for (sym, detail) in &collect_doc_symbols() {
                         completions.push(symbol_item(sym, detail, symbol_range));
@@ -1086,6 +1097,61 @@ impl AssemblyAnalyzer {
         completions
     }
 
+    /// `name=` completions for the parameters of the macro `name` that `args`
+    /// (the call's text up to the cursor) does not already set, either
+    /// positionally or by name. Nothing is offered once the current argument
+    /// has its own `=`.
+    fn macro_named_argument_completions(
+        &self,
+        document: &Document,
+        name: &str,
+        args: &str,
+        range: Range
+    ) -> Vec<CompletionItem> {
+        let mut previous = args.split(',').collect::<Vec<_>>();
+        if previous.pop().is_some_and(|current| current.contains('=')) {
+            return Vec::new();
+        }
+        let Some(params) = self.macro_parameters(document, name)
+        else {
+            return Vec::new();
+        };
+        let named = |arg: &str| {
+            arg.split_once('=')
+                .filter(|(_, value)| !value.starts_with('='))
+                .map(|(param, _)| param.trim().to_owned())
+        };
+        let given = previous
+            .iter()
+            .copied()
+            .filter_map(named)
+            .collect::<Vec<_>>();
+        let nb_positional = previous.iter().filter(|arg| named(arg).is_none()).count();
+
+        params
+            .iter()
+            .skip(nb_positional)
+            .map(|(param, default)| (param.trim_start_matches("r#"), default))
+            .filter(|(param, _)| *param != "..." && !given.iter().any(|given| given == param))
+            .map(|(param, default)| {
+                let detail = match default {
+                    Some(default) => format!("parameter of {name} (default: {default})"),
+                    None => format!("parameter of {name}")
+                };
+                CompletionItem {
+                    label: format!("{param}="),
+                    kind: Some(CompletionItemKind::FIELD),
+                    detail: Some(detail),
+                    text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                        range,
+                        new_text: format!("{param}=")
+                    })),
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+
     /// Collect `(name, detail)` for labels / EQU / assigns / macros of a document.
     ///
     /// Falls back to a text scan when the document does not parse — which is
@@ -2491,3 +2557,36 @@ mod symbols_cache_tests {
         );
     }
 }
+
+#[cfg(test)]
+mod macro_named_argument_tests {
+    use super::*;
+    use crate::common::document::Document;
+
+    fn labels_at_end(text: &str) -> Vec<String> {
+        let uri = Url::parse("file:///t.asm").unwrap();
+        let doc = Document::new(uri, text.to_string(), 1);
+        let line = text.lines().count() as u32 - 1;
+        let character = text.lines().last().unwrap().len() as u32;
+        AssemblyAnalyzer::new()
+            .completion(&doc, Position { line, character })
+            .iter()
+            .map(|i| i.label.clone())
+            .collect()
+    }
+
+    #[test]
+    fn macro_call_offers_the_parameters_it_does_not_set_yet() {
+        let ls = labels_at_end("MACRO blit x, y, r#label, height=8\nENDM\nblit 1, height=2, ");
+        assert!(ls.contains(&"y=".to_string()), "{ls:?}");
+        assert!(ls.contains(&"label=".to_string()), "{ls:?}");
+        assert!(!ls.contains(&"x=".to_string()), "{ls:?}");
+        assert!(!ls.contains(&"height=".to_string()), "{ls:?}");
+    }
+
+    #[test]
+    fn no_parameter_is_offered_for_the_value_of_a_named_argument() {
+        let ls = labels_at_end("MACRO blit x, y\nENDM\nblit y=");
+        assert!(!ls.iter().any(|l| l.ends_with('=')), "{ls:?}");
+    }
+}
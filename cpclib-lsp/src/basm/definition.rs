@@ -1070,7 +1070,7 @@ fn find_scoped_local_matches(
 
 // ─── Include file navigation ──────────────────────────────────────────────────
 
-const INCLUDE_DIRECTIVES: &[&str] = &["INCLUDE", "INCBIN", "BINCLUDE"];
+const INCLUDE_DIRECTIVES: &[&str] = &["INCLUDE", "INCBIN", "BINCLUDE", "INCIMG"];
 
 /// Directory-level markers that indicate the project root.  We stop walking
 /// up the ancestor tree when we find one of these in the current directory.
@@ -1092,7 +1092,7 @@ fn resolve_include_at(line: &str, col: usize, doc_uri: &Url) -> Option<Url> {
 }
 
 /// If `col` is inside a double-quoted string on a line that starts with an
-/// include-like directive (`INCLUDE`/`INCBIN`/`BINCLUDE`), return the raw
+/// include-like directive (`INCLUDE`/`INCBIN`/`BINCLUDE`/`INCIMG`), return the raw
 /// filename text (unresolved — may be a relative on-disk path or an
 /// `inner://...` embedded-resource reference). Shared by ctrl+click
 /// navigation (`resolve_include_at`) and hover content preview.
@@ -1101,9 +1101,9 @@ pub(super) fn include_filename_at(line: &str, col: usize) -> Option<String> {
 }
 
 /// As [`include_filename_at`], but also reports which directive matched
-/// (`"INCLUDE"`/`"INCBIN"`/`"BINCLUDE"`) — hover needs to tell them apart:
-/// `INCBIN` targets are raw binary data and get a hex/ASCII dump instead of
-/// a text preview.
+/// (`"INCLUDE"`/`"INCBIN"`/`"BINCLUDE"`/`"INCIMG"`) — hover needs to tell them
+/// apart: `INCBIN` targets are raw binary data and get a hex/ASCII dump instead
+/// of a text preview, and `INCIMG` images are not previewed.
 pub(super) fn include_directive_and_filename_at(
     line: &str,
     col: usize
@@ -412,6 +412,77 @@ impl AssemblyAnalyzer {
         }
     }
 
+    /// `(name, default)` of each parameter of the macro `name`, as declared
+    /// by its `MACRO` line. Read from the dry-run `Env` (so macros of the
+    /// included files are known too) when possible, and from the raw text
+    /// otherwise - the common case while a call is still being typed.
+    pub(super) fn macro_parameters(
+        &self,
+        document: &Document,
+        name: &str
+    ) -> Option<Vec<(String, Option<String>)>> {
+        let Ok(listing) = self.parse_document(document)
+        else {
+            return macro_parameters_by_text(&document.text(), name);
+        };
+        let env = self.dry_run_env_cached(document, &listing);
+        let Ok(Some(r#macro)) = env.symbols().macro_value(name)
+        else {
+            // the dry run may have stopped before the definition
+            return macro_parameters_by_text(&document.text(), name);
+        };
+        Some(
+            r#macro
+                .params()
+                .iter()
+                .enumerate()
+                .map(|(idx, param)| {
+                    (
+                        param.to_string(),
+                        r#macro.default_value(idx).map(ToOwned::to_owned)
+                    )
+                })
+                .collect()
+        )
+    }
+
+    /// Hover for the name of a named macro argument (`height` in
+    /// `BLIT x, y, height=3`): the parameter it sets and its default value.
+    pub(super) fn macro_named_argument_hover(
+        &self,
+        document: &Document,
+        position: Position,
+        line: &str
+    ) -> Option<String> {
+        let (word, _start, end) =
+            super::token::word_range_at_position(line, position.character as usize)?;
+        let after = line.chars().skip(end as usize).collect::<String>();
+        let after = after.trim_start();
+        if !after.starts_with('=') || after.starts_with("==") {
+            return None;
+        }
+
+        let listing = self.parse_document(document).ok()?;
+        let call = super::token::flatten_listing(listing.iter()).find(|t| {
+            t.is_call_macro_or_build_struct() && super::token::span_line(*t) == position.line
+        })?;
+        let name = call.macro_call_name().to_string();
+        let params = self.macro_parameters(document, &name)?;
+        let (idx, (param, default)) = params
+            .iter()
+            .enumerate()
+            .find(|(_, (param, _))| param.trim_start_matches("r#") == word)?;
+
+        let default = match default {
+            Some(default) => format!("defaults to `{default}`"),
+            None => "no default value".to_owned()
+        };
+        Some(format!(
+            "**{param}** — parameter {} of macro `{name}`, {default}",
+            idx + 1
+        ))
+    }
+
     /// Hover preview for a user-defined `FUNCTION` call under the cursor:
     /// its evaluated return value, or a graceful explanation of why it
     /// couldn't be evaluated (e.g. a parameter that isn't yet resolvable).
@@ -506,6 +577,65 @@ fn macro_param_display<P: cpclib_tokens::MacroParamElement>(param: &P) -> String
     }
 }
 
+/// Text-only fallback of `AssemblyAnalyzer::macro_parameters`: the
+/// parameters of the first `MACRO name ...` line of `text`. A default value
+/// containing a comma is only kept whole when it is parenthesized or quoted.
+fn macro_parameters_by_text(text: &str, name: &str) -> Option<Vec<(String, Option<String>)>> {
+    text.lines().find_map(|line| {
+        let line = super::format::strip_asm_comment(line).trim();
+        let (keyword, rest) = line.split_once(|c: char| c.is_whitespace())?;
+        if !keyword.eq_ignore_ascii_case("MACRO") {
+            return None;
+        }
+        let rest = rest.trim_start();
+        let name_len = rest
+            .find(|c: char| c.is_whitespace() || c == '(' || c == ',')
+            .unwrap_or(rest.len());
+        if !rest[..name_len].eq_ignore_ascii_case(name) {
+            return None;
+        }
+        let params = rest[name_len..].trim().trim_start_matches(',').trim();
+        let params = match params.strip_prefix('(') {
+            Some(params) => params.strip_suffix(')').unwrap_or(params),
+            None => params
+        };
+
+        let mut split = Vec::new();
+        let mut depth = 0i32;
+        let mut in_string = false;
+        let mut start = 0;
+        for (i, c) in params.char_indices() {
+            match c {
+                '"' => in_string = !in_string,
+                '(' | '[' if !in_string => depth += 1,
+                ')' | ']' if !in_string => depth -= 1,
+                ',' if !in_string && depth == 0 => {
+                    split.push(&params[start..i]);
+                    start = i + 1;
+                },
+                _ => {}
+            }
+        }
+        split.push(&params[start..]);
+
+        Some(
+            split
+                .into_iter()
+                .map(str::trim)
+                .filter(|param| !param.is_empty())
+                .map(|param| {
+                    match param.split_once('=') {
+                        Some((param, default)) => {
+                            (param.trim().to_owned(), Some(default.trim().to_owned()))
+                        },
+                        None => (param.to_owned(), None)
+                    }
+                })
+                .collect()
+        )
+    })
+}
+
 /// If `col` sits on a call to a user-defined `FUNCTION` (an identifier
 /// immediately, or after whitespace, followed by `(...)`), return its name
 /// and the raw `name(args)` call text.
@@ -604,6 +734,38 @@ mod tests {
         assert!(!hover.contains("Unknown symbol"), "{hover}");
     }
 
+    #[test]
+    fn named_macro_argument_hover_shows_the_parameter_and_its_default() {
+        let text = "MACRO blit x, height=8\n  db {x}, {height}\nENDM\n\nblit 1, height=3\n";
+        let doc = Document::new(Url::parse("file:///t.asm").unwrap(), text.to_string(), 1);
+        // Cursor on "height" at the call site (line 4).
+        let hover = AssemblyAnalyzer::new()
+            .macro_named_argument_hover(
+                &doc,
+                Position {
+                    line: 4,
+                    character: 9
+                },
+                "blit 1, height=3"
+            )
+            .expect("expected a parameter hover");
+        assert!(hover.contains("parameter 2 of macro `blit`"), "{hover}");
+        assert!(hover.contains("defaults to `8`"), "{hover}");
+    }
+
+    #[test]
+    fn macro_parameters_are_read_from_the_text_when_the_call_does_not_parse() {
+        let text = "MACRO blit(x, y=(1+2)*3, s=\"a,b\")\nENDM\n";
+        assert_eq!(
+            macro_parameters_by_text(text, "BLIT"),
+            Some(vec![
+                ("x".to_owned(), None),
+                ("y".to_owned(), Some("(1+2)*3".to_owned())),
+                ("s".to_owned(), Some("\"a,b\"".to_owned()))
+            ])
+        );
+    }
+
     #[test]
     fn struct_call_hover_shows_the_expanded_fields() {
         let text = "STRUCT point\n  x DB 0\n  y DB 0\nENDSTRUCT\n\npoint 1, 2\n";
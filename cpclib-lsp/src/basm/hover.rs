@@ -55,13 +55,18 @@ impl AssemblyAnalyzer {
                     return Some(hover);
                 }
             }
-            else if let Some(content) =
-                super::includes::read_included_file(&filename, &document.uri)
+            else if directive != "INCIMG"
+                && let Some(content) = super::includes::read_included_file(&filename, &document.uri)
             {
                 return Some(make_hover(format_include_preview(&filename, &content)));
             }
         }
 
+        // Named macro argument (`height=3`) — the parameter it sets.
+        if let Some(md) = self.macro_named_argument_hover(document, position, &line) {
+            return Some(make_hover(md));
+        }
+
         // Macro/struct call — show the expanded content for these arguments.
         if let Some(md) = self.macro_or_struct_call_hover(document, position) {
             return Some(make_hover(md));
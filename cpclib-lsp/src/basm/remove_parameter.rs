@@ -3,7 +3,7 @@
 //! which parameter is unused lives in `cpclib_asm::unused_bindings` and is
 //! reused here, never reimplemented - this module is purely about finding
 //! every *call site* of that macro/function and computing the edits needed
-//! to remove one positional argument from each.
+//! to remove one argument (positional, or named for a macro) from each.
 //!
 //! This is a materially bigger problem than the earlier warning-only
 //! feature: basm strictly enforces call arity (`MacroWithArgs::build`,
@@ -42,9 +42,9 @@ pub(crate) enum RemoveParameterKind {
 }
 
 /// What to remove: `owner_name`'s declared parameter at `param_index`
-/// (0-based). `param_name` is carried for messages/titles only - the index
-/// is what's actually load-bearing, since basm's call arguments are always
-/// positional (never named).
+/// (0-based). `param_name` is carried for messages/titles and for the
+/// macro calls that pass it by name (`name=value`) - the index is what's
+/// load-bearing for every positional argument.
 #[derive(Debug, Clone)]
 pub(crate) struct RemoveParameterTarget {
     pub kind: RemoveParameterKind,
@@ -158,20 +158,24 @@ fn macro_call_site_results(
         let arity = args.len();
         let (line, _column) = call.span().relative_line_and_column();
 
-        if target.param_index >= arity {
-            scan.blockers.push(RemovalBlocker::here(
-                line,
-                format!(
-                    "this call to '{}' passes only {arity} argument(s), but the parameter \
-                     being removed is at position {}",
-                    target.owner_name,
-                    target.param_index + 1
-                )
-            ));
+        // The positional arguments always come before the named ones, so a
+        // named argument at `param_index` means this parameter isn't passed
+        // positionally. A call that doesn't pass it at all relies on its
+        // default value: there's nothing to remove there.
+        let param_name = target.param_name.trim_start_matches("r#");
+        let Some(index) = args
+            .iter()
+            .position(|arg| named_argument_name(arg) == Some(param_name))
+            .or_else(|| {
+                args.get(target.param_index)
+                    .filter(|arg| named_argument_name(arg).is_none())
+                    .map(|_| target.param_index)
+            })
+        else {
             continue;
-        }
+        };
 
-        let Some(arg_span) = macro_arg_span(&args[target.param_index])
+        let Some(arg_span) = macro_arg_span(&args[index])
         else {
             scan.blockers.push(RemovalBlocker::here(
                 line,
@@ -184,7 +188,7 @@ fn macro_call_site_results(
             continue;
         };
 
-        match expand_to_removable_argument_span(text, arg_span, target.param_index, arity) {
+        match expand_to_removable_argument_span(text, arg_span, index, arity) {
             Some(removable) => scan.edits.push(byte_range_to_text_edit(text, removable)),
             None => {
                 scan.blockers.push(RemovalBlocker::here(
@@ -192,7 +196,7 @@ fn macro_call_site_results(
                     format!(
                         "couldn't locate the expected comma around argument {} of this call \
                          to '{}'",
-                        target.param_index + 1,
+                        index + 1,
                         target.owner_name
                     )
                 ));
@@ -201,6 +205,22 @@ fn macro_call_site_results(
     }
 }
 
+/// The parameter name of a named macro argument (`name=value`, but not the
+/// `name == value` comparison), or `None` for a positional one.
+fn named_argument_name(param: &LocatedMacroParam) -> Option<&str> {
+    let LocatedMacroParam::RawArgument(span) = param
+    else {
+        return None;
+    };
+    let text: &str = span.as_ref();
+    let (name, value) = text.split_once('=')?;
+    let name = name.trim();
+    (!value.starts_with('=')
+        && !name.is_empty()
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_'))
+    .then_some(name)
+}
+
 /// The target argument's raw `[start, end)` absolute byte range, or `None`
 /// for a `List`/`Empty`-shaped argument - `LocatedMacroParam::span()`
 /// panics for both (`List(_) => todo!()`, `Empty => panic!()`, verified
@@ -268,16 +288,26 @@ fn record_definition_header_edit(
     line: usize,
     scan: &mut FileScan
 ) {
-    let all_params: Vec<&str> = match target.kind {
-        RemoveParameterKind::Macro => token.macro_definition_arguments().to_vec(),
-        RemoveParameterKind::Function => token.function_definition_params().to_vec()
+    let (all_params, all_defaults): (Vec<&str>, Vec<Option<&str>>) = match target.kind {
+        RemoveParameterKind::Macro => {
+            (
+                token.macro_definition_arguments().to_vec(),
+                token.macro_definition_defaults().to_vec()
+            )
+        },
+        RemoveParameterKind::Function => (token.function_definition_params().to_vec(), Vec::new())
     };
 
     let header_start = token.span().offset_from_start();
     let header_text: &str = token.span().as_ref();
     let header_line = header_text.lines().next().unwrap_or("");
 
-    match definition_header_removable_span(header_line, &all_params, target.param_index) {
+    match definition_header_removable_span(
+        header_line,
+        &all_params,
+        &all_defaults,
+        target.param_index
+    ) {
         Some(local_range) => {
             let absolute = (header_start + local_range.start)..(header_start + local_range.end);
             scan.edits.push(byte_range_to_text_edit(text, absolute));
@@ -694,19 +724,24 @@ fn expand_to_removable_argument_span(
 /// parameters, remove index i." Parameter names are matched with any
 /// enclosing `(`/`)` trimmed first (a real, already-worked-around grammar
 /// quirk for a single-parameter MACRO/FUNCTION - see
-/// `token::macro_scoped_symbol_at`).
+/// `token::macro_scoped_symbol_at`). A MACRO parameter's default value
+/// (`b=2`, from `all_defaults`) is removed along with its name.
 fn definition_header_removable_span(
     header_line: &str,
     all_param_names: &[&str],
+    all_defaults: &[Option<&str>],
     param_index: usize
 ) -> Option<Range<usize>> {
     let mut search_from = 0usize;
     let mut spans = Vec::with_capacity(all_param_names.len());
-    for raw_name in all_param_names {
+    for (index, raw_name) in all_param_names.iter().enumerate() {
         let name = raw_name.trim_start_matches('(').trim_end_matches(')');
         let rel = header_line.get(search_from..)?.find(name)?;
         let start = search_from + rel;
-        let end = start + name.len();
+        let mut end = start + name.len();
+        if let Some(Some(default)) = all_defaults.get(index) {
+            end += header_line.get(end..)?.find(default)? + default.len();
+        }
         spans.push(start..end);
         search_from = end;
     }
@@ -1022,6 +1057,20 @@ mod tests {
         assert!(result.contains("foo(2)"), "{result}");
     }
 
+    #[test]
+    fn named_macro_argument_is_removed_wherever_it_is_given() {
+        let code = "MACRO foo, a, b=2, c=3\nENDM\nfoo(1, c=4, b=5)\nfoo(1, 6)\nfoo(1, c=7)\n";
+        let (d, listing) = parse(code);
+        let target = macro_target("foo", 1, "b");
+        let scan = scan_listing_for_parameter_removal(&listing, &d.text(), &target);
+        assert!(scan.blockers.is_empty(), "{:?}", scan.blockers);
+        let result = apply_edits(&d, &scan.edits);
+        assert!(result.contains("MACRO foo, a, c=3"), "{result}");
+        assert!(result.contains("foo(1, c=4)"), "{result}");
+        assert!(result.contains("foo(1)"), "{result}");
+        assert!(result.contains("foo(1, c=7)"), "{result}");
+    }
+
     #[test]
     fn a_list_shaped_call_argument_is_a_blocker_not_a_panic() {
         let code = "MACRO foo, a, b\nENDM\nfoo([1, 2, 3], 5)\n";
@@ -1142,7 +1191,7 @@ mod tests {
             (1, "MACRO foo, a, c"),
             (2, "MACRO foo, a, b")
         ] {
-            let range = definition_header_removable_span(header, &params, index).unwrap();
+            let range = definition_header_removable_span(header, &params, &[], index).unwrap();
             let mut s = header.to_string();
             s.replace_range(range, "");
             assert_eq!(s, expected);
@@ -1157,7 +1206,7 @@ mod tests {
         // this crate (`token::macro_scoped_symbol_at`).
         let header = "MACRO foo, x";
         let params = ["(x)"];
-        let range = definition_header_removable_span(header, &params, 0).unwrap();
+        let range = definition_header_removable_span(header, &params, &[], 0).unwrap();
         let mut s = header.to_string();
         s.replace_range(range, "");
         assert_eq!(s, "MACRO foo");
@@ -578,6 +578,7 @@ const BLOCK_KEYWORD_PAIRS: &[(&[&str], &[&str])] = &[
     (&["STRUCT"], &["ENDS"]),
     (&["SWITCH"], &["ENDSWITCH"]),
     (&["CONFINED"], &["ENDC", "ENDCONFINED"]),
+    (&["STABILIZE"], &["ENDSTABILIZE"]),
     (&["ENUM"], &["ENDENUM"]),
     (&["WHILE"], &["ENDW", "WEND"]),
     (&["ASMCONTROLENV"], &["ENDA", "ENDASMCONTROLENV"])
@@ -11,6 +11,7 @@ use cpclib_common::camino::{Utf8Path, Utf8PathBuf};
 use cpclib_common::itertools::Itertools;
 use cpclib_common::parse_value;
 use cpclib_csl::ResetType;
+use cpclib_z80emu::cpc::keyboard::KeyPosition;
 use delegate;
 use enigo::{Enigo, Key, Keyboard, Settings};
 #[cfg(windows)]
@@ -22,6 +23,7 @@ use crate::ace_config::{AceConfig, AceConfigFlag};
 use crate::delegated::{DelegatedRunner, clear_base_cache_folder};
 use crate::embedded::EmbeddedRoms;
 use crate::event::EventObserver;
+use crate::headless::{HeadlessEmulator, SharedHeadlessEmulator};
 use crate::runner::Runner;
 use crate::runner::emulator::Emulator;
 use crate::runner::runner::RunnerWithClap;
@@ -96,6 +98,11 @@ impl Crtc {
             Crtc::Four => cpclib_csl::CrtcModel::Type4
         }
     }
+
+    /// Convert to the CRTC of the headless emulator. Unsupported types fall back to the type 0
+    pub fn to_headless_model(self) -> cpclib_z80emu::cpc::crtc::CrtcType {
+        cpclib_z80emu::cpc::crtc::CrtcType::from_number(self as u8)
+    }
 }
 
 /// Convert memory size (in KB) to CSL MemoryExpansion
@@ -116,7 +123,9 @@ type EmuScreenShot = ImageBuffer<Rgba<u8>, Vec<u8>>;
 pub enum EmuWindow {
     #[cfg(feature = "screenshot")]
     Xcap(xcap::Window),
-    Xvfb(usize, Option<wmctrl::Window>)
+    Xvfb(usize, Option<wmctrl::Window>),
+    /// No window at all: the emulation is done in-process
+    Headless(SharedHeadlessEmulator)
 }
 
 impl EmuWindow {
@@ -146,17 +155,25 @@ impl EmuWindow {
                     }
                 }
             },
+            EmuWindow::Headless(emu) => emu.lock().unwrap().capture_image()
         }
     }
 }
 
 enum WindowEventsManager {
-    Enigo(Enigo)
+    Enigo(Box<Enigo>),
+    Headless(SharedHeadlessEmulator)
 }
 
 impl From<Enigo> for WindowEventsManager {
     fn from(value: Enigo) -> Self {
-        Self::Enigo(value)
+        Self::Enigo(Box::new(value))
+    }
+}
+
+impl From<SharedHeadlessEmulator> for WindowEventsManager {
+    fn from(value: SharedHeadlessEmulator) -> Self {
+        Self::Headless(value)
     }
 }
 
@@ -305,7 +322,9 @@ impl WindowEventsManager {
         match self {
             Self::Enigo(_enigo) => {
                 self.enigo_press_with_extra(Key::Alt, key);
-            }
+            },
+            // there is no ALT key on a CPC
+            Self::Headless(_) => {}
         }
     }
 
@@ -315,7 +334,8 @@ impl WindowEventsManager {
         match self {
             Self::Enigo(_enigo) => {
                 self.enigo_press_with_extra(Key::Control, c);
-            }
+            },
+            Self::Headless(emu) => Self::headless_type_key(emu, c, Some(KeyPosition::CONTROL))
         }
     }
 
@@ -325,7 +345,8 @@ impl WindowEventsManager {
         match self {
             Self::Enigo(_enigo) => {
                 self.enigo_press_with_extra(Key::Shift, c);
-            }
+            },
+            Self::Headless(emu) => Self::headless_type_key(emu, c, Some(KeyPosition::SHIFT))
         }
     }
 
@@ -349,14 +370,21 @@ impl WindowEventsManager {
                 Self::wait_a_bit();
                 enigo.key(extra, enigo::Direction::Release).unwrap();
                 Self::wait_a_bit();
-            }
+            },
+            Self::Headless(_) => unreachable!()
+        }
+    }
+
+    fn headless_type_key(emu: &SharedHeadlessEmulator, key: HostKey, extra: Option<KeyPosition>) {
+        if let Err(e) = emu.lock().unwrap().type_key(key, extra) {
+            eprintln!("{e}");
         }
     }
 
     pub fn type_text<T: Into<HostKeys>>(&mut self, txt: T) {
         let txt = txt.into();
         match self {
-            Self::Enigo(_enigo) => {
+            Self::Enigo(_) | Self::Headless(_) => {
                 // asking enigo to write the full char does not work at all
                 for k in txt.iter() {
                     self.type_key(*k)
@@ -367,7 +395,7 @@ impl WindowEventsManager {
 
     pub fn type_char(&mut self, c: char) {
         match self {
-            Self::Enigo(_enigo) => self.type_key(c)
+            Self::Enigo(_) | Self::Headless(_) => self.type_key(c)
         }
     }
 
@@ -382,7 +410,8 @@ impl WindowEventsManager {
                 else {
                     self.enigo_click_key(key);
                 }
-            }
+            },
+            Self::Headless(emu) => Self::headless_type_key(emu, k, None)
         }
     }
 
@@ -400,7 +429,8 @@ impl WindowEventsManager {
                 Self::wait_a_bit();
                 enigo.key(key, enigo::Direction::Release).unwrap();
                 Self::wait_a_bit();
-            }
+            },
+            Self::Headless(_) => unreachable!()
         }
     }
 
@@ -1840,7 +1870,7 @@ pub struct EmuCli {
         long,
         default_value = "ace",
         alias = "emu",
-        help = "Which emulator to use [possible values: ace, winape, cpcec, amspirit, sugarbox, cpcemupower, cpcemu, caprice, cadence, emulator1984, rvm]"
+        help = "Which emulator to use [possible values: ace, winape, cpcec, amspirit, sugarbox, cpcemupower, cpcemu, caprice, cadence, emulator1984, rvm, headless]"
     )]
     emulator: Emu,
 
@@ -1879,6 +1909,37 @@ pub struct EmuCli {
     #[arg(long, action=ArgAction::Append, help="List the ROMS to activate")]
     enable_rom: Vec<AmstradRom>,
 
+    #[arg(
+        long,
+        value_name = "ROM",
+        help = "Firmware ROM file (only for headless)"
+    )]
+    lower_rom: Option<Utf8PathBuf>,
+
+    #[arg(long, value_name = "SLOT=ROM", action = ArgAction::Append, value_parser = parse_upper_rom, help = "Upper ROM file to plug in the given slot (only for headless)")]
+    upper_rom: Vec<(u8, Utf8PathBuf)>,
+
+    #[arg(
+        long,
+        default_value = "50",
+        help = "Number of frames to run after the interaction (only for headless)"
+    )]
+    frames: usize,
+
+    #[arg(
+        long,
+        value_name = "PNG",
+        help = "Save a screenshot once the frames have been run (only for headless)"
+    )]
+    screenshot: Option<Utf8PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "SNA",
+        help = "Save a snapshot once the frames have been run (only for headless)"
+    )]
+    save_snapshot: Option<Utf8PathBuf>,
+
     #[command(subcommand)]
     command: Commands
 }
@@ -1897,7 +1958,20 @@ pub enum Emu {
     #[value(alias = "1984")]
     Emulator1984,
     #[value(alias = "retrovm")]
-    Rvm
+    Rvm,
+    /// In-process emulation without any window
+    #[value(alias = "none")]
+    Headless
+}
+
+pub fn parse_upper_rom(arg: &str) -> Result<(u8, Utf8PathBuf), String> {
+    let (slot, fname) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("{arg} does not follow the SLOT=ROM format"))?;
+    let slot = slot
+        .parse::<u8>()
+        .map_err(|e| format!("{slot} is not a valid slot. {e}"))?;
+    Ok((slot, fname.into()))
 }
 
 use clap::Args;
@@ -2025,6 +2099,10 @@ pub fn handle_arguments<E: EventObserver + Clone + 'static>(
         clear_base_cache_folder().map_err(|e| format!("Unable to clear the cache folder. {e}"))?;
     }
 
+    if cli.emulator == Emu::Headless {
+        return handle_headless_arguments(cli, o);
+    }
+
     let builder = EmulatorConf::builder()
         .transparent(cli.transparent)
         .maybe_drive_a(cli.drive_a.clone().map(|a| a.into()))
@@ -2050,7 +2128,8 @@ pub fn handle_arguments<E: EventObserver + Clone + 'static>(
         Emu::Cpcemu => Emulator::CpcEmu(Default::default()),
         Emu::Cadence => Emulator::Cadence(Default::default()),
         Emu::Emulator1984 => Emulator::Emulator1984(Default::default()),
-        Emu::Rvm => Emulator::RetroVm(Default::default())
+        Emu::Rvm => Emulator::RetroVm(Default::default()),
+        Emu::Headless => unreachable!()
     };
 
     {
@@ -2400,6 +2479,58 @@ pub fn handle_arguments<E: EventObserver + Clone + 'static>(
     res
 }
 
+/// Run the scenario with the in-process emulator. Nothing is installed nor launched
+fn handle_headless_arguments<E: EventObserver>(cli: EmuCli, o: &E) -> Result<(), String> {
+    if cli.drive_a.is_some() || cli.drive_b.is_some() || cli.albireo.is_some() {
+        o.emit_stderr("Discs are not handled by the headless emulator and are ignored\n");
+    }
+
+    let mut emu = match &cli.snapshot {
+        Some(sna) => HeadlessEmulator::from_snapshot_file(sna, cli.crtc)?,
+        None => HeadlessEmulator::new(cli.crtc.unwrap_or_default())
+    };
+    if let Some(rom) = &cli.lower_rom {
+        emu.load_rom(None, rom)?;
+    }
+    for (slot, rom) in &cli.upper_rom {
+        emu.load_rom(Some(*slot), rom)?;
+    }
+    if cli.snapshot.is_none() {
+        if cli.lower_rom.is_none() {
+            return Err(
+                "The headless emulator needs a snapshot or a firmware ROM to start".to_string()
+            );
+        }
+        emu.cpc_mut().reset();
+    }
+
+    let emu = emu.shared();
+    let mut events = WindowEventsManager::from(emu.clone());
+
+    match cli.command {
+        #[cfg(feature = "screenshot")]
+        Commands::Orgams(_) => {
+            return Err("Orgams cannot be driven by the headless emulator".to_string());
+        },
+        Commands::Run { text } => {
+            if let Some(text) = text {
+                events.type_text(text.replace(r"\n", "\n").as_str());
+            }
+        },
+    }
+
+    let mut emu = emu.lock().unwrap();
+    emu.run_frames(cli.frames);
+    if let Some(fname) = &cli.screenshot {
+        emu.save_screenshot(fname)?;
+    }
+    if let Some(fname) = &cli.save_snapshot {
+        emu.save_snapshot(fname)?;
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use cpclib_common::camino::Utf8PathBuf;
@@ -0,0 +1,385 @@
+//! Emulator that runs without any window thanks to `cpclib-z80emu`.
+//! It is handy on CI machines where the external emulators cannot be launched.
+
+use std::sync::{Arc, Mutex};
+
+use cpclib_common::camino::Utf8Path;
+use cpclib_csl::{
+    CpcModel, CrtcModel, CslBackend, KeyElement, ResetType, RomType, SnapshotVersion, SpecialKey
+};
+use cpclib_image::ga::Ink;
+use cpclib_sna::Snapshot;
+use cpclib_z80emu::Cpc;
+use cpclib_z80emu::cpc::keyboard::KeyPosition;
+use image::{Rgba, RgbaImage};
+
+use crate::emucontrol::{Crtc, HostKey};
+
+/// Headless emulator shared between the window and the events manager
+pub type SharedHeadlessEmulator = Arc<Mutex<HeadlessEmulator>>;
+
+#[derive(Debug, Default)]
+pub struct HeadlessEmulator {
+    cpc: Cpc
+}
+
+impl From<Cpc> for HeadlessEmulator {
+    fn from(cpc: Cpc) -> Self {
+        Self { cpc }
+    }
+}
+
+#[allow(missing_docs)]
+impl HeadlessEmulator {
+    pub fn new(crtc: Crtc) -> Self {
+        Cpc::new(crtc.to_headless_model()).into()
+    }
+
+    /// Build the emulator in the state of the snapshot. The CRTC of the snapshot is used when not provided
+    pub fn from_snapshot_file<P: AsRef<Utf8Path>>(
+        fname: P,
+        crtc: Option<Crtc>
+    ) -> Result<Self, String> {
+        let fname = fname.as_ref();
+        let sna = Snapshot::load(fname)
+            .map_err(|e| format!("Unable to load the snapshot {fname}. {e}"))?;
+        let mut cpc = Cpc::from_snapshot(&sna);
+        if let Some(crtc) = crtc {
+            cpc.crtc_mut().set_kind(crtc.to_headless_model());
+        }
+        Ok(cpc.into())
+    }
+
+    pub fn shared(self) -> SharedHeadlessEmulator {
+        Arc::new(Mutex::new(self))
+    }
+
+    pub fn cpc(&self) -> &Cpc {
+        &self.cpc
+    }
+
+    pub fn cpc_mut(&mut self) -> &mut Cpc {
+        &mut self.cpc
+    }
+
+    /// Plug a ROM file. `None` is the firmware, `Some(slot)` an upper ROM
+    pub fn load_rom<P: AsRef<Utf8Path>>(
+        &mut self,
+        slot: Option<u8>,
+        fname: P
+    ) -> Result<(), String> {
+        let fname = fname.as_ref();
+        let content =
+            fs_err::read(fname).map_err(|e| format!("Unable to load the ROM {fname}. {e}"))?;
+        match slot {
+            Some(slot) => self.cpc.set_upper_rom(slot, &content),
+            None => self.cpc.set_lower_rom(&content)
+        }
+        Ok(())
+    }
+
+    pub fn run_frames(&mut self, count: usize) {
+        self.cpc.run_frames(count);
+    }
+
+    /// Press and release the key as a host keyboard would do
+    pub fn type_key(&mut self, key: HostKey, extra: Option<KeyPosition>) -> Result<(), String> {
+        let (position, shift) = match key {
+            HostKey::Return => (KeyPosition::RETURN, false),
+            HostKey::Ascii(c) => {
+                KeyPosition::from_char(c)
+                    .ok_or_else(|| format!("Unable to type {c:?} on a CPC keyboard"))?
+            },
+            HostKey::F1 => (KeyPosition::function(1).unwrap(), false),
+            HostKey::F2 => (KeyPosition::function(2).unwrap(), false),
+            HostKey::F3 => (KeyPosition::function(3).unwrap(), false),
+            HostKey::F4 => (KeyPosition::function(4).unwrap(), false),
+            HostKey::F5 => (KeyPosition::function(5).unwrap(), false),
+            HostKey::F6 => (KeyPosition::function(6).unwrap(), false),
+            HostKey::F7 => (KeyPosition::function(7).unwrap(), false),
+            HostKey::F8 => (KeyPosition::function(8).unwrap(), false),
+            HostKey::F9 => (KeyPosition::function(9).unwrap(), false),
+            HostKey::F10 | HostKey::F11 | HostKey::F12 => {
+                return Err(format!("{key:?} does not exist on a CPC keyboard"));
+            }
+        };
+
+        match extra {
+            Some(extra) => self.cpc.type_keys(&[extra, position], shift),
+            None => self.cpc.type_keys(&[position], shift)
+        }
+        Ok(())
+    }
+
+    pub fn type_text(&mut self, text: &str) -> Result<(), String> {
+        self.cpc.type_text(text)
+    }
+
+    /// Picture of the monitor. Each line is doubled to respect the aspect ratio of the CPC
+    pub fn capture_image(&self) -> RgbaImage {
+        let screen = self.cpc.screen();
+        let colours = (0..32)
+            .map(|hardware| {
+                let rgb = Ink::from_hardware_color_number(hardware).color();
+                Rgba([rgb[0], rgb[1], rgb[2], 255])
+            })
+            .collect::<Vec<_>>();
+
+        RgbaImage::from_fn(screen.width() as u32, 2 * screen.height() as u32, |x, y| {
+            colours[screen.pixel(x as usize, y as usize / 2) as usize]
+        })
+    }
+
+    pub fn save_screenshot<P: AsRef<Utf8Path>>(&self, fname: P) -> Result<(), String> {
+        let fname = fname.as_ref();
+        self.capture_image()
+            .save(fname)
+            .map_err(|e| format!("Unable to save the screenshot {fname}. {e}"))
+    }
+
+    pub fn save_snapshot<P: AsRef<Utf8Path>>(&self, fname: P) -> Result<(), String> {
+        self.save_snapshot_with_version(fname, cpclib_sna::SnapshotVersion::V3)
+    }
+
+    pub fn save_snapshot_with_version<P: AsRef<Utf8Path>>(
+        &self,
+        fname: P,
+        version: cpclib_sna::SnapshotVersion
+    ) -> Result<(), String> {
+        let fname = fname.as_ref();
+        let mut sna = Snapshot::default();
+        self.cpc.update_snapshot(&mut sna);
+        sna.save(fname, version)
+            .map_err(|e| format!("Unable to save the snapshot {fname}. {e}"))
+    }
+
+    /// Keys of the matrix to press for a CSL key. SHIFT is added when the character needs it
+    fn csl_key_positions(key: &KeyElement) -> Result<Vec<KeyPosition>, String> {
+        let (position, shift) = match key {
+            KeyElement::Character(c) => {
+                KeyPosition::from_char(*c)
+                    .ok_or_else(|| format!("Unable to type {c:?} on a CPC keyboard"))?
+            },
+            KeyElement::Special(special) => {
+                match special {
+                    SpecialKey::Esc => (KeyPosition::ESC, false),
+                    SpecialKey::Tab => (KeyPosition::TAB, false),
+                    SpecialKey::CapsLock => (KeyPosition::CAPS_LOCK, false),
+                    SpecialKey::Shift => (KeyPosition::SHIFT, false),
+                    SpecialKey::Ctrl => (KeyPosition::CONTROL, false),
+                    SpecialKey::Copy => (KeyPosition::COPY, false),
+                    SpecialKey::Clr => (KeyPosition::CLR, false),
+                    SpecialKey::Del => (KeyPosition::DEL, false),
+                    SpecialKey::Return => (KeyPosition::RETURN, false),
+                    SpecialKey::Enter => (KeyPosition::ENTER, false),
+                    SpecialKey::ArrowLeft => (KeyPosition::CURSOR_LEFT, false),
+                    SpecialKey::ArrowRight => (KeyPosition::CURSOR_RIGHT, false),
+                    SpecialKey::ArrowUp => (KeyPosition::CURSOR_UP, false),
+                    SpecialKey::ArrowDown => (KeyPosition::CURSOR_DOWN, false),
+                    SpecialKey::F0 => (KeyPosition::function(0).unwrap(), false),
+                    SpecialKey::F1 => (KeyPosition::function(1).unwrap(), false),
+                    SpecialKey::F2 => (KeyPosition::function(2).unwrap(), false),
+                    SpecialKey::F3 => (KeyPosition::function(3).unwrap(), false),
+                    SpecialKey::F4 => (KeyPosition::function(4).unwrap(), false),
+                    SpecialKey::F5 => (KeyPosition::function(5).unwrap(), false),
+                    SpecialKey::F6 => (KeyPosition::function(6).unwrap(), false),
+                    SpecialKey::F7 => (KeyPosition::function(7).unwrap(), false),
+                    SpecialKey::F8 => (KeyPosition::function(8).unwrap(), false),
+                    SpecialKey::F9 => (KeyPosition::function(9).unwrap(), false),
+                    SpecialKey::LeftBrace => KeyPosition::from_char('{').unwrap(),
+                    SpecialKey::RightBrace => KeyPosition::from_char('}').unwrap(),
+                    SpecialKey::Backslash => KeyPosition::from_char('\\').unwrap(),
+                    SpecialKey::Quote => KeyPosition::from_char('\'').unwrap(),
+                    SpecialKey::NoDelayNextKey => return Ok(Vec::new())
+                }
+            },
+            KeyElement::Simultaneous(group) => {
+                return group
+                    .iter()
+                    .map(Self::csl_key_positions)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(|keys| keys.concat());
+            }
+        };
+
+        if shift {
+            Ok(vec![KeyPosition::SHIFT, position])
+        }
+        else {
+            Ok(vec![position])
+        }
+    }
+}
+
+/// Backend used to run CSL scripts without any external emulator
+impl CslBackend for HeadlessEmulator {
+    fn reset(&mut self, kind: ResetType) -> Result<(), String> {
+        self.cpc.reset();
+        if kind == ResetType::Hard {
+            self.cpc.z80_mut().memory_mut().ram_mut().fill(0);
+        }
+        Ok(())
+    }
+
+    fn load_snapshot(&mut self, fname: &Utf8Path) -> Result<(), String> {
+        let sna = Snapshot::load(fname)
+            .map_err(|e| format!("Unable to load the snapshot {fname}. {e}"))?;
+        self.cpc.load_snapshot(&sna);
+        Ok(())
+    }
+
+    fn save_snapshot(&mut self, fname: &Utf8Path, version: SnapshotVersion) -> Result<(), String> {
+        let version = match version {
+            SnapshotVersion::V1 => cpclib_sna::SnapshotVersion::V1,
+            SnapshotVersion::V2 => cpclib_sna::SnapshotVersion::V2,
+            SnapshotVersion::V3 => cpclib_sna::SnapshotVersion::V3
+        };
+        self.save_snapshot_with_version(fname, version)
+    }
+
+    fn save_screenshot(&mut self, fname: &Utf8Path) -> Result<(), String> {
+        HeadlessEmulator::save_screenshot(self, fname)
+    }
+
+    fn press_keys(&mut self, keys: &[KeyElement]) -> Result<(), String> {
+        for key in keys {
+            for position in Self::csl_key_positions(key)? {
+                self.cpc.keyboard_mut().press(position);
+            }
+        }
+        Ok(())
+    }
+
+    fn release_keys(&mut self, keys: &[KeyElement]) -> Result<(), String> {
+        for key in keys {
+            for position in Self::csl_key_positions(key)? {
+                self.cpc.keyboard_mut().release(position);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_keyboard(&mut self, lines: &[u8; 10]) -> Result<(), String> {
+        self.cpc.keyboard_mut().set_lines(*lines);
+        Ok(())
+    }
+
+    /// The CPC executes one nop per microsecond
+    fn wait(&mut self, microseconds: u64) -> Result<(), String> {
+        self.cpc.run_nops(microseconds as usize);
+        Ok(())
+    }
+
+    fn wait_vsync_off_on(&mut self) -> Result<(), String> {
+        self.cpc.run_until_vsync();
+        Ok(())
+    }
+
+    fn select_crtc(&mut self, model: CrtcModel) -> Result<(), String> {
+        let crtc = match model {
+            CrtcModel::Type0 => Crtc::Zero,
+            CrtcModel::Type1 | CrtcModel::Type1A | CrtcModel::Type1B => Crtc::One,
+            _ => {
+                return Err(format!(
+                    "CRTC {model} is not emulated. Only 0 and 1 are available"
+                ));
+            },
+        };
+        self.cpc.crtc_mut().set_kind(crtc.to_headless_model());
+        Ok(())
+    }
+
+    fn select_cpc_model(&mut self, model: CpcModel) -> Result<(), String> {
+        match model {
+            CpcModel::Cpc6128 => Ok(()),
+            _ => Err(format!("Only the CPC 6128 (2) is emulated, not {model}"))
+        }
+    }
+
+    fn configure_rom(&mut self, kind: &RomType, num: u8, fname: &Utf8Path) -> Result<(), String> {
+        match kind {
+            RomType::Lower => self.load_rom(None, fname),
+            RomType::Upper => self.load_rom(Some(num), fname),
+            _ => Err(format!("ROM type {kind} is not emulated"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cpclib_z80emu::HasValue;
+
+    use super::*;
+
+    #[test]
+    fn screenshot_has_the_border_colour() {
+        let mut cpc = Cpc::default();
+        // ld bc, 0x7F10 : out (c), c : ld c, 0x4C : out (c), c : jr $
+        cpc.z80_mut().memory_mut().load_bytes(
+            0x4000,
+            &[
+                0x01, 0x10, 0x7F, 0xED, 0x49, 0x0E, 0x4C, 0xED, 0x49, 0x18, 0xFE
+            ]
+        );
+        cpc.z80_mut().pc_mut().set(0x4000);
+
+        let mut emu = HeadlessEmulator::from(cpc);
+        emu.run_frames(2);
+        let image = emu.capture_image();
+        assert_eq!(image.width(), 768);
+        assert_eq!(image.height(), 544);
+        let red = Ink::from_hardware_color_number(0x0C).color();
+        assert_eq!(image.get_pixel(0, 0), &Rgba([red[0], red[1], red[2], 255]));
+    }
+
+    #[test]
+    fn csl_script_drives_the_emulator() {
+        let mut cpc = Cpc::default();
+        // ld bc, 0x7F10 : out (c), c : ld c, 0x4C : out (c), c : jr $
+        cpc.z80_mut().memory_mut().load_bytes(
+            0x4000,
+            &[
+                0x01, 0x10, 0x7F, 0xED, 0x49, 0x0E, 0x4C, 0xED, 0x49, 0x18, 0xFE
+            ]
+        );
+        cpc.z80_mut().pc_mut().set(0x4000);
+
+        let dir = camino_tempfile::tempdir().unwrap();
+        let script = cpclib_csl::parse_csl_with_rich_errors(
+            &format!(
+                "csl_version 1.2\n\
+                crtc_select 1\n\
+                key_output '{{\\(SHI)a}}'\n\
+                wait 40000\n\
+                screenshot_dir '{dir}'\n\
+                screenshot_name 'border'\n\
+                screenshot vsync\n\
+                snapshot_dir '{dir}'\n\
+                snapshot\n",
+                dir = dir.path()
+            ),
+            None
+        )
+        .unwrap();
+
+        let mut interpreter = cpclib_csl::CslInterpreter::new(HeadlessEmulator::from(cpc));
+        interpreter.run_script(&script).unwrap();
+        let emu = interpreter.into_backend();
+        assert!(!emu.cpc().keyboard().is_pressed(KeyPosition::SHIFT));
+        assert!(dir.path().join("border.png").exists());
+        assert!(dir.path().join("csl-0001.sna").exists());
+
+        let image = image::open(dir.path().join("border.png"))
+            .unwrap()
+            .to_rgba8();
+        let red = Ink::from_hardware_color_number(0x0C).color();
+        assert_eq!(image.get_pixel(0, 0), &Rgba([red[0], red[1], red[2], 255]));
+
+        let mut emu = emu;
+        assert!(
+            emu.select_crtc(CrtcModel::Type2)
+                .unwrap_err()
+                .contains("not emulated")
+        );
+    }
+}
@@ -7,6 +7,7 @@ pub mod child_registry;
 pub mod delegated;
 pub mod embedded;
 pub mod emucontrol;
+pub mod headless;
 pub mod runner;
 pub use child_registry::kill_all_children;
 pub use cpclib_common::event;
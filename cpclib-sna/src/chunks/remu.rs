@@ -3,6 +3,7 @@ use std::ops::Deref;
 
 use cpclib_common::riff::{RiffChunk, RiffCode, RiffLen};
 use delegate::delegate;
+use serde::{Deserialize, Serialize};
 use strum::{EnumString, IntoStaticStr};
 
 #[nutype::nutype(
@@ -12,7 +13,19 @@ use strum::{EnumString, IntoStaticStr};
 )]
 pub struct String127(String);
 
-#[derive(Debug, PartialEq, Eq, Clone, Hash, PartialOrd, Ord, IntoStaticStr, EnumString)]
+#[derive(
+    Debug,
+    PartialEq,
+    Eq,
+    Clone,
+    Hash,
+    PartialOrd,
+    Ord,
+    IntoStaticStr,
+    EnumString,
+    Serialize,
+    Deserialize,
+)]
 #[strum(serialize_all = "UPPERCASE", ascii_case_insensitive)]
 pub enum RemuBreakPointType {
     #[strum(serialize = "EXEC")]
@@ -26,7 +39,19 @@ pub enum RemuBreakPointType {
     Mem
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Hash, PartialOrd, Ord, IntoStaticStr, EnumString)]
+#[derive(
+    Debug,
+    PartialEq,
+    Eq,
+    Clone,
+    Hash,
+    PartialOrd,
+    Ord,
+    IntoStaticStr,
+    EnumString,
+    Serialize,
+    Deserialize,
+)]
 #[strum(serialize_all = "UPPERCASE", ascii_case_insensitive)]
 pub enum RemuBreakPointAccessMode {
     #[strum(serialize = "R")]
@@ -41,7 +66,19 @@ pub enum RemuBreakPointAccessMode {
     Write
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Hash, PartialOrd, Ord, IntoStaticStr, EnumString)]
+#[derive(
+    Debug,
+    PartialEq,
+    Eq,
+    Clone,
+    Hash,
+    PartialOrd,
+    Ord,
+    IntoStaticStr,
+    EnumString,
+    Serialize,
+    Deserialize,
+)]
 #[strum(serialize_all = "UPPERCASE", ascii_case_insensitive)]
 pub enum RemuBreakPointRunMode {
     #[strum(serialize = "STOP")]
@@ -2,11 +2,12 @@ use std::fmt;
 use std::str::FromStr;
 
 use cpclib_common::itertools::Itertools;
+use serde::{Deserialize, Serialize};
 
 use crate::error::*;
 
 /// Encode a flag of the snaphot
-#[derive(PartialEq, Eq, Debug, Copy, Clone, Hash)]
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Hash, Serialize, Deserialize)]
 #[allow(non_camel_case_types)]
 #[allow(missing_docs)]
 pub enum SnapshotFlag {
@@ -484,7 +485,7 @@ impl FromStr for SnapshotFlag {
 }
 
 /// Encode the type of the flag values
-#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+#[derive(PartialEq, Eq, Debug, Clone, Hash, Serialize, Deserialize)]
 pub enum FlagValue {
     /// The flag is a byte
     Byte(u8),
@@ -11,12 +11,15 @@ use cpclib_common::winnow::error::ContextError;
 #[cfg(feature = "cmdline")]
 use cpclib_common::winnow::stream::AsBStr;
 use fs_err::File;
+use serde::{Deserialize, Serialize};
 
 mod chunks;
 mod error;
 pub mod flags;
 mod memory;
 pub mod parse;
+#[cfg(feature = "screenshot")]
+mod screenshot;
 
 // Import clap for derive macro (used even with cmdline feature)
 #[cfg(feature = "cmdline")]
@@ -72,7 +75,7 @@ fn string_to_nb<S: AsRef<str>>(s: S) -> Result<u32, SnapshotError> {
 /// {'k',"saveIniFile",0,1,1,"Save <$1> init file"},
 pub const HEADER_SIZE: usize = 256;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum SnapshotVersion {
     /// Version 1 of Snapshsots
@@ -893,6 +896,18 @@ use cpclib_common::event::EventObserver;
 // TODO: use observers instead of printing on terminal !
 #[cfg(feature = "cmdline")]
 pub fn process<E: EventObserver>(matches: &ArgMatches, o: &E) -> Result<(), SnapshotError> {
+    if let Some(("screenshot", matches)) = matches.subcommand() {
+        let input = matches.get_one::<String>("INPUT").unwrap();
+        let output = matches.get_one::<String>("OUTPUT").unwrap();
+        let sna = Snapshot::load(input)
+            .map_err(|e| SnapshotError::AnyError(format!("Unable to load file {input}. {e}")))?;
+        return sna
+            .screenshot()
+            .as_image()
+            .save(output)
+            .map_err(|e| SnapshotError::AnyError(format!("Unable to save {output}. {e}")));
+    }
+
     // Display all tokens
 
     if matches.get_flag("flags") {
@@ -1098,7 +1113,24 @@ pub fn build_arg_parser() -> Command {
         built_info::BUILT_TIME_UTC
     );
 
-    SnapshotCli::command().before_help(desc_before)
+    SnapshotCli::command()
+        .before_help(desc_before)
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .subcommand(
+            Command::new("screenshot")
+                .about("Render the screen of a snapshot in a PNG file, according to the stored gate array and CRTC state")
+                .arg(
+                    clap::Arg::new("INPUT")
+                        .help("Snapshot to render")
+                        .required(true)
+                )
+                .arg(
+                    clap::Arg::new("OUTPUT")
+                        .help("PNG file to generate")
+                        .required(true)
+                )
+        )
 }
 
 #[cfg(test)]
@@ -1164,6 +1196,31 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "screenshot")]
+    #[test]
+    fn test_screenshot() {
+        use cpclib_image::ga::Ink;
+
+        use crate::SnapshotFlag;
+
+        let mut sna = Snapshot::default();
+        for (idx, value) in [(1, 32), (6, 2), (9, 7), (12, 0x30), (13, 0)] {
+            sna.set_value(SnapshotFlag::CRTC_REG(Some(idx)), value)
+                .unwrap();
+        }
+        sna.set_value(SnapshotFlag::GA_ROMCFG, 0x8D).unwrap(); // mode 1
+        sna.set_value(SnapshotFlag::GA_PAL(Some(0)), 0x54).unwrap(); // black
+        sna.set_value(SnapshotFlag::GA_PAL(Some(3)), 0x4B).unwrap(); // bright white
+        sna.set_byte(0xC000 + 0x800 + 2 * 32, 0b1000_1000);
+
+        let screen = sna.screenshot();
+        assert_eq!(screen.width(), 256);
+        assert_eq!(screen.height(), 16);
+        assert_eq!(*screen.get_ink(0, 9), Ink::BRIGHT_WHITE);
+        assert_eq!(*screen.get_ink(1, 9), Ink::BLACK);
+        assert_eq!(*screen.get_ink(0, 0), Ink::BLACK);
+    }
+
     #[test]
     fn test_memory() {
         assert!(SnapshotMemory::default().is_empty());
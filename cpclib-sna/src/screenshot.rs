@@ -0,0 +1,53 @@
+//! Rendering of the screen of a snapshot from the state of its gate array and CRTC.
+//!
+//! Only the displayed area is rendered (no border), line after line, as the CRTC reads it
+//! from the first 64kb of memory: overscan and 32kb screens are thus properly handled.
+//! Mid-frame changes (rasters, split screens...) are not.
+
+use cpclib_image::ga::{Ink, Palette, Pen};
+use cpclib_image::image::{ColorMatrix, Mode};
+use cpclib_image::screen::CrtcScreen;
+
+use crate::{Snapshot, SnapshotFlag};
+
+impl Snapshot {
+    fn byte_flag(&self, flag: SnapshotFlag) -> u8 {
+        self.get_value(&flag).as_u16().unwrap_or_default() as u8
+    }
+
+    /// Screen mode selected in the gate array
+    pub fn screen_mode(&self) -> Mode {
+        Mode::from(self.byte_flag(SnapshotFlag::GA_ROMCFG) & 3)
+    }
+
+    /// Inks of the 16 pens and of the border
+    pub fn palette(&self) -> Palette {
+        let mut palette = Palette::new();
+        for pen in 0..Pen::NB_PENS {
+            let colour = self.byte_flag(SnapshotFlag::GA_PAL(Some(pen as usize))) & 0x1F;
+            palette.set(pen, Ink::from_hardware_color_number(colour));
+        }
+        palette
+    }
+
+    /// Displayed area defined by the CRTC registers
+    pub fn crtc_screen(&self) -> CrtcScreen {
+        let registers = (0..18)
+            .map(|idx| self.byte_flag(SnapshotFlag::CRTC_REG(Some(idx))))
+            .collect::<Vec<_>>();
+        CrtcScreen::from_registers(&registers)
+    }
+
+    /// Picture displayed by the monitor
+    pub fn screenshot(&self) -> ColorMatrix {
+        let mut memory = self.memory_dump();
+        memory.resize(memory.len().max(0x10000), 0);
+
+        ColorMatrix::from_crtc_memory(
+            &memory,
+            &self.crtc_screen(),
+            self.screen_mode(),
+            &self.palette()
+        )
+    }
+}
@@ -2,6 +2,7 @@ use std::ops::Deref;
 
 use cpclib_common::smallvec::SmallVec;
 use memchr::memchr;
+use serde::{Deserialize, Serialize};
 
 /// Tokenize a macro body into MacroSegments.
 ///
@@ -102,7 +103,7 @@ pub fn tokenize_macro_body<'l, 'p>(
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum MacroSegment {
     Lit { start: usize, end: usize },
     Arg { index: usize },
@@ -111,7 +112,7 @@ pub enum MacroSegment {
     ArgCount
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash, Default)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
 pub struct TokenizedMacroContent {
     pub segments: Vec<MacroSegment>
 }
@@ -178,14 +178,6 @@ impl From<u16> for MemoryPhysicalAddress {
 
 impl MemoryPhysicalAddress {
     pub fn new(address: u16, mmr: u8) -> Self {
-        if mmr == 0xC1 {
-            return MemoryPhysicalAddress {
-                page: 1,
-                bank: (address / 0x4000) as u8,
-                address: address % 0x4000
-            };
-        }
-
         let possible_page = ((mmr >> 3) & 0b111) + 1;
         let possible_bank = mmr & 0b11;
         let standard_bank = match address {
@@ -202,7 +194,7 @@ impl MemoryPhysicalAddress {
                 (possible_page, possible_bank)
             }
             else {
-                (0, possible_bank)
+                (0, standard_bank)
             }
         }
         else {
@@ -239,6 +231,15 @@ impl MemoryPhysicalAddress {
         }
     }
 
+    /// Address of a bank chosen independently of the gate array configuration
+    pub fn new_in_bank(address: u16, page: u8, bank: u8) -> Self {
+        Self {
+            address,
+            bank,
+            page
+        }
+    }
+
     pub fn offset_in_bank(&self) -> u16 {
         self.address % 0x4000
     }
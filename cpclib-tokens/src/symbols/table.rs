@@ -321,6 +321,8 @@ pub struct SymbolsTable {
     /// When true labels are case-sensitive; when false they are normalised to uppercase.
     case_sensitive: bool,
     current_global_label: Symbol, //  Value of the current label to allow local labels
+    /// Last `@` label with the seed of its repeat. The local labels that follow it in the same repeat are attached to it
+    current_hidden_label: Option<(usize, Symbol)>,
     // Stack of namespaces
     namespace_stack: Vec<Symbol>,
 
@@ -347,6 +349,7 @@ impl Default for SymbolsTable {
             dummy: false,
             case_sensitive: true,
             current_global_label: "".into(),
+            current_hidden_label: None,
             assignable: Default::default(),
             seed_stack: Vec::new(),
             namespace_stack: Vec::new(),
@@ -367,6 +370,7 @@ impl Clone for SymbolsTable {
             dummy: self.dummy,
             case_sensitive: self.case_sensitive,
             current_global_label: self.current_global_label.clone(),
+            current_hidden_label: self.current_hidden_label.clone(),
             namespace_stack: self.namespace_stack.clone(),
             assignable: self.assignable.clone(),
             seed_stack: self.seed_stack.clone(),
@@ -550,12 +554,19 @@ impl SymbolsTable {
     {
         let label = Symbol::from(symbol);
 
-        if !label.value().starts_with('.') && !label.value().starts_with('@') {
+        if label.value().starts_with('@') {
+            if let Some(seed) = self.seed_stack.last().copied() {
+                let hidden = self.extend_local_and_patterns_for_symbol::<Symbol>(label)?;
+                self.current_hidden_label = Some((seed, hidden));
+            }
+        }
+        else if !label.value().starts_with('.') {
             if label.value().contains('.') {
                 return Err(SymbolError::WrongSymbol(label));
             }
             self.current_global_label =
                 self.extend_local_and_patterns_for_symbol::<Symbol>(label)?;
+            self.current_hidden_label = None;
         }
 
         Ok(())
@@ -622,8 +633,13 @@ impl SymbolsTable {
         }
 
         // Local symbols are expensed with their global symbol
+        // or with the `@` label that precedes them in the current repeat
         if symbol.starts_with('.') {
-            symbol = self.current_global_label.clone().value().to_owned() + &symbol;
+            let parent = match (&self.current_hidden_label, self.seed_stack.last()) {
+                (Some((hidden_seed, hidden)), Some(seed)) if hidden_seed == seed => hidden,
+                _ => &self.current_global_label
+            };
+            symbol = parent.value().to_owned() + &symbol;
         }
 
         // handle the hidden labels from repeats
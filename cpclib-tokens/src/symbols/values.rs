@@ -126,6 +126,8 @@ pub struct ValueMacro {
     name: SmolStr,
     // The name of its arguments
     params: Vec<SmolStr>,
+    // The default value of its arguments
+    defaults: Vec<Option<SmolStr>>,
     // The content
     code: String,
     segments: TokenizedMacroContent,
@@ -141,7 +143,7 @@ pub struct ValueMacro {
 impl ValueMacro {
     pub fn new(
         name: SmolStr,
-        params: &[&str],
+        params: &[(&str, Option<&str>)],
         code: String,
         tokenized_content: crate::macro_segment::TokenizedMacroContent,
         source: Option<SourceLocation>,
@@ -150,7 +152,8 @@ impl ValueMacro {
     ) -> Self {
         ValueMacro {
             name,
-            params: params.iter().map(|&s| SmolStr::from(s)).collect(),
+            params: params.iter().map(|(s, _)| SmolStr::from(*s)).collect(),
+            defaults: params.iter().map(|(_, d)| d.map(SmolStr::from)).collect(),
             code,
             segments: tokenized_content,
             source,
@@ -189,6 +192,12 @@ impl ValueMacro {
         self.params.len()
     }
 
+    /// Default value of the argument `idx` if any
+    #[inline]
+    pub fn default_value(&self, idx: usize) -> Option<&str> {
+        self.defaults.get(idx).and_then(|d| d.as_deref())
+    }
+
     #[inline]
     pub fn has_variadic(&self) -> bool {
         self.has_variadic
@@ -3,11 +3,12 @@ use std::fmt;
 use std::fmt::{Debug, Display};
 
 use paste;
+use serde::{Deserialize, Serialize};
 
 use crate::tokens::expression::*;
 use crate::tokens::registers::*;
 
-#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Serialize, Deserialize)]
 /// Encode the way mnemonics access to data
 #[allow(missing_docs)]
 pub enum DataAccess {
@@ -5,6 +5,7 @@ use std::ops::{Add, Deref, Sub};
 
 use cpclib_common::smol_str::SmolStr;
 use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
 
 use crate::ListingElement;
 use crate::tokens::Token;
@@ -13,7 +14,7 @@ use crate::tokens::Token;
 unsafe impl Sync for Expr {}
 
 /// Expression nodes.
-#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Serialize, Deserialize)]
 #[allow(missing_docs)]
 pub enum Expr {
     /// Only used for disassembled code
@@ -329,7 +330,7 @@ pub trait ExprElement: Sized {
     fn symbols(&self) -> std::collections::HashSet<String>;
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, Serialize, Deserialize)]
 /// Represents a prefix that provides information related to banks for a label
 pub enum LabelPrefix {
     /// We want the bank of the label
@@ -365,7 +366,7 @@ impl Display for LabelPrefix {
 /// set to 1 will be displayed as a 16 bits value.
 ///  fbin8g,fbin16g,fbin32g Force binary display with 8, 16 or 32 bits.
 ///  fintg Display value as integer.
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, Serialize, Deserialize)]
 pub enum ExprFormat {
     Hex(Option<u8>),
     Bin(Option<u8>),
@@ -417,7 +418,7 @@ impl ExprFormat {
 }
 
 /// Expression for a print expression
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum FormattedExpr {
     // A raw expression is represented as it is
     Raw(Expr),
@@ -448,7 +449,7 @@ impl From<Expr> for FormattedExpr {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
 pub enum UnaryOperation {
     Neg,
     Not,
@@ -466,7 +467,7 @@ impl Display for UnaryOperation {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
 pub enum UnaryTokenOperation {
     Duration,
     Opcode
@@ -482,7 +483,7 @@ impl Display for UnaryTokenOperation {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
 pub enum BinaryOperation {
     RightShift,
     LeftShift,
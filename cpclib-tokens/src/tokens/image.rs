@@ -0,0 +1,72 @@
+//! Output formats of the images converted while assembling by the INCIMG directive.
+use std::convert::Infallible;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// How INCIMG encodes the image.
+/// `E` is an expression while parsing, and its value once assembled
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Serialize, Deserialize)]
+pub enum ImageFormat<E> {
+    /// `SPRITE` produces a linear sprite
+    Sprite,
+    /// `MASKED_SPRITE, mask_ink, replacement_ink` produces a linear sprite followed by its mask.
+    /// The pixels of `mask_ink` are transparent and drawn with `replacement_ink` in the sprite
+    MaskedSprite { mask_ink: E, replacement_ink: E },
+    /// `TILES, width, height` produces the tiles of `width` bytes and `height` lines one after
+    /// the other, read from left to right and from top to bottom
+    Tiles { width: E, height: E },
+    /// `SCREEN` produces the 16kb of a standard screen
+    Screen,
+    /// `OVERSCAN` produces the 32kb of an overscan screen
+    Overscan
+}
+
+impl<E: fmt::Display> fmt::Display for ImageFormat<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sprite => write!(f, "SPRITE"),
+            Self::MaskedSprite {
+                mask_ink,
+                replacement_ink
+            } => write!(f, "MASKED_SPRITE, {mask_ink}, {replacement_ink}"),
+            Self::Tiles { width, height } => write!(f, "TILES, {width}, {height}"),
+            Self::Screen => write!(f, "SCREEN"),
+            Self::Overscan => write!(f, "OVERSCAN")
+        }
+    }
+}
+
+impl<E> ImageFormat<E> {
+    /// Build the same format with other expressions
+    pub fn map<F, R>(&self, mut f: F) -> ImageFormat<R>
+    where F: FnMut(&E) -> R {
+        self.try_map(|e| Ok::<R, Infallible>(f(e)))
+            .unwrap_or_else(|e| match e {})
+    }
+
+    /// Build the same format with other expressions (typically their values)
+    pub fn try_map<F, R, Err>(&self, mut f: F) -> Result<ImageFormat<R>, Err>
+    where F: FnMut(&E) -> Result<R, Err> {
+        Ok(match self {
+            Self::Sprite => ImageFormat::Sprite,
+            Self::MaskedSprite {
+                mask_ink,
+                replacement_ink
+            } => {
+                ImageFormat::MaskedSprite {
+                    mask_ink: f(mask_ink)?,
+                    replacement_ink: f(replacement_ink)?
+                }
+            },
+            Self::Tiles { width, height } => {
+                ImageFormat::Tiles {
+                    width: f(width)?,
+                    height: f(height)?
+                }
+            },
+            Self::Screen => ImageFormat::Screen,
+            Self::Overscan => ImageFormat::Overscan
+        })
+    }
+}
@@ -1,20 +1,22 @@
 use std::fmt;
 use std::fmt::Debug;
+use std::str::FromStr;
 
 use cpclib_common::itertools::Itertools;
 use cpclib_common::smol_str::SmolStr;
 use cpclib_sna::{
     RemuBreakPointAccessMode, RemuBreakPointRunMode, RemuBreakPointType, SnapshotVersion
 };
+use serde::{Deserialize, Serialize};
 
 use crate::macro_segment::TokenizedMacroContent;
 use crate::symbols::{SymbolsTable, SymbolsTableTrait, Value};
 use crate::tokens::data_access::*;
 use crate::tokens::expression::*;
 use crate::tokens::listing::ListingElement;
-use crate::{Listing, Register8};
+use crate::{ImageFormat, Listing, Register8, UnitTestStep};
 
-#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Serialize, Deserialize)]
 /// This structures encode the parameters of macros.
 /// The usual parameter is a string.
 /// However, it can be a list of parameters to allows nested structs
@@ -111,7 +113,7 @@ impl MacroParam {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
 #[allow(missing_docs)]
 pub enum Mnemonic {
     Adc,
@@ -363,7 +365,7 @@ is_mnemonic!(
 );
 
 /// Stable ticker serves to count nops with the assembler !
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[allow(missing_docs)]
 pub enum StableTickerAction<S: AsRef<str>> {
     /// Start of the ticker with its name that will contains its duration
@@ -371,7 +373,7 @@ pub enum StableTickerAction<S: AsRef<str>> {
     Stop(Option<S>)
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Copy, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Copy, Hash, Serialize, Deserialize)]
 #[allow(missing_docs)]
 pub enum CrunchType {
     LZ48,
@@ -397,7 +399,7 @@ pub enum CrunchType {
     BackwardBzBx2
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Copy, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Copy, Hash, Serialize, Deserialize)]
 #[allow(missing_docs)]
 pub enum DiscType {
     Dsk,
@@ -405,7 +407,7 @@ pub enum DiscType {
     Auto
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Copy, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Copy, Hash, Serialize, Deserialize)]
 #[allow(missing_docs)]
 pub enum SaveType {
     AmsdosBas,
@@ -416,7 +418,7 @@ pub enum SaveType {
 }
 
 /// Encode the kind of test done in if/elif/else cases
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[allow(missing_docs)]
 pub enum TestKind {
     // Test succeed if it is an expression that returns True
@@ -510,7 +512,7 @@ impl TestKindElement for TestKind {
 }
 
 /// List of transformations that can be applied to an imported binary file
-#[derive(Debug, Clone, PartialEq, Eq, Copy, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Copy, Hash, Serialize, Deserialize)]
 #[allow(missing_docs)]
 pub enum BinaryTransformation {
     // Raw include of the data
@@ -528,7 +530,7 @@ impl BinaryTransformation {
 }
 
 /// Define characters encoding
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum CharsetFormat {
     /// Reset the encoding knowledge
     Reset,
@@ -584,13 +586,13 @@ impl CharsetFormat {
                         Value::Expr(ExprResult::String(s)) | Value::String(s) => {
                             CharsetFormat::CharsList(s.chars().collect_vec(), expr2.clone())
                         },
-                        _ => {
-                            unimplemented!("There is a bug to handle here")
-                        }
+                        // a character code
+                        _ => CharsetFormat::Char(expr1.clone(), expr2.clone())
                     }
                 }
                 else {
-                    unimplemented!("There is a bug to handle here")
+                    // a character code
+                    CharsetFormat::Char(expr1.clone(), expr2.clone())
                 }
             },
             CharsetFormat::Interval(expr1, expr2, expr3) => {
@@ -611,7 +613,7 @@ impl ToSimpleToken for Token {
     }
 }
 
-#[derive(Debug, Clone, Hash, PartialEq)]
+#[derive(Debug, Clone, Hash, PartialEq, Serialize, Deserialize)]
 pub enum StandardAssemblerControlCommand {
     RestrictedAssemblingEnvironment { passes: Option<Expr>, lst: Listing },
     PrintAtParsingState(Vec<FormattedExpr>), // completely ignored during assembling
@@ -660,15 +662,52 @@ impl AssemblerControlCommand for StandardAssemblerControlCommand {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
 pub enum AssemblerFlavor {
     Basm,
     // mathematical expressions use []
-    Orgams
+    Orgams,
+    // rasm syntax and semantics on top of the basm ones
+    Rasm,
+    // WinAPE built-in assembler (Maxam syntax), macro arguments are not braced
+    Winape,
+    // sjasmplus syntax (DEVICE, PAGE, DISP/ENT, DEFINE), macro arguments are not braced
+    Sjasmplus
+}
+
+impl AssemblerFlavor {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Basm => "BASM",
+            Self::Orgams => "ORGAMS",
+            Self::Rasm => "RASM",
+            Self::Winape => "WINAPE",
+            Self::Sjasmplus => "SJASMPLUS"
+        }
+    }
+}
+
+impl FromStr for AssemblerFlavor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "BASM" => Ok(Self::Basm),
+            "ORGAMS" => Ok(Self::Orgams),
+            "RASM" => Ok(Self::Rasm),
+            "WINAPE" | "MAXAM" => Ok(Self::Winape),
+            "SJASMPLUS" => Ok(Self::Sjasmplus),
+            _ => {
+                Err(format!(
+                    "{s} is not a valid assembler flavor (BASM, ORGAMS, RASM, WINAPE or SJASMPLUS expected)"
+                ))
+            },
+        }
+    }
 }
 
 /// The embeded Listing can be of several kind (with the token or with decorated version of the token)
-#[derive(Debug, Clone, Hash, PartialEq)]
+#[derive(Debug, Clone, Hash, PartialEq, Serialize, Deserialize)]
 #[allow(missing_docs)]
 pub enum Token {
     Abyte(Expr, Vec<Expr>),
@@ -706,6 +745,7 @@ pub enum Token {
     BuildSna(Option<SnapshotVersion>),
     Charset(CharsetFormat),
     Comment(String),
+    Confined(Listing),
     CrunchedBinary(CrunchType, SmolStr),
     CrunchedSection(CrunchType, Listing),
     Defb(Vec<Expr>),
@@ -733,6 +773,8 @@ pub enum Token {
     Export(Vec<SmolStr>),
 
     Fail(Option<Vec<FormattedExpr>>),
+    /// Select the assembler flavor of the file. It is taken into account before parsing the file
+    Flavor(AssemblerFlavor),
     Field {
         label: SmolStr,
         expr: Expr
@@ -760,6 +802,13 @@ pub enum Token {
         off: bool,
         transformation: BinaryTransformation
     },
+    /// Image converted while assembling. The bytes are emitted in place and the size and inks
+    /// of the image are stored in local symbols
+    IncImage {
+        fname: Expr,
+        mode: Expr,
+        format: ImageFormat<Expr>
+    },
     // file may or may not be read during parse. If not, it is read on demand when assembling
     Include(Expr, Option<SmolStr>, bool),
     Iterate(SmolStr, either::Either<Vec<Expr>, Expr>, Listing),
@@ -772,6 +821,8 @@ pub enum Token {
     Macro {
         name: SmolStr,
         params: Vec<SmolStr>,
+        /// Default value of each parameter (`MACRO foo a, b=2`)
+        defaults: Vec<Option<SmolStr>>,
         content: String,
         flavor: AssemblerFlavor,
         tokenized_content: TokenizedMacroContent,
@@ -840,6 +891,7 @@ pub enum Token {
         size: Option<Expr>,
         save_type: Option<SaveType>,
         dsk_filename: Option<Expr>,
+        /// Side of the disc, or speed in bauds of the tape
         side: Option<Expr>
     },
     Section(SmolStr),
@@ -857,6 +909,8 @@ pub enum Token {
         cpclib_sna::flags::SnapshotFlag,
         cpclib_sna::flags::FlagValue
     ),
+    /// STABILIZE block: its conditional branches are padded to last the same duration
+    Stabilize(Listing),
     StableTicker(StableTickerAction<SmolStr>),
     StartingIndex {
         start: Option<Expr>,
@@ -867,6 +921,11 @@ pub enum Token {
     Switch(Expr, Vec<(Expr, Listing, bool)>, Option<Listing>),
 
     Undef(SmolStr),
+    /// TEST block: the routines are only executed by `basm --test`
+    UnitTest {
+        name: SmolStr,
+        steps: Vec<UnitTestStep<Expr>>
+    },
     WaitNops(Expr),
     /// Emit a warning message (like PRINT but not fatal)
     Warning(Option<Vec<FormattedExpr>>),
@@ -1124,6 +1183,13 @@ impl fmt::Display for Token {
             }
             Token::Comment( string)
                  => write!(f, " ; {}", string.replace('\n',"\n;")),
+            Token::Confined( code) => {
+                writeln!(f, "CONFINED")?;
+                for token in code.iter() {
+                    writeln!(f, "\t{token}")?;
+                }
+                write!(f, "\tENDCONFINED")
+            },
             Token::Defb( exprs)
                  => write!(f, "DB {}", expr_list_to_string(exprs)),
             Token::Defs( vals)
@@ -1170,6 +1236,7 @@ impl fmt::Display for Token {
                     write!(f, "FAIL")
                 }
             }
+            Token::Flavor(flavor) => write!(f, "FLAVOR {}", flavor.name()),
 
             Token::If(tests, default) => {
                 let get_code_string = |tokens: &[Token]| {
@@ -1283,6 +1350,10 @@ impl fmt::Display for Token {
 
                  }
 
+            Token::IncImage { fname, mode, format } => {
+                write!(f, "INCIMG {fname}, {mode}, {format}")
+            },
+
                  Token::Include( fname, Some(module), once)
                  => write!(f, "INCLUDE {}\"{}\" namespace {}", fname, module.as_str(), if *once {"ONCE "} else {""}),
 
@@ -1290,7 +1361,14 @@ impl fmt::Display for Token {
                  => write!(f, "INCLUDE {}\"{}\"", fname, if *once {"ONCE "} else {""}),
             Token::Label( string) => write!(f, "{string}"),
 
-            Token::Macro { name, params, content, flavor: _, tokenized_content: _, has_variadic } => {
+            Token::Macro { name, params, defaults, content, flavor: _, tokenized_content: _, has_variadic } => {
+                let params = params.iter()
+                    .enumerate()
+                    .map(|(i, p)| match defaults.get(i).and_then(Option::as_ref) {
+                        Some(d) => format!("{p}={d}"),
+                        None => p.to_string()
+                    })
+                    .collect::<Vec<_>>();
                 let params = if params.is_empty() && !has_variadic {
                     "(void)".to_owned()
                 } else if *has_variadic {
@@ -1374,6 +1452,14 @@ impl fmt::Display for Token {
                 write!(f, "SECTION {sec}")
             }
 
+            Token::Stabilize( code) => {
+                writeln!(f, "STABILIZE")?;
+                for token in code.iter() {
+                    writeln!(f, "\t{token}")?;
+                }
+                write!(f, "\tENDSTABILIZE")
+            },
+
             Token::StableTicker( ticker)
                 => {
                     match ticker {
@@ -1389,6 +1475,14 @@ impl fmt::Display for Token {
                     }
             },
 
+            Token::UnitTest { name, steps } => {
+                writeln!(f, "TEST \"{name}\"")?;
+                for step in steps {
+                    writeln!(f, "\t{step}")?;
+                }
+                write!(f, "\tENDTEST")
+            },
+
 
 
 
@@ -1654,12 +1748,14 @@ impl Token {
     pub fn has_at_least_one_listing(&self) -> bool {
         matches!(
             self,
-            Self::CrunchedSection(..)
+            Self::Confined(..)
+                | Self::CrunchedSection(..)
                 | Self::Include(..)
                 | Self::If(..)
                 | Self::Repeat(..)
                 | Self::RepeatUntil(..)
                 | Self::Rorg(..)
+                | Self::Stabilize(..)
                 | Self::Switch(..)
                 | Self::While(..)
         )
@@ -4,6 +4,7 @@ use std::iter::FromIterator;
 use std::ops::{Deref, DerefMut};
 
 use cpclib_common::smallvec::SmallVec;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     AssemblerControlCommand, AssemblerFlavor, BinaryTransformation, CrunchType, DataAccess,
@@ -127,6 +128,8 @@ where Self: Debug + Sized + Sync
     fn is_macro_definition(&self) -> bool;
     fn macro_definition_name(&self) -> &str;
     fn macro_definition_arguments(&self) -> SmallVec<[&str; 4]>;
+    /// Default value of each argument
+    fn macro_definition_defaults(&self) -> SmallVec<[Option<&str>; 4]>;
     fn macro_definition_code(&self) -> &str;
     fn macro_definition_is_variadic(&self) -> bool;
     fn macro_flavor(&self) -> AssemblerFlavor;
@@ -166,6 +169,12 @@ where Self: Debug + Sized + Sync
     fn is_confined(&self) -> bool;
     fn confined_listing(&self) -> &[Self];
 
+    fn is_stabilize(&self) -> bool;
+    fn stabilize_listing(&self) -> &[Self];
+
+    fn is_waitnops(&self) -> bool;
+    fn waitnops_count(&self) -> &Self::Expr;
+
     fn is_db(&self) -> bool;
     fn is_dw(&self) -> bool;
     fn is_str(&self) -> bool;
@@ -963,7 +972,7 @@ mod tests {
 }
 
 /// A listing is simply a list of things similar to token
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct BaseListing<T: Clone + ListingElement> {
     /// Ordered list of the tokens
     pub(crate) listing: Vec<T>,
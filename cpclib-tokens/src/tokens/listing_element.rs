@@ -59,6 +59,22 @@ macro_rules! listing_element_impl_most_methods {
             }
         }
 
+        #[inline]
+        fn is_waitnops(&self) -> bool {
+            match self.unwrapped() {
+                Self::WaitNops(..) => true,
+                _ => false
+            }
+        }
+
+        #[inline]
+        fn waitnops_count(&self) -> &Self::Expr {
+            match self.unwrapped() {
+                Self::WaitNops(count) => count,
+                _ => unreachable!()
+            }
+        }
+
         #[inline]
         fn run_expr(&self) -> &Self::Expr {
             match self.unwrapped() {
@@ -521,6 +537,16 @@ macro_rules! listing_element_impl_most_methods {
             }
         }
 
+        #[inline]
+        fn macro_definition_defaults(&self) -> SmallVec<[Option<&str>; 4]> {
+            match self.unwrapped() {
+                Self::Macro { defaults, .. } => {
+                    defaults.iter().map(|d| d.as_ref().map(|d| d.as_str())).collect()
+                },
+                _ => unreachable!()
+            }
+        }
+
         #[inline]
         fn macro_definition_code(&self) -> &str {
             match self.unwrapped() {
@@ -777,11 +803,25 @@ impl ListingElement for Token {
     }
 
     fn is_confined(&self) -> bool {
-        false // TODO implement properly
+        matches!(self.unwrapped(), Self::Confined(..))
     }
 
     fn confined_listing(&self) -> &[Self] {
-        todo!()
+        match self.unwrapped() {
+            Self::Confined(lst) => lst,
+            _ => unreachable!()
+        }
+    }
+
+    fn is_stabilize(&self) -> bool {
+        matches!(self.unwrapped(), Self::Stabilize(..))
+    }
+
+    fn stabilize_listing(&self) -> &[Self] {
+        match self.unwrapped() {
+            Self::Stabilize(lst) => lst,
+            _ => unreachable!()
+        }
     }
 
     fn switch_cases(&self) -> Box<dyn Iterator<Item = (&Self::Expr, &[Self], bool)> + '_> {
@@ -1,17 +1,21 @@
 pub mod data_access;
 pub mod expression;
+pub mod image;
 pub mod instructions;
 pub mod listing;
 pub mod listing_element;
 pub mod registers;
+pub mod unit_test;
 
 pub use data_access::*;
 pub use expression::*;
+pub use image::*;
 pub use instructions::*;
 pub use listing::*;
 pub use listing_element::*;
 pub use ordered_float;
 pub use registers::*;
+pub use unit_test::*;
 
 #[cfg(test)]
 mod test {}
@@ -1,6 +1,8 @@
 use std::fmt;
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Clone, Copy, Hash)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Clone, Copy, Hash, Serialize, Deserialize)]
 #[allow(missing_docs)]
 pub enum Register16 {
     Af,
@@ -78,7 +80,7 @@ macro_rules! is_reg16 {
 }
 is_reg16! {Af Bc De Hl Sp}
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
 #[allow(missing_docs)]
 pub enum IndexRegister16 {
     Ix,
@@ -113,7 +115,7 @@ impl fmt::Display for IndexRegister16 {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Clone, Hash, Copy)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Clone, Hash, Copy, Serialize, Deserialize)]
 #[allow(missing_docs)]
 pub enum Register8 {
     A,
@@ -196,7 +198,7 @@ impl fmt::Display for Register8 {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
 #[allow(missing_docs)]
 pub enum IndexRegister8 {
     Ixh,
@@ -247,7 +249,7 @@ impl IndexRegister8 {
 // }
 
 // TODO add missing flags
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
 #[allow(missing_docs)]
 pub enum FlagTest {
     NZ,
@@ -0,0 +1,128 @@
+//! Steps of the TEST blocks. They describe how to call a routine on an emulated Z80
+//! once the assembling is done and what is expected after its execution.
+use std::convert::Infallible;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{BinaryOperation, FlagTest, IndexRegister8, IndexRegister16, Register8, Register16};
+
+/// Register that can be set or checked by a unit test
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
+#[allow(missing_docs)]
+pub enum UnitTestRegister {
+    Register8(Register8),
+    Register16(Register16),
+    IndexRegister8(IndexRegister8),
+    IndexRegister16(IndexRegister16)
+}
+
+impl fmt::Display for UnitTestRegister {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Register8(r) => write!(f, "{r}"),
+            Self::Register16(r) => write!(f, "{r}"),
+            Self::IndexRegister8(r) => write!(f, "{r}"),
+            Self::IndexRegister16(r) => write!(f, "{r}")
+        }
+    }
+}
+
+impl UnitTestRegister {
+    pub fn is_8bits(&self) -> bool {
+        matches!(self, Self::Register8(_) | Self::IndexRegister8(_))
+    }
+}
+
+/// What is checked by an EXPECT step
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Serialize, Deserialize)]
+pub enum UnitTestExpectation<E> {
+    /// `EXPECT HL == value`
+    Register(UnitTestRegister, BinaryOperation, E),
+    /// `EXPECT (address) == value` checks a byte of the memory
+    Memory(E, BinaryOperation, E),
+    /// `EXPECT NOPS <= value` checks the duration of the last call
+    Nops(BinaryOperation, E),
+    /// `EXPECT Z` checks the state of a flag
+    Flag(FlagTest)
+}
+
+/// One line of a TEST block.
+/// `E` is an expression while parsing, and its value once assembled
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Serialize, Deserialize)]
+pub enum UnitTestStep<E> {
+    /// `LD register, value` sets a register before the call
+    Load(UnitTestRegister, E),
+    /// `POKE address, byte, ...` writes bytes in memory before the call
+    Poke(E, Vec<E>),
+    /// `CALL routine` executes the routine until it returns
+    Call(E),
+    /// `EXPECT ...` fails the test when the expectation is not met
+    Expect(UnitTestExpectation<E>)
+}
+
+impl<E: fmt::Display> fmt::Display for UnitTestExpectation<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Register(reg, op, value) => write!(f, "{reg} {op} {value}"),
+            Self::Memory(address, op, value) => write!(f, "({address}) {op} {value}"),
+            Self::Nops(op, value) => write!(f, "NOPS {op} {value}"),
+            Self::Flag(flag) => write!(f, "{flag}")
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for UnitTestStep<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Load(reg, value) => write!(f, "LD {reg}, {value}"),
+            Self::Poke(address, bytes) => {
+                write!(f, "POKE {address}")?;
+                for byte in bytes {
+                    write!(f, ", {byte}")?;
+                }
+                Ok(())
+            },
+            Self::Call(routine) => write!(f, "CALL {routine}"),
+            Self::Expect(expectation) => write!(f, "EXPECT {expectation}")
+        }
+    }
+}
+
+impl<E> UnitTestStep<E> {
+    /// Build the same step with other expressions
+    pub fn map<F, R>(&self, mut f: F) -> UnitTestStep<R>
+    where F: FnMut(&E) -> R {
+        self.try_map(|e| Ok::<R, Infallible>(f(e)))
+            .unwrap_or_else(|e| match e {})
+    }
+
+    /// Build the same step with other expressions (typically their values)
+    pub fn try_map<F, R, Err>(&self, mut f: F) -> Result<UnitTestStep<R>, Err>
+    where F: FnMut(&E) -> Result<R, Err> {
+        Ok(match self {
+            Self::Load(reg, value) => UnitTestStep::Load(*reg, f(value)?),
+            Self::Poke(address, bytes) => {
+                UnitTestStep::Poke(
+                    f(address)?,
+                    bytes.iter().map(&mut f).collect::<Result<Vec<_>, _>>()?
+                )
+            },
+            Self::Call(routine) => UnitTestStep::Call(f(routine)?),
+            Self::Expect(expectation) => {
+                UnitTestStep::Expect(match expectation {
+                    UnitTestExpectation::Register(reg, op, value) => {
+                        UnitTestExpectation::Register(*reg, *op, f(value)?)
+                    },
+                    UnitTestExpectation::Memory(address, op, value) => {
+                        UnitTestExpectation::Memory(f(address)?, *op, f(value)?)
+                    },
+                    UnitTestExpectation::Nops(op, value) => {
+                        UnitTestExpectation::Nops(*op, f(value)?)
+                    },
+                    UnitTestExpectation::Flag(flag) => UnitTestExpectation::Flag(*flag)
+                })
+            },
+        })
+    }
+}
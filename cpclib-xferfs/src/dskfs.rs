@@ -0,0 +1,1146 @@
+//! Mount the AMSDOS catalog of a DSK image as a file system.
+//!
+//! Each user area is a folder named `user0` to `user15`. Files are named `NAME.EXT` whatever the case
+//! and their content is the data stored after the AMSDOS header.
+//! The header is available through extended attributes:
+//! - `user.amsdos.type`: `basic`, `protected` or `binary`
+//! - `user.amsdos.loading_address` and `user.amsdos.execution_address`
+//! - `user.amsdos.header`: the 128 bytes of the header
+//!
+//! Files without header are ASCII files. Setting one of these attributes adds a header to the file
+//! and removing `user.amsdos.header` turns it back to an ASCII file.
+//!
+//! Opened files are kept in memory and written on the disc when closed.
+//! The image itself is saved on `fsync` and when the file system is unmounted.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+use cpclib_common::camino::{Utf8Path, Utf8PathBuf};
+use cpclib_common::parse_value;
+use cpclib_common::winnow::Parser;
+use cpclib_common::winnow::error::ContextError;
+use cpclib_disc::amsdos::{
+    AmsdosAddBehavior, AmsdosEntry, AmsdosError, AmsdosFile, AmsdosFileName, AmsdosFileType,
+    AmsdosHeader, AmsdosManagerMut, AmsdosManagerNonMut
+};
+use cpclib_disc::disc::Disc;
+use cpclib_disc::edsk::{ExtendedDsk, Head};
+use fuse_mt::{
+    CallbackResult, CreatedEntry, DirectoryEntry, FileAttr, FileType, FilesystemMT, RequestInfo,
+    ResultCreate, ResultEmpty, ResultEntry, ResultOpen, ResultReaddir, ResultSlice, ResultStatfs,
+    ResultWrite, ResultXattr, Statfs, Xattr
+};
+use libc::c_int;
+
+/// Attributes never change behind the back of the kernel
+const TTL: Duration = Duration::from_secs(1);
+
+const MAX_USER: u8 = 15;
+const RECORD_SIZE: usize = 128;
+/// Marks the end of an ASCII file in its last record
+const ASCII_EOF: u8 = 0x1A;
+
+const XATTR_TYPE: &str = "user.amsdos.type";
+const XATTR_LOADING_ADDRESS: &str = "user.amsdos.loading_address";
+const XATTR_EXECUTION_ADDRESS: &str = "user.amsdos.execution_address";
+const XATTR_HEADER: &str = "user.amsdos.header";
+
+/// What a path of the file system refers to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Node {
+    Root,
+    User(u8),
+    File(AmsdosFileName)
+}
+
+fn user_folder(user: u8) -> String {
+    format!("user{user}")
+}
+
+fn parse_user_folder(name: &str) -> Option<u8> {
+    let user = name.strip_prefix("user")?.parse::<u8>().ok()?;
+    (user <= MAX_USER && user_folder(user) == name).then_some(user)
+}
+
+/// Name of the file in its folder
+fn file_name(filename: &AmsdosFileName) -> String {
+    filename.ibm_filename()
+}
+
+/// Only names AMSDOS is able to store are accepted
+fn parse_file_name(user: u8, name: &str) -> Option<AmsdosFileName> {
+    let (name, extension) = name.split_once('.').unwrap_or((name, ""));
+    let is_valid = |part: &str| {
+        part.bytes()
+            .all(|c| c != b' ' && AmsdosFileName::is_valid_char(c.to_ascii_uppercase()))
+    };
+
+    if name.is_empty() || name.len() > 8 || extension.len() > 3 {
+        return None;
+    }
+    if !is_valid(name) || !is_valid(extension) {
+        return None;
+    }
+
+    AmsdosFileName::new_correct_case(user, name, extension).ok()
+}
+
+fn parse_path(path: &Path) -> Option<Node> {
+    let mut components = path.components();
+    if components.next() != Some(Component::RootDir) {
+        return None;
+    }
+
+    let user = match components.next() {
+        None => return Some(Node::Root),
+        Some(Component::Normal(name)) => parse_user_folder(name.to_str()?)?,
+        Some(_) => return None
+    };
+
+    let filename = match components.next() {
+        None => return Some(Node::User(user)),
+        Some(Component::Normal(name)) => parse_file_name(user, name.to_str()?)?,
+        Some(_) => return None
+    };
+
+    if components.next().is_some() {
+        None
+    }
+    else {
+        Some(Node::File(filename))
+    }
+}
+
+/// Retrieve the file an existing path refers to
+fn file_node(path: &Path) -> Result<AmsdosFileName, c_int> {
+    match parse_path(path) {
+        Some(Node::File(filename)) => Ok(filename),
+        Some(_) => Err(libc::EISDIR),
+        None => Err(libc::ENOENT)
+    }
+}
+
+/// Retrieve the file a new entry of a folder refers to
+fn new_file_node(parent: &Path, name: &OsStr) -> Result<AmsdosFileName, c_int> {
+    match parse_path(&parent.join(name)) {
+        Some(Node::File(filename)) => Ok(filename),
+        _ => {
+            match parse_path(parent) {
+                Some(Node::User(_)) => Err(libc::EINVAL),
+                Some(Node::Root) => Err(libc::EPERM),
+                _ => Err(libc::ENOENT)
+            }
+        },
+    }
+}
+
+fn errno(error: &AmsdosError) -> c_int {
+    match error {
+        AmsdosError::NoEntriesAvailable | AmsdosError::NoBlocAvailable => libc::ENOSPC,
+        AmsdosError::FileLargerThan64Kb => libc::EFBIG,
+        AmsdosError::WrongFileName { .. } => libc::EINVAL,
+        AmsdosError::FileAlreadyExists(_) => libc::EEXIST,
+        AmsdosError::FileDoesNotExist(_) => libc::ENOENT,
+        _ => libc::EIO
+    }
+}
+
+/// Length of an ASCII file. The last record either ends with end of file markers used as padding,
+/// or contains garbage after the first marker
+fn ascii_len(content: &[u8]) -> usize {
+    let last_record = content.len().saturating_sub(1) / RECORD_SIZE * RECORD_SIZE;
+    let record = &content[last_record..];
+    let len = if record.last() == Some(&ASCII_EOF) {
+        record
+            .iter()
+            .rposition(|&b| b != ASCII_EOF)
+            .map_or(0, |pos| pos + 1)
+    }
+    else {
+        record
+            .iter()
+            .position(|&b| b == ASCII_EOF)
+            .unwrap_or(record.len())
+    };
+    last_record + len
+}
+
+/// ASCII files are stored by records padded with the end of file marker.
+/// An empty file still needs a record to exist in the catalog
+fn ascii_padding(content: &[u8]) -> Vec<u8> {
+    let mut content = content.to_vec();
+    let len = content.len().max(1).next_multiple_of(RECORD_SIZE);
+    content.resize(len, ASCII_EOF);
+    content
+}
+
+fn file_type_name(file_type: AmsdosFileType) -> &'static str {
+    match file_type {
+        AmsdosFileType::Basic => "basic",
+        AmsdosFileType::Protected => "protected",
+        AmsdosFileType::Binary => "binary"
+    }
+}
+
+fn parse_file_type(value: &[u8]) -> Result<AmsdosFileType, c_int> {
+    match value.trim_ascii() {
+        b"basic" => Ok(AmsdosFileType::Basic),
+        b"protected" => Ok(AmsdosFileType::Protected),
+        b"binary" => Ok(AmsdosFileType::Binary),
+        _ => Err(libc::EINVAL)
+    }
+}
+
+/// Addresses use any notation understood by basm
+fn parse_address(value: &[u8]) -> Result<u16, c_int> {
+    parse_value::<_, ContextError>
+        .parse(value.trim_ascii())
+        .ok()
+        .and_then(|value| u16::try_from(value).ok())
+        .ok_or(libc::EINVAL)
+}
+
+/// Header of a file that may not have one yet
+fn header_mut<'h>(
+    header: &'h mut Option<AmsdosHeader>,
+    filename: &AmsdosFileName
+) -> &'h mut AmsdosHeader {
+    header.get_or_insert_with(|| {
+        AmsdosHeader::build_header(filename, AmsdosFileType::Binary, 0, 0, 0)
+    })
+}
+
+/// Follow the protocol of extended attributes: a null size requests the size of the value
+fn xattr_reply(value: Vec<u8>, size: u32) -> ResultXattr {
+    if size == 0 {
+        Ok(Xattr::Size(value.len() as u32))
+    }
+    else if value.len() > size as usize {
+        Err(libc::ERANGE)
+    }
+    else {
+        Ok(Xattr::Data(value))
+    }
+}
+
+/// A file opened at least once. Its content is written on the disc when it is flushed
+#[derive(Debug)]
+struct Buffer {
+    filename: AmsdosFileName,
+    header: Option<AmsdosHeader>,
+    content: Vec<u8>,
+    modified: bool,
+    handles: usize
+}
+
+/// The disc and the opened files
+#[derive(Debug)]
+struct DskFsState {
+    dsk: ExtendedDsk,
+    /// The disc differs from the saved image
+    modified: bool,
+    mtime: SystemTime,
+    buffers: Vec<Buffer>,
+    handles: HashMap<u64, AmsdosFileName>,
+    next_handle: u64
+}
+
+impl DskFsState {
+    fn new(dsk: ExtendedDsk, mtime: SystemTime) -> Self {
+        Self {
+            dsk,
+            modified: false,
+            mtime,
+            buffers: Vec::new(),
+            handles: HashMap::new(),
+            next_handle: 0
+        }
+    }
+
+    fn manager(&self) -> AmsdosManagerNonMut<'_, ExtendedDsk> {
+        AmsdosManagerNonMut::new_from_disc(&self.dsk, Head::A)
+    }
+
+    fn touch(&mut self) {
+        self.modified = true;
+        self.mtime = SystemTime::now();
+    }
+
+    fn save<P: AsRef<Utf8Path>>(&mut self, path: P) -> Result<(), String> {
+        if self.modified {
+            self.dsk.save(path)?;
+            self.modified = false;
+        }
+        Ok(())
+    }
+
+    /// First catalog entry of a file
+    fn entry(&self, filename: &AmsdosFileName) -> Option<AmsdosEntry> {
+        self.manager().catalog().for_file(filename).next().cloned()
+    }
+
+    fn buffer(&self, filename: &AmsdosFileName) -> Option<&Buffer> {
+        self.buffers.iter().find(|b| &b.filename == filename)
+    }
+
+    fn buffer_mut(&mut self, filename: &AmsdosFileName) -> Option<&mut Buffer> {
+        self.buffers.iter_mut().find(|b| &b.filename == filename)
+    }
+
+    fn handle(&self, handle: u64) -> Result<AmsdosFileName, c_int> {
+        self.handles.get(&handle).copied().ok_or(libc::EBADF)
+    }
+
+    fn handle_buffer(&self, handle: u64) -> Result<&Buffer, c_int> {
+        let filename = self.handle(handle)?;
+        self.buffer(&filename).ok_or(libc::EBADF)
+    }
+
+    fn handle_buffer_mut(&mut self, handle: u64) -> Result<&mut Buffer, c_int> {
+        let filename = self.handle(handle)?;
+        self.buffer_mut(&filename).ok_or(libc::EBADF)
+    }
+
+    /// Files of a user area, including the created ones not flushed yet.
+    /// Names that cannot be typed back are ignored
+    fn files(&self, user: u8) -> Vec<AmsdosFileName> {
+        let catalog = self.manager().catalog();
+        let stored = catalog.used_entries().map(|e| *e.amsdos_filename());
+        let opened = self.buffers.iter().map(|b| b.filename);
+
+        let mut files: Vec<AmsdosFileName> = Vec::new();
+        for filename in stored.chain(opened) {
+            if filename.user() == user
+                && parse_file_name(user, &file_name(&filename)) == Some(filename)
+                && !files.contains(&filename)
+            {
+                files.push(filename);
+            }
+        }
+        files
+    }
+
+    /// Header and content of a file stored on the disc
+    fn load(&self, filename: &AmsdosFileName) -> Option<(Option<AmsdosHeader>, Vec<u8>)> {
+        let manager = self.manager();
+        let file = manager.get_file(*filename)?;
+        let header = file.header();
+        let mut content = file.content().to_vec();
+        if header.is_none() {
+            // whole blocs are read whereas the catalog counts the records
+            let nb_records = manager
+                .catalog()
+                .for_file(filename)
+                .map(AmsdosEntry::nb_records)
+                .sum::<usize>();
+            content.truncate(nb_records * RECORD_SIZE);
+            content.truncate(ascii_len(&content));
+        }
+        Some((header, content))
+    }
+
+    /// Header and content of a file including the modifications not flushed yet
+    fn file(&self, filename: &AmsdosFileName) -> Option<(Option<AmsdosHeader>, Vec<u8>)> {
+        match self.buffer(filename) {
+            Some(buffer) => Some((buffer.header, buffer.content.clone())),
+            None => self.load(filename)
+        }
+    }
+
+    /// Replace the file on the disc, keeping its attributes.
+    /// The disc is left untouched when the file cannot be stored
+    fn store(
+        &mut self,
+        filename: &AmsdosFileName,
+        header: Option<AmsdosHeader>,
+        content: &[u8]
+    ) -> Result<(), c_int> {
+        let (system, read_only) = self
+            .entry(filename)
+            .map_or((false, false), |e| (e.is_system(), e.is_read_only()));
+
+        let file = match header {
+            Some(mut header) => {
+                let length = u16::try_from(content.len()).map_err(|_| libc::EFBIG)?;
+                header
+                    .set_amsdos_filename(filename)
+                    .set_file_length(length)
+                    .update_checksum();
+                AmsdosFile::from_header_and_buffer(header, content).map_err(|e| errno(&e))?
+            },
+            None => AmsdosFile::ascii_file_from_buffer_with_name(filename, &ascii_padding(content))
+        };
+
+        let backup = self.dsk.clone();
+        let res = AmsdosManagerMut::new_from_disc(&mut self.dsk, Head::A).add_file(
+            &file,
+            Some(filename),
+            system,
+            read_only,
+            AmsdosAddBehavior::ReplaceIfPresent
+        );
+        match res {
+            Ok(()) => {
+                self.touch();
+                Ok(())
+            },
+            Err(e) => {
+                self.dsk = backup;
+                Err(errno(&e))
+            }
+        }
+    }
+
+    /// Modify a file, in memory when it is opened, on the disc otherwise
+    fn update<F>(&mut self, filename: &AmsdosFileName, f: F) -> Result<(), c_int>
+    where F: FnOnce(&mut Option<AmsdosHeader>, &mut Vec<u8>) -> Result<(), c_int> {
+        if let Some(buffer) = self.buffer_mut(filename) {
+            f(&mut buffer.header, &mut buffer.content)?;
+            buffer.modified = true;
+            Ok(())
+        }
+        else {
+            let (mut header, mut content) = self.load(filename).ok_or(libc::ENOENT)?;
+            f(&mut header, &mut content)?;
+            self.store(filename, header, &content)
+        }
+    }
+
+    fn open(&mut self, filename: &AmsdosFileName, create: bool) -> Result<u64, c_int> {
+        if let Some(buffer) = self.buffer_mut(filename) {
+            buffer.handles += 1;
+        }
+        else {
+            let (header, content, modified) = match self.load(filename) {
+                Some((header, content)) => (header, content, false),
+                None if create => (None, Vec::new(), true),
+                None => return Err(libc::ENOENT)
+            };
+            self.buffers.push(Buffer {
+                filename: *filename,
+                header,
+                content,
+                modified,
+                handles: 1
+            });
+        }
+
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.handles.insert(handle, *filename);
+        Ok(handle)
+    }
+
+    fn write(&mut self, handle: u64, offset: usize, data: &[u8]) -> Result<u32, c_int> {
+        let buffer = self.handle_buffer_mut(handle)?;
+        let end = offset + data.len();
+        if buffer.content.len() < end {
+            buffer.content.resize(end, 0);
+        }
+        buffer.content[offset..end].copy_from_slice(data);
+        buffer.modified = true;
+        Ok(data.len() as u32)
+    }
+
+    fn flush(&mut self, filename: &AmsdosFileName) -> Result<(), c_int> {
+        let (header, content) = match self.buffer(filename) {
+            Some(buffer) if buffer.modified => (buffer.header, buffer.content.clone()),
+            _ => return Ok(())
+        };
+
+        self.store(filename, header, &content)?;
+        if let Some(buffer) = self.buffer_mut(filename) {
+            buffer.modified = false;
+        }
+        Ok(())
+    }
+
+    fn release(&mut self, handle: u64) -> Result<(), c_int> {
+        let filename = self.handles.remove(&handle).ok_or(libc::EBADF)?;
+        let res = self.flush(&filename);
+
+        if let Some(idx) = self.buffers.iter().position(|b| b.filename == filename) {
+            self.buffers[idx].handles -= 1;
+            if self.buffers[idx].handles == 0 {
+                self.buffers.remove(idx);
+            }
+        }
+        res
+    }
+
+    fn unlink(&mut self, filename: &AmsdosFileName) -> Result<(), c_int> {
+        match self.entry(filename) {
+            Some(entry) if entry.is_read_only() => return Err(libc::EACCES),
+            Some(_) => {
+                AmsdosManagerMut::new_from_disc(&mut self.dsk, Head::A)
+                    .erase_file(*filename, false)
+                    .map_err(|e| errno(&e))?;
+                self.touch();
+            },
+            None if self.buffer(filename).is_none() => return Err(libc::ENOENT),
+            None => {}
+        }
+
+        // opened files are forgotten
+        self.buffers.retain(|b| &b.filename != filename);
+        Ok(())
+    }
+
+    /// Rename a file, possibly in another user area, and replace the destination
+    fn rename(
+        &mut self,
+        source: &AmsdosFileName,
+        destination: &AmsdosFileName
+    ) -> Result<(), c_int> {
+        let stored = self.entry(source).is_some();
+        if !stored && self.buffer(source).is_none() {
+            return Err(libc::ENOENT);
+        }
+        // only the case differs
+        if source == destination {
+            return Ok(());
+        }
+
+        if self.entry(destination).is_some() || self.buffer(destination).is_some() {
+            self.unlink(destination)?;
+        }
+
+        if stored {
+            AmsdosManagerMut::new_from_disc(&mut self.dsk, Head::A)
+                .rename(*source, *destination)
+                .map_err(|e| errno(&e))?;
+            self.touch();
+        }
+
+        for buffer in self.buffers.iter_mut().filter(|b| &b.filename == source) {
+            buffer.filename = *destination;
+        }
+        for filename in self.handles.values_mut().filter(|f| *f == source) {
+            *filename = *destination;
+        }
+        Ok(())
+    }
+
+    fn set_read_only(&mut self, filename: &AmsdosFileName, read_only: bool) -> Result<(), c_int> {
+        let entries = self
+            .manager()
+            .catalog()
+            .for_file(filename)
+            .cloned()
+            .collect::<Vec<_>>();
+        if entries.is_empty() {
+            return self.buffer(filename).map(|_| ()).ok_or(libc::ENOENT);
+        }
+
+        let mut manager = AmsdosManagerMut::new_from_disc(&mut self.dsk, Head::A);
+        for mut entry in entries {
+            if read_only {
+                entry.set_read_only();
+            }
+            else {
+                entry.unset_read_only();
+            }
+            manager.update_entry(&entry);
+        }
+        self.touch();
+        Ok(())
+    }
+
+    fn xattr(&self, filename: &AmsdosFileName, name: &OsStr) -> Result<Vec<u8>, c_int> {
+        let (header, _) = self.file(filename).ok_or(libc::ENOENT)?;
+        let header = header.ok_or(libc::ENODATA)?;
+
+        match name.to_str() {
+            Some(XATTR_TYPE) => {
+                header
+                    .file_type()
+                    .map(|t| file_type_name(t).as_bytes().to_vec())
+                    .map_err(|_| libc::ENODATA)
+            },
+            Some(XATTR_LOADING_ADDRESS) => {
+                Ok(format!("0x{:04X}", header.loading_address()).into_bytes())
+            },
+            Some(XATTR_EXECUTION_ADDRESS) => {
+                Ok(format!("0x{:04X}", header.execution_address()).into_bytes())
+            },
+            Some(XATTR_HEADER) => Ok(header.as_bytes().to_vec()),
+            _ => Err(libc::ENODATA)
+        }
+    }
+
+    fn xattr_names(&self, filename: &AmsdosFileName) -> Result<Vec<u8>, c_int> {
+        let (header, _) = self.file(filename).ok_or(libc::ENOENT)?;
+        let names = if header.is_some() {
+            [
+                XATTR_TYPE,
+                XATTR_LOADING_ADDRESS,
+                XATTR_EXECUTION_ADDRESS,
+                XATTR_HEADER
+            ]
+            .as_slice()
+        }
+        else {
+            &[]
+        };
+
+        Ok(names
+            .iter()
+            .flat_map(|name| name.bytes().chain(std::iter::once(0)))
+            .collect())
+    }
+
+    fn set_xattr(
+        &mut self,
+        filename: &AmsdosFileName,
+        name: &OsStr,
+        value: &[u8]
+    ) -> Result<(), c_int> {
+        match name.to_str() {
+            Some(XATTR_TYPE) => {
+                let file_type = parse_file_type(value)?;
+                self.update(filename, |header, _| {
+                    header_mut(header, filename)
+                        .set_file_type(file_type)
+                        .update_checksum();
+                    Ok(())
+                })
+            },
+            Some(XATTR_LOADING_ADDRESS) => {
+                let address = parse_address(value)?;
+                self.update(filename, |header, _| {
+                    header_mut(header, filename)
+                        .set_loading_address(address)
+                        .update_checksum();
+                    Ok(())
+                })
+            },
+            Some(XATTR_EXECUTION_ADDRESS) => {
+                let address = parse_address(value)?;
+                self.update(filename, |header, _| {
+                    header_mut(header, filename)
+                        .set_execution_address(address)
+                        .update_checksum();
+                    Ok(())
+                })
+            },
+            Some(XATTR_HEADER) => {
+                if value.len() != AmsdosHeader::HEADER_SIZE {
+                    return Err(libc::EINVAL);
+                }
+                let new_header = AmsdosHeader::from_buffer(value);
+                self.update(filename, |header, _| {
+                    *header = Some(new_header);
+                    Ok(())
+                })
+            },
+            _ => Err(libc::ENOTSUP)
+        }
+    }
+
+    fn remove_xattr(&mut self, filename: &AmsdosFileName, name: &OsStr) -> Result<(), c_int> {
+        match name.to_str() {
+            Some(XATTR_HEADER) => {
+                self.update(filename, |header, _| {
+                    header.take().map(|_| ()).ok_or(libc::ENODATA)
+                })
+            },
+            Some(XATTR_TYPE | XATTR_LOADING_ADDRESS | XATTR_EXECUTION_ADDRESS) => Err(libc::EPERM),
+            _ => Err(libc::ENODATA)
+        }
+    }
+
+    fn statfs(&self) -> Statfs {
+        let manager = self.manager();
+        let dpb = *manager.dpb();
+        let catalog = manager.catalog();
+        let free_blocs = catalog.available_blocs().count() as u64;
+
+        Statfs {
+            blocks: dpb.nb_blocs() as u64,
+            bfree: free_blocs,
+            bavail: free_blocs,
+            files: u64::from(dpb.directory_entries),
+            ffree: catalog.free_entries().count() as u64,
+            bsize: u32::from(dpb.bloc_size),
+            namelen: 12,
+            frsize: u32::from(dpb.bloc_size)
+        }
+    }
+}
+
+/// File system giving access to the files of a DSK image
+#[derive(Debug)]
+pub struct DskFs {
+    path: Utf8PathBuf,
+    uid: u32,
+    gid: u32,
+    state: Mutex<DskFsState>
+}
+
+impl DskFs {
+    /// Load the image. Files belong to the owner of the image
+    pub fn open<P: AsRef<Utf8Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let metadata =
+            std::fs::metadata(path).map_err(|e| format!("Unable to read {path}. {e}"))?;
+        let dsk = ExtendedDsk::open(path)?;
+        let mtime = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+
+        Ok(Self {
+            path: path.to_owned(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            state: Mutex::new(DskFsState::new(dsk, mtime))
+        })
+    }
+
+    /// Write the image if it has been modified. Standard DSK images are saved as extended ones
+    pub fn save(&self) -> Result<(), String> {
+        self.state().save(&self.path)
+    }
+
+    fn state(&self) -> MutexGuard<'_, DskFsState> {
+        self.state.lock().unwrap()
+    }
+
+    fn attr(&self, state: &DskFsState, node: &Node) -> Result<FileAttr, c_int> {
+        let (kind, size, perm) = match node {
+            Node::Root | Node::User(_) => (FileType::Directory, 0, 0o755),
+            Node::File(filename) => {
+                let (_, content) = state.file(filename).ok_or(libc::ENOENT)?;
+                let read_only = state.entry(filename).is_some_and(|e| e.is_read_only());
+                let perm = if read_only { 0o444 } else { 0o644 };
+                (FileType::RegularFile, content.len() as u64, perm)
+            }
+        };
+
+        Ok(FileAttr {
+            size,
+            blocks: size.div_ceil(512),
+            atime: state.mtime,
+            mtime: state.mtime,
+            ctime: state.mtime,
+            crtime: state.mtime,
+            kind,
+            perm,
+            nlink: if kind == FileType::Directory { 2 } else { 1 },
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            flags: 0
+        })
+    }
+}
+
+impl FilesystemMT for DskFs {
+    fn init(&self, _req: RequestInfo) -> ResultEmpty {
+        Ok(())
+    }
+
+    fn destroy(&self) {
+        if let Err(e) = self.save() {
+            eprintln!("Unable to save {}. {e}", self.path);
+        }
+    }
+
+    fn getattr(&self, _req: RequestInfo, path: &Path, _fh: Option<u64>) -> ResultEntry {
+        let node = parse_path(path).ok_or(libc::ENOENT)?;
+        Ok((TTL, self.attr(&self.state(), &node)?))
+    }
+
+    /// Only the write permission is kept: it is the read-only attribute of the file
+    fn chmod(&self, _req: RequestInfo, path: &Path, _fh: Option<u64>, mode: u32) -> ResultEmpty {
+        let filename = file_node(path)?;
+        self.state().set_read_only(&filename, mode & 0o222 == 0)
+    }
+
+    fn truncate(&self, _req: RequestInfo, path: &Path, fh: Option<u64>, size: u64) -> ResultEmpty {
+        let mut state = self.state();
+        let filename = match fh {
+            Some(fh) => state.handle(fh)?,
+            None => file_node(path)?
+        };
+        state.update(&filename, |_, content| {
+            content.resize(size as usize, 0);
+            Ok(())
+        })
+    }
+
+    /// Times are not stored on the disc
+    fn utimens(
+        &self,
+        _req: RequestInfo,
+        path: &Path,
+        _fh: Option<u64>,
+        _atime: Option<SystemTime>,
+        _mtime: Option<SystemTime>
+    ) -> ResultEmpty {
+        let node = parse_path(path).ok_or(libc::ENOENT)?;
+        self.attr(&self.state(), &node).map(|_| ())
+    }
+
+    fn unlink(&self, _req: RequestInfo, parent: &Path, name: &OsStr) -> ResultEmpty {
+        let filename = file_node(&parent.join(name))?;
+        self.state().unlink(&filename)
+    }
+
+    fn rename(
+        &self,
+        _req: RequestInfo,
+        parent: &Path,
+        name: &OsStr,
+        newparent: &Path,
+        newname: &OsStr
+    ) -> ResultEmpty {
+        let source = file_node(&parent.join(name))?;
+        let destination = new_file_node(newparent, newname)?;
+        self.state().rename(&source, &destination)
+    }
+
+    fn open(&self, _req: RequestInfo, path: &Path, flags: u32) -> ResultOpen {
+        let filename = file_node(path)?;
+        let mut state = self.state();
+
+        let write = flags as c_int & libc::O_ACCMODE != libc::O_RDONLY;
+        if write && state.entry(&filename).is_some_and(|e| e.is_read_only()) {
+            return Err(libc::EACCES);
+        }
+
+        Ok((state.open(&filename, false)?, 0))
+    }
+
+    fn read(
+        &self,
+        _req: RequestInfo,
+        _path: &Path,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult
+    ) -> CallbackResult {
+        let state = self.state();
+        match state.handle_buffer(fh) {
+            Ok(buffer) => {
+                let content = &buffer.content;
+                let start = (offset as usize).min(content.len());
+                let end = start.saturating_add(size as usize).min(content.len());
+                callback(Ok(&content[start..end]))
+            },
+            Err(e) => callback(Err(e))
+        }
+    }
+
+    fn write(
+        &self,
+        _req: RequestInfo,
+        _path: &Path,
+        fh: u64,
+        offset: u64,
+        data: Vec<u8>,
+        _flags: u32
+    ) -> ResultWrite {
+        self.state().write(fh, offset as usize, &data)
+    }
+
+    fn flush(&self, _req: RequestInfo, _path: &Path, fh: u64, _lock_owner: u64) -> ResultEmpty {
+        let mut state = self.state();
+        let filename = state.handle(fh)?;
+        state.flush(&filename)
+    }
+
+    fn release(
+        &self,
+        _req: RequestInfo,
+        _path: &Path,
+        fh: u64,
+        _flags: u32,
+        _lock_owner: u64,
+        _flush: bool
+    ) -> ResultEmpty {
+        self.state().release(fh)
+    }
+
+    fn fsync(&self, _req: RequestInfo, _path: &Path, fh: u64, _datasync: bool) -> ResultEmpty {
+        let mut state = self.state();
+        let filename = state.handle(fh)?;
+        state.flush(&filename)?;
+        state.save(&self.path).map_err(|_| libc::EIO)
+    }
+
+    fn opendir(&self, _req: RequestInfo, path: &Path, _flags: u32) -> ResultOpen {
+        match parse_path(path) {
+            Some(Node::Root | Node::User(_)) => Ok((0, 0)),
+            Some(Node::File(_)) => Err(libc::ENOTDIR),
+            None => Err(libc::ENOENT)
+        }
+    }
+
+    fn readdir(&self, _req: RequestInfo, path: &Path, _fh: u64) -> ResultReaddir {
+        let entry = |name: String, kind: FileType| {
+            DirectoryEntry {
+                name: name.into(),
+                kind
+            }
+        };
+
+        let mut entries = vec![
+            entry(".".to_owned(), FileType::Directory),
+            entry("..".to_owned(), FileType::Directory),
+        ];
+        match parse_path(path).ok_or(libc::ENOENT)? {
+            Node::Root => {
+                entries.extend((0..=MAX_USER).map(|u| entry(user_folder(u), FileType::Directory)))
+            },
+            Node::User(user) => {
+                entries.extend(
+                    self.state()
+                        .files(user)
+                        .iter()
+                        .map(|f| entry(file_name(f), FileType::RegularFile))
+                )
+            },
+            Node::File(_) => return Err(libc::ENOTDIR)
+        }
+        Ok(entries)
+    }
+
+    fn releasedir(&self, _req: RequestInfo, _path: &Path, _fh: u64, _flags: u32) -> ResultEmpty {
+        Ok(())
+    }
+
+    fn statfs(&self, _req: RequestInfo, _path: &Path) -> ResultStatfs {
+        Ok(self.state().statfs())
+    }
+
+    fn setxattr(
+        &self,
+        _req: RequestInfo,
+        path: &Path,
+        name: &OsStr,
+        value: &[u8],
+        _flags: u32,
+        _position: u32
+    ) -> ResultEmpty {
+        match parse_path(path).ok_or(libc::ENOENT)? {
+            Node::File(filename) => self.state().set_xattr(&filename, name, value),
+            _ => Err(libc::ENOTSUP)
+        }
+    }
+
+    /// Folders have no attribute
+    fn getxattr(&self, _req: RequestInfo, path: &Path, name: &OsStr, size: u32) -> ResultXattr {
+        let value = match parse_path(path).ok_or(libc::ENOENT)? {
+            Node::File(filename) => self.state().xattr(&filename, name)?,
+            _ => return Err(libc::ENODATA)
+        };
+        xattr_reply(value, size)
+    }
+
+    fn listxattr(&self, _req: RequestInfo, path: &Path, size: u32) -> ResultXattr {
+        let names = match parse_path(path).ok_or(libc::ENOENT)? {
+            Node::File(filename) => self.state().xattr_names(&filename)?,
+            _ => Vec::new()
+        };
+        xattr_reply(names, size)
+    }
+
+    fn removexattr(&self, _req: RequestInfo, path: &Path, name: &OsStr) -> ResultEmpty {
+        match parse_path(path).ok_or(libc::ENOENT)? {
+            Node::File(filename) => self.state().remove_xattr(&filename, name),
+            _ => Err(libc::ENODATA)
+        }
+    }
+
+    fn create(
+        &self,
+        _req: RequestInfo,
+        parent: &Path,
+        name: &OsStr,
+        _mode: u32,
+        _flags: u32
+    ) -> ResultCreate {
+        let filename = new_file_node(parent, name)?;
+        let mut state = self.state();
+        let fh = state.open(&filename, true)?;
+        let attr = self.attr(&state, &Node::File(filename))?;
+
+        Ok(CreatedEntry {
+            ttl: TTL,
+            attr,
+            fh,
+            flags: 0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cpclib_disc::cfg::DiscConfig;
+
+    use super::*;
+
+    fn empty_state() -> DskFsState {
+        let dsk: ExtendedDsk = DiscConfig::single_head_data_format().into();
+        DskFsState::new(dsk, SystemTime::UNIX_EPOCH)
+    }
+
+    fn name(user: u8, name: &str) -> AmsdosFileName {
+        parse_file_name(user, name).unwrap()
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(parse_path(Path::new("/")), Some(Node::Root));
+        assert_eq!(parse_path(Path::new("/user15")), Some(Node::User(15)));
+        assert_eq!(
+            parse_path(Path::new("/user3/hello.bas")),
+            Some(Node::File(
+                AmsdosFileName::new_correct_case(3, "HELLO", "BAS").unwrap()
+            ))
+        );
+        assert_eq!(
+            parse_path(Path::new("/user0/README")),
+            Some(Node::File(
+                AmsdosFileName::new_correct_case(0, "README", "").unwrap()
+            ))
+        );
+
+        assert_eq!(parse_path(Path::new("/user16")), None);
+        assert_eq!(parse_path(Path::new("/user01")), None);
+        assert_eq!(parse_path(Path::new("/hello.bas")), None);
+        assert_eq!(parse_path(Path::new("/user0/toolongname.bas")), None);
+        assert_eq!(parse_path(Path::new("/user0/hello.text")), None);
+        assert_eq!(parse_path(Path::new("/user0/.hello.swp")), None);
+        assert_eq!(parse_path(Path::new("/user0/hello.bas/more")), None);
+    }
+
+    #[test]
+    fn ascii_file() {
+        let mut state = empty_state();
+        let filename = name(0, "notes.txt");
+
+        let handle = state.open(&filename, true).unwrap();
+        state.write(handle, 0, b"10 PRINT \"HELLO\"\r\n").unwrap();
+        state.release(handle).unwrap();
+
+        assert!(state.modified);
+        assert_eq!(state.files(0), vec![filename]);
+        let (header, content) = state.file(&filename).unwrap();
+        assert!(header.is_none());
+        assert_eq!(content, b"10 PRINT \"HELLO\"\r\n");
+
+        // an empty file is kept in the catalog
+        state
+            .update(&filename, |_, content| {
+                content.clear();
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(state.file(&filename).unwrap().1, b"");
+        assert_eq!(state.files(0), vec![filename]);
+
+        // AMSDOS leaves garbage after the marker
+        assert_eq!(ascii_len(b"10 END\r\n\x1aRUN\r\n"), 8);
+        assert_eq!(ascii_len(&[b'A'; 256]), 256);
+    }
+
+    #[test]
+    fn header_as_xattr() {
+        let mut state = empty_state();
+        let filename = name(0, "code.bin");
+        let data = (0..300).map(|i| i as u8).collect::<Vec<u8>>();
+
+        let handle = state.open(&filename, true).unwrap();
+        state.write(handle, 0, &data).unwrap();
+        state.release(handle).unwrap();
+        assert_eq!(state.xattr_names(&filename).unwrap(), b"");
+
+        state
+            .set_xattr(&filename, OsStr::new(XATTR_LOADING_ADDRESS), b"0x4000")
+            .unwrap();
+        state
+            .set_xattr(&filename, OsStr::new(XATTR_EXECUTION_ADDRESS), b"&4010\n")
+            .unwrap();
+
+        let (header, content) = state.load(&filename).unwrap();
+        let header = header.unwrap();
+        assert!(header.file_type() == Ok(AmsdosFileType::Binary));
+        assert_eq!(header.loading_address(), 0x4000);
+        assert_eq!(header.execution_address(), 0x4010);
+        assert_eq!(header.file_length(), 300);
+        assert_eq!(content, data);
+        assert_eq!(
+            state
+                .xattr(&filename, OsStr::new(XATTR_EXECUTION_ADDRESS))
+                .unwrap(),
+            b"0x4010"
+        );
+        assert_eq!(
+            state.xattr(&filename, OsStr::new(XATTR_TYPE)).unwrap(),
+            b"binary"
+        );
+
+        state
+            .remove_xattr(&filename, OsStr::new(XATTR_HEADER))
+            .unwrap();
+        let (header, content) = state.load(&filename).unwrap();
+        assert!(header.is_none());
+        assert_eq!(content, data);
+
+        // the header of an empty file is kept
+        state
+            .update(&filename, |_, content| {
+                content.clear();
+                Ok(())
+            })
+            .unwrap();
+        state
+            .set_xattr(&filename, OsStr::new(XATTR_TYPE), b"basic")
+            .unwrap();
+        let (header, content) = state.load(&filename).unwrap();
+        assert!(header.unwrap().file_type() == Ok(AmsdosFileType::Basic));
+        assert!(content.is_empty());
+    }
+
+    #[test]
+    fn rename_and_unlink() {
+        let mut state = empty_state();
+        let source = name(0, "a.txt");
+        let destination = name(2, "b.txt");
+
+        let handle = state.open(&source, true).unwrap();
+        state.write(handle, 0, b"first").unwrap();
+        state.release(handle).unwrap();
+
+        state.rename(&source, &destination).unwrap();
+        assert!(state.files(0).is_empty());
+        assert_eq!(state.files(2), vec![destination]);
+        assert_eq!(state.file(&destination).unwrap().1, b"first");
+
+        state.set_read_only(&destination, true).unwrap();
+        assert_eq!(state.unlink(&destination), Err(libc::EACCES));
+        state.set_read_only(&destination, false).unwrap();
+        state.unlink(&destination).unwrap();
+        assert!(state.files(2).is_empty());
+        assert_eq!(state.unlink(&destination), Err(libc::ENOENT));
+    }
+
+    #[test]
+    fn full_disc() {
+        let mut state = empty_state();
+        let filename = name(0, "big.txt");
+        let free = state.statfs().bfree;
+
+        let handle = state.open(&filename, true).unwrap();
+        state.write(handle, 0, &vec![b'A'; 200 * 1024]).unwrap();
+        assert_eq!(state.release(handle), Err(libc::ENOSPC));
+
+        assert!(state.files(0).is_empty());
+        assert_eq!(state.statfs().bfree, free);
+    }
+}
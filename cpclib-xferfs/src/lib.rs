@@ -0,0 +1,61 @@
+#[cfg(target_os = "linux")]
+pub mod dskfs;
+
+use cpclib_common::camino::Utf8PathBuf;
+use cpclib_common::clap::{Arg, ArgAction, ArgMatches, Command};
+use cpclib_common::utf8pathbuf_value_parser;
+
+pub mod built_info {
+    include!(concat!(env!("OUT_DIR"), "/built.rs"));
+}
+
+pub fn build_args_parser() -> Command {
+    Command::new("dskfs")
+        .author("Krusty/Benediction")
+        .version(built_info::PKG_VERSION)
+        .about("Mount the AMSDOS catalog of a DSK image. Each user area is a folder and AMSDOS headers are available as extended attributes. The image is saved when unmounted.")
+        .arg(
+            Arg::new("IMAGE")
+                .help("DSK image to mount")
+                .value_parser(|p: &str| utf8pathbuf_value_parser(true)(p))
+                .required(true)
+        )
+        .arg(
+            Arg::new("MOUNTPOINT")
+                .help("Existing folder where the image is mounted")
+                .value_parser(|p: &str| utf8pathbuf_value_parser(true)(p))
+                .required(true)
+        )
+        .arg(
+            Arg::new("READ_ONLY")
+                .help("Forbid any modification of the image")
+                .short('r')
+                .long("read-only")
+                .action(ArgAction::SetTrue)
+        )
+}
+
+/// Mount the image until the file system is unmounted
+#[cfg(target_os = "linux")]
+pub fn process(matches: &ArgMatches) -> Result<(), String> {
+    use std::ffi::OsStr;
+
+    let image = matches.get_one::<Utf8PathBuf>("IMAGE").unwrap();
+    let mountpoint = matches.get_one::<Utf8PathBuf>("MOUNTPOINT").unwrap();
+
+    let fs = dskfs::DskFs::open(image)?;
+
+    let fsname = format!("fsname={image}");
+    let mut options = vec![OsStr::new("-o"), OsStr::new(&fsname)];
+    if matches.get_flag("READ_ONLY") {
+        options.extend([OsStr::new("-o"), OsStr::new("ro")]);
+    }
+
+    fuse_mt::mount(fuse_mt::FuseMT::new(fs, 1), mountpoint, &options)
+        .map_err(|e| format!("Unable to mount {image} on {mountpoint}. {e}"))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn process(_matches: &ArgMatches) -> Result<(), String> {
+    Err("Mounting disc images is only available on Linux".to_owned())
+}
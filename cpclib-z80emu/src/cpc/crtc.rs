@@ -0,0 +1,369 @@
+//! CRTC 6845 as wired in a CPC. Types 0 (HD6845S/UM6845) and 1 (UM6845R) are handled.
+//!
+//! The CRTC is clocked once per character (i.e. once per nop). Only the behaviours needed to
+//! display standard and overscan screens and to generate the synchronisation signals are emulated.
+
+/// Flavours of CRTC we are able to emulate
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CrtcType {
+    #[default]
+    Zero,
+    One
+}
+
+impl CrtcType {
+    /// Build the type from its usual number. Unsupported types are handled as the closest supported one
+    pub fn from_number(number: u8) -> Self {
+        match number {
+            1 => Self::One,
+            _ => Self::Zero
+        }
+    }
+
+    pub fn number(&self) -> u8 {
+        match self {
+            Self::Zero => 0,
+            Self::One => 1
+        }
+    }
+}
+
+/// Values of the registers after the firmware initialisation
+pub const FIRMWARE_REGISTERS: [u8; 18] = [
+    63, 40, 46, 0x8E, 38, 0, 25, 30, 0, 7, 0, 0, 0x30, 0x00, 0xC0, 0x00, 0, 0
+];
+
+/// Number of bits kept by each register
+const REGISTER_MASKS: [u8; 18] = [
+    0xFF, 0xFF, 0xFF, 0xFF, 0x7F, 0x1F, 0x7F, 0x7F, 0xFF, 0x1F, 0x7F, 0x1F, 0x3F, 0xFF, 0x3F, 0xFF,
+    0x3F, 0xFF
+];
+
+/// Signals produced by the CRTC for a given character
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CrtcOutput {
+    /// Memory address (MA0-MA13)
+    pub ma: u16,
+    /// Raster address (RA0-RA4)
+    pub ra: u8,
+    /// Display enable
+    pub disp_en: bool,
+    pub hsync: bool,
+    pub vsync: bool
+}
+
+impl CrtcOutput {
+    /// Address of the first of the two bytes read by the gate array for this character
+    pub fn video_address(&self) -> u16 {
+        let ma = self.ma;
+        ((ma & 0x3000) << 2) | ((u16::from(self.ra) & 0x07) << 11) | ((ma & 0x03FF) << 1)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Crtc {
+    kind: CrtcType,
+    registers: [u8; 18],
+    selected: u8,
+
+    /// Horizontal character counter
+    hcc: u8,
+    /// Vertical character counter
+    vcc: u8,
+    /// Vertical line counter (raster address)
+    vlc: u8,
+    /// Number of lines already done in the vertical adjustment (None outside of it)
+    vertical_adjust: Option<u8>,
+
+    /// Remaining characters of the HSYNC
+    hsync_remaining: u8,
+    /// Remaining lines of the VSYNC
+    vsync_remaining: u8,
+
+    /// Memory address of the first character of the current character row
+    row_address: u16,
+
+    /// Cleared once the vertical displayed character rows (R6) have been done
+    vertical_display: bool
+}
+
+impl Default for Crtc {
+    fn default() -> Self {
+        Self::new(CrtcType::default())
+    }
+}
+
+#[allow(missing_docs)]
+impl Crtc {
+    pub fn new(kind: CrtcType) -> Self {
+        let mut crtc = Self {
+            kind,
+            registers: FIRMWARE_REGISTERS,
+            selected: 0,
+            hcc: 0,
+            vcc: 0,
+            vlc: 0,
+            vertical_adjust: None,
+            hsync_remaining: 0,
+            vsync_remaining: 0,
+            row_address: 0,
+            vertical_display: true
+        };
+        crtc.start_frame();
+        crtc
+    }
+
+    pub fn kind(&self) -> CrtcType {
+        self.kind
+    }
+
+    pub fn set_kind(&mut self, kind: CrtcType) {
+        self.kind = kind;
+    }
+
+    pub fn registers(&self) -> &[u8; 18] {
+        &self.registers
+    }
+
+    pub fn register(&self, idx: usize) -> u8 {
+        self.registers[idx]
+    }
+
+    pub fn selected_register(&self) -> u8 {
+        self.selected
+    }
+
+    pub fn select_register(&mut self, idx: u8) {
+        self.selected = idx & 0x1F;
+    }
+
+    /// Write the selected register. Writes to unknown or read-only registers are ignored
+    pub fn write_selected_register(&mut self, value: u8) {
+        self.set_register(self.selected as usize, value)
+    }
+
+    /// Direct access to a register (typically to restore a snapshot)
+    pub fn set_register(&mut self, idx: usize, value: u8) {
+        if idx < 16 {
+            self.registers[idx] = value & REGISTER_MASKS[idx];
+        }
+    }
+
+    /// Value obtained on the read port (&BFxx)
+    pub fn read_selected_register(&self) -> u8 {
+        let idx = self.selected as usize;
+        match (self.kind, idx) {
+            (CrtcType::Zero, 12..=17) | (CrtcType::One, 14..=17) => self.registers[idx],
+            _ => 0
+        }
+    }
+
+    /// Value obtained on the status port (&BExx). Only the type 1 has a status register
+    pub fn read_status(&self) -> u8 {
+        match self.kind {
+            CrtcType::Zero => 0xFF,
+            CrtcType::One => {
+                if self.vertical_display {
+                    0
+                }
+                else {
+                    0b0010_0000
+                }
+            },
+        }
+    }
+
+    pub fn hcc(&self) -> u8 {
+        self.hcc
+    }
+
+    pub fn vcc(&self) -> u8 {
+        self.vcc
+    }
+
+    pub fn vlc(&self) -> u8 {
+        self.vlc
+    }
+
+    pub fn is_in_hsync(&self) -> bool {
+        self.hsync_remaining > 0
+    }
+
+    pub fn is_in_vsync(&self) -> bool {
+        self.vsync_remaining > 0
+    }
+
+    fn display_start_address(&self) -> u16 {
+        (u16::from(self.registers[12]) << 8) | u16::from(self.registers[13])
+    }
+
+    fn hsync_width(&self) -> u8 {
+        self.registers[3] & 0x0F
+    }
+
+    fn vsync_width(&self) -> u8 {
+        match (self.kind, self.registers[3] >> 4) {
+            (CrtcType::One, _) | (CrtcType::Zero, 0) => 16,
+            (CrtcType::Zero, width) => width
+        }
+    }
+
+    fn start_frame(&mut self) {
+        self.vcc = 0;
+        self.vlc = 0;
+        self.vertical_adjust = None;
+        self.vertical_display = true;
+        self.row_address = self.display_start_address();
+    }
+
+    /// Signals of the current character
+    pub fn output(&self) -> CrtcOutput {
+        CrtcOutput {
+            ma: self.row_address.wrapping_add(u16::from(self.hcc)) & 0x3FFF,
+            ra: self.vlc,
+            disp_en: self.vertical_display
+                && self.hcc < self.registers[1]
+                && self.vcc < self.registers[6],
+            hsync: self.is_in_hsync(),
+            vsync: self.is_in_vsync()
+        }
+    }
+
+    /// Produce the signals of the current character and move to the next one
+    pub fn clock(&mut self) -> CrtcOutput {
+        // a width of 0 produces no HSYNC
+        if self.hsync_remaining == 0 && self.hcc == self.registers[2] {
+            self.hsync_remaining = self.hsync_width();
+        }
+
+        let output = self.output();
+
+        if self.hsync_remaining > 0 {
+            self.hsync_remaining -= 1;
+        }
+
+        if self.hcc == self.registers[0] {
+            self.hcc = 0;
+            self.end_of_line();
+        }
+        else {
+            self.hcc = self.hcc.wrapping_add(1);
+        }
+
+        output
+    }
+
+    fn end_of_line(&mut self) {
+        if self.vsync_remaining > 0 {
+            self.vsync_remaining -= 1;
+        }
+
+        match self.vertical_adjust {
+            Some(done) => {
+                if done + 1 >= self.registers[5] {
+                    self.start_frame();
+                }
+                else {
+                    self.vertical_adjust = Some(done + 1);
+                }
+            },
+            None => {
+                if self.vlc >= self.registers[9] {
+                    self.vlc = 0;
+                    self.row_address = self.row_address.wrapping_add(u16::from(self.registers[1]));
+                    if self.vcc >= self.registers[4] {
+                        if self.registers[5] > 0 {
+                            self.vertical_adjust = Some(0);
+                            self.vcc = self.vcc.wrapping_add(1) & 0x7F;
+                        }
+                        else {
+                            self.start_frame();
+                        }
+                    }
+                    else {
+                        self.vcc = self.vcc.wrapping_add(1) & 0x7F;
+                    }
+                }
+                else {
+                    self.vlc = (self.vlc + 1) & 0x1F;
+                }
+            },
+        }
+
+        // The type 1 reads R12/R13 on each line of the first character row
+        if self.kind == CrtcType::One && self.vcc == 0 && self.vertical_adjust.is_none() {
+            self.row_address = self.display_start_address();
+        }
+
+        if self.vcc == self.registers[6] {
+            self.vertical_display = false;
+        }
+
+        if self.vlc == 0
+            && self.vertical_adjust.is_none()
+            && self.vcc == self.registers[7]
+            && self.vsync_remaining == 0
+        {
+            self.vsync_remaining = self.vsync_width();
+        }
+    }
+
+    /// Restore the counters (typically from a snapshot)
+    pub fn set_counters(&mut self, hcc: u8, vcc: u8, vlc: u8) {
+        self.hcc = hcc;
+        self.vcc = vcc & 0x7F;
+        self.vlc = vlc & 0x1F;
+        self.vertical_display = self.vcc < self.registers[6];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Clock the CRTC until the beginning of the next VSYNC and returns the number of characters
+    fn characters_until_vsync(crtc: &mut Crtc) -> usize {
+        let mut count = 0;
+        loop {
+            let previous = crtc.clock().vsync;
+            count += 1;
+            if !previous && crtc.output().vsync {
+                return count;
+            }
+            assert!(count < 100_000);
+        }
+    }
+
+    #[test]
+    fn standard_frame_lasts_19968_nops() {
+        for kind in [CrtcType::Zero, CrtcType::One] {
+            let mut crtc = Crtc::new(kind);
+            characters_until_vsync(&mut crtc);
+            assert_eq!(characters_until_vsync(&mut crtc), 312 * 64, "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn hsync_and_display() {
+        let mut crtc = Crtc::default();
+        let outputs = (0..64).map(|_| crtc.clock()).collect::<Vec<_>>();
+        assert!(outputs[0].disp_en);
+        assert!(outputs[39].disp_en);
+        assert!(!outputs[40].disp_en);
+        assert!(!outputs[45].hsync);
+        assert!(outputs[46].hsync);
+        assert!(outputs[59].hsync);
+        assert!(!outputs[60].hsync);
+        assert_eq!(outputs[1].video_address(), 0xC002);
+    }
+
+    #[test]
+    fn register_access() {
+        let mut crtc = Crtc::new(CrtcType::Zero);
+        crtc.select_register(12);
+        crtc.write_selected_register(0xFF);
+        assert_eq!(crtc.read_selected_register(), 0x3F);
+        crtc.set_kind(CrtcType::One);
+        assert_eq!(crtc.read_selected_register(), 0);
+    }
+}
@@ -0,0 +1,337 @@
+//! Gate array: palette, screen mode, ROM configuration and interrupt generation.
+//! The RAM configuration is directly handled by [crate::Memory].
+
+/// Number of HSYNC between two interrupts
+pub const LINES_PER_INTERRUPT: u8 = 52;
+
+/// Number of HSYNC after the beginning of the VSYNC before the interrupt counter is resynchronised
+const HSYNC_BEFORE_VSYNC_RESYNC: u8 = 2;
+
+/// Hardware colours set by the firmware at reset
+const FIRMWARE_PALETTE: [u8; 17] = [
+    0x04, 0x0A, 0x13, 0x0C, 0x0B, 0x14, 0x15, 0x0D, 0x06, 0x1E, 0x1F, 0x07, 0x12, 0x19, 0x04, 0x17,
+    0x04
+];
+
+#[derive(Debug, Clone)]
+pub struct GateArray {
+    /// Selected pen (16 is the border)
+    pen: u8,
+    /// Hardware colour (0-31) of each pen and of the border
+    palette: [u8; 17],
+    /// Mode requested by the last write of the mode register
+    requested_mode: u8,
+    /// Mode used to decode the bytes. It is updated at the beginning of each HSYNC
+    mode: u8,
+    lower_rom_enabled: bool,
+    upper_rom_enabled: bool,
+
+    /// Number of HSYNC since the last interrupt
+    interrupt_counter: u8,
+    interrupt_pending: bool,
+    /// Number of HSYNC seen since the beginning of the VSYNC
+    hsync_since_vsync: Option<u8>,
+
+    previous_hsync: bool,
+    previous_vsync: bool
+}
+
+/// State of the gate array once the firmware has initialised the screen
+impl Default for GateArray {
+    fn default() -> Self {
+        Self {
+            pen: 0,
+            palette: FIRMWARE_PALETTE,
+            requested_mode: 1,
+            mode: 1,
+            lower_rom_enabled: true,
+            upper_rom_enabled: true,
+            interrupt_counter: 0,
+            interrupt_pending: false,
+            hsync_since_vsync: None,
+            previous_hsync: false,
+            previous_vsync: false
+        }
+    }
+}
+
+/// Events produced by the synchronisation signals
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SyncEvents {
+    pub hsync_start: bool,
+    pub vsync_start: bool
+}
+
+#[allow(missing_docs)]
+impl GateArray {
+    pub fn pen(&self) -> u8 {
+        self.pen
+    }
+
+    pub fn palette(&self) -> &[u8; 17] {
+        &self.palette
+    }
+
+    pub fn mode(&self) -> u8 {
+        self.mode
+    }
+
+    pub fn requested_mode(&self) -> u8 {
+        self.requested_mode
+    }
+
+    pub fn border(&self) -> u8 {
+        self.palette[16]
+    }
+
+    pub fn is_lower_rom_enabled(&self) -> bool {
+        self.lower_rom_enabled
+    }
+
+    pub fn is_upper_rom_enabled(&self) -> bool {
+        self.upper_rom_enabled
+    }
+
+    pub fn interrupt_counter(&self) -> u8 {
+        self.interrupt_counter
+    }
+
+    pub fn is_interrupt_pending(&self) -> bool {
+        self.interrupt_pending
+    }
+
+    /// Handle a byte sent to the gate array. The RAM configuration (`0b11xxxxxx`) is ignored here
+    pub fn write(&mut self, value: u8) {
+        match value >> 6 {
+            0b00 => {
+                self.pen = if value & 0b1_0000 != 0 {
+                    16
+                }
+                else {
+                    value & 0b1111
+                };
+            },
+            0b01 => {
+                self.palette[self.pen as usize] = value & 0b1_1111;
+            },
+            0b10 => {
+                self.set_multi_configuration(value);
+                if value & 0b1_0000 != 0 {
+                    self.interrupt_counter = 0;
+                    self.interrupt_pending = false;
+                }
+            },
+            _ => {}
+        }
+    }
+
+    /// Set mode and ROM configuration as stored in a snapshot
+    pub fn set_multi_configuration(&mut self, value: u8) {
+        self.requested_mode = value & 0b11;
+        self.lower_rom_enabled = value & 0b0100 == 0;
+        self.upper_rom_enabled = value & 0b1000 == 0;
+    }
+
+    /// Mode and ROM configuration as stored in a snapshot
+    pub fn multi_configuration(&self) -> u8 {
+        0b1000_0000
+            | self.requested_mode
+            | if self.lower_rom_enabled { 0 } else { 0b0100 }
+            | if self.upper_rom_enabled { 0 } else { 0b1000 }
+    }
+
+    pub fn select_pen(&mut self, pen: u8) {
+        self.pen = pen.min(16);
+    }
+
+    pub fn set_ink(&mut self, pen: usize, hardware_colour: u8) {
+        self.palette[pen] = hardware_colour & 0b1_1111;
+    }
+
+    /// Force the mode immediately (typically when restoring a snapshot)
+    pub fn set_mode(&mut self, mode: u8) {
+        self.requested_mode = mode & 0b11;
+        self.mode = self.requested_mode;
+    }
+
+    pub fn set_interrupt_counter(&mut self, counter: u8, pending: bool) {
+        self.interrupt_counter = counter % LINES_PER_INTERRUPT;
+        self.interrupt_pending = pending;
+    }
+
+    /// The Z80 has accepted the interrupt
+    pub fn acknowledge_interrupt(&mut self) {
+        self.interrupt_pending = false;
+        self.interrupt_counter &= 0b01_1111;
+    }
+
+    /// Follow the synchronisation signals of the CRTC to apply the mode and generate the interrupts
+    pub fn update_sync(&mut self, hsync: bool, vsync: bool) -> SyncEvents {
+        let events = SyncEvents {
+            hsync_start: hsync && !self.previous_hsync,
+            vsync_start: vsync && !self.previous_vsync
+        };
+        let hsync_end = !hsync && self.previous_hsync;
+        self.previous_hsync = hsync;
+        self.previous_vsync = vsync;
+
+        if events.vsync_start {
+            self.hsync_since_vsync = Some(0);
+        }
+
+        if events.hsync_start {
+            self.mode = self.requested_mode;
+        }
+
+        if hsync_end {
+            self.interrupt_counter += 1;
+            if self.interrupt_counter == LINES_PER_INTERRUPT {
+                self.interrupt_counter = 0;
+                self.interrupt_pending = true;
+            }
+
+            if let Some(count) = self.hsync_since_vsync.as_mut() {
+                *count += 1;
+                if *count == HSYNC_BEFORE_VSYNC_RESYNC {
+                    if self.interrupt_counter >= 32 {
+                        self.interrupt_pending = true;
+                    }
+                    self.interrupt_counter = 0;
+                    self.hsync_since_vsync = None;
+                }
+            }
+        }
+
+        events
+    }
+}
+
+/// Pens of the pixels encoded in a byte for the given mode. There are 2, 4 or 8 pixels
+pub fn byte_to_pens(byte: u8, mode: u8) -> impl Iterator<Item = u8> {
+    let bit = move |idx: u8| (byte >> idx) & 1;
+    let (count, pens): (usize, [u8; 8]) = match mode & 0b11 {
+        0 => {
+            (
+                2,
+                [
+                    bit(7) | (bit(3) << 1) | (bit(5) << 2) | (bit(1) << 3),
+                    bit(6) | (bit(2) << 1) | (bit(4) << 2) | (bit(0) << 3),
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0
+                ]
+            )
+        },
+        1 => {
+            (
+                4,
+                [
+                    bit(7) | (bit(3) << 1),
+                    bit(6) | (bit(2) << 1),
+                    bit(5) | (bit(1) << 1),
+                    bit(4) | (bit(0) << 1),
+                    0,
+                    0,
+                    0,
+                    0
+                ]
+            )
+        },
+        2 => {
+            (
+                8,
+                [
+                    bit(7),
+                    bit(6),
+                    bit(5),
+                    bit(4),
+                    bit(3),
+                    bit(2),
+                    bit(1),
+                    bit(0)
+                ]
+            )
+        },
+        _ => {
+            (
+                2,
+                [
+                    bit(7) | (bit(3) << 1),
+                    bit(6) | (bit(2) << 1),
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0
+                ]
+            )
+        },
+    };
+    pens.into_iter().take(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Simulate a line of 64 characters with a 14 characters long HSYNC
+    fn line(ga: &mut GateArray, vsync: bool) {
+        for hcc in 0..64 {
+            ga.update_sync((46..60).contains(&hcc), vsync);
+        }
+    }
+
+    #[test]
+    fn interrupt_every_52_lines() {
+        let mut ga = GateArray::default();
+        for _ in 0..51 {
+            line(&mut ga, false);
+            assert!(!ga.is_interrupt_pending());
+        }
+        line(&mut ga, false);
+        assert!(ga.is_interrupt_pending());
+        ga.acknowledge_interrupt();
+        assert!(!ga.is_interrupt_pending());
+    }
+
+    #[test]
+    fn vsync_resynchronisation() {
+        let mut ga = GateArray::default();
+        for _ in 0..40 {
+            line(&mut ga, false);
+        }
+        line(&mut ga, true);
+        line(&mut ga, true);
+        // counter was >= 32: interrupt and reset
+        assert!(ga.is_interrupt_pending());
+        assert_eq!(ga.interrupt_counter(), 0);
+    }
+
+    #[test]
+    fn pens_decoding() {
+        assert_eq!(byte_to_pens(0b1000_0000, 0).collect::<Vec<_>>(), vec![1, 0]);
+        assert_eq!(byte_to_pens(0b0000_0001, 0).collect::<Vec<_>>(), vec![0, 8]);
+        assert_eq!(
+            byte_to_pens(0b1000_1000, 1).collect::<Vec<_>>(),
+            vec![3, 0, 0, 0]
+        );
+        assert_eq!(
+            byte_to_pens(0b0100_0000, 2).collect::<Vec<_>>(),
+            vec![0, 1, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn multi_configuration() {
+        let mut ga = GateArray::default();
+        ga.write(0b1000_1010);
+        assert_eq!(ga.requested_mode(), 2);
+        assert!(ga.is_lower_rom_enabled());
+        assert!(!ga.is_upper_rom_enabled());
+        assert_eq!(ga.multi_configuration(), 0b1000_1010);
+    }
+}
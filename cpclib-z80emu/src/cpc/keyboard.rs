@@ -0,0 +1,275 @@
+//! Keyboard matrix of the CPC (English layout).
+//! A pressed key clears its bit in the line read through the PSG.
+
+/// Number of lines of the matrix (the last one is shared with the joystick)
+pub const NB_LINES: usize = 10;
+
+/// Position of a key in the matrix
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct KeyPosition {
+    pub line: u8,
+    pub bit: u8
+}
+
+#[allow(missing_docs)]
+impl KeyPosition {
+    pub const ALT_ENTER: Self = Self::new(0, 6);
+    pub const CAPS_LOCK: Self = Self::new(8, 6);
+    pub const CLR: Self = Self::new(2, 0);
+    pub const CONTROL: Self = Self::new(2, 7);
+    pub const COPY: Self = Self::new(1, 1);
+    pub const CURSOR_DOWN: Self = Self::new(0, 2);
+    pub const CURSOR_LEFT: Self = Self::new(1, 0);
+    pub const CURSOR_RIGHT: Self = Self::new(0, 1);
+    pub const CURSOR_UP: Self = Self::new(0, 0);
+    pub const DEL: Self = Self::new(9, 7);
+    pub const ENTER: Self = Self::ALT_ENTER;
+    pub const ESC: Self = Self::new(8, 2);
+    pub const JOYSTICK_DOWN: Self = Self::new(9, 1);
+    pub const JOYSTICK_FIRE1: Self = Self::new(9, 5);
+    pub const JOYSTICK_FIRE2: Self = Self::new(9, 4);
+    pub const JOYSTICK_LEFT: Self = Self::new(9, 2);
+    pub const JOYSTICK_RIGHT: Self = Self::new(9, 3);
+    pub const JOYSTICK_UP: Self = Self::new(9, 0);
+    pub const RETURN: Self = Self::new(2, 2);
+    pub const SHIFT: Self = Self::new(2, 5);
+    pub const SPACE: Self = Self::new(5, 7);
+    pub const TAB: Self = Self::new(8, 4);
+
+    pub const fn new(line: u8, bit: u8) -> Self {
+        Self { line, bit }
+    }
+
+    /// Key of the numeric keypad (f0 to f9 and f.)
+    pub fn function(number: u8) -> Option<Self> {
+        let (line, bit) = match number {
+            0 => (1, 7),
+            1 => (1, 5),
+            2 => (1, 6),
+            3 => (0, 5),
+            4 => (2, 4),
+            5 => (1, 4),
+            6 => (0, 4),
+            7 => (1, 2),
+            8 => (1, 3),
+            9 => (0, 3),
+            _ => return None
+        };
+        Some(Self::new(line, bit))
+    }
+
+    /// Key `f.` of the numeric keypad
+    pub fn function_dot() -> Self {
+        Self::new(0, 7)
+    }
+
+    /// Key to press to type the character. The boolean is true when SHIFT must be pressed too.
+    /// Letters are typed without SHIFT whatever is their case
+    pub fn from_char(c: char) -> Option<(Self, bool)> {
+        // (line, unshifted, shifted) in the order of the bits
+        const LAYOUT: [(u8, [(char, char); 8]); 7] = [
+            (
+                3,
+                [
+                    ('^', '£'),
+                    ('-', '='),
+                    ('@', '|'),
+                    ('p', 'P'),
+                    (';', '+'),
+                    (':', '*'),
+                    ('/', '?'),
+                    ('.', '>')
+                ]
+            ),
+            (
+                4,
+                [
+                    ('0', '_'),
+                    ('9', ')'),
+                    ('o', 'O'),
+                    ('i', 'I'),
+                    ('l', 'L'),
+                    ('k', 'K'),
+                    ('m', 'M'),
+                    (',', '<')
+                ]
+            ),
+            (
+                5,
+                [
+                    ('8', '('),
+                    ('7', '\''),
+                    ('u', 'U'),
+                    ('y', 'Y'),
+                    ('h', 'H'),
+                    ('j', 'J'),
+                    ('n', 'N'),
+                    (' ', ' ')
+                ]
+            ),
+            (
+                6,
+                [
+                    ('6', '&'),
+                    ('5', '%'),
+                    ('r', 'R'),
+                    ('t', 'T'),
+                    ('g', 'G'),
+                    ('f', 'F'),
+                    ('b', 'B'),
+                    ('v', 'V')
+                ]
+            ),
+            (
+                7,
+                [
+                    ('4', '$'),
+                    ('3', '#'),
+                    ('e', 'E'),
+                    ('w', 'W'),
+                    ('s', 'S'),
+                    ('d', 'D'),
+                    ('c', 'C'),
+                    ('x', 'X')
+                ]
+            ),
+            (
+                8,
+                [
+                    ('1', '!'),
+                    ('2', '"'),
+                    ('\u{1B}', '\u{1B}'),
+                    ('q', 'Q'),
+                    ('\t', '\t'),
+                    ('a', 'A'),
+                    ('\0', '\0'),
+                    ('z', 'Z')
+                ]
+            ),
+            (
+                2,
+                [
+                    ('\0', '\0'),
+                    ('[', '{'),
+                    ('\n', '\n'),
+                    (']', '}'),
+                    ('\0', '\0'),
+                    ('\0', '\0'),
+                    ('\\', '`'),
+                    ('\0', '\0')
+                ]
+            )
+        ];
+
+        if c == '\0' {
+            return None;
+        }
+        if c == '\r' {
+            return Some((Self::RETURN, false));
+        }
+
+        for (line, keys) in LAYOUT.iter() {
+            for (bit, (normal, shifted)) in keys.iter().enumerate() {
+                let position = Self::new(*line, bit as u8);
+                if c == *normal {
+                    return Some((position, false));
+                }
+                if c == *shifted {
+                    return Some((position, !c.is_ascii_alphabetic()));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// State of the whole matrix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keyboard {
+    lines: [u8; NB_LINES]
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Self {
+            lines: [0xFF; NB_LINES]
+        }
+    }
+}
+
+#[allow(missing_docs)]
+impl Keyboard {
+    pub fn press(&mut self, key: KeyPosition) {
+        if let Some(line) = self.lines.get_mut(key.line as usize) {
+            *line &= !(1 << key.bit);
+        }
+    }
+
+    pub fn release(&mut self, key: KeyPosition) {
+        if let Some(line) = self.lines.get_mut(key.line as usize) {
+            *line |= 1 << key.bit;
+        }
+    }
+
+    pub fn is_pressed(&self, key: KeyPosition) -> bool {
+        self.line(key.line) & (1 << key.bit) == 0
+    }
+
+    pub fn release_all(&mut self) {
+        self.lines = [0xFF; NB_LINES];
+    }
+
+    /// Value read for the given line. Lines outside of the matrix have no key pressed
+    pub fn line(&self, line: u8) -> u8 {
+        self.lines.get(line as usize).copied().unwrap_or(0xFF)
+    }
+
+    pub fn lines(&self) -> &[u8; NB_LINES] {
+        &self.lines
+    }
+
+    /// Replace the whole matrix (0 bits are pressed keys)
+    pub fn set_lines(&mut self, lines: [u8; NB_LINES]) {
+        self.lines = lines;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn characters() {
+        assert_eq!(
+            KeyPosition::from_char('a'),
+            Some((KeyPosition::new(8, 5), false))
+        );
+        assert_eq!(
+            KeyPosition::from_char('A'),
+            Some((KeyPosition::new(8, 5), false))
+        );
+        assert_eq!(
+            KeyPosition::from_char('"'),
+            Some((KeyPosition::new(8, 1), true))
+        );
+        assert_eq!(
+            KeyPosition::from_char(' '),
+            Some((KeyPosition::SPACE, false))
+        );
+        assert_eq!(
+            KeyPosition::from_char('\n'),
+            Some((KeyPosition::RETURN, false))
+        );
+        assert_eq!(KeyPosition::from_char('é'), None);
+    }
+
+    #[test]
+    fn matrix() {
+        let mut keyboard = Keyboard::default();
+        keyboard.press(KeyPosition::SHIFT);
+        assert_eq!(keyboard.line(2), 0b1101_1111);
+        assert!(keyboard.is_pressed(KeyPosition::SHIFT));
+        keyboard.release(KeyPosition::SHIFT);
+        assert_eq!(keyboard.line(2), 0xFF);
+    }
+}
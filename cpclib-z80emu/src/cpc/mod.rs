@@ -0,0 +1,573 @@
+//! Headless emulation of a whole CPC: Z80, gate array, CRTC, PPI, PSG and keyboard.
+//!
+//! The emulation is done at the nop level which is enough to run most programs and to obtain a picture of the screen.
+//! Nothing is displayed nor played: the screen is available as a framebuffer of hardware colours.
+
+pub mod crtc;
+pub mod gate_array;
+pub mod keyboard;
+pub mod ppi;
+pub mod psg;
+pub mod screen;
+
+use cpclib_sna::{Snapshot, SnapshotFlag, SnapshotVersion};
+
+use self::crtc::{Crtc, CrtcType};
+use self::gate_array::GateArray;
+use self::keyboard::{KeyPosition, Keyboard};
+use self::ppi::{Ppi, PsgFunction};
+use self::psg::Psg;
+use self::screen::Screen;
+use crate::io::Ports;
+use crate::memory::DEFAULT_MMR;
+use crate::snapshot::{flag, set_flag};
+use crate::z80::{HasValue, Z80};
+
+/// Duration of a standard frame in nops
+pub const NOPS_PER_FRAME: usize = 312 * 64;
+
+/// Number of frames a key is kept pressed (and then released) by [Cpc::type_text]
+const FRAMES_PER_KEY_EVENT: usize = 2;
+
+/// Peripherals reachable through the I/O ports
+#[derive(Default, Debug, Clone)]
+struct Hardware {
+    gate_array: GateArray,
+    crtc: Crtc,
+    ppi: Ppi,
+    psg: Psg,
+    keyboard: Keyboard,
+    upper_rom: u8
+}
+
+impl Hardware {
+    /// Apply the PSG function currently selected by the PPI
+    fn update_psg(&mut self) {
+        match self.ppi.psg_function() {
+            PsgFunction::SelectRegister => self.psg.select_register(self.ppi.port_a()),
+            PsgFunction::Write => self.psg.write_selected_register(self.ppi.port_a()),
+            PsgFunction::Read | PsgFunction::Inactive => {}
+        }
+    }
+
+    fn read_ppi_port_a(&self) -> u8 {
+        if self.ppi.is_port_a_input() && self.ppi.psg_function() == PsgFunction::Read {
+            let line = self.keyboard.line(self.ppi.keyboard_line());
+            self.psg.read_selected_register(line)
+        }
+        else {
+            self.ppi.port_a()
+        }
+    }
+}
+
+impl Ports for Hardware {
+    fn port_in(&mut self, port: u16) -> u8 {
+        let function = (port >> 8) & 0b11;
+        let mut value = 0xFF;
+
+        if port & 0x4000 == 0 {
+            value &= match function {
+                2 => self.crtc.read_status(),
+                3 => self.crtc.read_selected_register(),
+                _ => 0xFF
+            };
+        }
+
+        if port & 0x0800 == 0 {
+            value &= match function {
+                0 => self.read_ppi_port_a(),
+                1 => self.ppi.port_b(self.crtc.is_in_vsync()),
+                2 => self.ppi.port_c(),
+                _ => 0xFF
+            };
+        }
+
+        value
+    }
+
+    fn port_out(&mut self, port: u16, value: u8) {
+        let function = (port >> 8) & 0b11;
+
+        if port & 0xC000 == 0x4000 {
+            self.gate_array.write(value);
+        }
+
+        if port & 0x4000 == 0 {
+            match function {
+                0 => self.crtc.select_register(value),
+                1 => self.crtc.write_selected_register(value),
+                _ => {}
+            }
+        }
+
+        if port & 0x2000 == 0 {
+            self.upper_rom = value;
+        }
+
+        if port & 0x0800 == 0 {
+            match function {
+                0 => self.ppi.set_port_a(value),
+                2 => self.ppi.set_port_c(value),
+                3 => self.ppi.write_control(value),
+                _ => return
+            }
+            self.update_psg();
+        }
+    }
+}
+
+/// A CPC without any display. ROMs have to be provided to be able to boot;
+/// they are useless when running a snapshot that does not call the firmware.
+#[derive(Debug, Clone)]
+pub struct Cpc {
+    z80: Z80,
+    hardware: Hardware,
+    screen: Screen,
+    /// Number of nops elapsed since the creation of the machine
+    nops: u64
+}
+
+impl Default for Cpc {
+    fn default() -> Self {
+        Self::new(CrtcType::default())
+    }
+}
+
+#[allow(missing_docs)]
+impl Cpc {
+    pub fn new(crtc: CrtcType) -> Self {
+        let mut cpc = Self {
+            z80: Z80::default(),
+            hardware: Hardware::default(),
+            screen: Screen::default(),
+            nops: 0
+        };
+        cpc.hardware.crtc.set_kind(crtc);
+        cpc.sync_memory_configuration();
+        cpc
+    }
+
+    /// Build a CPC in the state stored in the snapshot
+    pub fn from_snapshot(sna: &Snapshot) -> Self {
+        let mut cpc = Self::default();
+        cpc.load_snapshot(sna);
+        cpc
+    }
+
+    pub fn z80(&self) -> &Z80 {
+        &self.z80
+    }
+
+    pub fn z80_mut(&mut self) -> &mut Z80 {
+        &mut self.z80
+    }
+
+    pub fn gate_array(&self) -> &GateArray {
+        &self.hardware.gate_array
+    }
+
+    pub fn crtc(&self) -> &Crtc {
+        &self.hardware.crtc
+    }
+
+    pub fn crtc_mut(&mut self) -> &mut Crtc {
+        &mut self.hardware.crtc
+    }
+
+    pub fn ppi(&self) -> &Ppi {
+        &self.hardware.ppi
+    }
+
+    pub fn psg(&self) -> &Psg {
+        &self.hardware.psg
+    }
+
+    pub fn keyboard(&self) -> &Keyboard {
+        &self.hardware.keyboard
+    }
+
+    pub fn keyboard_mut(&mut self) -> &mut Keyboard {
+        &mut self.hardware.keyboard
+    }
+
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    /// Number of nops elapsed since the creation of the machine
+    pub fn nops(&self) -> u64 {
+        self.nops
+    }
+
+    /// Plug the firmware
+    pub fn set_lower_rom(&mut self, rom: &[u8]) {
+        self.z80.memory_mut().set_lower_rom(rom);
+    }
+
+    /// Plug a ROM in an upper slot (BASIC is in slot 0, AMSDOS in slot 7)
+    pub fn set_upper_rom(&mut self, slot: u8, rom: &[u8]) {
+        self.z80.memory_mut().set_upper_rom(slot, rom);
+    }
+
+    /// Press the reset button: the RAM is kept, everything else is reinitialised and the Z80 starts in 0
+    pub fn reset(&mut self) {
+        let memory = std::mem::take(self.z80.memory_mut());
+        self.z80 = Z80::default();
+        *self.z80.memory_mut() = memory;
+        self.z80.memory_mut().set_mmr(DEFAULT_MMR);
+
+        let crtc = self.hardware.crtc.kind();
+        self.hardware = Hardware::default();
+        self.hardware.crtc.set_kind(crtc);
+        self.sync_memory_configuration();
+    }
+
+    /// The ROM configuration is selected by the gate array and the upper ROM port,
+    /// but only the memory knows what is visible by the Z80
+    fn sync_memory_configuration(&mut self) {
+        let lower = self.hardware.gate_array.is_lower_rom_enabled();
+        let upper = self.hardware.gate_array.is_upper_rom_enabled();
+        let memory = self.z80.memory_mut();
+        memory.set_rom_configuration(lower, upper);
+        memory.select_upper_rom(self.hardware.upper_rom);
+    }
+
+    /// Move the CRTC and the gate array forward
+    fn clock(&mut self, nops: usize) {
+        for _ in 0..nops {
+            let output = self.hardware.crtc.clock();
+            let events = self
+                .hardware
+                .gate_array
+                .update_sync(output.hsync, output.vsync);
+            self.screen.draw(
+                &output,
+                events,
+                &self.hardware.gate_array,
+                self.z80.memory()
+            );
+        }
+        self.nops += nops as u64;
+    }
+
+    /// Execute one instruction (and the interrupt it may trigger). Returns the number of nops elapsed
+    pub fn step(&mut self) -> usize {
+        let mut nops = self.z80.step_with_ports(&mut self.hardware);
+        self.sync_memory_configuration();
+        self.clock(nops);
+
+        if self.hardware.gate_array.is_interrupt_pending() {
+            let duration = self.z80.interrupt();
+            if duration > 0 {
+                self.hardware.gate_array.acknowledge_interrupt();
+                self.clock(duration);
+                nops += duration;
+            }
+        }
+
+        nops
+    }
+
+    /// Run at least the given number of nops. Returns the number of nops really elapsed
+    pub fn run_nops(&mut self, nops: usize) -> usize {
+        let mut elapsed = 0;
+        while elapsed < nops {
+            elapsed += self.step();
+        }
+        elapsed
+    }
+
+    /// Run until the beginning of the next VSYNC.
+    /// When the CRTC is programmed to never produce it, the run stops after two standard frames.
+    /// Returns the number of nops elapsed
+    pub fn run_until_vsync(&mut self) -> usize {
+        let frame = self.screen.frames();
+        let mut elapsed = 0;
+        while self.screen.frames() == frame && elapsed < 2 * NOPS_PER_FRAME {
+            elapsed += self.step();
+        }
+        elapsed
+    }
+
+    /// Run the given number of frames. Returns the number of nops elapsed
+    pub fn run_frames(&mut self, count: usize) -> usize {
+        (0..count).map(|_| self.run_until_vsync()).sum()
+    }
+
+    /// Type the text on the keyboard. Each character is pressed and released during few frames.
+    /// Fails with the first character that cannot be typed with an English keyboard
+    pub fn type_text(&mut self, text: &str) -> Result<(), String> {
+        for c in text.chars() {
+            let (key, shift) = KeyPosition::from_char(c)
+                .ok_or_else(|| format!("Unable to type {c:?} on a CPC keyboard"))?;
+            self.type_keys(&[key], shift);
+        }
+        Ok(())
+    }
+
+    /// Press simultaneously the keys (and SHIFT if required), wait, and release them
+    pub fn type_keys(&mut self, keys: &[KeyPosition], shift: bool) {
+        let keyboard = self.keyboard_mut();
+        if shift {
+            keyboard.press(KeyPosition::SHIFT);
+        }
+        for key in keys {
+            keyboard.press(*key);
+        }
+        self.run_frames(FRAMES_PER_KEY_EVENT);
+
+        let keyboard = self.keyboard_mut();
+        for key in keys {
+            keyboard.release(*key);
+        }
+        if shift {
+            keyboard.release(KeyPosition::SHIFT);
+        }
+        self.run_frames(FRAMES_PER_KEY_EVENT);
+    }
+
+    /// Replace the whole state of the machine by the one of the snapshot. The ROMs are kept
+    pub fn load_snapshot(&mut self, sna: &Snapshot) {
+        self.z80.load_snapshot(sna);
+
+        let hardware = &mut self.hardware;
+        for pen in 0..17 {
+            let colour = flag(sna, SnapshotFlag::GA_PAL(Some(pen))) as u8;
+            hardware.gate_array.set_ink(pen, colour);
+        }
+        hardware
+            .gate_array
+            .select_pen(flag(sna, SnapshotFlag::GA_PEN) as u8);
+        let multi_configuration = flag(sna, SnapshotFlag::GA_ROMCFG) as u8;
+        hardware
+            .gate_array
+            .set_multi_configuration(multi_configuration);
+        hardware.gate_array.set_mode(multi_configuration);
+
+        for idx in 0..18 {
+            let value = flag(sna, SnapshotFlag::CRTC_REG(Some(idx))) as u8;
+            hardware.crtc.set_register(idx, value);
+        }
+        hardware
+            .crtc
+            .select_register(flag(sna, SnapshotFlag::CRTC_SEL) as u8);
+        hardware.upper_rom = flag(sna, SnapshotFlag::ROM_UP) as u8;
+
+        hardware
+            .ppi
+            .write_control(flag(sna, SnapshotFlag::PPI_CTL) as u8);
+        hardware
+            .ppi
+            .set_port_a(flag(sna, SnapshotFlag::PPI_A) as u8);
+        hardware
+            .ppi
+            .set_port_c(flag(sna, SnapshotFlag::PPI_C) as u8);
+
+        for idx in 0..16 {
+            let value = flag(sna, SnapshotFlag::PSG_REG(Some(idx))) as u8;
+            hardware.psg.set_register(idx, value);
+        }
+        hardware
+            .psg
+            .select_register(flag(sna, SnapshotFlag::PSG_SEL) as u8);
+
+        if sna.version() == SnapshotVersion::V3 {
+            hardware.crtc.set_kind(CrtcType::from_number(
+                flag(sna, SnapshotFlag::CRTC_TYPE) as u8
+            ));
+            hardware.crtc.set_counters(
+                flag(sna, SnapshotFlag::CRTC_HCC) as u8,
+                flag(sna, SnapshotFlag::CRTC_CLC) as u8,
+                flag(sna, SnapshotFlag::CRTC_RLC) as u8
+            );
+            hardware.gate_array.set_interrupt_counter(
+                flag(sna, SnapshotFlag::GA_ISC) as u8,
+                flag(sna, SnapshotFlag::INT_REQ) != 0
+            );
+        }
+
+        self.sync_memory_configuration();
+    }
+
+    /// Store the state of the machine in the snapshot
+    pub fn update_snapshot(&self, sna: &mut Snapshot) {
+        self.z80.update_snapshot(sna);
+
+        let hardware = &self.hardware;
+        for (pen, colour) in hardware.gate_array.palette().iter().enumerate() {
+            set_flag(sna, SnapshotFlag::GA_PAL(Some(pen)), (*colour).into());
+        }
+        set_flag(sna, SnapshotFlag::GA_PEN, hardware.gate_array.pen().into());
+        set_flag(
+            sna,
+            SnapshotFlag::GA_ROMCFG,
+            hardware.gate_array.multi_configuration().into()
+        );
+
+        for (idx, value) in hardware.crtc.registers().iter().enumerate() {
+            set_flag(sna, SnapshotFlag::CRTC_REG(Some(idx)), (*value).into());
+        }
+        set_flag(
+            sna,
+            SnapshotFlag::CRTC_SEL,
+            hardware.crtc.selected_register().into()
+        );
+        set_flag(sna, SnapshotFlag::ROM_UP, hardware.upper_rom.into());
+
+        set_flag(sna, SnapshotFlag::PPI_A, hardware.read_ppi_port_a().into());
+        set_flag(
+            sna,
+            SnapshotFlag::PPI_B,
+            hardware.ppi.port_b(hardware.crtc.is_in_vsync()).into()
+        );
+        set_flag(sna, SnapshotFlag::PPI_C, hardware.ppi.port_c().into());
+        set_flag(sna, SnapshotFlag::PPI_CTL, hardware.ppi.control().into());
+
+        for (idx, value) in hardware.psg.registers().iter().enumerate() {
+            set_flag(sna, SnapshotFlag::PSG_REG(Some(idx)), (*value).into());
+        }
+        set_flag(
+            sna,
+            SnapshotFlag::PSG_SEL,
+            hardware.psg.selected_register().into()
+        );
+
+        if sna.version() == SnapshotVersion::V3 {
+            set_flag(
+                sna,
+                SnapshotFlag::CRTC_TYPE,
+                hardware.crtc.kind().number().into()
+            );
+            set_flag(sna, SnapshotFlag::CRTC_HCC, hardware.crtc.hcc().into());
+            set_flag(sna, SnapshotFlag::CRTC_CLC, hardware.crtc.vcc().into());
+            set_flag(sna, SnapshotFlag::CRTC_RLC, hardware.crtc.vlc().into());
+            set_flag(
+                sna,
+                SnapshotFlag::GA_ISC,
+                hardware.gate_array.interrupt_counter().into()
+            );
+            set_flag(
+                sna,
+                SnapshotFlag::INT_REQ,
+                hardware.gate_array.is_interrupt_pending().into()
+            );
+        }
+    }
+
+    /// Value of the program counter (handy to check where a program is stuck)
+    pub fn pc(&self) -> u16 {
+        self.z80.pc().value()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cpclib_asm::assemble;
+
+    use super::*;
+
+    fn cpc_with_code(code: &str) -> Cpc {
+        let bytes = assemble(code).unwrap();
+        let mut cpc = Cpc::default();
+        cpc.z80_mut().memory_mut().load_bytes(0x4000, &bytes);
+        cpc.z80_mut().pc_mut().set(0x4000);
+        cpc.z80_mut().sp_mut().set(0x3FFE);
+        cpc
+    }
+
+    #[test]
+    fn frames_last_19968_nops() {
+        let mut cpc = cpc_with_code(" org 0x4000\n jr $");
+        cpc.run_until_vsync();
+        assert!(cpc.run_until_vsync().abs_diff(NOPS_PER_FRAME) < 4);
+        assert_eq!(cpc.screen().frames(), 2);
+    }
+
+    #[test]
+    fn interrupts_are_raised_6_times_per_frame() {
+        // count the interrupts in 0x8000
+        let mut cpc = cpc_with_code(
+            " org 0x4000
+            im 1
+            ei
+            jr $"
+        );
+        let handler = assemble(
+            " org 0x38
+            push hl
+            ld hl, (0x8000) : inc hl : ld (0x8000), hl
+            pop hl
+            ei
+            ret"
+        )
+        .unwrap();
+        cpc.z80_mut().memory_mut().load_bytes(0x38, &handler);
+        cpc.run_frames(1);
+        cpc.z80_mut().memory_mut().write_word(0x8000, 0);
+        cpc.run_frames(10);
+        let count = cpc.z80().memory().read_word(0x8000);
+        assert!((59..=61).contains(&count), "{count}");
+    }
+
+    #[test]
+    fn keyboard_is_read_through_the_ppi_and_the_psg() {
+        // Read the line 8 and store it in 0x8000
+        let mut cpc = cpc_with_code(
+            " org 0x4000
+            ld bc, 0xF40E : out (c), c
+            ld bc, 0xF6C0 : out (c), c
+            xor a : out (c), a
+            ld bc, 0xF792 : out (c), c
+            ld bc, 0xF648 : out (c), c
+            ld b, 0xF4 : in a, (c)
+            ld (0x8000), a
+            ld bc, 0xF782 : out (c), c
+            jr $"
+        );
+        cpc.keyboard_mut().press(KeyPosition::new(8, 5));
+        cpc.run_nops(200);
+        assert_eq!(cpc.z80().memory().read_byte(0x8000), 0b1101_1111);
+    }
+
+    #[test]
+    fn border_and_screen_are_displayed() {
+        // Border in bright white, pen 1 in bright red, first byte of the screen fully in pen 1 (mode 1)
+        let mut cpc = cpc_with_code(
+            " org 0x4000
+            ld bc, 0x7F10 : out (c), c
+            ld c, 0x4B : out (c), c
+            ld bc, 0x7F01 : out (c), c
+            ld c, 0x4C : out (c), c
+            ld bc, 0x7F8D : out (c), c
+            ld a, 0xF0 : ld (0xC000), a
+            jr $"
+        );
+        cpc.run_frames(3);
+        let screen = cpc.screen();
+        assert_eq!(screen.pixel(0, 0), 0x0B);
+        assert_eq!(screen.pixel(64, 36), 0x0C);
+        assert_eq!(screen.pixel(71, 36), 0x0C);
+        assert_eq!(screen.pixel(72, 36), cpc.gate_array().palette()[0]);
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut cpc = cpc_with_code(
+            " org 0x4000
+            ld bc, 0x7F00 : out (c), c
+            ld c, 0x54 : out (c), c
+            ld bc, 0xBC06 : out (c), c
+            ld bc, 0xBD14 : out (c), c
+            jr $"
+        );
+        cpc.run_frames(1);
+        let mut sna = Snapshot::default();
+        cpc.update_snapshot(&mut sna);
+
+        let restored = Cpc::from_snapshot(&sna);
+        assert_eq!(restored.gate_array().palette()[0], 0x14);
+        assert_eq!(restored.crtc().register(6), 0x14);
+        assert_eq!(restored.pc(), cpc.pc());
+    }
+}
@@ -0,0 +1,96 @@
+//! 8255 PPI: it links the Z80 to the PSG, the keyboard lines, the cassette and some status bits.
+
+/// Operation requested to the PSG through the bits 6-7 of the port C
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PsgFunction {
+    Inactive,
+    Read,
+    Write,
+    SelectRegister
+}
+
+/// Value of the port B except for the VSYNC bit: Amstrad brand, 50Hz screen, no expansion, printer not busy
+const PORT_B_STATIC: u8 = 0b0101_1110;
+
+#[derive(Debug, Clone)]
+pub struct Ppi {
+    port_a: u8,
+    port_c: u8,
+    control: u8
+}
+
+/// Configuration set by the firmware: port A output, port B input, port C output
+impl Default for Ppi {
+    fn default() -> Self {
+        Self {
+            port_a: 0,
+            port_c: 0,
+            control: 0x82
+        }
+    }
+}
+
+#[allow(missing_docs)]
+impl Ppi {
+    pub fn port_a(&self) -> u8 {
+        self.port_a
+    }
+
+    pub fn port_c(&self) -> u8 {
+        self.port_c
+    }
+
+    pub fn control(&self) -> u8 {
+        self.control
+    }
+
+    pub fn set_port_a(&mut self, value: u8) {
+        self.port_a = value;
+    }
+
+    pub fn set_port_c(&mut self, value: u8) {
+        self.port_c = value;
+    }
+
+    /// A control byte either configures the ports (bit 7 set) or sets/resets a bit of port C
+    pub fn write_control(&mut self, value: u8) {
+        if value & 0x80 != 0 {
+            self.control = value;
+            // A mode change clears the outputs
+            self.port_a = 0;
+            self.port_c = 0;
+        }
+        else {
+            let bit = (value >> 1) & 0b111;
+            if value & 1 != 0 {
+                self.port_c |= 1 << bit;
+            }
+            else {
+                self.port_c &= !(1 << bit);
+            }
+        }
+    }
+
+    pub fn is_port_a_input(&self) -> bool {
+        self.control & 0b1_0000 != 0
+    }
+
+    /// Value read on port B
+    pub fn port_b(&self, vsync: bool) -> u8 {
+        PORT_B_STATIC | u8::from(vsync)
+    }
+
+    /// Keyboard line selected by the port C
+    pub fn keyboard_line(&self) -> u8 {
+        self.port_c & 0b1111
+    }
+
+    pub fn psg_function(&self) -> PsgFunction {
+        match self.port_c >> 6 {
+            0b00 => PsgFunction::Inactive,
+            0b01 => PsgFunction::Read,
+            0b10 => PsgFunction::Write,
+            _ => PsgFunction::SelectRegister
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! AY-3-8912 registers. No sound is produced; only the register file and the I/O port used to read
+//! the keyboard are emulated.
+
+/// Number of bits kept by each register
+const REGISTER_MASKS: [u8; 16] = [
+    0xFF, 0x0F, 0xFF, 0x0F, 0xFF, 0x0F, 0x1F, 0xFF, 0x1F, 0x1F, 0x1F, 0xFF, 0xFF, 0x0F, 0xFF, 0xFF
+];
+
+/// Register connected to the keyboard lines
+pub const KEYBOARD_REGISTER: u8 = 14;
+
+/// Register that configures the direction of the I/O port
+const MIXER_REGISTER: usize = 7;
+
+#[derive(Default, Debug, Clone)]
+pub struct Psg {
+    registers: [u8; 16],
+    selected: u8
+}
+
+#[allow(missing_docs)]
+impl Psg {
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    pub fn selected_register(&self) -> u8 {
+        self.selected
+    }
+
+    pub fn select_register(&mut self, idx: u8) {
+        self.selected = idx;
+    }
+
+    pub fn set_register(&mut self, idx: usize, value: u8) {
+        if idx < 16 {
+            self.registers[idx] = value & REGISTER_MASKS[idx];
+        }
+    }
+
+    pub fn write_selected_register(&mut self, value: u8) {
+        self.set_register(self.selected as usize, value);
+    }
+
+    /// Value of the selected register. `keyboard_line` is the value presented on the I/O port A
+    pub fn read_selected_register(&self, keyboard_line: u8) -> u8 {
+        match self.selected {
+            KEYBOARD_REGISTER if self.is_port_a_input() => keyboard_line,
+            idx @ 0..16 => self.registers[idx as usize],
+            _ => 0xFF
+        }
+    }
+
+    fn is_port_a_input(&self) -> bool {
+        self.registers[MIXER_REGISTER] & 0b0100_0000 == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyboard_is_read_on_register_14() {
+        let mut psg = Psg::default();
+        psg.select_register(14);
+        assert_eq!(psg.read_selected_register(0b1111_1011), 0b1111_1011);
+        psg.select_register(1);
+        psg.write_selected_register(0xFF);
+        assert_eq!(psg.read_selected_register(0), 0x0F);
+    }
+}
@@ -0,0 +1,128 @@
+//! Picture produced by the gate array as seen by a monitor.
+//!
+//! The visible area is positioned relative to the synchronisation signals as on a real monitor:
+//! a standard screen has 4 characters of border on each side and 36 lines above and below.
+
+use super::crtc::CrtcOutput;
+use super::gate_array::{GateArray, SyncEvents, byte_to_pens};
+use crate::memory::Memory;
+
+/// Width of the visible area in mode 2 pixels (48 characters)
+pub const SCREEN_WIDTH: usize = 768;
+/// Height of the visible area in lines
+pub const SCREEN_HEIGHT: usize = 272;
+
+/// Number of characters between the beginning of the HSYNC and the left of the visible area
+const FIRST_VISIBLE_CHARACTER: usize = 14;
+/// Number of lines between the beginning of the VSYNC and the top of the visible area
+const FIRST_VISIBLE_LINE: usize = 36;
+/// Hardware colour displayed during the synchronisation signals
+const BLACK: u8 = 0x14;
+
+/// Each pixel is stored as a hardware colour number (0-31)
+#[derive(Debug, Clone)]
+pub struct Screen {
+    pixels: Vec<u8>,
+    /// Characters since the beginning of the last HSYNC
+    beam_x: usize,
+    /// Lines since the beginning of the last VSYNC
+    beam_y: usize,
+    /// Number of VSYNC seen
+    frames: u64
+}
+
+impl Default for Screen {
+    fn default() -> Self {
+        Self {
+            pixels: vec![BLACK; SCREEN_WIDTH * SCREEN_HEIGHT],
+            beam_x: 0,
+            beam_y: 0,
+            frames: 0
+        }
+    }
+}
+
+#[allow(missing_docs)]
+impl Screen {
+    pub fn width(&self) -> usize {
+        SCREEN_WIDTH
+    }
+
+    pub fn height(&self) -> usize {
+        SCREEN_HEIGHT
+    }
+
+    /// Hardware colours of the pixels, line after line
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Hardware colour of the given mode 2 pixel
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * SCREEN_WIDTH + x]
+    }
+
+    /// Number of frames (i.e. VSYNC) displayed since the creation of the screen
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Display the character currently produced by the CRTC
+    pub(crate) fn draw(
+        &mut self,
+        output: &CrtcOutput,
+        events: SyncEvents,
+        gate_array: &GateArray,
+        memory: &Memory
+    ) {
+        if events.vsync_start {
+            self.beam_y = 0;
+            self.frames += 1;
+        }
+        if events.hsync_start {
+            self.beam_x = 0;
+            self.beam_y += 1;
+        }
+
+        let x = self.beam_x;
+        self.beam_x += 1;
+
+        if !(FIRST_VISIBLE_CHARACTER..FIRST_VISIBLE_CHARACTER + SCREEN_WIDTH / 16).contains(&x)
+            || !(FIRST_VISIBLE_LINE..FIRST_VISIBLE_LINE + SCREEN_HEIGHT).contains(&self.beam_y)
+        {
+            return;
+        }
+
+        let start =
+            (self.beam_y - FIRST_VISIBLE_LINE) * SCREEN_WIDTH + (x - FIRST_VISIBLE_CHARACTER) * 16;
+        let pixels = &mut self.pixels[start..start + 16];
+
+        if output.hsync || output.vsync {
+            pixels.fill(BLACK);
+        }
+        else if output.disp_en {
+            let address = output.video_address();
+            let bytes = [
+                memory.read_video_byte(address),
+                memory.read_video_byte(address.wrapping_add(1))
+            ];
+            let mode = gate_array.mode();
+            let width = match mode {
+                0 | 3 => 4,
+                1 => 2,
+                _ => 1
+            };
+            for (idx, pen) in bytes
+                .into_iter()
+                .flat_map(|byte| byte_to_pens(byte, mode))
+                .enumerate()
+            {
+                let colour = gate_array.palette()[pen as usize];
+                pixels[idx * width..(idx + 1) * width].fill(colour);
+            }
+        }
+        else {
+            pixels.fill(gate_array.border());
+        }
+    }
+}
@@ -107,14 +107,14 @@ impl Z80 {
 
             Mnemonic::Pop => {
                 let word = self.read_memory_word(self.sp().value());
-                self.bc_mut().set(word);
+                self.get_register_16_mut(arg1.unwrap()).set(word);
                 self.sp_mut().add(2);
             },
 
             Mnemonic::Ldi => {
                 let byte = self.read_memory_byte(self.hl().value());
                 self.write_memory_byte(self.de().value(), byte);
-                self.bc_mut().inc();
+                self.bc_mut().dec();
                 self.de_mut().inc();
                 self.hl_mut().inc();
             },
@@ -249,8 +249,8 @@ impl Z80 {
                     },
 
                     // Write in memory
-                    (Some(DataAccess::MemoryRegister16(_reg)), Some(_)) => {
-                        let address = self.get_value(arg1.unwrap()).unwrap();
+                    (Some(DataAccess::MemoryRegister16(reg)), Some(_)) => {
+                        let address = self.get_register_16(&DataAccess::Register16(*reg)).value();
                         let value = self.get_value(arg2.unwrap()).unwrap();
                         self.write_memory_byte(address, value as _);
                     },
@@ -269,23 +269,6 @@ impl Z80 {
         duration
     }
 
-    /// TODO need to manage memory
-    fn write_memory_byte(&self, _addr: u16, _val: u8) {
-        eprintln!("[ERROR] Memory byte not written");
-    }
-
-    /// TODO need to manage memory
-    fn read_memory_byte(&self, _addr: u16) -> u8 {
-        eprintln!("[ERROR] Memory byte not read");
-        u8::default()
-    }
-
-    fn read_memory_word(&self, addr: u16) -> u16 {
-        let low = self.read_memory_byte(addr);
-        let high = self.read_memory_byte(addr + 1); // TODO manage overflow case
-        u16::from(low) + u16::from(high) * 256
-    }
-
     /// Read the value provided by the given access.
     /// None is returned if we do not have enough information to get it
     /// TODO better emulation to never return None
@@ -319,7 +302,7 @@ impl Z80 {
     }
 
     /// Returns the register encoded by the DataAccess
-    fn get_register_16(&self, reg: &DataAccess) -> &crate::z80::Register16 {
+    pub(crate) fn get_register_16(&self, reg: &DataAccess) -> &crate::z80::Register16 {
         match reg {
             DataAccess::IndexRegister16(reg) => {
                 match reg {
@@ -340,7 +323,7 @@ impl Z80 {
         }
     }
 
-    fn get_register_16_mut(&mut self, reg: &DataAccess) -> &mut crate::z80::Register16 {
+    pub(crate) fn get_register_16_mut(&mut self, reg: &DataAccess) -> &mut crate::z80::Register16 {
         match reg {
             DataAccess::IndexRegister16(reg) => {
                 match reg {
@@ -361,7 +344,7 @@ impl Z80 {
         }
     }
 
-    fn get_register_8(&self, reg: &DataAccess) -> &crate::z80::Register8 {
+    pub(crate) fn get_register_8(&self, reg: &DataAccess) -> &crate::z80::Register8 {
         match reg {
             DataAccess::Register8(reg) => {
                 match reg {
@@ -388,7 +371,7 @@ impl Z80 {
     }
 
     // Mutable version to be synced with the immutable one
-    fn get_register_8_mut(&mut self, reg: &DataAccess) -> &mut crate::z80::Register8 {
+    pub(crate) fn get_register_8_mut(&mut self, reg: &DataAccess) -> &mut crate::z80::Register8 {
         match reg {
             DataAccess::Register8(reg) => {
                 match reg {
@@ -0,0 +1,30 @@
+//! Input/output ports seen by the Z80
+
+/// Hardware connected to the I/O ports of the Z80.
+/// The memory mapping register of the gate array is already handled by the Z80 itself;
+/// an implementation only has to deal with the other peripherals.
+pub trait Ports {
+    /// Value read by `IN`, `INI` and friends
+    fn port_in(&mut self, port: u16) -> u8;
+
+    /// Value written by `OUT`, `OUTI` and friends
+    fn port_out(&mut self, port: u16, value: u8);
+}
+
+/// No peripheral at all: reads return 0xFF and writes are lost
+#[derive(Default, Debug, Copy, Clone)]
+pub struct NoPorts;
+
+impl Ports for NoPorts {
+    fn port_in(&mut self, _port: u16) -> u8 {
+        0xFF
+    }
+
+    fn port_out(&mut self, _port: u16, _value: u8) {}
+}
+
+/// Returns true when the port/value pair selects the RAM configuration.
+/// As on a real 6128, the PAL only decodes A15 (the usual port is 0x7Fxx).
+pub fn is_gate_array_mmr_write(port: u16, value: u8) -> bool {
+    (port & 0x8000) == 0 && (value & 0b1100_0000) == 0b1100_0000
+}
@@ -1,12 +1,22 @@
 /// ! Z80 emulator
 /// ! This should be deprecated in favor of a real emulator (WIP in another repo)
+pub mod cpc;
 pub mod emul;
+pub mod io;
+pub mod memory;
+mod opcodes;
 mod preamble;
+mod snapshot;
 pub mod track;
+pub mod unit_test;
 mod z80;
 
 use cpclib_asm::preamble::*;
 
+pub use self::cpc::Cpc;
+pub use self::io::{NoPorts, Ports};
+pub use self::memory::Memory;
+pub use self::unit_test::run_unit_test;
 pub use self::z80::{HasValue, Z80};
 
 /// Result on the listing execution
@@ -0,0 +1,311 @@
+//! Banked memory of the CPC as seen by the Z80
+use std::collections::BTreeMap;
+use std::fmt;
+
+use cpclib_asm::MemoryPhysicalAddress;
+
+/// Size of the base memory of any CPC
+pub const BASE_MEMORY_SIZE: usize = 0x1_0000;
+
+/// Value of the gate array memory mapping register at reset
+pub const DEFAULT_MMR: u8 = 0xC0;
+
+/// Size of a ROM
+pub const ROM_SIZE: usize = 0x4000;
+
+/// RAM of a CPC: the base 64kb followed by the optional 64kb extension pages.
+/// The 16kb banks visible by the Z80 are selected through the gate array memory mapping register (MMR)
+/// exactly as on a CPC 6128 (`0xC0` to `0xC7`, bits 3-5 selecting the extension page for bigger expansions).
+///
+/// ROMs can be plugged: when enabled, they hide the RAM for reads only (writes always go to the RAM).
+/// A ROM that has not been provided is never visible.
+#[derive(Clone)]
+pub struct Memory {
+    /// Linear view of the RAM: base memory then the extension pages
+    ram: Vec<u8>,
+    /// Last value written in the memory mapping register of the gate array
+    mmr: u8,
+    /// Firmware visible in 0x0000-0x3FFF
+    lower_rom: Option<Vec<u8>>,
+    /// ROMs visible in 0xC000-0xFFFF depending on the selection
+    upper_roms: BTreeMap<u8, Vec<u8>>,
+    lower_rom_enabled: bool,
+    upper_rom_enabled: bool,
+    selected_upper_rom: u8
+}
+
+/// By default, we have the memory of a CPC 6128
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new_128k()
+    }
+}
+
+/// The content of the memory is never displayed
+impl fmt::Debug for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Memory")
+            .field("size", &self.ram.len())
+            .field("mmr", &format_args!("0x{:02X}", self.mmr))
+            .field("lower_rom", &self.lower_rom.is_some())
+            .field("upper_roms", &self.upper_roms.keys().collect::<Vec<_>>())
+            .field("lower_rom_enabled", &self.lower_rom_enabled)
+            .field("upper_rom_enabled", &self.upper_rom_enabled)
+            .field("selected_upper_rom", &self.selected_upper_rom)
+            .finish()
+    }
+}
+
+#[allow(missing_docs)]
+impl Memory {
+    /// Memory of a CPC 464/664
+    pub fn new_64k() -> Self {
+        Self::new_with_size(BASE_MEMORY_SIZE)
+    }
+
+    /// Memory of a CPC 6128
+    pub fn new_128k() -> Self {
+        Self::new_with_size(2 * BASE_MEMORY_SIZE)
+    }
+
+    /// Build an empty memory of the given size. It is increased to the next multiple of 64kb if needed
+    pub fn new_with_size(size: usize) -> Self {
+        let size = size.max(1).div_ceil(BASE_MEMORY_SIZE) * BASE_MEMORY_SIZE;
+        Self {
+            ram: vec![0; size],
+            mmr: DEFAULT_MMR,
+            lower_rom: None,
+            upper_roms: BTreeMap::new(),
+            lower_rom_enabled: false,
+            upper_rom_enabled: false,
+            selected_upper_rom: 0
+        }
+    }
+
+    /// Build a memory from a linear dump (typically the one of a snapshot)
+    pub fn from_dump(dump: &[u8]) -> Self {
+        let mut memory = Self::new_with_size(dump.len());
+        memory.load_dump(dump);
+        memory
+    }
+
+    /// Replace the whole RAM by a linear dump. The plugged ROMs are kept
+    pub fn load_dump(&mut self, dump: &[u8]) {
+        let size = dump.len().max(1).div_ceil(BASE_MEMORY_SIZE) * BASE_MEMORY_SIZE;
+        self.ram = vec![0; size];
+        self.ram[..dump.len()].copy_from_slice(dump);
+    }
+
+    /// Number of 64kb pages (the base memory is counted)
+    pub fn nb_pages(&self) -> usize {
+        self.ram.len() / BASE_MEMORY_SIZE
+    }
+
+    pub fn mmr(&self) -> u8 {
+        self.mmr
+    }
+
+    /// Select the banks visible by the Z80. Only the 6 lowest bits matter
+    pub fn set_mmr(&mut self, mmr: u8) {
+        self.mmr = 0b1100_0000 | (mmr & 0b0011_1111);
+    }
+
+    /// Linear view of the whole RAM whatever is the current mapping
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    /// Returns the offset in the linear RAM of the given Z80 address for the current mapping.
+    /// A page that is not available is ignored (the base memory is used) as on a machine without the expansion.
+    pub fn physical_address(&self, address: u16) -> usize {
+        let offset = MemoryPhysicalAddress::new(address, self.mmr).offset_in_cpc() as usize;
+        if offset < self.ram.len() {
+            offset
+        }
+        else {
+            address as usize
+        }
+    }
+
+    /// Plug the firmware ROM. Its content is truncated or padded to 16kb
+    pub fn set_lower_rom(&mut self, rom: &[u8]) {
+        self.lower_rom = Some(Self::rom_content(rom));
+    }
+
+    /// Plug a ROM in the given upper slot. Its content is truncated or padded to 16kb
+    pub fn set_upper_rom(&mut self, slot: u8, rom: &[u8]) {
+        self.upper_roms.insert(slot, Self::rom_content(rom));
+    }
+
+    pub fn remove_upper_rom(&mut self, slot: u8) {
+        self.upper_roms.remove(&slot);
+    }
+
+    fn rom_content(rom: &[u8]) -> Vec<u8> {
+        let mut content = rom[..rom.len().min(ROM_SIZE)].to_vec();
+        content.resize(ROM_SIZE, 0xFF);
+        content
+    }
+
+    pub fn has_lower_rom(&self) -> bool {
+        self.lower_rom.is_some()
+    }
+
+    pub fn is_lower_rom_enabled(&self) -> bool {
+        self.lower_rom_enabled
+    }
+
+    pub fn is_upper_rom_enabled(&self) -> bool {
+        self.upper_rom_enabled
+    }
+
+    pub fn selected_upper_rom(&self) -> u8 {
+        self.selected_upper_rom
+    }
+
+    /// Configure the ROM visibility as done by the gate array
+    pub fn set_rom_configuration(&mut self, lower_rom_enabled: bool, upper_rom_enabled: bool) {
+        self.lower_rom_enabled = lower_rom_enabled;
+        self.upper_rom_enabled = upper_rom_enabled;
+    }
+
+    /// Select the upper ROM. As on a CPC, an empty slot selects the ROM 0
+    pub fn select_upper_rom(&mut self, slot: u8) {
+        self.selected_upper_rom = slot;
+    }
+
+    /// Returns the ROM visible at the address if any
+    fn visible_rom(&self, address: u16) -> Option<&[u8]> {
+        if address < 0x4000 && self.lower_rom_enabled {
+            self.lower_rom.as_deref()
+        }
+        else if address >= 0xC000 && self.upper_rom_enabled {
+            self.upper_roms
+                .get(&self.selected_upper_rom)
+                .or_else(|| self.upper_roms.get(&0))
+                .map(|rom| rom.as_slice())
+        }
+        else {
+            None
+        }
+    }
+
+    /// Read the byte visible by the Z80 at this address
+    pub fn read_byte(&self, address: u16) -> u8 {
+        match self.visible_rom(address) {
+            Some(rom) => rom[address as usize % ROM_SIZE],
+            None => self.ram[self.physical_address(address)]
+        }
+    }
+
+    /// Read the base memory as done by the gate array to display the screen (MMR and ROMs are ignored)
+    pub fn read_video_byte(&self, address: u16) -> u8 {
+        self.ram[address as usize]
+    }
+
+    /// Write the byte at the Z80 address
+    pub fn write_byte(&mut self, address: u16, value: u8) {
+        let offset = self.physical_address(address);
+        self.ram[offset] = value;
+    }
+
+    /// Little endian read of a word
+    pub fn read_word(&self, address: u16) -> u16 {
+        let low = self.read_byte(address);
+        let high = self.read_byte(address.wrapping_add(1));
+        u16::from_le_bytes([low, high])
+    }
+
+    /// Little endian write of a word
+    pub fn write_word(&mut self, address: u16, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        self.write_byte(address, low);
+        self.write_byte(address.wrapping_add(1), high);
+    }
+
+    /// Copy the bytes from the given Z80 address using the current mapping
+    pub fn load_bytes(&mut self, address: u16, bytes: &[u8]) {
+        for (idx, byte) in bytes.iter().enumerate() {
+            self.write_byte(address.wrapping_add(idx as u16), *byte);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mmr_selection() {
+        let mut memory = Memory::new_128k();
+
+        memory.write_byte(0x4000, 1);
+        memory.set_mmr(0xC4);
+        assert_eq!(memory.read_byte(0x4000), 0);
+        memory.write_byte(0x4000, 2);
+        assert_eq!(memory.ram()[0x1_0000], 2);
+
+        memory.set_mmr(0xC2);
+        assert_eq!(memory.read_byte(0x0000), 2);
+
+        memory.set_mmr(0xC0);
+        assert_eq!(memory.read_byte(0x4000), 1);
+
+        // C4 only changes 0x4000
+        memory.write_byte(0x8000, 4);
+        memory.set_mmr(0xC4);
+        assert_eq!(memory.read_byte(0x8000), 4);
+        memory.set_mmr(0xC0);
+
+        // C3 maps page 7 in 0xC000 and bank 3 in 0x4000
+        memory.write_byte(0xC000, 3);
+        memory.set_mmr(0xC3);
+        assert_eq!(memory.read_byte(0x4000), 3);
+        assert_eq!(memory.physical_address(0xC000), 0x1_C000);
+
+        // C1 only maps page 7 in 0xC000
+        memory.set_mmr(0xC1);
+        assert_eq!(memory.physical_address(0x0000), 0x0000);
+        assert_eq!(memory.physical_address(0x4000), 0x4000);
+        assert_eq!(memory.physical_address(0x8000), 0x8000);
+        assert_eq!(memory.physical_address(0xC000), 0x1_C000);
+        assert_eq!(memory.read_byte(0x4000), 1);
+        memory.write_byte(0xC000, 5);
+        assert_eq!(memory.ram()[0x1_C000], 5);
+        memory.set_mmr(0xC0);
+        assert_eq!(memory.read_byte(0xC000), 3);
+    }
+
+    #[test]
+    fn roms_hide_ram_for_reading() {
+        let mut memory = Memory::new_128k();
+        memory.set_lower_rom(&[1; ROM_SIZE]);
+        memory.set_upper_rom(0, &[2; ROM_SIZE]);
+        memory.set_upper_rom(7, &[3; ROM_SIZE]);
+
+        memory.set_rom_configuration(true, true);
+        memory.write_byte(0x0000, 4);
+        assert_eq!(memory.read_byte(0x0000), 1);
+        assert_eq!(memory.read_byte(0xC000), 2);
+        memory.select_upper_rom(7);
+        assert_eq!(memory.read_byte(0xC000), 3);
+        memory.select_upper_rom(5);
+        assert_eq!(memory.read_byte(0xC000), 2);
+
+        memory.set_rom_configuration(false, false);
+        assert_eq!(memory.read_byte(0x0000), 4);
+        assert_eq!(memory.read_byte(0xC000), 0);
+    }
+
+    #[test]
+    fn missing_extension_is_ignored() {
+        let mut memory = Memory::new_64k();
+        memory.write_byte(0x4000, 5);
+        memory.set_mmr(0xC4);
+        assert_eq!(memory.read_byte(0x4000), 5);
+    }
+}
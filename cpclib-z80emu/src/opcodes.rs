@@ -0,0 +1,1366 @@
+//! Fetch, decode and execute the bytes stored in memory.
+//! Durations are expressed in nops as on the CPC where each instruction is stretched to a multiple of 4 T-states.
+//! Decoding follows the x/y/z/p/q decomposition of the opcode
+use crate::io::{NoPorts, Ports, is_gate_array_mmr_write};
+use crate::z80::{HasValue, Z80};
+
+const FLAG_S: u8 = 0b1000_0000;
+const FLAG_Z: u8 = 0b0100_0000;
+const FLAG_Y: u8 = 0b0010_0000;
+const FLAG_H: u8 = 0b0001_0000;
+const FLAG_X: u8 = 0b0000_1000;
+const FLAG_P: u8 = 0b0000_0100;
+const FLAG_N: u8 = 0b0000_0010;
+const FLAG_C: u8 = 0b0000_0001;
+
+/// Duration of the acceptance of an interrupt in mode 0 or 1
+const IM1_DURATION: usize = 5;
+/// Duration of the acceptance of an interrupt in mode 2
+const IM2_DURATION: usize = 7;
+
+/// Register used in place of HL depending on the prefix of the instruction
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum IndexMode {
+    Hl,
+    Ix,
+    Iy
+}
+
+impl IndexMode {
+    /// Extra duration of the prefix
+    fn prefix_duration(self) -> usize {
+        match self {
+            IndexMode::Hl => 0,
+            _ => 1
+        }
+    }
+}
+
+/// Sign, zero, parity and undocumented flags of a result
+fn szp_flags(value: u8) -> u8 {
+    let mut f = value & (FLAG_S | FLAG_Y | FLAG_X);
+    if value == 0 {
+        f |= FLAG_Z;
+    }
+    if value.count_ones().is_multiple_of(2) {
+        f |= FLAG_P;
+    }
+    f
+}
+
+/// Sign, zero and undocumented flags of a result
+fn sz_flags(value: u8) -> u8 {
+    let mut f = value & (FLAG_S | FLAG_Y | FLAG_X);
+    if value == 0 {
+        f |= FLAG_Z;
+    }
+    f
+}
+
+impl Z80 {
+    /// Execute the instruction pointed by PC without any peripheral connected.
+    /// Returns its duration in nops
+    pub fn step(&mut self) -> usize {
+        self.step_with_ports(&mut NoPorts)
+    }
+
+    /// Execute the instruction pointed by PC. Returns its duration in nops
+    pub fn step_with_ports<P: Ports>(&mut self, ports: &mut P) -> usize {
+        self.extra.ei_delay = false;
+
+        if self.extra.halted {
+            self.inc_r();
+            return 1;
+        }
+
+        let opcode = self.fetch_opcode();
+        self.execute_main(opcode, IndexMode::Hl, ports)
+    }
+
+    /// Execute instructions until PC reaches `stop` or `max_nops` are elapsed.
+    /// Returns the number of nops when `stop` has been reached
+    pub fn run_until<P: Ports>(
+        &mut self,
+        stop: u16,
+        max_nops: usize,
+        ports: &mut P
+    ) -> Option<usize> {
+        let mut nops = 0;
+        while self.pc().value() != stop {
+            if nops >= max_nops {
+                return None;
+            }
+            nops += self.step_with_ports(ports);
+        }
+        Some(nops)
+    }
+
+    /// Request a maskable interrupt with 0xFF on the data bus, as on the CPC.
+    /// Returns the duration of its acceptance, 0 when it is refused
+    pub fn interrupt(&mut self) -> usize {
+        if !self.extra.iff1 || self.extra.ei_delay {
+            return 0;
+        }
+
+        self.extra.halted = false;
+        self.extra.iff1 = false;
+        self.extra.iff2 = false;
+        self.inc_r();
+
+        let pc = self.pc().value();
+        self.push(pc);
+
+        match self.extra.im {
+            2 => {
+                let vector = u16::from(self.i().value()) * 256 + 0xFF;
+                let address = self.read_memory_word(vector);
+                self.pc_mut().set(address);
+                IM2_DURATION
+            },
+            _ => {
+                self.pc_mut().set(0x38);
+                IM1_DURATION
+            }
+        }
+    }
+
+    /// Request a non maskable interrupt. Returns its duration
+    pub fn non_maskable_interrupt(&mut self) -> usize {
+        self.extra.halted = false;
+        self.extra.iff1 = false;
+        self.inc_r();
+        let pc = self.pc().value();
+        self.push(pc);
+        self.pc_mut().set(0x66);
+        IM1_DURATION - 2
+    }
+
+    // ─── bus access ────────────────────────────────────────────────────────
+
+    fn inc_r(&mut self) {
+        let r = self.r().value();
+        self.r_mut().set((r & 0x80) | (r.wrapping_add(1) & 0x7F));
+    }
+
+    fn fetch_byte(&mut self) -> u8 {
+        let pc = self.pc().value();
+        self.pc_mut().set(pc.wrapping_add(1));
+        self.read_memory_byte(pc)
+    }
+
+    fn fetch_opcode(&mut self) -> u8 {
+        self.inc_r();
+        self.fetch_byte()
+    }
+
+    fn fetch_word(&mut self) -> u16 {
+        let low = self.fetch_byte();
+        let high = self.fetch_byte();
+        u16::from_le_bytes([low, high])
+    }
+
+    fn push(&mut self, value: u16) {
+        let sp = self.sp().value().wrapping_sub(2);
+        self.sp_mut().set(sp);
+        self.write_memory_word(sp, value);
+    }
+
+    fn pop(&mut self) -> u16 {
+        let sp = self.sp().value();
+        let value = self.read_memory_word(sp);
+        self.sp_mut().set(sp.wrapping_add(2));
+        value
+    }
+
+    fn port_in<P: Ports>(&mut self, port: u16, ports: &mut P) -> u8 {
+        ports.port_in(port)
+    }
+
+    fn port_out<P: Ports>(&mut self, port: u16, value: u8, ports: &mut P) {
+        if is_gate_array_mmr_write(port, value) {
+            self.memory.set_mmr(value);
+        }
+        ports.port_out(port, value);
+    }
+
+    // ─── register access ───────────────────────────────────────────────────
+
+    fn flags(&self) -> u8 {
+        self.f().value()
+    }
+
+    fn set_flags(&mut self, f: u8) {
+        self.f_mut().set(f);
+    }
+
+    fn carry(&self) -> u8 {
+        self.flags() & FLAG_C
+    }
+
+    fn index_value(&self, mode: IndexMode) -> u16 {
+        match mode {
+            IndexMode::Hl => self.hl().value(),
+            IndexMode::Ix => self.ix().value(),
+            IndexMode::Iy => self.iy().value()
+        }
+    }
+
+    fn set_index_value(&mut self, mode: IndexMode, value: u16) {
+        match mode {
+            IndexMode::Hl => self.hl_mut().set(value),
+            IndexMode::Ix => self.ix_mut().set(value),
+            IndexMode::Iy => self.iy_mut().set(value)
+        }
+    }
+
+    /// Address of the memory operand: (HL) or (IX+d)/(IY+d) whose displacement is fetched
+    fn memory_operand_address(&mut self, mode: IndexMode) -> u16 {
+        match mode {
+            IndexMode::Hl => self.hl().value(),
+            _ => {
+                let delta = self.fetch_byte() as i8;
+                self.index_value(mode).wrapping_add_signed(i16::from(delta))
+            }
+        }
+    }
+
+    /// Register encoded on 3 bits (6 (memory) is not handled there).
+    /// H and L are replaced by the index register halves when prefixed
+    fn reg8(&self, idx: u8, mode: IndexMode) -> u8 {
+        match (idx, mode) {
+            (0, _) => self.b().value(),
+            (1, _) => self.c().value(),
+            (2, _) => self.d().value(),
+            (3, _) => self.e().value(),
+            (4, IndexMode::Hl) => self.h().value(),
+            (4, IndexMode::Ix) => self.ixh().value(),
+            (4, IndexMode::Iy) => self.iyh().value(),
+            (5, IndexMode::Hl) => self.l().value(),
+            (5, IndexMode::Ix) => self.ixl().value(),
+            (5, IndexMode::Iy) => self.iyl().value(),
+            (7, _) => self.a().value(),
+            _ => unreachable!()
+        }
+    }
+
+    fn set_reg8(&mut self, idx: u8, mode: IndexMode, value: u8) {
+        match (idx, mode) {
+            (0, _) => self.b_mut().set(value),
+            (1, _) => self.c_mut().set(value),
+            (2, _) => self.d_mut().set(value),
+            (3, _) => self.e_mut().set(value),
+            (4, IndexMode::Hl) => self.h_mut().set(value),
+            (4, IndexMode::Ix) => self.ixh_mut().set(value),
+            (4, IndexMode::Iy) => self.iyh_mut().set(value),
+            (5, IndexMode::Hl) => self.l_mut().set(value),
+            (5, IndexMode::Ix) => self.ixl_mut().set(value),
+            (5, IndexMode::Iy) => self.iyl_mut().set(value),
+            (7, _) => self.a_mut().set(value),
+            _ => unreachable!()
+        }
+    }
+
+    /// Register pair of the table rp (BC, DE, HL, SP)
+    fn reg16(&self, idx: u8, mode: IndexMode) -> u16 {
+        match idx {
+            0 => self.bc().value(),
+            1 => self.de().value(),
+            2 => self.index_value(mode),
+            3 => self.sp().value(),
+            _ => unreachable!()
+        }
+    }
+
+    fn set_reg16(&mut self, idx: u8, mode: IndexMode, value: u16) {
+        match idx {
+            0 => self.bc_mut().set(value),
+            1 => self.de_mut().set(value),
+            2 => self.set_index_value(mode, value),
+            3 => self.sp_mut().set(value),
+            _ => unreachable!()
+        }
+    }
+
+    /// Register pair of the table rp2 (BC, DE, HL, AF)
+    fn reg16_af(&self, idx: u8, mode: IndexMode) -> u16 {
+        if idx == 3 {
+            self.af().value()
+        }
+        else {
+            self.reg16(idx, mode)
+        }
+    }
+
+    fn set_reg16_af(&mut self, idx: u8, mode: IndexMode, value: u16) {
+        if idx == 3 {
+            self.af_mut().set(value)
+        }
+        else {
+            self.set_reg16(idx, mode, value)
+        }
+    }
+
+    /// Condition of the table cc (NZ, Z, NC, C, PO, PE, P, M)
+    fn condition(&self, idx: u8) -> bool {
+        let f = self.flags();
+        match idx {
+            0 => f & FLAG_Z == 0,
+            1 => f & FLAG_Z != 0,
+            2 => f & FLAG_C == 0,
+            3 => f & FLAG_C != 0,
+            4 => f & FLAG_P == 0,
+            5 => f & FLAG_P != 0,
+            6 => f & FLAG_S == 0,
+            7 => f & FLAG_S != 0,
+            _ => unreachable!()
+        }
+    }
+
+    // ─── arithmetic and logic ──────────────────────────────────────────────
+
+    fn add8(&mut self, value: u8, carry: u8) {
+        let a = self.a().value();
+        let result = u16::from(a) + u16::from(value) + u16::from(carry);
+        let r = result as u8;
+        let mut f = sz_flags(r) | ((a ^ value ^ r) & FLAG_H);
+        if (a ^ !value) & (a ^ r) & 0x80 != 0 {
+            f |= FLAG_P;
+        }
+        if result > 0xFF {
+            f |= FLAG_C;
+        }
+        self.a_mut().set(r);
+        self.set_flags(f);
+    }
+
+    /// Compute the subtraction flags and returns the result
+    fn sub8_flags(&mut self, value: u8, carry: u8) -> u8 {
+        let a = self.a().value();
+        let result = i16::from(a) - i16::from(value) - i16::from(carry);
+        let r = result as u8;
+        let mut f = sz_flags(r) | ((a ^ value ^ r) & FLAG_H) | FLAG_N;
+        if (a ^ value) & (a ^ r) & 0x80 != 0 {
+            f |= FLAG_P;
+        }
+        if result < 0 {
+            f |= FLAG_C;
+        }
+        self.set_flags(f);
+        r
+    }
+
+    fn sub8(&mut self, value: u8, carry: u8) {
+        let r = self.sub8_flags(value, carry);
+        self.a_mut().set(r);
+    }
+
+    fn cp8(&mut self, value: u8) {
+        self.sub8_flags(value, 0);
+        let f = (self.flags() & !(FLAG_Y | FLAG_X)) | (value & (FLAG_Y | FLAG_X));
+        self.set_flags(f);
+    }
+
+    /// Operation of the table alu (ADD, ADC, SUB, SBC, AND, XOR, OR, CP)
+    fn alu(&mut self, operation: u8, value: u8) {
+        match operation {
+            0 => self.add8(value, 0),
+            1 => self.add8(value, self.carry()),
+            2 => self.sub8(value, 0),
+            3 => self.sub8(value, self.carry()),
+            4 => {
+                let r = self.a().value() & value;
+                self.a_mut().set(r);
+                self.set_flags(szp_flags(r) | FLAG_H);
+            },
+            5 => {
+                let r = self.a().value() ^ value;
+                self.a_mut().set(r);
+                self.set_flags(szp_flags(r));
+            },
+            6 => {
+                let r = self.a().value() | value;
+                self.a_mut().set(r);
+                self.set_flags(szp_flags(r));
+            },
+            7 => self.cp8(value),
+            _ => unreachable!()
+        }
+    }
+
+    fn inc8(&mut self, value: u8) -> u8 {
+        let r = value.wrapping_add(1);
+        let mut f = (self.flags() & FLAG_C) | sz_flags(r);
+        if value & 0x0F == 0x0F {
+            f |= FLAG_H;
+        }
+        if value == 0x7F {
+            f |= FLAG_P;
+        }
+        self.set_flags(f);
+        r
+    }
+
+    fn dec8(&mut self, value: u8) -> u8 {
+        let r = value.wrapping_sub(1);
+        let mut f = (self.flags() & FLAG_C) | sz_flags(r) | FLAG_N;
+        if value & 0x0F == 0 {
+            f |= FLAG_H;
+        }
+        if value == 0x80 {
+            f |= FLAG_P;
+        }
+        self.set_flags(f);
+        r
+    }
+
+    fn add16(&mut self, a: u16, value: u16) -> u16 {
+        let result = u32::from(a) + u32::from(value);
+        let r = result as u16;
+        let mut f = (self.flags() & (FLAG_S | FLAG_Z | FLAG_P))
+            | (((a ^ value ^ r) >> 8) as u8 & FLAG_H)
+            | ((r >> 8) as u8 & (FLAG_Y | FLAG_X));
+        if result > 0xFFFF {
+            f |= FLAG_C;
+        }
+        self.set_flags(f);
+        r
+    }
+
+    fn adc16(&mut self, a: u16, value: u16) -> u16 {
+        let result = u32::from(a) + u32::from(value) + u32::from(self.carry());
+        let r = result as u16;
+        let mut f =
+            (((a ^ value ^ r) >> 8) as u8 & FLAG_H) | ((r >> 8) as u8 & (FLAG_S | FLAG_Y | FLAG_X));
+        if r == 0 {
+            f |= FLAG_Z;
+        }
+        if (a ^ !value) & (a ^ r) & 0x8000 != 0 {
+            f |= FLAG_P;
+        }
+        if result > 0xFFFF {
+            f |= FLAG_C;
+        }
+        self.set_flags(f);
+        r
+    }
+
+    fn sbc16(&mut self, a: u16, value: u16) -> u16 {
+        let result = i32::from(a) - i32::from(value) - i32::from(self.carry());
+        let r = result as u16;
+        let mut f = (((a ^ value ^ r) >> 8) as u8 & FLAG_H)
+            | ((r >> 8) as u8 & (FLAG_S | FLAG_Y | FLAG_X))
+            | FLAG_N;
+        if r == 0 {
+            f |= FLAG_Z;
+        }
+        if (a ^ value) & (a ^ r) & 0x8000 != 0 {
+            f |= FLAG_P;
+        }
+        if result < 0 {
+            f |= FLAG_C;
+        }
+        self.set_flags(f);
+        r
+    }
+
+    /// Operation of the table rot (RLC, RRC, RL, RR, SLA, SRA, SL1, SRL)
+    fn rot(&mut self, operation: u8, value: u8) -> u8 {
+        let carry = self.carry();
+        let (r, c) = match operation {
+            0 => (value.rotate_left(1), value >> 7),
+            1 => (value.rotate_right(1), value & 1),
+            2 => ((value << 1) | carry, value >> 7),
+            3 => ((value >> 1) | (carry << 7), value & 1),
+            4 => (value << 1, value >> 7),
+            5 => ((value >> 1) | (value & 0x80), value & 1),
+            6 => ((value << 1) | 1, value >> 7),
+            7 => (value >> 1, value & 1),
+            _ => unreachable!()
+        };
+        self.set_flags(szp_flags(r) | c);
+        r
+    }
+
+    /// RLCA, RRCA, RLA, RRA
+    fn rot_a(&mut self, operation: u8) {
+        let flags = self.flags();
+        let r = self.rot(operation, self.a().value());
+        self.a_mut().set(r);
+        let f = (flags & (FLAG_S | FLAG_Z | FLAG_P))
+            | (self.flags() & FLAG_C)
+            | (r & (FLAG_Y | FLAG_X));
+        self.set_flags(f);
+    }
+
+    fn bit(&mut self, bit: u8, value: u8, undocumented: u8) {
+        let tested = value & (1 << bit);
+        let mut f = (self.flags() & FLAG_C) | FLAG_H | (undocumented & (FLAG_Y | FLAG_X));
+        if tested == 0 {
+            f |= FLAG_Z | FLAG_P;
+        }
+        if bit == 7 && tested != 0 {
+            f |= FLAG_S;
+        }
+        self.set_flags(f);
+    }
+
+    fn daa(&mut self) {
+        let a = self.a().value();
+        let f = self.flags();
+        let mut correction = 0;
+        let mut carry = f & FLAG_C;
+
+        if f & FLAG_H != 0 || a & 0x0F > 9 {
+            correction |= 0x06;
+        }
+        if carry != 0 || a > 0x99 {
+            correction |= 0x60;
+            carry = FLAG_C;
+        }
+
+        let r = if f & FLAG_N != 0 {
+            a.wrapping_sub(correction)
+        }
+        else {
+            a.wrapping_add(correction)
+        };
+
+        let new_f = szp_flags(r) | carry | (f & FLAG_N) | ((a ^ r) & FLAG_H);
+        self.a_mut().set(r);
+        self.set_flags(new_f);
+    }
+
+    // ─── decoding ──────────────────────────────────────────────────────────
+
+    /// Execute an unprefixed opcode (or a DD/FD prefixed one when mode is not HL)
+    fn execute_main<P: Ports>(&mut self, opcode: u8, mode: IndexMode, ports: &mut P) -> usize {
+        let x = opcode >> 6;
+        let y = (opcode >> 3) & 0b111;
+        let z = opcode & 0b111;
+        let p = y >> 1;
+        let q = y & 1;
+        let prefix = mode.prefix_duration();
+
+        let duration = match (x, z) {
+            (0, 0) => {
+                match y {
+                    0 => 1,
+                    1 => {
+                        self.ex_af_af_prime();
+                        1
+                    },
+                    2 => {
+                        let delta = self.fetch_byte() as i8;
+                        let b = self.b().value().wrapping_sub(1);
+                        self.b_mut().set(b);
+                        if b != 0 {
+                            self.relative_jump(delta);
+                            4
+                        }
+                        else {
+                            3
+                        }
+                    },
+                    3 => {
+                        let delta = self.fetch_byte() as i8;
+                        self.relative_jump(delta);
+                        3
+                    },
+                    _ => {
+                        let delta = self.fetch_byte() as i8;
+                        if self.condition(y - 4) {
+                            self.relative_jump(delta);
+                            3
+                        }
+                        else {
+                            2
+                        }
+                    }
+                }
+            },
+
+            (0, 1) => {
+                if q == 0 {
+                    let value = self.fetch_word();
+                    self.set_reg16(p, mode, value);
+                    3
+                }
+                else {
+                    let a = self.index_value(mode);
+                    let r = self.add16(a, self.reg16(p, mode));
+                    self.set_index_value(mode, r);
+                    3
+                }
+            },
+
+            (0, 2) => {
+                match (q, p) {
+                    (0, 0) => {
+                        self.write_memory_byte(self.bc().value(), self.a().value());
+                        2
+                    },
+                    (0, 1) => {
+                        self.write_memory_byte(self.de().value(), self.a().value());
+                        2
+                    },
+                    (0, 2) => {
+                        let address = self.fetch_word();
+                        self.write_memory_word(address, self.index_value(mode));
+                        5
+                    },
+                    (0, 3) => {
+                        let address = self.fetch_word();
+                        self.write_memory_byte(address, self.a().value());
+                        4
+                    },
+                    (1, 0) => {
+                        let value = self.read_memory_byte(self.bc().value());
+                        self.a_mut().set(value);
+                        2
+                    },
+                    (1, 1) => {
+                        let value = self.read_memory_byte(self.de().value());
+                        self.a_mut().set(value);
+                        2
+                    },
+                    (1, 2) => {
+                        let address = self.fetch_word();
+                        let value = self.read_memory_word(address);
+                        self.set_index_value(mode, value);
+                        5
+                    },
+                    _ => {
+                        let address = self.fetch_word();
+                        let value = self.read_memory_byte(address);
+                        self.a_mut().set(value);
+                        4
+                    }
+                }
+            },
+
+            (0, 3) => {
+                let value = self.reg16(p, mode);
+                let value = if q == 0 {
+                    value.wrapping_add(1)
+                }
+                else {
+                    value.wrapping_sub(1)
+                };
+                self.set_reg16(p, mode, value);
+                2
+            },
+
+            (0, 4) | (0, 5) => {
+                if y == 6 {
+                    let address = self.memory_operand_address(mode);
+                    let value = self.read_memory_byte(address);
+                    let r = if z == 4 {
+                        self.inc8(value)
+                    }
+                    else {
+                        self.dec8(value)
+                    };
+                    self.write_memory_byte(address, r);
+                    return self.indexed_duration(3, mode);
+                }
+                let value = self.reg8(y, mode);
+                let r = if z == 4 {
+                    self.inc8(value)
+                }
+                else {
+                    self.dec8(value)
+                };
+                self.set_reg8(y, mode, r);
+                1
+            },
+
+            (0, 6) => {
+                if y == 6 {
+                    let address = self.memory_operand_address(mode);
+                    let value = self.fetch_byte();
+                    self.write_memory_byte(address, value);
+                    return self.indexed_duration(3, mode);
+                }
+                let value = self.fetch_byte();
+                self.set_reg8(y, mode, value);
+                2
+            },
+
+            (0, 7) => {
+                match y {
+                    0..=3 => self.rot_a(y),
+                    4 => self.daa(),
+                    5 => {
+                        let a = !self.a().value();
+                        self.a_mut().set(a);
+                        let f = (self.flags() & (FLAG_S | FLAG_Z | FLAG_P | FLAG_C))
+                            | FLAG_H
+                            | FLAG_N
+                            | (a & (FLAG_Y | FLAG_X));
+                        self.set_flags(f);
+                    },
+                    6 => {
+                        let f = (self.flags() & (FLAG_S | FLAG_Z | FLAG_P))
+                            | FLAG_C
+                            | (self.a().value() & (FLAG_Y | FLAG_X));
+                        self.set_flags(f);
+                    },
+                    _ => {
+                        let old = self.flags();
+                        let f = (old & (FLAG_S | FLAG_Z | FLAG_P))
+                            | (if old & FLAG_C != 0 { FLAG_H } else { FLAG_C })
+                            | (self.a().value() & (FLAG_Y | FLAG_X));
+                        self.set_flags(f);
+                    }
+                }
+                1
+            },
+
+            (1, _) => {
+                if y == 6 && z == 6 {
+                    self.extra.halted = true;
+                    return 1 + prefix;
+                }
+                if z == 6 {
+                    // H and L are never replaced when the memory is accessed
+                    let address = self.memory_operand_address(mode);
+                    let value = self.read_memory_byte(address);
+                    self.set_reg8(y, IndexMode::Hl, value);
+                    return self.indexed_duration(2, mode);
+                }
+                if y == 6 {
+                    let address = self.memory_operand_address(mode);
+                    let value = self.reg8(z, IndexMode::Hl);
+                    self.write_memory_byte(address, value);
+                    return self.indexed_duration(2, mode);
+                }
+                let value = self.reg8(z, mode);
+                self.set_reg8(y, mode, value);
+                1
+            },
+
+            (2, _) => {
+                if z == 6 {
+                    let address = self.memory_operand_address(mode);
+                    let value = self.read_memory_byte(address);
+                    self.alu(y, value);
+                    return self.indexed_duration(2, mode);
+                }
+                let value = self.reg8(z, mode);
+                self.alu(y, value);
+                1
+            },
+
+            (3, 0) => {
+                if self.condition(y) {
+                    let address = self.pop();
+                    self.pc_mut().set(address);
+                    4
+                }
+                else {
+                    2
+                }
+            },
+
+            (3, 1) => {
+                if q == 0 {
+                    let value = self.pop();
+                    self.set_reg16_af(p, mode, value);
+                    3
+                }
+                else {
+                    match p {
+                        0 => {
+                            let address = self.pop();
+                            self.pc_mut().set(address);
+                            3
+                        },
+                        1 => {
+                            self.exx();
+                            1
+                        },
+                        2 => {
+                            let address = self.index_value(mode);
+                            self.pc_mut().set(address);
+                            1
+                        },
+                        _ => {
+                            let value = self.index_value(mode);
+                            self.sp_mut().set(value);
+                            2
+                        }
+                    }
+                }
+            },
+
+            (3, 2) => {
+                let address = self.fetch_word();
+                if self.condition(y) {
+                    self.pc_mut().set(address);
+                }
+                3
+            },
+
+            (3, 3) => {
+                match y {
+                    0 => {
+                        let address = self.fetch_word();
+                        self.pc_mut().set(address);
+                        3
+                    },
+                    1 => return self.execute_cb(mode),
+                    2 => {
+                        let n = self.fetch_byte();
+                        let a = self.a().value();
+                        self.port_out(u16::from_be_bytes([a, n]), a, ports);
+                        3
+                    },
+                    3 => {
+                        let n = self.fetch_byte();
+                        let a = self.a().value();
+                        let value = self.port_in(u16::from_be_bytes([a, n]), ports);
+                        self.a_mut().set(value);
+                        3
+                    },
+                    4 => {
+                        let sp = self.sp().value();
+                        let value = self.read_memory_word(sp);
+                        self.write_memory_word(sp, self.index_value(mode));
+                        self.set_index_value(mode, value);
+                        6
+                    },
+                    5 => {
+                        self.ex_de_hl();
+                        1
+                    },
+                    6 => {
+                        self.extra.iff1 = false;
+                        self.extra.iff2 = false;
+                        1
+                    },
+                    _ => {
+                        self.extra.iff1 = true;
+                        self.extra.iff2 = true;
+                        self.extra.ei_delay = true;
+                        1
+                    }
+                }
+            },
+
+            (3, 4) => {
+                let address = self.fetch_word();
+                if self.condition(y) {
+                    let pc = self.pc().value();
+                    self.push(pc);
+                    self.pc_mut().set(address);
+                    5
+                }
+                else {
+                    3
+                }
+            },
+
+            (3, 5) => {
+                if q == 0 {
+                    let value = self.reg16_af(p, mode);
+                    self.push(value);
+                    4
+                }
+                else {
+                    match p {
+                        0 => {
+                            let address = self.fetch_word();
+                            let pc = self.pc().value();
+                            self.push(pc);
+                            self.pc_mut().set(address);
+                            5
+                        },
+                        1 => return self.execute_prefixed(IndexMode::Ix, ports),
+                        2 => return self.execute_ed(ports),
+                        _ => return self.execute_prefixed(IndexMode::Iy, ports)
+                    }
+                }
+            },
+
+            (3, 6) => {
+                let value = self.fetch_byte();
+                self.alu(y, value);
+                2
+            },
+
+            (3, 7) => {
+                let pc = self.pc().value();
+                self.push(pc);
+                self.pc_mut().set(u16::from(y) * 8);
+                4
+            },
+
+            _ => unreachable!()
+        };
+
+        duration + prefix
+    }
+
+    /// Duration of an instruction accessing (HL) or (IX+d)/(IY+d)
+    fn indexed_duration(&self, hl_duration: usize, mode: IndexMode) -> usize {
+        match mode {
+            IndexMode::Hl => hl_duration,
+            _ => hl_duration + 3
+        }
+    }
+
+    fn relative_jump(&mut self, delta: i8) {
+        let pc = self.pc().value().wrapping_add_signed(i16::from(delta));
+        self.pc_mut().set(pc);
+    }
+
+    /// Handle the byte following a DD or FD prefix.
+    /// A prefix followed by another prefix behaves as a NOP
+    fn execute_prefixed<P: Ports>(&mut self, mode: IndexMode, ports: &mut P) -> usize {
+        let next = self.read_memory_byte(self.pc().value());
+        if matches!(next, 0xDD | 0xED | 0xFD) {
+            return 1;
+        }
+        let opcode = self.fetch_opcode();
+        if opcode == 0xCB {
+            self.execute_index_cb(mode)
+        }
+        else {
+            self.execute_main(opcode, mode, ports)
+        }
+    }
+
+    fn execute_cb(&mut self, mode: IndexMode) -> usize {
+        debug_assert_eq!(mode, IndexMode::Hl);
+        let opcode = self.fetch_opcode();
+        let x = opcode >> 6;
+        let y = (opcode >> 3) & 0b111;
+        let z = opcode & 0b111;
+
+        if z == 6 {
+            let address = self.hl().value();
+            let value = self.read_memory_byte(address);
+            match x {
+                0 => {
+                    let r = self.rot(y, value);
+                    self.write_memory_byte(address, r);
+                    4
+                },
+                1 => {
+                    self.bit(y, value, (address >> 8) as u8);
+                    3
+                },
+                2 => {
+                    self.write_memory_byte(address, value & !(1 << y));
+                    4
+                },
+                _ => {
+                    self.write_memory_byte(address, value | (1 << y));
+                    4
+                }
+            }
+        }
+        else {
+            let value = self.reg8(z, IndexMode::Hl);
+            match x {
+                0 => {
+                    let r = self.rot(y, value);
+                    self.set_reg8(z, IndexMode::Hl, r);
+                },
+                1 => self.bit(y, value, value),
+                2 => self.set_reg8(z, IndexMode::Hl, value & !(1 << y)),
+                _ => self.set_reg8(z, IndexMode::Hl, value | (1 << y))
+            }
+            2
+        }
+    }
+
+    /// DDCB/FDCB: the displacement is before the opcode that is not an M1 cycle.
+    /// The result is also copied in the register encoded in the opcode (undocumented)
+    fn execute_index_cb(&mut self, mode: IndexMode) -> usize {
+        let address = self.memory_operand_address(mode);
+        let opcode = self.fetch_byte();
+        let x = opcode >> 6;
+        let y = (opcode >> 3) & 0b111;
+        let z = opcode & 0b111;
+        let value = self.read_memory_byte(address);
+
+        let result = match x {
+            0 => self.rot(y, value),
+            1 => {
+                self.bit(y, value, (address >> 8) as u8);
+                return 6;
+            },
+            2 => value & !(1 << y),
+            _ => value | (1 << y)
+        };
+
+        self.write_memory_byte(address, result);
+        if z != 6 {
+            self.set_reg8(z, IndexMode::Hl, result);
+        }
+        7
+    }
+
+    fn execute_ed<P: Ports>(&mut self, ports: &mut P) -> usize {
+        let opcode = self.fetch_opcode();
+        let x = opcode >> 6;
+        let y = (opcode >> 3) & 0b111;
+        let z = opcode & 0b111;
+        let p = y >> 1;
+        let q = y & 1;
+
+        match (x, z) {
+            (1, 0) => {
+                let value = self.port_in(self.bc().value(), ports);
+                if y != 6 {
+                    self.set_reg8(y, IndexMode::Hl, value);
+                }
+                let f = (self.flags() & FLAG_C) | szp_flags(value);
+                self.set_flags(f);
+                4
+            },
+
+            (1, 1) => {
+                let value = if y == 6 {
+                    0
+                }
+                else {
+                    self.reg8(y, IndexMode::Hl)
+                };
+                self.port_out(self.bc().value(), value, ports);
+                4
+            },
+
+            (1, 2) => {
+                let hl = self.hl().value();
+                let value = self.reg16(p, IndexMode::Hl);
+                let r = if q == 0 {
+                    self.sbc16(hl, value)
+                }
+                else {
+                    self.adc16(hl, value)
+                };
+                self.hl_mut().set(r);
+                4
+            },
+
+            (1, 3) => {
+                let address = self.fetch_word();
+                if q == 0 {
+                    self.write_memory_word(address, self.reg16(p, IndexMode::Hl));
+                }
+                else {
+                    let value = self.read_memory_word(address);
+                    self.set_reg16(p, IndexMode::Hl, value);
+                }
+                6
+            },
+
+            (1, 4) => {
+                let a = self.a().value();
+                self.a_mut().set(0);
+                self.sub8(a, 0);
+                2
+            },
+
+            (1, 5) => {
+                self.extra.iff1 = self.extra.iff2;
+                let address = self.pop();
+                self.pc_mut().set(address);
+                4
+            },
+
+            (1, 6) => {
+                self.extra.im = [0, 0, 1, 2][usize::from(y & 0b11)];
+                2
+            },
+
+            (1, 7) => {
+                match y {
+                    0 => {
+                        let a = self.a().value();
+                        self.i_mut().set(a);
+                        3
+                    },
+                    1 => {
+                        let a = self.a().value();
+                        self.r_mut().set(a);
+                        3
+                    },
+                    2 | 3 => {
+                        let value = if y == 2 {
+                            self.i().value()
+                        }
+                        else {
+                            self.r().value()
+                        };
+                        self.a_mut().set(value);
+                        let mut f = (self.flags() & FLAG_C) | sz_flags(value);
+                        if self.extra.iff2 {
+                            f |= FLAG_P;
+                        }
+                        self.set_flags(f);
+                        3
+                    },
+                    4 | 5 => {
+                        let address = self.hl().value();
+                        let memory = self.read_memory_byte(address);
+                        let a = self.a().value();
+                        let (new_a, new_memory) = if y == 4 {
+                            // RRD
+                            ((a & 0xF0) | (memory & 0x0F), (a << 4) | (memory >> 4))
+                        }
+                        else {
+                            // RLD
+                            ((a & 0xF0) | (memory >> 4), (memory << 4) | (a & 0x0F))
+                        };
+                        self.a_mut().set(new_a);
+                        self.write_memory_byte(address, new_memory);
+                        let f = (self.flags() & FLAG_C) | szp_flags(new_a);
+                        self.set_flags(f);
+                        5
+                    },
+                    _ => 2
+                }
+            },
+
+            (2, 0..=3) if y >= 4 => self.execute_block(y, z, ports),
+
+            _ => 2
+        }
+    }
+
+    /// LDI/CPI/INI/OUTI and their decrementing and repeating variants
+    fn execute_block<P: Ports>(&mut self, y: u8, z: u8, ports: &mut P) -> usize {
+        let decrement = y & 1 == 1;
+        let repeat = y >= 6;
+        let hl = self.hl().value();
+        let next_hl = if decrement {
+            hl.wrapping_sub(1)
+        }
+        else {
+            hl.wrapping_add(1)
+        };
+
+        let (keep_going, base_duration) = match z {
+            0 => {
+                let de = self.de().value();
+                let value = self.read_memory_byte(hl);
+                self.write_memory_byte(de, value);
+                let next_de = if decrement {
+                    de.wrapping_sub(1)
+                }
+                else {
+                    de.wrapping_add(1)
+                };
+                self.de_mut().set(next_de);
+                let bc = self.bc().value().wrapping_sub(1);
+                self.bc_mut().set(bc);
+
+                let n = value.wrapping_add(self.a().value());
+                let mut f = (self.flags() & (FLAG_S | FLAG_Z | FLAG_C))
+                    | (n & FLAG_X)
+                    | ((n << 4) & FLAG_Y);
+                if bc != 0 {
+                    f |= FLAG_P;
+                }
+                self.set_flags(f);
+                (bc != 0, 5)
+            },
+            1 => {
+                let value = self.read_memory_byte(hl);
+                let carry = self.flags() & FLAG_C;
+                let r = self.sub8_flags(value, 0);
+                let bc = self.bc().value().wrapping_sub(1);
+                self.bc_mut().set(bc);
+
+                let n = r.wrapping_sub((self.flags() & FLAG_H) >> 4);
+                let mut f = (self.flags() & (FLAG_S | FLAG_Z | FLAG_H))
+                    | FLAG_N
+                    | carry
+                    | (n & FLAG_X)
+                    | ((n << 4) & FLAG_Y);
+                if bc != 0 {
+                    f |= FLAG_P;
+                }
+                self.set_flags(f);
+                (bc != 0 && r != 0, 4)
+            },
+            2 => {
+                let value = self.port_in(self.bc().value(), ports);
+                self.write_memory_byte(hl, value);
+                let b = self.b().value().wrapping_sub(1);
+                self.b_mut().set(b);
+                self.set_flags(sz_flags(b) | FLAG_N);
+                (b != 0, 5)
+            },
+            _ => {
+                let value = self.read_memory_byte(hl);
+                let b = self.b().value().wrapping_sub(1);
+                self.b_mut().set(b);
+                self.port_out(self.bc().value(), value, ports);
+                self.set_flags(sz_flags(b) | FLAG_N);
+                (b != 0, 5)
+            }
+        };
+
+        self.hl_mut().set(next_hl);
+
+        if repeat && keep_going {
+            let pc = self.pc().value().wrapping_sub(2);
+            self.pc_mut().set(pc);
+            6
+        }
+        else {
+            base_duration
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Build a z80 with the given code at 0x4000
+    fn z80_with_code(code: &[u8]) -> Z80 {
+        let mut z80 = Z80::default();
+        z80.memory_mut().load_bytes(0x4000, code);
+        z80.pc_mut().set(0x4000);
+        z80.sp_mut().set(0xC000);
+        z80
+    }
+
+    #[test]
+    fn ld_add_store() {
+        // ld a, 5 : add 3 : ld (0x8000), a
+        let mut z80 = z80_with_code(&[0x3E, 0x05, 0xC6, 0x03, 0x32, 0x00, 0x80]);
+        assert_eq!(z80.step(), 2);
+        assert_eq!(z80.step(), 2);
+        assert_eq!(z80.step(), 4);
+        assert_eq!(z80.read_memory_byte(0x8000), 8);
+        assert_eq!(z80.pc().value(), 0x4007);
+    }
+
+    #[test]
+    fn flags_of_sub() {
+        // xor a : sub 1
+        let mut z80 = z80_with_code(&[0xAF, 0xD6, 0x01]);
+        z80.step();
+        assert_eq!(z80.f().value() & (FLAG_Z | FLAG_P), FLAG_Z | FLAG_P);
+        z80.step();
+        assert_eq!(z80.a().value(), 0xFF);
+        assert_eq!(
+            z80.f().value() & (FLAG_S | FLAG_Z | FLAG_C | FLAG_N),
+            FLAG_S | FLAG_C | FLAG_N
+        );
+    }
+
+    #[test]
+    fn djnz_loop() {
+        // ld b, 3 : loop djnz loop
+        let mut z80 = z80_with_code(&[0x06, 0x03, 0x10, 0xFE]);
+        let nops = z80.run_until(0x4004, 100, &mut NoPorts).unwrap();
+        assert_eq!(nops, 2 + 4 + 4 + 3);
+        assert_eq!(z80.b().value(), 0);
+    }
+
+    #[test]
+    fn call_ret() {
+        // call 0x4010 ... 0x4010: ld a, 0x12 : ret
+        let mut code = vec![0xCD, 0x10, 0x40];
+        code.resize(0x10, 0);
+        code.extend_from_slice(&[0x3E, 0x12, 0xC9]);
+        let mut z80 = z80_with_code(&code);
+
+        assert_eq!(z80.step(), 5);
+        assert_eq!(z80.sp().value(), 0xBFFE);
+        assert_eq!(z80.read_memory_word(0xBFFE), 0x4003);
+        z80.step();
+        assert_eq!(z80.step(), 3);
+        assert_eq!(z80.pc().value(), 0x4003);
+        assert_eq!(z80.a().value(), 0x12);
+    }
+
+    #[test]
+    fn indexed_access() {
+        // ld ix, 0x8000 : ld (ix+2), 0x55 : ld a, (ix+2) : inc (ix+2)
+        let mut z80 = z80_with_code(&[
+            0xDD, 0x21, 0x00, 0x80, 0xDD, 0x36, 0x02, 0x55, 0xDD, 0x7E, 0x02, 0xDD, 0x34, 0x02
+        ]);
+        assert_eq!(z80.step(), 4);
+        assert_eq!(z80.step(), 6);
+        assert_eq!(z80.step(), 5);
+        assert_eq!(z80.a().value(), 0x55);
+        assert_eq!(z80.step(), 6);
+        assert_eq!(z80.read_memory_byte(0x8002), 0x56);
+    }
+
+    #[test]
+    fn ldir() {
+        // ld hl, 0x8000 : ld de, 0x9000 : ld bc, 3 : ldir
+        let mut z80 = z80_with_code(&[
+            0x21, 0x00, 0x80, 0x11, 0x00, 0x90, 0x01, 0x03, 0x00, 0xED, 0xB0
+        ]);
+        z80.memory_mut().load_bytes(0x8000, &[1, 2, 3]);
+        let nops = z80.run_until(0x400B, 100, &mut NoPorts).unwrap();
+        assert_eq!(nops, 3 * 3 + 6 + 6 + 5);
+        assert_eq!(&z80.memory().ram()[0x9000..0x9003], &[1, 2, 3]);
+        assert_eq!(z80.bc().value(), 0);
+    }
+
+    #[test]
+    fn bank_switching_with_out() {
+        // ld bc, 0x7FC4 : out (c), c : ld a, 0x42 : ld (0x4000), a
+        // the code is not at 0x4000 as this bank is switched
+        let mut z80 = Z80::default();
+        z80.memory_mut().load_bytes(
+            0x8000,
+            &[0x01, 0xC4, 0x7F, 0xED, 0x49, 0x3E, 0x42, 0x32, 0x00, 0x40]
+        );
+        z80.pc_mut().set(0x8000);
+        for _ in 0..4 {
+            z80.step();
+        }
+        assert_eq!(z80.memory().mmr(), 0xC4);
+        assert_eq!(z80.memory().ram()[0x1_0000], 0x42);
+    }
+
+    #[test]
+    fn interrupt_mode_1() {
+        // ei : nop : halt
+        let mut z80 = z80_with_code(&[0xFB, 0x00, 0x76]);
+        z80.set_interrupt_mode(1);
+        z80.step();
+        assert_eq!(z80.interrupt(), 0);
+        z80.step();
+        z80.step();
+        assert!(z80.is_halted());
+        assert_eq!(z80.interrupt(), 5);
+        assert!(!z80.is_halted());
+        assert_eq!(z80.pc().value(), 0x38);
+        assert_eq!(z80.read_memory_word(z80.sp().value()), 0x4003);
+    }
+
+    #[test]
+    fn run_assembled_code() {
+        let code = cpclib_asm::assemble(
+            "
+            org 0x4000
+            ld hl, 0x8000
+            ld b, 10
+            xor a
+loop
+            add b
+            ld (hl), a
+            inc hl
+            djnz loop
+            "
+        )
+        .unwrap();
+        let mut z80 = z80_with_code(&code);
+        z80.run_until(0x4000 + code.len() as u16, 1000, &mut NoPorts)
+            .unwrap();
+        assert_eq!(z80.read_memory_byte(0x8009), 55);
+    }
+}
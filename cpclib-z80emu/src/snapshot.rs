@@ -0,0 +1,108 @@
+//! Exchange of the CPU and memory state with a snapshot
+use cpclib_sna::{Snapshot, SnapshotFlag};
+
+use crate::z80::{HasValue, Z80};
+
+pub(crate) fn flag(sna: &Snapshot, flag: SnapshotFlag) -> u16 {
+    sna.get_value(&flag).as_u16().unwrap()
+}
+
+pub(crate) fn set_flag(sna: &mut Snapshot, flag: SnapshotFlag, value: u16) {
+    sna.set_value(flag, value).unwrap();
+}
+
+impl Z80 {
+    /// Build a Z80 whose registers and memory come from the snapshot
+    pub fn from_snapshot(sna: &Snapshot) -> Self {
+        let mut z80 = Self::default();
+        z80.load_snapshot(sna);
+        z80
+    }
+
+    /// Replace the registers, interrupt state and memory by the ones of the snapshot
+    pub fn load_snapshot(&mut self, sna: &Snapshot) {
+        self.af_mut().set(flag(sna, SnapshotFlag::Z80_AF));
+        self.bc_mut().set(flag(sna, SnapshotFlag::Z80_BC));
+        self.de_mut().set(flag(sna, SnapshotFlag::Z80_DE));
+        self.hl_mut().set(flag(sna, SnapshotFlag::Z80_HL));
+        self.ix_mut().set(flag(sna, SnapshotFlag::Z80_IX));
+        self.iy_mut().set(flag(sna, SnapshotFlag::Z80_IY));
+        self.sp_mut().set(flag(sna, SnapshotFlag::Z80_SP));
+        self.pc_mut().set(flag(sna, SnapshotFlag::Z80_PC));
+        self.i_mut().set(flag(sna, SnapshotFlag::Z80_I) as u8);
+        self.r_mut().set(flag(sna, SnapshotFlag::Z80_R) as u8);
+
+        self.af_prime_mut().set(flag(sna, SnapshotFlag::Z80_AFX));
+        self.bc_prime_mut().set(flag(sna, SnapshotFlag::Z80_BCX));
+        self.de_prime_mut().set(flag(sna, SnapshotFlag::Z80_DEX));
+        self.hl_prime_mut().set(flag(sna, SnapshotFlag::Z80_HLX));
+
+        // Snapshot names them IFF0/IFF1 whereas Zilog speaks about IFF1/IFF2
+        self.set_iff1(flag(sna, SnapshotFlag::Z80_IFF0) != 0);
+        self.set_iff2(flag(sna, SnapshotFlag::Z80_IFF1) != 0);
+        self.set_interrupt_mode(flag(sna, SnapshotFlag::Z80_IM) as u8);
+        self.set_halted(false);
+
+        let memory = self.memory_mut();
+        memory.load_dump(&sna.memory_dump());
+        memory.set_mmr(flag(sna, SnapshotFlag::GA_RAMCFG) as u8);
+    }
+
+    /// Store the registers, interrupt state and memory in the snapshot.
+    /// The other hardware values of the snapshot are kept untouched
+    pub fn update_snapshot(&self, sna: &mut Snapshot) {
+        set_flag(sna, SnapshotFlag::Z80_AF, self.af().value());
+        set_flag(sna, SnapshotFlag::Z80_BC, self.bc().value());
+        set_flag(sna, SnapshotFlag::Z80_DE, self.de().value());
+        set_flag(sna, SnapshotFlag::Z80_HL, self.hl().value());
+        set_flag(sna, SnapshotFlag::Z80_IX, self.ix().value());
+        set_flag(sna, SnapshotFlag::Z80_IY, self.iy().value());
+        set_flag(sna, SnapshotFlag::Z80_SP, self.sp().value());
+        set_flag(sna, SnapshotFlag::Z80_PC, self.pc().value());
+        set_flag(sna, SnapshotFlag::Z80_I, self.i().value().into());
+        set_flag(sna, SnapshotFlag::Z80_R, self.r().value().into());
+
+        set_flag(sna, SnapshotFlag::Z80_AFX, self.af_prime().value());
+        set_flag(sna, SnapshotFlag::Z80_BCX, self.bc_prime().value());
+        set_flag(sna, SnapshotFlag::Z80_DEX, self.de_prime().value());
+        set_flag(sna, SnapshotFlag::Z80_HLX, self.hl_prime().value());
+
+        set_flag(sna, SnapshotFlag::Z80_IFF0, self.iff1().into());
+        set_flag(sna, SnapshotFlag::Z80_IFF1, self.iff2().into());
+        set_flag(sna, SnapshotFlag::Z80_IM, self.interrupt_mode().into());
+
+        set_flag(sna, SnapshotFlag::GA_RAMCFG, self.memory().mmr().into());
+
+        let ram = self.memory().ram();
+        sna.resize(self.memory().nb_pages());
+        for (address, byte) in ram.iter().enumerate() {
+            sna.set_byte(address as u32, *byte);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut sna = Snapshot::default();
+        sna.set_value(SnapshotFlag::Z80_PC, 0x4000).unwrap();
+        sna.set_value(SnapshotFlag::Z80_SP, 0xBFF0).unwrap();
+        // ld a, 0x42 : ld (0x8000), a
+        sna.add_data(&[0x3E, 0x42, 0x32, 0x00, 0x80], 0x4000)
+            .unwrap();
+
+        let mut z80 = Z80::from_snapshot(&sna);
+        assert_eq!(z80.pc().value(), 0x4000);
+        assert_eq!(z80.sp().value(), 0xBFF0);
+        z80.step();
+        z80.step();
+
+        z80.update_snapshot(&mut sna);
+        assert_eq!(sna.get_byte(0x8000), 0x42);
+        assert_eq!(sna.get_value(&SnapshotFlag::Z80_PC).as_u16(), Some(0x4005));
+        assert_eq!(sna.get_value(&SnapshotFlag::Z80_A).as_u16(), Some(0x42));
+    }
+}
@@ -10,14 +10,20 @@
 //! control-flow boundary to a hover position). It operates directly on
 //! `cpclib_asm`'s parsed `LocatedToken`/`LocatedDataAccess` — no
 //! assembled-bytes/memory-bus step needed.
+//!
+//! [`liveness`] works the other way round: it walks a whole flat token
+//! sequence backward (following the jumps to its own labels) to tell which
+//! registers and flags may still be read after each instruction. basm's
+//! peephole optimiser (`cpclib_asm::rewrite`) relies on it.
 
 use std::collections::HashMap;
 
 use cpclib_asm::assembler::Env;
 use cpclib_asm::implementation::expression::ExprEvaluationExt;
 use cpclib_asm::parser::obtained::{LocatedDataAccess, LocatedToken};
+use cpclib_asm::rewrite::{LabelIndex, LiveSet, Resource};
 use cpclib_tokens::{
-    IndexRegister8, IndexRegister16, ListingElement, Mnemonic, Register8, Register16
+    ExprElement, IndexRegister8, IndexRegister16, ListingElement, Mnemonic, Register8, Register16
 };
 
 /// Tracked register/flag state. Every field's absence means "not statically
@@ -393,7 +399,11 @@ pub fn apply(state: &mut TrackedState, token: &LocatedToken, env: &mut Env) {
             // but if one somehow does, the safe behavior is a full reset.
             state.invalidate_all();
         },
-        Mnemonic::Jp | Mnemonic::Jr | Mnemonic::Jq | Mnemonic::Djnz | Mnemonic::Reti
+        Mnemonic::Jp
+        | Mnemonic::Jr
+        | Mnemonic::Jq
+        | Mnemonic::Djnz
+        | Mnemonic::Reti
         | Mnemonic::Retn => {
             state.invalidate_all();
         },
@@ -733,6 +743,427 @@ fn apply_rotate_shift(
     state.flag_c = carry_out;
 }
 
+// ─── liveness ──────────────────────────────────────────────────────────────
+
+/// Where the execution continues after a token
+enum Flow {
+    /// The next token
+    Next,
+    /// The target token (None when it is not known)
+    Jump(Option<usize>),
+    /// The target token or the next one
+    Branch(Option<usize>),
+    /// Somewhere else (return, computed jump)
+    Leave
+}
+
+/// Registers and flags an instruction reads and those it always overwrites.
+/// When in doubt, reads are over-approximated and writes under-approximated.
+struct Effect {
+    reads: LiveSet,
+    writes: LiveSet,
+    flow: Flow
+}
+
+impl Effect {
+    fn new(reads: LiveSet, writes: LiveSet) -> Self {
+        Self {
+            reads,
+            writes,
+            flow: Flow::Next
+        }
+    }
+
+    /// Anything may be read (call, macro, directive generating unknown code...)
+    fn barrier() -> Self {
+        Self::new(LiveSet::all(), LiveSet::empty())
+    }
+}
+
+/// Registers read when `access` is used as a source (including the
+/// registers needed to compute a memory address)
+fn source(access: Option<&LocatedDataAccess>) -> LiveSet {
+    match access {
+        Some(LocatedDataAccess::Register8(r, _)) => Resource::register8(*r).into(),
+        Some(LocatedDataAccess::IndexRegister8(r, _)) => Resource::index8(*r).into(),
+        Some(LocatedDataAccess::Register16(r, _) | LocatedDataAccess::MemoryRegister16(r, _)) => {
+            Resource::register16(*r)
+        },
+        Some(
+            LocatedDataAccess::IndexRegister16(r, _)
+            | LocatedDataAccess::MemoryIndexRegister16(r, _)
+            | LocatedDataAccess::IndexRegister16WithIndex(r, ..)
+        ) => Resource::index16(*r),
+        Some(LocatedDataAccess::PortC(_)) => Resource::B | Resource::C,
+        _ => LiveSet::empty()
+    }
+}
+
+/// Registers needed to compute the address of a memory destination
+fn address(access: Option<&LocatedDataAccess>) -> LiveSet {
+    match access {
+        Some(
+            LocatedDataAccess::MemoryRegister16(..)
+            | LocatedDataAccess::MemoryIndexRegister16(..)
+            | LocatedDataAccess::IndexRegister16WithIndex(..)
+        ) => source(access),
+        _ => LiveSet::empty()
+    }
+}
+
+/// Registers overwritten when `access` is used as a destination
+fn destination(access: Option<&LocatedDataAccess>) -> LiveSet {
+    match access {
+        Some(
+            LocatedDataAccess::Register8(..)
+            | LocatedDataAccess::IndexRegister8(..)
+            | LocatedDataAccess::Register16(..)
+            | LocatedDataAccess::IndexRegister16(..)
+        ) => source(access),
+        _ => LiveSet::empty()
+    }
+}
+
+fn jump_target(
+    access: Option<&LocatedDataAccess>,
+    labels: &LabelIndex,
+    idx: usize
+) -> Option<usize> {
+    match access {
+        Some(LocatedDataAccess::Expression(expr)) if expr.is_label() => {
+            labels.resolve(expr.label(), idx)
+        },
+        _ => None
+    }
+}
+
+fn effect(token: &LocatedToken, labels: &LabelIndex, idx: usize) -> Effect {
+    if token.is_fake_instruction() {
+        return Effect::barrier();
+    }
+    if token.is_warning() {
+        return effect(token.warning_token(), labels, idx);
+    }
+    if token.is_label() || token.is_comment() || token.is_equ() || token.is_assign() {
+        return Effect::new(LiveSet::empty(), LiveSet::empty());
+    }
+    let Some(mnemonic) = token.mnemonic()
+    else {
+        return Effect::barrier();
+    };
+    let arg1 = token.mnemonic_arg1();
+    let arg2 = token.mnemonic_arg2();
+
+    let flags = Resource::flags();
+    // every flag except the carry, for the instructions that preserve it
+    let no_carry = flags & !LiveSet::from(Resource::FlagC);
+    let bcdehl = Resource::register16(Register16::Bc)
+        | Resource::register16(Register16::De)
+        | Resource::register16(Register16::Hl);
+    let none = LiveSet::empty();
+
+    match mnemonic {
+        Mnemonic::Nop
+        | Mnemonic::Nop2
+        | Mnemonic::Di
+        | Mnemonic::Ei
+        | Mnemonic::Im
+        | Mnemonic::Halt => Effect::new(none, none),
+
+        Mnemonic::Ld => {
+            match (arg1, arg2) {
+                (
+                    _,
+                    Some(
+                        LocatedDataAccess::SpecialRegisterI(_)
+                        | LocatedDataAccess::SpecialRegisterR(_)
+                    )
+                ) => Effect::new(none, destination(arg1) | no_carry),
+                _ => Effect::new(source(arg2) | address(arg1), destination(arg1))
+            }
+        },
+
+        Mnemonic::Push => Effect::new(source(arg1) | Resource::Sp, Resource::Sp.into()),
+        Mnemonic::Pop => Effect::new(Resource::Sp.into(), destination(arg1) | Resource::Sp),
+
+        // the previous values go to the shadow registers or the stack and may come back
+        Mnemonic::ExAf => Effect::new(Resource::register16(Register16::Af), none),
+        Mnemonic::Exx => Effect::new(bcdehl, none),
+        Mnemonic::ExHlDe => {
+            Effect::new(
+                Resource::register16(Register16::De) | Resource::register16(Register16::Hl),
+                none
+            )
+        },
+        Mnemonic::ExMemSp => Effect::new(source(arg1) | Resource::Sp, none),
+
+        Mnemonic::Add
+        | Mnemonic::Adc
+        | Mnemonic::Sub
+        | Mnemonic::Sbc
+        | Mnemonic::And
+        | Mnemonic::Or
+        | Mnemonic::Xor
+        | Mnemonic::Cp => {
+            let carry = if matches!(mnemonic, Mnemonic::Adc | Mnemonic::Sbc) {
+                LiveSet::from(Resource::FlagC)
+            }
+            else {
+                none
+            };
+
+            if matches!(
+                arg1,
+                Some(LocatedDataAccess::Register16(..) | LocatedDataAccess::IndexRegister16(..))
+            ) {
+                // add hl,rr preserves S, Z and P/V
+                let written_flags = if *mnemonic == Mnemonic::Add {
+                    Resource::FlagH | Resource::FlagN | Resource::FlagC
+                }
+                else {
+                    flags
+                };
+                Effect::new(
+                    source(arg1) | source(arg2) | carry,
+                    destination(arg1) | written_flags
+                )
+            }
+            else {
+                let operand = arg2.or(arg1);
+                let result = if *mnemonic == Mnemonic::Cp {
+                    none
+                }
+                else {
+                    LiveSet::from(Resource::A)
+                };
+                // xor a, sub a and cp a do not depend on the accumulator
+                let zero_idiom = matches!(mnemonic, Mnemonic::Xor | Mnemonic::Sub | Mnemonic::Cp)
+                    && matches!(operand, Some(LocatedDataAccess::Register8(Register8::A, _)));
+                let reads = if zero_idiom {
+                    none
+                }
+                else {
+                    source(operand) | Resource::A | carry
+                };
+                Effect::new(reads, result | flags)
+            }
+        },
+
+        Mnemonic::Inc | Mnemonic::Dec => {
+            match arg1 {
+                Some(LocatedDataAccess::Register8(..) | LocatedDataAccess::IndexRegister8(..)) => {
+                    Effect::new(source(arg1), destination(arg1) | no_carry)
+                },
+                Some(
+                    LocatedDataAccess::Register16(..) | LocatedDataAccess::IndexRegister16(..)
+                ) => Effect::new(source(arg1), destination(arg1)),
+                _ => Effect::new(address(arg1), no_carry)
+            }
+        },
+
+        Mnemonic::Rlca | Mnemonic::Rrca => {
+            Effect::new(
+                Resource::A.into(),
+                Resource::A | Resource::FlagH | Resource::FlagN | Resource::FlagC
+            )
+        },
+        Mnemonic::Rla | Mnemonic::Rra => {
+            Effect::new(
+                Resource::A | Resource::FlagC,
+                Resource::A | Resource::FlagH | Resource::FlagN | Resource::FlagC
+            )
+        },
+        Mnemonic::Rlc
+        | Mnemonic::Rrc
+        | Mnemonic::Rl
+        | Mnemonic::Rr
+        | Mnemonic::Sla
+        | Mnemonic::Sra
+        | Mnemonic::Srl
+        | Mnemonic::Sl1 => {
+            let carry = if matches!(mnemonic, Mnemonic::Rl | Mnemonic::Rr) {
+                LiveSet::from(Resource::FlagC)
+            }
+            else {
+                none
+            };
+            // the second argument is the register that receives a copy of the result
+            Effect::new(
+                source(arg1) | carry,
+                destination(arg1) | destination(arg2) | flags
+            )
+        },
+        Mnemonic::Rld | Mnemonic::Rrd => {
+            Effect::new(
+                Resource::A | Resource::H | Resource::L,
+                no_carry | Resource::A
+            )
+        },
+
+        Mnemonic::Bit => Effect::new(source(arg2), no_carry),
+        Mnemonic::Res | Mnemonic::Set => Effect::new(source(arg2), destination(arg2)),
+
+        Mnemonic::Cpl => {
+            Effect::new(
+                Resource::A.into(),
+                Resource::A | Resource::FlagH | Resource::FlagN
+            )
+        },
+        Mnemonic::Neg => Effect::new(Resource::A.into(), flags | Resource::A),
+        Mnemonic::Daa => {
+            Effect::new(
+                Resource::A | Resource::FlagH | Resource::FlagN | Resource::FlagC,
+                flags | Resource::A
+            )
+        },
+        Mnemonic::Scf => Effect::new(none, Resource::FlagH | Resource::FlagN | Resource::FlagC),
+        Mnemonic::Ccf => {
+            Effect::new(
+                Resource::FlagC.into(),
+                Resource::FlagH | Resource::FlagN | Resource::FlagC
+            )
+        },
+
+        Mnemonic::Ldi | Mnemonic::Ldd | Mnemonic::Ldir | Mnemonic::Lddr => {
+            Effect::new(
+                bcdehl,
+                bcdehl | Resource::FlagH | Resource::FlagPV | Resource::FlagN
+            )
+        },
+        Mnemonic::Cpi | Mnemonic::Cpd | Mnemonic::Cpir | Mnemonic::Cpdr => {
+            Effect::new(
+                Resource::A | Resource::B | Resource::C | Resource::H | Resource::L,
+                Resource::B | Resource::C | Resource::H | Resource::L | no_carry
+            )
+        },
+        Mnemonic::Ini
+        | Mnemonic::Ind
+        | Mnemonic::Inir
+        | Mnemonic::Indr
+        | Mnemonic::Outi
+        | Mnemonic::Outd
+        | Mnemonic::Otir
+        | Mnemonic::Otdr => {
+            Effect::new(
+                Resource::B | Resource::C | Resource::H | Resource::L,
+                Resource::B | Resource::H | Resource::L | Resource::FlagZ | Resource::FlagN
+            )
+        },
+
+        Mnemonic::In => {
+            match arg2 {
+                Some(LocatedDataAccess::PortC(_)) => {
+                    Effect::new(Resource::B | Resource::C, destination(arg1) | no_carry)
+                },
+                // in a,(n) uses a as the high byte of the port
+                _ => Effect::new(Resource::A.into(), destination(arg1))
+            }
+        },
+        Mnemonic::Out => Effect::new(source(arg2) | Resource::A | Resource::B | Resource::C, none),
+
+        Mnemonic::Jp | Mnemonic::Jr => {
+            let condition = match arg1 {
+                Some(LocatedDataAccess::FlagTest(test, _)) => Some(*test),
+                _ => None
+            };
+            let target = arg2;
+            let reads = condition
+                .map(|test| LiveSet::from(Resource::flag_test(test)))
+                .unwrap_or_default();
+
+            match target {
+                Some(
+                    LocatedDataAccess::MemoryRegister16(..)
+                    | LocatedDataAccess::MemoryIndexRegister16(..)
+                ) => {
+                    Effect {
+                        reads: reads | source(target),
+                        writes: none,
+                        flow: Flow::Leave
+                    }
+                },
+                _ => {
+                    let target = jump_target(target, labels, idx);
+                    Effect {
+                        reads,
+                        writes: none,
+                        flow: if condition.is_some() {
+                            Flow::Branch(target)
+                        }
+                        else {
+                            Flow::Jump(target)
+                        }
+                    }
+                }
+            }
+        },
+        Mnemonic::Djnz => {
+            Effect {
+                reads: Resource::B.into(),
+                writes: Resource::B.into(),
+                flow: Flow::Branch(jump_target(arg1, labels, idx))
+            }
+        },
+        Mnemonic::Ret | Mnemonic::Reti | Mnemonic::Retn => {
+            Effect {
+                reads: none,
+                writes: none,
+                flow: Flow::Leave
+            }
+        },
+
+        // the called code may read anything
+        Mnemonic::Call | Mnemonic::Rst => Effect::barrier(),
+        Mnemonic::Jq | Mnemonic::Srl8 => Effect::barrier()
+    }
+}
+
+/// Backward liveness analysis of a flat sequence of tokens: for each token,
+/// the registers and flags whose current value may still be read after it.
+///
+/// Unlike `apply`, jumps are followed when their target is a label of the
+/// sequence. Anything leaving the sequence (return, jump to an unknown
+/// address, end of the tokens) is considered to read every register and
+/// flag, as do calls, macro calls and directives.
+pub fn liveness(tokens: &[LocatedToken]) -> Vec<LiveSet> {
+    let labels = LabelIndex::new(tokens);
+    let effects = tokens
+        .iter()
+        .enumerate()
+        .map(|(idx, token)| effect(token, &labels, idx))
+        .collect::<Vec<_>>();
+
+    let count = tokens.len();
+    let mut live_in = vec![LiveSet::empty(); count + 1];
+    live_in[count] = LiveSet::all();
+    let mut live_out = vec![LiveSet::empty(); count];
+
+    // iterate up to the fixed point as backward jumps make the information flow upward
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for idx in (0..count).rev() {
+            let at = |target: Option<usize>| target.map(|t| live_in[t]).unwrap_or(LiveSet::all());
+            let effect = &effects[idx];
+            let out = match effect.flow {
+                Flow::Next => live_in[idx + 1],
+                Flow::Jump(target) => at(target),
+                Flow::Branch(target) => live_in[idx + 1] | at(target),
+                Flow::Leave => LiveSet::all()
+            };
+            let live = (out & !effect.writes) | effect.reads;
+
+            if out != live_out[idx] || live != live_in[idx] {
+                live_out[idx] = out;
+                live_in[idx] = live;
+                changed = true;
+            }
+        }
+    }
+
+    live_out
+}
+
 #[cfg(test)]
 mod tests {
     use cpclib_asm::assembler::Env;
@@ -1050,4 +1481,46 @@ mod tests {
         let s = apply_all("ld a, 300\n");
         assert_eq!(s.get8(Register8::A), Some((300i32 & 0xFF) as u8));
     }
+
+    fn live_after(text: &str) -> Vec<LiveSet> {
+        let builder = ParserContextBuilder::default().set_quiet(true);
+        let listing = LocatedListing::new_complete_source(text, builder)
+            .unwrap_or_else(|_| panic!("expected {text:?} to parse cleanly"));
+        liveness(listing.listing())
+    }
+
+    #[test]
+    fn overwritten_register_is_dead() {
+        let live = live_after(" ld a,1\n ld a,2\n ret\n");
+        assert!(!live[0].contains(Resource::A));
+        assert!(live[1].contains(Resource::A));
+        // the end of the code may be followed by anything
+        assert_eq!(live[2], LiveSet::all());
+    }
+
+    #[test]
+    fn flags_are_live_before_a_conditional_jump() {
+        let live = live_after(" xor a\n or b\n jr z,skip\n ld a,3\nskip\n ld a,4\n cp 5\n ret\n");
+        assert!(live[1].contains(Resource::FlagZ));
+        assert!(!live[1].contains(Resource::FlagC));
+        // xor a is overwritten by or b whatever its flags
+        assert!(!live[0].contains(Resource::FlagZ));
+        // a is overwritten on both paths
+        assert!(!live[2].contains(Resource::A));
+    }
+
+    #[test]
+    fn loops_propagate_liveness_backward() {
+        let live = live_after(" ld b,4\n ld c,0\nloop\n inc c\n djnz loop\n ld a,c\n ret\n");
+        // c is read by the loop body through the back edge
+        assert!(live[1].contains(Resource::C));
+        assert!(live[0].contains(Resource::B));
+        assert!(!live[0].contains(Resource::C));
+    }
+
+    #[test]
+    fn calls_read_everything() {
+        let live = live_after(" ld a,1\n call 0xbb5a\n ld a,2\n ret\n");
+        assert_eq!(live[0], LiveSet::all());
+    }
 }
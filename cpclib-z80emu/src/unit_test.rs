@@ -0,0 +1,290 @@
+//! Execution of the TEST blocks collected by the assembler
+use cpclib_asm::assembler::unit_test::UnitTest;
+use cpclib_asm::error::AssemblerError;
+use cpclib_asm::preamble::Z80Span;
+use cpclib_sna::Snapshot;
+use cpclib_tokens::{BinaryOperation, UnitTestExpectation, UnitTestRegister, UnitTestStep};
+
+use crate::emul::FlagIsActive;
+use crate::preamble::DataAccess;
+use crate::z80::{HasValue, Z80};
+
+/// Stack pointer used when the test does not set it
+pub const DEFAULT_STACK: u16 = 0xC000;
+/// Return address pushed by CALL. The routine has returned when PC reaches it with the initial stack
+const RETURN_ADDRESS: u16 = 0x0000;
+/// Duration after which a routine is considered to never return (20 seconds of CPC)
+pub const MAX_NOPS: usize = 20_000_000;
+
+/// Execute a TEST block on a Z80 whose memory comes from the assembled snapshot.
+/// The first failing step is reported as an error located on its line
+pub fn run_unit_test(sna: &Snapshot, test: &UnitTest) -> Result<(), Box<AssemblerError>> {
+    let mut z80 = Z80::from_snapshot(sna);
+    z80.sp_mut().set(DEFAULT_STACK);
+    z80.set_iff1(false);
+    z80.set_iff2(false);
+
+    let mut last_call_nops = None;
+    for (step, span) in test.steps() {
+        run_step(&mut z80, step, &mut last_call_nops).map_err(|msg| {
+            let error = AssemblerError::AssertionFailed {
+                test: step.to_string(),
+                msg,
+                guidance: format!("TEST \"{}\" failed", test.name())
+            };
+            Box::new(match span.as_ref().or(test.span()) {
+                Some(span) => error.locate(Z80Span::clone(span)),
+                None => error
+            })
+        })?;
+    }
+
+    Ok(())
+}
+
+fn run_step(
+    z80: &mut Z80,
+    step: &UnitTestStep<i32>,
+    last_call_nops: &mut Option<usize>
+) -> Result<(), String> {
+    match step {
+        UnitTestStep::Load(reg, value) => {
+            let value = checked_value(*value, reg.is_8bits())?;
+            let access = data_access(reg);
+            if reg.is_8bits() {
+                z80.get_register_8_mut(&access).set(value as u8);
+            }
+            else {
+                z80.get_register_16_mut(&access).set(value);
+            }
+        },
+        UnitTestStep::Poke(address, bytes) => {
+            let address = checked_value(*address, false)?;
+            for (offset, byte) in bytes.iter().enumerate() {
+                let byte = checked_value(*byte, true)?;
+                z80.write_memory_byte(address.wrapping_add(offset as u16), byte as u8);
+            }
+        },
+        UnitTestStep::Call(routine) => {
+            let routine = checked_value(*routine, false)?;
+            *last_call_nops = Some(call(z80, routine)?);
+        },
+        UnitTestStep::Expect(expectation) => check(z80, expectation, *last_call_nops)?
+    }
+
+    Ok(())
+}
+
+/// Execute the routine until it returns and provide its duration in nops
+fn call(z80: &mut Z80, routine: u16) -> Result<usize, String> {
+    let stack = z80.sp().value();
+    let sp = stack.wrapping_sub(2);
+    z80.sp_mut().set(sp);
+    z80.write_memory_word(sp, RETURN_ADDRESS);
+    z80.pc_mut().set(routine);
+
+    let mut nops = 0;
+    while z80.pc().value() != RETURN_ADDRESS || z80.sp().value() != stack {
+        if nops >= MAX_NOPS {
+            return Err(format!(
+                "The routine 0x{routine:04X} did not return after {MAX_NOPS} nops (PC=0x{:04X})",
+                z80.pc().value()
+            ));
+        }
+        nops += z80.step();
+    }
+
+    Ok(nops)
+}
+
+fn check(
+    z80: &Z80,
+    expectation: &UnitTestExpectation<i32>,
+    last_call_nops: Option<usize>
+) -> Result<(), String> {
+    let (what, value, op, expected, is_8bits) = match expectation {
+        UnitTestExpectation::Register(reg, op, expected) => {
+            let access = data_access(reg);
+            let value = if reg.is_8bits() {
+                z80.get_register_8(&access).value().into()
+            }
+            else {
+                z80.get_register_16(&access).value()
+            };
+            (
+                reg.to_string(),
+                i32::from(value),
+                op,
+                *expected,
+                reg.is_8bits()
+            )
+        },
+        UnitTestExpectation::Memory(address, op, expected) => {
+            let address = checked_value(*address, false)?;
+            let value = z80.read_memory_byte(address);
+            (
+                format!("(0x{address:04X})"),
+                i32::from(value),
+                op,
+                *expected,
+                true
+            )
+        },
+        UnitTestExpectation::Nops(op, expected) => {
+            let nops = last_call_nops.ok_or_else(|| "NOPS needs a previous CALL".to_owned())?;
+            return if compare(nops as i32, op, *expected)? {
+                Ok(())
+            }
+            else {
+                Err(format!("The last call lasted {nops} nops"))
+            };
+        },
+        UnitTestExpectation::Flag(flag) => {
+            return if flag.flag_is_active(z80.f().value()) {
+                Ok(())
+            }
+            else {
+                Err(format!(
+                    "Flag {flag} is not set (F=0b{:08b})",
+                    z80.f().value()
+                ))
+            };
+        }
+    };
+
+    // negative values are compared to their two's complement
+    let expected = if expected < 0 {
+        checked_value(expected, is_8bits)? as i32
+    }
+    else {
+        expected
+    };
+
+    if compare(value, op, expected)? {
+        Ok(())
+    }
+    else if is_8bits {
+        Err(format!("{what} = {value} (0x{value:02X})"))
+    }
+    else {
+        Err(format!("{what} = {value} (0x{value:04X})"))
+    }
+}
+
+fn compare(value: i32, op: &BinaryOperation, expected: i32) -> Result<bool, String> {
+    Ok(match op {
+        BinaryOperation::Equal => value == expected,
+        BinaryOperation::Different => value != expected,
+        BinaryOperation::LowerOrEqual => value <= expected,
+        BinaryOperation::GreaterOrEqual => value >= expected,
+        BinaryOperation::StrictlyLower => value < expected,
+        BinaryOperation::StrictlyGreater => value > expected,
+        _ => return Err(format!("{op} is not a comparison"))
+    })
+}
+
+/// Ensure the value fits in a byte or a word
+fn checked_value(value: i32, is_8bits: bool) -> Result<u16, String> {
+    let (min, max, mask) = if is_8bits {
+        (-0x80, 0xFF, 0xFF)
+    }
+    else {
+        (-0x8000, 0xFFFF, 0xFFFF)
+    };
+
+    if (min..=max).contains(&value) {
+        Ok((value & mask) as u16)
+    }
+    else {
+        Err(format!(
+            "{value} does not fit in {} bits",
+            if is_8bits { 8 } else { 16 }
+        ))
+    }
+}
+
+fn data_access(reg: &UnitTestRegister) -> DataAccess {
+    match reg {
+        UnitTestRegister::Register8(reg) => DataAccess::Register8(*reg),
+        UnitTestRegister::Register16(reg) => DataAccess::Register16(*reg),
+        UnitTestRegister::IndexRegister8(reg) => DataAccess::IndexRegister8(*reg),
+        UnitTestRegister::IndexRegister16(reg) => DataAccess::IndexRegister16(*reg)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cpclib_asm::preamble::*;
+
+    use super::*;
+
+    fn run(code: &str) -> Vec<Result<(), String>> {
+        cpclib_asm::assemble_and_inspect(code, EnvOptions::default(), |env| {
+            env.unit_tests()
+                .iter()
+                .map(|test| run_unit_test(env.sna(), test).map_err(|e| e.to_string()))
+                .collect()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn passing_test() {
+        let results = run(r#"
+            org 0x4000
+double
+            add a, a
+            ld (hl), a
+            ret
+
+            TEST "double"
+                ld a, 3 : ld hl, 0x8000
+                poke 0x8000, 0xFF
+                call double
+                expect a == 6
+                expect (0x8000) == 6
+                expect hl == 0x8000
+                expect nz
+                expect c == 0
+                expect nops == 1 + 2 + 3
+            ENDTEST
+        "#);
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_ok(), "{:?}", results[0]);
+    }
+
+    #[test]
+    fn failing_test() {
+        let results = run(r#"
+            org 0x4000
+inc_a
+            inc a
+            ret
+
+            TEST "wrong"
+                ld a, -1
+                call inc_a
+                expect z
+                expect a == 1
+            ENDTEST
+        "#);
+        let error = results[0].as_ref().unwrap_err();
+        assert!(error.contains("expect a == 1"), "{error}");
+        assert!(error.contains("A = 0"), "{error}");
+    }
+
+    #[test]
+    fn routine_without_return() {
+        let results = run(r#"
+            org 0x4000
+loop
+            jr loop
+
+            TEST "infinite"
+                call loop
+            ENDTEST
+        "#);
+        let error = results[0].as_ref().unwrap_err();
+        assert!(error.contains("did not return"), "{error}");
+    }
+}
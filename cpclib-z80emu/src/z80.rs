@@ -12,6 +12,7 @@ use cpclib_common::num::One;
 use cpclib_common::num::integer::Integer;
 use cpclib_common::num::traits::{WrappingAdd, WrappingSub};
 
+use crate::memory::Memory;
 use crate::preamble::*;
 
 /// Common trait for Register 8 and 6 bits
@@ -164,7 +165,7 @@ impl EmulationContext {
 }
 
 /// Highly simplify z80 model.
-/// TODO Add memory
+/// The memory is the banked memory of a CPC; tokens as well as real opcodes can be executed.
 #[derive(Default, Debug, Clone)]
 pub struct Z80 {
     reg_pc: Register16,
@@ -188,6 +189,10 @@ pub struct Z80 {
     reg_de_prime: Register16,
     reg_hl_prime: Register16,
 
+    pub(crate) extra: ExtraFlags,
+
+    pub(crate) memory: Memory,
+
     pub(crate) context: EmulationContext
 }
 
@@ -286,6 +291,50 @@ impl Z80 {
         tmp.low()
     }
 
+    pub fn i(&self) -> &Register8 {
+        &self.reg_i
+    }
+
+    pub fn r(&self) -> &Register8 {
+        &self.reg_r
+    }
+
+    pub fn af_prime(&self) -> &Register16 {
+        &self.reg_af_prime
+    }
+
+    pub fn bc_prime(&self) -> &Register16 {
+        &self.reg_bc_prime
+    }
+
+    pub fn de_prime(&self) -> &Register16 {
+        &self.reg_de_prime
+    }
+
+    pub fn hl_prime(&self) -> &Register16 {
+        &self.reg_hl_prime
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn iff1(&self) -> bool {
+        self.extra.iff1
+    }
+
+    pub fn iff2(&self) -> bool {
+        self.extra.iff2
+    }
+
+    pub fn interrupt_mode(&self) -> u8 {
+        self.extra.im
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.extra.halted
+    }
+
     // Mutable accessors
     pub fn pc_mut(&mut self) -> &mut Register16 {
         &mut self.reg_pc
@@ -379,6 +428,68 @@ impl Z80 {
         tmp.low_mut()
     }
 
+    pub fn i_mut(&mut self) -> &mut Register8 {
+        &mut self.reg_i
+    }
+
+    pub fn r_mut(&mut self) -> &mut Register8 {
+        &mut self.reg_r
+    }
+
+    pub fn af_prime_mut(&mut self) -> &mut Register16 {
+        &mut self.reg_af_prime
+    }
+
+    pub fn bc_prime_mut(&mut self) -> &mut Register16 {
+        &mut self.reg_bc_prime
+    }
+
+    pub fn de_prime_mut(&mut self) -> &mut Register16 {
+        &mut self.reg_de_prime
+    }
+
+    pub fn hl_prime_mut(&mut self) -> &mut Register16 {
+        &mut self.reg_hl_prime
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    pub fn set_iff1(&mut self, iff1: bool) {
+        self.extra.iff1 = iff1;
+    }
+
+    pub fn set_iff2(&mut self, iff2: bool) {
+        self.extra.iff2 = iff2;
+    }
+
+    pub fn set_interrupt_mode(&mut self, im: u8) {
+        self.extra.im = im;
+    }
+
+    pub fn set_halted(&mut self, halted: bool) {
+        self.extra.halted = halted;
+    }
+
+    /// Read a byte in the memory through the current mapping
+    pub fn read_memory_byte(&self, address: u16) -> u8 {
+        self.memory.read_byte(address)
+    }
+
+    /// Write a byte in the memory through the current mapping
+    pub fn write_memory_byte(&mut self, address: u16, value: u8) {
+        self.memory.write_byte(address, value);
+    }
+
+    pub fn read_memory_word(&self, address: u16) -> u16 {
+        self.memory.read_word(address)
+    }
+
+    pub fn write_memory_word(&mut self, address: u16, value: u16) {
+        self.memory.write_word(address, value);
+    }
+
     pub fn ex_af_af_prime(&mut self) {
         swap(&mut self.reg_af_prime, &mut self.reg_af);
     }
@@ -432,10 +543,17 @@ pub enum FlagPos {
     Carry = 0
 }
 
-#[allow(unused)]
-struct ExtraFlags {
-    iff1: u8,
-    iff2: u8
+/// State of the CPU that is not stored in registers
+#[derive(Default, Debug, Copy, Clone)]
+pub(crate) struct ExtraFlags {
+    pub(crate) iff1: bool,
+    pub(crate) iff2: bool,
+    /// Interrupt mode (0, 1 or 2)
+    pub(crate) im: u8,
+    /// The CPU is stuck on a HALT until the next interrupt
+    pub(crate) halted: bool,
+    /// Interrupts are not accepted just after EI
+    pub(crate) ei_delay: bool
 }
 
 #[cfg(test)]
@@ -544,4 +544,126 @@ mod tests {
             assert!(!entry.read_only());
         }
     }
+
+    #[test]
+    fn protected_edsk_round_trip() {
+        let fname = "tests/dsk/Turlogh Le Rodeur (F) (Face A) (1987) [Original] (GAPS).dsk";
+        let dsk = cpclib::disc::edsk::ExtendedDsk::open(fname).unwrap();
+
+        // sectors of the protected track also store the gap bytes
+        let sector = dsk.sector(cpclib::disc::edsk::Head::A, 39, 0x45).unwrap();
+        assert_eq!(sector.len(), 512);
+        assert_eq!(sector.stored_values().len(), 1076);
+        assert_eq!(sector.copies().count(), 1);
+        assert_eq!(
+            dsk.sector_read_bytes(cpclib::disc::edsk::Head::A, 39, 0x45)
+                .unwrap()
+                .len(),
+            512
+        );
+
+        let mut buffer = Vec::new();
+        dsk.to_buffer(&mut buffer);
+        let original = std::fs::read(fname).unwrap();
+        assert_eq!(&buffer[0x30..], &original[0x30..buffer.len()]);
+        assert_eq!(cpclib::disc::edsk::ExtendedDsk::from_buffer(&buffer), dsk);
+    }
+
+    #[test]
+    fn weak_sectors_and_deleted_data() {
+        use cpclib::disc::cfg::DiscConfig;
+        use cpclib::disc::edsk::{ExtendedDsk, Head};
+
+        let mut dsk: ExtendedDsk = DiscConfig::single_head_data_format().into();
+        let copies = [[1u8; 512], [2; 512], [3; 512]];
+
+        let weak = dsk.edsk_sector_mut(Head::A, 2, 0xC1).unwrap();
+        weak.set_copies(&copies.iter().map(|c| &c[..]).collect_vec())
+            .unwrap();
+        weak.set_deleted_data_mark(true);
+        assert!(weak.set_copies(&[&[0; 512], &[0; 256]]).is_err());
+
+        let truncated = dsk.edsk_sector_mut(Head::A, 2, 0xC2).unwrap();
+        truncated.set_copies(&[&[4; 300]]).unwrap();
+
+        let mut buffer = Vec::new();
+        dsk.to_buffer(&mut buffer);
+        let dsk2 = ExtendedDsk::from_buffer(&buffer);
+        let mut buffer2 = Vec::new();
+        dsk2.to_buffer(&mut buffer2);
+        assert_eq!(buffer2, buffer);
+
+        let weak = dsk2.sector(Head::A, 2, 0xC1).unwrap();
+        assert!(weak.sector_information_bloc().is_weak());
+        assert!(weak.sector_information_bloc().has_deleted_data_mark());
+        assert_eq!(weak.len(), 512);
+        assert_eq!(
+            weak.copies().collect_vec(),
+            copies.iter().map(|c| &c[..]).collect_vec()
+        );
+        assert!(format!("{weak}").ends_with("512 bytes, 3 copies [deleted data]"));
+
+        let truncated = dsk2.sector(Head::A, 2, 0xC2).unwrap();
+        assert_eq!(truncated.values(), &[4; 300]);
+        assert!(format!("{truncated}").ends_with("300 of 512 bytes"));
+
+        // writing a weak sector writes all its copies
+        let mut dsk2 = dsk2;
+        dsk2.sector_write_bytes(Head::A, 2, 0xC1, &[5; 512])
+            .unwrap();
+        let weak = dsk2.sector(Head::A, 2, 0xC1).unwrap();
+        assert!(weak.copies().all(|c| c == [5; 512]));
+    }
+
+    #[test]
+    fn open_standard_dsk() {
+        let dsk = cpclib::disc::edsk::ExtendedDsk::default();
+        let mut extended = Vec::new();
+        dsk.to_buffer(&mut extended);
+
+        // Same content with the layout of a standard DSK
+        let track_size = 256 + 9 * 512;
+        let mut standard = extended.clone();
+        standard[..34].copy_from_slice(b"MV - CPCEMU Disk-File\r\nDisk-Info\r\n");
+        standard[0x32..0x34].copy_from_slice(&(track_size as u16).to_le_bytes());
+        standard[0x34..0x100].fill(0);
+        for track in standard[256..].chunks_mut(track_size) {
+            for sector in 0..9 {
+                track[0x18 + sector * 8 + 6] = 0;
+                track[0x18 + sector * 8 + 7] = 0;
+            }
+        }
+
+        let reloaded = cpclib::disc::edsk::ExtendedDsk::from_buffer(&standard);
+        assert_eq!(reloaded.nb_tracks(), dsk.nb_tracks());
+        let mut buffer = Vec::new();
+        reloaded.to_buffer(&mut buffer);
+        assert_eq!(buffer[0x30..], extended[0x30..]);
+
+        // the last track is cut in the middle of its second sector
+        let last_track = dsk.nb_tracks() as u8 - 1;
+        let truncated = &standard[..standard.len() - track_size + 256 + 600];
+        let reloaded = cpclib::disc::edsk::ExtendedDsk::from_buffer(truncated);
+        assert_eq!(reloaded.nb_tracks(), dsk.nb_tracks());
+        let track = reloaded
+            .get_track_information(cpclib::disc::edsk::Head::A, last_track)
+            .unwrap();
+        let lengths = track
+            .sector_information_list()
+            .sectors()
+            .iter()
+            .map(|s| s.values().len())
+            .collect_vec();
+        assert_eq!(lengths, [512, 88, 0, 0, 0, 0, 0, 0, 0]);
+
+        // the last track is cut in the middle of its header
+        let truncated = &standard[..standard.len() - track_size + 100];
+        let reloaded = cpclib::disc::edsk::ExtendedDsk::from_buffer(truncated);
+        assert!(
+            !reloaded
+                .get_track_information(cpclib::disc::edsk::Head::A, last_track)
+                .unwrap()
+                .is_formatted()
+        );
+    }
 }